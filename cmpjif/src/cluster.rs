@@ -0,0 +1,112 @@
+//! Clustering of JIFs by their private pages
+//!
+//! Snapshots which share most of their private pages are good candidates to share a delta base
+//! (see `jiftool make-delta`). The similarity of two JIFs is the Jaccard index of the sets of
+//! digests of their private pages: JIFs are grouped when they are (transitively) at least as
+//! similar as a threshold, and the suggested base of a group is its member most similar to the
+//! others
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// A group of similar JIFs (by index)
+#[derive(Debug)]
+pub(crate) struct Group {
+    /// Suggested base
+    pub(crate) base: usize,
+
+    /// Members of the group, the base included
+    pub(crate) members: Vec<usize>,
+}
+
+/// Jaccard index of two sets (two empty sets are identical)
+fn jaccard<T: Eq + Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    let intersection = a.intersection(b).count();
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        return 1.0;
+    }
+
+    intersection as f64 / union as f64
+}
+
+/// Pairwise similarity of the sets
+pub(crate) fn similarity_matrix<T: Eq + Hash>(sets: &[&HashSet<T>]) -> Vec<Vec<f64>> {
+    sets.iter()
+        .map(|a| sets.iter().map(|b| jaccard(a, b)).collect())
+        .collect()
+}
+
+/// Group the sets which are (transitively) at least `threshold` similar
+pub(crate) fn group(matrix: &[Vec<f64>], threshold: f64) -> Vec<Group> {
+    let mut grouped = vec![false; matrix.len()];
+    let mut groups = Vec::new();
+    for first in 0..matrix.len() {
+        if grouped[first] {
+            continue;
+        }
+
+        grouped[first] = true;
+        let mut members = vec![first];
+        let mut idx = 0;
+        while idx < members.len() {
+            let member = members[idx];
+            for other in 0..matrix.len() {
+                if !grouped[other] && matrix[member][other] >= threshold {
+                    grouped[other] = true;
+                    members.push(other);
+                }
+            }
+            idx += 1;
+        }
+        members.sort();
+
+        // the member with the highest total similarity to the others (the first on a tie)
+        let total =
+            |member: usize| -> f64 { members.iter().map(|other| matrix[member][*other]).sum() };
+        let mut base = members[0];
+        for member in &members[1..] {
+            if total(*member) > total(base) {
+                base = *member;
+            }
+        }
+
+        groups.push(Group { base, members });
+    }
+
+    groups
+}
+
+/// Print the similarity matrix and the suggested groups
+pub(crate) fn print_clusters(names: &[String], matrix: &[Vec<f64>], threshold: f64) {
+    println!("similarity of the private pages (Jaccard index):");
+    print!("{:>4}", "");
+    for idx in 0..names.len() {
+        print!(" {:>6}", idx);
+    }
+    println!();
+    for (idx, (name, row)) in names.iter().zip(matrix).enumerate() {
+        print!("{:>4}", idx);
+        for similarity in row {
+            print!(" {:>6.3}", similarity);
+        }
+        println!("  {}", name);
+    }
+
+    println!();
+    println!("suggested groups (similarity >= {:.2}):", threshold);
+    for group in group(matrix, threshold) {
+        if group.members.len() == 1 {
+            println!("- {} (no similar JIF)", names[group.base]);
+            continue;
+        }
+
+        let others = group
+            .members
+            .iter()
+            .filter(|member| **member != group.base)
+            .map(|member| names[*member].as_str())
+            .collect::<Vec<_>>();
+        println!("- base {}: {}", names[group.base], others.join(", "));
+    }
+}
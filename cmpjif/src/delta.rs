@@ -0,0 +1,398 @@
+//! Byte level comparison of two JIFs
+//!
+//! Comparing the digests of the pages tells whether a page changed, not by how much: a page
+//! with a single different byte costs a full page in a page level delta. To tell whether a
+//! binary delta (from a base JIF to a target JIF) is worth building, the matching virtual ranges
+//! (the intersections of the pheaders of both JIFs) are compared byte by byte, and the size of
+//! the delta is estimated by content defined chunking:
+//!  - the data of both JIFs is cut into chunks by a rolling (gear) hash, so that the chunk
+//!    boundaries follow the contents (and realign after an insertion)
+//!  - a chunk of the target found anywhere in the base (by digest), or made of zeros, is encoded
+//!    as a copy, and any other chunk either as a literal or as a patch of the base chunk at the
+//!    same address (a copy, and a literal for every run of differing bytes), whichever is smaller
+//!
+//! The ranges which are trivially alike are skipped: zero in both JIFs, or mapped to the same
+//! offset of the same file. The private data of the ranges only mapped by the target is a
+//! literal, unless its chunks are found in the base
+
+use jif::digest::{sha256, Sha256Hash};
+use jif::itree::interval::DataSource;
+use jif::pheader::JifPheader;
+use jif::{Jif, PathResolver};
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+
+/// Smallest chunk (unless the data ends)
+const MIN_CHUNK: usize = 256;
+
+/// Largest chunk
+const MAX_CHUNK: usize = 8 << 10;
+
+/// Bits of the rolling hash which are zero at a chunk boundary (for 2 KiB chunks on average)
+const BOUNDARY_BITS: u32 = 11;
+
+/// Estimated size of a copy instruction (an offset and a length)
+const COPY_COST: u64 = 16;
+
+/// Estimated size of the header of a literal (a length)
+const LITERAL_COST: u64 = 8;
+
+/// Random table of the gear hash (from a fixed seed, so the estimates are reproducible)
+fn gear_table() -> [u64; 256] {
+    let mut state = 0x6a09e667f3bcc908u64;
+    [0; 256].map(|_: u64| {
+        // splitmix64
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// Cut the data into content defined chunks
+fn chunks<'a>(data: &'a [u8], gear: &'a [u64; 256]) -> impl Iterator<Item = &'a [u8]> + 'a {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut hash = 0u64;
+        let mut len = std::cmp::min(rest.len(), MAX_CHUNK);
+        for (idx, byte) in rest.iter().enumerate().take(len).skip(MIN_CHUNK) {
+            hash = (hash << 1).wrapping_add(gear[*byte as usize]);
+            if hash >> (u64::BITS - BOUNDARY_BITS) == 0 {
+                len = idx + 1;
+                break;
+            }
+        }
+
+        let (chunk, tail) = rest.split_at(len);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// Estimated size of a patch of the base chunk into the target chunk (at the same address): a
+/// copy of the base chunk, with a literal for every run of differing bytes
+fn patch_cost(base: &[u8], target: &[u8]) -> u64 {
+    let mut cost = COPY_COST;
+    let mut in_run = false;
+    for (base_byte, target_byte) in base.iter().zip(target) {
+        match (base_byte == target_byte, in_run) {
+            (false, false) => cost += 1 + LITERAL_COST,
+            (false, true) => cost += 1,
+            _ => {}
+        }
+        in_run = base_byte != target_byte;
+    }
+
+    cost
+}
+
+/// Identity of the data of an address in a pheader: the data source, and where a shared page
+/// is read from
+#[derive(Debug, PartialEq, Eq)]
+enum Source<'a> {
+    Zero,
+    Shared(&'a str, u64),
+    Private,
+}
+
+/// Identity of the data at `addr` of an interval of a pheader
+fn source_at(pheader: &JifPheader, source: DataSource, addr: u64) -> Source<'_> {
+    match (source, pheader.pathname(), pheader.ref_offset()) {
+        (DataSource::Zero, _, _) => Source::Zero,
+        (DataSource::Shared, Some(path), Some(offset)) => {
+            Source::Shared(path, offset + addr - pheader.virtual_range().0)
+        }
+        _ => Source::Private,
+    }
+}
+
+/// A piece of the address space, with the values of the lists covering it (see [`overlay`])
+type Overlaid<A, B> = ((u64, u64), Option<A>, Option<B>);
+
+/// Split the address space by two sorted lists of disjoint ranges (with some attached values),
+/// into the pieces covered by at least one of the lists
+fn overlay<A: Copy, B: Copy>(a: &[((u64, u64), A)], b: &[((u64, u64), B)]) -> Vec<Overlaid<A, B>> {
+    fn covering<T: Copy>(ranges: &[((u64, u64), T)], addr: u64) -> Option<T> {
+        let idx = ranges.partition_point(|((_start, end), _value)| *end <= addr);
+        ranges
+            .get(idx)
+            .filter(|((start, _end), _value)| *start <= addr)
+            .map(|(_range, value)| *value)
+    }
+
+    let mut bounds = a
+        .iter()
+        .map(|(range, _)| *range)
+        .chain(b.iter().map(|(range, _)| *range))
+        .flat_map(|(start, end)| [start, end])
+        .collect::<Vec<_>>();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    bounds
+        .windows(2)
+        .filter_map(|window| {
+            let (start, end) = (window[0], window[1]);
+            match (covering(a, start), covering(b, start)) {
+                (None, None) => None,
+                (a, b) => Some(((start, end), a, b)),
+            }
+        })
+        .collect()
+}
+
+/// Logical intervals of a pheader within a range, as `(range, source)`
+fn intervals(pheader: &JifPheader, (start, end): (u64, u64)) -> Vec<((u64, u64), DataSource)> {
+    pheader
+        .itree()
+        .iter_logical_intervals()
+        .filter(|ival| ival.start < end && start < ival.end)
+        .map(|ival| {
+            (
+                (
+                    std::cmp::max(ival.start, start),
+                    std::cmp::min(ival.end, end),
+                ),
+                ival.source,
+            )
+        })
+        .collect()
+}
+
+/// Pheaders of a JIF, as `(range, pheader)`, in address order
+fn pheaders(jif: &Jif) -> Vec<((u64, u64), &JifPheader)> {
+    let mut pheaders = jif
+        .pheaders()
+        .iter()
+        .map(|pheader| (pheader.virtual_range(), pheader))
+        .collect::<Vec<_>>();
+    pheaders.sort_by_key(|(range, _pheader)| *range);
+    pheaders
+}
+
+/// A piece of the address space whose data has to be compared: `None` in a JIF which does
+/// not map it, or where it is not worth reading (the zero pages, or the shared pages in the
+/// ranges only mapped by one JIF)
+type Piece = Overlaid<DataSource, DataSource>;
+
+/// Pieces of a virtual range which are not trivially alike in the base and in the target
+fn pieces(range: (u64, u64), base: Option<&JifPheader>, target: Option<&JifPheader>) -> Vec<Piece> {
+    let base_ivals = base
+        .map(|pheader| intervals(pheader, range))
+        .unwrap_or_default();
+    let target_ivals = target
+        .map(|pheader| intervals(pheader, range))
+        .unwrap_or_default();
+
+    overlay(&base_ivals, &target_ivals)
+        .into_iter()
+        .filter_map(|(range, base_source, target_source)| {
+            let (base_source, target_source) = match (base, target) {
+                (Some(base), Some(target)) => {
+                    let base_source = base_source?;
+                    let target_source = target_source?;
+                    if source_at(base, base_source, range.0)
+                        == source_at(target, target_source, range.0)
+                        && base_source != DataSource::Private
+                    {
+                        return None;
+                    }
+                    (
+                        Some(base_source).filter(|source| *source != DataSource::Zero),
+                        Some(target_source).filter(|source| *source != DataSource::Zero),
+                    )
+                }
+                _ => (
+                    base_source.filter(|source| *source == DataSource::Private),
+                    target_source.filter(|source| *source == DataSource::Private),
+                ),
+            };
+
+            (base_source.is_some() || target_source.is_some()).then_some((
+                range,
+                base_source,
+                target_source,
+            ))
+        })
+        .collect()
+}
+
+/// Byte level comparison of a virtual range
+#[derive(Debug, Default)]
+pub(crate) struct RangeDelta {
+    /// Virtual range
+    pub(crate) range: (u64, u64),
+
+    /// Whether the range is mapped by the base and by the target
+    pub(crate) mapped: (bool, bool),
+
+    /// Number of bytes which differ (zero when the range is only mapped by one JIF)
+    pub(crate) differing: u64,
+
+    /// Estimated size of the delta of the range
+    pub(crate) estimate: u64,
+}
+
+/// Byte level comparison of two JIFs (see [`crate::delta`])
+#[derive(Debug, Default)]
+pub(crate) struct DeltaReport {
+    /// Compared ranges, in address order
+    pub(crate) ranges: Vec<RangeDelta>,
+
+    /// Size of the private data of the target (what a full image carries)
+    pub(crate) target_private: u64,
+}
+
+/// Read a piece of a JIF, zero filled where it is not read
+fn read_piece(
+    jif: &Jif,
+    (start, end): (u64, u64),
+    source: Option<DataSource>,
+    paths: &dyn PathResolver,
+) -> anyhow::Result<Vec<u8>> {
+    match source {
+        None | Some(DataSource::Zero) => Ok(vec![0; (end - start) as usize]),
+        Some(_) => jif.read_range(start, end - start, paths).with_context(|| {
+            format!(
+                "failed to read [{:#x}; {:#x}) (are the referenced files relative to a --chroot?)",
+                start, end
+            )
+        }),
+    }
+}
+
+/// Compare two JIFs byte by byte, estimating the size of a delta from the base to the target
+pub(crate) fn compare(
+    base: &Jif,
+    target: &Jif,
+    paths: &dyn PathResolver,
+) -> anyhow::Result<DeltaReport> {
+    let gear = gear_table();
+    let ranges = overlay(&pheaders(base), &pheaders(target))
+        .into_iter()
+        .map(|(range, base, target)| (range, base, target, pieces(range, base, target)))
+        .collect::<Vec<_>>();
+
+    // the chunks of the base the target can copy from
+    let mut base_chunks = HashSet::<Sha256Hash>::new();
+    for (piece, base_source, _target_source) in ranges.iter().flat_map(|range| &range.3) {
+        if base_source.is_some() {
+            let data = read_piece(base, *piece, *base_source, paths)?;
+            base_chunks.extend(chunks(&data, &gear).map(sha256));
+        }
+    }
+
+    let mut report = DeltaReport {
+        target_private: target
+            .pheaders()
+            .iter()
+            .flat_map(|pheader| pheader.itree().iter_logical_intervals())
+            .filter(|ival| ival.source == DataSource::Private)
+            .map(|ival| ival.end - ival.start)
+            .sum(),
+        ..Default::default()
+    };
+    for (range, base_pheader, target_pheader, pieces) in ranges {
+        let mut delta = RangeDelta {
+            range,
+            mapped: (base_pheader.is_some(), target_pheader.is_some()),
+            ..Default::default()
+        };
+        for (piece, base_source, target_source) in pieces {
+            let target_data = read_piece(target, piece, target_source, paths)?;
+            let base_data = match delta.mapped {
+                (true, true) => Some(read_piece(base, piece, base_source, paths)?),
+                _ if target_source.is_none() => continue,
+                _ => None,
+            };
+            if let Some(base_data) = &base_data {
+                delta.differing += base_data
+                    .iter()
+                    .zip(&target_data)
+                    .filter(|(base_byte, target_byte)| base_byte != target_byte)
+                    .count() as u64;
+                if *base_data == target_data {
+                    continue;
+                }
+            }
+
+            let mut offset = 0;
+            for chunk in chunks(&target_data, &gear) {
+                let in_place = base_data
+                    .as_ref()
+                    .map(|base_data| patch_cost(&base_data[offset..offset + chunk.len()], chunk));
+                offset += chunk.len();
+                delta.estimate += if chunk.iter().all(|byte| *byte == 0)
+                    || base_chunks.contains(&sha256(chunk))
+                {
+                    COPY_COST
+                } else {
+                    std::cmp::min(
+                        chunk.len() as u64 + LITERAL_COST,
+                        in_place.unwrap_or(u64::MAX),
+                    )
+                };
+            }
+        }
+        report.ranges.push(delta);
+    }
+
+    Ok(report)
+}
+
+/// Print the comparison, one virtual range per line
+pub(crate) fn print_delta(report: &DeltaReport) {
+    fn percentage(parcel: u64, total: u64) -> f64 {
+        match total {
+            0 => 0.0,
+            _ => (parcel * 100) as f64 / total as f64,
+        }
+    }
+
+    println!(
+        "{:^32} | {:^6} | {:^22} | {:^12} |",
+        "range", "mapped", "differing bytes", "delta (est)"
+    );
+    for delta in &report.ranges {
+        let (start, end) = delta.range;
+        let mapped = match delta.mapped {
+            (true, true) => "both",
+            (true, false) => "base",
+            _ => "target",
+        };
+        println!(
+            "[{:#014x}; {:#014x}) | {:>6} | {:>12} ({:5.1}%) | {:>12} |",
+            start,
+            end,
+            mapped,
+            delta.differing,
+            percentage(delta.differing, end - start),
+            delta.estimate
+        );
+    }
+
+    let (differing, estimate) = report
+        .ranges
+        .iter()
+        .fold((0, 0), |(differing, estimate), delta| {
+            (differing + delta.differing, estimate + delta.estimate)
+        });
+    println!();
+    println!(
+        "differing bytes (in the ranges mapped by both): {}",
+        differing
+    );
+    println!(
+        "estimated delta: {} B ({:.1}% of the {} B of private data of the target)",
+        estimate,
+        percentage(estimate, report.target_private),
+        report.target_private
+    );
+}
@@ -7,22 +7,28 @@
 //! $ cmpjif a.jif b.jif # compare a.jif and b.jif
 //! # cmpjif --private a.jif b.jif c.jif # compare a.jif, b.jif and c.jif, comparing only the private pages
 //! # cmpjif --shared a.jif b.jif c.jif # compare a.jif, b.jif and c.jif, comparing only the shared pages
+//! # cmpjif --shared --hash-shared --chroot root/ a.jif b.jif # compare the contents of the shared pages
+//! # cmpjif --output plot.svg --backend svg a.jif b.jif # plot the intersection without python
+//! # cmpjif --cluster a.jif b.jif c.jif d.jif # group the JIFs which should share a delta base
+//! # cmpjif --delta --chroot root/ a.jif b.jif # count the differing bytes and estimate the size of a delta from a.jif to b.jif
 //! ```
 
+mod cluster;
+mod delta;
+mod svg;
+
+use jif::digest::sha256;
 use jif::itree::interval::DataSource;
 use jif::*;
 
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
 use anyhow::Context;
 use clap::Parser;
-use sha2::{Digest, Sha256};
-
-type Sha256Hash = [u8; 32];
 
 const PLOT_UPSET_PY: &str = "
 import matplotlib.pyplot as plt
@@ -80,39 +86,154 @@ struct Cli {
     full: bool,
 
     /// Compare only the shared pages
-    #[arg(short, long, value_name = "FILE", required_unless_present = "full", value_hint = clap::ValueHint::FilePath)]
+    #[arg(short, long, value_name = "FILE", required_unless_present_any = ["full", "cluster", "delta"], value_hint = clap::ValueHint::FilePath)]
     output: Option<std::path::PathBuf>,
+
+    /// Compare the contents of the shared pages (read from the referenced files) instead of
+    /// their (path, offset) identity
+    #[arg(long)]
+    hash_shared: bool,
+
+    /// Directory the referenced files are relative to (when hashing the shared pages, or
+    /// comparing them byte by byte)
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    chroot: Option<std::path::PathBuf>,
+
+    /// Backend used to plot the intersection
+    #[arg(long, value_enum, default_value_t = Backend::Python)]
+    backend: Backend,
+
+    /// Print the pairwise similarity of the private pages, and suggest groups of JIFs to share a
+    /// delta base
+    #[arg(long, conflicts_with_all = ["shared", "full", "output"])]
+    cluster: bool,
+
+    /// When clustering, group the JIFs at least this similar (Jaccard index of the private pages)
+    #[arg(long, default_value_t = 0.5)]
+    threshold: f64,
+
+    /// Compare two JIFs (a base and a target) byte by byte, per matching virtual range, and
+    /// estimate the size of a binary delta from the base to the target
+    #[arg(long, conflicts_with_all = ["shared", "private", "ordering", "full", "output", "cluster"])]
+    delta: bool,
+}
+
+/// Plotting backends
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// Shell out to python (requires matplotlib and upsetplot)
+    Python,
+
+    /// Render an SVG natively
+    Svg,
+}
+
+/// Identity of a shared page
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SharedPage {
+    /// The page at an offset of a referenced file
+    Location(String, u64),
+
+    /// The contents of the page
+    Content(Sha256Hash),
+}
+
+/// Resolves the shared pages into their identities, either by location or by content
+struct SharedPageResolver {
+    /// Whether to hash the contents, and the directory the files are relative to
+    hash: Option<Option<PathBuf>>,
+
+    /// Referenced files opened so far
+    files: HashMap<String, File>,
 }
 
-fn sha256_page(page: &[u8]) -> Sha256Hash {
-    let mut hasher = Sha256::new();
-    hasher.update(page);
-    hasher.finalize().into()
+impl SharedPageResolver {
+    fn new(hash: bool, chroot: Option<PathBuf>) -> Self {
+        SharedPageResolver {
+            hash: hash.then_some(chroot),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Resolve the page (of `page_size`) at `offset` of the file at `path`
+    ///
+    /// Pages past the end of the file are zero filled (as when mapped)
+    fn resolve(&mut self, path: &str, offset: u64, page_size: usize) -> anyhow::Result<SharedPage> {
+        let Some(chroot) = &self.hash else {
+            return Ok(SharedPage::Location(path.to_string(), offset));
+        };
+
+        let file = match self.files.entry(path.to_string()) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let full_path = match chroot {
+                    None => PathBuf::from(path),
+                    Some(chroot) => chroot.join(path.trim_start_matches('/')),
+                };
+                entry.insert(File::open(&full_path).context(format!(
+                    "failed to open referenced file {}",
+                    full_path.display()
+                ))?)
+            }
+        };
+
+        let mut page = Vec::with_capacity(page_size);
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(page_size as u64)
+            .read_to_end(&mut page)
+            .context(format!("failed to read {} at {:#x}", path, offset))?;
+        page.resize(page_size, 0);
+
+        Ok(SharedPage::Content(sha256(&page)))
+    }
 }
 
 /// Build a set of hashes of the private pages
 fn build_private_pages_hash_set(jif: &Jif) -> HashSet<Sha256Hash> {
-    jif.iter_private_pages().map(sha256_page).collect()
+    jif.page_digests(DataSource::Private)
+        .into_iter()
+        .map(|(_addr, digest)| digest)
+        .collect()
 }
 
-/// Build a set of hashes of pages
-fn build_shared_pages_set(jif: &Jif) -> HashSet<(String, u64)> {
+/// Build a set of the shared pages
+fn build_shared_pages_set(
+    jif: &Jif,
+    resolver: &mut SharedPageResolver,
+) -> anyhow::Result<HashSet<SharedPage>> {
     jif.iter_shared_regions()
         .flat_map(|(string, start, end)| {
             (start..end)
-                .step_by(0x1000)
-                .map(|addr| (string.to_string(), addr))
+                .step_by(jif.page_size())
+                .map(move |offset| (string, offset))
         })
+        .map(|(string, offset)| resolver.resolve(string, offset, jif.page_size()))
         .collect()
 }
 
 /// Build a digest from the ordering section
-fn build_ordering_digest(jif: &Jif, include_private: bool, include_shared: bool) -> JifDigest {
+fn build_ordering_digest(
+    jif: &Jif,
+    include_private: bool,
+    include_shared: bool,
+    resolver: &mut SharedPageResolver,
+) -> anyhow::Result<JifDigest> {
     let mut private = Vec::new();
     let mut shared = Vec::new();
     let mut zero_pages = 0;
+    let private_digests = if include_private {
+        jif.page_digests(DataSource::Private)
+            .into_iter()
+            .collect::<HashMap<_, _>>()
+    } else {
+        HashMap::new()
+    };
 
-    for page in jif.ord_chunks().iter().flat_map(|ord| ord.pages()) {
+    for page in jif
+        .ord_chunks()
+        .iter()
+        .flat_map(|ord| ord.pages(jif.page_size()))
+    {
         match jif.resolve(page) {
             None => {
                 eprintln!(
@@ -132,28 +253,30 @@ fn build_ordering_digest(jif: &Jif, include_private: bool, include_shared: bool)
                         let offset_into_region = page - pheader.virtual_range().0;
                         let filename = pheader.pathname().expect("if the address resolves into a shared region, it must have a filename").to_string();
                         let ref_offset = pheader.ref_offset().expect("if the address maps to a shared region, it must have a base file offset");
-                        shared.push((filename, ref_offset + offset_into_region));
+                        shared.push(resolver.resolve(
+                            &filename,
+                            ref_offset + offset_into_region,
+                            jif.page_size(),
+                        )?);
                     }
                 }
                 DataSource::Private => {
                     if include_private {
-                        let page_data = jif
-                            .resolve_data(page)
+                        let digest = private_digests
+                            .get(&page)
                             .expect("if it resolves and is private it must have data");
-
-                        assert_eq!(page_data.len(), 0x1000, "page is not page sized");
-                        private.push(sha256_page(page_data));
+                        private.push(*digest);
                     }
                 }
             },
         }
     }
 
-    JifDigest {
+    Ok(JifDigest {
         private_pages: private.into_iter().collect(),
         shared_pages: shared.into_iter().collect(),
         zero_pages,
-    }
+    })
 }
 
 /// Open the JIF file
@@ -173,19 +296,71 @@ struct JifDigest {
     // digest of each private page
     private_pages: HashSet<Sha256Hash>,
 
-    // <pathname, offset> for shared pages
-    shared_pages: HashSet<(String, u64)>,
+    // <pathname, offset> (or digest) for shared pages
+    shared_pages: HashSet<SharedPage>,
 
     // number of zero pages
     zero_pages: usize,
 }
 
+/// Labels of the pages in a digest (unique across the private and shared pages)
+fn digest_labels(digest: &JifDigest) -> impl Iterator<Item = String> + '_ {
+    let private = digest.private_pages.iter().map(|hash| {
+        format!(
+            "private_{}",
+            hash.map(|byte| format!("{:x}", byte)).join("")
+        )
+    });
+    let shared = digest.shared_pages.iter().map(|shared_page| {
+        let str = match shared_page {
+            SharedPage::Location(pathname, offset) => format!("{}_{:x}", pathname, offset),
+            SharedPage::Content(hash) => hash.map(|byte| format!("{:x}", byte)).join(""),
+        };
+        format!("shared_{}", str)
+    });
+
+    private.chain(shared)
+}
+
 /// Plot the intersection between the files
-/// Constructs an [upset plot](https://en.wikipedia.org/wiki/UpSet_plot) by shelling out to python
+/// Constructs an [upset plot](https://en.wikipedia.org/wiki/UpSet_plot) with the chosen backend
 fn plot_intersections(
     digests: HashMap<std::path::PathBuf, JifDigest>,
     plot_title: &str,
     output_filename: PathBuf,
+    backend: Backend,
+) -> anyhow::Result<()> {
+    match backend {
+        Backend::Python => plot_intersections_python(digests, plot_title, output_filename),
+        Backend::Svg => {
+            let mut sets = digests
+                .iter()
+                .map(|(path, digest)| {
+                    (
+                        format!("{}", path.display()),
+                        digest_labels(digest).collect::<HashSet<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            sets.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            let plot = svg::render_upset(
+                &format!("Intersection of {} regions among jif snapshots", plot_title),
+                &sets,
+            );
+            std::fs::write(&output_filename, plot).context(format!(
+                "failed to write plot to {}",
+                output_filename.display()
+            ))
+        }
+    }
+}
+
+/// Plot the intersection by shelling out to python
+fn plot_intersections_python(
+    digests: HashMap<std::path::PathBuf, JifDigest>,
+    plot_title: &str,
+    output_filename: PathBuf,
 ) -> anyhow::Result<()> {
     let mut child = Command::new("python")
         .arg("-c")
@@ -194,7 +369,7 @@ fn plot_intersections(
         .arg(format!("{}", output_filename.display()))
         .stdin(Stdio::piped())
         .spawn()
-        .context("failed to spawn python plotter: make sure the python packages are installed (matplotlib and upsetplot)")?;
+        .context("failed to spawn python plotter: make sure the python packages are installed (matplotlib and upsetplot), or use `--backend svg`")?;
 
     let mut stdin = child
         .stdin
@@ -206,17 +381,9 @@ fn plot_intersections(
             .write_all(format!("{}: ", path.display()).as_bytes())
             .context("failed to write")?;
 
-        for hash in &digest.private_pages {
-            let str = hash.map(|byte| format!("{:x}", byte)).join("");
+        for label in digest_labels(&digest) {
             stdin
-                .write_all(format!("private_{}, ", str).as_bytes())
-                .context("failed to write")?;
-        }
-
-        //eprintln!("{:?}: {:?}", path, &digest);
-        for (pathname, offset) in digest.shared_pages {
-            stdin
-                .write_all(format!("shared_{}_{:x}, ", pathname, offset).as_bytes())
+                .write_all(format!("{}, ", label).as_bytes())
                 .context("failed to write")?;
         }
 
@@ -240,7 +407,7 @@ fn print_intersections(digests: HashMap<std::path::PathBuf, JifDigest>) {
     fn is_unique_shared_page(
         digests: &HashMap<PathBuf, JifDigest>,
         path: &std::path::Path,
-        shared_page: &(String, u64),
+        shared_page: &SharedPage,
     ) -> bool {
         for (_path, digest) in digests.iter().filter(|(p, _)| p.as_path() != path) {
             if digest.shared_pages.contains(shared_page) {
@@ -257,9 +424,11 @@ fn print_intersections(digests: HashMap<std::path::PathBuf, JifDigest>) {
 
     let mut stats = HashMap::new();
     for (path, digest) in &digests {
-        let mut stat = Stats::default();
-        stat.zero_pages = digest.zero_pages;
-        stat.private_pages = digest.private_pages.len();
+        let mut stat = Stats {
+            zero_pages: digest.zero_pages,
+            private_pages: digest.private_pages.len(),
+            ..Default::default()
+        };
 
         for shared_page in &digest.shared_pages {
             if is_unique_shared_page(&digests, path, shared_page) {
@@ -273,8 +442,8 @@ fn print_intersections(digests: HashMap<std::path::PathBuf, JifDigest>) {
     }
 
     let max_width = stats
-        .iter()
-        .filter_map(|(path, _stat)| path.as_path().to_str().map(|s| s.len()))
+        .keys()
+        .filter_map(|path| path.as_path().to_str().map(|s| s.len()))
         .chain(std::iter::once("filename".len()))
         .max()
         .unwrap_or("filename".len());
@@ -306,8 +475,18 @@ fn print_intersections(digests: HashMap<std::path::PathBuf, JifDigest>) {
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    if cli.delta {
+        let [base, target] = cli.jif_files.as_slice() else {
+            anyhow::bail!("--delta compares two JIFs: a base and a target");
+        };
+        let report = delta::compare(&open_jif(base)?, &open_jif(target)?, &cli.chroot)?;
+        delta::print_delta(&report);
+        return Ok(());
+    }
+
     let include_private = !cli.shared;
-    let include_shared = !cli.private;
+    let include_shared = !cli.private && !cli.cluster;
+    let mut resolver = SharedPageResolver::new(cli.hash_shared, cli.chroot);
     let hashes = cli
         .jif_files
         .into_iter()
@@ -315,7 +494,7 @@ fn main() -> anyhow::Result<()> {
             let jif = open_jif(&p)?;
 
             let digest = if cli.ordering {
-                build_ordering_digest(&jif, include_private, include_shared)
+                build_ordering_digest(&jif, include_private, include_shared, &mut resolver)?
             } else {
                 let mut digest = JifDigest::default();
                 if include_private {
@@ -323,7 +502,7 @@ fn main() -> anyhow::Result<()> {
                 }
 
                 if include_shared {
-                    digest.shared_pages = build_shared_pages_set(&jif);
+                    digest.shared_pages = build_shared_pages_set(&jif, &mut resolver)?;
                 }
 
                 digest.zero_pages = jif.zero_pages();
@@ -333,7 +512,22 @@ fn main() -> anyhow::Result<()> {
 
             Ok::<_, anyhow::Error>((p, digest))
         })
-        .collect::<Result<HashMap<_, _>, _>>()?;
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if cli.cluster {
+        let names = hashes
+            .iter()
+            .map(|(path, _digest)| format!("{}", path.display()))
+            .collect::<Vec<_>>();
+        let sets = hashes
+            .iter()
+            .map(|(_path, digest)| &digest.private_pages)
+            .collect::<Vec<_>>();
+        cluster::print_clusters(&names, &cluster::similarity_matrix(&sets), cli.threshold);
+        return Ok(());
+    }
+
+    let hashes = hashes.into_iter().collect::<HashMap<_, _>>();
 
     if let Some(output) = cli.output {
         let plot_title = if cli.shared {
@@ -343,7 +537,7 @@ fn main() -> anyhow::Result<()> {
         } else {
             "all"
         };
-        plot_intersections(hashes, plot_title, output)
+        plot_intersections(hashes, plot_title, output, cli.backend)
     } else {
         print_intersections(hashes);
         Ok(())
@@ -0,0 +1,211 @@
+//! Native rendering of upset plots as SVG
+//!
+//! The layout follows the one of `upsetplot`: the intersection sizes are drawn as vertical bars
+//! on top of a membership matrix (one row per set, one column per intersection), while the set
+//! sizes are drawn as horizontal bars to the left of the matrix
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+/// Width of an intersection column
+const COLUMN_WIDTH: usize = 28;
+
+/// Height of a set row
+const ROW_HEIGHT: usize = 28;
+
+/// Height of the tallest intersection bar
+const BAR_HEIGHT: usize = 240;
+
+/// Width of the widest set size bar
+const SET_BAR_WIDTH: usize = 160;
+
+/// Room left for the set size counts
+const SET_COUNT_WIDTH: usize = 48;
+
+/// Approximate width of a character of the labels
+const CHAR_WIDTH: usize = 8;
+
+const MARGIN: usize = 20;
+const TITLE_HEIGHT: usize = 40;
+const DOT_RADIUS: usize = 8;
+
+/// Where the set size bars end (they grow to the left)
+const SET_BARS_END: usize = MARGIN + SET_COUNT_WIDTH + SET_BAR_WIDTH;
+
+/// Render the upset plot of the `sets` (pairs of name and elements)
+///
+/// The intersections are exclusive (each element is counted in the intersection of exactly the
+/// sets it belongs to) and sorted by size
+pub(crate) fn render_upset(title: &str, sets: &[(String, HashSet<String>)]) -> String {
+    // membership of each element -> number of elements
+    let mut intersections: BTreeMap<Vec<bool>, usize> = BTreeMap::new();
+    for element in sets.iter().flat_map(|(_name, elements)| elements) {
+        let membership = sets
+            .iter()
+            .map(|(_name, elements)| elements.contains(element))
+            .collect::<Vec<_>>();
+        *intersections.entry(membership).or_default() += 1;
+    }
+
+    // every element was counted once per set it belongs to
+    let mut intersections = intersections
+        .into_iter()
+        .map(|(membership, count)| {
+            let degree = membership.iter().filter(|m| **m).count();
+            (membership, count / degree)
+        })
+        .collect::<Vec<_>>();
+    intersections.sort_by(|(m1, c1), (m2, c2)| c2.cmp(c1).then_with(|| m1.cmp(m2)));
+
+    let max_intersection = intersections
+        .iter()
+        .map(|(_m, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let max_set = sets
+        .iter()
+        .map(|(_name, elements)| elements.len())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let label_width = sets
+        .iter()
+        .map(|(name, _elements)| name.len())
+        .max()
+        .unwrap_or(0)
+        * CHAR_WIDTH
+        + MARGIN;
+
+    let matrix_x = SET_BARS_END + label_width;
+    let matrix_y = TITLE_HEIGHT + BAR_HEIGHT + MARGIN;
+    let width = matrix_x + intersections.len() * COLUMN_WIDTH + MARGIN;
+    let height = matrix_y + sets.len() * ROW_HEIGHT + MARGIN;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{width}" height="{height}" fill="white"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle" font-size="16">{}</text>"#,
+        width / 2,
+        TITLE_HEIGHT / 2 + 6,
+        escape(title)
+    );
+
+    // intersection sizes
+    for (column, (_membership, count)) in intersections.iter().enumerate() {
+        let bar_height = count * BAR_HEIGHT / max_intersection;
+        let x = matrix_x + column * COLUMN_WIDTH;
+        let y = TITLE_HEIGHT + BAR_HEIGHT - bar_height;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{y}" width="{}" height="{bar_height}" fill="black"/>"#,
+            x + 4,
+            COLUMN_WIDTH - 8,
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle" font-size="9">{}</text>"#,
+            x + COLUMN_WIDTH / 2,
+            y.saturating_sub(3),
+            with_separators(*count)
+        );
+    }
+
+    // set names and sizes
+    for (row, (name, elements)) in sets.iter().enumerate() {
+        let y = matrix_y + row * ROW_HEIGHT;
+        if row % 2 == 0 {
+            let _ = writeln!(
+                svg,
+                r##"<rect x="{}" y="{y}" width="{}" height="{ROW_HEIGHT}" fill="#eeeeee"/>"##,
+                SET_BARS_END,
+                width - MARGIN - SET_BARS_END,
+            );
+        }
+
+        let bar_width = elements.len() * SET_BAR_WIDTH / max_set;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{}" y="{}" width="{bar_width}" height="{}" fill="black"/>"#,
+            SET_BARS_END - bar_width,
+            y + 4,
+            ROW_HEIGHT - 8,
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end" font-size="9">{}</text>"#,
+            (SET_BARS_END - bar_width).saturating_sub(3),
+            y + ROW_HEIGHT / 2 + 3,
+            with_separators(elements.len())
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+            matrix_x - MARGIN / 2,
+            y + ROW_HEIGHT / 2 + 4,
+            escape(name)
+        );
+    }
+
+    // membership matrix
+    for (column, (membership, _count)) in intersections.iter().enumerate() {
+        let x = matrix_x + column * COLUMN_WIDTH + COLUMN_WIDTH / 2;
+        let member_y = |row: usize| matrix_y + row * ROW_HEIGHT + ROW_HEIGHT / 2;
+
+        let mut members = membership
+            .iter()
+            .enumerate()
+            .filter(|(_row, member)| **member)
+            .map(|(row, _member)| row);
+        if let (Some(first), Some(last)) = (members.clone().next(), members.next_back()) {
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="black" stroke-width="3"/>"#,
+                member_y(first),
+                member_y(last),
+            );
+        }
+
+        for (row, member) in membership.iter().enumerate() {
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{x}" cy="{}" r="{DOT_RADIUS}" fill="{}"/>"#,
+                member_y(row),
+                if *member { "black" } else { "lightgray" },
+            );
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Escape the XML special characters
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format a number with thousands separators (as `show_counts='{:,}'` does in python)
+fn with_separators(n: usize) -> String {
+    let digits = n.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    formatted
+}
@@ -0,0 +1,100 @@
+/*
+ * C API of the `jif` library (see `src/lib.rs`)
+ *
+ * The JIFs are opaque handles, opened by `jif_open` (or `jif_from_bytes`) and released by
+ * `jif_free`. Every fallible function returns a status (`JIF_OK`, or a negative `JIF_ERR_*`
+ * code), the message of the last failure of the calling thread being kept by `jif_last_error`.
+ * The strings and buffers handed out are owned by the handle, and valid until it is freed.
+ * A panic of the library does not unwind into the caller: the function fails with
+ * `JIF_ERR_PANIC` (or returns what it returns for a null handle, if it has no status).
+ *
+ * Keep in sync with `src/lib.rs` (the `header` test checks every function is declared).
+ */
+
+#ifndef JIF_H
+#define JIF_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* statuses */
+#define JIF_OK 0
+#define JIF_ERR_INVALID (-1)      /* a null pointer (or a path which is not UTF-8) was passed */
+#define JIF_ERR_IO (-2)           /* a file could not be opened or read */
+#define JIF_ERR_PARSE (-3)        /* the JIF is malformed (or a pathname holds a nul) */
+#define JIF_ERR_OUT_OF_RANGE (-4) /* an index is past the end of the pheaders (or intervals) */
+#define JIF_ERR_NOT_MAPPED (-5)   /* an address is not mapped by the JIF */
+#define JIF_ERR_NOT_PRIVATE (-6)  /* an address is not backed by private data */
+#define JIF_ERR_PANIC (-7)        /* the library panicked (see the message of jif_last_error) */
+
+/* protection bits of the pheaders */
+#define JIF_PROT_READ (1 << 2)
+#define JIF_PROT_WRITE (1 << 1)
+#define JIF_PROT_EXEC (1 << 0)
+
+/* data sources of the logical intervals */
+#define JIF_SOURCE_ZERO 0
+#define JIF_SOURCE_SHARED 1
+#define JIF_SOURCE_PRIVATE 2
+
+typedef struct JifHandle jif_t;
+
+typedef struct {
+    uint64_t start; /* virtual address range, [start; end) */
+    uint64_t end;
+    uint8_t prot;         /* JIF_PROT_* bits */
+    const char *pathname; /* referenced file (NULL for the anonymous pheaders) */
+    uint64_t ref_offset;  /* offset of the range into the referenced file */
+    size_t n_intervals;   /* number of logical intervals */
+} jif_pheader_t;
+
+typedef struct {
+    uint64_t start; /* virtual address range, [start; end) */
+    uint64_t end;
+    uint8_t source; /* JIF_SOURCE_* */
+} jif_interval_t;
+
+/* message of the last failure of the calling thread (NULL if none failed) */
+const char *jif_last_error(void);
+
+/* open and parse the JIF at `path` */
+int jif_open(const char *path, jif_t **out);
+
+/* parse a JIF from the `len` bytes at `data` */
+int jif_from_bytes(const uint8_t *data, size_t len, jif_t **out);
+
+/* release a JIF (and everything handed out from it) */
+void jif_free(jif_t *jif);
+
+/* size of the pages of the JIF, in B */
+size_t jif_page_size(const jif_t *jif);
+
+/* number of pheaders of the JIF */
+size_t jif_n_pheaders(const jif_t *jif);
+
+/* describe the pheader `idx` (in address order) */
+int jif_pheader(const jif_t *jif, size_t idx, jif_pheader_t *out);
+
+/* describe the logical interval `idx` (in address order) of the pheader `pheader` */
+int jif_interval(const jif_t *jif, size_t pheader, size_t idx, jif_interval_t *out);
+
+/* resolve an address into the logical interval mapping it (and the index of its pheader, if
+ * `pheader` is not NULL) */
+int jif_resolve(const jif_t *jif, uint64_t addr, size_t *pheader, jif_interval_t *out);
+
+/* point `page` to the private data of the page holding `addr` (jif_page_size bytes) */
+int jif_private_page(const jif_t *jif, uint64_t addr, const uint8_t **page);
+
+/* read the logical contents of [addr; addr + len) into `buf`: private data, zero pages and
+ * contents of the referenced files (relative to `chroot`, unless it is NULL) */
+int jif_read_range(const jif_t *jif, uint64_t addr, size_t len, const char *chroot, uint8_t *buf);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* JIF_H */
@@ -0,0 +1,637 @@
+//! C API of the `jif` library
+//!
+//! Exposes the parsing of JIFs, the queries of their pheaders and logical intervals and the
+//! access to their pages over a stable C ABI (declared in `include/jif.h`), such that the junction
+//! runtime (C++) and the analysis notebooks (Python, with `ctypes`) reuse this implementation.
+//!
+//! Conventions:
+//!  - the JIFs are opaque handles (`jif_t`), opened by `jif_open` (or `jif_from_bytes`) and
+//!    released by `jif_free`;
+//!  - every fallible function returns a status (`JIF_OK`, or a negative `JIF_ERR_*` code), the
+//!    message of the last failure of the calling thread being kept by `jif_last_error`;
+//!  - the strings and buffers handed out (e.g., the pathnames and the private pages) are owned
+//!    by the handle, and valid until it is freed;
+//!  - a panic of the library does not unwind into the caller: the function fails instead (with
+//!    `JIF_ERR_PANIC`, or the value of a null handle for the functions without a status)
+
+use jif::itree::interval::{DataSource, LogicalInterval};
+use jif::{Jif, JifError};
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+
+pub const JIF_OK: c_int = 0;
+/// A null pointer (or a path which is not UTF-8) was passed
+pub const JIF_ERR_INVALID: c_int = -1;
+/// A file could not be opened or read
+pub const JIF_ERR_IO: c_int = -2;
+/// The JIF is malformed (or cannot be handed out, e.g., a pathname holds a nul)
+pub const JIF_ERR_PARSE: c_int = -3;
+/// An index is past the end of the pheaders (or of the intervals of a pheader)
+pub const JIF_ERR_OUT_OF_RANGE: c_int = -4;
+/// An address is not mapped by the JIF
+pub const JIF_ERR_NOT_MAPPED: c_int = -5;
+/// An address is not backed by private data
+pub const JIF_ERR_NOT_PRIVATE: c_int = -6;
+/// The library panicked (the panic stops at the API, instead of unwinding into the caller)
+pub const JIF_ERR_PANIC: c_int = -7;
+
+pub const JIF_SOURCE_ZERO: u8 = 0;
+pub const JIF_SOURCE_SHARED: u8 = 1;
+pub const JIF_SOURCE_PRIVATE: u8 = 2;
+
+/// A JIF opened through the C API (`jif_t`)
+///
+/// The pathnames (as C strings) and the logical intervals are resolved when the JIF is opened,
+/// such that the queries only index into them
+pub struct JifHandle {
+    jif: Jif,
+    pathnames: Vec<Option<CString>>,
+    intervals: Vec<Vec<LogicalInterval>>,
+}
+
+impl JifHandle {
+    /// Fails if a pathname cannot be handed out as a C string (i.e., it holds a nul)
+    fn new(jif: Jif) -> Result<Self, String> {
+        let pathnames = jif
+            .pheaders()
+            .iter()
+            .map(|pheader| {
+                pheader
+                    .pathname()
+                    .map(|path| {
+                        CString::new(path).map_err(|_| format!("pathname {:?} holds a nul", path))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        let intervals = jif
+            .pheaders()
+            .iter()
+            .map(|pheader| pheader.itree().iter_logical_intervals().collect())
+            .collect();
+
+        Ok(JifHandle {
+            jif,
+            pathnames,
+            intervals,
+        })
+    }
+}
+
+/// A pheader (`jif_pheader_t`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct JifPheaderInfo {
+    /// Virtual address range, `[start; end)`
+    pub start: u64,
+    pub end: u64,
+
+    /// Protections (`JIF_PROT_*` bits)
+    pub prot: u8,
+
+    /// Referenced file (null for the anonymous pheaders)
+    pub pathname: *const c_char,
+
+    /// Offset of the range into the referenced file
+    pub ref_offset: u64,
+
+    /// Number of logical intervals
+    pub n_intervals: usize,
+}
+
+/// A logical interval (`jif_interval_t`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JifIntervalInfo {
+    /// Virtual address range, `[start; end)`
+    pub start: u64,
+    pub end: u64,
+
+    /// Data source (`JIF_SOURCE_*`)
+    pub source: u8,
+}
+
+impl From<&LogicalInterval> for JifIntervalInfo {
+    fn from(ival: &LogicalInterval) -> Self {
+        JifIntervalInfo {
+            start: ival.start,
+            end: ival.end,
+            source: match ival.source {
+                DataSource::Zero => JIF_SOURCE_ZERO,
+                DataSource::Shared => JIF_SOURCE_SHARED,
+                DataSource::Private => JIF_SOURCE_PRIVATE,
+            },
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Record the message of a failure, returning its status
+fn fail(status: c_int, message: impl std::fmt::Display) -> c_int {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    status
+}
+
+/// Borrow a C string as a `&str`
+///
+/// # Safety
+/// `s` has to be null or point to a nul terminated string
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, c_int> {
+    if s.is_null() {
+        return Err(fail(JIF_ERR_INVALID, format!("{} is null", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| fail(JIF_ERR_INVALID, format!("{} is not UTF-8", name)))
+}
+
+/// Hand a new handle out through `out`
+///
+/// # Safety
+/// `out` has to be valid for writes
+unsafe fn hand_out(jif: Jif, out: *mut *mut JifHandle) -> c_int {
+    match JifHandle::new(jif) {
+        Ok(handle) => {
+            *out = Box::into_raw(Box::new(handle));
+            JIF_OK
+        }
+        Err(message) => fail(JIF_ERR_PARSE, message),
+    }
+}
+
+/// Run the body of an exported function, turning a panic into a failure (a panic unwinding
+/// across the C ABI aborts the caller): `on_panic` is returned, with the message of the panic as
+/// the last error
+fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    std::panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        fail(JIF_ERR_PANIC, format!("panicked: {}", message));
+        on_panic
+    })
+}
+
+/// Message of the last failure of the calling thread (null if none failed)
+///
+/// The message is valid until the next failure of the thread
+#[no_mangle]
+pub extern "C" fn jif_last_error() -> *const c_char {
+    catch_panic(std::ptr::null(), || {
+        LAST_ERROR.with(|last| {
+            last.borrow()
+                .as_ref()
+                .map_or(std::ptr::null(), |message| message.as_ptr())
+        })
+    })
+}
+
+/// Open and parse the JIF at `path`
+///
+/// # Safety
+/// `path` has to be a nul terminated string and `out` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_open(path: *const c_char, out: *mut *mut JifHandle) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        if out.is_null() {
+            return fail(JIF_ERR_INVALID, "out is null");
+        }
+        let path = match str_arg(path, "path") {
+            Ok(path) => path,
+            Err(status) => return status,
+        };
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) => return fail(JIF_ERR_IO, format!("failed to open {}: {}", path, error)),
+        };
+        match Jif::from_reader(&mut BufReader::new(file)) {
+            Ok(jif) => hand_out(jif, out),
+            Err(error) => fail(JIF_ERR_PARSE, error),
+        }
+    })
+}
+
+/// Parse a JIF from the `len` bytes at `data`
+///
+/// # Safety
+/// `data` has to be valid for `len` bytes of reads and `out` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_from_bytes(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut JifHandle,
+) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        if data.is_null() || out.is_null() {
+            return fail(JIF_ERR_INVALID, "data or out is null");
+        }
+
+        match Jif::from_bytes(std::slice::from_raw_parts(data, len)) {
+            Ok(jif) => hand_out(jif, out),
+            Err(error) => fail(JIF_ERR_PARSE, error),
+        }
+    })
+}
+
+/// Release a JIF (and everything handed out from it)
+///
+/// # Safety
+/// `jif` has to be null or a handle of `jif_open` (or `jif_from_bytes`) not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn jif_free(jif: *mut JifHandle) {
+    catch_panic((), || {
+        if !jif.is_null() {
+            drop(Box::from_raw(jif));
+        }
+    })
+}
+
+/// Size of the pages of the JIF, in B (0 for a null handle)
+///
+/// # Safety
+/// `jif` has to be null or a live handle
+#[no_mangle]
+pub unsafe extern "C" fn jif_page_size(jif: *const JifHandle) -> usize {
+    catch_panic(0, || jif.as_ref().map_or(0, |jif| jif.jif.page_size()))
+}
+
+/// Number of pheaders of the JIF (0 for a null handle)
+///
+/// # Safety
+/// `jif` has to be null or a live handle
+#[no_mangle]
+pub unsafe extern "C" fn jif_n_pheaders(jif: *const JifHandle) -> usize {
+    catch_panic(0, || jif.as_ref().map_or(0, |jif| jif.jif.pheaders().len()))
+}
+
+/// Describe the pheader `idx` (in address order)
+///
+/// # Safety
+/// `jif` has to be a live handle and `out` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_pheader(
+    jif: *const JifHandle,
+    idx: usize,
+    out: *mut JifPheaderInfo,
+) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        let Some(jif) = jif.as_ref().filter(|_| !out.is_null()) else {
+            return fail(JIF_ERR_INVALID, "jif or out is null");
+        };
+        let Some(pheader) = jif.jif.pheaders().get(idx) else {
+            return fail(
+                JIF_ERR_OUT_OF_RANGE,
+                format!("no pheader {} (of {})", idx, jif.jif.pheaders().len()),
+            );
+        };
+
+        let (start, end) = pheader.virtual_range();
+        *out = JifPheaderInfo {
+            start,
+            end,
+            prot: pheader.prot().bits(),
+            pathname: jif.pathnames[idx]
+                .as_ref()
+                .map_or(std::ptr::null(), |path| path.as_ptr()),
+            ref_offset: pheader.ref_offset().unwrap_or(0),
+            n_intervals: jif.intervals[idx].len(),
+        };
+        JIF_OK
+    })
+}
+
+/// Describe the logical interval `idx` (in address order) of the pheader `pheader`
+///
+/// # Safety
+/// `jif` has to be a live handle and `out` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_interval(
+    jif: *const JifHandle,
+    pheader: usize,
+    idx: usize,
+    out: *mut JifIntervalInfo,
+) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        let Some(jif) = jif.as_ref().filter(|_| !out.is_null()) else {
+            return fail(JIF_ERR_INVALID, "jif or out is null");
+        };
+        let Some(ival) = jif
+            .intervals
+            .get(pheader)
+            .and_then(|intervals| intervals.get(idx))
+        else {
+            return fail(
+                JIF_ERR_OUT_OF_RANGE,
+                format!("no interval {} in pheader {}", idx, pheader),
+            );
+        };
+
+        *out = ival.into();
+        JIF_OK
+    })
+}
+
+/// Resolve an address into the logical interval mapping it (and the index of its pheader, if
+/// `pheader` is not null)
+///
+/// # Safety
+/// `jif` has to be a live handle, `pheader` has to be null or valid for writes and `out` has to
+/// be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_resolve(
+    jif: *const JifHandle,
+    addr: u64,
+    pheader: *mut usize,
+    out: *mut JifIntervalInfo,
+) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        let Some(jif) = jif.as_ref().filter(|_| !out.is_null()) else {
+            return fail(JIF_ERR_INVALID, "jif or out is null");
+        };
+        let idx = jif
+            .jif
+            .pheaders()
+            .partition_point(|pheader| pheader.virtual_range().0 <= addr)
+            .checked_sub(1)
+            .filter(|idx| jif.jif.pheaders()[*idx].virtual_range().1 > addr);
+        let Some(idx) = idx else {
+            return fail(JIF_ERR_NOT_MAPPED, format!("{:#x} is not mapped", addr));
+        };
+
+        let ival = &jif.intervals[idx][jif.intervals[idx].partition_point(|ival| ival.end <= addr)];
+        if !pheader.is_null() {
+            *pheader = idx;
+        }
+        *out = ival.into();
+        JIF_OK
+    })
+}
+
+/// Point `page` to the private data of the page holding `addr` (`jif_page_size` bytes, owned
+/// by the handle)
+///
+/// # Safety
+/// `jif` has to be a live handle and `page` has to be valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_private_page(
+    jif: *const JifHandle,
+    addr: u64,
+    page: *mut *const u8,
+) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        let Some(jif) = jif.as_ref().filter(|_| !page.is_null()) else {
+            return fail(JIF_ERR_INVALID, "jif or page is null");
+        };
+        let page_addr = addr - addr % jif.jif.page_size() as u64;
+        match (jif.jif.resolve(page_addr), jif.jif.resolve_data(page_addr)) {
+            (None, _) => fail(JIF_ERR_NOT_MAPPED, format!("{:#x} is not mapped", addr)),
+            (Some(_), None) => fail(
+                JIF_ERR_NOT_PRIVATE,
+                format!("{:#x} is not backed by private data", addr),
+            ),
+            (Some(_), Some(data)) => {
+                *page = data.as_ptr();
+                JIF_OK
+            }
+        }
+    })
+}
+
+/// Read the logical contents of `[addr; addr + len)` into `buf`: private data, zero pages and
+/// contents of the referenced files (relative to `chroot`, unless it is null)
+///
+/// # Safety
+/// `jif` has to be a live handle, `chroot` has to be null or a nul terminated string and `buf`
+/// has to be valid for `len` bytes of writes
+#[no_mangle]
+pub unsafe extern "C" fn jif_read_range(
+    jif: *const JifHandle,
+    addr: u64,
+    len: usize,
+    chroot: *const c_char,
+    buf: *mut u8,
+) -> c_int {
+    catch_panic(JIF_ERR_PANIC, || {
+        let Some(jif) = jif.as_ref().filter(|_| !buf.is_null()) else {
+            return fail(JIF_ERR_INVALID, "jif or buf is null");
+        };
+        let chroot = match chroot.is_null() {
+            true => None,
+            false => match str_arg(chroot, "chroot") {
+                Ok(chroot) => Some(PathBuf::from(chroot)),
+                Err(status) => return status,
+            },
+        };
+
+        match jif.jif.read_range(addr, len as u64, &chroot) {
+            Ok(data) => {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+                JIF_OK
+            }
+            Err(error @ JifError::UnmappedAddress { .. }) => fail(JIF_ERR_NOT_MAPPED, error),
+            Err(error) => fail(JIF_ERR_IO, error),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jif::{JifBuilder, JifRaw, ProtFlags};
+
+    const PAGE_SIZE: usize = 0x1000;
+
+    fn open() -> *mut JifHandle {
+        let mut builder = JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x10000, 0x14000),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![(0x11000, vec![0xcc; 2 * PAGE_SIZE])],
+            )
+            .add_reference_segment(
+                (0x20000, 0x22000),
+                ProtFlags::READ | ProtFlags::EXEC,
+                "/lib/libc.so".to_string(),
+                0x3000,
+                vec![],
+            );
+        let mut bytes = Vec::new();
+        JifRaw::from_materialized(builder.build().unwrap(), false)
+            .to_writer(&mut bytes)
+            .unwrap();
+
+        let mut jif = std::ptr::null_mut();
+        assert_eq!(
+            unsafe { jif_from_bytes(bytes.as_ptr(), bytes.len(), &mut jif) },
+            JIF_OK
+        );
+        jif
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(jif_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn query() {
+        let jif = open();
+        unsafe {
+            assert_eq!(jif_page_size(jif), PAGE_SIZE);
+            assert_eq!(jif_n_pheaders(jif), 2);
+
+            let mut pheader = std::mem::zeroed::<JifPheaderInfo>();
+            assert_eq!(jif_pheader(jif, 1, &mut pheader), JIF_OK);
+            assert_eq!((pheader.start, pheader.end), (0x20000, 0x22000));
+            assert_eq!(pheader.prot, (ProtFlags::READ | ProtFlags::EXEC).bits());
+            assert_eq!(
+                CStr::from_ptr(pheader.pathname).to_str(),
+                Ok("/lib/libc.so")
+            );
+            assert_eq!((pheader.ref_offset, pheader.n_intervals), (0x3000, 1));
+
+            assert_eq!(jif_pheader(jif, 0, &mut pheader), JIF_OK);
+            assert!(pheader.pathname.is_null());
+            assert_eq!(pheader.n_intervals, 3);
+
+            let mut ival = std::mem::zeroed::<JifIntervalInfo>();
+            assert_eq!(jif_interval(jif, 0, 1, &mut ival), JIF_OK);
+            assert_eq!(
+                ival,
+                JifIntervalInfo {
+                    start: 0x11000,
+                    end: 0x13000,
+                    source: JIF_SOURCE_PRIVATE
+                }
+            );
+
+            let mut idx = 0;
+            assert_eq!(jif_resolve(jif, 0x13800, &mut idx, &mut ival), JIF_OK);
+            assert_eq!((idx, ival.source), (0, JIF_SOURCE_ZERO));
+            assert_eq!(jif_resolve(jif, 0x21000, &mut idx, &mut ival), JIF_OK);
+            assert_eq!((idx, ival.source), (1, JIF_SOURCE_SHARED));
+
+            assert_eq!(jif_pheader(jif, 2, &mut pheader), JIF_ERR_OUT_OF_RANGE);
+            assert_eq!(last_error(), "no pheader 2 (of 2)");
+            assert_eq!(
+                jif_resolve(jif, 0x18000, std::ptr::null_mut(), &mut ival),
+                JIF_ERR_NOT_MAPPED
+            );
+
+            jif_free(jif);
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let jif = open();
+        unsafe {
+            let mut page = std::ptr::null();
+            assert_eq!(jif_private_page(jif, 0x12010, &mut page), JIF_OK);
+            assert_eq!(
+                std::slice::from_raw_parts(page, PAGE_SIZE),
+                &[0xcc; PAGE_SIZE]
+            );
+            assert_eq!(
+                jif_private_page(jif, 0x10000, &mut page),
+                JIF_ERR_NOT_PRIVATE
+            );
+            assert_eq!(
+                jif_private_page(jif, 0x30000, &mut page),
+                JIF_ERR_NOT_MAPPED
+            );
+
+            // across the end of the private data, into the zero page
+            let mut buf = [0xffu8; 0x20];
+            assert_eq!(
+                jif_read_range(jif, 0x12ff0, buf.len(), std::ptr::null(), buf.as_mut_ptr()),
+                JIF_OK
+            );
+            assert_eq!(buf[..0x10], [0xcc; 0x10]);
+            assert_eq!(buf[0x10..], [0; 0x10]);
+
+            assert_eq!(
+                jif_read_range(jif, 0x13ff0, buf.len(), std::ptr::null(), buf.as_mut_ptr()),
+                JIF_ERR_NOT_MAPPED
+            );
+
+            jif_free(jif);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut jif = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(jif_from_bytes(b"JIF".as_ptr(), 3, &mut jif), JIF_ERR_PARSE);
+            assert!(jif.is_null());
+
+            let path = CString::new("/nonexistent/a.jif").unwrap();
+            assert_eq!(jif_open(path.as_ptr(), &mut jif), JIF_ERR_IO);
+            assert!(last_error().starts_with("failed to open /nonexistent/a.jif"));
+            assert_eq!(jif_open(std::ptr::null(), &mut jif), JIF_ERR_INVALID);
+
+            assert_eq!(jif_n_pheaders(std::ptr::null()), 0);
+            jif_free(std::ptr::null_mut());
+        }
+
+        // the pathnames are handed out as C strings
+        let mut builder = JifBuilder::new();
+        builder.add_reference_segment(
+            (0x20000, 0x22000),
+            ProtFlags::READ,
+            "/lib/lib\0c.so".to_string(),
+            0,
+            vec![],
+        );
+        assert_eq!(
+            unsafe { hand_out(builder.build().unwrap(), &mut jif) },
+            JIF_ERR_PARSE
+        );
+        assert_eq!(last_error(), "pathname \"/lib/lib\\0c.so\" holds a nul");
+        assert!(jif.is_null());
+
+        // and the panics do not unwind out of the API
+        assert_eq!(
+            catch_panic(JIF_ERR_PANIC, || panic!("bad {}", 1)),
+            JIF_ERR_PANIC
+        );
+        assert_eq!(last_error(), "panicked: bad 1");
+        assert_eq!(catch_panic(0usize, || panic!("bad")), 0);
+        assert_eq!(last_error(), "panicked: bad");
+    }
+
+    /// The header declares every function of the API
+    #[test]
+    fn header() {
+        let header = include_str!("../include/jif.h");
+        let exported = include_str!("lib.rs")
+            .lines()
+            .filter_map(|line| line.split_once("extern \"C\" fn "))
+            .filter_map(|(_prefix, signature)| signature.split_once('('))
+            .map(|(name, _args)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(exported.len(), 11);
+        for name in exported {
+            assert!(
+                header.contains(&format!("{}(", name)),
+                "{} is not declared",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Benchmarks of the hot paths: parsing, writing, building the interval trees, fragmenting and
+//! setting up the prefetch
+//!
+//! Run with `cargo bench -p jif [-- <filter>]`; the benchmarks run over synthetic JIFs
+//! (see [`SyntheticJif`]), whose size is set by the environment:
+//!  - `JIF_BENCH_PHEADERS`: number of pheaders (default: 64)
+//!  - `JIF_BENCH_PAGES`: number of pages per pheader (default: 256)
+//!  - `JIF_BENCH_ITERS`: number of timed iterations of each benchmark (default: 20)
+//!
+//! For long-running comparisons, the JIFs can be made arbitrarily large: each benchmark reports
+//! the minimum, median and mean time per iteration, and the throughput over the JIF's pages
+
+use jif::{AsRecorded, Jif, JifRaw, SyntheticJif};
+
+use std::hint::black_box;
+use std::io::{BufReader, Cursor};
+use std::time::{Duration, Instant};
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+struct Bench {
+    filter: Option<String>,
+    iters: usize,
+    n_pages: usize,
+}
+
+impl Bench {
+    /// Time `routine` over a fresh input from `setup` (which is not timed)
+    fn run<I, O>(&self, name: &str, mut setup: impl FnMut() -> I, mut routine: impl FnMut(I) -> O) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !name.contains(filter.as_str()))
+        {
+            return;
+        }
+
+        // warm up
+        black_box(routine(setup()));
+
+        let mut times = (0..self.iters)
+            .map(|_| {
+                let input = setup();
+                let start = Instant::now();
+                black_box(routine(input));
+                start.elapsed()
+            })
+            .collect::<Vec<_>>();
+        times.sort_unstable();
+
+        let mean = times.iter().sum::<Duration>() / self.iters as u32;
+        let median = times[times.len() / 2];
+        println!(
+            "{:<16} min: {:>10.3?}  median: {:>10.3?}  mean: {:>10.3?}  ({:.0} pages/s)",
+            name,
+            times[0],
+            median,
+            mean,
+            self.n_pages as f64 / median.as_secs_f64()
+        );
+    }
+}
+
+fn main() {
+    let params = SyntheticJif::new(
+        env_or("JIF_BENCH_PHEADERS", 64),
+        env_or("JIF_BENCH_PAGES", 256),
+    );
+    let bench = Bench {
+        // cargo passes `--bench` (and other flags) along
+        filter: std::env::args().skip(1).find(|arg| !arg.starts_with('-')),
+        iters: env_or("JIF_BENCH_ITERS", 20).max(1),
+        n_pages: params.n_pages(),
+    };
+    println!(
+        "{} pheaders x {} pages ({} iterations)",
+        params.n_pheaders, params.pages_per_pheader, bench.iters
+    );
+
+    let generate = || params.generate().expect("failed to generate the JIF");
+    let built = || {
+        let mut jif = generate();
+        jif.build_itrees(&AsRecorded, None)
+            .expect("failed to build the interval trees");
+        jif
+    };
+    let write = |raw: JifRaw| {
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).expect("failed to write the JIF");
+        buffer
+    };
+    let file = write(JifRaw::from_materialized(built(), true));
+
+    bench.run(
+        "from_reader",
+        || file.clone(),
+        |file| {
+            Jif::from_reader(&mut BufReader::new(Cursor::new(file)))
+                .expect("failed to read the JIF")
+        },
+    );
+    bench.run(
+        "to_writer",
+        || JifRaw::from_materialized(built(), false),
+        write,
+    );
+    bench.run("build_itrees", generate, |mut jif: Jif| {
+        jif.build_itrees(&AsRecorded, None)
+            .expect("failed to build the interval trees");
+        jif
+    });
+    bench.run("fragment", generate, |mut jif: Jif| {
+        jif.fragment(&AsRecorded, None)
+            .expect("failed to fragment the JIF");
+        jif
+    });
+    bench.run("setup_prefetch", built, |jif| {
+        JifRaw::from_materialized(jif, true)
+    });
+}
@@ -0,0 +1,25 @@
+#![no_main]
+
+use jif::{Jif, JifRaw};
+use libfuzzer_sys::fuzz_target;
+
+use std::io::{BufReader, Cursor};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(jif) = Jif::from_reader(&mut BufReader::new(Cursor::new(data))) {
+        jif.validate();
+        for ival in jif
+            .pheaders()
+            .iter()
+            .flat_map(|pheader| pheader.itree().iter_logical_intervals())
+        {
+            jif.resolve_data(ival.start);
+        }
+        jif.restore_footprint();
+        jif.restore_plan();
+
+        // writing it back out (with the prefetch set up) goes through the fracturing path
+        let mut buffer = Vec::new();
+        let _ = JifRaw::from_materialized(jif, true).to_writer(&mut buffer);
+    }
+});
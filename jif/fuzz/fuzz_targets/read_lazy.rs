@@ -0,0 +1,15 @@
+#![no_main]
+
+use jif::LazyJif;
+use libfuzzer_sys::fuzz_target;
+
+use std::io::{BufReader, Cursor};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(lazy) = LazyJif::from_reader(BufReader::new(Cursor::new(data))) {
+        for page in lazy.iter_private_pages() {
+            let _ = page;
+        }
+        let _ = lazy.materialize();
+    }
+});
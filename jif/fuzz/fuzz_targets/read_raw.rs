@@ -0,0 +1,13 @@
+#![no_main]
+
+use jif::JifRaw;
+use libfuzzer_sys::fuzz_target;
+
+use std::io::{BufReader, Cursor};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(raw) = JifRaw::from_reader(&mut BufReader::new(Cursor::new(data))) {
+        raw.validate();
+        raw.prefetch_layout();
+    }
+});
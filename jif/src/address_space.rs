@@ -0,0 +1,125 @@
+//! Address space walk
+//!
+//! The logical intervals of every pheader, stitched together in address order (with the gaps
+//! between the pheaders), so that the whole address space of a JIF can be walked without going
+//! through [`Jif::resolve`] page by page (see [`Jif::iter_logical_intervals`])
+
+use crate::itree::interval::{DataSource, LogicalInterval};
+use crate::jif::Jif;
+
+/// An interval of the address space of a JIF
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceInterval {
+    /// A logical interval (explicit or not) of a pheader
+    Mapped {
+        /// Index of the pheader
+        pheader_idx: usize,
+
+        interval: LogicalInterval,
+    },
+
+    /// A gap between two pheaders
+    Unmapped { start: u64, end: u64 },
+}
+
+impl SpaceInterval {
+    /// Start address of the interval
+    pub fn start(&self) -> u64 {
+        match self {
+            SpaceInterval::Mapped { interval, .. } => interval.start,
+            SpaceInterval::Unmapped { start, .. } => *start,
+        }
+    }
+
+    /// End address of the interval (exclusive)
+    pub fn end(&self) -> u64 {
+        match self {
+            SpaceInterval::Mapped { interval, .. } => interval.end,
+            SpaceInterval::Unmapped { end, .. } => *end,
+        }
+    }
+
+    /// Data source of the interval (`None` if it is not mapped)
+    pub fn source(&self) -> Option<DataSource> {
+        match self {
+            SpaceInterval::Mapped { interval, .. } => Some(interval.source),
+            SpaceInterval::Unmapped { .. } => None,
+        }
+    }
+}
+
+impl Jif {
+    /// Iterate over the address space, from the start of the first pheader to the end of the
+    /// last one, in address order
+    ///
+    /// Each pheader yields its logical intervals (including the implicit zero and shared ones),
+    /// and each gap between two pheaders an unmapped interval: consecutive intervals are
+    /// contiguous
+    pub fn iter_logical_intervals(&self) -> impl Iterator<Item = SpaceInterval> + '_ {
+        self.pheaders
+            .iter()
+            .enumerate()
+            .flat_map(move |(pheader_idx, pheader)| {
+                let start = pheader.virtual_range().0;
+                let gap = pheader_idx
+                    .checked_sub(1)
+                    .map(|prev_idx| self.pheaders[prev_idx].virtual_range().1)
+                    .filter(|prev_end| *prev_end < start)
+                    .map(|prev_end| SpaceInterval::Unmapped {
+                        start: prev_end,
+                        end: start,
+                    });
+
+                gap.into_iter()
+                    .chain(
+                        pheader
+                            .itree()
+                            .iter_logical_intervals()
+                            .map(move |interval| SpaceInterval::Mapped {
+                                pheader_idx,
+                                interval,
+                            }),
+                    )
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::test::gen_jif;
+
+    #[test]
+    fn iter_logical_intervals() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x5000), &[(0x2000, 0x3000)]),
+            ((0x5000, 0x6000), &[]),
+            ((0x10000, 0x12000), &[(0x10000, 0x12000)]),
+        ]);
+
+        let intervals = jif.iter_logical_intervals().collect::<Vec<_>>();
+        let summary = intervals
+            .iter()
+            .map(|ival| (ival.start(), ival.end(), ival.source()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (0x1000, 0x2000, Some(DataSource::Zero)),
+                (0x2000, 0x3000, Some(DataSource::Private)),
+                (0x3000, 0x5000, Some(DataSource::Zero)),
+                (0x5000, 0x6000, Some(DataSource::Zero)),
+                (0x6000, 0x10000, None),
+                (0x10000, 0x12000, Some(DataSource::Private)),
+            ]
+        );
+        assert!(matches!(
+            intervals[3],
+            SpaceInterval::Mapped { pheader_idx: 1, .. }
+        ));
+        assert!(intervals
+            .iter()
+            .zip(intervals.iter().skip(1))
+            .all(|(prev, next)| prev.end() == next.start()));
+    }
+}
@@ -0,0 +1,280 @@
+//! Architecture tag of a JIF
+//!
+//! A snapshot can only be restored on a host with the same instruction set, endianness and a
+//! compatible page size. The tag is carried in the flags of the header: JIFs written before it
+//! existed read back as [`Isa::Unknown`], little endian, with 4KiB pages
+//!
+//! Note that the JIF itself is always encoded in little endian, the tag describes the memory of
+//! the snapshotted process
+
+use crate::error::*;
+use crate::jif::{JIF_FLAG_BIG_ENDIAN, JIF_ISA_MASK, JIF_PAGE_SHIFT_MASK};
+use crate::utils::{is_aligned, is_valid_page_size, PAGE_SIZE};
+
+const JIF_PAGE_SHIFT_OFFSET: u32 = JIF_PAGE_SHIFT_MASK.trailing_zeros();
+const JIF_ISA_OFFSET: u32 = JIF_ISA_MASK.trailing_zeros();
+
+/// Instruction set architecture of the snapshotted process
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Isa {
+    /// Not recorded (e.g., JIFs written before the tag existed)
+    #[default]
+    Unknown = 0,
+
+    X86_64 = 1,
+    Aarch64 = 2,
+    Riscv64 = 3,
+    Powerpc64 = 4,
+}
+
+impl Isa {
+    /// The instruction set this crate was compiled for
+    pub fn host() -> Self {
+        if cfg!(target_arch = "x86_64") {
+            Isa::X86_64
+        } else if cfg!(target_arch = "aarch64") {
+            Isa::Aarch64
+        } else if cfg!(target_arch = "riscv64") {
+            Isa::Riscv64
+        } else if cfg!(target_arch = "powerpc64") {
+            Isa::Powerpc64
+        } else {
+            Isa::Unknown
+        }
+    }
+
+    /// Page sizes the architecture supports (any valid one if empty)
+    fn page_sizes(&self) -> &'static [usize] {
+        match self {
+            Isa::Unknown => &[],
+            Isa::X86_64 | Isa::Riscv64 => &[PAGE_SIZE],
+            Isa::Aarch64 => &[PAGE_SIZE, 0x4000, 0x10000],
+            Isa::Powerpc64 => &[PAGE_SIZE, 0x10000],
+        }
+    }
+
+    /// Endianness the architecture is restricted to, if any
+    fn endianness(&self) -> Option<Endianness> {
+        match self {
+            Isa::X86_64 | Isa::Riscv64 => Some(Endianness::Little),
+            Isa::Unknown | Isa::Aarch64 | Isa::Powerpc64 => None,
+        }
+    }
+}
+
+impl TryFrom<u32> for Isa {
+    type Error = JifError;
+    fn try_from(isa: u32) -> Result<Self, Self::Error> {
+        match isa {
+            0 => Ok(Isa::Unknown),
+            1 => Ok(Isa::X86_64),
+            2 => Ok(Isa::Aarch64),
+            3 => Ok(Isa::Riscv64),
+            4 => Ok(Isa::Powerpc64),
+            isa => Err(JifError::UnknownIsa { isa }),
+        }
+    }
+}
+
+impl std::fmt::Display for Isa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Isa::Unknown => "unknown",
+            Isa::X86_64 => "x86_64",
+            Isa::Aarch64 => "aarch64",
+            Isa::Riscv64 => "riscv64",
+            Isa::Powerpc64 => "powerpc64",
+        })
+    }
+}
+
+/// Byte order of the memory of the snapshotted process
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// The endianness this crate was compiled for
+    pub fn host() -> Self {
+        if cfg!(target_endian = "big") {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+}
+
+/// Architecture (and ABI) a JIF was captured on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arch {
+    pub isa: Isa,
+    pub endianness: Endianness,
+
+    /// every address, offset and length in the JIF is aligned to the page size
+    pub page_size: usize,
+}
+
+impl Default for Arch {
+    fn default() -> Self {
+        Arch {
+            isa: Isa::default(),
+            endianness: Endianness::default(),
+            page_size: PAGE_SIZE,
+        }
+    }
+}
+
+impl Arch {
+    /// The architecture this crate was compiled for, with pages of `page_size`
+    pub fn host(page_size: usize) -> Self {
+        Arch {
+            isa: Isa::host(),
+            endianness: Endianness::host(),
+            page_size,
+        }
+    }
+
+    /// Whether a JIF of this architecture can be restored on the `host`
+    ///
+    /// The instruction set and endianness have to match (unless unknown), while the pages just
+    /// have to be coarser than the ones of the host
+    pub fn is_compatible_with(&self, host: &Arch) -> bool {
+        (self.isa == Isa::Unknown || host.isa == Isa::Unknown || self.isa == host.isa)
+            && self.endianness == host.endianness
+            && is_aligned(self.page_size as u64, host.page_size)
+    }
+
+    /// Check that the architecture is consistent (i.e., that it supports the page size and the
+    /// endianness)
+    pub fn validate(&self) -> JifResult<()> {
+        if !is_valid_page_size(self.page_size) {
+            return Err(JifError::BadPageSize {
+                page_size: self.page_size as u64,
+            });
+        }
+
+        let page_sizes = self.isa.page_sizes();
+        let page_size_ok = page_sizes.is_empty() || page_sizes.contains(&self.page_size);
+        let endianness_ok = self
+            .isa
+            .endianness()
+            .is_none_or(|endianness| endianness == self.endianness);
+        if !page_size_ok || !endianness_ok {
+            return Err(JifError::BadArch { arch: *self });
+        }
+
+        Ok(())
+    }
+
+    /// Decode (and validate) the architecture from the header flags
+    pub(crate) fn from_flags(flags: u32) -> JifResult<Self> {
+        let shift = (flags & JIF_PAGE_SHIFT_MASK) >> JIF_PAGE_SHIFT_OFFSET;
+        let arch = Arch {
+            isa: Isa::try_from((flags & JIF_ISA_MASK) >> JIF_ISA_OFFSET)?,
+            endianness: if flags & JIF_FLAG_BIG_ENDIAN != 0 {
+                Endianness::Big
+            } else {
+                Endianness::Little
+            },
+            page_size: PAGE_SIZE << shift,
+        };
+        arch.validate()?;
+
+        Ok(arch)
+    }
+
+    /// Encode the architecture into the header flags
+    pub(crate) fn flags(&self) -> u32 {
+        let endianness = match self.endianness {
+            Endianness::Little => 0,
+            Endianness::Big => JIF_FLAG_BIG_ENDIAN,
+        };
+        ((self.page_size / PAGE_SIZE).trailing_zeros() << JIF_PAGE_SHIFT_OFFSET)
+            | ((self.isa as u32) << JIF_ISA_OFFSET)
+            | endianness
+    }
+}
+
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} ({} endian, {}KiB pages)",
+            self.isa,
+            match self.endianness {
+                Endianness::Little => "little",
+                Endianness::Big => "big",
+            },
+            self.page_size / 1024
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flags_roundtrip() {
+        for arch in [
+            Arch::default(),
+            Arch::host(PAGE_SIZE),
+            Arch {
+                isa: Isa::Aarch64,
+                endianness: Endianness::Little,
+                page_size: 0x4000,
+            },
+            Arch {
+                isa: Isa::Powerpc64,
+                endianness: Endianness::Big,
+                page_size: 0x10000,
+            },
+        ] {
+            assert_eq!(Arch::from_flags(arch.flags()).unwrap(), arch);
+        }
+
+        // JIFs written before the tag existed
+        assert_eq!(Arch::from_flags(0).unwrap(), Arch::default());
+
+        assert!(matches!(
+            Arch::from_flags(0xf << JIF_ISA_OFFSET),
+            Err(JifError::UnknownIsa { isa: 0xf })
+        ));
+        assert!(matches!(
+            Arch::from_flags((Isa::X86_64 as u32) << JIF_ISA_OFFSET | JIF_FLAG_BIG_ENDIAN),
+            Err(JifError::BadArch { .. })
+        ));
+        assert!(matches!(
+            Arch::from_flags((Isa::X86_64 as u32) << JIF_ISA_OFFSET | 2 << JIF_PAGE_SHIFT_OFFSET),
+            Err(JifError::BadArch { .. })
+        ));
+    }
+
+    #[test]
+    fn compatibility() {
+        let host = Arch {
+            isa: Isa::Aarch64,
+            endianness: Endianness::Little,
+            page_size: 0x4000,
+        };
+        let jif = |isa, page_size| Arch {
+            isa,
+            endianness: Endianness::Little,
+            page_size,
+        };
+
+        assert!(jif(Isa::Aarch64, 0x4000).is_compatible_with(&host));
+        assert!(jif(Isa::Aarch64, 0x10000).is_compatible_with(&host));
+        assert!(jif(Isa::Unknown, 0x4000).is_compatible_with(&host));
+        assert!(!jif(Isa::Aarch64, PAGE_SIZE).is_compatible_with(&host));
+        assert!(!jif(Isa::X86_64, 0x4000).is_compatible_with(&host));
+        assert!(!Arch {
+            isa: Isa::Aarch64,
+            endianness: Endianness::Big,
+            page_size: 0x4000
+        }
+        .is_compatible_with(&host));
+    }
+}
@@ -0,0 +1,380 @@
+//! Asynchronous reading and writing of JIFs (behind the `async` feature)
+//!
+//! The library does not depend on an async runtime: [`AsyncRead`], [`AsyncSeek`] and
+//! [`AsyncWrite`] are the poll based traits of `futures-io`, so that the I/O types of any runtime
+//! (e.g., tokio's, through the compat layer of `tokio-util`) are adapted in a few lines, and the
+//! futures ([`JifRaw::from_async_reader`], [`JifRaw::to_async_writer`]) run on any executor.
+//! [`Blocking`] adapts the synchronous readers and writers (e.g., in-memory buffers)
+//!
+//! The metadata (header, pheaders, strings, interval trees and ordering chunks) is small, and its
+//! size is known once the header is read: it is read into a buffer and parsed as when reading
+//! synchronously. The data section is streamed, one data segment at a time (unless compressed,
+//! as the compressed section is decompressed as a whole)
+
+use std::future::poll_fn;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(doc)]
+use crate::jif::JifRaw;
+
+/// Read bytes asynchronously (as `futures-io`'s `AsyncRead`)
+pub trait AsyncRead {
+    /// Attempt to read into `buf`, returning the number of bytes read (0 at the end of the
+    /// stream)
+    ///
+    /// When no data is available, the task is scheduled to be woken up through `cx` and
+    /// [`Poll::Pending`] is returned
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>>;
+}
+
+/// Seek asynchronously (as `futures-io`'s `AsyncSeek`)
+pub trait AsyncSeek {
+    /// Attempt to seek to `pos`, returning the new position from the start of the stream
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<std::io::Result<u64>>;
+}
+
+/// Write bytes asynchronously (as `futures-io`'s `AsyncWrite`)
+pub trait AsyncWrite {
+    /// Attempt to write `buf`, returning the number of bytes written
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>>;
+
+    /// Attempt to flush the buffered data
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>>;
+}
+
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for &mut T {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut **self).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncSeek + Unpin + ?Sized> AsyncSeek for &mut T {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut **self).poll_seek(cx, pos)
+    }
+}
+
+impl<T: AsyncWrite + Unpin + ?Sized> AsyncWrite for &mut T {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+}
+
+/// Adapter of a synchronous reader (or writer), whose operations are always ready
+///
+/// Meant for the readers which do not block (e.g., a [`std::io::Cursor`] over a buffer already
+/// fetched): over a file or a socket, each operation blocks the executor thread
+#[derive(Debug, Default, Clone)]
+pub struct Blocking<T>(pub T);
+
+impl<T: Read + Unpin> AsyncRead for Blocking<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(self.0.read(buf))
+    }
+}
+
+impl<T: Seek + Unpin> AsyncSeek for Blocking<T> {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(self.0.seek(pos))
+    }
+}
+
+impl<T: Write + Unpin> AsyncWrite for Blocking<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(self.0.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(self.0.flush())
+    }
+}
+
+/// Read into `buf` until it is full or the stream ends, returning the number of bytes read
+pub(crate) async fn read_full<R: AsyncRead + Unpin>(
+    r: &mut R,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let read = poll_fn(|cx| Pin::new(&mut *r).poll_read(cx, &mut buf[filled..])).await;
+        match read {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Seek to `pos`, returning the new position
+pub(crate) async fn seek<R: AsyncSeek + Unpin>(r: &mut R, pos: SeekFrom) -> std::io::Result<u64> {
+    poll_fn(|cx| Pin::new(&mut *r).poll_seek(cx, pos)).await
+}
+
+/// Write all of `buf`
+pub(crate) async fn write_all<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    mut buf: &[u8],
+) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *w).poll_write(cx, buf)).await {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(written) => buf = &buf[written..],
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Flush the buffered data
+pub(crate) async fn flush<W: AsyncWrite + Unpin>(w: &mut W) -> std::io::Result<()> {
+    poll_fn(|cx| Pin::new(&mut *w).poll_flush(cx)).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::Compression;
+    use crate::error::{JifError, JifSection};
+    use crate::jif::{Jif, JifRaw};
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::future::Future;
+    use std::io::Cursor;
+    use std::task::Waker;
+
+    /// Run a future to completion, polling it again whenever it is pending
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Reader (and writer) which is pending every other poll, and moves at most 100 bytes at a
+    /// time
+    struct Trickle<T> {
+        inner: T,
+        ready: bool,
+    }
+
+    impl<T> Trickle<T> {
+        fn new(inner: T) -> Self {
+            Trickle {
+                inner,
+                ready: false,
+            }
+        }
+
+        /// Be pending every other call (waking the task up right away)
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+            self.ready = !self.ready;
+            if self.ready {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    impl<T: Read + Unpin> AsyncRead for Trickle<T> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            std::task::ready!(self.poll_ready(cx));
+            let len = std::cmp::min(buf.len(), 100);
+            Poll::Ready(self.inner.read(&mut buf[..len]))
+        }
+    }
+
+    impl<T: Seek + Unpin> AsyncSeek for Trickle<T> {
+        fn poll_seek(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            pos: SeekFrom,
+        ) -> Poll<std::io::Result<u64>> {
+            std::task::ready!(self.poll_ready(cx));
+            Poll::Ready(self.inner.seek(pos))
+        }
+    }
+
+    impl<T: Write + Unpin> AsyncWrite for Trickle<T> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            std::task::ready!(self.poll_ready(cx));
+            let len = std::cmp::min(buf.len(), 100);
+            Poll::Ready(self.inner.write(&buf[..len]))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            std::task::ready!(self.poll_ready(cx));
+            Poll::Ready(self.inner.flush())
+        }
+    }
+
+    fn gen_raw() -> JifRaw {
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x1000, 0x5000),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![
+                    (0x1000, vec![1; PAGE_SIZE]),
+                    (0x3000, vec![2; 2 * PAGE_SIZE]),
+                ],
+            )
+            .add_reference_segment(
+                (0x10000, 0x13000),
+                ProtFlags::READ,
+                "/lib/libfoo.so".to_string(),
+                0,
+                vec![(0x11000, vec![3; PAGE_SIZE])],
+            );
+        let mut jif = builder.build().unwrap();
+        jif.set_metadata("host", "test");
+        JifRaw::from_materialized(jif, false)
+    }
+
+    fn to_bytes(raw: &JifRaw) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn async_roundtrip() {
+        for (compression, checksums, page_index) in [
+            (Compression::None, false, false),
+            (Compression::None, true, true),
+            (Compression::Lz4, false, false),
+            (Compression::Lz4, true, true),
+        ] {
+            let mut raw = gen_raw();
+            raw.set_compression(compression);
+            raw.set_checksums(checksums);
+            raw.set_page_hash_index(page_index);
+            let bytes = to_bytes(&raw);
+
+            // writing out asynchronously writes the same bytes
+            let mut written = Trickle::new(Cursor::new(Vec::new()));
+            let len = block_on(raw.to_async_writer(&mut written)).unwrap();
+            assert_eq!(len, bytes.len());
+            assert_eq!(written.inner.into_inner(), bytes);
+
+            let mut reader = Trickle::new(Cursor::new(&bytes));
+            let read = block_on(JifRaw::from_async_reader(&mut reader)).unwrap();
+            assert_eq!(to_bytes(&read), bytes);
+            assert_eq!(
+                read.metadata().get("host").map(String::as_str),
+                Some("test")
+            );
+
+            let jif = block_on(Jif::from_async_reader(&mut Blocking(Cursor::new(&bytes))));
+            assert!(jif.unwrap().equivalent(&Jif::from_bytes(&bytes).unwrap()));
+        }
+
+        let mut written = Blocking(Vec::new());
+        let jif = Jif::from_raw(gen_raw()).unwrap();
+        block_on(jif.to_async_writer(&mut written)).unwrap();
+        assert!(Jif::from_bytes(&written.0).is_ok());
+    }
+
+    #[test]
+    fn async_errors() {
+        let mut raw = gen_raw();
+        raw.set_checksums(true);
+        let bytes = to_bytes(&raw);
+        let read = |bytes: &[u8]| {
+            block_on(JifRaw::from_async_reader(&mut Trickle::new(Cursor::new(
+                bytes,
+            ))))
+        };
+
+        // the errors are the ones of the synchronous reader (and located alike)
+        let mut corrupt = bytes.clone();
+        corrupt[raw.data_offset as usize] ^= 0xff;
+        let error = read(&corrupt).unwrap_err();
+        assert_eq!(error.file_range().unwrap().0, JifSection::Data);
+        assert!(matches!(error.root(), JifError::BadSegmentChecksum { .. }));
+        assert_eq!(
+            error.to_string(),
+            JifRaw::from_bytes(&corrupt).unwrap_err().to_string()
+        );
+
+        // in the padding of the pheaders, which is not parsed
+        let mut corrupt = bytes.clone();
+        corrupt[0x800] ^= 0xff;
+        assert!(matches!(
+            read(&corrupt).unwrap_err().root(),
+            JifError::BadFileChecksum { .. }
+        ));
+
+        // truncated in the metadata, then in the data section (whose end holds no integrity
+        // trailer anymore)
+        assert!(read(&bytes[..raw.data_offset as usize + 0x10]).is_err());
+        let bytes = to_bytes(&gen_raw());
+        for len in [0x20, 0x1010, raw.data_offset as usize + 0x10] {
+            let error = read(&bytes[..len]).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                JifRaw::from_bytes(&bytes[..len]).unwrap_err().to_string()
+            );
+        }
+    }
+}
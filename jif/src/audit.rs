@@ -0,0 +1,222 @@
+//! Auditing the referenced files
+//!
+//! Restoring a JIF maps the shared pages of the reference pheaders from the referenced files,
+//! which have to be present on the target filesystem. The audit checks, ahead of the restore,
+//! that each of them exists, can be read and is long enough for the shared pages mapped from
+//! it (see [`Jif::audit_refs`])
+
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+use crate::paths::PathResolver;
+use crate::pheader::JifPheader;
+use crate::utils::open_file;
+
+use std::collections::BTreeMap;
+
+/// Why a referenced file cannot back its pheader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefProblem {
+    /// The file does not exist
+    Missing,
+
+    /// The file cannot be opened (or is not a regular file)
+    Unreadable(std::io::ErrorKind),
+
+    /// The file ends before the last shared page of the pheader
+    TooShort { file_size: u64, required: u64 },
+}
+
+/// A reference pheader whose file cannot back it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefFinding {
+    /// Index of the pheader
+    pub pheader_idx: usize,
+
+    /// Virtual address range of the pheader
+    pub virtual_range: (u64, u64),
+
+    /// Referenced path (as recorded in the JIF, i.e., not resolved)
+    pub path: String,
+
+    /// What is wrong with the file
+    pub problem: RefProblem,
+}
+
+impl std::fmt::Display for RefProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefProblem::Missing => f.write_str("file not found"),
+            RefProblem::Unreadable(kind) => {
+                f.write_fmt(format_args!("file cannot be read ({})", kind))
+            }
+            RefProblem::TooShort {
+                file_size,
+                required,
+            } => f.write_fmt(format_args!(
+                "file has {:#x} B, but the shared pages need {:#x} B",
+                file_size, required
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for RefFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "pheader (idx = {}) [{:#x}; {:#x}) referencing {}: {}",
+            self.pheader_idx, self.virtual_range.0, self.virtual_range.1, self.path, self.problem
+        ))
+    }
+}
+
+impl Jif {
+    /// Check that the referenced files (as resolved by `paths`) can back the reference
+    /// pheaders, returning a finding per pheader which cannot be restored
+    ///
+    /// A file has to reach into the last shared page of each pheader referencing it: the
+    /// remainder of a partial last page is zero filled (as `mmap` does), and the private and
+    /// zero pages are not read from the file
+    pub fn audit_refs(&self, paths: &dyn PathResolver) -> Vec<RefFinding> {
+        let page_size = self.arch.page_size as u64;
+
+        // each file is only looked up once
+        let mut file_sizes = BTreeMap::new();
+        let mut findings = Vec::new();
+        for (pheader_idx, pheader) in self.pheaders.iter().enumerate() {
+            let JifPheader::Reference {
+                ref_path,
+                ref_offset,
+                vaddr_range,
+                ..
+            } = pheader
+            else {
+                continue;
+            };
+
+            let file_size = file_sizes
+                .entry(ref_path.as_str())
+                .or_insert_with(|| file_size(&paths.resolve_path(ref_path)));
+            let problem = match file_size {
+                Err(problem) => Some(problem.clone()),
+                Ok(file_size) => pheader
+                    .itree()
+                    .iter_logical_intervals()
+                    .filter(|ival| ival.source == DataSource::Shared)
+                    .map(|ival| std::cmp::min(ival.end, vaddr_range.1))
+                    .max()
+                    .map(|shared_end| ref_offset + (shared_end - vaddr_range.0))
+                    .filter(|required| *file_size + page_size <= *required)
+                    .map(|required| RefProblem::TooShort {
+                        file_size: *file_size,
+                        required,
+                    }),
+            };
+
+            if let Some(problem) = problem {
+                findings.push(RefFinding {
+                    pheader_idx,
+                    virtual_range: *vaddr_range,
+                    path: ref_path.clone(),
+                    problem,
+                });
+            }
+        }
+
+        findings
+    }
+}
+
+/// Size of a readable regular file
+fn file_size(path: &std::path::Path) -> Result<u64, RefProblem> {
+    let file = open_file(path).map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => RefProblem::Missing,
+        kind => RefProblem::Unreadable(kind),
+    })?;
+    let metadata = file
+        .metadata()
+        .map_err(|error| RefProblem::Unreadable(error.kind()))?;
+    if !metadata.is_file() {
+        return Err(RefProblem::Unreadable(std::io::ErrorKind::InvalidInput));
+    }
+
+    Ok(metadata.len())
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod test {
+    use super::*;
+    use crate::paths::AsRecorded;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn audit_refs() {
+        let chroot = std::env::temp_dir().join(format!("jif-audit-{}", std::process::id()));
+        std::fs::create_dir_all(chroot.join("lib/dir.so")).unwrap();
+        std::fs::write(chroot.join("lib/ok.so"), vec![0; 2 * PAGE_SIZE + 0x10]).unwrap();
+        std::fs::write(chroot.join("lib/short.so"), vec![0; PAGE_SIZE]).unwrap();
+
+        let mut builder = crate::JifBuilder::new();
+        for (idx, (path, offset, overlay)) in [
+            // ends mid page: the rest of the last page is zero filled
+            ("/lib/ok.so", 0, vec![]),
+            ("/lib/short.so", 0, vec![]),
+            // the page past the end of the file is private
+            (
+                "/lib/short.so",
+                0,
+                vec![(0x31000, vec![1; PAGE_SIZE]), (0x32000, vec![1; PAGE_SIZE])],
+            ),
+            ("/lib/ok.so", 0x2000, vec![]),
+            ("/lib/missing.so", 0, vec![]),
+            ("/lib/dir.so", 0, vec![]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let start = 0x10000 * (idx as u64 + 1);
+            builder.add_reference_segment(
+                (start, start + 0x3000),
+                ProtFlags::READ,
+                path.to_string(),
+                offset,
+                overlay,
+            );
+        }
+        let jif = builder.build().unwrap();
+
+        let findings = jif.audit_refs(&Some(chroot.clone()));
+        let without_chroot = jif.audit_refs(&AsRecorded);
+        std::fs::remove_dir_all(&chroot).unwrap();
+
+        assert_eq!(
+            findings
+                .iter()
+                .map(|finding| (finding.pheader_idx, finding.problem.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    1,
+                    RefProblem::TooShort {
+                        file_size: PAGE_SIZE as u64,
+                        required: 0x3000
+                    }
+                ),
+                (
+                    3,
+                    RefProblem::TooShort {
+                        file_size: 0x2010,
+                        required: 0x5000
+                    }
+                ),
+                (4, RefProblem::Missing),
+                (5, RefProblem::Unreadable(std::io::ErrorKind::InvalidInput)),
+            ]
+        );
+        assert_eq!(findings[2].path, "/lib/missing.so");
+        assert_eq!(findings[2].virtual_range, (0x50000, 0x53000));
+
+        // none of the files are in the root filesystem
+        assert_eq!(without_chroot.len(), 6);
+    }
+}
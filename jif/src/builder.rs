@@ -0,0 +1,426 @@
+//! Programmatic construction of JIFs
+//!
+//! The [`JifBuilder`] assembles a [`Jif`] from scratch (e.g., when converting from other
+//! snapshot formats or generating fixtures): segments are described by their virtual range,
+//! protections and private data, and everything is validated when the JIF is built
+
+use crate::arch::Arch;
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::ord::OrdChunk;
+use crate::pheader::{JifPheader, ProtFlags};
+use crate::utils::is_page_aligned;
+
+use std::collections::BTreeMap;
+
+/// Private data of a segment: `(vaddr, contents)` pairs, where both the address and the length of
+/// the contents are page aligned
+pub type SegmentData = Vec<(u64, Vec<u8>)>;
+
+/// Builder for [`Jif`]s
+///
+/// Example:
+/// ```
+/// use jif::{JifBuilder, ProtFlags};
+///
+/// let mut builder = JifBuilder::new();
+/// builder
+///     .add_anonymous_segment((0x1000, 0x4000), ProtFlags::READ, vec![(0x2000, vec![1; 0x1000])])
+///     .add_reference_segment(
+///         (0x10000, 0x12000),
+///         ProtFlags::READ | ProtFlags::EXEC,
+///         "/usr/lib/libc.so".to_string(),
+///         0,
+///         vec![],
+///     );
+/// let jif = builder.build().unwrap();
+/// assert_eq!(jif.pheaders().len(), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct JifBuilder {
+    segments: Vec<Segment>,
+    ord_chunks: Vec<OrdChunk>,
+    arch: Arch,
+}
+
+#[derive(Debug)]
+struct Segment {
+    vaddr_range: (u64, u64),
+    prot: ProtFlags,
+    reference: Option<(String, u64)>,
+    data: SegmentData,
+}
+
+impl JifBuilder {
+    /// Create an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an anonymous segment: the pages not covered by `data` are zero pages
+    pub fn add_anonymous_segment(
+        &mut self,
+        vaddr_range: (u64, u64),
+        prot: ProtFlags,
+        data: SegmentData,
+    ) -> &mut Self {
+        self.segments.push(Segment {
+            vaddr_range,
+            prot,
+            reference: None,
+            data,
+        });
+        self
+    }
+
+    /// Add a segment backed by the file at `path` (starting at `offset`): the pages in the
+    /// `overlay` are private, while the remaining ones are shared with the file
+    pub fn add_reference_segment(
+        &mut self,
+        vaddr_range: (u64, u64),
+        prot: ProtFlags,
+        path: String,
+        offset: u64,
+        overlay: SegmentData,
+    ) -> &mut Self {
+        self.segments.push(Segment {
+            vaddr_range,
+            prot,
+            reference: Some((path, offset)),
+            data: overlay,
+        });
+        self
+    }
+
+    /// Set the ordering section (replacing any previously set one)
+    pub fn set_ordering(&mut self, ord_chunks: Vec<OrdChunk>) -> &mut Self {
+        self.ord_chunks = ord_chunks;
+        self
+    }
+
+    /// Set the page size of the JIF (4KiB by default): every address, offset and length has to
+    /// be aligned to it
+    pub fn page_size(&mut self, page_size: usize) -> &mut Self {
+        self.arch.page_size = page_size;
+        self
+    }
+
+    /// Set the architecture the JIF is tagged with, page size included (unknown by default)
+    pub fn arch(&mut self, arch: Arch) -> &mut Self {
+        self.arch = arch;
+        self
+    }
+
+    /// Validate the segments and ordering and build the [`Jif`]
+    pub fn build(self) -> JifResult<Jif> {
+        self.arch.validate()?;
+        let page_size = self.arch.page_size;
+
+        let mut segments = self.segments;
+        segments.sort_by_key(|segment| segment.vaddr_range.0);
+
+        for (pheader_idx, segment) in segments.iter().enumerate() {
+            segment
+                .validate(page_size)
+                .map_err(|pheader_err| JifError::BadPheader {
+                    pheader_idx,
+                    pheader_err,
+                })?;
+        }
+
+        if let Some((a, b)) = segments
+            .iter()
+            .zip(segments.iter().skip(1))
+            .find(|(a, b)| a.vaddr_range.1 > b.vaddr_range.0)
+        {
+            return Err(JifError::OverlappingPheaders {
+                pheader_1: a.vaddr_range,
+                pheader_2: b.vaddr_range,
+            });
+        }
+
+        let pheaders = segments
+            .into_iter()
+            .map(Segment::into_pheader)
+            .collect::<JifResult<Vec<_>>>()?;
+
+        let mut jif = Jif {
+            pheaders,
+            ord_chunks: Vec::new(),
+            deduper: Deduper::default(),
+            arch: self.arch,
+            meta: BTreeMap::new(),
+        };
+
+        let ord_chunks = self
+            .ord_chunks
+            .into_iter()
+            .filter(|chunk| !chunk.is_empty())
+            .collect::<Vec<_>>();
+        jif.validate_ord_chunks(&ord_chunks)?;
+        jif.ord_chunks = ord_chunks;
+
+        Ok(jif)
+    }
+}
+
+impl Segment {
+    fn validate(&self, page_size: usize) -> PheaderResult<()> {
+        let (start, end) = self.vaddr_range;
+        if !is_page_aligned(start, page_size) {
+            return Err(PheaderError::BadAlignment(start));
+        }
+        if !is_page_aligned(end, page_size) {
+            return Err(PheaderError::BadAlignment(end));
+        }
+        if start >= end {
+            return Err(PheaderError::BadVirtualRange(start, end));
+        }
+        if let Some((_path, offset)) = &self.reference {
+            if !is_page_aligned(*offset, page_size) {
+                return Err(PheaderError::BadAlignment(*offset));
+            }
+        }
+
+        for (vaddr, data) in &self.data {
+            if !is_page_aligned(*vaddr, page_size) {
+                return Err(PheaderError::BadAlignment(*vaddr));
+            }
+            if !is_page_aligned(data.len() as u64, page_size) {
+                return Err(PheaderError::BadAlignment(vaddr + data.len() as u64));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_pheader(self) -> JifResult<JifPheader> {
+        let vaddr_range = self.vaddr_range;
+        let invalid_itree = |error| JifError::InvalidITree {
+            virtual_range: vaddr_range,
+            error,
+        };
+
+        let pheader = match self.reference {
+            None => JifPheader::Anonymous {
+                vaddr_range,
+                itree: ITree::build(intervals(self.data, AnonIntervalData::Owned), vaddr_range)
+                    .map_err(invalid_itree)?,
+                prot: self.prot,
+            },
+            Some((ref_path, ref_offset)) => JifPheader::Reference {
+                vaddr_range,
+                itree: ITree::build(intervals(self.data, RefIntervalData::Owned), vaddr_range)
+                    .map_err(invalid_itree)?,
+                prot: self.prot,
+                ref_path,
+                ref_offset,
+            },
+        };
+
+        Ok(pheader)
+    }
+}
+
+fn intervals<Data: IntervalData>(
+    data: SegmentData,
+    owned: fn(Vec<u8>) -> Data,
+) -> Vec<Interval<Data>> {
+    data.into_iter()
+        .filter(|(_vaddr, data)| !data.is_empty())
+        .map(|(vaddr, data)| Interval::new(vaddr, vaddr + data.len() as u64, owned(data)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::{Endianness, Isa};
+    use crate::itree::interval::DataSource;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+    use crate::JifRaw;
+
+    use std::io::BufReader;
+
+    #[test]
+    fn build_roundtrip() {
+        let mut builder = JifBuilder::new();
+        builder
+            .add_reference_segment(
+                (0x10000, 0x14000),
+                ProtFlags::READ | ProtFlags::WRITE,
+                "/lib/libfoo.so".to_string(),
+                0x2000,
+                vec![(0x11000, vec![0x11; PAGE_SIZE])],
+            )
+            .add_anonymous_segment(
+                (0x1000, 0x5000),
+                ProtFlags::READ,
+                vec![
+                    (0x1000, vec![1; PAGE_SIZE]),
+                    (0x3000, vec![3; 2 * PAGE_SIZE]),
+                ],
+            )
+            .set_ordering(vec![
+                OrdChunk::new(0x3000, 2, DataSource::Private),
+                OrdChunk::new(0x10000, 1, DataSource::Shared),
+            ]);
+        let jif = builder.build().unwrap();
+
+        assert_eq!(jif.pheaders().len(), 2);
+        assert_eq!(jif.pheaders()[0].virtual_range(), (0x1000, 0x5000));
+        assert_eq!(jif.pheaders()[1].pathname(), Some("/lib/libfoo.so"));
+        assert_eq!(jif.pheaders()[1].ref_offset(), Some(0x2000));
+        assert_eq!(jif.private_pages(), 4);
+        assert_eq!(jif.resolve_data(0x4000), Some(&[3u8; PAGE_SIZE][..]));
+        assert_eq!(jif.resolve_data(0x2000), None);
+
+        // the built JIF can be written out and read back
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(read.private_pages(), 4);
+        assert_eq!(read.ord_chunks().len(), 2);
+        assert_eq!(read.resolve_data(0x11000), Some(&[0x11u8; PAGE_SIZE][..]));
+    }
+
+    #[test]
+    fn build_invalid() {
+        let mut builder = JifBuilder::new();
+        builder.add_anonymous_segment((0x1000, 0x1800), ProtFlags::READ, vec![]);
+        assert!(matches!(
+            builder.build(),
+            Err(JifError::BadPheader {
+                pheader_idx: 0,
+                pheader_err: PheaderError::BadAlignment(0x1800)
+            })
+        ));
+
+        let mut builder = JifBuilder::new();
+        builder
+            .add_anonymous_segment((0x1000, 0x4000), ProtFlags::READ, vec![])
+            .add_anonymous_segment((0x3000, 0x5000), ProtFlags::READ, vec![]);
+        assert!(matches!(
+            builder.build(),
+            Err(JifError::OverlappingPheaders { .. })
+        ));
+
+        let mut builder = JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x1000, 0x4000),
+            ProtFlags::READ,
+            vec![(0x3000, vec![0; 2 * PAGE_SIZE])],
+        );
+        assert!(matches!(
+            builder.build(),
+            Err(JifError::InvalidITree { .. })
+        ));
+
+        let mut builder = JifBuilder::new();
+        builder
+            .add_anonymous_segment((0x1000, 0x4000), ProtFlags::READ, vec![])
+            .set_ordering(vec![OrdChunk::new(0x3000, 2, DataSource::Zero)]);
+        assert!(matches!(
+            builder.build(),
+            Err(JifError::BadOrdChunk {
+                ord_chunk_idx: 0,
+                ord_chunk_err: OrdChunkError::UnmappedAddress(0x4000)
+            })
+        ));
+    }
+
+    #[test]
+    fn build_large_pages() {
+        const LARGE_PAGE: usize = 4 * PAGE_SIZE;
+
+        let mut builder = JifBuilder::new();
+        builder
+            .page_size(LARGE_PAGE)
+            .add_anonymous_segment(
+                (0x10000, 0x20000),
+                ProtFlags::READ,
+                vec![(0x14000, vec![7; LARGE_PAGE])],
+            )
+            .set_ordering(vec![OrdChunk::new(0x14000, 2, DataSource::Private)]);
+        let jif = builder.build().unwrap();
+        assert_eq!(jif.page_size(), LARGE_PAGE);
+        assert_eq!(jif.private_pages(), 1);
+        assert_eq!(jif.total_pages(), 4);
+        assert_eq!(jif.resolve_data(0x14000), Some(&[7u8; LARGE_PAGE][..]));
+
+        // the page size is carried in the header
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        assert_eq!(buffer.len() % LARGE_PAGE, 0);
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(read.page_size(), LARGE_PAGE);
+        assert_eq!(read.private_pages(), 1);
+        assert_eq!(read.zero_pages(), 3);
+        assert_eq!(read.resolve_data(0x14000), Some(&[7u8; LARGE_PAGE][..]));
+
+        let mut builder = JifBuilder::new();
+        builder.page_size(LARGE_PAGE).add_anonymous_segment(
+            (0x1000, 0x10000),
+            ProtFlags::READ,
+            vec![],
+        );
+        assert!(matches!(
+            builder.build(),
+            Err(JifError::BadPheader {
+                pheader_idx: 0,
+                pheader_err: PheaderError::BadAlignment(0x1000)
+            })
+        ));
+
+        let mut builder = JifBuilder::new();
+        builder.page_size(0x3000);
+        assert!(matches!(
+            builder.build(),
+            Err(JifError::BadPageSize { page_size: 0x3000 })
+        ));
+    }
+
+    #[test]
+    fn build_arch() {
+        let arch = Arch {
+            isa: Isa::Aarch64,
+            endianness: Endianness::Little,
+            page_size: 4 * PAGE_SIZE,
+        };
+
+        let mut builder = JifBuilder::new();
+        builder
+            .arch(arch)
+            .add_anonymous_segment((0x10000, 0x20000), ProtFlags::READ, vec![]);
+        let jif = builder.build().unwrap();
+        assert_eq!(jif.arch(), arch);
+
+        // the tag is carried in the header
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(read.arch(), arch);
+        assert!(!read.arch().is_compatible_with(&Arch {
+            isa: Isa::X86_64,
+            ..Arch::default()
+        }));
+
+        // x86_64 only has 4KiB pages
+        let mut builder = JifBuilder::new();
+        builder.arch(Arch {
+            isa: Isa::X86_64,
+            ..arch
+        });
+        assert!(matches!(builder.build(), Err(JifError::BadArch { .. })));
+    }
+}
@@ -0,0 +1,226 @@
+//! Canonical dumps
+//!
+//! A line oriented dump of a JIF which only depends on what it restores: the pheaders, their
+//! logical intervals (with a digest of the private data) and the ordering section, but not how
+//! the JIF is laid out (e.g., the interval trees, the data offsets or the deduplication). The
+//! dumps of two snapshots are meant to be compared with `diff(1)` (see [`Jif::write_canonical`]),
+//! while two JIFs in memory are compared directly (see [`Jif::equivalent`])
+
+use crate::digest::sha256;
+use crate::error::*;
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+use crate::ord::AccessKind;
+use crate::paths::AsRecorded;
+
+use std::io::Write;
+
+/// Name of a data source in the dump
+fn source_name(source: DataSource) -> &'static str {
+    match source {
+        DataSource::Private => "private",
+        DataSource::Zero => "zero",
+        DataSource::Shared => "shared",
+    }
+}
+
+impl Jif {
+    /// Whether two JIFs restore the same memory contents, regardless of how they are laid out
+    ///
+    /// The architectures, the pheaders (virtual ranges, protections and backing files), their
+    /// logical intervals (adjacent intervals with the same source merged), the private page
+    /// contents and the ordering sections (in order) have to be equal: the interval trees, data
+    /// offsets and deduplication may differ. This is the equality a write and read back has to
+    /// preserve
+    pub fn equivalent(&self, other: &Jif) -> bool {
+        self.arch == other.arch
+            && self.pheaders.len() == other.pheaders.len()
+            && self.ord_chunks == other.ord_chunks
+            && self.diff(other).is_empty()
+    }
+
+    /// Write the canonical dump of the JIF, one item per line
+    ///
+    /// The pheaders come in address order, each followed by its (indented) logical intervals.
+    /// The ordering chunks are kept in their order, which is the prefetch order
+    pub fn write_canonical<W: Write>(&self, w: &mut W) -> JifResult<()> {
+        writeln!(w, "arch {}", self.arch)?;
+
+        for pheader in &self.pheaders {
+            let (start, end) = pheader.virtual_range();
+            write!(w, "pheader [{:#x}; {:#x}) {}", start, end, pheader.prot())?;
+            match (pheader.pathname(), pheader.ref_offset()) {
+                (Some(pathname), Some(ref_offset)) => {
+                    writeln!(w, " ref {} +{:#x}", pathname, ref_offset)?
+                }
+                _ => writeln!(w, " anon")?,
+            }
+
+            for ival in pheader.itree().iter_logical_intervals() {
+                write!(
+                    w,
+                    "  {} [{:#x}; {:#x})",
+                    source_name(ival.source),
+                    ival.start,
+                    ival.end
+                )?;
+                if ival.source == DataSource::Private {
+                    let data = self.read_range(ival.start, ival.end - ival.start, &AsRecorded)?;
+                    write!(w, " sha256:")?;
+                    for byte in sha256(&data) {
+                        write!(w, "{:02x}", byte)?;
+                    }
+                }
+                writeln!(w)?;
+            }
+        }
+
+        for chunk in &self.ord_chunks {
+            write!(
+                w,
+                "ord {:#x} +{} {}",
+                chunk.addr(),
+                chunk.size(),
+                source_name(chunk.kind())
+            )?;
+            if let Some(tid) = chunk.tid() {
+                write!(w, " tid {}", tid)?;
+            }
+            match chunk.access() {
+                Some(AccessKind::Read) => write!(w, " read")?,
+                Some(AccessKind::Write) => write!(w, " write")?,
+                None => {}
+            }
+            if let Some(weight) = chunk.weight() {
+                write!(w, " weight {}", weight)?;
+            }
+            writeln!(w)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::test::gen_jif;
+    use crate::ord::OrdChunk;
+
+    use std::io::{BufReader, Cursor};
+
+    fn canonical(jif: &Jif) -> String {
+        let mut dump = Vec::new();
+        jif.write_canonical(&mut dump).unwrap();
+        String::from_utf8(dump).unwrap()
+    }
+
+    #[test]
+    fn canonical_dump() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x5000), &[(0x1000, 0x2000), (0x3000, 0x5000)]),
+            ((0x10000, 0x12000), &[(0x10000, 0x12000)]),
+        ]);
+        jif.add_ordering_info(vec![OrdChunk::new(0x3000, 2, DataSource::Private)
+            .with_provenance(Some(7), Some(AccessKind::Write))])
+            .unwrap();
+        let dump = canonical(&jif);
+        assert_eq!(dump.lines().filter(|l| l.starts_with("pheader")).count(), 2);
+        assert_eq!(
+            dump.lines().filter(|l| l.starts_with("  private")).count(),
+            3
+        );
+        assert!(dump.contains("\n  zero [0x2000; 0x3000)\n"));
+        assert!(dump.ends_with("ord 0x3000 +2 private tid 7 write\n"));
+
+        // the layout does not show (here, the equal data is deduplicated when read back)
+        let mut file = Vec::new();
+        jif.to_writer(&mut file).unwrap();
+        let mut jif = Jif::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        assert_eq!(canonical(&jif), dump);
+
+        // changing the data only changes the digest of its interval
+        jif.update_interval((0x10000, 0x12000), vec![1; 0x2000])
+            .unwrap();
+        let changed = canonical(&jif);
+        let differing = dump
+            .lines()
+            .zip(changed.lines())
+            .filter(|(before, after)| before != after)
+            .count();
+        assert_eq!(dump.lines().count(), changed.lines().count());
+        assert_eq!(differing, 1);
+    }
+    #[test]
+    fn equivalent() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x5000), &[(0x1000, 0x2000), (0x3000, 0x5000)]),
+            ((0x10000, 0x12000), &[(0x10000, 0x12000)]),
+        ]);
+        assert!(jif.equivalent(&jif));
+        assert!(jif.equivalent(&jif.clone_cow()));
+
+        let mut protected = jif.clone_cow();
+        protected
+            .set_prot(
+                (0x1000, 0x5000),
+                crate::ProtFlags::READ | crate::ProtFlags::WRITE,
+            )
+            .unwrap();
+        let mut ordered = jif.clone_cow();
+        ordered
+            .add_ordering_info(vec![OrdChunk::new(0x3000, 1, DataSource::Private)])
+            .unwrap();
+        let mut dropped = jif.clone_cow();
+        dropped.drop_pheaders((0x10000, 0x11000));
+        let mut updated = jif.clone_cow();
+        updated
+            .update_interval((0x10000, 0x12000), vec![1; 0x2000])
+            .unwrap();
+        for other in [protected, ordered, dropped, updated] {
+            assert!(!jif.equivalent(&other), "{:#x?}", other);
+            assert!(!other.equivalent(&jif), "{:#x?}", other);
+        }
+    }
+
+    /// Round trips (materialized, raw, written, read and materialized again) over JIFs of every
+    /// shape preserve the equivalence
+    #[test]
+    fn round_trip_equivalent() {
+        use crate::jif::JifRaw;
+        use crate::SyntheticJif;
+
+        for seed in 0..32u64 {
+            let params = SyntheticJif {
+                n_pheaders: 1 + (seed % 5) as usize,
+                pages_per_pheader: 1 + (seed * 7 % 40) as usize,
+                zero_ratio: (seed % 4) as f64 / 4.0,
+                shared_ratio: (seed % 3) as f64 / 2.0,
+                mean_run_pages: 1 + (seed % 6) as usize,
+                prebuilt_itrees: seed % 2 == 0,
+                ord_ratio: (seed % 5) as f64 / 4.0,
+                seed,
+                ..Default::default()
+            };
+            for prefetch in [false, true] {
+                let jif = params.generate().unwrap();
+                let mut file = Vec::new();
+                JifRaw::from_materialized(jif, prefetch)
+                    .to_writer(&mut file)
+                    .unwrap();
+                let read = Jif::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+                let mut expected = params.generate().unwrap();
+                if prefetch {
+                    // setting up the prefetch compacts the ordering section
+                    expected.compact_ordering_by_kind();
+                }
+                assert!(
+                    read.equivalent(&expected),
+                    "{:?} (prefetch: {})",
+                    params,
+                    prefetch
+                );
+            }
+        }
+    }
+}
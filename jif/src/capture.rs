@@ -0,0 +1,275 @@
+//! Capture a live Linux process as a JIF
+//!
+//! The VMAs are read from `/proc/<pid>/maps`: file backed private mappings become reference
+//! pheaders (whose private pages are the ones which were copied on write), while the remaining
+//! mappings become anonymous pheaders. `/proc/<pid>/pagemap` tells which pages are populated
+//! and their contents are read from `/proc/<pid>/mem`
+//!
+//! Reading the memory of another process requires ptrace access to it (e.g., being its parent
+//! or having `CAP_SYS_PTRACE`)
+
+use crate::arch::Arch;
+use crate::builder::{JifBuilder, SegmentData};
+use crate::error::*;
+use crate::jif::Jif;
+use crate::pheader::ProtFlags;
+use crate::utils::PAGE_SIZE;
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+
+/// The page is present in memory
+const PAGEMAP_PRESENT: u64 = 1 << 63;
+
+/// The page is swapped out
+const PAGEMAP_SWAPPED: u64 = 1 << 62;
+
+/// The page is a page cache page (or shared anonymous)
+const PAGEMAP_FILE: u64 = 1 << 61;
+
+/// Number of pagemap entries read at a time
+const PAGEMAP_BATCH: usize = 512;
+
+/// Auxiliary vector entry holding the page size
+const AT_PAGESZ: u64 = 6;
+
+/// Special mappings which cannot (or should not) be captured
+const SKIPPED_MAPPINGS: &[&str] = &["[vvar]", "[vvar_vclock]", "[vsyscall]"];
+
+/// An entry of `/proc/<pid>/maps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapsEntry {
+    /// virtual address range
+    pub vaddr_range: (u64, u64),
+
+    /// protections
+    pub prot: ProtFlags,
+
+    /// whether the mapping is private (i.e., copy on write)
+    pub private: bool,
+
+    /// offset into the backing file
+    pub offset: u64,
+
+    /// backing path or pseudo-path (e.g., `[heap]`), if any
+    pub pathname: Option<String>,
+}
+
+impl MapsEntry {
+    /// Parse a line of `/proc/<pid>/maps`
+    pub fn parse(line: &str) -> JifResult<Self> {
+        let bad_entry = || JifError::BadMapsEntry {
+            line: line.to_string(),
+        };
+
+        let mut fields = line.splitn(6, ' ');
+        let mut next = || fields.next().ok_or_else(bad_entry);
+
+        let (start, end) = next()?.split_once('-').ok_or_else(bad_entry)?;
+        let perms = next()?.as_bytes();
+        let offset = next()?;
+        let _dev = next()?;
+        let _inode = next()?;
+        let pathname = fields.next().map(str::trim_start).filter(|p| !p.is_empty());
+
+        let hex = |s: &str| u64::from_str_radix(s, 16).map_err(|_| bad_entry());
+        if perms.len() != 4 {
+            return Err(bad_entry());
+        }
+
+        let prot = [
+            (b'r', ProtFlags::READ),
+            (b'w', ProtFlags::WRITE),
+            (b'x', ProtFlags::EXEC),
+        ]
+        .iter()
+        .zip(perms)
+        .filter(|((flag, _prot), perm)| flag == *perm)
+        .fold(ProtFlags::empty(), |acc, ((_flag, prot), _perm)| {
+            acc | *prot
+        });
+
+        Ok(MapsEntry {
+            vaddr_range: (hex(start)?, hex(end)?),
+            prot,
+            private: perms[3] == b'p',
+            offset: hex(offset)?,
+            pathname: pathname.map(|p| p.to_string()),
+        })
+    }
+
+    /// Path of the backing file, if the mapping is backed by a (still existing) file
+    pub fn backing_file(&self) -> Option<&str> {
+        self.pathname
+            .as_deref()
+            .filter(|p| p.starts_with('/') && !p.ends_with(" (deleted)"))
+    }
+}
+
+/// Parse the contents of `/proc/<pid>/maps`
+pub fn parse_maps(maps: &str) -> JifResult<Vec<MapsEntry>> {
+    maps.lines()
+        .filter(|line| !line.is_empty())
+        .map(MapsEntry::parse)
+        .collect()
+}
+
+/// Capture the address space of the process `pid` as a [`Jif`]
+///
+/// The process should be stopped (e.g., with `SIGSTOP`) for the snapshot to be consistent
+pub fn capture(pid: u32) -> JifResult<Jif> {
+    let proc_dir = std::path::PathBuf::from(format!("/proc/{}", pid));
+    let maps = parse_maps(&std::fs::read_to_string(proc_dir.join("maps"))?)?;
+    let mut pagemap = File::open(proc_dir.join("pagemap"))?;
+    let mem = File::open(proc_dir.join("mem"))?;
+    let page_size = page_size_of(&proc_dir).unwrap_or(PAGE_SIZE);
+
+    let mut builder = JifBuilder::new();
+    builder.arch(Arch::host(page_size));
+    for entry in maps {
+        if entry
+            .pathname
+            .as_deref()
+            .is_some_and(|p| SKIPPED_MAPPINGS.contains(&p))
+        {
+            continue;
+        }
+
+        // shared file mappings are entirely backed by the file
+        let data = match entry.backing_file() {
+            Some(_) if !entry.private => Vec::new(),
+            _ if !entry.prot.contains(ProtFlags::READ) => Vec::new(),
+            backing => read_private_data(&entry, backing.is_some(), page_size, &mut pagemap, &mem)?,
+        };
+
+        match entry.backing_file() {
+            Some(path) => builder.add_reference_segment(
+                entry.vaddr_range,
+                entry.prot,
+                path.to_string(),
+                entry.offset,
+                data,
+            ),
+            None => builder.add_anonymous_segment(entry.vaddr_range, entry.prot, data),
+        };
+    }
+
+    builder.build()
+}
+
+/// Page size of the process, as found in its auxiliary vector (`/proc/<pid>/auxv`)
+fn page_size_of(proc_dir: &std::path::Path) -> Option<usize> {
+    let auxv = std::fs::read(proc_dir.join("auxv")).ok()?;
+    auxv.chunks_exact(16)
+        .map(|entry| {
+            let key = u64::from_ne_bytes(entry[..8].try_into().unwrap());
+            let value = u64::from_ne_bytes(entry[8..].try_into().unwrap());
+            (key, value)
+        })
+        .find(|(key, _value)| *key == AT_PAGESZ)
+        .map(|(_key, value)| value as usize)
+}
+
+/// Read the private pages of a mapping, coalesced into contiguous runs
+///
+/// For file backed mappings only the pages not in the page cache (i.e., copied on write) are
+/// private; zero pages of anonymous mappings are omitted
+fn read_private_data(
+    entry: &MapsEntry,
+    file_backed: bool,
+    page_size: usize,
+    pagemap: &mut File,
+    mem: &File,
+) -> JifResult<SegmentData> {
+    let (start, end) = entry.vaddr_range;
+    let n_pages = ((end - start) / page_size as u64) as usize;
+    pagemap.seek(SeekFrom::Start(start / page_size as u64 * 8))?;
+
+    let mut data: SegmentData = Vec::new();
+    let mut page = vec![0u8; page_size];
+    let mut buffer = vec![0u8; PAGEMAP_BATCH * 8];
+    let mut page_idx = 0;
+    while page_idx < n_pages {
+        let batch = std::cmp::min(PAGEMAP_BATCH, n_pages - page_idx);
+        pagemap.read_exact(&mut buffer[..batch * 8])?;
+
+        for entry in buffer[..batch * 8].chunks_exact(8) {
+            let entry = u64::from_le_bytes(entry.try_into().unwrap());
+            let addr = start + (page_idx * page_size) as u64;
+            page_idx += 1;
+
+            let populated = entry & (PAGEMAP_PRESENT | PAGEMAP_SWAPPED) != 0;
+            let private = !file_backed || entry & PAGEMAP_FILE == 0;
+            if !populated || !private {
+                continue;
+            }
+
+            mem.read_exact_at(&mut page, addr)?;
+            if !file_backed && page.iter().all(|b| *b == 0) {
+                continue;
+            }
+
+            match data.last_mut() {
+                Some((run_start, run)) if *run_start + run.len() as u64 == addr => {
+                    run.extend_from_slice(&page)
+                }
+                _ => data.push((addr, page.clone())),
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_maps_entries() {
+        let maps = "\
+55d0c0a00000-55d0c0a02000 r--p 00000000 fe:01 1234                       /usr/bin/cat
+55d0c0a02000-55d0c0a07000 r-xp 00002000 fe:01 1234                       /usr/bin/cat
+55d0c1b52000-55d0c1b73000 rw-p 00000000 00:00 0                          [heap]
+7f1e2c000000-7f1e2c021000 rw-s 00000000 00:01 42                         /dev/zero (deleted)
+7f1e2c400000-7f1e2c401000 ---p 00000000 00:00 0
+ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0                  [vsyscall]
+";
+        let entries = parse_maps(maps).unwrap();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(
+            entries[1],
+            MapsEntry {
+                vaddr_range: (0x55d0c0a02000, 0x55d0c0a07000),
+                prot: ProtFlags::READ | ProtFlags::EXEC,
+                private: true,
+                offset: 0x2000,
+                pathname: Some("/usr/bin/cat".to_string()),
+            }
+        );
+        assert_eq!(entries[1].backing_file(), Some("/usr/bin/cat"));
+        assert_eq!(entries[2].pathname.as_deref(), Some("[heap]"));
+        assert_eq!(entries[2].backing_file(), None);
+        assert!(!entries[3].private);
+        assert_eq!(entries[3].backing_file(), None);
+        assert_eq!(entries[4].prot, ProtFlags::empty());
+        assert_eq!(entries[4].pathname, None);
+
+        assert!(matches!(
+            MapsEntry::parse("55d0c0a00000 r--p 00000000 fe:01 1234"),
+            Err(JifError::BadMapsEntry { .. })
+        ));
+    }
+
+    #[test]
+    fn capture_self() {
+        let buffer = vec![0x5au8; 4 * PAGE_SIZE];
+        let page_addr = (buffer.as_ptr() as u64).next_multiple_of(PAGE_SIZE as u64);
+
+        let jif = capture(std::process::id()).unwrap();
+        assert!(jif.mapping_pheader(page_addr).is_some());
+        assert_eq!(jif.resolve_data(page_addr), Some(&[0x5au8; PAGE_SIZE][..]));
+        assert!(jif.pheaders().iter().any(|p| p.pathname().is_some()));
+    }
+}
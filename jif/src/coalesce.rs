@@ -0,0 +1,135 @@
+//! Interval coalescing
+//!
+//! Fracturing and building the interval trees (possibly more than once) leaves behind adjacent
+//! intervals with the same source, each taking a slot in the tree. Coalescing merges them back,
+//! and rebuilds the trees as compact as they can be
+
+use crate::error::*;
+use crate::jif::Jif;
+use crate::pheader::JifPheader;
+
+/// Statistics of a coalescing pass (see [`Jif::coalesce`])
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CoalesceStats {
+    /// Number of intervals before the pass
+    pub intervals_before: usize,
+
+    /// Number of intervals after the pass
+    pub intervals_after: usize,
+
+    /// Number of itree nodes before the pass
+    pub nodes_before: usize,
+
+    /// Number of itree nodes after the pass
+    pub nodes_after: usize,
+}
+
+impl CoalesceStats {
+    /// Number of itree nodes saved by the pass
+    pub fn saved_nodes(&self) -> usize {
+        self.nodes_before - self.nodes_after
+    }
+}
+
+impl Jif {
+    /// Merge the adjacent intervals with the same source in every interval tree
+    ///
+    /// Merged data is owned by the resulting interval: this undoes the splits done when
+    /// deduplicating pages (see [`Jif::dedup_pages`]) or setting up prefetching
+    pub fn coalesce(&mut self) -> JifResult<CoalesceStats> {
+        let mut stats = CoalesceStats::default();
+        for pheader in self.pheaders.iter_mut() {
+            let virtual_range = pheader.virtual_range();
+            let invalid_itree = |error| JifError::InvalidITree {
+                virtual_range,
+                error,
+            };
+
+            stats.intervals_before += pheader.itree().n_intervals();
+            stats.nodes_before += pheader.itree().n_nodes();
+            match pheader {
+                JifPheader::Anonymous { itree, .. } => {
+                    itree.coalesce(&self.deduper).map_err(invalid_itree)?;
+                }
+                JifPheader::Reference { itree, .. } => {
+                    itree.coalesce(&self.deduper).map_err(invalid_itree)?;
+                }
+            }
+            stats.intervals_after += pheader.itree().n_intervals();
+            stats.nodes_after += pheader.itree().n_nodes();
+        }
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::deduper::Deduper;
+    use crate::itree::interval::{AnonIntervalData, Interval};
+    use crate::itree::ITree;
+    use crate::jif::JifRaw;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::collections::BTreeMap;
+    use std::io::BufReader;
+
+    #[test]
+    fn coalesce_roundtrip() {
+        let gen_jif = || Jif {
+            pheaders: vec![JifPheader::Anonymous {
+                vaddr_range: (0x1000, 0x10000),
+                itree: ITree::build(
+                    (1..6)
+                        .map(|idx| {
+                            Interval::new(
+                                idx * 0x1000,
+                                (idx + 1) * 0x1000,
+                                AnonIntervalData::Owned(vec![idx as u8; PAGE_SIZE]),
+                            )
+                        })
+                        .collect(),
+                    (0x1000, 0x10000),
+                )
+                .unwrap(),
+                prot: ProtFlags::READ,
+            }],
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+
+        let mut jif = gen_jif();
+        let stats = jif.coalesce().unwrap();
+        assert_eq!(
+            stats,
+            CoalesceStats {
+                intervals_before: 5,
+                intervals_after: 1,
+                nodes_before: 2,
+                nodes_after: 1,
+            }
+        );
+        assert_eq!(stats.saved_nodes(), 1);
+        assert_eq!(jif.coalesce().unwrap().saved_nodes(), 0);
+
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        let orig = gen_jif();
+        for addr in (0x1000..0x10000).step_by(PAGE_SIZE) {
+            assert_eq!(
+                read.resolve_data(addr),
+                orig.resolve_data(addr),
+                "{:#x}",
+                addr
+            );
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Compression of the data section
+//!
+//! The data section is split into blocks of (at most) [`BLOCK_SIZE`] bytes, each compressed
+//! independently with the LZ4 block format. On disk every block is laid out as:
+//!  - uncompressed length (`u32`)
+//!  - compressed length (`u32`)
+//!  - the compressed bytes
+
+use crate::error::{JifError, JifResult};
+use crate::jif::JIF_FLAG_LZ4;
+use crate::utils::par_map;
+
+/// Uncompressed size of a compressed block
+const BLOCK_SIZE: usize = 1 << 20;
+
+const MIN_MATCH: usize = 4;
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_LOG: u32 = 16;
+
+/// Compression applied to the data section of a JIF
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Raw pages (the only layout that can be mapped directly)
+    #[default]
+    None,
+
+    /// LZ4 compressed blocks
+    Lz4,
+}
+
+impl Compression {
+    /// Decode the compression from the header flags
+    pub(crate) fn from_flags(flags: u32) -> Self {
+        if flags & JIF_FLAG_LZ4 != 0 {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Encode the compression into the header flags
+    pub(crate) fn flags(&self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => JIF_FLAG_LZ4,
+        }
+    }
+}
+
+/// Compress a data section into a sequence of blocks (in parallel)
+pub(crate) fn compress_blocks(data: &[u8]) -> Vec<u8> {
+    let blocks = data.chunks(BLOCK_SIZE).collect::<Vec<_>>();
+    let compressed_blocks = par_map(&blocks, |block| lz4_compress(block));
+
+    let mut out = Vec::with_capacity(data.len() / 2);
+    for (block, compressed) in blocks.iter().zip(compressed_blocks) {
+        out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        out.extend_from_slice(&compressed);
+    }
+
+    out
+}
+
+/// Decompress a sequence of blocks into the data section
+pub(crate) fn decompress_blocks(mut data: &[u8]) -> JifResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut block_idx = 0;
+    while !data.is_empty() {
+        let bad_block = JifError::BadCompressedBlock { block_idx };
+        if data.len() < 2 * std::mem::size_of::<u32>() {
+            return Err(bad_block);
+        }
+
+        let uncompressed_len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+        let compressed_len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        data = &data[8..];
+        if data.len() < compressed_len {
+            return Err(bad_block);
+        }
+
+        let start = out.len();
+        lz4_decompress(&data[..compressed_len], &mut out).ok_or(bad_block)?;
+        if out.len() - start != uncompressed_len {
+            return Err(JifError::BadCompressedBlock { block_idx });
+        }
+
+        data = &data[compressed_len..];
+        block_idx += 1;
+    }
+
+    Ok(out)
+}
+
+fn read_u32_at(data: &[u8], idx: usize) -> u32 {
+    u32::from_le_bytes(data[idx..idx + 4].try_into().unwrap())
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 0xff {
+        out.push(0xff);
+        len -= 0xff;
+    }
+    out.push(len as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], mtch: Option<(usize, usize)>) {
+    let lit_token = std::cmp::min(literals.len(), 0xf);
+    let match_token = mtch.map(|(_off, len)| std::cmp::min(len - MIN_MATCH, 0xf));
+    out.push(((lit_token as u8) << 4) | match_token.unwrap_or(0) as u8);
+    if lit_token == 0xf {
+        write_len(out, literals.len() - 0xf);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, len)) = mtch {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if len - MIN_MATCH >= 0xf {
+            write_len(out, len - MIN_MATCH - 0xf);
+        }
+    }
+}
+
+/// Greedy LZ4 block compression
+pub(crate) fn lz4_compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut anchor = 0;
+
+    if input.len() > MF_LIMIT {
+        let mut table = vec![usize::MAX; 1 << HASH_LOG];
+        let match_limit = input.len() - LAST_LITERALS;
+        let mut idx = 0;
+        while idx < input.len() - MF_LIMIT {
+            let seq = read_u32_at(input, idx);
+            let h = hash(seq);
+            let candidate = table[h];
+            table[h] = idx;
+
+            if candidate == usize::MAX
+                || idx - candidate > MAX_OFFSET
+                || read_u32_at(input, candidate) != seq
+            {
+                idx += 1;
+                continue;
+            }
+
+            let mut len = MIN_MATCH;
+            while idx + len < match_limit && input[candidate + len] == input[idx + len] {
+                len += 1;
+            }
+
+            write_sequence(&mut out, &input[anchor..idx], Some((idx - candidate, len)));
+            idx += len;
+            anchor = idx;
+        }
+    }
+
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+/// LZ4 block decompression, appending to `out`
+///
+/// Returns `None` if the block is malformed
+fn lz4_decompress(input: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    fn read_len(input: &[u8], idx: &mut usize, mut len: usize) -> Option<usize> {
+        loop {
+            let byte = *input.get(*idx)?;
+            *idx += 1;
+            len += byte as usize;
+            if byte != 0xff {
+                return Some(len);
+            }
+        }
+    }
+
+    let start = out.len();
+    let mut idx = 0;
+    loop {
+        let token = *input.get(idx)?;
+        idx += 1;
+
+        let mut lit_len = (token >> 4) as usize;
+        if lit_len == 0xf {
+            lit_len = read_len(input, &mut idx, lit_len)?;
+        }
+        out.extend_from_slice(input.get(idx..idx + lit_len)?);
+        idx += lit_len;
+
+        // the last sequence only has literals
+        if idx == input.len() {
+            return Some(());
+        }
+
+        let offset = u16::from_le_bytes(input.get(idx..idx + 2)?.try_into().unwrap()) as usize;
+        idx += 2;
+        if offset == 0 || offset > out.len() - start {
+            return None;
+        }
+
+        let mut match_len = (token & 0xf) as usize;
+        if match_len == 0xf {
+            match_len = read_len(input, &mut idx, match_len)?;
+        }
+        match_len += MIN_MATCH;
+
+        // matches may overlap with the bytes they produce: copy byte by byte
+        let match_start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[match_start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) {
+        let compressed = compress_blocks(data);
+        assert_eq!(decompress_blocks(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn roundtrip_small() {
+        roundtrip(&[]);
+        roundtrip(b"a");
+        roundtrip(b"abcdefghijklm");
+        roundtrip(b"abcabcabcabcabcabcabcabcabcabcabcabc");
+    }
+
+    #[test]
+    fn roundtrip_pages() {
+        let mut data = vec![0u8; 3 * BLOCK_SIZE + 0x1234];
+        for (idx, byte) in data.iter_mut().enumerate() {
+            // a mix of runs and pseudo-random bytes
+            *byte = if (idx / 0x1000) % 2 == 0 {
+                (idx / 0x100) as u8
+            } else {
+                (idx.wrapping_mul(2654435761) >> 13) as u8
+            };
+        }
+
+        let compressed = compress_blocks(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress_blocks(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn bad_block() {
+        let mut compressed = compress_blocks(&[0x42; 0x2000]);
+        compressed.truncate(compressed.len() - 1);
+        assert!(matches!(
+            decompress_blocks(&compressed),
+            Err(JifError::BadCompressedBlock { block_idx: 0 })
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Copy-on-write clones
+//!
+//! Speculative transformations (e.g., fragmenting the VMAs to see how many pheaders it yields)
+//! should not need to deep copy the data of a JIF, nor mutate the original. A copy-on-write clone
+//! copies the pheaders and the ordering section (which are small), and shares the data segments
+//! of the deduper (see [`Jif::clone_cow`])
+
+use crate::jif::Jif;
+
+impl Jif {
+    /// Clone the JIF, sharing its data segments copy-on-write
+    ///
+    /// The clone is independent from the original: transforming either one (building the
+    /// interval trees, fragmenting, dropping pheaders, changing the ordering, writing it out)
+    /// never changes the other. The data segments are immutable in the deduper, so they are only
+    /// shared until one side drops them (or writes them out, which copies the data still shared
+    /// with the other side). Data added to one side is not seen by the other
+    ///
+    /// The data held by the intervals themselves (i.e., not yet in the deduper, as for a JIF
+    /// being built) is copied
+    pub fn clone_cow(&self) -> Jif {
+        Jif {
+            pheaders: self.pheaders.clone(),
+            ord_chunks: self.ord_chunks.clone(),
+            deduper: self.deduper.clone_cow(),
+            arch: self.arch,
+            meta: self.meta.clone(),
+        }
+    }
+
+    /// Number of data segments shared with another JIF (i.e., with a copy-on-write clone or the
+    /// JIF it was cloned from; see [`Jif::clone_cow`])
+    pub fn n_shared_segments(&self, other: &Jif) -> usize {
+        self.deduper.n_shared_with(&other.deduper)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+    use crate::{AsRecorded, JifRaw};
+
+    use std::io::{BufReader, Cursor};
+
+    fn read_back(jif: Jif) -> Jif {
+        let mut file = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut file)
+            .unwrap();
+        Jif::from_reader(&mut BufReader::new(Cursor::new(file))).unwrap()
+    }
+
+    #[test]
+    fn clone_cow() {
+        let mut builder = crate::JifBuilder::new();
+        for (start, byte) in [(0x100000u64, 1u8), (0x200000, 2)] {
+            builder.add_anonymous_segment(
+                (start, start + 4 * PAGE_SIZE as u64),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![(start + PAGE_SIZE as u64, vec![byte; PAGE_SIZE])],
+            );
+        }
+        let jif = read_back(builder.build().unwrap());
+        let n_segments = jif.deduper.tokens().count();
+        assert_eq!(n_segments, 2);
+
+        let mut clone = jif.clone_cow();
+        assert_eq!(jif.n_shared_segments(&clone), n_segments);
+
+        // transforming the clone leaves the original untouched
+        clone.fragment(&AsRecorded, None).unwrap();
+        clone.drop_pheaders((0x200000, 0x204000));
+        assert_eq!(clone.pheaders().len(), 3);
+        assert_eq!(jif.pheaders().len(), 2);
+        assert_eq!(jif.private_pages(), 2);
+        assert_eq!(clone.private_pages(), 1);
+
+        // writing the clone out copies the data still shared with the original
+        let clone = read_back(clone);
+        assert_eq!(jif.n_shared_segments(&clone), 0);
+        for (addr, byte) in [(0x101000, 1), (0x201000, 2)] {
+            assert_eq!(jif.resolve_data(addr), Some(&[byte; PAGE_SIZE][..]));
+        }
+        assert_eq!(clone.resolve_data(0x101000), Some(&[1; PAGE_SIZE][..]));
+    }
+}
@@ -1,8 +1,13 @@
 //! Data deduplication logic
 
+use crate::digest::DigestCache;
+#[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+use crate::mmap::Mmap;
+use crate::utils::par_map;
 use std::collections::hash_map::RandomState;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
 
 /// Tokens issued by a [`Deduper`]
 ///
@@ -11,13 +16,106 @@ use std::hash::{BuildHasher, Hash};
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DedupToken(u64);
 
+/// Statistics of the data held by a [`Deduper`] (see [`Deduper::stats`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Number of distinct data segments (i.e., tokens)
+    pub n_tokens: usize,
+
+    /// Number of references to the data segments (at least one per token)
+    pub n_references: usize,
+
+    /// Bytes held (once per token)
+    pub stored_bytes: usize,
+
+    /// Bytes referenced (once per reference)
+    pub referenced_bytes: usize,
+
+    /// Number of segments by size, bucketed by the next power of two (in B)
+    pub size_histogram: BTreeMap<usize, usize>,
+}
+
+impl DedupStats {
+    /// Bytes which are not stored thanks to the deduplication
+    pub fn saved_bytes(&self) -> usize {
+        self.referenced_bytes - self.stored_bytes
+    }
+}
+
+impl std::fmt::Display for DedupStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} segments, referenced {} times",
+            self.n_tokens, self.n_references
+        )?;
+        writeln!(f, "stored:     {:#x} B", self.stored_bytes)?;
+        writeln!(f, "referenced: {:#x} B", self.referenced_bytes)?;
+        writeln!(f, "saved:      {:#x} B", self.saved_bytes())?;
+        writeln!(f, "segment sizes:")?;
+        for (bucket, count) in &self.size_histogram {
+            writeln!(f, "  <= {:#x} B: {}", bucket, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A data segment held by the [`Deduper`]
+///
+/// Segments are immutable once inserted: cloning one only shares it (see [`Deduper::clone_cow`])
+#[derive(Clone)]
+enum Segment {
+    /// Data owned by the deduper (and its copy-on-write clones)
+    Owned(Arc<Vec<u8>>),
+
+    /// Data borrowed from a memory mapped file
+    #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+    Mapped {
+        map: Arc<Mmap>,
+        range: std::ops::Range<usize>,
+    },
+}
+
+impl Segment {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Segment::Owned(data) => data.as_slice(),
+            #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+            Segment::Mapped { map, range } => &map[range.clone()],
+        }
+    }
+
+    /// Take the data out, copying it only if a clone of the deduper still shares it
+    fn into_vec(self) -> Vec<u8> {
+        match self {
+            Segment::Owned(data) => Arc::unwrap_or_clone(data),
+            #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+            Segment::Mapped { map, range } => map[range].to_vec(),
+        }
+    }
+}
+
 /// The data aggregator to de-duplicate data segments
 ///
-/// This holds all the non-owned interval data and is used to deduplicate them
+/// This holds all the non-owned interval data and is used to deduplicate them.
+/// The data is either owned or borrowed from a memory mapped JIF
 #[derive(Default)]
 pub struct Deduper {
-    /// map from data hash to the owned data
-    canonical: HashMap<u64, Vec<u8>>,
+    /// map from token to the data
+    canonical: HashMap<u64, Segment>,
+
+    /// map from data hash to the tokens whose data hashes to it
+    by_hash: HashMap<u64, Vec<DedupToken>>,
+
+    /// map from token to the number of times its data was inserted
+    insertions: HashMap<u64, usize>,
+
+    /// next token to be issued
+    next_token: u64,
+
+    /// digests of the pages of the data (see [`crate::Jif::page_digests`])
+    digests: DigestCache,
 
     /// hash builder
     hash_builder: RandomState,
@@ -27,6 +125,10 @@ impl Deduper {
     pub(crate) fn with_capacity(n: usize) -> Self {
         Deduper {
             canonical: HashMap::with_capacity(n),
+            by_hash: HashMap::with_capacity(n),
+            insertions: HashMap::with_capacity(n),
+            next_token: 0,
+            digests: DigestCache::default(),
             hash_builder: RandomState::default(),
         }
     }
@@ -35,14 +137,79 @@ impl Deduper {
         data_map: BTreeMap<(u64, u64), Vec<u8>>,
     ) -> (Self, BTreeMap<(u64, u64), DedupToken>) {
         let mut deduper = Self::with_capacity(data_map.len());
-        let mut offset_index = BTreeMap::new();
+        let offset_index = deduper.insert_data_map(data_map);
+        (deduper, offset_index)
+    }
 
-        for (range, data) in data_map {
-            let token = deduper.insert(data);
-            offset_index.insert(range, token);
+    /// Insert the data segments of a data map, returning the offset index (see
+    /// [`Self::from_data_map`])
+    pub(crate) fn insert_data_map(
+        &mut self,
+        data_map: BTreeMap<(u64, u64), Vec<u8>>,
+    ) -> BTreeMap<(u64, u64), DedupToken> {
+        let (ranges, segments): (Vec<_>, Vec<_>) = data_map
+            .into_iter()
+            .map(|(range, data)| (range, Segment::Owned(Arc::new(data))))
+            .unzip();
+        let tokens = self.insert_all(segments);
+
+        ranges.into_iter().zip(tokens).collect()
+    }
+
+    /// Build a deduper over data segments which live in a memory mapped file
+    ///
+    /// The segments are given as ranges relative to `data_offset` (as in [`Self::from_data_map`])
+    #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+    pub(crate) fn from_mapped_segments(
+        map: &Arc<Mmap>,
+        data_offset: u64,
+        segments: impl ExactSizeIterator<Item = (u64, u64)>,
+    ) -> (Self, BTreeMap<(u64, u64), DedupToken>) {
+        let mut deduper = Self::with_capacity(segments.len());
+        let (ranges, segments): (Vec<_>, Vec<_>) = segments
+            .map(|range| {
+                let mapped_range =
+                    (data_offset + range.0) as usize..(data_offset + range.1) as usize;
+                (
+                    range,
+                    Segment::Mapped {
+                        map: map.clone(),
+                        range: mapped_range,
+                    },
+                )
+            })
+            .unzip();
+        let tokens = deduper.insert_all(segments);
+
+        (deduper, ranges.into_iter().zip(tokens).collect())
+    }
+
+    /// Clone the deduper, sharing its data segments (see [`crate::Jif::clone_cow`])
+    ///
+    /// The clones are independent from then on: the data inserted into one is not seen by the
+    /// other, and the tokens issued afterwards are only valid in the deduper which issued them
+    pub(crate) fn clone_cow(&self) -> Self {
+        Deduper {
+            canonical: self.canonical.clone(),
+            by_hash: self.by_hash.clone(),
+            insertions: self.insertions.clone(),
+            next_token: self.next_token,
+            digests: self.digests.clone(),
+            hash_builder: self.hash_builder.clone(),
         }
+    }
 
-        (deduper, offset_index)
+    /// Number of data segments shared with another deduper (e.g., a copy-on-write clone)
+    pub(crate) fn n_shared_with(&self, other: &Deduper) -> usize {
+        self.canonical
+            .iter()
+            .filter(|(token, segment)| {
+                other
+                    .canonical
+                    .get(token)
+                    .is_some_and(|other| std::ptr::eq(segment.as_slice(), other.as_slice()))
+            })
+            .count()
     }
 
     fn hash(&self, data: &[u8]) -> u64 {
@@ -50,18 +217,127 @@ impl Deduper {
     }
 
     pub(crate) fn insert(&mut self, data: Vec<u8>) -> DedupToken {
-        // let token = self.hash(&data);
-        // if self.canonical.contains_key(&token) {
-        //     return DedupToken(token);
-        // }
+        let data = Segment::Owned(Arc::new(data));
+        self.insert_hashed(self.hash(data.as_slice()), data)
+    }
+
+    /// Insert several data segments, returning their tokens (in order)
+    ///
+    /// Hashing the data is the bulk of an insertion: the segments are hashed in parallel, and
+    /// then inserted in order (so the tokens are the ones sequential insertions would issue)
+    fn insert_all(&mut self, segments: Vec<Segment>) -> Vec<DedupToken> {
+        let hashes = par_map(&segments, |segment| self.hash(segment.as_slice()));
+        hashes
+            .into_iter()
+            .zip(segments)
+            .map(|(hash, segment)| self.insert_hashed(hash, segment))
+            .collect()
+    }
 
-        let token = self.canonical.len() as u64;
-        self.canonical.insert(token, data);
-        DedupToken(token)
+    /// Insert a data segment, given the hash of its data
+    fn insert_hashed(&mut self, hash: u64, data: Segment) -> DedupToken {
+        // hashes can collide: only reuse a token if the data actually matches
+        let candidates = self.by_hash.entry(hash).or_default();
+        if let Some(token) = candidates
+            .iter()
+            .find(|tok| self.canonical.get(&tok.0).map(Segment::as_slice) == Some(data.as_slice()))
+        {
+            *self.insertions.entry(token.0).or_default() += 1;
+            return *token;
+        }
+
+        let token = DedupToken(self.next_token);
+        self.next_token += 1;
+        candidates.push(token);
+        self.canonical.insert(token.0, data);
+        self.insertions.insert(token.0, 1);
+        token
     }
 
     pub(crate) fn get(&self, token: DedupToken) -> &[u8] {
-        self.canonical.get(&token.0).map(|v| v.as_ref()).expect("by construction, requesting data from the deduper with a dedup token should always work")
+        self.canonical.get(&token.0).map(Segment::as_slice).expect("by construction, requesting data from the deduper with a dedup token should always work")
+    }
+
+    /// Get the data of a token, if it was issued by this deduper
+    pub(crate) fn try_get(&self, token: DedupToken) -> Option<&[u8]> {
+        self.canonical.get(&token.0).map(Segment::as_slice)
+    }
+
+    /// The cache of the page digests of the data held
+    pub(crate) fn digests(&self) -> &DigestCache {
+        &self.digests
+    }
+
+    /// Iterate over the tokens of the data held
+    pub(crate) fn tokens(&self) -> impl Iterator<Item = DedupToken> + '_ {
+        self.canonical.keys().map(|token| DedupToken(*token))
+    }
+
+    /// Statistics of the data held, counting every insertion as a reference
+    ///
+    /// Note that the data read from a JIF is already deduplicated (see [`crate::Jif::dedup_stats`]
+    /// for the sharing in a JIF)
+    pub fn stats(&self) -> DedupStats {
+        self.stats_of(
+            self.insertions
+                .iter()
+                .flat_map(|(token, count)| std::iter::repeat_n(DedupToken(*token), *count)),
+        )
+    }
+
+    /// Statistics of the data held, with the given references (tokens not issued by this
+    /// deduper are ignored)
+    pub(crate) fn stats_of(&self, references: impl Iterator<Item = DedupToken>) -> DedupStats {
+        let mut stats = DedupStats::default();
+        let mut referenced = HashMap::new();
+        for token in references {
+            let Some(data) = self.try_get(token) else {
+                continue;
+            };
+            stats.n_references += 1;
+            stats.referenced_bytes += data.len();
+            *referenced.entry(token).or_insert(0usize) += 1;
+        }
+
+        for token in referenced.keys() {
+            let len = self.get(*token).len();
+            stats.n_tokens += 1;
+            stats.stored_bytes += len;
+            *stats
+                .size_histogram
+                .entry(len.next_power_of_two())
+                .or_default() += 1;
+        }
+
+        stats
+    }
+
+    /// Drop the data segments whose tokens are not `referenced`, returning the bytes dropped
+    pub(crate) fn retain(&mut self, referenced: &HashSet<DedupToken>) -> usize {
+        let dead = self
+            .tokens()
+            .filter(|token| !referenced.contains(token))
+            .collect::<Vec<_>>();
+
+        let mut reclaimed = 0;
+        for token in dead {
+            let segment = self
+                .canonical
+                .remove(&token.0)
+                .expect("the token was issued by this deduper");
+            let hash = self.hash(segment.as_slice());
+            if let Some(candidates) = self.by_hash.get_mut(&hash) {
+                candidates.retain(|t| *t != token);
+                if candidates.is_empty() {
+                    self.by_hash.remove(&hash);
+                }
+            }
+            self.insertions.remove(&token.0);
+            self.digests.remove(token);
+            reclaimed += segment.as_slice().len();
+        }
+
+        reclaimed
     }
 
     pub(crate) fn destructure(
@@ -77,15 +353,23 @@ impl Deduper {
         let mut data_map = BTreeMap::new();
         let mut last_issued = intervals.first().map(|(_tok, range)| range.0).unwrap_or(0);
         for (tok, range) in intervals {
-            assert_eq!(
-                range.0, last_issued,
-                "badly constructed data segment: there is a gap"
+            // the layout may leave gaps between the segments (see [`crate::DataLayout`])
+            assert!(
+                range.0 >= last_issued,
+                "badly constructed data segment: segments overlap"
             );
 
             let data = self
                 .canonical
                 .remove(&tok.0)
-                .expect("by construction, data should be here");
+                .expect("by construction, data should be here")
+                .into_vec();
+            self.insertions.remove(&tok.0);
+            self.digests.remove(tok);
+            let hash = self.hash(&data);
+            if let Some(candidates) = self.by_hash.get_mut(&hash) {
+                candidates.retain(|t| *t != tok);
+            }
             data_map.insert(range, data);
             last_issued = range.1;
         }
@@ -111,6 +395,29 @@ mod test {
         assert_eq!(deduper.get(token3), &[0xb; 0x1000]);
     }
 
+    #[test]
+    fn stats() {
+        let mut deduper = Deduper::default();
+        deduper.insert(vec![0xa; 0x1000]);
+        deduper.insert(vec![0xa; 0x1000]);
+        deduper.insert(vec![0xa; 0x1000]);
+        deduper.insert(vec![0xb; 0x3000]);
+
+        let stats = deduper.stats();
+        assert_eq!(
+            stats,
+            DedupStats {
+                n_tokens: 2,
+                n_references: 4,
+                stored_bytes: 0x4000,
+                referenced_bytes: 0x6000,
+                size_histogram: BTreeMap::from([(0x1000, 1), (0x4000, 1)]),
+            }
+        );
+        assert_eq!(stats.saved_bytes(), 0x2000);
+        assert_eq!(Deduper::default().stats(), DedupStats::default());
+    }
+
     #[test]
     fn from_data_map() {
         let mut data_map = BTreeMap::new();
@@ -133,6 +440,32 @@ mod test {
         assert_eq!(deduper.get(*token), &[0x1; 0x1000]);
     }
 
+    #[test]
+    fn insert_all() {
+        let segments = (0..64u8)
+            .map(|idx| vec![idx % 5; 0x1000 * (1 + idx as usize % 3)])
+            .collect::<Vec<_>>();
+
+        // the bulk insertion issues the tokens sequential insertions would
+        let mut sequential = Deduper::default();
+        let expected = segments
+            .iter()
+            .map(|data| sequential.insert(data.clone()))
+            .collect::<Vec<_>>();
+        let mut bulk = Deduper::default();
+        let tokens = bulk.insert_all(
+            segments
+                .iter()
+                .map(|data| Segment::Owned(Arc::new(data.clone())))
+                .collect(),
+        );
+        assert_eq!(tokens, expected);
+        assert_eq!(bulk.stats(), sequential.stats());
+        for (token, data) in tokens.iter().zip(&segments) {
+            assert_eq!(bulk.get(*token), &data[..]);
+        }
+    }
+
     #[test]
     fn destructure() {
         let mut deduper = Deduper::default();
@@ -0,0 +1,378 @@
+//! Delta JIFs
+//!
+//! A delta JIF only stores the private pages which cannot be found in a designated base JIF:
+//! intervals whose data lives in the base have their offset tagged with [`RAW_BASE_FLAG`]
+//! and point into the data section of the base. Delta JIFs are marked by a header flag, and
+//! the base is needed to materialize them (see [`JifRaw::resolve_base`])
+
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::itree::interval::RAW_BASE_FLAG;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::{Jif, JifRaw};
+use crate::pheader::JifPheader;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufReader, Read, Seek};
+
+/// Index from page contents to their offset in the base JIF
+type BasePageIndex<'a> = HashMap<&'a [u8], u64>;
+
+impl JifRaw {
+    /// Construct a delta JIF from a materialized one: pages which are found in `base` are
+    /// referenced instead of stored
+    ///
+    /// `base` has to be a full JIF (with the same architecture), read back from the file it will
+    /// be resolved against
+    pub fn make_delta(mut jif: Jif, base: &JifRaw) -> JifResult<Self> {
+        if base.delta {
+            return Err(JifError::BaseIsDelta);
+        } else if base.arch != jif.arch {
+            return Err(JifError::ArchMismatch {
+                expected: jif.arch,
+                found: base.arch,
+            });
+        }
+
+        let page_size = jif.arch.page_size;
+        let index = base_page_index(base);
+
+        // split the intervals such that each is either entirely found (contiguously) in the base
+        // or not at all
+        for pheader in jif.pheaders.iter_mut() {
+            let virtual_range = pheader.virtual_range();
+            let invalid_itree = |error| JifError::InvalidITree {
+                virtual_range,
+                error,
+            };
+            match pheader {
+                JifPheader::Anonymous { itree, .. } => {
+                    let intervals = split_by_base(
+                        itree.take().into_iter_intervals(),
+                        &jif.deduper,
+                        &index,
+                        page_size,
+                        AnonIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                }
+                JifPheader::Reference { itree, .. } => {
+                    let intervals = split_by_base(
+                        itree.take().into_iter_intervals(),
+                        &jif.deduper,
+                        &index,
+                        page_size,
+                        RefIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                }
+            }
+        }
+
+        let mut raw = JifRaw::from_materialized(jif, false);
+
+        // point the intervals found in the base to it, remembering which segments are still local
+        let mut local_segments = BTreeSet::new();
+        for ival in raw
+            .itree_nodes
+            .iter_mut()
+            .flat_map(|node| node.ranges.iter_mut())
+            .filter(|ival| ival.is_data())
+        {
+            let start = ival.offset - raw.data_offset;
+            let data = &raw.data_segments[&(start, start + ival.len())];
+            let base_offset = index.get(&data[..page_size]).copied().filter(|first| {
+                data.chunks_exact(page_size)
+                    .enumerate()
+                    .all(|(idx, page)| index.get(page) == Some(&(first + (idx * page_size) as u64)))
+            });
+
+            match base_offset {
+                Some(base_offset) => ival.offset = RAW_BASE_FLAG | base_offset,
+                None => {
+                    local_segments.insert(start);
+                }
+            }
+        }
+
+        // compact the remaining data segments (relative to the data offset)
+        let mut relocations = BTreeMap::new();
+        let mut data_segments = BTreeMap::new();
+        let mut cursor = 0;
+        for ((start, end), data) in std::mem::take(&mut raw.data_segments) {
+            if local_segments.contains(&start) {
+                relocations.insert(start, raw.data_offset + cursor);
+                data_segments.insert((cursor, cursor + end - start), data);
+                cursor += end - start;
+            }
+        }
+
+        for ival in raw
+            .itree_nodes
+            .iter_mut()
+            .flat_map(|node| node.ranges.iter_mut())
+            .filter(|ival| ival.is_data())
+        {
+            ival.offset = relocations[&(ival.offset - raw.data_offset)];
+        }
+
+        raw.data_segments = data_segments;
+        raw.delta = true;
+        Ok(raw)
+    }
+
+    /// Check whether this is a delta JIF (i.e., which needs a base to be materialized)
+    pub fn is_delta(&self) -> bool {
+        self.delta
+    }
+
+    /// Pull in the data referenced from the `base` JIF, making this JIF self contained
+    pub fn resolve_base(&mut self, base: &JifRaw) -> JifResult<()> {
+        if base.delta {
+            return Err(JifError::BaseIsDelta);
+        } else if base.arch != self.arch {
+            return Err(JifError::ArchMismatch {
+                expected: self.arch,
+                found: base.arch,
+            });
+        }
+
+        let mut cursor = self
+            .data_segments
+            .keys()
+            .last()
+            .map(|(_start, end)| *end)
+            .unwrap_or(0);
+
+        // base offset and length to local offset
+        let mut resolved = BTreeMap::new();
+        for ival in self
+            .itree_nodes
+            .iter_mut()
+            .flat_map(|node| node.ranges.iter_mut())
+            .filter(|ival| ival.is_base())
+        {
+            let base_offset = ival.offset & !RAW_BASE_FLAG;
+            let len = ival.len();
+
+            let offset = match resolved.get(&(base_offset, len)) {
+                Some(offset) => *offset,
+                None => {
+                    let data =
+                        base.data_at(base_offset, len)
+                            .ok_or(JifError::DataSegmentNotFound {
+                                data_range: (base_offset, base_offset + len),
+                                virtual_range: (ival.start, ival.end),
+                                found_len: 0,
+                            })?;
+
+                    let offset = cursor;
+                    self.data_segments
+                        .insert((offset, offset + len), data.to_vec());
+                    resolved.insert((base_offset, len), offset);
+                    cursor += len;
+                    offset
+                }
+            };
+
+            ival.offset = self.data_offset + offset;
+        }
+
+        self.delta = false;
+        self.refresh_page_hash_index();
+        Ok(())
+    }
+
+    /// Find the data at an offset (in the file) of the data section
+    fn data_at(&self, offset: u64, len: u64) -> Option<&[u8]> {
+        let offset = offset.checked_sub(self.data_offset)?;
+        let ((start, end), data) = self
+            .data_segments
+            .range(..=(offset, u64::MAX))
+            .next_back()?;
+
+        (*start <= offset && offset + len <= *end)
+            .then(|| &data[(offset - start) as usize..(offset + len - start) as usize])
+    }
+}
+
+impl Jif {
+    /// Read a delta [`Jif`] from a file, resolving the pages it references through `base`
+    pub fn from_reader_with_base<R: Read + Seek>(
+        r: &mut BufReader<R>,
+        base: &JifRaw,
+    ) -> JifResult<Self> {
+        let mut raw = JifRaw::from_reader(r)?;
+        raw.resolve_base(base)?;
+        Jif::from_raw(raw)
+    }
+}
+
+/// Index the private pages of the base (by their offset in its file)
+fn base_page_index(base: &JifRaw) -> BasePageIndex<'_> {
+    let mut index = HashMap::new();
+    for ((start, _end), data) in &base.data_segments {
+        for (page_idx, page) in data.chunks_exact(base.arch.page_size).enumerate() {
+            index
+                .entry(page)
+                .or_insert(base.data_offset + start + (page_idx * base.arch.page_size) as u64);
+        }
+    }
+
+    index
+}
+
+/// Split the intervals into runs of pages which are either found contiguously in the base or
+/// not found at all
+fn split_by_base<Data: IntervalData>(
+    intervals: impl Iterator<Item = Interval<Data>>,
+    deduper: &Deduper,
+    index: &BasePageIndex,
+    page_size: usize,
+    owned: fn(Vec<u8>) -> Data,
+) -> Vec<Interval<Data>> {
+    let mut split = Vec::new();
+    for interval in intervals {
+        let Some(data) = interval.data.get_data(deduper) else {
+            split.push(interval);
+            continue;
+        };
+
+        let pages = data
+            .chunks_exact(page_size)
+            .map(|page| index.get(page).copied())
+            .collect::<Vec<_>>();
+
+        // indices of the pages which start a new run
+        let run_starts = (0..pages.len())
+            .filter(|idx| {
+                *idx == 0
+                    || match (pages[idx - 1], pages[*idx]) {
+                        (None, None) => false,
+                        (Some(prev), Some(cur)) => cur != prev + page_size as u64,
+                        _ => true,
+                    }
+            })
+            .chain(std::iter::once(pages.len()))
+            .collect::<Vec<_>>();
+
+        if run_starts.len() <= 2 {
+            split.push(interval);
+            continue;
+        }
+
+        for run in run_starts.windows(2) {
+            let (first, last) = (run[0] * page_size, run[1] * page_size);
+            split.push(Interval::new(
+                interval.start + first as u64,
+                interval.start + last as u64,
+                owned(data[first..last].to_vec()),
+            ));
+        }
+    }
+
+    split
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    fn gen_anon(vaddr_range: (u64, u64), pages: &[(u64, u8)]) -> JifPheader {
+        JifPheader::Anonymous {
+            vaddr_range,
+            itree: ITree::build(
+                pages
+                    .iter()
+                    .map(|(addr, byte)| {
+                        Interval::new(
+                            *addr,
+                            addr + PAGE_SIZE as u64,
+                            AnonIntervalData::Owned(vec![*byte; PAGE_SIZE]),
+                        )
+                    })
+                    .collect(),
+                vaddr_range,
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+        }
+    }
+
+    fn gen_jif(pheaders: Vec<JifPheader>) -> Jif {
+        Jif {
+            pheaders,
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        }
+    }
+
+    fn write_and_read(raw: JifRaw) -> (Vec<u8>, JifRaw) {
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).unwrap();
+        let raw = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&buffer))).unwrap();
+        (buffer, raw)
+    }
+
+    #[test]
+    fn delta_roundtrip() {
+        let base = gen_jif(vec![gen_anon(
+            (0x1000, 0x10000),
+            &[(0x1000, 1), (0x2000, 2), (0x3000, 3), (0x4000, 4)],
+        )]);
+        let snapshot = gen_jif(vec![
+            gen_anon(
+                (0x1000, 0x10000),
+                &[(0x1000, 1), (0x2000, 2), (0x3000, 42), (0x4000, 4)],
+            ),
+            gen_anon((0x20000, 0x30000), &[(0x20000, 3), (0x21000, 43)]),
+        ]);
+
+        let (_, base) = write_and_read(JifRaw::from_materialized(base, false));
+        let (full_buffer, full) = write_and_read(JifRaw::from_materialized(
+            gen_jif(vec![
+                gen_anon(
+                    (0x1000, 0x10000),
+                    &[(0x1000, 1), (0x2000, 2), (0x3000, 42), (0x4000, 4)],
+                ),
+                gen_anon((0x20000, 0x30000), &[(0x20000, 3), (0x21000, 43)]),
+            ]),
+            false,
+        ));
+
+        let (delta_buffer, mut delta) =
+            write_and_read(JifRaw::make_delta(snapshot, &base).unwrap());
+        assert!(delta.is_delta());
+        // only the pages not in the base are stored
+        assert_eq!(delta.data_size(), 2 * PAGE_SIZE);
+        assert!(delta_buffer.len() < full_buffer.len());
+        assert!(matches!(
+            Jif::from_raw(
+                JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&delta_buffer)))
+                    .unwrap()
+            ),
+            Err(JifError::DeltaWithoutBase)
+        ));
+
+        delta.resolve_base(&base).unwrap();
+        assert!(!delta.is_delta());
+        let delta = Jif::from_raw(delta).unwrap();
+        let full = Jif::from_raw(full).unwrap();
+
+        for addr in (0x1000..0x5000).chain(0x20000..0x22000).step_by(PAGE_SIZE) {
+            assert_eq!(
+                delta.resolve_data(addr),
+                full.resolve_data(addr),
+                "{:#x}",
+                addr
+            );
+        }
+        assert_eq!(delta.private_pages(), full.private_pages());
+    }
+}
@@ -0,0 +1,358 @@
+//! Structural differences between two JIFs
+//!
+//! Pheaders are matched by their virtual range: pheaders which are only present in one of the JIFs
+//! are reported as added/removed, while matching pheaders are compared in terms of protections,
+//! backing file, logical intervals and private page contents
+//!
+//! This is unrelated to [`crate::itree::diff`], which compares the saved pages with the
+//! referenced files to build the interval trees (both work on the intervals of
+//! [`crate::itree::interval`])
+
+use crate::itree::interval::{DataSource, LogicalInterval};
+use crate::jif::Jif;
+use crate::pheader::{JifPheader, ProtFlags};
+
+/// File backing a pheader: `(path, offset)`, `None` if anonymous
+pub type RefSource = Option<(String, u64)>;
+
+/// Delta between two [`Jif`]s
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct JifDiff {
+    /// Virtual ranges of the pheaders only present in the first JIF
+    pub removed_pheaders: Vec<(u64, u64)>,
+
+    /// Virtual ranges of the pheaders only present in the second JIF
+    pub added_pheaders: Vec<(u64, u64)>,
+
+    /// Pheaders present in both JIFs which differ
+    pub changed_pheaders: Vec<PheaderDiff>,
+}
+
+/// Delta between two pheaders mapping the same virtual range
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PheaderDiff {
+    /// Virtual range of the pheaders
+    pub virtual_range: (u64, u64),
+
+    /// Protections, if they changed
+    pub prot: Option<(ProtFlags, ProtFlags)>,
+
+    /// Backing file, if it changed
+    pub reference: Option<(RefSource, RefSource)>,
+
+    /// Logical intervals only present in the first pheader
+    pub removed_intervals: Vec<LogicalInterval>,
+
+    /// Logical intervals only present in the second pheader
+    pub added_intervals: Vec<LogicalInterval>,
+
+    /// Pages which are private in both pheaders, but whose contents changed (with the smallest
+    /// page size of both JIFs)
+    pub changed_pages: Vec<u64>,
+}
+
+impl JifDiff {
+    /// Compute the delta from `a` to `b`
+    pub(crate) fn between(a: &Jif, b: &Jif) -> Self {
+        fn find(jif: &Jif, range: (u64, u64)) -> Option<&JifPheader> {
+            jif.pheaders()
+                .iter()
+                .find(|phdr| phdr.virtual_range() == range)
+        }
+
+        let mut diff = JifDiff::default();
+        for phdr_a in a.pheaders() {
+            match find(b, phdr_a.virtual_range()) {
+                None => diff.removed_pheaders.push(phdr_a.virtual_range()),
+                Some(phdr_b) => {
+                    let phdr_diff = PheaderDiff::between(a, phdr_a, b, phdr_b);
+                    if !phdr_diff.is_empty() {
+                        diff.changed_pheaders.push(phdr_diff);
+                    }
+                }
+            }
+        }
+
+        diff.added_pheaders = b
+            .pheaders()
+            .iter()
+            .map(|phdr| phdr.virtual_range())
+            .filter(|range| find(a, *range).is_none())
+            .collect();
+
+        diff.removed_pheaders.sort();
+        diff.added_pheaders.sort();
+        diff.changed_pheaders.sort_by_key(|d| d.virtual_range);
+        diff
+    }
+
+    /// Check if there are no differences
+    pub fn is_empty(&self) -> bool {
+        self.removed_pheaders.is_empty()
+            && self.added_pheaders.is_empty()
+            && self.changed_pheaders.is_empty()
+    }
+}
+
+impl PheaderDiff {
+    fn between(a: &Jif, phdr_a: &JifPheader, b: &Jif, phdr_b: &JifPheader) -> Self {
+        let reference = |phdr: &JifPheader| {
+            phdr.pathname()
+                .map(|p| p.to_string())
+                .zip(phdr.ref_offset())
+        };
+
+        let ivals_a = logical_intervals(phdr_a);
+        let ivals_b = logical_intervals(phdr_b);
+
+        let page_size = std::cmp::min(a.page_size(), b.page_size());
+        let changed_pages = ivals_a
+            .iter()
+            .filter(|ival| ival.source == DataSource::Private)
+            .flat_map(|ival| (ival.start..ival.end).step_by(page_size))
+            .filter(|addr| {
+                match (
+                    phdr_a.resolve_data(*addr, &a.deduper, page_size),
+                    phdr_b.resolve_data(*addr, &b.deduper, page_size),
+                ) {
+                    (Some(data_a), Some(data_b)) => data_a != data_b,
+                    _ => false,
+                }
+            })
+            .collect();
+
+        PheaderDiff {
+            virtual_range: phdr_a.virtual_range(),
+            prot: (phdr_a.prot() != phdr_b.prot()).then_some((phdr_a.prot(), phdr_b.prot())),
+            reference: (reference(phdr_a) != reference(phdr_b))
+                .then(|| (reference(phdr_a), reference(phdr_b))),
+            removed_intervals: ivals_a
+                .iter()
+                .filter(|ival| !ivals_b.contains(ival))
+                .copied()
+                .collect(),
+            added_intervals: ivals_b
+                .iter()
+                .filter(|ival| !ivals_a.contains(ival))
+                .copied()
+                .collect(),
+            changed_pages,
+        }
+    }
+
+    /// Check if there are no differences
+    pub fn is_empty(&self) -> bool {
+        self.prot.is_none()
+            && self.reference.is_none()
+            && self.removed_intervals.is_empty()
+            && self.added_intervals.is_empty()
+            && self.changed_pages.is_empty()
+    }
+}
+
+/// Partition the virtual range of the pheader into logical intervals
+///
+/// Adjacent intervals with the same source are merged, so that explicit and implicit mappings
+/// compare equal
+fn logical_intervals(phdr: &JifPheader) -> Vec<LogicalInterval> {
+    let mut ivals: Vec<LogicalInterval> = Vec::new();
+    for ival in phdr.itree().iter_logical_intervals() {
+        match ivals.last_mut() {
+            Some(last) if last.end == ival.start && last.source == ival.source => {
+                last.end = ival.end
+            }
+            _ => ivals.push(ival),
+        }
+    }
+
+    ivals
+}
+
+fn reference_str(reference: &RefSource) -> String {
+    match reference {
+        Some((path, offset)) => format!("{}[{:#x}..]", path, offset),
+        None => "anonymous".to_string(),
+    }
+}
+
+fn interval_str(ival: &LogicalInterval) -> String {
+    format!("[{:#x}; {:#x}) {:?}", ival.start, ival.end, ival.source)
+}
+
+impl std::fmt::Display for JifDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (start, end) in &self.removed_pheaders {
+            writeln!(f, "- pheader [{:#x}; {:#x})", start, end)?;
+        }
+        for (start, end) in &self.added_pheaders {
+            writeln!(f, "+ pheader [{:#x}; {:#x})", start, end)?;
+        }
+        for phdr_diff in &self.changed_pheaders {
+            phdr_diff.fmt(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PheaderDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "~ pheader [{:#x}; {:#x})",
+            self.virtual_range.0, self.virtual_range.1
+        )?;
+        if let Some((a, b)) = self.prot {
+            writeln!(f, "    prot: {} -> {}", a, b)?;
+        }
+        if let Some((a, b)) = &self.reference {
+            writeln!(
+                f,
+                "    reference: {} -> {}",
+                reference_str(a),
+                reference_str(b)
+            )?;
+        }
+        for ival in &self.removed_intervals {
+            writeln!(f, "    - interval {}", interval_str(ival))?;
+        }
+        for ival in &self.added_intervals {
+            writeln!(f, "    + interval {}", interval_str(ival))?;
+        }
+        for page in &self.changed_pages {
+            writeln!(f, "    ~ page {:#x}", page)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::deduper::Deduper;
+    use crate::itree::interval::{AnonIntervalData, Interval};
+    use crate::itree::ITree;
+    use crate::jif::test::gen_jif;
+
+    use std::collections::BTreeMap;
+
+    fn gen_anon(vaddr_range: (u64, u64), ivals: &[(u64, u64, u8)], prot: ProtFlags) -> JifPheader {
+        JifPheader::Anonymous {
+            vaddr_range,
+            itree: ITree::build(
+                ivals
+                    .iter()
+                    .map(|(start, end, byte)| Interval {
+                        start: *start,
+                        end: *end,
+                        data: AnonIntervalData::Owned(vec![*byte; (end - start) as usize]),
+                    })
+                    .collect(),
+                vaddr_range,
+            )
+            .unwrap(),
+            prot,
+        }
+    }
+
+    #[test]
+    fn diff_identical() {
+        let a = gen_jif(&[((0x1000, 0x8000), &[(0x1000, 0x3000)])]);
+        let b = gen_jif(&[((0x1000, 0x8000), &[(0x1000, 0x3000)])]);
+        assert!(a.diff(&b).is_empty());
+    }
+
+    #[test]
+    fn diff_pheaders() {
+        let a = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000)]),
+            ((0x10000, 0x12000), &[]),
+        ]);
+        let b = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x4000)]),
+            ((0x20000, 0x22000), &[]),
+        ]);
+
+        let diff = a.diff(&b);
+        assert_eq!(diff.removed_pheaders, vec![(0x10000, 0x12000)]);
+        assert_eq!(diff.added_pheaders, vec![(0x20000, 0x22000)]);
+        assert_eq!(diff.changed_pheaders.len(), 1);
+
+        let phdr_diff = &diff.changed_pheaders[0];
+        assert_eq!(phdr_diff.prot, None);
+        assert_eq!(phdr_diff.reference, None);
+        assert_eq!(
+            phdr_diff.removed_intervals,
+            vec![
+                LogicalInterval {
+                    start: 0x1000,
+                    end: 0x3000,
+                    source: DataSource::Private
+                },
+                LogicalInterval {
+                    start: 0x3000,
+                    end: 0x8000,
+                    source: DataSource::Zero
+                },
+            ]
+        );
+        assert_eq!(
+            phdr_diff.added_intervals,
+            vec![
+                LogicalInterval {
+                    start: 0x1000,
+                    end: 0x4000,
+                    source: DataSource::Private
+                },
+                LogicalInterval {
+                    start: 0x4000,
+                    end: 0x8000,
+                    source: DataSource::Zero
+                },
+            ]
+        );
+        assert!(phdr_diff.changed_pages.is_empty());
+    }
+
+    #[test]
+    fn diff_pages_and_prot() {
+        let a = Jif {
+            pheaders: vec![gen_anon(
+                (0x1000, 0x8000),
+                &[(0x1000, 0x2000, 1), (0x2000, 0x4000, 2)],
+                ProtFlags::READ,
+            )],
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+        let b = Jif {
+            pheaders: vec![gen_anon(
+                (0x1000, 0x8000),
+                &[(0x1000, 0x2000, 1), (0x2000, 0x4000, 3)],
+                ProtFlags::READ | ProtFlags::WRITE,
+            )],
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+
+        let diff = a.diff(&b);
+        assert!(diff.removed_pheaders.is_empty());
+        assert!(diff.added_pheaders.is_empty());
+        assert_eq!(diff.changed_pheaders.len(), 1);
+
+        let phdr_diff = &diff.changed_pheaders[0];
+        assert_eq!(
+            phdr_diff.prot,
+            Some((ProtFlags::READ, ProtFlags::READ | ProtFlags::WRITE))
+        );
+        assert!(phdr_diff.removed_intervals.is_empty());
+        assert!(phdr_diff.added_intervals.is_empty());
+        assert_eq!(phdr_diff.changed_pages, vec![0x2000, 0x3000]);
+    }
+}
@@ -0,0 +1,261 @@
+//! Page digests
+//!
+//! SHA-256 digests of the pages of a JIF, to compare JIFs by content. Hashing is spread over the
+//! available cores, and the digests of the deduplicated segments are cached by their tokens:
+//! hashing a segment referenced by many intervals (or hashing it again) is free
+
+use crate::deduper::{DedupToken, Deduper};
+use crate::itree::interval::{DataSource, IntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::page_index::PageHashIndex;
+use crate::pheader::JifPheader;
+use crate::utils::par_map;
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// A SHA-256 digest
+pub type Sha256Hash = [u8; 32];
+
+/// Hash some data with SHA-256
+pub fn sha256(data: &[u8]) -> Sha256Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Digests of the pages of the deduplicated segments, by token
+///
+/// The page size of a JIF is fixed, so the tokens are enough to key the digests
+#[derive(Default)]
+pub(crate) struct DigestCache {
+    by_token: Mutex<HashMap<DedupToken, Arc<[Sha256Hash]>>>,
+}
+
+impl DigestCache {
+    fn get(&self, token: DedupToken) -> Option<Arc<[Sha256Hash]>> {
+        self.lock().get(&token).cloned()
+    }
+
+    fn insert(&self, token: DedupToken, digests: Arc<[Sha256Hash]>) {
+        self.lock().insert(token, digests);
+    }
+
+    /// Seed the digests of the tokens read from the data segments of a JIF (by their offsets in
+    /// the data section) from its page hash index: the tokens with pages missing from the index
+    /// are left to be hashed
+    pub(crate) fn seed(
+        &self,
+        index: &PageHashIndex,
+        offset_index: &BTreeMap<(u64, u64), DedupToken>,
+        page_size: usize,
+    ) {
+        let by_offset = index
+            .iter()
+            .map(|(digest, offset)| (offset, *digest))
+            .collect::<HashMap<_, _>>();
+        let mut by_token = self.lock();
+        for ((start, end), token) in offset_index {
+            if by_token.contains_key(token) {
+                continue;
+            }
+            let digests = (*start..*end)
+                .step_by(page_size)
+                .map(|offset| by_offset.get(&offset).copied())
+                .collect::<Option<Arc<[_]>>>();
+            if let Some(digests) = digests {
+                by_token.insert(*token, digests);
+            }
+        }
+    }
+
+    /// Forget the digests of a token (e.g., when its data leaves the deduper)
+    pub(crate) fn remove(&mut self, token: DedupToken) {
+        self.by_token
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&token);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<DedupToken, Arc<[Sha256Hash]>>> {
+        // the map is always left consistent, even if a holder panicked
+        self.by_token
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Clone for DigestCache {
+    /// Copy the cache (the digests themselves are shared)
+    fn clone(&self) -> Self {
+        DigestCache {
+            by_token: Mutex::new(self.lock().clone()),
+        }
+    }
+}
+
+impl Jif {
+    /// Digests of the pages of a `kind`, by address (in address order)
+    ///
+    /// Private pages are hashed from their data, and zero pages all have the digest of the zero
+    /// page. Shared pages are backed by the referenced files, which are not in the JIF: no
+    /// digests are returned for them
+    pub fn page_digests(&self, kind: DataSource) -> Vec<(u64, Sha256Hash)> {
+        let page_size = self.arch.page_size;
+        match kind {
+            DataSource::Shared => Vec::new(),
+            DataSource::Zero => {
+                let zero_page = sha256(&vec![0u8; page_size]);
+                self.pheaders
+                    .iter()
+                    .flat_map(|pheader| pheader.itree().iter_logical_intervals())
+                    .filter(|ival| ival.source == DataSource::Zero)
+                    .flat_map(|ival| (ival.start..ival.end).step_by(page_size))
+                    .map(|addr| (addr, zero_page))
+                    .collect()
+            }
+            DataSource::Private => self.private_page_digests(),
+        }
+    }
+
+    fn private_page_digests(&self) -> Vec<(u64, Sha256Hash)> {
+        let page_size = self.arch.page_size;
+        let cache = self.deduper.digests();
+        let intervals = self
+            .pheaders
+            .iter()
+            .flat_map(|pheader| data_intervals(pheader, &self.deduper))
+            .collect::<Vec<_>>();
+
+        // the segments to hash: the ones owned by the intervals and the uncached deduplicated
+        // ones (once each)
+        let mut pending = HashSet::new();
+        let to_hash = intervals
+            .iter()
+            .filter(|(_start, token, _data)| {
+                token.is_none_or(|token| cache.get(token).is_none() && pending.insert(token))
+            })
+            .map(|(_start, token, data)| (*token, *data))
+            .collect::<Vec<_>>();
+        let pages = to_hash
+            .iter()
+            .flat_map(|(_token, data)| data.chunks(page_size))
+            .collect::<Vec<_>>();
+        let mut hashed = par_map(&pages, |page| sha256(page)).into_iter();
+
+        let mut owned = VecDeque::new();
+        for (token, data) in to_hash {
+            let digests = hashed
+                .by_ref()
+                .take(data.len().div_ceil(page_size))
+                .collect::<Arc<[_]>>();
+            match token {
+                Some(token) => cache.insert(token, digests),
+                None => owned.push_back(digests),
+            }
+        }
+
+        intervals
+            .into_iter()
+            .flat_map(|(start, token, _data)| {
+                let digests = match token {
+                    Some(token) => cache.get(token).expect("the digests were just cached"),
+                    None => owned
+                        .pop_front()
+                        .expect("the owned intervals were hashed in order"),
+                };
+                (0..digests.len()).map(move |idx| (start + (idx * page_size) as u64, digests[idx]))
+            })
+            .collect()
+    }
+}
+
+/// The data intervals of a pheader, as `(start, token, data)` (the token is missing for the data
+/// which is not deduplicated)
+fn data_intervals<'a>(
+    pheader: &'a JifPheader,
+    deduper: &'a Deduper,
+) -> Vec<(u64, Option<DedupToken>, &'a [u8])> {
+    fn with_data<'a, Data: IntervalData>(
+        itree: &'a ITree<Data>,
+        deduper: &'a Deduper,
+    ) -> Vec<(u64, Option<DedupToken>, &'a [u8])> {
+        itree
+            .in_order_intervals()
+            .filter_map(|ival| {
+                ival.data
+                    .get_data(deduper)
+                    .map(|data| (ival.start, ival.data.dedup_token(), data))
+            })
+            .collect()
+    }
+
+    match pheader {
+        JifPheader::Anonymous { itree, .. } => with_data(itree, deduper),
+        JifPheader::Reference { itree, .. } => with_data(itree, deduper),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::itree::interval::{AnonIntervalData, Interval};
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+    #[test]
+    fn page_digests() {
+        let mut deduper = Deduper::default();
+        let token = deduper.insert(vec![1; 2 * PAGE_SIZE]);
+        let gen_anon = |vaddr_range: (u64, u64), ivals: Vec<Interval<AnonIntervalData>>| {
+            JifPheader::Anonymous {
+                vaddr_range,
+                itree: ITree::build(ivals, vaddr_range).unwrap(),
+                prot: ProtFlags::READ,
+            }
+        };
+        let jif = Jif {
+            pheaders: vec![
+                gen_anon(
+                    (0x1000, 0x5000),
+                    vec![
+                        Interval::new(0x1000, 0x3000, AnonIntervalData::Ref(token)),
+                        Interval::new(0x4000, 0x5000, AnonIntervalData::Owned(vec![2; PAGE_SIZE])),
+                    ],
+                ),
+                gen_anon(
+                    (0x10000, 0x12000),
+                    vec![Interval::new(
+                        0x10000,
+                        0x12000,
+                        AnonIntervalData::Ref(token),
+                    )],
+                ),
+            ],
+            ord_chunks: vec![],
+            deduper,
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+
+        let one = sha256(&[1; PAGE_SIZE]);
+        let expected = vec![
+            (0x1000, one),
+            (0x2000, one),
+            (0x4000, sha256(&[2; PAGE_SIZE])),
+            (0x10000, one),
+            (0x11000, one),
+        ];
+        assert_eq!(jif.page_digests(DataSource::Private), expected);
+        assert!(jif.deduper.digests().get(token).is_some());
+
+        // cached
+        assert_eq!(jif.page_digests(DataSource::Private), expected);
+
+        let zero = sha256(&[0; PAGE_SIZE]);
+        assert_eq!(jif.page_digests(DataSource::Zero), vec![(0x3000, zero)]);
+        assert!(jif.page_digests(DataSource::Shared).is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+//! Duplication of the private data
+//!
+//! The [`crate::deduper::Deduper`] only shares identical data segments: identical pages inside
+//! different segments (e.g., pages filled with `0xcc`, or buffers copied around by the process)
+//! are stored once per segment. The private pages are grouped by their digests (see
+//! [`Jif::page_digests`], which hashes each deduplicated segment once), telling how much of the
+//! private data is unique and which page contents are duplicated the most. These are the pages
+//! [`Jif::dedup_pages`] stores once
+
+use crate::digest::Sha256Hash;
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+
+use std::collections::HashMap;
+
+/// Contents of a private page found more than once in the JIF (see [`Jif::duplication`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicatePage {
+    /// Digest of the contents
+    pub digest: Sha256Hash,
+
+    /// Addresses of the pages with these contents (in address order)
+    pub addrs: Vec<u64>,
+
+    /// The byte the page is filled with, if it is a single byte repeated
+    pub fill: Option<u8>,
+}
+
+impl DuplicatePage {
+    /// Number of pages with these contents
+    pub fn copies(&self) -> usize {
+        self.addrs.len()
+    }
+}
+
+impl std::fmt::Display for DuplicatePage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} copies of ", self.copies())?;
+        match self.fill {
+            Some(byte) => write!(f, "a page of {:#04x}", byte)?,
+            None => {
+                for byte in &self.digest[..8] {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+        }
+        write!(f, ": [")?;
+        for (idx, addr) in self.addrs.iter().take(4).enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:#x}", addr)?;
+        }
+        if self.addrs.len() > 4 {
+            write!(f, ", ...")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Duplication of the private pages of a JIF (see [`Jif::duplication`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DuplicationReport {
+    /// Size of the pages, in B
+    pub page_size: usize,
+
+    /// Number of private pages
+    pub private_pages: usize,
+
+    /// Number of distinct private page contents
+    pub unique_pages: usize,
+
+    /// The most duplicated page contents, by decreasing number of copies
+    pub top: Vec<DuplicatePage>,
+}
+
+impl DuplicationReport {
+    /// Bytes of private data once every page is stored once
+    pub fn unique_bytes(&self) -> usize {
+        self.unique_pages * self.page_size
+    }
+
+    /// Bytes of private data which duplicate other pages
+    pub fn duplicated_bytes(&self) -> usize {
+        (self.private_pages - self.unique_pages) * self.page_size
+    }
+}
+
+impl std::fmt::Display for DuplicationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} private pages, {} unique",
+            self.private_pages, self.unique_pages
+        )?;
+        writeln!(
+            f,
+            "private:    {:#x} B",
+            self.private_pages * self.page_size
+        )?;
+        writeln!(f, "unique:     {:#x} B", self.unique_bytes())?;
+        writeln!(f, "duplicated: {:#x} B", self.duplicated_bytes())?;
+        if !self.top.is_empty() {
+            writeln!(f, "most duplicated pages:")?;
+            for page in &self.top {
+                writeln!(f, "  {}", page)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Jif {
+    /// Bytes of private data once every page is stored once (i.e., after [`Jif::dedup_pages`])
+    pub fn total_unique_private_bytes(&self) -> usize {
+        self.duplication(0).unique_bytes()
+    }
+
+    /// Duplication of the private pages, with the `top` most duplicated page contents
+    pub fn duplication(&self, top: usize) -> DuplicationReport {
+        let digests = self.page_digests(DataSource::Private);
+        let private_pages = digests.len();
+        let pages = group_by_digest(digests);
+        let unique_pages = pages.len();
+
+        let mut duplicated = pages
+            .into_iter()
+            .filter(|(_digest, addrs)| addrs.len() > 1)
+            .collect::<Vec<_>>();
+        duplicated.sort_by(|(_d1, a1), (_d2, a2)| a2.len().cmp(&a1.len()).then(a1[0].cmp(&a2[0])));
+
+        let top = duplicated
+            .into_iter()
+            .take(top)
+            .map(|(digest, addrs)| {
+                let fill = self.resolve_data(addrs[0]).and_then(|page| {
+                    let first = *page.first()?;
+                    page.iter().all(|byte| *byte == first).then_some(first)
+                });
+                DuplicatePage {
+                    digest,
+                    addrs,
+                    fill,
+                }
+            })
+            .collect();
+
+        DuplicationReport {
+            page_size: self.arch.page_size,
+            private_pages,
+            unique_pages,
+            top,
+        }
+    }
+
+    /// Addresses of the private pages whose contents appear more than once, grouped by contents
+    pub(crate) fn duplicated_pages(&self) -> Vec<Vec<u64>> {
+        group_by_digest(self.page_digests(DataSource::Private))
+            .into_values()
+            .filter(|addrs| addrs.len() > 1)
+            .collect()
+    }
+}
+
+/// Group the pages by their digests (the addresses of each group are kept in address order)
+fn group_by_digest(digests: Vec<(u64, Sha256Hash)>) -> HashMap<Sha256Hash, Vec<u64>> {
+    let mut pages: HashMap<Sha256Hash, Vec<u64>> = HashMap::new();
+    for (addr, digest) in digests {
+        pages.entry(digest).or_default().push(addr);
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::digest::sha256;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn duplication() {
+        let pages = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .flat_map(|byte| std::iter::repeat_n(*byte, PAGE_SIZE))
+                .collect::<Vec<_>>()
+        };
+        let mut copied = vec![0u8; PAGE_SIZE];
+        copied[0] = 1;
+
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x10000, 0x20000),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![
+                    (0x10000, pages(&[0xcc, 0xcc, 1])),
+                    (0x14000, copied.clone()),
+                ],
+            )
+            .add_anonymous_segment(
+                (0x20000, 0x30000),
+                ProtFlags::READ,
+                vec![(0x20000, pages(&[0xcc, 2])), (0x28000, copied.clone())],
+            );
+        let jif = builder.build().unwrap();
+
+        let report = jif.duplication(1);
+        assert_eq!(report.private_pages, 7);
+        assert_eq!(report.unique_pages, 4);
+        assert_eq!(report.duplicated_bytes(), 3 * PAGE_SIZE);
+        assert_eq!(
+            report.top,
+            vec![DuplicatePage {
+                digest: sha256(&[0xcc; PAGE_SIZE]),
+                addrs: vec![0x10000, 0x11000, 0x20000],
+                fill: Some(0xcc),
+            }]
+        );
+        assert_eq!(
+            report.top[0].to_string(),
+            "3 copies of a page of 0xcc: [0x10000, 0x11000, 0x20000]"
+        );
+        assert_eq!(jif.total_unique_private_bytes(), 4 * PAGE_SIZE);
+
+        // the copied buffer is not a single byte repeated
+        let report = jif.duplication(usize::MAX);
+        assert_eq!(report.top.len(), 2);
+        assert_eq!(report.top[1].addrs, vec![0x14000, 0x28000]);
+        assert_eq!(report.top[1].fill, None);
+    }
+}
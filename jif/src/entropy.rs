@@ -0,0 +1,146 @@
+//! Entropy and compressibility of the private data
+//!
+//! The estimates are computed over a sample of (at most [`MAX_SAMPLED_PAGES`]) private pages,
+//! evenly spread over the pheader. Compressibility is estimated with the LZ4 compression used
+//! for the data section (see [`Compression`](crate::Compression)); data with an entropy close to
+//! 8 bits per byte is likely to be already compressed (or encrypted)
+
+use crate::compress::lz4_compress;
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+use crate::pheader::JifPheader;
+
+/// Maximum number of pages sampled per pheader
+pub const MAX_SAMPLED_PAGES: usize = 256;
+
+/// Estimated entropy and compressibility of the private data of a pheader (see
+/// [`Jif::data_stats`])
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DataStats {
+    /// Number of bytes sampled
+    pub sampled_bytes: usize,
+
+    /// Shannon entropy of the sampled bytes, in bits per byte (between 0 and 8)
+    pub entropy: f64,
+
+    /// Size of the sampled bytes once compressed, in B
+    pub compressed_bytes: usize,
+}
+
+impl DataStats {
+    /// Estimated compression ratio (uncompressed over compressed size; 1 without samples)
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            1.0
+        } else {
+            self.sampled_bytes as f64 / self.compressed_bytes as f64
+        }
+    }
+}
+
+impl Jif {
+    /// Estimate the entropy and compressibility of the private data of one of the pheaders
+    pub fn data_stats(&self, pheader: &JifPheader) -> DataStats {
+        let page_size = self.arch.page_size;
+        let stride = pheader
+            .private_pages(page_size)
+            .div_ceil(MAX_SAMPLED_PAGES)
+            .max(1);
+
+        let sample = pheader
+            .itree()
+            .iter_logical_intervals()
+            .filter(|ival| ival.source == DataSource::Private)
+            .flat_map(|ival| (ival.start..ival.end).step_by(page_size))
+            .step_by(stride)
+            .filter_map(|addr| pheader.resolve_data(addr, &self.deduper, page_size))
+            .flatten()
+            .copied()
+            .collect::<Vec<u8>>();
+
+        if sample.is_empty() {
+            return DataStats::default();
+        }
+
+        DataStats {
+            sampled_bytes: sample.len(),
+            entropy: entropy(&sample),
+            compressed_bytes: lz4_compress(&sample).len(),
+        }
+    }
+}
+
+/// Shannon entropy of the bytes, in bits per byte
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::{AnonIntervalData, Interval};
+    use crate::itree::ITree;
+    use crate::jif::test::gen_jif;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn data_stats() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000)]),
+            ((0x10000, 0x14000), &[]),
+        ]);
+
+        // every byte value appears equally often (but not in a compressible pattern)
+        let mut state = 1u32;
+        let mut noise = (0..PAGE_SIZE)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<_>>();
+        noise[..256].copy_from_slice(&(0..=255).collect::<Vec<u8>>());
+        jif.pheaders.push(JifPheader::Anonymous {
+            vaddr_range: (0x20000, 0x21000),
+            itree: ITree::build(
+                vec![Interval::new(
+                    0x20000,
+                    0x21000,
+                    AnonIntervalData::Owned(noise),
+                )],
+                (0x20000, 0x21000),
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+        });
+
+        // gen_jif fills the intervals with a single byte
+        let repeated = jif.data_stats(&jif.pheaders()[0]);
+        assert_eq!(repeated.sampled_bytes, 2 * PAGE_SIZE);
+        assert_eq!(repeated.entropy, 0.0);
+        assert!(repeated.compression_ratio() > 50.0);
+
+        let empty = jif.data_stats(&jif.pheaders()[1]);
+        assert_eq!(empty, DataStats::default());
+        assert_eq!(empty.compression_ratio(), 1.0);
+
+        let random = jif.data_stats(&jif.pheaders()[2]);
+        assert_eq!(random.sampled_bytes, PAGE_SIZE);
+        assert!(random.entropy > 7.5, "{}", random.entropy);
+        assert!(random.compression_ratio() < 1.1);
+
+        assert_eq!(entropy(&[0, 1, 2, 3]), 2.0);
+    }
+}
@@ -17,6 +17,12 @@ pub enum IntervalError {
 
     /// Zero interval in anonymous segment
     ZeroIntervalInAnon,
+
+    /// The interval points to a data range (relative to the data section) which was not read
+    DataNotFound(u64, u64),
+
+    /// The data segment of the interval has a different length than the interval
+    BadDataLength(u64, usize),
 }
 
 impl std::fmt::Display for IntervalError {
@@ -37,6 +43,13 @@ impl std::fmt::Display for IntervalError {
             IntervalError::ZeroIntervalInAnon => {
                 f.write_str("anonymous segment has an explicit zero interval")
             }
+            IntervalError::DataNotFound(start, end) => {
+                f.write_fmt(format_args!("no data segment at [{:x}; {:x})", start, end))
+            }
+            IntervalError::BadDataLength(expected, found) => f.write_fmt(format_args!(
+                "data segment has {:x} B, but the interval spans {:x} B",
+                found, expected
+            )),
         }
     }
 }
@@ -2,15 +2,47 @@
 //!
 //! Encodes the error types possible when parsing/writing and manipulating JIF files
 
+use crate::arch::Arch;
 use crate::error::itree::ITreeError;
 use crate::error::itree_node::ITreeNodeError;
 use crate::error::ord::OrdChunkError;
 use crate::error::pheader::PheaderError;
 use crate::jif::JIF_MAGIC_HEADER;
+use crate::validate::Finding;
 
 /// JIF result type
 pub type JifResult<T> = core::result::Result<T, JifError>;
 
+/// Section of a JIF file (see [`JifError::InSection`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JifSection {
+    Header,
+    Pheaders,
+    Strings,
+    ITrees,
+    Ord,
+    Data,
+    PageIndex,
+    Meta,
+    Integrity,
+}
+
+impl std::fmt::Display for JifSection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            JifSection::Header => "header",
+            JifSection::Pheaders => "pheaders",
+            JifSection::Strings => "strings",
+            JifSection::ITrees => "itrees",
+            JifSection::Ord => "ord",
+            JifSection::Data => "data",
+            JifSection::PageIndex => "page index",
+            JifSection::Meta => "meta",
+            JifSection::Integrity => "integrity",
+        })
+    }
+}
+
 /// JIF error type
 #[derive(Debug)]
 pub enum JifError {
@@ -29,15 +61,160 @@ pub enum JifError {
         found: u32,
     },
 
+    /// Unknown flags in the header
+    BadFlags {
+        flags: u32,
+    },
+
     /// A particular section was poorly aligned
     BadAlignment,
 
+    /// Unsupported page size (see [`crate::Jif::page_size`])
+    BadPageSize {
+        page_size: u64,
+    },
+
+    /// Unsupported interval tree fanout (see [`crate::JifRaw::set_itree_fanout`])
+    BadFanout {
+        fanout: usize,
+    },
+
+    /// Unsupported alignment of the data segments (see [`crate::layout::DataLayout::new`])
+    BadDataAlignment {
+        alignment: u64,
+    },
+
+    /// Unknown instruction set in the architecture tag
+    UnknownIsa {
+        isa: u32,
+    },
+
+    /// Inconsistent architecture tag (e.g., a page size the architecture does not support)
+    BadArch {
+        arch: Arch,
+    },
+
+    /// The JIFs (e.g., being merged) have different architectures
+    ArchMismatch {
+        expected: Arch,
+        found: Arch,
+    },
+
+    /// A block of the compressed data section is corrupted
+    BadCompressedBlock {
+        block_idx: usize,
+    },
+
+    /// The operation requires direct access to the data section, which is compressed
+    CompressedDataSection,
+
+    /// The JIF is a delta, and its base has not been resolved
+    DeltaWithoutBase,
+
+    /// The base of a delta JIF is itself a delta
+    BaseIsDelta,
+
+    /// The page hash index is malformed
+    BadPageIndex {
+        reason: &'static str,
+    },
+
+    /// The custom metadata section is malformed
+    BadMeta {
+        reason: &'static str,
+    },
+
+    /// The integrity trailer is malformed
+    BadIntegrityTrailer,
+
+    /// The checksum of a data segment does not match the integrity section
+    BadSegmentChecksum {
+        data_range: (u64, u64),
+    },
+
+    /// The checksum of the file does not match the integrity section
+    BadFileChecksum {
+        expected: u32,
+        found: u32,
+    },
+
     /// Error with a particular pheader
     BadPheader {
         pheader_idx: usize,
         pheader_err: PheaderError,
     },
 
+    /// Two pheaders map intersecting virtual ranges
+    OverlappingPheaders {
+        pheader_1: (u64, u64),
+        pheader_2: (u64, u64),
+    },
+
+    /// A pheader salvaged from a corrupt file fails the validation (see
+    /// [`crate::Jif::from_reader_lenient`])
+    InvalidPheader {
+        virtual_range: (u64, u64),
+        finding: Finding,
+    },
+
+    /// A pheader cannot be moved by `delta` bytes (the delta is not page aligned, the pheader
+    /// is partially outside the moved range, or it would leave the address space)
+    BadMove {
+        virtual_range: (u64, u64),
+        delta: i64,
+    },
+
+    /// The range of an update is not page aligned, is not inside a single pheader, or does not
+    /// match the length of the new data
+    BadUpdate {
+        range: (u64, u64),
+        len: usize,
+    },
+
+    /// The range of a protection change is empty or not page aligned
+    BadProtRange {
+        range: (u64, u64),
+    },
+
+    /// The maximum size of a pheader is zero or not page aligned (see
+    /// [`crate::Jif::split_large_pheaders`])
+    BadPheaderSize {
+        max_size: u64,
+    },
+
+    /// The data blob of a split JIF is malformed, or does not match its metadata
+    BadDataBlob {
+        reason: &'static str,
+    },
+
+    /// The JIF cannot be rewritten over the file in place
+    CannotRewriteInPlace {
+        reason: &'static str,
+    },
+
+    /// The address is not mapped by any pheader
+    UnmappedAddress {
+        addr: u64,
+    },
+
+    /// Malformed entry of `/proc/<pid>/maps`
+    BadMapsEntry {
+        line: String,
+    },
+
+    /// The page size of the JIF is not the one of the system it is loaded on
+    LoadPageSize {
+        page_size: usize,
+        system_page_size: usize,
+    },
+
+    /// An operation of the restore plan failed while loading the JIF (see [`crate::restore`])
+    LoadFailed {
+        op: &'static str,
+        range: (u64, u64),
+        error: std::io::Error,
+    },
+
     /// Error with a particular itree node
     BadITreeNode {
         itree_node_idx: usize,
@@ -79,6 +256,57 @@ pub enum JifError {
         virtual_range: (u64, u64),
         error: ITreeError,
     },
+
+    /// Error reading one of several JIF files
+    InFile {
+        path: std::path::PathBuf,
+        error: Box<JifError>,
+    },
+
+    /// Error parsing the bytes of a JIF file
+    InSection {
+        section: JifSection,
+
+        /// Absolute byte range (`[start; end)`) of the item which failed to parse
+        file_range: (u64, u64),
+
+        error: Box<JifError>,
+    },
+}
+
+impl JifError {
+    /// Locate the error in the bytes of the file (unless it is already located)
+    pub(crate) fn in_section(self, section: JifSection, file_range: (u64, u64)) -> Self {
+        match self {
+            JifError::InSection { .. } => self,
+            error => JifError::InSection {
+                section,
+                file_range,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Section and absolute byte range of the file where parsing failed, if known
+    pub fn file_range(&self) -> Option<(JifSection, (u64, u64))> {
+        match self {
+            JifError::InSection {
+                section,
+                file_range,
+                ..
+            } => Some((*section, *file_range)),
+            JifError::InFile { error, .. } => error.file_range(),
+            _ => None,
+        }
+    }
+
+    /// The error, without the file and location it happened in
+    pub fn root(&self) -> &JifError {
+        match self {
+            JifError::InSection { error, .. } | JifError::InFile { error, .. } => error.root(),
+            error => error,
+        }
+    }
 }
 
 impl std::fmt::Display for JifError {
@@ -91,7 +319,56 @@ impl std::fmt::Display for JifError {
                 f.debug_list().entries(JIF_MAGIC_HEADER.iter()).finish()
             }
             JifError::BadHeader => f.write_str("bad header"),
+            JifError::BadFlags { flags } => f.write_fmt(format_args!("bad header flags: {:#x}", flags)),
             JifError::BadAlignment => f.write_str("bad alignment"),
+            JifError::BadPageSize { page_size } => {
+                f.write_fmt(format_args!("unsupported page size: {:#x}", page_size))
+            }
+            JifError::BadFanout { fanout } => f.write_fmt(format_args!(
+                "unsupported interval tree fanout: {} (expected a power of two from 4 to 512)",
+                fanout
+            )),
+            JifError::BadDataAlignment { alignment } => f.write_fmt(format_args!(
+                "unsupported data segment alignment: {:#x} (expected a power of two)",
+                alignment
+            )),
+            JifError::UnknownIsa { isa } => {
+                f.write_fmt(format_args!("unknown instruction set in the header: {}", isa))
+            }
+            JifError::BadArch { arch } => {
+                f.write_fmt(format_args!("inconsistent architecture: {}", arch))
+            }
+            JifError::ArchMismatch { expected, found } => f.write_fmt(format_args!(
+                "architecture mismatch: expected {} found {}",
+                expected, found
+            )),
+            JifError::BadCompressedBlock { block_idx } => f.write_fmt(format_args!(
+                "corrupted compressed data block (idx = {})",
+                block_idx
+            )),
+            JifError::CompressedDataSection => {
+                f.write_str("data section is compressed and cannot be accessed directly")
+            }
+            JifError::DeltaWithoutBase => {
+                f.write_str("delta JIF references data in a base JIF, which was not provided")
+            }
+            JifError::BaseIsDelta => f.write_str("the base JIF cannot itself be a delta"),
+            JifError::BadPageIndex { reason } => {
+                f.write_fmt(format_args!("malformed page hash index: {}", reason))
+            }
+            JifError::BadMeta { reason } => f.write_fmt(format_args!(
+                "malformed custom metadata section: {}",
+                reason
+            )),
+            JifError::BadIntegrityTrailer => f.write_str("malformed integrity section"),
+            JifError::BadSegmentChecksum { data_range } => f.write_fmt(format_args!(
+                "checksum mismatch in data segment [{:#x}; {:#x})",
+                data_range.0, data_range.1
+            )),
+            JifError::BadFileChecksum { expected, found } => f.write_fmt(format_args!(
+                "file checksum mismatch: expected {:#010x} found {:#010x}",
+                expected, found
+            )),
             JifError::BadVersion { expected, found } => {
                 f.write_str("bad version, expected v")?;
                 expected.fmt(f)?;
@@ -105,6 +382,65 @@ impl std::fmt::Display for JifError {
                 "bad pheader (idx = {}): {}",
                 pheader_idx, pheader_err
             )),
+            JifError::BadMove {
+                virtual_range,
+                delta,
+            } => f.write_fmt(format_args!(
+                "cannot move pheader [{:#x}; {:#x}) by {}{:#x}",
+                virtual_range.0,
+                virtual_range.1,
+                if *delta < 0 { "-" } else { "" },
+                delta.unsigned_abs()
+            )),
+            JifError::BadUpdate { range, len } => f.write_fmt(format_args!(
+                "cannot update [{:#x}; {:#x}) with {:#x} B",
+                range.0, range.1, len
+            )),
+            JifError::BadProtRange { range } => f.write_fmt(format_args!(
+                "cannot change the protections of [{:#x}; {:#x})",
+                range.0, range.1
+            )),
+            JifError::BadPheaderSize { max_size } => f.write_fmt(format_args!(
+                "cannot split the pheaders in pieces of {:#x} B",
+                max_size
+            )),
+            JifError::BadDataBlob { reason } => {
+                f.write_fmt(format_args!("bad data blob: {}", reason))
+            }
+            JifError::CannotRewriteInPlace { reason } => {
+                f.write_fmt(format_args!("cannot rewrite the JIF in place: {}", reason))
+            }
+            JifError::UnmappedAddress { addr } => {
+                f.write_fmt(format_args!("address {:#x} is not mapped", addr))
+            }
+            JifError::BadMapsEntry { line } => {
+                f.write_fmt(format_args!("malformed maps entry: {:?}", line))
+            }
+            JifError::LoadPageSize {
+                page_size,
+                system_page_size,
+            } => f.write_fmt(format_args!(
+                "cannot load a JIF with {:#x} B pages on a system with {:#x} B pages",
+                page_size, system_page_size
+            )),
+            JifError::LoadFailed { op, range, error } => f.write_fmt(format_args!(
+                "failed to {} [{:#x}; {:#x}): {}",
+                op, range.0, range.1, error
+            )),
+            JifError::OverlappingPheaders {
+                pheader_1,
+                pheader_2,
+            } => f.write_fmt(format_args!(
+                "pheaders [{:#x}; {:#x}) and [{:#x}; {:#x}) overlap",
+                pheader_1.0, pheader_1.1, pheader_2.0, pheader_2.1
+            )),
+            JifError::InvalidPheader {
+                virtual_range,
+                finding,
+            } => f.write_fmt(format_args!(
+                "pheader [{:#x}; {:#x}) is invalid: {}",
+                virtual_range.0, virtual_range.1, finding
+            )),
             JifError::BadOrdChunk {
                 ord_chunk_idx,
                 ord_chunk_err,
@@ -138,6 +474,18 @@ impl std::fmt::Display for JifError {
                 "could not find full interval tree at [{}; {}) (there are only {} itree nodes)",
                 index, len, n_nodes
             )),
+            // the wrapped errors are their source
+            JifError::InFile { path, .. } => {
+                f.write_fmt(format_args!("failed to read {}", path.display()))
+            }
+            JifError::InSection {
+                section,
+                file_range,
+                ..
+            } => f.write_fmt(format_args!(
+                "failed to parse the {} section at [{:#x}; {:#x})",
+                section, file_range.0, file_range.1
+            )),
         }
     }
 }
@@ -148,14 +496,44 @@ impl std::error::Error for JifError {
             JifError::IoError(io) => Some(io),
             JifError::BadMagic => None,
             JifError::BadHeader => None,
+            JifError::BadFlags { .. } => None,
             JifError::BadAlignment => None,
+            JifError::BadPageSize { .. } => None,
+            JifError::BadFanout { .. } => None,
+            JifError::BadDataAlignment { .. } => None,
+            JifError::UnknownIsa { .. } => None,
+            JifError::BadArch { .. } => None,
+            JifError::ArchMismatch { .. } => None,
+            JifError::BadCompressedBlock { .. } => None,
+            JifError::CompressedDataSection => None,
+            JifError::DeltaWithoutBase => None,
+            JifError::BaseIsDelta => None,
+            JifError::BadPageIndex { .. } => None,
+            JifError::BadMeta { .. } => None,
+            JifError::BadIntegrityTrailer => None,
+            JifError::BadSegmentChecksum { .. } => None,
+            JifError::BadFileChecksum { .. } => None,
             JifError::BadVersion { .. } => None,
             JifError::BadPheader { pheader_err, .. } => Some(pheader_err),
+            JifError::OverlappingPheaders { .. } => None,
+            JifError::InvalidPheader { .. } => None,
+            JifError::BadMove { .. } => None,
+            JifError::BadUpdate { .. } => None,
+            JifError::BadProtRange { .. } => None,
+            JifError::BadPheaderSize { .. } => None,
+            JifError::BadDataBlob { .. } => None,
+            JifError::CannotRewriteInPlace { .. } => None,
+            JifError::UnmappedAddress { .. } => None,
+            JifError::BadMapsEntry { .. } => None,
+            JifError::LoadPageSize { .. } => None,
+            JifError::LoadFailed { error, .. } => Some(error),
             JifError::BadITreeNode { itree_node_err, .. } => Some(itree_node_err),
             JifError::BadOrdChunk { ord_chunk_err, .. } => Some(ord_chunk_err),
             JifError::InvalidITree { error, .. } => Some(error),
             JifError::DataSegmentNotFound { .. } => None,
             JifError::ITreeNotFound { .. } => None,
+            JifError::InFile { error, .. } => Some(error),
+            JifError::InSection { error, .. } => Some(error),
         }
     }
 }
@@ -8,6 +8,18 @@ pub enum OrdChunkError {
 
     /// The integer should have been page aligned, but wasn't
     BadAlignment(u64),
+
+    /// The address is not mapped by any pheader
+    UnmappedAddress(u64),
+
+    /// The provenance word has unknown (or conflicting) bits set
+    BadProvenance(u64),
+
+    /// The virtual address does not carry a (single) known kind flag
+    BadKind(u64),
+
+    /// The chunk (at the virtual address, with the number of pages) overflows the address space
+    Overflow(u64, u64),
 }
 
 impl std::fmt::Display for OrdChunkError {
@@ -19,6 +31,21 @@ impl std::fmt::Display for OrdChunkError {
                 "expected virtual address to be page aligned: {:x}",
                 v
             )),
+            OrdChunkError::UnmappedAddress(v) => f.write_fmt(format_args!(
+                "virtual address is not mapped by any pheader: {:x}",
+                v
+            )),
+            OrdChunkError::BadProvenance(v) => {
+                f.write_fmt(format_args!("invalid provenance word: {:x}", v))
+            }
+            OrdChunkError::BadKind(v) => f.write_fmt(format_args!(
+                "invalid kind flags in virtual address: {:x}",
+                v
+            )),
+            OrdChunkError::Overflow(vaddr, n_pages) => f.write_fmt(format_args!(
+                "chunk of {} pages at {:x} overflows the address space",
+                n_pages, vaddr
+            )),
         }
     }
 }
@@ -0,0 +1,142 @@
+//! Interval tree fanout
+//!
+//! The interval trees are laid out as balanced B-Trees: with the default fanout of 4, each node
+//! holds 3 intervals. Wider nodes make for shallower trees (fewer nodes to visit on a lookup, at
+//! the cost of more intervals to compare in each), which suits the large pheaders of long running
+//! processes. The fanout the nodes were written with is recorded in the header (see
+//! [`JifRaw::set_itree_fanout`]); materializing a JIF lays its trees out anew with the default
+//! fanout
+
+use crate::error::*;
+use crate::itree::balanced_layout;
+use crate::itree::interval::RawInterval;
+use crate::itree::itree_node::{RawITreeNode, FANOUT};
+use crate::jif::{JifRaw, JIF_FANOUT_SHIFT_MASK};
+
+const JIF_FANOUT_SHIFT_OFFSET: u32 = JIF_FANOUT_SHIFT_MASK.trailing_zeros();
+
+/// Largest supported fanout (the largest shift the header can hold)
+const MAX_FANOUT: usize = FANOUT << (JIF_FANOUT_SHIFT_MASK >> JIF_FANOUT_SHIFT_OFFSET);
+
+/// Decode the fanout from the header flags
+pub(crate) fn fanout_from_flags(flags: u32) -> usize {
+    FANOUT << ((flags & JIF_FANOUT_SHIFT_MASK) >> JIF_FANOUT_SHIFT_OFFSET)
+}
+
+/// Encode the fanout in the header flags
+pub(crate) fn fanout_flags(fanout: usize) -> u32 {
+    (fanout / FANOUT).trailing_zeros() << JIF_FANOUT_SHIFT_OFFSET
+}
+
+impl JifRaw {
+    /// Fanout of the interval trees (i.e., each node holds `fanout - 1` intervals)
+    pub fn itree_fanout(&self) -> usize {
+        self.itree_fanout
+    }
+
+    /// Lay the interval trees out anew with a different fanout (a power of two from 4 to 512)
+    ///
+    /// Only the nodes (and the spans of the pheaders into them) change, along with the data
+    /// offset when the interval tree section changes size: the intervals keep pointing at the
+    /// same data
+    pub fn set_itree_fanout(&mut self, fanout: usize) -> JifResult<()> {
+        if !fanout.is_power_of_two() || !(FANOUT..=MAX_FANOUT).contains(&fanout) {
+            return Err(JifError::BadFanout { fanout });
+        } else if fanout == self.itree_fanout {
+            return Ok(());
+        }
+
+        let mut itree_nodes = Vec::new();
+        for pheader in self.pheaders.iter_mut() {
+            let mut intervals = pheader
+                .itree()
+                .map(|(idx, n)| {
+                    self.itree_nodes[idx as usize..(idx + n) as usize]
+                        .iter()
+                        .flat_map(|node| node.ranges.iter())
+                        .filter(|ival| !ival.is_empty())
+                        .copied()
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            intervals.sort_by_key(|ival| ival.start);
+
+            let nodes = balanced_layout(intervals, fanout);
+            pheader.itree_idx = itree_nodes.len() as u32;
+            pheader.itree_n_nodes = nodes.len() as u32;
+            itree_nodes.extend(nodes.into_iter().map(|mut ranges| {
+                ranges.resize(fanout - 1, RawInterval::default());
+                RawITreeNode::new(ranges)
+            }));
+        }
+
+        self.itree_nodes = itree_nodes;
+        self.itree_fanout = fanout;
+
+        // move the data section up to the end of the (resized) metadata, keeping the segments
+        // aligned (see [`DataLayout`](crate::DataLayout))
+        let alignment = self.data_layout.data_offset_alignment(self.arch.page_size);
+        let metadata_size = self.metadata_size();
+        let data_offset = metadata_size
+            + (self.data_offset % alignment + alignment - metadata_size % alignment) % alignment;
+        for ival in self
+            .itree_nodes
+            .iter_mut()
+            .flat_map(|node| node.ranges.iter_mut())
+            .filter(|ival| ival.is_data())
+        {
+            ival.offset = ival.offset - self.data_offset + data_offset;
+        }
+        self.data_offset = data_offset;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::test::gen_jif;
+    use crate::jif::Jif;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn itree_fanout() {
+        let intervals = (0..20)
+            .map(|idx| (0x100000 + idx * 0x2000, 0x101000 + idx * 0x2000))
+            .collect::<Vec<_>>();
+        let jif = || {
+            gen_jif(&[
+                ((0x1000, 0x5000), &[(0x1000, 0x2000), (0x3000, 0x5000)]),
+                ((0x100000, 0x140000), &intervals),
+            ])
+        };
+        let expected = format!("{:?}", jif());
+
+        let mut raw = JifRaw::from_materialized(jif(), false);
+        for fanout in [2, 12, 1024] {
+            assert!(raw.set_itree_fanout(fanout).is_err());
+        }
+
+        for fanout in [8, 64, 512] {
+            let mut raw = JifRaw::from_materialized(jif(), false);
+            let n_nodes = raw.itree_nodes.len();
+            raw.set_itree_fanout(fanout).unwrap();
+            assert!(raw.itree_nodes.len() < n_nodes);
+            assert!(raw
+                .itree_nodes
+                .iter()
+                .all(|node| node.ranges.len() == fanout - 1));
+
+            let mut file = Vec::new();
+            raw.to_writer(&mut file).unwrap();
+            let raw = JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+            assert_eq!(raw.itree_fanout(), fanout);
+
+            // materializing goes back to the default fanout
+            let jif = Jif::from_raw(raw).unwrap();
+            assert_eq!(format!("{:?}", jif), expected);
+            assert!(jif.validate().is_clean(), "{}", jif.validate());
+        }
+    }
+}
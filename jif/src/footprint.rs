@@ -0,0 +1,172 @@
+//! Memory and disk footprint estimation
+//!
+//! Restoring a JIF maps its private data into the address space, while the shared pages come
+//! from the page cache: they only take up memory of their own when written to (copy on write).
+//! The zero pages take up no memory until they are touched. The estimate assumes every private
+//! page is read in and every shared page of a writable VMA is eventually copied
+
+use crate::itree::itree_node::{RawITreeNode, FANOUT};
+use crate::jif::Jif;
+use crate::pheader::{JifPheader, ProtFlags};
+
+/// Estimated footprint of a [`Jif`] (see [`Jif::restore_footprint`]), in B
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Footprint {
+    /// Private data mapped in on restore
+    pub private_bytes: usize,
+
+    /// Shared pages expected to be copied on write (those of writable VMAs)
+    pub cow_bytes: usize,
+
+    /// Size of the JIF file
+    pub disk: DiskFootprint,
+
+    /// Contribution of each pheader (in pheader order)
+    pub pheaders: Vec<PheaderFootprint>,
+}
+
+impl Footprint {
+    /// Estimated resident set size after restore
+    pub fn rss_bytes(&self) -> usize {
+        self.private_bytes + self.cow_bytes
+    }
+}
+
+/// Size of a JIF file by section, in B (each section is padded to the page size)
+///
+/// The data size is the size of the data once deduplicated and uncompressed, and the optional
+/// integrity section is not included
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiskFootprint {
+    /// Header and pheaders
+    pub metadata: usize,
+
+    /// String table
+    pub strings: usize,
+
+    /// Interval tree nodes
+    pub itrees: usize,
+
+    /// Ordering section
+    pub ord: usize,
+
+    /// Data section
+    pub data: usize,
+}
+
+impl DiskFootprint {
+    /// Size of the JIF file
+    pub fn size(&self) -> usize {
+        self.metadata + self.strings + self.itrees + self.ord + self.data
+    }
+}
+
+/// Estimated footprint of a pheader, in B
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PheaderFootprint {
+    pub virtual_range: (u64, u64),
+
+    /// Private data mapped in on restore (before deduplication)
+    pub private_bytes: usize,
+
+    /// Shared pages expected to be copied on write
+    pub cow_bytes: usize,
+
+    /// Size of the interval tree nodes (unpadded)
+    pub itree_bytes: usize,
+}
+
+impl PheaderFootprint {
+    /// Estimated contribution to the resident set size after restore
+    pub fn rss_bytes(&self) -> usize {
+        self.private_bytes + self.cow_bytes
+    }
+}
+
+impl Jif {
+    /// Estimate the memory footprint of restoring the JIF (and the size of the JIF file)
+    ///
+    /// Private data shared between intervals is mapped once, so it only counts once towards the
+    /// total (but once per pheader in the pheader contributions)
+    pub fn restore_footprint(&self) -> Footprint {
+        let page_size = self.arch.page_size;
+        let pheaders = self
+            .pheaders
+            .iter()
+            .map(|pheader| PheaderFootprint {
+                virtual_range: pheader.virtual_range(),
+                private_bytes: pheader.data_size(),
+                cow_bytes: match pheader {
+                    JifPheader::Reference { prot, .. } if prot.contains(ProtFlags::WRITE) => {
+                        pheader.shared_pages(page_size) * page_size
+                    }
+                    _ => 0,
+                },
+                itree_bytes: pheader.n_itree_nodes() * RawITreeNode::serialized_size(FANOUT),
+            })
+            .collect::<Vec<_>>();
+
+        let stored_data_size = self.stored_data_size();
+        Footprint {
+            private_bytes: stored_data_size,
+            cow_bytes: pheaders.iter().map(|pheader| pheader.cow_bytes).sum(),
+            disk: DiskFootprint {
+                data: stored_data_size,
+                ..self.metadata_footprint()
+            },
+            pheaders,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::RefIntervalData;
+    use crate::itree::ITree;
+    use crate::jif::test::gen_jif;
+    use crate::jif::JifRaw;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn restore_footprint() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+        for (prot, start) in [(ProtFlags::READ, 0x20000), (ProtFlags::WRITE, 0x30000)] {
+            jif.pheaders.push(JifPheader::Reference {
+                vaddr_range: (start, start + 0x4000),
+                itree: ITree::single((start, start + 0x1000), RefIntervalData::Zero),
+                prot,
+                ref_path: "/lib/libc.so".to_string(),
+                ref_offset: 0,
+            });
+        }
+
+        let footprint = jif.restore_footprint();
+        // every interval of gen_jif holds the same bytes: the 2 page intervals are stored once
+        assert_eq!(footprint.private_bytes, 3 * PAGE_SIZE);
+        assert_eq!(footprint.cow_bytes, 3 * PAGE_SIZE);
+        assert_eq!(footprint.rss_bytes(), 6 * PAGE_SIZE);
+        assert_eq!(
+            footprint
+                .pheaders
+                .iter()
+                .map(|pheader| (pheader.private_bytes, pheader.cow_bytes))
+                .collect::<Vec<_>>(),
+            vec![
+                (3 * PAGE_SIZE, 0),
+                (2 * PAGE_SIZE, 0),
+                (0, 0),
+                (0, 3 * PAGE_SIZE)
+            ]
+        );
+
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        assert_eq!(footprint.disk.size(), buffer.len());
+    }
+}
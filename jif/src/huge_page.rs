@@ -0,0 +1,338 @@
+//! Huge page (2MiB) awareness
+//!
+//! A 2MiB aligned region can be mapped with a transparent huge page only when it is backed by a
+//! single source: one data interval covering all of it, or no interval at all (i.e., entirely
+//! zero for anonymous pheaders, entirely shared for reference ones). Building the interval trees
+//! fractures the data around every zero page, which saves space but makes those regions
+//! unmappable as huge pages: aligning undoes the fracturing where it saves only a few zero pages
+
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::pheader::JifPheader;
+use crate::utils::{align, align_down, HUGE_PAGE_SIZE};
+
+impl Jif {
+    /// Merge the intervals of the 2MiB aligned regions which would be huge mappable if at most
+    /// `max_zero_pages` zero pages were stored as data
+    ///
+    /// Only the regions without shared pages are merged (the shared pages would have to be
+    /// copied in). Returns the number of merged regions
+    pub fn align_huge_pages(&mut self, max_zero_pages: usize) -> JifResult<usize> {
+        let mut n_regions = 0;
+        for pheader in self.pheaders.iter_mut() {
+            let virtual_range = pheader.virtual_range();
+            let invalid_itree = |error| JifError::InvalidITree {
+                virtual_range,
+                error,
+            };
+            match pheader {
+                JifPheader::Anonymous { itree, .. } => {
+                    let (intervals, merged) = merge_regions(
+                        itree.take().into_iter_intervals().collect(),
+                        virtual_range,
+                        &self.deduper,
+                        self.arch.page_size,
+                        max_zero_pages,
+                        true,
+                        AnonIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                    n_regions += merged;
+                }
+                JifPheader::Reference { itree, .. } => {
+                    let (intervals, merged) = merge_regions(
+                        itree.take().into_iter_intervals().collect(),
+                        virtual_range,
+                        &self.deduper,
+                        self.arch.page_size,
+                        max_zero_pages,
+                        false,
+                        RefIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                    n_regions += merged;
+                }
+            }
+        }
+
+        Ok(n_regions)
+    }
+
+    /// Number of pages which can be mapped with huge pages
+    pub fn huge_mappable_pages(&self) -> usize {
+        self.pheaders
+            .iter()
+            .map(|phdr| phdr.huge_mappable_pages(self.arch.page_size))
+            .sum()
+    }
+}
+
+impl JifPheader {
+    /// Number of pages (of `page_size`) in 2MiB aligned regions backed by a single source
+    /// (i.e., which can be mapped with a huge page)
+    pub fn huge_mappable_pages(&self, page_size: usize) -> usize {
+        /// Size of the huge mappable regions
+        fn mappable_size<Data: IntervalData>(
+            itree: &ITree<Data>,
+            virtual_range: (u64, u64),
+        ) -> usize {
+            let intervals = itree
+                .in_order_intervals()
+                .filter(|ival| !ival.data.is_none())
+                .collect::<Vec<_>>();
+            huge_regions(virtual_range)
+                .filter(|region| {
+                    let mut overlapping = overlapping(&intervals, *region);
+                    match (overlapping.next(), overlapping.next()) {
+                        (None, _) => true,
+                        (Some(ival), None) => ival.start <= region.0 && region.1 <= ival.end,
+                        _ => false,
+                    }
+                })
+                .count()
+                * HUGE_PAGE_SIZE
+        }
+
+        match self {
+            JifPheader::Anonymous {
+                itree, vaddr_range, ..
+            } => mappable_size(itree, *vaddr_range) / page_size,
+            JifPheader::Reference {
+                itree, vaddr_range, ..
+            } => mappable_size(itree, *vaddr_range) / page_size,
+        }
+    }
+}
+
+/// The 2MiB aligned regions fully inside a virtual range
+fn huge_regions((start, end): (u64, u64)) -> impl Iterator<Item = (u64, u64)> {
+    let first = align(start, HUGE_PAGE_SIZE);
+    let last = align_down(end, HUGE_PAGE_SIZE);
+    (first..last)
+        .step_by(HUGE_PAGE_SIZE)
+        .map(|region_start| (region_start, region_start + HUGE_PAGE_SIZE as u64))
+}
+
+/// The intervals (sorted by address) overlapping a region
+fn overlapping<'a, 'b, Data: IntervalData>(
+    intervals: &'b [&'a Interval<Data>],
+    (start, end): (u64, u64),
+) -> impl Iterator<Item = &'a Interval<Data>> + 'b {
+    let first = intervals.partition_point(|ival| ival.end <= start);
+    intervals[first..]
+        .iter()
+        .take_while(move |ival| ival.start < end)
+        .copied()
+}
+
+/// Merge the intervals of the regions with few enough zero pages into a single data interval
+///
+/// Zero pages are the intervals referencing the zero page, plus the missing intervals when
+/// `missing_is_zero` (otherwise regions with missing intervals are not merged)
+fn merge_regions<Data: IntervalData + Clone>(
+    mut intervals: Vec<Interval<Data>>,
+    virtual_range: (u64, u64),
+    deduper: &Deduper,
+    page_size: usize,
+    max_zero_pages: usize,
+    missing_is_zero: bool,
+    owned: fn(Vec<u8>) -> Data,
+) -> (Vec<Interval<Data>>, usize) {
+    intervals.retain(|ival| !ival.data.is_none());
+    intervals.sort_by_key(|ival| ival.start);
+
+    let merged_regions = {
+        let refs = intervals.iter().collect::<Vec<_>>();
+        huge_regions(virtual_range)
+            .filter(|region| {
+                let (mut data_pages, mut zero_pages, mut n_intervals) = (0, 0, 0);
+                for ival in overlapping(&refs, *region) {
+                    let len =
+                        std::cmp::min(ival.end, region.1) - std::cmp::max(ival.start, region.0);
+                    let pages = len as usize / page_size;
+                    if ival.data.is_data() {
+                        data_pages += pages;
+                    } else if ival.data.is_zero() {
+                        zero_pages += pages;
+                    }
+                    n_intervals += 1;
+                }
+
+                let missing_pages = HUGE_PAGE_SIZE / page_size - data_pages - zero_pages;
+                if missing_pages > 0 && !missing_is_zero {
+                    return false;
+                }
+
+                let zero_pages = zero_pages + missing_pages;
+                let single_source = n_intervals <= 1 && (data_pages == 0 || zero_pages == 0);
+                data_pages > 0 && !single_source && zero_pages <= max_zero_pages
+            })
+            .collect::<Vec<_>>()
+    };
+
+    if merged_regions.is_empty() {
+        return (intervals, 0);
+    }
+
+    let mut merged = merged_regions
+        .iter()
+        .map(|(start, _end)| (*start, vec![0u8; HUGE_PAGE_SIZE]))
+        .collect::<Vec<_>>();
+    let mut result = Vec::with_capacity(intervals.len() + merged.len());
+    for ival in intervals {
+        let first = merged_regions.partition_point(|region| region.1 <= ival.start);
+        let last = merged_regions.partition_point(|region| region.0 < ival.end);
+        if first == last {
+            result.push(ival);
+            continue;
+        }
+
+        let data = ival.data.get_data(deduper);
+        let slice = |start: u64, end: u64| {
+            data.map(|data| {
+                data[(start - ival.start) as usize..(end - ival.start) as usize].to_vec()
+            })
+        };
+
+        // copy the parts inside merged regions into them, keep the ones in between
+        let mut cursor = ival.start;
+        for (region_idx, (region_start, region_end)) in merged_regions[first..last]
+            .iter()
+            .enumerate()
+            .map(|(idx, region)| (first + idx, *region))
+        {
+            if cursor < region_start {
+                result.push(split_part(&ival, cursor, region_start, slice, owned));
+            }
+
+            let start = std::cmp::max(cursor, region_start);
+            let end = std::cmp::min(ival.end, region_end);
+            if let Some(part) = slice(start, end) {
+                let offset = (start - region_start) as usize;
+                merged[region_idx].1[offset..offset + part.len()].copy_from_slice(&part);
+            }
+            cursor = end;
+        }
+        if cursor < ival.end {
+            result.push(split_part(&ival, cursor, ival.end, slice, owned));
+        }
+    }
+
+    let n_merged = merged.len();
+    result.extend(
+        merged
+            .into_iter()
+            .map(|(start, data)| Interval::new(start, start + HUGE_PAGE_SIZE as u64, owned(data))),
+    );
+    result.sort_by_key(|ival| ival.start);
+
+    (result, n_merged)
+}
+
+/// Part `[start, end)` of an interval, with the same source
+pub(crate) fn split_part<Data: IntervalData + Clone>(
+    ival: &Interval<Data>,
+    start: u64,
+    end: u64,
+    slice: impl Fn(u64, u64) -> Option<Vec<u8>>,
+    owned: fn(Vec<u8>) -> Data,
+) -> Interval<Data> {
+    match slice(start, end) {
+        Some(data) => Interval::new(start, end, owned(data)),
+        None => Interval::new(start, end, ival.data.clone()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::jif::JifRaw;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::collections::BTreeMap;
+    use std::io::BufReader;
+
+    /// Number of (base) pages in a huge page
+    const PAGES_PER_HUGE_PAGE: usize = HUGE_PAGE_SIZE / PAGE_SIZE;
+
+    /// An anonymous pheader with a data interval for each `(start, end, byte)`
+    fn gen_anon(vaddr_range: (u64, u64), ivals: &[(u64, u64, u8)]) -> JifPheader {
+        JifPheader::Anonymous {
+            vaddr_range,
+            itree: ITree::build(
+                ivals
+                    .iter()
+                    .map(|(start, end, byte)| {
+                        Interval::new(
+                            *start,
+                            *end,
+                            AnonIntervalData::Owned(vec![*byte; (end - start) as usize]),
+                        )
+                    })
+                    .collect(),
+                vaddr_range,
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+        }
+    }
+
+    #[test]
+    fn align_huge_pages_roundtrip() {
+        const HUGE: u64 = HUGE_PAGE_SIZE as u64;
+        const PAGE: u64 = PAGE_SIZE as u64;
+
+        let gen_jif = || Jif {
+            pheaders: vec![gen_anon(
+                (0x1000, 4 * HUGE),
+                &[
+                    // region 0 is not fully inside the pheader
+                    (0x1000, 0x3000, 1),
+                    // region 1: two zero pages
+                    (HUGE, HUGE + 10 * PAGE, 2),
+                    (HUGE + 12 * PAGE, 2 * HUGE, 3),
+                    // region 2: mostly zero
+                    (2 * HUGE, 2 * HUGE + PAGE, 4),
+                    // region 3: one interval, crossing into region 2
+                    (3 * HUGE - PAGE, 4 * HUGE, 5),
+                ],
+            )],
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+
+        let mut jif = gen_jif();
+        assert_eq!(jif.huge_mappable_pages(), PAGES_PER_HUGE_PAGE);
+        assert_eq!(jif.align_huge_pages(0).unwrap(), 0);
+        assert_eq!(jif.align_huge_pages(16).unwrap(), 1);
+        assert_eq!(jif.huge_mappable_pages(), 2 * PAGES_PER_HUGE_PAGE);
+        assert_eq!(jif.pheaders()[0].itree().n_intervals(), 4);
+
+        // the contents are unchanged, zero pages are now stored as data
+        assert_eq!(
+            jif.resolve_data(HUGE + 10 * PAGE),
+            Some(&[0u8; PAGE_SIZE][..])
+        );
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        let orig = gen_jif();
+        for addr in (0x1000..4 * HUGE).step_by(PAGE_SIZE) {
+            assert_eq!(
+                read.resolve_data(addr).unwrap_or(&[0u8; PAGE_SIZE]),
+                orig.resolve_data(addr).unwrap_or(&[0u8; PAGE_SIZE]),
+                "{:#x}",
+                addr
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Integrity section
+//!
+//! JIFs can optionally be followed by an integrity trailer, which allows detecting truncated or
+//! corrupted files. The trailer is laid out as:
+//!  - number of data segments (`u64`)
+//!  - CRC32C of each (uncompressed) data segment, in offset order (`u32` each)
+//!  - CRC32C of every byte in the file preceding the trailer (`u32`)
+//!  - size of the trailer, including this field (`u64`)
+
+use std::io::{IoSlice, Write};
+
+/// CRC32C (Castagnoli) lookup table
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// Continue a CRC32C computation over `data` (start with a `crc` of 0)
+pub(crate) fn crc32c(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, byte| {
+        CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Integrity trailer of a JIF
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct IntegrityTrailer {
+    /// checksums of the data segments
+    pub(crate) segment_crcs: Vec<u32>,
+
+    /// checksum of the file up to the trailer
+    pub(crate) file_crc: u32,
+
+    /// offset of the trailer in the file
+    pub(crate) offset: u64,
+}
+
+impl IntegrityTrailer {
+    /// Size of the trailer when serialized
+    pub(crate) fn serialized_size(&self) -> usize {
+        2 * std::mem::size_of::<u64>() + (self.segment_crcs.len() + 1) * std::mem::size_of::<u32>()
+    }
+}
+
+/// Writer adapter which computes the CRC32C of everything written through it
+pub(crate) struct CrcWriter<'a, W: Write> {
+    inner: &'a mut W,
+    crc: u32,
+}
+
+impl<'a, W: Write> CrcWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        CrcWriter { inner, crc: 0 }
+    }
+
+    /// Checksum of the bytes written so far
+    pub(crate) fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
+impl<W: Write> Write for CrcWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = crc32c(self.crc, &buf[..written]);
+        Ok(written)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let written = self.inner.write_vectored(bufs)?;
+        let mut remaining = written;
+        for buf in bufs {
+            let len = std::cmp::min(remaining, buf.len());
+            self.crc = crc32c(self.crc, &buf[..len]);
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(0, b""), 0);
+        assert_eq!(crc32c(0, b"123456789"), 0xe306_9283);
+
+        // incremental computation matches the one-shot one
+        assert_eq!(crc32c(crc32c(0, b"1234"), b"56789"), 0xe306_9283);
+    }
+}
@@ -1,6 +1,83 @@
 //! Interval tree building logic
 use crate::itree::interval::{AnonIntervalData, Interval, RawInterval, RefIntervalData};
-use crate::utils::{compare_pages, is_page_aligned, is_zero, PageCmp, PAGE_SIZE};
+use crate::utils::{is_page_aligned, is_zero, par_map, PageCmp};
+
+/// Decides whether a saved page is the same as the page of the referenced file, in which case it
+/// is restored from the file instead of being stored as private data (see
+/// [`Jif::build_itrees_with`](crate::Jif::build_itrees_with))
+///
+/// Comparators other than [`ExactComparator`] are lossy: the pages they deem the same are
+/// restored with the contents of the file, dropping the bytes in which they differ. Zero pages
+/// are found before comparing, and the pages may be compared concurrently
+pub trait PageComparator: Sync {
+    /// Whether the `overlay` page (saved at `vaddr`, in a pheader backed by the file at `path`)
+    /// is the same as the `base` page read from the file
+    fn is_same(&self, path: &str, vaddr: u64, base: &[u8], overlay: &[u8]) -> bool;
+}
+
+impl<F: Fn(&str, u64, &[u8], &[u8]) -> bool + Sync> PageComparator for F {
+    fn is_same(&self, path: &str, vaddr: u64, base: &[u8], overlay: &[u8]) -> bool {
+        self(path, vaddr, base, overlay)
+    }
+}
+
+/// Byte for byte comparison (the default)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExactComparator;
+
+impl PageComparator for ExactComparator {
+    fn is_same(&self, _path: &str, _vaddr: u64, base: &[u8], overlay: &[u8]) -> bool {
+        base == overlay
+    }
+}
+
+/// Comparison which ignores the bytes in some virtual address ranges (e.g., those holding
+/// timestamps or pointers)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IgnoreRanges {
+    /// Sorted by start address
+    ranges: Vec<(u64, u64)>,
+}
+
+impl IgnoreRanges {
+    /// Ignore the differences in the `[start; end)` virtual address ranges
+    pub fn new(mut ranges: Vec<(u64, u64)>) -> Self {
+        ranges.retain(|(start, end)| start < end);
+        ranges.sort_unstable();
+        IgnoreRanges { ranges }
+    }
+
+    /// The ignored ranges (sorted by start address)
+    pub fn ranges(&self) -> &[(u64, u64)] {
+        &self.ranges
+    }
+}
+
+impl PageComparator for IgnoreRanges {
+    fn is_same(&self, _path: &str, vaddr: u64, base: &[u8], overlay: &[u8]) -> bool {
+        let end = vaddr + overlay.len() as u64;
+        let same = |from: u64, to: u64| {
+            let range = (from - vaddr) as usize..(to - vaddr) as usize;
+            base[range.clone()] == overlay[range]
+        };
+
+        // compare the bytes between the ignored ranges
+        let mut cursor = vaddr;
+        for (start, stop) in self
+            .ranges
+            .iter()
+            .filter(|(start, stop)| *start < end && vaddr < *stop)
+        {
+            let start = std::cmp::max(*start, vaddr);
+            if cursor < start && !same(cursor, start) {
+                return false;
+            }
+            cursor = std::cmp::max(cursor, std::cmp::min(*stop, end));
+        }
+
+        cursor >= end || same(cursor, end)
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum AnonDiffState {
@@ -15,14 +92,44 @@ enum RefDiffState {
     AccumulatingZero,
 }
 
+/// Number of pages classified by each worker thread at a time
+const SCAN_BLOCK_PAGES: usize = 1024;
+
+/// Classify each page of the `data` with `f` (given the offset of the page), spreading the blocks
+/// of pages over the available cores
+///
+/// Only the classification is parallel: the intervals are then built from the classes in order,
+/// so none are split at the edges of the blocks
+fn scan_pages<C: Send>(
+    data: &[u8],
+    page_size: usize,
+    f: impl Fn(u64, &[u8]) -> C + Sync,
+) -> Vec<C> {
+    let blocks = data
+        .chunks(SCAN_BLOCK_PAGES * page_size)
+        .collect::<Vec<_>>();
+    par_map(&blocks, |block| {
+        let block_offset = block.as_ptr() as usize - data.as_ptr() as usize;
+        block
+            .chunks_exact(page_size)
+            .enumerate()
+            .map(|(idx, page)| f((block_offset + idx * page_size) as u64, page))
+            .collect::<Vec<_>>()
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
 /// Create an [`ITree`] from a privately mapped region (by removing zero pages)
 pub(crate) fn create_anon_itree_from_zero_page(
     data: &[u8],
     virtual_base: u64,
+    page_size: usize,
     intervals: &mut Vec<Interval<AnonIntervalData>>,
 ) {
     assert!(
-        is_page_aligned(data.len() as u64),
+        is_page_aligned(data.len() as u64, page_size),
         "data should be page aligned because data segments are page aligned"
     );
 
@@ -30,9 +137,9 @@ pub(crate) fn create_anon_itree_from_zero_page(
     let mut raw_intervals = Vec::new();
     let mut interval = RawInterval::default();
     let mut state = AnonDiffState::Initial;
-    for page in data.chunks_exact(PAGE_SIZE) {
+    for zero in scan_pages(data, page_size, |_offset, page| is_zero(page)) {
         let virtual_offset = virtual_base + offset;
-        state = match (state, is_zero(page)) {
+        state = match (state, zero) {
             (AnonDiffState::Initial, false) => {
                 interval.start = virtual_offset;
                 interval.offset = offset;
@@ -50,7 +157,7 @@ pub(crate) fn create_anon_itree_from_zero_page(
             }
         };
 
-        offset += PAGE_SIZE as u64;
+        offset += page_size as u64;
     }
 
     // last interval
@@ -66,10 +173,11 @@ pub(crate) fn create_anon_itree_from_zero_page(
 pub(crate) fn create_ref_itree_from_zero_page(
     data: &[u8],
     virtual_base: u64,
+    page_size: usize,
     intervals: &mut Vec<Interval<RefIntervalData>>,
 ) {
     assert!(
-        is_page_aligned(data.len() as u64),
+        is_page_aligned(data.len() as u64, page_size),
         "data should be page aligned because data segments are page aligned"
     );
 
@@ -77,9 +185,9 @@ pub(crate) fn create_ref_itree_from_zero_page(
     let mut raw_intervals = Vec::new();
     let mut interval = RawInterval::default();
     let mut state = RefDiffState::Initial;
-    for page in data.chunks_exact(PAGE_SIZE) {
+    for zero in scan_pages(data, page_size, |_offset, page| is_zero(page)) {
         let virtual_offset = virtual_base + offset;
-        state = match (state, is_zero(page)) {
+        state = match (state, zero) {
             (RefDiffState::Initial, false) => {
                 interval.start = virtual_offset;
                 interval.offset = offset;
@@ -109,7 +217,7 @@ pub(crate) fn create_ref_itree_from_zero_page(
             }
         };
 
-        offset += PAGE_SIZE as u64;
+        offset += page_size as u64;
     }
 
     // last interval
@@ -122,31 +230,51 @@ pub(crate) fn create_ref_itree_from_zero_page(
 }
 
 /// Create an [`ITree`] by diffing a base (reference file) with an overlay (saved data)
+///
+/// The base only needs to cover its overlap with the overlay: the file may end in the middle of
+/// a page (the rest of which reads as zeroes), and the pages past its end never match. The pages
+/// are compared with the `comparator` (given the `path` of the file)
 pub(crate) fn create_itree_from_diff(
     base: &[u8],
     overlay: &[u8],
     virtual_base: u64,
+    page_size: usize,
+    (path, comparator): (&str, &dyn PageComparator),
     intervals: &mut Vec<Interval<RefIntervalData>>,
 ) {
     assert!(
-        is_page_aligned(overlay.len() as u64),
+        is_page_aligned(overlay.len() as u64, page_size),
         "the overlay should be page aligned because the data segment should be page aligned"
     );
-    assert!(
-        is_page_aligned(base.len() as u64),
-        "the base should be page aligned because we extend it"
-    );
 
     let mut offset = 0;
     let mut raw_intervals = Vec::new();
     let mut interval = RawInterval::default();
     let mut state = RefDiffState::Initial;
-    for (base_page, overlay_page) in base
-        .chunks_exact(PAGE_SIZE)
-        .zip(overlay.chunks_exact(PAGE_SIZE))
-    {
+    let cmps = scan_pages(overlay, page_size, |offset, overlay_page| {
+        let base_start = offset as usize;
+        let compare = |base_page: &[u8]| {
+            if comparator.is_same(path, virtual_base + offset, base_page, overlay_page) {
+                PageCmp::Same
+            } else {
+                PageCmp::Diff
+            }
+        };
+        if is_zero(overlay_page) {
+            PageCmp::Zero
+        } else if base_start + page_size <= base.len() {
+            compare(&base[base_start..base_start + page_size])
+        } else if base_start < base.len() {
+            let mut base_page = base[base_start..].to_vec();
+            base_page.resize(page_size, 0x00);
+            compare(&base_page)
+        } else {
+            PageCmp::Diff
+        }
+    });
+    for cmp in cmps {
         let virtual_offset = virtual_base + offset;
-        state = match (state, compare_pages(base_page, overlay_page)) {
+        state = match (state, cmp) {
             (RefDiffState::Initial, PageCmp::Same) => state,
             (RefDiffState::Initial, PageCmp::Diff) => {
                 interval.start = virtual_offset;
@@ -190,47 +318,7 @@ pub(crate) fn create_itree_from_diff(
             (RefDiffState::AccumulatingZero, PageCmp::Zero) => state,
         };
 
-        offset += PAGE_SIZE as u64;
-    }
-
-    if overlay.len() > base.len() {
-        let virtual_offset = virtual_base + offset;
-        for page in overlay
-            .chunks_exact(PAGE_SIZE)
-            .skip(virtual_offset as usize / PAGE_SIZE)
-        {
-            let virtual_offset = virtual_base + offset;
-            state = match (state, is_zero(page)) {
-                (RefDiffState::Initial, false) => {
-                    interval.start = virtual_offset;
-                    interval.offset = offset;
-                    RefDiffState::AccumulatingData
-                }
-                (RefDiffState::Initial, true) => {
-                    interval.start = virtual_offset;
-                    interval.offset = u64::MAX;
-                    RefDiffState::AccumulatingZero
-                }
-                (RefDiffState::AccumulatingData, false) => state,
-                (RefDiffState::AccumulatingData, true) => {
-                    interval.end = virtual_offset;
-                    raw_intervals.push(interval);
-
-                    interval = RawInterval::new(virtual_offset, 0, u64::MAX);
-                    RefDiffState::AccumulatingZero
-                }
-                (RefDiffState::AccumulatingZero, false) => {
-                    interval.end = virtual_offset;
-                    raw_intervals.push(interval);
-
-                    interval = RawInterval::new(virtual_offset, 0, offset);
-                    RefDiffState::AccumulatingData
-                }
-                (RefDiffState::AccumulatingZero, true) => state,
-            };
-
-            offset += PAGE_SIZE as u64;
-        }
+        offset += page_size as u64;
     }
 
     // last interval
@@ -296,18 +384,19 @@ fn materialize_raw_ref_intervals(
 mod test {
     use crate::deduper::Deduper;
     use crate::itree::ITree;
+    use crate::utils::PAGE_SIZE;
 
     use super::*;
 
     fn create_anon_from_zero(data: &[u8], virtual_range: (u64, u64)) -> ITree<AnonIntervalData> {
         let mut intervals = Vec::new();
-        create_anon_itree_from_zero_page(data, virtual_range.0, &mut intervals);
+        create_anon_itree_from_zero_page(data, virtual_range.0, PAGE_SIZE, &mut intervals);
         ITree::build(intervals, virtual_range).unwrap()
     }
 
     fn create_ref_from_zero(data: &[u8], virtual_range: (u64, u64)) -> ITree<RefIntervalData> {
         let mut intervals = Vec::new();
-        create_ref_itree_from_zero_page(data, virtual_range.0, &mut intervals);
+        create_ref_itree_from_zero_page(data, virtual_range.0, PAGE_SIZE, &mut intervals);
         ITree::build(intervals, virtual_range).unwrap()
     }
 
@@ -317,7 +406,14 @@ mod test {
         virtual_range: (u64, u64),
     ) -> ITree<RefIntervalData> {
         let mut intervals = Vec::new();
-        create_itree_from_diff(base, overlay, virtual_range.0, &mut intervals);
+        create_itree_from_diff(
+            base,
+            overlay,
+            virtual_range.0,
+            PAGE_SIZE,
+            ("/lib/ref.so", &ExactComparator),
+            &mut intervals,
+        );
         ITree::build(intervals, virtual_range).unwrap()
     }
 
@@ -346,7 +442,7 @@ mod test {
             0x1000 * 3
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
@@ -369,7 +465,7 @@ mod test {
         assert_eq!(itree.private_data_size(), 0);
         assert_eq!(itree.explicitely_mapped_subregion_size(0x0000, 0x3000), 0);
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next(), None);
     }
 
@@ -399,7 +495,7 @@ mod test {
             0x1000 * 2
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next(), None);
@@ -431,7 +527,7 @@ mod test {
             0x1000 * 2
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
@@ -463,7 +559,7 @@ mod test {
             0x1000 * 3
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
@@ -493,7 +589,7 @@ mod test {
             3 * 0x1000
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next(), None);
     }
 
@@ -525,7 +621,7 @@ mod test {
             4 * 0x1000
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next(), None);
@@ -558,7 +654,7 @@ mod test {
             0x1000 * 4
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
@@ -583,7 +679,7 @@ mod test {
             0x1000 * 5
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next(), None);
     }
 
@@ -613,7 +709,7 @@ mod test {
         );
         assert_eq!(itree.implicitely_mapped_subregion_size(0x0000, 0x5000), 0);
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0x88; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0x88; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0x88; 0x1000]);
@@ -644,7 +740,7 @@ mod test {
         );
         assert_eq!(itree.implicitely_mapped_subregion_size(0x0000, 0x5000), 0);
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next(), None);
     }
 
@@ -682,7 +778,7 @@ mod test {
             0x1000
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
@@ -751,11 +847,151 @@ mod test {
             0x1000 * 2
         );
 
-        let mut it = itree.iter_private_pages(&deduper);
+        let mut it = itree.iter_private_pages(&deduper, PAGE_SIZE);
         assert_eq!(it.next().unwrap(), vec![0xaa; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xaa; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next().unwrap(), vec![0xff; 0x1000]);
         assert_eq!(it.next(), None);
     }
+
+    #[test]
+    // test that the base may end in the middle of a page (which is then padded with zeroes), with
+    // the overlay extending past it, away from address zero
+    fn create_diff_5() {
+        let base = [0xffu8; 0x1800];
+        let mut overlay = [0xffu8; 0x1000 * 4];
+        overlay[0x1800..0x2000].fill(0x00); // same: the padding of the base
+        overlay[0x3000..].fill(0x00); // zero
+
+        let itree = create_from_diff(&base, &overlay, (0x10000, 0x14000));
+        let target_itree = ITree::build(
+            vec![
+                Interval::new(0x12000, 0x13000, RefIntervalData::Owned(vec![0xff; 0x1000])),
+                Interval::new(0x13000, 0x14000, RefIntervalData::Zero),
+            ],
+            (0x10000, 0x14000),
+        )
+        .unwrap();
+        assert_eq!(itree.nodes, target_itree.nodes);
+    }
+
+    #[test]
+    // test that the differences a comparator ignores keep the page shared
+    fn create_diff_masked() {
+        let base = [0xffu8; 0x1000 * 4];
+        let mut overlay = [0xffu8; 0x1000 * 4];
+        overlay[0x0010..0x0018].fill(0xaa); // ignored
+        overlay[0x1ff8..0x2008].fill(0xaa); // ignored, across pages
+        overlay[0x3010] = 0xaa; // next to an ignored range
+        let ignore = IgnoreRanges::new(vec![
+            (0x13018, 0x13020),
+            (0x11ff0, 0x12010),
+            (0x13000, 0x13010),
+            (0x10010, 0x10018),
+        ]);
+        assert_eq!(ignore.ranges()[0], (0x10010, 0x10018));
+
+        let build = |comparator: &dyn PageComparator| {
+            let mut intervals = Vec::new();
+            create_itree_from_diff(
+                &base,
+                &overlay,
+                0x10000,
+                PAGE_SIZE,
+                ("/lib/ref.so", comparator),
+                &mut intervals,
+            );
+            ITree::build(intervals, (0x10000, 0x14000)).unwrap()
+        };
+        let private = |start: u64, end: u64| {
+            let range = (start - 0x10000) as usize..(end - 0x10000) as usize;
+            Interval::new(start, end, RefIntervalData::Owned(overlay[range].to_vec()))
+        };
+
+        let itree = build(&ignore);
+        let target_itree =
+            ITree::build(vec![private(0x13000, 0x14000)], (0x10000, 0x14000)).unwrap();
+        assert_eq!(itree.nodes, target_itree.nodes);
+
+        // the exact comparison keeps every page with a difference
+        let itree = build(&ExactComparator);
+        assert_eq!(itree.private_data_size(), 0x1000 * 4);
+
+        // closures are comparators, and are given the path of the file
+        let itree = build(&|path: &str, vaddr: u64, _base: &[u8], _overlay: &[u8]| {
+            path == "/lib/ref.so" && vaddr != 0x12000
+        });
+        let target_itree =
+            ITree::build(vec![private(0x12000, 0x13000)], (0x10000, 0x14000)).unwrap();
+        assert_eq!(itree.nodes, target_itree.nodes);
+    }
+
+    #[test]
+    // test that the intervals across the edges of the parallel scan blocks are not split
+    fn scan_blocks() {
+        let edge = SCAN_BLOCK_PAGES * PAGE_SIZE;
+        let mut data = vec![0xffu8; 2 * edge + 3 * PAGE_SIZE];
+        data[edge - 2 * PAGE_SIZE..edge + PAGE_SIZE].fill(0x00);
+        data[2 * edge..2 * edge + PAGE_SIZE].fill(0x00);
+        let virtual_range = (0x100000, 0x100000 + data.len() as u64);
+        let virtual_edge = virtual_range.0 + edge as u64;
+
+        let itree = create_anon_from_zero(&data, virtual_range);
+        let target_itree = ITree::build(
+            vec![
+                Interval::new(
+                    virtual_range.0,
+                    virtual_edge - 2 * PAGE_SIZE as u64,
+                    AnonIntervalData::Owned(vec![0xff; edge - 2 * PAGE_SIZE]),
+                ),
+                Interval::new(
+                    virtual_edge + PAGE_SIZE as u64,
+                    virtual_edge + edge as u64,
+                    AnonIntervalData::Owned(vec![0xff; edge - PAGE_SIZE]),
+                ),
+                Interval::new(
+                    virtual_edge + edge as u64 + PAGE_SIZE as u64,
+                    virtual_range.1,
+                    AnonIntervalData::Owned(vec![0xff; 2 * PAGE_SIZE]),
+                ),
+            ],
+            virtual_range,
+        )
+        .unwrap();
+        assert_eq!(itree.nodes, target_itree.nodes);
+
+        // the base differs on both sides of the first edge
+        let mut base = data.clone();
+        base[edge - 3 * PAGE_SIZE..edge - 2 * PAGE_SIZE].fill(0xaa);
+        base[edge + PAGE_SIZE..edge + 2 * PAGE_SIZE].fill(0xaa);
+        let itree = create_from_diff(&base, &data, virtual_range);
+        let target_itree = ITree::build(
+            vec![
+                Interval::new(
+                    virtual_edge - 3 * PAGE_SIZE as u64,
+                    virtual_edge - 2 * PAGE_SIZE as u64,
+                    RefIntervalData::Owned(vec![0xff; PAGE_SIZE]),
+                ),
+                Interval::new(
+                    virtual_edge - 2 * PAGE_SIZE as u64,
+                    virtual_edge + PAGE_SIZE as u64,
+                    RefIntervalData::Zero,
+                ),
+                Interval::new(
+                    virtual_edge + PAGE_SIZE as u64,
+                    virtual_edge + 2 * PAGE_SIZE as u64,
+                    RefIntervalData::Owned(vec![0xff; PAGE_SIZE]),
+                ),
+                Interval::new(
+                    virtual_edge + edge as u64,
+                    virtual_edge + edge as u64 + PAGE_SIZE as u64,
+                    RefIntervalData::Zero,
+                ),
+            ],
+            virtual_range,
+        )
+        .unwrap();
+        assert_eq!(itree.nodes, target_itree.nodes);
+    }
 }
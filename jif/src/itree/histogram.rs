@@ -0,0 +1,200 @@
+//! Interval statistics
+//!
+//! How fragmented the interval trees are: the number of logical intervals of each data source,
+//! bucketed by size, and how full the nodes holding them are
+
+use crate::itree::interval::DataSource;
+use crate::itree::itree_node::IVAL_PER_NODE;
+use crate::itree::ITreeView;
+
+use std::collections::BTreeMap;
+
+/// Sizes of the logical intervals with the same data source
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// Number of logical intervals
+    pub n_intervals: usize,
+
+    /// Bytes spanned by the intervals
+    pub bytes: u64,
+
+    /// Number of intervals by size, bucketed by the next power of two (in B)
+    pub buckets: BTreeMap<u64, usize>,
+}
+
+impl SizeHistogram {
+    /// Average size of the intervals, in B
+    pub fn mean_size(&self) -> u64 {
+        self.bytes.checked_div(self.n_intervals as u64).unwrap_or(0)
+    }
+
+    fn add(&mut self, size: u64) {
+        self.n_intervals += 1;
+        self.bytes += size;
+        *self.buckets.entry(size.next_power_of_two()).or_default() += 1;
+    }
+
+    fn merge(&mut self, other: &SizeHistogram) {
+        self.n_intervals += other.n_intervals;
+        self.bytes += other.bytes;
+        for (bucket, count) in &other.buckets {
+            *self.buckets.entry(*bucket).or_default() += count;
+        }
+    }
+}
+
+/// Statistics of the intervals of one or more interval trees (see
+/// [`ITreeView::interval_histogram`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IntervalHistogram {
+    /// Number of interval trees
+    pub n_itrees: usize,
+
+    /// Number of nodes
+    pub n_nodes: usize,
+
+    /// Number of intervals stored in the nodes
+    pub n_intervals: usize,
+
+    /// Logical intervals mapping private data
+    pub private: SizeHistogram,
+
+    /// Logical intervals mapping the zero page (explicitly or not)
+    pub zero: SizeHistogram,
+
+    /// Logical intervals mapping the referenced file (explicitly or not)
+    pub shared: SizeHistogram,
+}
+
+impl IntervalHistogram {
+    /// Histogram of the logical intervals with a data source
+    pub fn source(&self, source: DataSource) -> &SizeHistogram {
+        match source {
+            DataSource::Private => &self.private,
+            DataSource::Zero => &self.zero,
+            DataSource::Shared => &self.shared,
+        }
+    }
+
+    /// Fraction of the interval slots of the nodes which are taken
+    pub fn node_fill(&self) -> f64 {
+        if self.n_nodes == 0 {
+            return 0.0;
+        }
+
+        self.n_intervals as f64 / (self.n_nodes * IVAL_PER_NODE) as f64
+    }
+
+    /// Add the statistics of other interval trees
+    pub fn merge(&mut self, other: &IntervalHistogram) {
+        self.n_itrees += other.n_itrees;
+        self.n_nodes += other.n_nodes;
+        self.n_intervals += other.n_intervals;
+        self.private.merge(&other.private);
+        self.zero.merge(&other.zero);
+        self.shared.merge(&other.shared);
+    }
+}
+
+impl std::fmt::Display for IntervalHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} itrees: {} intervals in {} nodes ({:.0}% full)",
+            self.n_itrees,
+            self.n_intervals,
+            self.n_nodes,
+            self.node_fill() * 100.0
+        )?;
+        for (name, histogram) in [
+            ("private", &self.private),
+            ("zero", &self.zero),
+            ("shared", &self.shared),
+        ] {
+            writeln!(
+                f,
+                "{}: {} intervals, {:#x} B (mean {:#x} B)",
+                name,
+                histogram.n_intervals,
+                histogram.bytes,
+                histogram.mean_size()
+            )?;
+            for (bucket, count) in &histogram.buckets {
+                writeln!(f, "  <= {:#x} B: {}", bucket, count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ITreeView<'_> {
+    /// Histogram of the sizes of the logical intervals (see
+    /// [`ITreeView::iter_logical_intervals`]), by data source
+    pub fn interval_histogram(&self) -> IntervalHistogram {
+        let mut histogram = IntervalHistogram {
+            n_itrees: 1,
+            n_nodes: self.n_nodes(),
+            n_intervals: self.n_intervals(),
+            ..Default::default()
+        };
+        for ival in self.iter_logical_intervals() {
+            let size = ival.end - ival.start;
+            match ival.source {
+                DataSource::Private => histogram.private.add(size),
+                DataSource::Zero => histogram.zero.add(size),
+                DataSource::Shared => histogram.shared.add(size),
+            }
+        }
+
+        histogram
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::{Interval, RefIntervalData};
+    use crate::itree::ITree;
+
+    #[test]
+    fn interval_histogram() {
+        let itree = ITree::build(
+            vec![
+                Interval::new(0x1000, 0x2000, RefIntervalData::Owned(vec![1; 0x1000])),
+                Interval::new(0x2000, 0x3000, RefIntervalData::Zero),
+                Interval::new(0x5000, 0x8000, RefIntervalData::Owned(vec![2; 0x3000])),
+            ],
+            (0x0, 0x10000),
+        )
+        .unwrap();
+        let view = ITreeView::Ref { inner: &itree };
+
+        let histogram = view.interval_histogram();
+        assert_eq!(histogram.n_itrees, 1);
+        assert_eq!(histogram.n_intervals, 3);
+        assert_eq!(histogram.n_nodes, 1);
+        assert_eq!(histogram.node_fill(), 1.0);
+        assert_eq!(
+            histogram.private,
+            SizeHistogram {
+                n_intervals: 2,
+                bytes: 0x4000,
+                buckets: BTreeMap::from([(0x1000, 1), (0x4000, 1)]),
+            }
+        );
+        assert_eq!(histogram.zero.n_intervals, 1);
+        // [0x0; 0x1000), [0x3000; 0x5000), [0x8000; 0x10000)
+        assert_eq!(
+            histogram.source(DataSource::Shared).buckets,
+            BTreeMap::from([(0x1000, 1), (0x2000, 1), (0x8000, 1)])
+        );
+        assert_eq!(histogram.shared.mean_size(), 0xb000 / 3);
+
+        let mut merged = histogram.clone();
+        merged.merge(&histogram);
+        assert_eq!(merged.n_itrees, 2);
+        assert_eq!(merged.private.buckets[&0x4000], 2);
+        assert_eq!(merged.node_fill(), 1.0);
+    }
+}
@@ -45,10 +45,15 @@ pub(crate) struct IntermediateInterval {
     pub(crate) data: IntermediateIntervalData,
 }
 
+/// Flag (in the offset of a [`RawInterval`]) marking data which lives in the base JIF
+/// (see [`crate::delta`])
+pub(crate) const RAW_BASE_FLAG: u64 = 1 << 63;
+
 /// Raw interval representation
 ///
 /// We consider an interval valid if `start != u64::MAX` and `end != u64::MAX`
 /// If `offset == u64::MAX` it symbolizes that the interval references the zero page
+/// If `offset` is tagged with [`RAW_BASE_FLAG`], the data lives in the base JIF
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct RawInterval {
     pub(crate) start: u64,
@@ -105,6 +110,9 @@ pub trait IntervalData: Default {
 
     /// View the data (whether owned or referenced)
     fn get_data<'a>(&'a self, deduper: &'a Deduper) -> Option<&'a [u8]>;
+
+    /// The token of the data, if it is held by the deduper
+    fn dedup_token(&self) -> Option<DedupToken>;
 }
 
 impl IntervalData for AnonIntervalData {
@@ -133,6 +141,13 @@ impl IntervalData for AnonIntervalData {
             None
         }
     }
+    fn dedup_token(&self) -> Option<DedupToken> {
+        if let AnonIntervalData::Ref(token) = self {
+            Some(*token)
+        } else {
+            None
+        }
+    }
 }
 
 impl IntervalData for RefIntervalData {
@@ -161,6 +176,13 @@ impl IntervalData for RefIntervalData {
             None
         }
     }
+    fn dedup_token(&self) -> Option<DedupToken> {
+        if let RefIntervalData::Ref(token) = self {
+            Some(*token)
+        } else {
+            None
+        }
+    }
 }
 
 impl IntervalData for IntermediateIntervalData {
@@ -183,6 +205,13 @@ impl IntervalData for IntermediateIntervalData {
             None
         }
     }
+    fn dedup_token(&self) -> Option<DedupToken> {
+        if let IntermediateIntervalData::Ref(token) = self {
+            Some(*token)
+        } else {
+            None
+        }
+    }
 }
 
 impl From<&Interval<AnonIntervalData>> for LogicalInterval {
@@ -230,18 +259,46 @@ impl<Data: IntervalData> Interval<Data> {
         Interval { start, end, data }
     }
 
+    /// First address of the interval
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// End of the interval (exclusive)
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// The data this interval maps to
+    pub fn data(&self) -> &Data {
+        &self.data
+    }
+
+    /// The source of the data this interval maps to
+    pub fn source(&self) -> DataSource
+    where
+        for<'b> DataSource: From<&'b Data>,
+    {
+        (&self.data).into()
+    }
+
+    /// Private data of the interval (if any)
+    pub fn get_data<'a>(&'a self, deduper: &'a Deduper) -> Option<&'a [u8]> {
+        self.data.get_data(deduper)
+    }
+
     /// Check if the interval actually maps something or is just a stub
     pub(crate) fn is_none(&self) -> bool {
         self.start == u64::MAX || self.end == u64::MAX || self.data.is_none()
     }
 
     /// Check if the interval maps to the zero page
-    pub(crate) fn is_zero(&self) -> bool {
+    pub fn is_zero(&self) -> bool {
         self.data.is_zero()
     }
 
     /// Check if the interval maps to the private data
-    pub(crate) fn is_data(&self) -> bool {
+    pub fn is_data(&self) -> bool {
         self.data.is_data()
     }
 
@@ -300,16 +357,8 @@ impl Interval<AnonIntervalData> {
         } else if raw.is_zero() {
             Err(IntervalError::ZeroIntervalInAnon)
         } else {
-            let data_range = (
-                raw.offset - data_offset,
-                raw.offset + raw.len() - data_offset,
-            );
-            let priv_data_token = offset_idx
-                .get(&data_range)
-                .expect("by construction, the data map should have this data");
-            let priv_data = deduper.get(*priv_data_token);
-            assert_eq!(priv_data.len(), (raw.end - raw.start) as usize);
-            let data = AnonIntervalData::Ref(*priv_data_token);
+            let priv_data_token = raw.data_token(data_offset, deduper, offset_idx)?;
+            let data = AnonIntervalData::Ref(priv_data_token);
             Ok(Interval {
                 start: raw.start,
                 end: raw.end,
@@ -326,31 +375,23 @@ impl Interval<RefIntervalData> {
         data_offset: u64,
         deduper: &Deduper,
         offset_idx: &BTreeMap<(u64, u64), DedupToken>,
-    ) -> Self {
+    ) -> IntervalResult<Self> {
         if raw.is_empty() {
-            Interval::default()
+            Ok(Interval::default())
         } else if raw.is_zero() {
-            Interval {
+            Ok(Interval {
                 start: raw.start,
                 end: raw.end,
                 data: RefIntervalData::Zero,
-            }
+            })
         } else {
-            let data_range = (
-                raw.offset - data_offset,
-                raw.offset + raw.len() - data_offset,
-            );
-            let priv_data_token = offset_idx
-                .get(&data_range)
-                .expect("by construction, the data map should have this data");
-            let priv_data = deduper.get(*priv_data_token);
-            assert_eq!(priv_data.len(), (raw.end - raw.start) as usize);
-            let data = RefIntervalData::Ref(*priv_data_token);
-            Interval {
+            let priv_data_token = raw.data_token(data_offset, deduper, offset_idx)?;
+            let data = RefIntervalData::Ref(priv_data_token);
+            Ok(Interval {
                 start: raw.start,
                 end: raw.end,
                 data,
-            }
+            })
         }
     }
 }
@@ -473,6 +514,34 @@ impl RawInterval {
         }
     }
 
+    /// Find the token of the private data of the interval (whose offset is relative to the file)
+    fn data_token(
+        &self,
+        data_offset: u64,
+        deduper: &Deduper,
+        offset_idx: &BTreeMap<(u64, u64), DedupToken>,
+    ) -> IntervalResult<DedupToken> {
+        let start = self
+            .offset
+            .checked_sub(data_offset)
+            .ok_or(IntervalError::InvalidInterval(
+                self.start,
+                self.end,
+                self.offset,
+            ))?;
+        let data_range = (start, start + self.len());
+        let token = *offset_idx
+            .get(&data_range)
+            .ok_or(IntervalError::DataNotFound(data_range.0, data_range.1))?;
+
+        let data_len = deduper.get(token).len();
+        if data_len as u64 != self.len() {
+            return Err(IntervalError::BadDataLength(self.len(), data_len));
+        }
+
+        Ok(token)
+    }
+
     /// Check if the interval is empty
     pub(crate) fn is_empty(&self) -> bool {
         self.start == u64::MAX || self.end == u64::MAX
@@ -483,9 +552,14 @@ impl RawInterval {
         self.offset == u64::MAX
     }
 
-    /// Check if the interval points to private data
+    /// Check if the interval points to private data (in this JIF)
     pub(crate) fn is_data(&self) -> bool {
-        !self.is_empty() && !self.is_zero()
+        !self.is_empty() && !self.is_zero() && !self.is_base()
+    }
+
+    /// Check if the interval points to private data in the base JIF
+    pub(crate) fn is_base(&self) -> bool {
+        !self.is_empty() && !self.is_zero() && self.offset & RAW_BASE_FLAG != 0
     }
 }
 
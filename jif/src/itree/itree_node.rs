@@ -26,10 +26,11 @@ pub struct IntermediateITreeNode {
 
 /// Node in a raw interval tree
 ///
-/// Encodes a series of [`RawInterval`]s
-#[derive(Default, Clone, PartialEq, Eq)]
+/// Encodes a series of [`RawInterval`]s: `fanout - 1` of them, where the fanout is the one the
+/// JIF was written with (see [`crate::JifRaw::itree_fanout`])
+#[derive(Clone, PartialEq, Eq)]
 pub struct RawITreeNode {
-    pub(crate) ranges: [RawInterval; IVAL_PER_NODE],
+    pub(crate) ranges: Vec<RawInterval>,
 }
 
 impl ITreeNode<AnonIntervalData> {
@@ -61,12 +62,18 @@ impl ITreeNode<RefIntervalData> {
         data_offset: u64,
         deduper: &Deduper,
         offset_idx: &BTreeMap<(u64, u64), DedupToken>,
-    ) -> Self {
+    ) -> ITreeNodeResult<Self> {
         let mut node = ITreeNode::default();
-        for (raw_interval, interval) in raw.ranges.iter().zip(node.ranges.iter_mut()) {
-            *interval = Interval::from_raw_ref(raw_interval, data_offset, deduper, offset_idx);
+        for (interval_idx, (raw_interval, interval)) in
+            raw.ranges.iter().zip(node.ranges.iter_mut()).enumerate()
+        {
+            *interval = Interval::from_raw_ref(raw_interval, data_offset, deduper, offset_idx)
+                .map_err(|interval_err| ITreeNodeError::Interval {
+                    interval_idx,
+                    interval_err,
+                })?;
         }
-        node
+        Ok(node)
     }
 }
 
@@ -131,7 +138,7 @@ impl IntermediateITreeNode {
         deduper: &mut Deduper,
     ) -> Self {
         let mut inter = IntermediateITreeNode::default();
-        for (inter_interval, interval) in inter.ranges.iter_mut().zip(node.ranges.into_iter()) {
+        for (inter_interval, interval) in inter.ranges.iter_mut().zip(node.ranges) {
             *inter_interval = IntermediateInterval::from_materialized_anon(interval, deduper);
         }
         inter
@@ -143,7 +150,7 @@ impl IntermediateITreeNode {
         deduper: &mut Deduper,
     ) -> Self {
         let mut inter = IntermediateITreeNode::default();
-        for (inter_interval, interval) in inter.ranges.iter_mut().zip(node.ranges.into_iter()) {
+        for (inter_interval, interval) in inter.ranges.iter_mut().zip(node.ranges) {
             *inter_interval = IntermediateInterval::from_materialized_ref(interval, deduper);
         }
         inter
@@ -151,13 +158,13 @@ impl IntermediateITreeNode {
 }
 
 impl RawITreeNode {
-    /// Size of the [`RawITreeNode`] when serialized
-    pub(crate) const fn serialized_size() -> usize {
-        IVAL_PER_NODE * RawInterval::serialized_size()
+    /// Size of the [`RawITreeNode`] when serialized (in a tree with this `fanout`)
+    pub(crate) const fn serialized_size(fanout: usize) -> usize {
+        (fanout - 1) * RawInterval::serialized_size()
     }
 
     /// Build an [`RawITreeNode`]
-    pub(crate) fn new(ranges: [RawInterval; IVAL_PER_NODE]) -> Self {
+    pub(crate) fn new(ranges: Vec<RawInterval>) -> Self {
         RawITreeNode { ranges }
     }
 
@@ -170,10 +177,8 @@ impl RawITreeNode {
         intermediate: IntermediateITreeNode,
         raw_intervals: &mut BTreeMap<(u64, u64), RawInterval>,
     ) -> Self {
-        let mut raw = RawITreeNode::default();
-        for (raw_interval, inter_interval) in
-            raw.ranges.iter_mut().zip(intermediate.ranges.into_iter())
-        {
+        let mut raw = RawITreeNode::new(vec![RawInterval::default(); IVAL_PER_NODE]);
+        for (raw_interval, inter_interval) in raw.ranges.iter_mut().zip(intermediate.ranges) {
             if inter_interval.is_none() {
                 continue;
             }
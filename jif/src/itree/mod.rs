@@ -1,8 +1,10 @@
 pub mod diff;
+mod histogram;
 pub mod interval;
 pub mod itree_node;
 mod tree;
 mod view;
 
+pub use histogram::*;
 pub use tree::*;
 pub use view::*;
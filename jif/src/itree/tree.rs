@@ -2,9 +2,10 @@
 
 use crate::deduper::Deduper;
 use crate::error::*;
-use crate::itree::interval::{Interval, IntervalData};
+use crate::itree::interval::{
+    AnonIntervalData, DataSource, Interval, IntervalData, LogicalInterval, RefIntervalData,
+};
 use crate::itree::itree_node::{ITreeNode, FANOUT};
-use crate::utils::PAGE_SIZE;
 
 /// Interval Tree representation
 ///
@@ -23,8 +24,9 @@ use crate::utils::PAGE_SIZE;
 ///  - Address is found, with offset `u64::MAX`: means the address is backed by the zero page
 ///  - Address is found, with a valid offset: means the address is backed by the page at that offset of the JIF file
 ///  - Address is not found: means the address is backed by the reference file (with the offset
-///  being the offset of the virtual address into the virtual address range)
+///    being the offset of the virtual address into the virtual address range)
 ///
+#[derive(Clone)]
 pub struct ITree<Data: IntervalData> {
     pub(crate) nodes: Vec<ITreeNode<Data>>,
     virtual_range: (u64, u64),
@@ -132,43 +134,17 @@ impl<Data: IntervalData + std::default::Default> ITree<Data> {
         mut intervals: Vec<Interval<Data>>,
         virtual_range: (u64, u64),
     ) -> ITreeResult<Self> {
-        fn fill<Data: IntervalData>(
-            nodes: &mut Vec<ITreeNode<Data>>,
-            intervals: &mut Vec<Interval<Data>>,
-            node_idx: usize,
-        ) {
-            // first base case: no node with this index
-            if node_idx >= nodes.len() {
-                return;
-            }
-
-            let mut child_idx = node_idx * FANOUT + 1;
-            for i in 0..(FANOUT - 1) {
-                // recursion
-                fill(nodes, intervals, child_idx);
-
-                if let Some(interval) = intervals.pop() {
-                    // insert an interval
-                    nodes[node_idx].ranges[i] = interval;
-                    child_idx += 1;
-                } else {
-                    // second base case: no more intervals
-                    return;
+        intervals.sort_by_key(|it| it.start);
+        let nodes = balanced_layout(intervals, FANOUT)
+            .into_iter()
+            .map(|slots| {
+                let mut node = ITreeNode::default();
+                for (range, interval) in node.ranges.iter_mut().zip(slots) {
+                    *range = interval;
                 }
-            }
-
-            // FANOUT == IVAL_PER_NODE - 1, so we need to insert right_most child
-            fill(nodes, intervals, child_idx);
-        }
-
-        let n_nodes = Self::n_itree_nodes_from_intervals(intervals.len());
-        let mut nodes = (0..n_nodes)
-            .map(|_| ITreeNode::default())
+                node
+            })
             .collect::<Vec<_>>();
-
-        // sort intervals in descending order of start (we pop them out the back)
-        intervals.sort_by(|it1, it2| it2.start.cmp(&it1.start));
-        fill(&mut nodes, &mut intervals, 0);
         ITree::new(nodes, virtual_range)
     }
 
@@ -192,15 +168,128 @@ impl<Data: IntervalData + std::default::Default> ITree<Data> {
         self.nodes.iter().map(|n| n.n_data_intervals()).sum()
     }
 
+    /// Shift the virtual range and the intervals by `delta` bytes
+    ///
+    /// The order of the intervals is kept, so the tree stays balanced. The caller has to make
+    /// sure the shifted range does not overflow
+    pub(crate) fn shift(&mut self, delta: i64) {
+        let shift = |addr: u64| addr.wrapping_add_signed(delta);
+        self.virtual_range = (shift(self.virtual_range.0), shift(self.virtual_range.1));
+        for ival in self
+            .nodes
+            .iter_mut()
+            .flat_map(|n| n.ranges.iter_mut())
+            .filter(|ival| !ival.is_none())
+        {
+            ival.start = shift(ival.start);
+            ival.end = shift(ival.end);
+        }
+    }
+
+    /// Merge the adjacent intervals with the same source and rebuild the tree (see
+    /// [`ITree::coalesce`])
+    ///
+    /// Returns the number of intervals which were merged away
+    fn coalesce_with(
+        &mut self,
+        deduper: &Deduper,
+        owned: fn(Vec<u8>) -> Data,
+    ) -> ITreeResult<usize> {
+        let mut intervals = self
+            .take()
+            .into_iter_intervals()
+            .filter(|ival| !ival.is_none())
+            .collect::<Vec<_>>();
+        intervals.sort_by_key(|ival| ival.start);
+        let n_intervals = intervals.len();
+
+        let mut coalesced: Vec<Interval<Data>> = Vec::with_capacity(n_intervals);
+        for ival in intervals {
+            match coalesced.last_mut() {
+                Some(last)
+                    if last.end == ival.start && last.data.is_zero() && ival.data.is_zero() =>
+                {
+                    last.end = ival.end;
+                }
+                Some(last)
+                    if last.end == ival.start && last.data.is_data() && ival.data.is_data() =>
+                {
+                    let mut data = match last.data.take_data() {
+                        Some(data) => data,
+                        None => last
+                            .data
+                            .get_data(deduper)
+                            .expect("data intervals have data")
+                            .to_vec(),
+                    };
+                    data.extend_from_slice(
+                        ival.data
+                            .get_data(deduper)
+                            .expect("data intervals have data"),
+                    );
+                    last.data = owned(data);
+                    last.end = ival.end;
+                }
+                _ => coalesced.push(ival),
+            }
+        }
+
+        let n_merged = n_intervals - coalesced.len();
+        *self = ITree::build(coalesced, self.virtual_range)?;
+        Ok(n_merged)
+    }
+
     /// Iterate over the intervals
     pub(crate) fn into_iter_intervals(self) -> impl Iterator<Item = Interval<Data>> {
         self.nodes.into_iter().flat_map(|n| n.ranges.into_iter())
     }
-    /// Iterate over the intervals
-    pub(crate) fn in_order_intervals(&self) -> impl Iterator<Item = &Interval<Data>> {
+    /// Iterate over the explicitly mapped intervals, in address order
+    pub fn in_order_intervals(&self) -> impl Iterator<Item = &Interval<Data>> {
         ITreeIterator::new(self)
     }
 
+    /// Iterate over the logical intervals partitioning the virtual range, in address order
+    ///
+    /// The gaps between explicit intervals resolve to `implicit`
+    pub(crate) fn iter_logical_intervals(
+        &self,
+        implicit: DataSource,
+    ) -> impl Iterator<Item = LogicalInterval> + '_
+    where
+        for<'b> DataSource: From<&'b Data>,
+    {
+        let (start, end) = self.virtual_range;
+        let mut cursor = start;
+        let mut explicit = self.in_order_intervals().peekable();
+        std::iter::from_fn(move || {
+            if cursor >= end {
+                return None;
+            }
+
+            match explicit.peek() {
+                Some(ival) if ival.start <= cursor => {
+                    let ival = explicit.next()?;
+                    cursor = ival.end;
+                    Some(LogicalInterval {
+                        start: ival.start,
+                        end: ival.end,
+                        source: (&ival.data).into(),
+                    })
+                }
+                next => {
+                    let gap_end = next.map(|ival| ival.start).unwrap_or(end);
+                    let ival = LogicalInterval {
+                        start: cursor,
+                        end: gap_end,
+                        source: implicit,
+                    };
+                    cursor = gap_end;
+                    Some(ival)
+                }
+            }
+        })
+    }
+
     /// How much of the interval tree consists of zero page mappings
     pub fn zero_byte_size(&self) -> usize {
         self.nodes.iter().map(ITreeNode::zero_byte_size).sum()
@@ -226,16 +315,14 @@ impl<Data: IntervalData + std::default::Default> ITree<Data> {
         (end - start) as usize - self.explicitely_mapped_subregion_size(start, end)
     }
 
-    /// Iterate over the private pages in the interval tree
-    ///
-    // TODO(array_chunks): waiting on the `array_chunks` (#![feature(iter_array_chunks)]) that carries
-    // the size information to change the output type to &[u8; PAGE_SIZE]
+    /// Iterate over the private pages (of `page_size`) in the interval tree
     pub fn iter_private_pages<'a>(
         &'a self,
         deduper: &'a Deduper,
-    ) -> impl Iterator<Item = &[u8]> + 'a {
+        page_size: usize,
+    ) -> impl Iterator<Item = &'a [u8]> + 'a {
         self.in_order_intervals()
-            .filter_map(|i| i.data.get_data(deduper).map(|d| d.chunks_exact(PAGE_SIZE)))
+            .filter_map(move |i| i.data.get_data(deduper).map(|d| d.chunks_exact(page_size)))
             .flatten()
     }
 
@@ -300,6 +387,27 @@ impl<Data: IntervalData + std::default::Default> ITree<Data> {
     }
 }
 
+impl ITree<AnonIntervalData> {
+    /// Merge the adjacent data intervals and rebuild the tree with as few nodes as possible
+    ///
+    /// Merged data is owned by the resulting interval, so this undoes the splits done to share
+    /// data (e.g., [`crate::Jif::dedup_pages`]). Returns the number of intervals merged away
+    pub fn coalesce(&mut self, deduper: &Deduper) -> ITreeResult<usize> {
+        self.coalesce_with(deduper, AnonIntervalData::Owned)
+    }
+}
+
+impl ITree<RefIntervalData> {
+    /// Merge the adjacent data intervals (and the adjacent zero intervals) and rebuild the tree
+    /// with as few nodes as possible
+    ///
+    /// Merged data is owned by the resulting interval, so this undoes the splits done to share
+    /// data (e.g., [`crate::Jif::dedup_pages`]). Returns the number of intervals merged away
+    pub fn coalesce(&mut self, deduper: &Deduper) -> ITreeResult<usize> {
+        self.coalesce_with(deduper, RefIntervalData::Owned)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum InOrderTraversalState {
     Outer {
@@ -389,6 +497,44 @@ impl<'a, Data: IntervalData> Iterator for ITreeIterator<'a, Data> {
     }
 }
 
+/// Lay out the (sorted) `items` in the nodes of a balanced tree with this `fanout`, in the order
+/// the lookups expect: each node holds up to `fanout - 1` items, and the children of node `i` are
+/// nodes `i * fanout + 1` to `i * fanout + fanout`
+pub(crate) fn balanced_layout<T>(mut items: Vec<T>, fanout: usize) -> Vec<Vec<T>> {
+    fn fill<T>(nodes: &mut Vec<Vec<T>>, items: &mut Vec<T>, fanout: usize, node_idx: usize) {
+        // first base case: no node with this index
+        if node_idx >= nodes.len() {
+            return;
+        }
+
+        let mut child_idx = node_idx * fanout + 1;
+        for _ in 0..(fanout - 1) {
+            // recursion
+            fill(nodes, items, fanout, child_idx);
+
+            if let Some(item) = items.pop() {
+                // insert an item
+                nodes[node_idx].push(item);
+                child_idx += 1;
+            } else {
+                // second base case: no more items
+                return;
+            }
+        }
+
+        // a node has one more child than items, so we need to insert the right most child
+        fill(nodes, items, fanout, child_idx);
+    }
+
+    let n_nodes = items.len().div_ceil(fanout - 1);
+    let mut nodes = (0..n_nodes).map(|_| Vec::new()).collect::<Vec<_>>();
+
+    // reverse the items (we pop them out the back)
+    items.reverse();
+    fill(&mut nodes, &mut items, fanout, 0);
+    nodes
+}
+
 impl<Data: IntervalData + std::fmt::Debug> std::fmt::Debug for ITree<Data> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_list().entries(self.nodes.iter()).finish()
@@ -398,6 +544,7 @@ impl<Data: IntervalData + std::fmt::Debug> std::fmt::Debug for ITree<Data> {
 #[cfg(test)]
 pub(crate) mod test {
     use crate::itree::interval::{AnonIntervalData, RefIntervalData};
+    use crate::utils::PAGE_SIZE;
 
     use super::*;
 
@@ -490,8 +637,8 @@ pub(crate) mod test {
         ITree::build(intervals, (VADDR_BEGIN, VADDR_END)).unwrap()
     }
 
-    fn test_empty<Data: IntervalData>() {
-        let tree: ITree<RefIntervalData> = gen_empty();
+    fn test_empty<Data: IntervalData + PartialEq + std::fmt::Debug>() {
+        let tree: ITree<Data> = gen_empty();
         assert_eq!(tree.n_nodes(), 0);
         assert_eq!(tree.n_intervals(), 0);
         assert_eq!(tree.n_data_intervals(), 0);
@@ -507,7 +654,7 @@ pub(crate) mod test {
             (VADDR_END - VADDR_BEGIN) as usize
         );
         let deduper = Deduper::default();
-        assert_eq!(tree.iter_private_pages(&deduper).count(), 0);
+        assert_eq!(tree.iter_private_pages(&deduper, PAGE_SIZE).count(), 0);
         assert_eq!(tree.resolve(0), Err((VADDR_BEGIN, VADDR_END)));
         assert_eq!(tree.resolve(VADDR_BEGIN), Err((VADDR_BEGIN, VADDR_END)));
         assert_eq!(
@@ -529,10 +676,13 @@ pub(crate) mod test {
     #[test]
     fn test_anon_tree() {
         let tree = gen_anon_tree();
-        let mut cnt = 0;
         // ranges are mapped on and off
         // we query the midpoint in each range
-        for range in VADDRS.into_iter().zip(VADDRS.into_iter().skip(1)) {
+        for (cnt, range) in VADDRS
+            .into_iter()
+            .zip(VADDRS.into_iter().skip(1))
+            .enumerate()
+        {
             let addr = (range.0 + range.1) / 2;
             let resolve = tree.resolve(addr);
             match cnt % 2 {
@@ -543,7 +693,6 @@ pub(crate) mod test {
                 1 => assert_eq!(resolve.unwrap_err(), range),
                 _ => unreachable!(),
             };
-            cnt += 1
         }
 
         // test the in order traversal is in order
@@ -573,10 +722,13 @@ pub(crate) mod test {
     #[test]
     fn test_ref_tree() {
         let tree = gen_ref_tree();
-        let mut cnt = 0;
         // ranges are mapped in an Owned -> Zero -> Ref cycle (Ref is implied)
         // we query the midpoint in each range
-        for range in VADDRS.into_iter().zip(VADDRS.into_iter().skip(1)) {
+        for (cnt, range) in VADDRS
+            .into_iter()
+            .zip(VADDRS.into_iter().skip(1))
+            .enumerate()
+        {
             let addr = (range.0 + range.1) / 2;
             let resolve = tree.resolve(addr);
             match cnt % 3 {
@@ -585,7 +737,6 @@ pub(crate) mod test {
                 2 => assert_eq!(resolve.unwrap_err(), range),
                 _ => unreachable!(),
             };
-            cnt += 1
         }
 
         // test the in order traversal is in order
@@ -596,4 +747,72 @@ pub(crate) mod test {
             assert!(i1.end <= i2.start);
         }
     }
+
+    #[test]
+    fn test_coalesce() {
+        let page = PAGE_SIZE as u64;
+        let mut deduper = Deduper::default();
+        let token = deduper.insert(vec![2; PAGE_SIZE]);
+        let mut tree = ITree::build(
+            vec![
+                Interval::new(
+                    VADDR_BEGIN,
+                    VADDR_BEGIN + page,
+                    RefIntervalData::Owned(vec![1; PAGE_SIZE]),
+                ),
+                Interval::new(
+                    VADDR_BEGIN + page,
+                    VADDR_BEGIN + 2 * page,
+                    RefIntervalData::Ref(token),
+                ),
+                Interval::new(
+                    VADDR_BEGIN + 2 * page,
+                    VADDR_BEGIN + 3 * page,
+                    RefIntervalData::Zero,
+                ),
+                Interval::new(
+                    VADDR_BEGIN + 3 * page,
+                    VADDR_BEGIN + 4 * page,
+                    RefIntervalData::Zero,
+                ),
+                // not adjacent
+                Interval::new(
+                    VADDR_BEGIN + 5 * page,
+                    VADDR_BEGIN + 6 * page,
+                    RefIntervalData::Ref(token),
+                ),
+                Interval::new(
+                    VADDR_BEGIN + 6 * page,
+                    VADDR_BEGIN + 7 * page,
+                    RefIntervalData::Ref(token),
+                ),
+            ],
+            (VADDR_BEGIN, VADDR_END),
+        )
+        .unwrap();
+        assert_eq!(tree.n_nodes(), 2);
+
+        assert_eq!(tree.coalesce(&deduper).unwrap(), 3);
+        assert_eq!(tree.n_intervals(), 3);
+        assert_eq!(tree.n_nodes(), 1);
+        assert_eq!(
+            tree.in_order_intervals()
+                .map(|ival| (ival.start, ival.end))
+                .collect::<Vec<_>>(),
+            vec![
+                (VADDR_BEGIN, VADDR_BEGIN + 2 * page),
+                (VADDR_BEGIN + 2 * page, VADDR_BEGIN + 4 * page),
+                (VADDR_BEGIN + 5 * page, VADDR_BEGIN + 7 * page),
+            ]
+        );
+        let first = tree.resolve(VADDR_BEGIN).unwrap();
+        assert_eq!(
+            first.data.get_data(&deduper).unwrap(),
+            [vec![1; PAGE_SIZE], vec![2; PAGE_SIZE]].concat()
+        );
+        assert!(tree.resolve(VADDR_BEGIN + 3 * page).unwrap().data.is_zero());
+
+        // nothing left to merge
+        assert_eq!(tree.coalesce(&deduper).unwrap(), 0);
+    }
 }
@@ -3,7 +3,6 @@
 use crate::deduper::Deduper;
 use crate::itree::interval::{AnonIntervalData, DataSource, LogicalInterval, RefIntervalData};
 use crate::itree::ITree;
-use crate::utils::PAGE_SIZE;
 
 /// Generic view over the two possible types of [`ITree`]
 pub enum ITreeView<'a> {
@@ -63,14 +62,26 @@ impl<'a> ITreeView<'a> {
         }
     }
 
-    /// Iterate over the private pages in the interval tree
+    /// Iterate over the private pages (of `page_size`) in the interval tree
     pub fn iter_private_pages(
         &'a self,
         deduper: &'a Deduper,
-    ) -> Box<dyn Iterator<Item = &[u8]> + 'a> {
+        page_size: usize,
+    ) -> Box<dyn Iterator<Item = &'a [u8]> + 'a> {
         match self {
-            ITreeView::Anon { inner } => Box::new(inner.iter_private_pages(deduper)),
-            ITreeView::Ref { inner } => Box::new(inner.iter_private_pages(deduper)),
+            ITreeView::Anon { inner } => Box::new(inner.iter_private_pages(deduper, page_size)),
+            ITreeView::Ref { inner } => Box::new(inner.iter_private_pages(deduper, page_size)),
+        }
+    }
+
+    /// Iterate over the logical intervals partitioning the virtual range, in address order
+    ///
+    /// Unlike the intervals stored in the tree, the implicit mappings (to the zero page in
+    /// anonymous trees, to the file in reference trees) are included
+    pub fn iter_logical_intervals(&self) -> Box<dyn Iterator<Item = LogicalInterval> + 'a> {
+        match self {
+            ITreeView::Anon { inner } => Box::new(inner.iter_logical_intervals(DataSource::Zero)),
+            ITreeView::Ref { inner } => Box::new(inner.iter_logical_intervals(DataSource::Shared)),
         }
     }
 
@@ -106,21 +117,26 @@ impl<'a> ITreeView<'a> {
         }
     }
 
-    /// Resolve address in the interval tree into a private data page
+    /// Resolve address in the interval tree into a private data page (of `page_size`)
     // TODO(array_chunks)
-    pub fn resolve_data(&self, addr: u64, deduper: &'a Deduper) -> Option<&'a [u8]> {
+    pub fn resolve_data(
+        &self,
+        addr: u64,
+        deduper: &'a Deduper,
+        page_size: usize,
+    ) -> Option<&'a [u8]> {
         match self {
             ITreeView::Anon { inner } => {
                 inner.resolve(addr).ok().and_then(|ival| match ival.data {
                     AnonIntervalData::None => None,
                     AnonIntervalData::Owned(ref data) => {
                         let offset = (addr - ival.start) as usize;
-                        Some(&data[offset..(offset + PAGE_SIZE)])
+                        Some(&data[offset..(offset + page_size)])
                     }
                     AnonIntervalData::Ref(tok) => {
                         let data = deduper.get(tok);
                         let offset = (addr - ival.start) as usize;
-                        Some(&data[offset..(offset + PAGE_SIZE)])
+                        Some(&data[offset..(offset + page_size)])
                     }
                 })
             }
@@ -128,12 +144,12 @@ impl<'a> ITreeView<'a> {
                 RefIntervalData::None | RefIntervalData::Zero => None,
                 RefIntervalData::Owned(ref data) => {
                     let offset = (addr - ival.start) as usize;
-                    Some(&data[offset..(offset + PAGE_SIZE)])
+                    Some(&data[offset..(offset + page_size)])
                 }
                 RefIntervalData::Ref(tok) => {
                     let data = deduper.get(tok);
                     let offset = (addr - ival.start) as usize;
-                    Some(&data[offset..(offset + PAGE_SIZE)])
+                    Some(&data[offset..(offset + page_size)])
                 }
             }),
         }
@@ -185,11 +201,11 @@ mod test {
         let itree: ITree<AnonIntervalData> = gen_anon_tree();
         let view = ITreeView::Anon { inner: &itree };
 
-        let mut cnt = 0;
-        for addr in VADDRS
+        for (cnt, addr) in VADDRS
             .iter()
             .zip(VADDRS.iter().skip(1))
             .map(|(start, end)| (start + end) / 2)
+            .enumerate()
         {
             let resolve = view.resolve(addr);
             match cnt % 2 {
@@ -197,7 +213,29 @@ mod test {
                 1 => assert_eq!(resolve.source, DataSource::Zero),
                 _ => unreachable!(),
             };
-            cnt += 1
+        }
+    }
+
+    #[test]
+    fn logical_intervals_partition() {
+        let anon: ITree<AnonIntervalData> = gen_anon_tree();
+        let rf: ITree<RefIntervalData> = gen_ref_tree();
+        let empty: ITree<AnonIntervalData> = gen_empty();
+
+        for view in [
+            ITreeView::Anon { inner: &anon },
+            ITreeView::Ref { inner: &rf },
+            ITreeView::Anon { inner: &empty },
+        ] {
+            let ivals = view.iter_logical_intervals().collect::<Vec<_>>();
+            assert_eq!(ivals.first().map(|i| i.start), Some(VADDR_BEGIN));
+            assert_eq!(ivals.last().map(|i| i.end), Some(VADDR_END));
+            for (a, b) in ivals.iter().zip(ivals.iter().skip(1)) {
+                assert_eq!(a.end, b.start);
+            }
+            for ival in ivals {
+                assert_eq!(view.resolve(ival.start), ival);
+            }
         }
     }
 
@@ -206,11 +244,11 @@ mod test {
         let itree: ITree<RefIntervalData> = gen_ref_tree();
         let view = ITreeView::Ref { inner: &itree };
 
-        let mut cnt = 0;
-        for addr in VADDRS
+        for (cnt, addr) in VADDRS
             .iter()
             .zip(VADDRS.iter().skip(1))
             .map(|(start, end)| (start + end) / 2)
+            .enumerate()
         {
             let resolve = view.resolve(addr);
             match cnt % 3 {
@@ -219,7 +257,6 @@ mod test {
                 2 => assert_eq!(resolve.source, DataSource::Shared),
                 _ => unreachable!(),
             };
-            cnt += 1
         }
     }
 }
@@ -2,35 +2,85 @@
 //!
 //! Includes both the raw and materialized variants
 
-use crate::deduper::{DedupToken, Deduper};
+use crate::arch::Arch;
+use crate::compress::Compression;
+use crate::deduper::{DedupStats, DedupToken, Deduper};
+use crate::diff::JifDiff;
 use crate::error::*;
+use crate::footprint::DiskFootprint;
+use crate::itree::diff::{ExactComparator, PageComparator};
 use crate::itree::interval::DataSource;
-use crate::itree::interval::IntermediateInterval;
 use crate::itree::interval::Interval;
 use crate::itree::interval::{AnonIntervalData, LogicalInterval, RawInterval, RefIntervalData};
-use crate::itree::itree_node::{ITreeNode, IntermediateITreeNode, RawITreeNode};
-use crate::itree::ITree;
-use crate::ord::OrdChunk;
+use crate::itree::interval::{IntermediateInterval, IntermediateIntervalData};
+use crate::itree::itree_node::{ITreeNode, IntermediateITreeNode, RawITreeNode, FANOUT};
+use crate::itree::{ITree, IntervalHistogram};
+use crate::layout::DataLayout;
+#[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+use crate::mmap::Mmap;
+use crate::ord::{has_provenance, placement_order, OrdChunk};
+use crate::page_index::PageHashIndex;
+use crate::paths::PathResolver;
 use crate::pheader::{JifPheader, JifRawPheader};
-use crate::utils::{page_align, PAGE_SIZE};
+use crate::utils::{is_page_aligned, open_file, page_align, page_align_down};
+use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashSet};
-use std::io::{BufReader, Read, Seek, Write};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
 use std::str::from_utf8;
-use std::u64;
 
 pub(crate) const JIF_MAGIC_HEADER: [u8; 4] = [0x77, b'J', b'I', b'F'];
 pub(crate) const JIF_VERSION: u32 = 2;
 
+/// Version of the JIFs whose ordering chunks carry their weights (see [`OrdChunk::weight`]):
+/// the JIFs without weights keep the previous version, so that the readers which predate them
+/// still read them
+pub(crate) const JIF_VERSION_ORD_WEIGHTS: u32 = 3;
+
+/// Bits of the version word in the header reserved for flags
+pub(crate) const JIF_FLAGS_MASK: u32 = 0xffff_0000;
+
+/// Flag marking an LZ4 compressed data section
+pub(crate) const JIF_FLAG_LZ4: u32 = 1 << 16;
+
+/// Flag marking a delta JIF (i.e., which references data in a base JIF)
+pub(crate) const JIF_FLAG_DELTA: u32 = 1 << 17;
+
+/// Flag marking a JIF followed by an integrity section (see [`crate::integrity`])
+pub(crate) const JIF_FLAG_CHECKSUMS: u32 = 1 << 18;
+
+/// Flag marking a JIF of a big endian process (see [`Arch`])
+pub(crate) const JIF_FLAG_BIG_ENDIAN: u32 = 1 << 19;
+
+/// Bits of the flags holding the instruction set (see [`crate::arch::Isa`])
+pub(crate) const JIF_ISA_MASK: u32 = 0x00f0_0000;
+
+/// Bits of the flags holding the page size, as its shift over the default
+/// (i.e., 0 for 4KiB pages, 2 for 16KiB pages and 4 for 64KiB pages)
+pub(crate) const JIF_PAGE_SHIFT_MASK: u32 = 0x0f00_0000;
+
+/// Flag marking an ordering section whose chunks carry their provenance (see [`OrdChunk`])
+pub(crate) const JIF_FLAG_ORD_PROVENANCE: u32 = 1 << 28;
+
+/// Bits of the flags holding the fanout of the interval trees, as its shift over the default
+/// (i.e., 0 for a fanout of 4, 1 for 8, up to 7 for 512; see [`JifRaw::itree_fanout`])
+pub(crate) const JIF_FANOUT_SHIFT_MASK: u32 = 0xe000_0000;
+
 /// The materialized view over the JIF file
 ///
 /// After materialization the JIF format simplifies greatly:
 /// it is simply a list of virtual memory areas (the pheaders)
 /// and the ordering list for the prefetcher
 pub struct Jif {
+    /// sorted by virtual address, so that the mapping pheader can be binary searched
     pub(crate) pheaders: Vec<JifPheader>,
     pub(crate) ord_chunks: Vec<OrdChunk>,
     pub(crate) deduper: Deduper,
+    pub(crate) arch: Arch,
+
+    /// custom metadata (see [`crate::meta`])
+    pub(crate) meta: BTreeMap<String, String>,
 }
 
 /// The "raw" JIF file representation
@@ -44,10 +94,31 @@ pub struct JifRaw {
     pub(crate) data_offset: u64,
     pub(crate) data_segments: BTreeMap<(u64, u64), Vec<u8>>,
     pub(crate) n_prefetch: u64,
+    pub(crate) compression: Compression,
+    pub(crate) delta: bool,
+    pub(crate) checksums: bool,
+    pub(crate) itree_fanout: usize,
+    pub(crate) data_layout: DataLayout,
+    pub(crate) arch: Arch,
+
+    /// page hash index, if the JIF has one (see [`crate::page_index`])
+    pub(crate) page_index: Option<PageHashIndex>,
+
+    /// custom metadata (see [`crate::meta`])
+    pub(crate) meta: BTreeMap<String, String>,
+}
+
+/// A lazily loaded view over a JIF file
+///
+/// Only the metadata (pheaders, strings, interval trees and ordering chunks) is read eagerly:
+/// private data is read from the underlying reader when it is requested
+pub struct LazyJif<R> {
+    raw: JifRaw,
+    reader: RefCell<BufReader<R>>,
 }
 
 #[allow(dead_code)]
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct JifHeaderBinary {
     magic: [u8; 4],
     n_pheaders: u32,
@@ -61,23 +132,59 @@ pub struct JifHeaderBinary {
 impl Jif {
     /// Materialize a [`Jif`] from its raw counterpart
     pub fn from_raw(mut raw: JifRaw) -> JifResult<Self> {
+        if raw.delta {
+            return Err(JifError::DeltaWithoutBase);
+        }
+
         let data_map = raw.take_data();
         let (deduper, offset_index) = Deduper::from_data_map(data_map);
-        let pheaders = raw
-            .pheaders
-            .iter()
-            .map(|raw_pheader| JifPheader::from_raw(&raw, raw_pheader, &deduper, &offset_index))
-            .collect::<Result<Vec<JifPheader>, _>>()?;
+        Jif::from_raw_with_deduper(raw, deduper, &offset_index)
+    }
 
-        Ok(Jif {
+    /// Materialize a [`Jif`] from a (data-less) raw counterpart, with the data already in the deduper
+    fn from_raw_with_deduper(
+        mut raw: JifRaw,
+        deduper: Deduper,
+        offset_index: &BTreeMap<(u64, u64), DedupToken>,
+    ) -> JifResult<Self> {
+        if let Some(index) = &raw.page_index {
+            deduper
+                .digests()
+                .seed(index, offset_index, raw.arch.page_size);
+        }
+        let pheaders = Jif::materialize_pheaders(&mut raw, &deduper, offset_index)?;
+        let jif = Jif {
             pheaders,
             ord_chunks: raw.ord_chunks,
             deduper,
-        })
+            arch: raw.arch,
+            meta: raw.meta,
+        };
+        // the chunks are only read on their own: they have to fit the pheaders too
+        jif.validate_ord_chunks(&jif.ord_chunks)?;
+        Ok(jif)
+    }
+
+    /// Materialize the pheaders of a (data-less) raw JIF, sorted by address, with the data
+    /// already in the deduper
+    pub(crate) fn materialize_pheaders(
+        raw: &mut JifRaw,
+        deduper: &Deduper,
+        offset_index: &BTreeMap<(u64, u64), DedupToken>,
+    ) -> JifResult<Vec<JifPheader>> {
+        // the materialized interval trees have the default fanout
+        raw.set_itree_fanout(FANOUT)?;
+        let mut pheaders = raw
+            .pheaders
+            .iter()
+            .map(|raw_pheader| JifPheader::from_raw(raw, raw_pheader, deduper, offset_index))
+            .collect::<Result<Vec<JifPheader>, _>>()?;
+        pheaders.sort_by_key(|pheader| pheader.virtual_range().0);
+        Ok(pheaders)
     }
 
-    /// List out all the strings in the pheaders
-    pub fn strings(&self) -> HashSet<&str> {
+    /// List out all the strings in the pheaders (sorted)
+    pub fn strings(&self) -> BTreeSet<&str> {
         self.pheaders
             .iter()
             .filter_map(|phdr| match phdr {
@@ -87,11 +194,77 @@ impl Jif {
             .collect()
     }
 
+    /// Map each referenced path to the indices of the pheaders referencing it
+    pub fn path_usage(&self) -> BTreeMap<String, Vec<usize>> {
+        let mut usage: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (pheader_idx, pheader) in self.pheaders.iter().enumerate() {
+            if let Some(path) = pheader.pathname() {
+                usage.entry(path.to_string()).or_default().push(pheader_idx);
+            }
+        }
+
+        usage
+    }
+
     /// Read the [`Jif`] from a file
     pub fn from_reader<R: Read + Seek>(r: &mut BufReader<R>) -> JifResult<Self> {
         Jif::from_raw(JifRaw::from_reader(r)?)
     }
 
+    /// Read the [`Jif`] from the bytes of a file (e.g., fetched by a browser, without file
+    /// system access)
+    pub fn from_bytes(bytes: &[u8]) -> JifResult<Self> {
+        Jif::from_raw(JifRaw::from_bytes(bytes)?)
+    }
+
+    /// Open a JIF file lazily, without reading the data segments (see [`LazyJif`])
+    pub fn open_lazy<P: AsRef<std::path::Path>>(path: P) -> JifResult<LazyJif<File>> {
+        LazyJif::from_reader(BufReader::new(open_file(path.as_ref())?))
+    }
+
+    /// Read the [`Jif`] from a memory mapped file
+    ///
+    /// The data segments are not copied: they are borrowed from the map, which is kept alive
+    /// for as long as the data is referenced. The file should not be modified in the meantime.
+    /// The integrity trailer (if any) is verified, as with [`Jif::from_reader`]
+    #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+    pub fn from_mmap<P: AsRef<std::path::Path>>(path: P) -> JifResult<Self> {
+        let map = std::sync::Arc::new(Mmap::map(&File::open(path)?)?);
+        let mut r = BufReader::new(std::io::Cursor::new(&map[..]));
+        let mut raw = JifRaw::from_reader_metadata(&mut r)?;
+        let trailer = raw.read_integrity_trailer(&mut r)?;
+        raw.read_trailing_sections(&mut r)?;
+        if raw.compression != Compression::None {
+            return Err(JifError::CompressedDataSection);
+        } else if raw.delta {
+            return Err(JifError::DeltaWithoutBase);
+        }
+
+        // the segments have to be in the map (the offsets of a corrupt file may overflow)
+        let eof = || JifError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        let segments = JifRaw::data_segment_ranges(&raw.itree_nodes, raw.data_offset)
+            .into_iter()
+            .map(|(offset, len)| {
+                let end = offset.checked_add(len)?;
+                let file_end = raw.data_offset.checked_add(end)?;
+                (file_end <= map.len() as u64).then_some((offset, end))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(eof)?;
+
+        if let Some(trailer) = trailer {
+            let data_segments = segments.iter().map(|(start, end)| {
+                let range = (raw.data_offset + start) as usize..(raw.data_offset + end) as usize;
+                ((*start, *end), &map[range])
+            });
+            raw.verify_integrity(&trailer, &mut r, data_segments)?;
+        }
+
+        let (deduper, offset_index) =
+            Deduper::from_mapped_segments(&map, raw.data_offset, segments.into_iter());
+        Jif::from_raw_with_deduper(raw, deduper, &offset_index)
+    }
+
     /// Write the [`Jif`] to a file
     pub fn to_writer<W: Write>(self, w: &mut W) -> std::io::Result<usize> {
         let raw = JifRaw::from_materialized(self, false);
@@ -100,6 +273,11 @@ impl Jif {
 
     /// Compute the data offset (i.e., the offset where data starts being laid out)
     pub fn data_offset(&self) -> u64 {
+        self.metadata_footprint().size() as u64
+    }
+
+    /// On-disk size of the sections before the data (the data size is left as 0)
+    pub(crate) fn metadata_footprint(&self) -> DiskFootprint {
         let header_size = std::mem::size_of::<JifHeaderBinary>();
 
         let pheader_size = self.pheaders.len() * JifRawPheader::serialized_size();
@@ -118,14 +296,19 @@ impl Jif {
                 JifPheader::Reference { itree, .. } => itree.n_nodes(),
             })
             .sum::<usize>()
-            * RawITreeNode::serialized_size();
-
-        let ord_size = self.ord_chunks.len() * OrdChunk::serialized_size();
-
-        page_align((header_size + pheader_size) as u64)
-            + page_align(strings_size as u64)
-            + page_align(itree_size as u64)
-            + page_align(ord_size as u64)
+            * RawITreeNode::serialized_size(FANOUT);
+
+        let ord_size =
+            self.ord_chunks.len() * OrdChunk::serialized_size(has_provenance(&self.ord_chunks));
+
+        let align = |size: usize| page_align(size as u64, self.arch.page_size) as usize;
+        DiskFootprint {
+            metadata: align(header_size + pheader_size),
+            strings: align(strings_size),
+            itrees: align(itree_size),
+            ord: align(ord_size),
+            data: 0,
+        }
     }
 
     // Use ordering chunks to break apart intervals so that data pages can be reordered.
@@ -196,6 +379,17 @@ impl Jif {
         // Collect all data segments.
         let mut data_segments: BTreeMap<(u64, u64), Vec<u8>> = self.deduper.destructure(token_map);
 
+        // Deduplicated intervals share data segments: count how many intervals point at each one,
+        // so that fracturing one interval does not pull the data from under the others
+        let mut n_refs: BTreeMap<u64, usize> = BTreeMap::new();
+        for iv in hdrs
+            .iter()
+            .flat_map(|(_rng, _prot, _r1, _r2, ivs)| ivs.iter())
+            .filter(|iv| iv.offset < u64::MAX)
+        {
+            *n_refs.entry(iv.offset).or_default() += 1;
+        }
+
         // For each ordering chunk, find a corresponding data interval and fragment the interval.
         for chunk in &self.ord_chunks {
             let rph = hdrs
@@ -218,11 +412,16 @@ impl Jif {
                 continue;
             }
 
-            let chunksz = chunk.n_pages * PAGE_SIZE as u64;
-            let chunk_va_end = chunk.vaddr + chunksz;
             let ppos = pos.unwrap();
             let mut v = ivs.remove(ppos);
 
+            // a chunk crossing the end of the interval only fractures the part inside it
+            let chunksz = std::cmp::min(
+                chunk.n_pages.saturating_mul(self.arch.page_size as u64),
+                v.end - chunk.vaddr,
+            );
+            let chunk_va_end = chunk.vaddr + chunksz;
+
             let interval_size = v.end - v.start;
             let left_size = chunk.vaddr - v.start;
             let right_size = interval_size - left_size - chunksz;
@@ -237,32 +436,45 @@ impl Jif {
                     .unwrap()
             };
 
-            let data = data_segments.remove(&found_key).unwrap();
+            let shared = n_refs.get(&found_key.0).copied().unwrap_or(0) > 1;
+            let (data, base) = if shared {
+                // other intervals still reference this segment: the fractured pieces get a copy
+                // of the data at a fresh offset
+                if let Some(cnt) = n_refs.get_mut(&found_key.0) {
+                    *cnt -= 1;
+                }
+                let base = data_offset;
+                data_offset += interval_size;
+                (data_segments[&found_key].clone(), base)
+            } else {
+                (data_segments.remove(&found_key).unwrap(), v.offset)
+            };
+
+            let chunk_off_start = base + left_size;
+            let chunk_off_end = chunk_off_start + chunksz;
 
             if left_size > 0 {
-                ivs.push(RawInterval::new(v.start, chunk.vaddr, v.offset));
+                ivs.push(RawInterval::new(v.start, chunk.vaddr, base));
+                n_refs.insert(base, 1);
                 data_segments.insert(
-                    (v.offset, v.offset + left_size),
+                    (base, base + left_size),
                     data[..left_size as usize].to_vec(),
                 );
             }
 
             if right_size > 0 {
-                ivs.push(RawInterval::new(
-                    chunk_va_end,
-                    v.end,
-                    v.offset + left_size + chunksz,
-                ));
-                assert!(v.offset + left_size + chunksz == chunk_off_end);
+                ivs.push(RawInterval::new(chunk_va_end, v.end, chunk_off_end));
+                n_refs.insert(chunk_off_end, 1);
                 data_segments.insert(
                     (chunk_off_end, chunk_off_end + right_size),
                     data[(left_size + chunksz) as usize..].to_vec(),
                 );
             }
-            v.offset += left_size;
+            v.offset = chunk_off_start;
             v.start = chunk.vaddr;
             v.end = v.start + chunksz;
             ivs.push(v);
+            n_refs.insert(chunk_off_start, 1);
             data_segments.insert(
                 (chunk_off_start, chunk_off_end),
                 data[left_size as usize..(left_size + chunksz) as usize].to_vec(),
@@ -279,6 +491,7 @@ impl Jif {
                     .iter()
                     .map(|iv| {
                         Interval::<RefIntervalData>::from_raw_ref(iv, 0, &new_dedup, &new_map)
+                            .unwrap()
                     })
                     .collect();
                 intervals.sort_by_key(|k| k.start);
@@ -306,34 +519,176 @@ impl Jif {
             }
         }
 
+        headers.sort_by_key(|pheader| pheader.virtual_range().0);
         self.pheaders = headers;
         self.deduper = new_dedup;
     }
 
     /// Construct the interval trees of all the pheaders
-    pub fn build_itrees(&mut self, chroot: Option<std::path::PathBuf>) -> JifResult<()> {
+    ///
+    /// With `huge_page_align`, the 2MiB regions which would only save up to that many zero pages
+    /// are not fractured (see [`Jif::align_huge_pages`])
+    pub fn build_itrees(
+        &mut self,
+        paths: &dyn PathResolver,
+        huge_page_align: Option<usize>,
+    ) -> JifResult<()> {
+        self.build_itrees_with(paths, huge_page_align, &ExactComparator)
+    }
+
+    /// Construct the interval trees of all the pheaders, comparing the pages of the reference
+    /// pheaders with those of the files with the `comparator`
+    ///
+    /// The pages the comparator deems the same as the file's are shared, even if their bytes
+    /// differ: e.g., [`IgnoreRanges`](crate::itree::diff::IgnoreRanges) masks out fields known to be irrelevant to the restore
+    pub fn build_itrees_with(
+        &mut self,
+        paths: &dyn PathResolver,
+        huge_page_align: Option<usize>,
+        comparator: &dyn PageComparator,
+    ) -> JifResult<()> {
         for pheader in self.pheaders.iter_mut() {
             pheader
-                .build_itree(&self.deduper, &chroot)
+                .build_itree_with(&self.deduper, self.arch.page_size, paths, comparator)
                 .map_err(|error| JifError::InvalidITree {
                     virtual_range: pheader.virtual_range(),
                     error,
                 })?;
         }
 
+        if let Some(max_zero_pages) = huge_page_align {
+            self.align_huge_pages(max_zero_pages)?;
+        }
+
         Ok(())
     }
 
     /// Fragment vmas based on their source
-    pub fn fragment(&mut self, chroot: Option<std::path::PathBuf>) -> JifResult<()> {
-        self.pheaders = self
-            .pheaders
-            .drain(..)
-            .map(|pheader| pheader.fragment(&self.deduper, &chroot))
-            .collect::<Result<Vec<_>, _>>()?
+    ///
+    /// With `huge_page_align`, the 2MiB regions which would only save up to that many zero pages
+    /// are not fractured (see [`Jif::align_huge_pages`])
+    pub fn fragment(
+        &mut self,
+        paths: &dyn PathResolver,
+        huge_page_align: Option<usize>,
+    ) -> JifResult<()> {
+        self.fragment_with(paths, huge_page_align, &ExactComparator)
+    }
+
+    /// Fragment vmas based on their source, building the interval trees with the `comparator`
+    /// (see [`Jif::build_itrees_with`])
+    pub fn fragment_with(
+        &mut self,
+        paths: &dyn PathResolver,
+        huge_page_align: Option<usize>,
+        comparator: &dyn PageComparator,
+    ) -> JifResult<()> {
+        self.pheaders = if huge_page_align.is_some() {
+            self.build_itrees_with(paths, huge_page_align, comparator)?;
+            self.pheaders
+                .drain(..)
+                .flat_map(|pheader| pheader.split_intervals())
+                .collect()
+        } else {
+            self.pheaders
+                .drain(..)
+                .map(|pheader| {
+                    pheader.fragment_with(&self.deduper, self.arch.page_size, paths, comparator)
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .flat_map(|x| x.into_iter())
+                .collect::<Vec<_>>()
+        };
+        self.pheaders
+            .sort_by_key(|pheader| pheader.virtual_range().0);
+
+        Ok(())
+    }
+
+    /// Drop the pheaders overlapping a virtual address range, along with their ordering chunks
+    ///
+    /// Their data is dropped when the JIF is written out (see [`JifRaw::from_materialized`]) or
+    /// collected ([`Jif::gc`]), and the number of dropped pheaders is returned
+    pub fn drop_pheaders(&mut self, range: (u64, u64)) -> usize {
+        let n_pheaders = self.pheaders.len();
+        self.retain_pheaders(range, false);
+        n_pheaders - self.pheaders.len()
+    }
+
+    /// Keep only the pheaders overlapping a virtual address range, along with their ordering
+    /// chunks (e.g., to share a single VMA)
+    ///
+    /// The strings and data of the other pheaders are dropped when the JIF is written out (see
+    /// [`JifRaw::from_materialized`]), and the number of kept pheaders is returned
+    pub fn keep_pheaders(&mut self, range: (u64, u64)) -> usize {
+        self.retain_pheaders(range, true);
+        self.pheaders.len()
+    }
+
+    /// Retain the pheaders which overlap `[start; end)` (or the ones which do not), dropping the
+    /// ordering chunks of the others
+    fn retain_pheaders(&mut self, (start, end): (u64, u64), overlapping: bool) {
+        self.pheaders.retain(|pheader| {
+            let (phdr_start, phdr_end) = pheader.virtual_range();
+            (phdr_start < end && start < phdr_end) == overlapping
+        });
+
+        let ord_chunks = std::mem::take(&mut self.ord_chunks);
+        self.ord_chunks = ord_chunks
             .into_iter()
-            .flat_map(|x| x.into_iter())
+            .filter(|chunk| self.mapping_pheader_idx(chunk.vaddr).is_some())
+            .collect();
+    }
+
+    /// Merge the pheaders (with their data) and ordering chunks of `other` into this [`Jif`]
+    ///
+    /// Both JIFs have to have the same architecture (and page size), and their virtual ranges have to be disjoint.
+    /// The ordering chunks of `other` are prefetched after the ones of this JIF, and its custom
+    /// metadata is added (for the keys this JIF does not have)
+    pub fn merge(&mut self, other: Jif) -> JifResult<()> {
+        if other.arch != self.arch {
+            return Err(JifError::ArchMismatch {
+                expected: self.arch,
+                found: other.arch,
+            });
+        }
+
+        let mut ranges = self
+            .pheaders
+            .iter()
+            .chain(other.pheaders.iter())
+            .map(|pheader| pheader.virtual_range())
             .collect::<Vec<_>>();
+        ranges.sort();
+        if let Some((pheader_1, pheader_2)) = ranges
+            .iter()
+            .zip(ranges.iter().skip(1))
+            .find(|(a, b)| a.1 > b.0)
+        {
+            return Err(JifError::OverlappingPheaders {
+                pheader_1: *pheader_1,
+                pheader_2: *pheader_2,
+            });
+        }
+
+        let Jif {
+            pheaders,
+            ord_chunks,
+            deduper,
+            meta,
+            ..
+        } = other;
+        for (key, value) in meta {
+            self.meta.entry(key).or_insert(value);
+        }
+        for mut pheader in pheaders {
+            pheader.move_data(&deduper, &mut self.deduper);
+            self.pheaders.push(pheader);
+        }
+        self.pheaders
+            .sort_by_key(|pheader| pheader.virtual_range().0);
+        self.ord_chunks.extend(ord_chunks);
 
         Ok(())
     }
@@ -346,16 +701,111 @@ impl Jif {
     }
 
     /// Add a new ordering section
+    ///
+    /// Fails if any of the chunks does not fit the pheaders (see
+    /// [`Jif::add_ordering_info_lenient`] to prune them instead)
     pub fn add_ordering_info(&mut self, ordering_info: Vec<OrdChunk>) -> JifResult<()> {
-        self.ord_chunks = ordering_info
+        let ord_chunks = ordering_info
             .into_iter()
             .filter(|chunk| !chunk.is_empty())
-            .inspect(|chunk| {
-                self.mapping_pheader_idx(chunk.vaddr)
-                    .expect(&format!("bad ord chunk {}", chunk.vaddr));
+            .collect::<Vec<_>>();
+        self.validate_ord_chunks(&ord_chunks)?;
+        self.ord_chunks = ord_chunks;
+        Ok(())
+    }
+
+    /// Check that every ordering chunk is page aligned, and that all their pages are mapped by a
+    /// pheader
+    pub(crate) fn validate_ord_chunks(&self, ord_chunks: &[OrdChunk]) -> JifResult<()> {
+        for (ord_chunk_idx, chunk) in ord_chunks.iter().enumerate() {
+            if !is_page_aligned(chunk.vaddr, self.arch.page_size) {
+                return Err(JifError::BadOrdChunk {
+                    ord_chunk_idx,
+                    ord_chunk_err: OrdChunkError::BadAlignment(chunk.vaddr),
+                });
+            }
+
+            if let Some(vaddr) = self.unmapped_page(chunk) {
+                return Err(JifError::BadOrdChunk {
+                    ord_chunk_idx,
+                    ord_chunk_err: OrdChunkError::UnmappedAddress(vaddr),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Modify the ordering section in place
+    ///
+    /// The empty chunks are dropped and the remaining ones are validated once `f` returns: if they
+    /// are not valid, the ordering section is left untouched
+    pub fn ord_chunks_mut<R>(&mut self, f: impl FnOnce(&mut Vec<OrdChunk>) -> R) -> JifResult<R> {
+        let mut ord_chunks = self.ord_chunks.clone();
+        let ret = f(&mut ord_chunks);
+        ord_chunks.retain(|chunk| !chunk.is_empty());
+        self.validate_ord_chunks(&ord_chunks)?;
+        self.ord_chunks = ord_chunks;
+        Ok(ret)
+    }
+
+    /// Insert an ordering chunk at `idx` (or at the end, if `idx` is past it)
+    pub fn insert_ord_chunk(&mut self, idx: usize, chunk: OrdChunk) -> JifResult<()> {
+        self.ord_chunks_mut(|ord_chunks| {
+            ord_chunks.insert(std::cmp::min(idx, ord_chunks.len()), chunk)
+        })
+    }
+
+    /// Remove the pages overlapping a virtual address range from the ordering section, splitting
+    /// the chunks which are only partially covered
+    ///
+    /// Returns the number of removed pages
+    pub fn remove_ord_range(&mut self, (start, end): (u64, u64)) -> usize {
+        let page_size = self.arch.page_size;
+        let mut removed = 0;
+        self.ord_chunks = std::mem::take(&mut self.ord_chunks)
+            .into_iter()
+            .flat_map(|chunk| {
+                let chunk_end = chunk.end(page_size);
+                if chunk_end <= start || end <= chunk.vaddr {
+                    return vec![chunk];
+                }
+
+                let before_end = std::cmp::max(chunk.vaddr, page_align_down(start, page_size));
+                let after_start = std::cmp::min(chunk_end, page_align(end, page_size));
+                let before = chunk.part(
+                    chunk.vaddr,
+                    (before_end - chunk.vaddr) / page_size as u64,
+                    chunk.kind,
+                );
+                let after = chunk.part(
+                    after_start,
+                    (chunk_end - after_start) / page_size as u64,
+                    chunk.kind,
+                );
+                removed += (chunk.n_pages - before.n_pages - after.n_pages) as usize;
+
+                [before, after]
+                    .into_iter()
+                    .filter(|chunk| !chunk.is_empty())
+                    .collect()
             })
             .collect();
-        Ok(())
+
+        removed
+    }
+
+    /// Reorder the ordering section by a key (the sort is stable)
+    pub fn reorder_ord_by<K: Ord>(&mut self, key: impl FnMut(&OrdChunk) -> K) {
+        self.ord_chunks.sort_by_key(key);
+    }
+
+    /// Remove the ordering section
+    ///
+    /// Once written out (without prefetching) the data is laid out by address again. Returns the
+    /// number of removed chunks
+    pub fn clear_ordering(&mut self) -> usize {
+        std::mem::take(&mut self.ord_chunks).len()
     }
 
     /// Access the pheaders
@@ -363,6 +813,18 @@ impl Jif {
         &self.pheaders
     }
 
+    /// The page size of the JIF: every address, offset and length is aligned to it, and the
+    /// page counts are in pages of this size
+    pub fn page_size(&self) -> usize {
+        self.arch.page_size
+    }
+
+    /// The architecture the JIF was captured on (see [`Arch::is_compatible_with`] to check
+    /// whether it can be restored on a host)
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
     /// Stored data size in B
     pub fn date_size(&self) -> usize {
         self.pheaders.iter().map(|phdr| phdr.data_size()).sum()
@@ -375,45 +837,116 @@ impl Jif {
 
     /// Compute the total number of zero pages encoded (by omission) in the [`Jif`]
     pub fn zero_pages(&self) -> usize {
-        self.pheaders.iter().map(|phdr| phdr.zero_pages()).sum()
+        self.pheaders
+            .iter()
+            .map(|phdr| phdr.zero_pages(self.arch.page_size))
+            .sum()
     }
 
     /// Compute the total number of private pages stored (directly) in the [`Jif`]
     pub fn private_pages(&self) -> usize {
-        self.pheaders.iter().map(|phdr| phdr.private_pages()).sum()
+        self.pheaders
+            .iter()
+            .map(|phdr| phdr.private_pages(self.arch.page_size))
+            .sum()
     }
 
     /// Compute the total number of shared pages referenced by the [`Jif`]
     pub fn shared_pages(&self) -> usize {
-        self.pheaders.iter().map(|phdr| phdr.shared_pages()).sum()
+        self.pheaders
+            .iter()
+            .map(|phdr| phdr.shared_pages(self.arch.page_size))
+            .sum()
     }
 
     /// The total number of pages
     pub fn total_pages(&self) -> usize {
-        self.pheaders.iter().map(|phdr| phdr.total_pages()).sum()
+        self.pheaders
+            .iter()
+            .map(|phdr| phdr.total_pages(self.arch.page_size))
+            .sum()
     }
 
-    // Find the pheader (by index) that maps a particular address
-    pub(crate) fn mapping_pheader_idx(&self, vaddr: u64) -> Option<usize> {
-        self.pheaders
+    /// Statistics of the sharing of the private data: every interval referencing a data
+    /// segment counts as a reference
+    ///
+    /// The data owned by the intervals (e.g., of interval trees just built) is not deduplicated
+    /// until the JIF is written out, and is not included
+    pub fn dedup_stats(&self) -> DedupStats {
+        self.deduper
+            .stats_of(self.pheaders.iter().flat_map(JifPheader::dedup_tokens))
+    }
+
+    /// Drop the data segments which no interval references anymore (e.g., after dropping
+    /// pheaders or minimizing the JIF), returning the bytes reclaimed
+    ///
+    /// Writing the JIF out never carries this data (only the referenced segments are written: see
+    /// [`JifRaw::from_materialized`]), but it is held in memory until then, and it is counted in
+    /// the validation report ([`Jif::validate`])
+    pub fn gc(&mut self) -> usize {
+        let referenced = self
+            .pheaders
             .iter()
-            .enumerate()
-            .find(|(_idx, pheader)| pheader.mapps_addr(vaddr))
-            .map(|(idx, _pheader)| idx)
+            .flat_map(JifPheader::held_tokens)
+            .collect::<HashSet<_>>();
+        self.deduper.retain(&referenced)
+    }
+
+    /// Statistics of the logical intervals of every interval tree (see
+    /// [`crate::itree::ITreeView::interval_histogram`])
+    pub fn interval_histogram(&self) -> IntervalHistogram {
+        let mut histogram = IntervalHistogram::default();
+        for pheader in &self.pheaders {
+            histogram.merge(&pheader.itree().interval_histogram());
+        }
+
+        histogram
+    }
+
+    /// Find the pheader (by index) that maps a particular address
+    ///
+    /// The pheaders are sorted and disjoint, so the only candidate is the last one starting at or
+    /// before the address
+    pub(crate) fn mapping_pheader_idx(&self, vaddr: u64) -> Option<usize> {
+        let idx = self
+            .pheaders
+            .partition_point(|pheader| pheader.virtual_range().0 <= vaddr)
+            .checked_sub(1)?;
+        self.pheaders[idx].mapps_addr(vaddr).then_some(idx)
+    }
+
+    /// The first page of an ordering chunk which no pheader maps, if any
+    ///
+    /// This walks the pheaders the chunk spans, rather than its pages (the chunks read from a
+    /// corrupt file may span the whole address space)
+    pub(crate) fn unmapped_page(&self, chunk: &OrdChunk) -> Option<u64> {
+        let page_size = self.arch.page_size;
+        let end = chunk
+            .vaddr
+            .saturating_add(chunk.n_pages.saturating_mul(page_size as u64));
+        let mut cursor = chunk.vaddr;
+        while cursor < end {
+            let idx = match self.mapping_pheader_idx(cursor) {
+                Some(idx) => idx,
+                None => return Some(page_align_down(cursor, page_size)),
+            };
+            cursor = self.pheaders[idx].virtual_range().1;
+        }
+
+        None
     }
 
-    // Find the pheader (by index) that maps a particular address
+    /// Find the pheader that maps a particular address
     pub fn mapping_pheader(&self, vaddr: u64) -> Option<&JifPheader> {
-        self.pheaders
-            .iter()
-            .find(|pheader| pheader.mapps_addr(vaddr))
+        self.mapping_pheader_idx(vaddr)
+            .map(|idx| &self.pheaders[idx])
     }
 
     /// Iterate over all the private pages
     pub fn iter_private_pages(&self) -> impl Iterator<Item = &[u8]> {
         self.pheaders
             .iter()
-            .flat_map(|phdr| phdr.iter_private_pages(&self.deduper))
+            .flat_map(|phdr| phdr.iter_private_pages(&self.deduper, self.arch.page_size))
     }
 
     /// Iterate over all the shared regions
@@ -425,22 +958,55 @@ impl Jif {
 
     /// Resolve an address into a [`DataSource`]
     pub fn resolve(&self, addr: u64) -> Option<LogicalInterval> {
-        self.pheaders
-            .iter()
-            .find(|phdr| phdr.mapps_addr(addr))
-            .map(|phdr| phdr.resolve(addr))
+        self.mapping_pheader(addr).map(|phdr| phdr.resolve(addr))
     }
 
     /// Resolve an address into the private data
     pub fn resolve_data(&self, addr: u64) -> Option<&[u8]> {
-        self.pheaders
-            .iter()
-            .find_map(|phdr| phdr.resolve_data(addr, &self.deduper))
+        self.mapping_pheader(addr)
+            .and_then(|phdr| phdr.resolve_data(addr, &self.deduper, self.arch.page_size))
+    }
+
+    /// Reconstruct the logical memory contents of `[addr; addr + len)`, which may span several
+    /// pheaders
+    ///
+    /// Shared pages are read from the referenced files (as resolved by `paths`), and the whole
+    /// range has to be mapped
+    pub fn read_range(&self, addr: u64, len: u64, paths: &dyn PathResolver) -> JifResult<Vec<u8>> {
+        let end = addr.saturating_add(len);
+        let mut data = Vec::new();
+        let mut cursor = addr;
+        while cursor < end {
+            let pheader = self
+                .mapping_pheader(cursor)
+                .ok_or(JifError::UnmappedAddress { addr: cursor })?;
+            let read_end = std::cmp::min(end, pheader.virtual_range().1);
+            pheader.read_range_into(
+                (cursor, read_end),
+                &self.deduper,
+                self.arch.page_size,
+                paths,
+                &mut data,
+            )?;
+            cursor = read_end;
+        }
+
+        Ok(data)
+    }
+
+    /// Compute the structural differences between this [`Jif`] and `other`
+    pub fn diff(&self, other: &Jif) -> JifDiff {
+        JifDiff::between(self, other)
     }
 }
 
 impl JifRaw {
-    /// Order the data segments keeping in mind the ordering in the ord_chunks
+    /// Order the data segments keeping in mind the ordering in the ord_chunks (the hottest ones
+    /// first: see [`OrdChunk::weight`]), placing them as the `layout` dictates
+    ///
+    /// Returns the number of pages to prefetch (the padding before the segments of the ordering
+    /// chunks included)
+    ///
     /// Assumptions:
     ///  - intervals in [`ITree`]s are unique
     ///  - intervals don't overlap
@@ -449,7 +1015,21 @@ impl JifRaw {
         itree_nodes: Vec<IntermediateITreeNode>,
         ord_chunks: &[OrdChunk],
         mut data_offset: u64,
+        page_size: usize,
+        layout: &DataLayout,
     ) -> (BTreeMap<DedupToken, (u64, u64)>, Vec<RawITreeNode>, u64) {
+        // move the cursor to where the layout places the data of the interval (if it is not
+        // already placed)
+        let place = |inter: &IntermediateInterval,
+                     token_map: &BTreeMap<DedupToken, (u64, u64)>,
+                     data_offset: u64,
+                     ordered: bool| match &inter.data {
+            IntermediateIntervalData::Ref(token) if !token_map.contains_key(token) => {
+                layout.place(data_offset, ordered, page_size)
+            }
+            _ => data_offset,
+        };
+
         let mut intervals = {
             let mut v = itree_nodes
                 .iter()
@@ -464,7 +1044,11 @@ impl JifRaw {
         let mut raw_intervals = BTreeMap::new();
         let mut prefetch_pages = 0;
 
-        for chunk in ord_chunks {
+        // the hottest chunks place their data first
+        for chunk in placement_order(ord_chunks)
+            .into_iter()
+            .map(|idx| &ord_chunks[idx])
+        {
             // if an ordering chunk is not found it is ignored
             if let Ok(idx) = intervals.binary_search_by(|(ival, _)| {
                 if ival.start > chunk.vaddr {
@@ -482,6 +1066,10 @@ impl JifRaw {
 
                 intervals[idx].1 = true;
 
+                // the padding is prefetched along with the segments
+                let placed = place(intervals[idx].0, &token_map, data_offset, true);
+                prefetch_pages += (placed - data_offset) / page_size as u64;
+                data_offset = placed;
                 let new_interval = RawInterval::from_intermediate(
                     intervals[idx].0,
                     &mut token_map,
@@ -490,11 +1078,12 @@ impl JifRaw {
 
                 raw_intervals.insert((new_interval.start, new_interval.end), new_interval);
 
-                prefetch_pages += (new_interval.end - new_interval.start) / PAGE_SIZE as u64;
+                prefetch_pages += (new_interval.end - new_interval.start) / page_size as u64;
             }
         }
 
         for inter in intervals.iter_mut().filter(|(_ival, touched)| !touched) {
+            data_offset = place(inter.0, &token_map, data_offset, false);
             let new_interval =
                 RawInterval::from_intermediate(inter.0, &mut token_map, &mut data_offset);
 
@@ -510,8 +1099,52 @@ impl JifRaw {
     }
 
     /// Construct a raw JIF from a materialized one
-    pub fn from_materialized(mut jif: Jif, prefetch_chunks: bool) -> Self {
+    ///
+    /// The construction is deterministic: the strings are sorted and the data segments are laid
+    /// out in address order (or in ordering chunk order, when prefetching), so equal JIFs are
+    /// always written out to the same bytes. Only the strings referenced by the pheaders are
+    /// written out (see [`Jif::path_usage`]), so stale ones (e.g., after renaming a file or
+    /// dropping pheaders) are garbage collected
+    ///
+    /// When prefetching, the ordering chunks are grouped by kind and compacted (see
+    /// [`Jif::compact_ordering`]) before fracturing the intervals by them
+    ///
+    /// The data segments no interval references are garbage collected first (see [`Jif::gc`]).
+    /// Either way, only the referenced segments are laid out, so writing out a JIF is equivalent
+    /// to writing it out after [`Jif::gc`]: [`JifRaw::from_materialized_with_gc`] skips the
+    /// collection (e.g., when only the headers were edited, leaving no data to collect)
+    pub fn from_materialized(jif: Jif, prefetch_chunks: bool) -> Self {
+        Self::from_materialized_with_layout(jif, prefetch_chunks, DataLayout::default())
+    }
+
+    /// Construct a raw JIF from a materialized one, placing the data segments as the `layout`
+    /// dictates (see [`JifRaw::from_materialized`])
+    ///
+    /// The layout is recorded (see [`JifRaw::data_layout`]), so that the segments stay aligned
+    /// when the data section moves (e.g., [`JifRaw::set_itree_fanout`])
+    pub fn from_materialized_with_layout(
+        jif: Jif,
+        prefetch_chunks: bool,
+        layout: DataLayout,
+    ) -> Self {
+        Self::from_materialized_with_gc(jif, prefetch_chunks, layout, true)
+    }
+
+    /// Construct a raw JIF from a materialized one, garbage collecting the unreferenced data
+    /// segments first only if `gc` (see [`JifRaw::from_materialized_with_layout`])
+    pub fn from_materialized_with_gc(
+        mut jif: Jif,
+        prefetch_chunks: bool,
+        layout: DataLayout,
+        gc: bool,
+    ) -> Self {
+        if gc {
+            jif.gc();
+        }
         if prefetch_chunks {
+            // the chunks of a kind are written out together: merging the ones which then continue
+            // one another leaves fewer chunks (and fewer intervals fractured by them)
+            jif.compact_ordering_by_kind();
             jif.fracture_by_ord_chunk()
         }
 
@@ -519,17 +1152,11 @@ impl JifRaw {
         jif.pheaders.sort_by_key(|phdr| phdr.virtual_range().0);
 
         let string_map = {
-            let strings = jif
-                .strings()
-                .into_iter()
-                .map(|s| s.to_string())
-                .collect::<HashSet<String>>();
-
             let mut offset = 0;
-            strings
+            jif.strings()
                 .into_iter()
                 .map(|s| {
-                    let r = (s, offset);
+                    let r = (s.to_string(), offset);
                     offset += r.0.len() + 1 /* NUL */;
                     r
                 })
@@ -572,16 +1199,22 @@ impl JifRaw {
             s
         };
 
-        // Sort chunks by kind.
-        jif.ord_chunks.sort_by_key(|c| match c.kind {
-            DataSource::Zero => 1,
-            DataSource::Shared => 2,
-            DataSource::Private => 0,
-        });
+        sort_ord_chunks_by_kind(&mut jif.ord_chunks);
 
-        let (token_map, itree_nodes, prefetch_pages) =
-            Self::order_data_segments(itree_nodes, &jif.ord_chunks, data_offset);
-        let data_segments = jif.deduper.destructure(token_map);
+        let (token_map, itree_nodes, prefetch_pages) = Self::order_data_segments(
+            itree_nodes,
+            &jif.ord_chunks,
+            data_offset,
+            jif.arch.page_size,
+            &layout,
+        );
+        // the data segments are keyed by their offset in the data section (as when reading)
+        let data_segments = jif
+            .deduper
+            .destructure(token_map)
+            .into_iter()
+            .map(|((start, end), data)| ((start - data_offset, end - data_offset), data))
+            .collect();
 
         JifRaw {
             pheaders,
@@ -591,6 +1224,14 @@ impl JifRaw {
             data_offset,
             data_segments,
             n_prefetch: if prefetch_chunks { prefetch_pages } else { 0 },
+            compression: Compression::None,
+            delta: false,
+            checksums: false,
+            itree_fanout: FANOUT,
+            data_layout: layout,
+            arch: jif.arch,
+            page_index: None,
+            meta: jif.meta,
         }
     }
 
@@ -609,6 +1250,42 @@ impl JifRaw {
         &self.ord_chunks
     }
 
+    /// The page size of the JIF (see [`Jif::page_size`])
+    pub fn page_size(&self) -> usize {
+        self.arch.page_size
+    }
+
+    /// The architecture the JIF was captured on (see [`Jif::arch`])
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// The compression of the data section
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Set the compression used when writing out the data section
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Whether the JIF has (or will be written with) an integrity section
+    pub fn checksums(&self) -> bool {
+        self.checksums
+    }
+
+    /// Set whether to write an integrity section (with data segment and file checksums)
+    pub fn set_checksums(&mut self, checksums: bool) {
+        self.checksums = checksums;
+    }
+
+    /// The layout the data segments were placed with (the default one, i.e. packed, for a JIF
+    /// read from a file: see [`crate::LayoutMap::padded_segments`] for the one of the file)
+    pub fn data_layout(&self) -> DataLayout {
+        self.data_layout
+    }
+
     /// Access the interval tree node list
     pub fn itree_nodes(&self) -> &[RawITreeNode] {
         &self.itree_nodes
@@ -636,6 +1313,15 @@ impl JifRaw {
             .collect::<Vec<&str>>()
     }
 
+    /// Size of the strings in the string table, in B (without the padding)
+    pub fn strings_size(&self) -> usize {
+        self.strings_backing
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map(|idx| std::cmp::min(idx + 2, self.strings_backing.len()))
+            .unwrap_or(0)
+    }
+
     /// Find a string at a particular offset
     pub(crate) fn string_at_offset(&self, offset: usize) -> Option<&str> {
         if offset > self.strings_backing.len() {
@@ -707,10 +1393,18 @@ impl JifRaw {
         let nodes = self
             .itree_nodes
             .iter()
+            .enumerate()
             .skip(index)
             .take(n)
-            .map(|raw| ITreeNode::from_raw_ref(raw, self.data_offset, deduper, offset_idx))
-            .collect::<Vec<_>>();
+            .map(|(itree_node_idx, raw)| {
+                ITreeNode::from_raw_ref(raw, self.data_offset, deduper, offset_idx).map_err(
+                    |itree_node_err| JifError::BadITreeNode {
+                        itree_node_idx,
+                        itree_node_err,
+                    },
+                )
+            })
+            .collect::<JifResult<Vec<_>>>()?;
 
         ITree::new(nodes, virtual_range).map_err(|error| JifError::InvalidITree {
             virtual_range,
@@ -719,11 +1413,158 @@ impl JifRaw {
     }
 }
 
+impl<R: Read + Seek> LazyJif<R> {
+    /// Read the metadata of a JIF, deferring the data segments until they are requested
+    pub fn from_reader(mut r: BufReader<R>) -> JifResult<Self> {
+        let mut raw = JifRaw::from_reader_metadata(&mut r)?;
+        if raw.compression != Compression::None {
+            return Err(JifError::CompressedDataSection);
+        } else if raw.delta {
+            return Err(JifError::DeltaWithoutBase);
+        }
+        raw.read_trailing_sections(&mut r)?;
+
+        // the data is only read on demand: check upfront that it is all in the file
+        let file_len = r.seek(SeekFrom::End(0))?;
+        let lazy = LazyJif {
+            raw,
+            reader: RefCell::new(r),
+        };
+        for phdr in &lazy.raw.pheaders {
+            for ival in lazy.data_intervals(phdr) {
+                let end = ival.offset.checked_add(ival.len());
+                if end.is_none_or(|end| end > file_len) {
+                    return Err(JifError::DataSegmentNotFound {
+                        data_range: (ival.offset, end.unwrap_or(u64::MAX)),
+                        virtual_range: phdr.virtual_range(),
+                        found_len: file_len.saturating_sub(ival.offset) as usize,
+                    });
+                }
+            }
+        }
+
+        Ok(lazy)
+    }
+
+    /// Materialize the full [`Jif`], reading all the data segments
+    pub fn materialize(self) -> JifResult<Jif> {
+        let LazyJif { mut raw, reader } = self;
+        let mut r = reader.into_inner();
+        r.seek(SeekFrom::Start(raw.data_offset))?;
+        raw.data_segments = JifRaw::read_data_segments(&mut r, &raw.itree_nodes, raw.data_offset)?;
+        Jif::from_raw(raw)
+    }
+
+    /// Access the underlying [`JifRaw`] (which holds no data segments)
+    pub fn raw(&self) -> &JifRaw {
+        &self.raw
+    }
+
+    /// Access the pheaders
+    pub fn pheaders(&self) -> &[JifRawPheader] {
+        self.raw.pheaders()
+    }
+
+    /// Access the ordering list
+    pub fn ord_chunks(&self) -> &[OrdChunk] {
+        self.raw.ord_chunks()
+    }
+
+    /// The page size of the JIF (see [`Jif::page_size`])
+    pub fn page_size(&self) -> usize {
+        self.raw.page_size()
+    }
+
+    /// The architecture the JIF was captured on (see [`Jif::arch`])
+    pub fn arch(&self) -> Arch {
+        self.raw.arch()
+    }
+
+    /// Access the string table
+    pub fn strings(&self) -> Vec<&str> {
+        self.raw.strings()
+    }
+
+    /// Resolve an address into the private data page backing it, reading it from the file
+    pub fn resolve_data(&self, addr: u64) -> JifResult<Option<Vec<u8>>> {
+        let page_addr = page_align_down(addr, self.page_size());
+        let ival = self
+            .raw
+            .pheaders
+            .iter()
+            .find(|phdr| phdr.vbegin <= addr && addr < phdr.vend)
+            .and_then(|phdr| {
+                self.data_intervals(phdr)
+                    .into_iter()
+                    .find(|ival| ival.start <= page_addr && page_addr < ival.end)
+            });
+
+        match ival {
+            Some(ival) => self
+                .read_page(ival.offset + (page_addr - ival.start))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Iterate over all the private pages, reading them from the file
+    ///
+    /// The pages are yielded in the same order as [`Jif::iter_private_pages`], and the iteration
+    /// ends with the first error
+    pub fn iter_private_pages(&self) -> impl Iterator<Item = JifResult<Vec<u8>>> + '_ {
+        self.raw
+            .pheaders
+            .iter()
+            .flat_map(|phdr| self.data_intervals(phdr))
+            .flat_map(|ival| (ival.offset..(ival.offset + ival.len())).step_by(self.page_size()))
+            .scan(false, |failed, offset| {
+                if *failed {
+                    return None;
+                }
+                let page = self.read_page(offset);
+                *failed = page.is_err();
+                Some(page)
+            })
+    }
+
+    /// The data intervals of a pheader, sorted by address
+    fn data_intervals(&self, phdr: &JifRawPheader) -> Vec<RawInterval> {
+        let mut ivals = phdr
+            .itree()
+            .map(|(idx, n)| {
+                self.raw.itree_nodes[idx as usize..(idx + n) as usize]
+                    .iter()
+                    .flat_map(|node| node.ranges.iter())
+                    .filter(|ival| ival.is_data())
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        ivals.sort_by_key(|ival| ival.start);
+        ivals
+    }
+
+    /// Read a page at a particular file offset
+    fn read_page(&self, offset: u64) -> JifResult<Vec<u8>> {
+        let mut r = self.reader.borrow_mut();
+
+        // seeking relatively keeps the buffer around, which helps with sequential reads
+        let cur = r.stream_position()?;
+        r.seek_relative(offset as i64 - cur as i64)?;
+
+        let mut page = vec![0u8; self.page_size()];
+        r.read_exact(&mut page)?;
+        Ok(page)
+    }
+}
+
 impl std::fmt::Debug for Jif {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Jif")
+            .field("arch", &self.arch.to_string())
             .field("pheaders", &self.pheaders)
             .field("ord", &self.ord_chunks)
+            .field("meta", &self.meta)
             .finish()
     }
 }
@@ -732,8 +1573,10 @@ impl std::fmt::Debug for JifRaw {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let strings = self.strings();
         f.debug_struct("Jif")
+            .field("arch", &self.arch.to_string())
             .field("pheaders", &self.pheaders)
             .field("strings", &strings)
+            .field("itree_fanout", &self.itree_fanout)
             .field("itrees", &self.itree_nodes)
             .field("ord", &self.ord_chunks)
             .field(
@@ -744,30 +1587,819 @@ impl std::fmt::Debug for JifRaw {
                     self.data_offset as usize + self.data_size()
                 ),
             )
+            .field("meta", &self.meta)
             .finish()
     }
 }
 
+impl<R> std::fmt::Debug for LazyJif<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyJif").field("raw", &self.raw).finish()
+    }
+}
+
+/// Sort the ordering chunks by kind (private, then zero, then shared), as they are written out
+/// (the sort is stable)
+pub(crate) fn sort_ord_chunks_by_kind(ord_chunks: &mut [OrdChunk]) {
+    ord_chunks.sort_by_key(|c| match c.kind {
+        DataSource::Zero => 1,
+        DataSource::Shared => 2,
+        DataSource::Private => 0,
+    });
+}
+
 #[cfg(test)]
 pub(crate) mod test {
     use super::*;
 
     use crate::itree::interval::{IntermediateInterval, IntermediateIntervalData};
     use crate::pheader::test::gen_pheader;
-    pub(crate) fn gen_jif(vaddrs: &[((u64, u64), &[(u64, u64)])]) -> Jif {
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+    pub(crate) type GenPheader<'a> = ((u64, u64), &'a [(u64, u64)]);
+
+    pub(crate) fn gen_jif(vaddrs: &[GenPheader]) -> Jif {
         Jif {
             pheaders: vaddrs
-                .into_iter()
+                .iter()
                 .map(|(range, ivals)| gen_pheader(*range, ivals))
                 .collect(),
             ord_chunks: vec![],
             deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
         }
     }
 
+    #[test]
+    fn lazy_matches_materialized() {
+        // every page gets distinct contents, so that misplaced reads are caught
+        let gen_ival = |start: u64, end: u64| Interval {
+            start,
+            end,
+            data: AnonIntervalData::Owned(
+                (start..end)
+                    .step_by(PAGE_SIZE)
+                    .flat_map(|addr| std::iter::repeat_n((addr >> 12) as u8, PAGE_SIZE))
+                    .collect(),
+            ),
+        };
+        let gen_phdr = |vaddr_range: (u64, u64), ivals: &[(u64, u64)]| JifPheader::Anonymous {
+            vaddr_range,
+            itree: ITree::build(
+                ivals.iter().map(|(s, e)| gen_ival(*s, *e)).collect(),
+                vaddr_range,
+            )
+            .unwrap(),
+            prot: crate::ProtFlags::READ,
+        };
+        let jif = Jif {
+            pheaders: vec![
+                gen_phdr((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                gen_phdr((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ],
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+
+        let lazy =
+            LazyJif::from_reader(BufReader::new(std::io::Cursor::new(buffer.clone()))).unwrap();
+        let jif = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+
+        let lazy_pages = lazy
+            .iter_private_pages()
+            .collect::<JifResult<Vec<_>>>()
+            .unwrap();
+        let pages = jif.iter_private_pages().collect::<Vec<_>>();
+        assert_eq!(lazy_pages, pages);
+
+        for addr in [0x1000, 0x2000, 0x4000, 0x5000, 0x12000, 0x13000, 0x20000] {
+            assert_eq!(
+                lazy.resolve_data(addr).unwrap().as_deref(),
+                jif.resolve_data(addr)
+            );
+        }
+
+        let materialized = lazy.materialize().unwrap();
+        assert_eq!(materialized.private_pages(), jif.private_pages());
+    }
+
+    #[test]
+    fn lazy_out_of_bounds() {
+        let jif = gen_jif(&[((0x10000, 0x14000), &[(0x11000, 0x13000)])]);
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+
+        // make the data interval span far past the end of the file
+        let raw = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&buffer))).unwrap();
+        let node_size = RawITreeNode::serialized_size(raw.itree_fanout);
+        let (node_idx, ival_idx) = raw
+            .itree_nodes
+            .iter()
+            .enumerate()
+            .find_map(|(node_idx, node)| {
+                let ival_idx = node.ranges.iter().position(|ival| ival.is_data())?;
+                Some((node_idx, ival_idx))
+            })
+            .unwrap();
+        let end_offset = raw.layout().itrees.0 as usize + node_idx * node_size + ival_idx * 24 + 8;
+        buffer[end_offset..end_offset + 8].copy_from_slice(&(0x11000u64 + (1 << 56)).to_le_bytes());
+
+        assert!(matches!(
+            LazyJif::from_reader(BufReader::new(std::io::Cursor::new(buffer))),
+            Err(JifError::DataSegmentNotFound {
+                virtual_range: (0x10000, 0x14000),
+                ..
+            })
+        ));
+    }
+
+    #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+    #[test]
+    fn mmap_matches_reader() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+
+        let path = std::env::temp_dir().join(format!("jif-mmap-test-{}.jif", std::process::id()));
+        std::fs::write(&path, &buffer).unwrap();
+        let mapped = Jif::from_mmap(&path);
+        std::fs::remove_file(&path).unwrap();
+        let mapped = mapped.unwrap();
+
+        let jif = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(
+            mapped.iter_private_pages().collect::<Vec<_>>(),
+            jif.iter_private_pages().collect::<Vec<_>>()
+        );
+        assert_eq!(mapped.private_pages(), jif.private_pages());
+
+        // the checksums are verified as well
+        let mut raw = JifRaw::from_materialized(jif, false);
+        raw.set_checksums(true);
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).unwrap();
+        let map = |buffer: &[u8]| {
+            std::fs::write(&path, buffer).unwrap();
+            let mapped = Jif::from_mmap(&path);
+            std::fs::remove_file(&path).unwrap();
+            mapped
+        };
+        assert!(map(&buffer).is_ok());
+        buffer[raw.data_offset as usize + 0x10] ^= 0xff;
+        assert!(matches!(
+            map(&buffer).unwrap_err().root(),
+            JifError::BadSegmentChecksum { .. }
+        ));
+    }
+
+    #[test]
+    fn from_bytes_matches_reader() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+
+        let from_bytes = Jif::from_bytes(&buffer).unwrap();
+        let jif = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(&buffer))).unwrap();
+        assert_eq!(
+            from_bytes.iter_private_pages().collect::<Vec<_>>(),
+            jif.iter_private_pages().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            JifRaw::from_bytes(&buffer).unwrap().pheaders().len(),
+            jif.pheaders().len()
+        );
+        assert!(Jif::from_bytes(&buffer[..0x10]).is_err());
+    }
+
+    #[test]
+    fn to_file_matches_writer() {
+        let path =
+            std::env::temp_dir().join(format!("jif-to-file-test-{}.jif", std::process::id()));
+        let mut written = Vec::new();
+        for checksums in [false, true] {
+            let jif = gen_jif(&[
+                ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ]);
+            let mut raw = JifRaw::from_materialized(jif, false);
+            raw.set_checksums(checksums);
+            let mut buffer = Vec::new();
+            raw.to_writer(&mut buffer).unwrap();
+
+            // a longer file is truncated
+            std::fs::write(&path, vec![0xff; buffer.len() * 2]).unwrap();
+            let file = File::options().write(true).open(&path).unwrap();
+            assert_eq!(raw.to_file(&file).unwrap(), buffer.len());
+            written.push((std::fs::read(&path).unwrap(), buffer));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        for (file, buffer) in written {
+            assert_eq!(file, buffer);
+        }
+    }
+
+    #[test]
+    fn compressed_roundtrip() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+
+        let mut raw =
+            JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&buffer))).unwrap();
+        raw.set_compression(Compression::Lz4);
+        let mut compressed = Vec::new();
+        raw.to_writer(&mut compressed).unwrap();
+        assert!(compressed.len() < buffer.len());
+
+        let raw =
+            JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&compressed))).unwrap();
+        assert_eq!(raw.compression(), Compression::Lz4);
+        assert!(matches!(
+            LazyJif::from_reader(BufReader::new(std::io::Cursor::new(&compressed))),
+            Err(JifError::CompressedDataSection)
+        ));
+
+        let compressed_jif = Jif::from_raw(raw).unwrap();
+        let jif = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(
+            compressed_jif.iter_private_pages().collect::<Vec<_>>(),
+            jif.iter_private_pages().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reproducible_output() {
+        let gen_jif_with_refs = || {
+            let mut jif = gen_jif(&[
+                ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ]);
+            jif.pheaders.extend((0..16u64).map(|idx| {
+                let vaddr_range = (0x100000 + idx * 0x10000, 0x104000 + idx * 0x10000);
+                JifPheader::Reference {
+                    vaddr_range,
+                    itree: ITree::single(
+                        (vaddr_range.0, vaddr_range.0 + 0x1000),
+                        RefIntervalData::Owned(vec![idx as u8; PAGE_SIZE]),
+                    ),
+                    prot: crate::ProtFlags::READ,
+                    ref_path: format!("/usr/lib/lib{}.so", idx),
+                    ref_offset: 0,
+                }
+            }));
+            jif.add_ordering_info(vec![
+                OrdChunk::new(0x11000, 1, DataSource::Private),
+                OrdChunk::new(0x1000, 2, DataSource::Private),
+            ])
+            .unwrap();
+            jif
+        };
+        let write = |jif: Jif, prefetch: bool| {
+            let mut buffer = Vec::new();
+            JifRaw::from_materialized(jif, prefetch)
+                .to_writer(&mut buffer)
+                .unwrap();
+            buffer
+        };
+
+        for prefetch in [false, true] {
+            let buffer = write(gen_jif_with_refs(), prefetch);
+            assert_eq!(write(gen_jif_with_refs(), prefetch), buffer);
+
+            // reading it back does not change the output either
+            let read =
+                Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(&buffer))).unwrap();
+            assert_eq!(write(read, prefetch), buffer);
+        }
+    }
+
+    #[test]
+    fn drop_pheaders() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ((0x20000, 0x24000), &[(0x20000, 0x21000)]),
+        ]);
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x1000, 2, DataSource::Private),
+            OrdChunk::new(0x11000, 1, DataSource::Private),
+            OrdChunk::new(0x20000, 1, DataSource::Private),
+        ])
+        .unwrap();
+
+        assert_eq!(jif.drop_pheaders((0x30000, 0x40000)), 0);
+        assert_eq!(jif.drop_pheaders((0x7000, 0x12000)), 2);
+        assert_eq!(jif.pheaders().len(), 1);
+        assert_eq!(jif.pheaders()[0].virtual_range(), (0x20000, 0x24000));
+        assert_eq!(jif.ord_chunks().len(), 1);
+        assert_eq!(jif.ord_chunks()[0].addr(), 0x20000);
+
+        let raw = JifRaw::from_materialized(jif, false);
+        assert_eq!(raw.data_size(), PAGE_SIZE);
+    }
+
+    /// A JIF (written out) whose pheaders share a data segment, the second pheader
+    /// `[0x10000; 0x13000)` holding `2 * PAGE_SIZE` B of data of its own
+    fn gen_shared_jif() -> Vec<u8> {
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x1000, 0x4000),
+                crate::ProtFlags::READ,
+                vec![(0x1000, vec![1; PAGE_SIZE]), (0x3000, vec![2; PAGE_SIZE])],
+            )
+            .add_anonymous_segment(
+                (0x10000, 0x13000),
+                crate::ProtFlags::READ,
+                vec![
+                    (0x10000, vec![1; PAGE_SIZE]),
+                    (0x11000, vec![3; 2 * PAGE_SIZE]),
+                ],
+            );
+        let mut buffer = Vec::new();
+        builder.build().unwrap().to_writer(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn gc() {
+        let buffer = gen_shared_jif();
+        let mut jif = Jif::from_bytes(&buffer).unwrap();
+        assert_eq!(jif.gc(), 0);
+        let unreferenced = |jif: &Jif| {
+            jif.validate()
+                .findings()
+                .iter()
+                .any(|finding| matches!(finding, crate::validate::Finding::UnreferencedData { .. }))
+        };
+
+        // the data shared with the dropped pheader is kept
+        jif.drop_pheaders((0x10000, 0x13000));
+        assert!(unreferenced(&jif));
+        assert_eq!(jif.gc(), 2 * PAGE_SIZE);
+        assert_eq!(jif.gc(), 0);
+        assert!(!unreferenced(&jif));
+        assert_eq!(jif.resolve_data(0x1000), Some(&[1; PAGE_SIZE][..]));
+        assert_eq!(jif.dedup_stats().n_tokens, 2);
+    }
+
+    #[test]
+    fn write_gc() {
+        let buffer = gen_shared_jif();
+        let edited = || {
+            let mut jif = Jif::from_bytes(&buffer).unwrap();
+            jif.drop_pheaders((0x10000, 0x13000));
+            jif
+        };
+
+        // writing out the JIF drops the unreferenced data, as collecting it first does (even
+        // skipping the collection on writing)
+        for (prefetch, gc) in [(false, false), (false, true), (true, false), (true, true)] {
+            let mut collected = edited();
+            assert_eq!(collected.gc(), 2 * PAGE_SIZE);
+            let write = |jif: Jif| {
+                let mut raw =
+                    JifRaw::from_materialized_with_gc(jif, prefetch, DataLayout::default(), gc);
+                raw.set_checksums(true);
+                let mut buffer = Vec::new();
+                raw.to_writer(&mut buffer).unwrap();
+                buffer
+            };
+            let written = write(edited());
+            assert_eq!(written, write(collected));
+
+            let jif = Jif::from_bytes(&written).unwrap();
+            assert!(jif.validate().is_clean());
+            assert_eq!(jif.dedup_stats().stored_bytes, 2 * PAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn keep_pheaders() {
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x1000, 0x3000),
+                ProtFlags::READ,
+                vec![(0x1000, vec![1; PAGE_SIZE])],
+            )
+            .add_reference_segment(
+                (0x10000, 0x13000),
+                ProtFlags::READ,
+                "/lib/libfoo.so".to_string(),
+                0,
+                vec![(0x11000, vec![2; 2 * PAGE_SIZE])],
+            )
+            .add_reference_segment(
+                (0x20000, 0x22000),
+                ProtFlags::READ,
+                "/lib/libbar.so".to_string(),
+                0,
+                vec![(0x20000, vec![3; PAGE_SIZE])],
+            );
+        let mut jif = builder.build().unwrap();
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x1000, 1, DataSource::Private),
+            OrdChunk::new(0x11000, 2, DataSource::Private),
+            OrdChunk::new(0x20000, 1, DataSource::Private),
+        ])
+        .unwrap();
+
+        assert_eq!(jif.keep_pheaders((0x12000, 0x12001)), 1);
+        assert_eq!(jif.pheaders()[0].virtual_range(), (0x10000, 0x13000));
+        assert_eq!(jif.ord_chunks().len(), 1);
+        assert_eq!(jif.ord_chunks()[0].addr(), 0x11000);
+
+        // only the string and data of the kept pheader are written out
+        let mut file = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut file)
+            .unwrap();
+        let raw = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&file))).unwrap();
+        assert_eq!(raw.strings(), vec!["/lib/libfoo.so"]);
+        assert_eq!(raw.data_size(), 2 * PAGE_SIZE);
+        let jif = Jif::from_raw(raw).unwrap();
+        assert!(jif.validate().is_clean(), "{}", jif.validate());
+    }
+
+    #[test]
+    fn path_usage_gc() {
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment((0x1000, 0x2000), ProtFlags::READ, vec![])
+            .add_reference_segment(
+                (0x10000, 0x12000),
+                ProtFlags::READ,
+                "/lib/libfoo.so".to_string(),
+                0,
+                vec![],
+            )
+            .add_reference_segment(
+                (0x20000, 0x22000),
+                ProtFlags::READ,
+                "/lib/libbar.so".to_string(),
+                0,
+                vec![],
+            )
+            .add_reference_segment(
+                (0x30000, 0x32000),
+                ProtFlags::READ,
+                "/lib/libfoo.so".to_string(),
+                0x2000,
+                vec![],
+            );
+        let mut jif = builder.build().unwrap();
+        assert_eq!(
+            jif.path_usage(),
+            BTreeMap::from([
+                ("/lib/libbar.so".to_string(), vec![2]),
+                ("/lib/libfoo.so".to_string(), vec![1, 3]),
+            ])
+        );
+
+        jif.rename_file("/lib/libbar.so", "/lib/libfoo.so");
+        assert_eq!(
+            jif.path_usage(),
+            BTreeMap::from([("/lib/libfoo.so".to_string(), vec![1, 2, 3])])
+        );
+
+        // only the referenced strings are written out
+        let raw = JifRaw::from_materialized(jif, false);
+        assert_eq!(raw.strings(), vec!["/lib/libfoo.so"]);
+        assert_eq!(raw.strings_size(), "/lib/libfoo.so".len() + 1);
+        assert!(raw.validate().is_clean());
+
+        let mut jif = Jif::from_raw(raw).unwrap();
+        jif.drop_pheaders((0x10000, 0x40000));
+        assert!(jif.path_usage().is_empty());
+        let raw = JifRaw::from_materialized(jif, false);
+        assert_eq!(raw.strings_size(), 0);
+        assert!(raw.validate().is_clean());
+    }
+
+    #[test]
+    fn clear_ordering() {
+        let vaddrs: &[GenPheader] = &[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ];
+        let write = |jif: Jif, prefetch: bool| {
+            let mut buffer = Vec::new();
+            JifRaw::from_materialized(jif, prefetch)
+                .to_writer(&mut buffer)
+                .unwrap();
+            buffer
+        };
+
+        let mut jif = gen_jif(vaddrs);
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x11000, 2, DataSource::Private),
+            OrdChunk::new(0x5000, 1, DataSource::Private),
+        ])
+        .unwrap();
+        let ordered = write(jif, true);
+        let raw = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&ordered))).unwrap();
+        assert_eq!(raw.n_prefetch, 3);
+
+        let mut jif = Jif::from_raw(raw).unwrap();
+        assert_eq!(jif.clear_ordering(), 2);
+        assert!(jif.ord_chunks().is_empty());
+        assert_eq!(jif.clear_ordering(), 0);
+
+        // same as if it had never been ordered
+        let stripped = write(jif, false);
+        assert_eq!(stripped, write(gen_jif(vaddrs), false));
+        let raw =
+            JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(&stripped))).unwrap();
+        assert_eq!(raw.n_prefetch, 0);
+    }
+
+    #[test]
+    fn edit_ord_chunks() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x1000, 6, DataSource::Private),
+            OrdChunk::new(0x11000, 2, DataSource::Private),
+        ])
+        .unwrap();
+
+        // unmapped chunks are rejected (and leave the ordering untouched)
+        assert!(matches!(
+            jif.insert_ord_chunk(0, OrdChunk::new(0x7000, 2, DataSource::Zero)),
+            Err(JifError::BadOrdChunk {
+                ord_chunk_idx: 0,
+                ord_chunk_err: OrdChunkError::UnmappedAddress(0x8000)
+            })
+        ));
+        assert!(jif
+            .add_ordering_info(vec![OrdChunk::new(0x9000, 1, DataSource::Zero)])
+            .is_err());
+        assert_eq!(jif.ord_chunks().len(), 2);
+
+        jif.insert_ord_chunk(usize::MAX, OrdChunk::new(0x13000, 1, DataSource::Zero))
+            .unwrap();
+        assert_eq!(jif.ord_chunks()[2].addr(), 0x13000);
+
+        // the first chunk is split around the removed range
+        assert_eq!(jif.remove_ord_range((0x2000, 0x3800)), 2);
+        assert_eq!(
+            jif.ord_chunks()
+                .iter()
+                .map(|chunk| (chunk.addr(), chunk.size()))
+                .collect::<Vec<_>>(),
+            vec![(0x1000, 1), (0x4000, 3), (0x11000, 2), (0x13000, 1)]
+        );
+
+        jif.reorder_ord_by(|chunk| std::cmp::Reverse(chunk.addr()));
+        assert_eq!(jif.ord_chunks()[0].addr(), 0x13000);
+
+        assert_eq!(
+            jif.ord_chunks_mut(|ord_chunks| ord_chunks.drain(..2).count())
+                .unwrap(),
+            2
+        );
+        assert_eq!(jif.ord_chunks().len(), 2);
+        assert!(
+            jif.ord_chunks_mut(
+                |ord_chunks| ord_chunks[0] = OrdChunk::new(0x20000, 1, DataSource::Zero)
+            )
+            .is_err()
+        );
+        assert_eq!(jif.ord_chunks()[0].addr(), 0x4000);
+    }
+
+    #[test]
+    fn unmapped_ord_chunks() {
+        let jif = gen_jif(&[((0x10000, 0x14000), &[(0x11000, 0x13000)])]);
+        let mut raw = JifRaw::from_materialized(jif, false);
+
+        // a chunk spanning the address space is found unmapped without walking its pages
+        raw.ord_chunks = vec![OrdChunk {
+            n_pages: 1 << 40,
+            ..OrdChunk::new(0x10000, 1, DataSource::Private)
+        }];
+        assert!(matches!(
+            Jif::from_raw(raw),
+            Err(JifError::BadOrdChunk {
+                ord_chunk_idx: 0,
+                ord_chunk_err: OrdChunkError::UnmappedAddress(0x14000)
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn read_range_stitches() {
+        let chroot =
+            std::env::temp_dir().join(format!("jif-read-range-test-{}", std::process::id()));
+        std::fs::create_dir_all(chroot.join("lib")).unwrap();
+        std::fs::write(chroot.join("lib/ref.bin"), vec![0x77u8; 0x3800]).unwrap();
+
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x1000, 0x3000),
+                ProtFlags::empty(),
+                vec![(0x2000, vec![0xaa; PAGE_SIZE])],
+            )
+            .add_reference_segment(
+                (0x3000, 0x6000),
+                ProtFlags::empty(),
+                "/lib/ref.bin".to_string(),
+                0x1000,
+                vec![(0x4000, vec![0xbb; PAGE_SIZE])],
+            );
+        let jif = builder.build().unwrap();
+
+        let data = jif.read_range(0x1800, 0x4400, &Some(chroot.clone()));
+        let unmapped = jif.read_range(0x5000, 0x2000, &Some(chroot.clone()));
+        std::fs::remove_dir_all(&chroot).unwrap();
+
+        let expected = [
+            (0x800, 0u8),
+            (0x1000, 0xaa),
+            (0x1000, 0x77),
+            (0x1000, 0xbb),
+            (0x800, 0x77), // the file ends mid page
+            (0x400, 0),
+        ]
+        .iter()
+        .flat_map(|(len, byte)| std::iter::repeat_n(*byte, *len))
+        .collect::<Vec<_>>();
+        assert_eq!(data.unwrap(), expected);
+        assert!(matches!(
+            unmapped,
+            Err(JifError::UnmappedAddress { addr: 0x6000 })
+        ));
+    }
+
+    #[test]
+    fn mapping_pheader_lookup() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000)]),
+            ((0x8000, 0x9000), &[]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+
+        let lookup = |addr| jif.mapping_pheader(addr).map(|p| p.virtual_range());
+        assert_eq!(lookup(0x0), None);
+        assert_eq!(lookup(0x1000), Some((0x1000, 0x8000)));
+        assert_eq!(lookup(0x7fff), Some((0x1000, 0x8000)));
+        assert_eq!(lookup(0x8000), Some((0x8000, 0x9000)));
+        assert_eq!(lookup(0x9000), None);
+        assert_eq!(lookup(0x13fff), Some((0x10000, 0x14000)));
+        assert_eq!(lookup(0x14000), None);
+        assert_eq!(jif.mapping_pheader_idx(0x12000), Some(2));
+
+        // pheaders read in any order are sorted
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+        let mut raw =
+            JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        raw.pheaders.reverse();
+        let jif = Jif::from_raw(raw).unwrap();
+        assert_eq!(jif.mapping_pheader_idx(0x1000), Some(0));
+        assert_eq!(jif.resolve_data(0x11000), Some(&[42u8; PAGE_SIZE][..]));
+    }
+
+    #[test]
+    fn merge_jifs() {
+        // read the JIFs back, so that their data is deduplicated
+        let roundtrip = |jif: Jif| {
+            let mut buffer = Vec::new();
+            jif.to_writer(&mut buffer).unwrap();
+            Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap()
+        };
+
+        let mut jif = roundtrip(gen_jif(&[((0x10000, 0x14000), &[(0x11000, 0x13000)])]));
+        let mut other = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000)]),
+            ((0x20000, 0x24000), &[(0x20000, 0x21000)]),
+        ]);
+        other
+            .add_ordering_info(vec![OrdChunk::new(0x20000, 1, DataSource::Private)])
+            .unwrap();
+        let other = roundtrip(other);
+
+        let overlapping = gen_jif(&[((0x13000, 0x15000), &[])]);
+        assert!(matches!(
+            jif.merge(overlapping),
+            Err(JifError::OverlappingPheaders {
+                pheader_1: (0x10000, 0x14000),
+                pheader_2: (0x13000, 0x15000)
+            })
+        ));
+        assert_eq!(jif.pheaders().len(), 1);
+
+        jif.merge(other).unwrap();
+        assert_eq!(
+            jif.pheaders()
+                .iter()
+                .map(|pheader| pheader.virtual_range())
+                .collect::<Vec<_>>(),
+            vec![(0x1000, 0x8000), (0x10000, 0x14000), (0x20000, 0x24000)]
+        );
+        assert_eq!(jif.private_pages(), 5);
+        assert_eq!(jif.ord_chunks().len(), 1);
+        assert_eq!(jif.resolve_data(0x2000), Some(&[42u8; PAGE_SIZE][..]));
+
+        let merged = roundtrip(jif);
+        assert_eq!(merged.private_pages(), 5);
+        assert_eq!(merged.resolve_data(0x20000), Some(&[42u8; PAGE_SIZE][..]));
+    }
+
+    #[test]
+    fn checksums_roundtrip() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+
+        let mut raw = JifRaw::from_materialized(jif, false);
+        raw.set_checksums(true);
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).unwrap();
+
+        let read =
+            |buffer: &[u8]| JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer)));
+        let checked = read(&buffer).unwrap();
+        assert!(checked.checksums());
+        assert_eq!(checked.data_size(), raw.data_size());
+
+        // compressed data sections are covered as well
+        let mut compressed_raw = read(&buffer).unwrap();
+        compressed_raw.set_compression(Compression::Lz4);
+        let mut compressed = Vec::new();
+        compressed_raw.to_writer(&mut compressed).unwrap();
+        assert_eq!(read(&compressed).unwrap().data_size(), raw.data_size());
+
+        // corrupt a private page
+        let mut corrupted = buffer.clone();
+        corrupted[checked.data_offset as usize + 0x10] ^= 0xff;
+        let err = read(&corrupted).unwrap_err();
+        assert!(matches!(err.root(), JifError::BadSegmentChecksum { .. }));
+        let (section, (start, end)) = err.file_range().unwrap();
+        assert_eq!(section, JifSection::Data);
+        assert!(start <= checked.data_offset + 0x10 && checked.data_offset + 0x10 < end);
+
+        // corrupt the metadata (the prefetch count, which still parses)
+        let mut corrupted = buffer.clone();
+        corrupted[std::mem::size_of::<JifHeaderBinary>() - 1] ^= 0xff;
+        assert!(matches!(
+            read(&corrupted).unwrap_err().root(),
+            JifError::BadFileChecksum { .. }
+        ));
+
+        // corrupt the first pheader (its virtual range)
+        let mut corrupted = buffer.clone();
+        let pheader_offset = std::mem::size_of::<JifHeaderBinary>();
+        corrupted[pheader_offset..pheader_offset + 16].fill(0xff);
+        let err = read(&corrupted).unwrap_err();
+        assert!(matches!(
+            err.root(),
+            JifError::BadPheader { pheader_idx: 0, .. }
+        ));
+        assert_eq!(
+            err.file_range(),
+            Some((
+                JifSection::Pheaders,
+                (
+                    pheader_offset as u64,
+                    (pheader_offset + JifRawPheader::serialized_size()) as u64
+                )
+            ))
+        );
+
+        // truncated
+        assert!(read(&buffer[..buffer.len() - 4]).is_err());
+    }
+
     #[test]
     fn test_order_segments_empty() {
-        let (token_map, itree_nodes, _n_prefetch) = JifRaw::order_data_segments(vec![], &[], 0);
+        let (token_map, itree_nodes, _n_prefetch) =
+            JifRaw::order_data_segments(vec![], &[], 0, PAGE_SIZE, &DataLayout::default());
         assert!(token_map.is_empty());
         assert!(itree_nodes.is_empty());
     }
@@ -819,41 +2451,22 @@ pub(crate) mod test {
 
         // 2: create some ordering segments (make sure they aren't bad)
         let ord_chunks = [
-            OrdChunk {
-                vaddr: 0x10000,
-                n_pages: 1,
-                kind: DataSource::Zero,
-            },
-            OrdChunk {
-                vaddr: 0x7000,
-                n_pages: 1,
-                kind: DataSource::Zero,
-            },
-            OrdChunk {
-                vaddr: 0x8000,
-                n_pages: 1,
-                kind: DataSource::Zero,
-            },
-            OrdChunk {
-                vaddr: 0x6000,
-                n_pages: 1,
-                kind: DataSource::Zero,
-            },
-            OrdChunk {
-                vaddr: 0x3000,
-                n_pages: 2,
-                kind: DataSource::Zero,
-            },
-            OrdChunk {
-                vaddr: 0x1000,
-                n_pages: 1,
-                kind: DataSource::Zero,
-            },
+            OrdChunk::new(0x10000, 1, DataSource::Zero),
+            OrdChunk::new(0x7000, 1, DataSource::Zero),
+            OrdChunk::new(0x8000, 1, DataSource::Zero),
+            OrdChunk::new(0x6000, 1, DataSource::Zero),
+            OrdChunk::new(0x3000, 2, DataSource::Zero),
+            OrdChunk::new(0x1000, 1, DataSource::Zero),
         ];
 
         // 3: call order_data_segments
-        let (token_map, itree_nodes, _n_prefetch) =
-            JifRaw::order_data_segments(intermediate_nodes, &ord_chunks, 0);
+        let (token_map, itree_nodes, _n_prefetch) = JifRaw::order_data_segments(
+            intermediate_nodes,
+            &ord_chunks,
+            0,
+            PAGE_SIZE,
+            &DataLayout::default(),
+        );
 
         // 4: check order
         assert_eq!(token_map.get(&token1), Some(&(0x1000, 0x3000)));
@@ -900,4 +2513,83 @@ pub(crate) mod test {
             ]
         );
     }
+
+    #[test]
+    fn dedup_stats() {
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x1000, 0x4000),
+                crate::ProtFlags::READ,
+                vec![(0x1000, vec![1; PAGE_SIZE]), (0x3000, vec![2; PAGE_SIZE])],
+            )
+            .add_anonymous_segment(
+                (0x10000, 0x12000),
+                crate::ProtFlags::READ,
+                vec![(0x10000, vec![1; PAGE_SIZE])],
+            );
+        let jif = builder.build().unwrap();
+
+        // owned data is not in the deduper yet
+        assert_eq!(jif.dedup_stats(), DedupStats::default());
+
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let jif = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        let stats = jif.dedup_stats();
+        assert_eq!(stats.n_tokens, 2);
+        assert_eq!(stats.n_references, 3);
+        assert_eq!(stats.stored_bytes, 2 * PAGE_SIZE);
+        assert_eq!(stats.saved_bytes(), PAGE_SIZE);
+        assert_eq!(stats.size_histogram, BTreeMap::from([(PAGE_SIZE, 2)]));
+    }
+
+    #[test]
+    fn corrupt_inputs() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+        jif.ord_chunks = vec![
+            OrdChunk::new(0x11000, 1, DataSource::Private),
+            OrdChunk::new(0x1000, 2, DataSource::Private),
+        ];
+        let mut valid = Vec::new();
+        JifRaw::from_materialized(jif, true)
+            .to_writer(&mut valid)
+            .unwrap();
+
+        // corrupt or truncated files are rejected (or read), but never crash the reader
+        let read = |bytes: Vec<u8>| {
+            let Ok(raw) = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(bytes)))
+            else {
+                return;
+            };
+            raw.validate();
+            if let Ok(jif) = Jif::from_raw(raw) {
+                jif.validate();
+                for addr in (0..0x14000).step_by(PAGE_SIZE) {
+                    jif.resolve_data(addr);
+                }
+                JifRaw::from_materialized(jif, true);
+            }
+        };
+        for len in (0..valid.len()).step_by(64) {
+            read(valid[..len].to_vec());
+        }
+        let data_offset =
+            JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(valid.clone())))
+                .unwrap()
+                .data_offset as usize;
+        // every section is small: the rest of its page is padding
+        for idx in (0..data_offset).filter(|idx| idx % PAGE_SIZE < 0x100) {
+            for byte in [0x01, 0x10, 0x80, 0xff] {
+                let mut corrupt = valid.clone();
+                corrupt[idx] ^= byte;
+                read(corrupt);
+            }
+        }
+    }
 }
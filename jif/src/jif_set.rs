@@ -0,0 +1,269 @@
+//! Sets of JIFs sharing their data
+//!
+//! Snapshots of the same application tend to hold many identical data segments. Opening each
+//! of them as a [`Jif`] keeps a copy per file: a [`JifSet`] reads them (in parallel) into a single
+//! [`Deduper`], holding every distinct segment once and telling how much of the data the files
+//! share (see [`JifSet::sharing`])
+
+use crate::arch::Arch;
+use crate::deduper::{DedupToken, Deduper};
+use crate::error::*;
+use crate::jif::{Jif, JifRaw};
+use crate::ord::OrdChunk;
+use crate::pheader::JifPheader;
+use crate::utils::{open_file, par_map};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// A JIF of the set, whose data lives in the deduper of the set
+struct Member {
+    path: PathBuf,
+    pheaders: Vec<JifPheader>,
+    ord_chunks: Vec<OrdChunk>,
+    arch: Arch,
+    meta: BTreeMap<String, String>,
+
+    /// Distinct tokens of the private data
+    tokens: BTreeSet<DedupToken>,
+}
+
+/// Several materialized JIFs, sharing a [`Deduper`]
+pub struct JifSet {
+    members: Vec<Member>,
+    deduper: Deduper,
+}
+
+/// How much of the private data the JIFs of a [`JifSet`] share (see [`JifSet::sharing`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SetSharing {
+    /// Number of JIFs
+    pub n_jifs: usize,
+
+    /// Bytes held by the JIFs on their own (i.e., deduplicated in each JIF, but not across them)
+    pub jif_bytes: usize,
+
+    /// Bytes held by the set
+    pub stored_bytes: usize,
+
+    /// Bytes of the data segments referenced by exactly that many JIFs
+    pub bytes_by_n_jifs: BTreeMap<usize, usize>,
+}
+
+impl SetSharing {
+    /// Bytes which are not stored thanks to the deduplication across the JIFs
+    pub fn saved_bytes(&self) -> usize {
+        self.jif_bytes - self.stored_bytes
+    }
+}
+
+impl std::fmt::Display for SetSharing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} JIFs", self.n_jifs)?;
+        writeln!(f, "separately: {:#x} B", self.jif_bytes)?;
+        writeln!(f, "stored:     {:#x} B", self.stored_bytes)?;
+        writeln!(f, "saved:      {:#x} B", self.saved_bytes())?;
+        writeln!(f, "shared by:")?;
+        for (n_jifs, bytes) in &self.bytes_by_n_jifs {
+            writeln!(f, "  {} JIFs: {:#x} B", n_jifs, bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl JifSet {
+    /// Read the JIFs at `paths` (in parallel), deduplicating their data across the files
+    ///
+    /// The JIFs keep the order of the paths. Errors name the file they come from (see
+    /// [`JifError::InFile`])
+    pub fn open<P: AsRef<Path> + Sync>(paths: &[P]) -> JifResult<Self> {
+        let read = |path: &Path| {
+            let raw = JifRaw::from_reader(&mut BufReader::new(open_file(path)?))?;
+            if raw.delta {
+                return Err(JifError::DeltaWithoutBase);
+            }
+            Ok(raw)
+        };
+        let raws = par_map(paths, |path| {
+            read(path.as_ref()).map_err(|error| JifError::InFile {
+                path: path.as_ref().to_path_buf(),
+                error: Box::new(error),
+            })
+        });
+
+        let mut deduper = Deduper::default();
+        let mut members = Vec::with_capacity(raws.len());
+        for (path, raw) in paths.iter().zip(raws) {
+            let mut raw = raw?;
+            let offset_index = deduper.insert_data_map(raw.take_data());
+            let pheaders =
+                Jif::materialize_pheaders(&mut raw, &deduper, &offset_index).map_err(|error| {
+                    JifError::InFile {
+                        path: path.as_ref().to_path_buf(),
+                        error: Box::new(error),
+                    }
+                })?;
+
+            members.push(Member {
+                path: path.as_ref().to_path_buf(),
+                tokens: pheaders.iter().flat_map(JifPheader::dedup_tokens).collect(),
+                pheaders,
+                ord_chunks: raw.ord_chunks,
+                arch: raw.arch,
+                meta: raw.meta,
+            });
+        }
+
+        Ok(JifSet { members, deduper })
+    }
+
+    /// Number of JIFs in the set
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the set holds no JIF
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Path of the `idx`-th JIF
+    pub fn path(&self, idx: usize) -> &Path {
+        &self.members[idx].path
+    }
+
+    /// Pheaders of the `idx`-th JIF
+    pub fn pheaders(&self, idx: usize) -> &[JifPheader] {
+        &self.members[idx].pheaders
+    }
+
+    /// Ordering chunks of the `idx`-th JIF
+    pub fn ord_chunks(&self, idx: usize) -> &[OrdChunk] {
+        &self.members[idx].ord_chunks
+    }
+
+    /// Architecture of the `idx`-th JIF
+    pub fn arch(&self, idx: usize) -> Arch {
+        self.members[idx].arch
+    }
+
+    /// The deduper holding the data of every JIF
+    pub fn deduper(&self) -> &Deduper {
+        &self.deduper
+    }
+
+    /// Bytes of private data which both the `a`-th and the `b`-th JIF hold
+    pub fn shared_bytes(&self, a: usize, b: usize) -> usize {
+        self.members[a]
+            .tokens
+            .intersection(&self.members[b].tokens)
+            .map(|token| self.deduper.get(*token).len())
+            .sum()
+    }
+
+    /// How much of the private data the JIFs share
+    pub fn sharing(&self) -> SetSharing {
+        let mut n_jifs = BTreeMap::<DedupToken, usize>::new();
+        for token in self.members.iter().flat_map(|member| member.tokens.iter()) {
+            *n_jifs.entry(*token).or_default() += 1;
+        }
+
+        let mut sharing = SetSharing {
+            n_jifs: self.members.len(),
+            ..Default::default()
+        };
+        for (token, n_jifs) in n_jifs {
+            let len = self.deduper.get(token).len();
+            sharing.jif_bytes += n_jifs * len;
+            sharing.stored_bytes += len;
+            *sharing.bytes_by_n_jifs.entry(n_jifs).or_default() += len;
+        }
+
+        sharing
+    }
+
+    /// Split the set into standalone JIFs (copying the data they share)
+    pub fn into_jifs(self) -> Vec<Jif> {
+        let JifSet { members, deduper } = self;
+        members
+            .into_iter()
+            .map(|member| {
+                let mut jif_deduper = Deduper::default();
+                let mut pheaders = member.pheaders;
+                for pheader in pheaders.iter_mut() {
+                    pheader.move_data(&deduper, &mut jif_deduper);
+                }
+
+                Jif {
+                    pheaders,
+                    ord_chunks: member.ord_chunks,
+                    deduper: jif_deduper,
+                    arch: member.arch,
+                    meta: member.meta,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod test {
+    use super::*;
+    use crate::builder::JifBuilder;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn jif_set() {
+        let dir = std::env::temp_dir().join(format!("jif-set-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // a common page, and one page of each JIF
+        let paths = (0..3u8)
+            .map(|idx| {
+                let mut builder = JifBuilder::new();
+                builder
+                    .add_anonymous_segment(
+                        (0x1000, 0x2000),
+                        ProtFlags::READ,
+                        vec![(0x1000, vec![0xaa; PAGE_SIZE])],
+                    )
+                    .add_anonymous_segment(
+                        (0x10000, 0x11000),
+                        ProtFlags::READ,
+                        vec![(0x10000, vec![idx; PAGE_SIZE])],
+                    );
+                let path = dir.join(format!("{}.jif", idx));
+                let mut file = std::fs::File::create(&path).unwrap();
+                builder.build().unwrap().to_writer(&mut file).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+
+        let set = JifSet::open(&paths).unwrap();
+        let missing = JifSet::open(&[dir.join("missing.jif")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(missing, Err(JifError::InFile { .. })));
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.path(2), paths[2]);
+        assert_eq!(set.shared_bytes(0, 1), PAGE_SIZE);
+        assert_eq!(set.shared_bytes(1, 1), 2 * PAGE_SIZE);
+        assert_eq!(
+            set.sharing(),
+            SetSharing {
+                n_jifs: 3,
+                jif_bytes: 6 * PAGE_SIZE,
+                stored_bytes: 4 * PAGE_SIZE,
+                bytes_by_n_jifs: BTreeMap::from([(1, 3 * PAGE_SIZE), (3, PAGE_SIZE)]),
+            }
+        );
+        assert_eq!(set.sharing().saved_bytes(), 2 * PAGE_SIZE);
+
+        let jifs = set.into_jifs();
+        assert_eq!(jifs[2].resolve_data(0x10000), Some(&[2; PAGE_SIZE][..]));
+        assert_eq!(jifs[0].dedup_stats().stored_bytes, 2 * PAGE_SIZE);
+    }
+}
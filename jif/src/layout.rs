@@ -0,0 +1,553 @@
+//! File layout
+//!
+//! Where each section of a JIF lands in the file, as [`JifRaw::to_writer`] lays it out: the
+//! header and the pheaders share the first pages, followed by the strings, the interval tree
+//! nodes and the ordering section (each padded to the page size), the data section (starting at
+//! the data offset) and, optionally, the page hash index, the custom metadata and integrity
+//! sections
+//!
+//! The data segments are packed, unless a [`DataLayout`] aligns them to the blocks of the
+//! storage device (see [`JifRaw::from_materialized_with_layout`]): the restore time prefetcher
+//! then reads each segment in as few blocks as it spans
+
+use crate::compress::{compress_blocks, Compression};
+use crate::error::*;
+use crate::integrity::IntegrityTrailer;
+use crate::jif::{JifHeaderBinary, JifRaw};
+use crate::meta::meta_size;
+use crate::pheader::JifRawPheader;
+
+use std::collections::BTreeMap;
+
+/// Byte ranges (`[start; end)`) of the sections of a JIF file (see [`JifRaw::layout`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LayoutMap {
+    /// File header
+    pub header: (u64, u64),
+
+    /// Pheader table (up to the padding before the strings)
+    pub pheaders: (u64, u64),
+
+    /// String table
+    pub strings: (u64, u64),
+
+    /// Interval tree nodes
+    pub itrees: (u64, u64),
+
+    /// Ordering section (padded up to the data offset)
+    pub ord: (u64, u64),
+
+    /// Data section, as stored (i.e., compressed, if it is)
+    pub data: (u64, u64),
+
+    /// Data segments, in file order
+    ///
+    /// When the data section is compressed, their ranges are offsets into the decompressed data
+    /// section (which also starts at the data offset)
+    pub segments: Vec<SegmentLayout>,
+
+    /// Page hash index, if any (see [`crate::page_index`])
+    pub page_index: Option<(u64, u64)>,
+
+    /// Custom metadata section, if any (see [`crate::meta`])
+    pub meta: Option<(u64, u64)>,
+
+    /// Integrity section, if any
+    pub integrity: Option<(u64, u64)>,
+
+    /// Whether the data section is compressed
+    pub compressed: bool,
+}
+
+/// A data segment and the intervals whose private data it stores (more than one when
+/// deduplicated)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SegmentLayout {
+    /// Byte range of the segment
+    pub range: (u64, u64),
+
+    /// Intervals backed by the segment
+    pub owners: Vec<SegmentOwner>,
+}
+
+/// An interval backed by a data segment
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentOwner {
+    /// Index of the pheader
+    pub pheader_idx: usize,
+
+    /// Virtual address range of the interval
+    pub virtual_range: (u64, u64),
+}
+
+impl LayoutMap {
+    /// Size of the file
+    pub fn file_size(&self) -> u64 {
+        self.integrity
+            .or(self.meta)
+            .or(self.page_index)
+            .map_or(self.data.1, |(_start, end)| end)
+    }
+
+    /// Bytes of padding between the data segments (and before the first one)
+    pub fn padding(&self) -> u64 {
+        let mut cursor = self.data.0;
+        let mut padding = 0;
+        for segment in &self.segments {
+            padding += segment.range.0.saturating_sub(cursor);
+            cursor = std::cmp::max(cursor, segment.range.1);
+        }
+
+        padding
+    }
+
+    /// Number of data segments preceded by padding, and their alignment (the largest power of
+    /// two their offsets are a multiple of)
+    pub fn padded_segments(&self) -> (usize, u64) {
+        let mut cursor = self.data.0;
+        let (mut n_padded, mut alignment) = (0, 0);
+        for segment in &self.segments {
+            if segment.range.0 > cursor {
+                n_padded += 1;
+                alignment |= segment.range.0;
+            }
+            cursor = std::cmp::max(cursor, segment.range.1);
+        }
+
+        if n_padded == 0 {
+            return (0, 0);
+        }
+        (n_padded, 1 << alignment.trailing_zeros())
+    }
+}
+
+/// Default alignment of the data segments, in B (see [`DataLayout`])
+pub const DEFAULT_DATA_ALIGNMENT: u64 = 0x10000;
+
+/// Which data segments a [`DataLayout`] aligns
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentPadding {
+    /// The segments are packed
+    #[default]
+    None,
+
+    /// The segments of the ordering chunks (i.e., the ones read by the prefetcher)
+    Ordered,
+
+    /// Every segment
+    All,
+}
+
+/// Layout of the data section: where the data segments are placed when writing a materialized
+/// JIF out (see [`JifRaw::from_materialized_with_layout`])
+///
+/// Aligned segments start at a multiple of the alignment in the file, with zeros padding the
+/// gaps before them. A segment which would need more padding than the maximum gap is not
+/// aligned (i.e., small segments do not blow the data section up)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataLayout {
+    alignment: u64,
+    padding: SegmentPadding,
+    max_gap: u64,
+}
+
+impl Default for DataLayout {
+    fn default() -> Self {
+        DataLayout {
+            alignment: DEFAULT_DATA_ALIGNMENT,
+            padding: SegmentPadding::None,
+            max_gap: u64::MAX,
+        }
+    }
+}
+
+impl DataLayout {
+    /// Align the `padding` segments to `alignment` B (a power of two: alignments under the
+    /// page size align to the page)
+    pub fn new(alignment: u64, padding: SegmentPadding) -> JifResult<Self> {
+        if !alignment.is_power_of_two() {
+            return Err(JifError::BadDataAlignment { alignment });
+        }
+
+        Ok(DataLayout {
+            alignment,
+            padding,
+            max_gap: u64::MAX,
+        })
+    }
+
+    /// Do not align the segments which would need more than `max_gap` B of padding
+    pub fn with_max_gap(mut self, max_gap: u64) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Alignment of the padded segments, in B
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    /// Which segments are aligned
+    pub fn padding(&self) -> SegmentPadding {
+        self.padding
+    }
+
+    /// Largest padding before a segment, in B
+    pub fn max_gap(&self) -> u64 {
+        self.max_gap
+    }
+
+    /// Alignment which the layout keeps the data offset at (so that moving the data section
+    /// keeps the segments aligned)
+    pub(crate) fn data_offset_alignment(&self, page_size: usize) -> u64 {
+        match self.padding {
+            SegmentPadding::None => page_size as u64,
+            _ => std::cmp::max(self.alignment, page_size as u64),
+        }
+    }
+
+    /// Offset in the file of a new segment, with the data section written up to `cursor`
+    /// (`ordered` if the segment is in the ordering chunks)
+    pub(crate) fn place(&self, cursor: u64, ordered: bool, page_size: usize) -> u64 {
+        let aligned = match self.padding {
+            SegmentPadding::None => return cursor,
+            SegmentPadding::Ordered if !ordered => return cursor,
+            _ => cursor.next_multiple_of(std::cmp::max(self.alignment, page_size as u64)),
+        };
+
+        if aligned - cursor > self.max_gap {
+            cursor
+        } else {
+            aligned
+        }
+    }
+}
+
+impl std::fmt::Display for DataLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.padding {
+            SegmentPadding::None => f.write_str("packed"),
+            padding => {
+                f.write_fmt(format_args!(
+                    "{} segments aligned to {:#x} B",
+                    if padding == SegmentPadding::All {
+                        "all"
+                    } else {
+                        "ordered"
+                    },
+                    self.alignment
+                ))?;
+                if self.max_gap != u64::MAX {
+                    f.write_fmt(format_args!(" (up to {:#x} B of padding)", self.max_gap))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl JifRaw {
+    /// The data segments, in file order, with the intervals they back
+    pub(crate) fn segment_layouts(&self) -> Vec<SegmentLayout> {
+        let mut segments = BTreeMap::<(u64, u64), Vec<SegmentOwner>>::new();
+        for (pheader_idx, pheader) in self.pheaders.iter().enumerate() {
+            let nodes = pheader.itree_idx as usize
+                ..(pheader.itree_idx as usize + pheader.itree_n_nodes as usize);
+            for ival in self
+                .itree_nodes
+                .get(nodes)
+                .unwrap_or_default()
+                .iter()
+                .flat_map(|node| node.ranges.iter())
+                .filter(|ival| ival.is_data())
+            {
+                segments
+                    .entry((ival.offset, ival.offset + ival.len()))
+                    .or_default()
+                    .push(SegmentOwner {
+                        pheader_idx,
+                        virtual_range: (ival.start, ival.end),
+                    });
+            }
+        }
+
+        segments
+            .into_iter()
+            .map(|(range, owners)| SegmentLayout { range, owners })
+            .collect()
+    }
+
+    /// Map out the byte ranges of the sections of the JIF file (as it is written by
+    /// [`JifRaw::to_writer`])
+    ///
+    /// The data segments are the ones referenced by the interval trees: the data of a delta JIF
+    /// which lives in its base is not included
+    pub fn layout(&self) -> LayoutMap {
+        let sizes = self.metadata_sizes();
+        let header_end = std::mem::size_of::<JifHeaderBinary>() as u64;
+        let pheaders_end = sizes.ord_offset - sizes.itrees - sizes.strings;
+        let strings_end = pheaders_end + sizes.strings;
+        let itrees_end = strings_end + sizes.itrees;
+
+        let segments = self.segment_layouts();
+
+        let data_size = match self.compression {
+            // up to the end of the last segment (the layout may pad the segments)
+            Compression::None => self
+                .data_segments
+                .keys()
+                .last()
+                .map_or(0, |(_start, end)| *end),
+            Compression::Lz4 => {
+                let mut data = Vec::new();
+                self.write_data_segments(&mut data, self.data_offset as usize)
+                    .expect("writing to memory does not fail");
+                compress_blocks(&data).len() as u64
+            }
+        };
+        let data = (self.data_offset, self.data_offset + data_size);
+        let page_index = self
+            .page_index
+            .as_ref()
+            .map(|index| (data.1, data.1 + index.serialized_size()));
+        let meta_offset = page_index.map_or(data.1, |(_start, end)| end);
+        let meta =
+            (!self.meta.is_empty()).then(|| (meta_offset, meta_offset + meta_size(&self.meta)));
+        let integrity = self.checksums.then(|| {
+            let offset = meta.map_or(meta_offset, |(_start, end)| end);
+            let trailer = IntegrityTrailer {
+                segment_crcs: vec![0; self.data_segments.len()],
+                file_crc: 0,
+                offset,
+            };
+            (offset, offset + trailer.serialized_size() as u64)
+        });
+
+        LayoutMap {
+            header: (0, header_end),
+            pheaders: (
+                header_end,
+                header_end + (self.pheaders.len() * JifRawPheader::serialized_size()) as u64,
+            ),
+            strings: (pheaders_end, strings_end),
+            itrees: (strings_end, itrees_end),
+            ord: (sizes.ord_offset, sizes.ord_offset + sizes.ord),
+            data,
+            segments,
+            page_index,
+            meta,
+            integrity,
+            compressed: self.compression != Compression::None,
+        }
+    }
+}
+
+impl std::fmt::Display for LayoutMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let section = |f: &mut std::fmt::Formatter<'_>, name: &str, (start, end): (u64, u64)| {
+            f.write_fmt(format_args!(
+                "{:<10} [{:#x}; {:#x}) ({:#x} B)\n",
+                name,
+                start,
+                end,
+                end - start
+            ))
+        };
+
+        section(f, "header", self.header)?;
+        section(f, "pheaders", self.pheaders)?;
+        section(f, "strings", self.strings)?;
+        section(f, "itrees", self.itrees)?;
+        section(f, "ord", self.ord)?;
+        section(f, "data", self.data)?;
+        if self.compressed {
+            f.write_str("  (compressed: the segments are in the decompressed data section)\n")?;
+        }
+        let (n_padded, alignment) = self.padded_segments();
+        if n_padded > 0 {
+            f.write_fmt(format_args!(
+                "  (padding: {:#x} B, before {} segments aligned to {:#x} B)\n",
+                self.padding(),
+                n_padded,
+                alignment
+            ))?;
+        }
+        for segment in &self.segments {
+            f.write_fmt(format_args!(
+                "  segment [{:#x}; {:#x}) ({:#x} B):",
+                segment.range.0,
+                segment.range.1,
+                segment.range.1 - segment.range.0
+            ))?;
+            for owner in &segment.owners {
+                f.write_fmt(format_args!(
+                    " pheader {} [{:#x}; {:#x})",
+                    owner.pheader_idx, owner.virtual_range.0, owner.virtual_range.1
+                ))?;
+            }
+            f.write_str("\n")?;
+        }
+        if let Some(page_index) = self.page_index {
+            section(f, "page index", page_index)?;
+        }
+        if let Some(meta) = self.meta {
+            section(f, "meta", meta)?;
+        }
+        if let Some(integrity) = self.integrity {
+            section(f, "integrity", integrity)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::jif::test::gen_jif;
+    use crate::jif::Jif;
+    use crate::ord::OrdChunk;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn data_layout() {
+        let jif = || {
+            let mut builder = crate::JifBuilder::new();
+            for idx in 0..4u8 {
+                let start = 0x100000 * (idx as u64 + 1);
+                builder.add_anonymous_segment(
+                    (start, start + 0x2000),
+                    ProtFlags::READ,
+                    vec![(
+                        start,
+                        [vec![2 * idx; PAGE_SIZE], vec![2 * idx + 1; PAGE_SIZE]].concat(),
+                    )],
+                );
+            }
+            let mut jif = builder.build().unwrap();
+            jif.add_ordering_info(vec![
+                OrdChunk::new(0x300000, 2, DataSource::Private),
+                OrdChunk::new(0x100000, 1, DataSource::Private),
+            ])
+            .unwrap();
+            jif
+        };
+        assert!(matches!(
+            DataLayout::new(0x3000, SegmentPadding::All),
+            Err(JifError::BadDataAlignment { alignment: 0x3000 })
+        ));
+
+        let layout = DataLayout::new(0x10000, SegmentPadding::Ordered).unwrap();
+        let raw = JifRaw::from_materialized_with_layout(jif(), true, layout);
+        assert_eq!(raw.data_layout(), layout);
+        let mut file = Vec::new();
+        raw.to_writer(&mut file).unwrap();
+
+        // the segments of the ordering chunks are aligned, the others packed after them
+        let read = JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        let map = read.layout();
+        let starts = map
+            .segments
+            .iter()
+            .map(|segment| segment.range.0)
+            .collect::<Vec<_>>();
+        assert_eq!(starts[0] % 0x10000, 0);
+        assert_eq!(starts[1] % 0x10000, 0);
+        // (the first interval is fractured by its ordering chunk)
+        assert_eq!(starts.len(), 5);
+        assert_eq!(starts[2], starts[1] + 0x1000);
+        assert_eq!(starts[3], starts[2] + 0x1000);
+        assert_eq!(map.padded_segments(), (2, 0x10000));
+        assert_eq!(
+            map.padding(),
+            starts[0] - map.data.0 + (starts[1] - starts[0] - 0x2000)
+        );
+        assert_eq!(map.file_size(), file.len() as u64);
+        // the padding before the second segment is prefetched with it
+        assert_eq!(
+            read.n_prefetch(),
+            (starts[2] - map.data.0) / PAGE_SIZE as u64
+        );
+
+        // the data reads the same, and survives a raw round trip
+        let read_jif = Jif::from_raw(read).unwrap();
+        for idx in 0..4u8 {
+            let start = 0x100000 * (idx as u64 + 1);
+            assert_eq!(
+                read_jif.resolve_data(start + 0x1000),
+                Some(&[2 * idx + 1; PAGE_SIZE][..])
+            );
+        }
+        let read = JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        let mut rewritten = Vec::new();
+        read.to_writer(&mut rewritten).unwrap();
+        assert_eq!(rewritten, file);
+
+        // moving the data section keeps the segments aligned
+        let mut raw = JifRaw::from_materialized_with_layout(jif(), true, layout);
+        raw.set_itree_fanout(16).unwrap();
+        assert!(raw
+            .layout()
+            .segments
+            .iter()
+            .take(2)
+            .all(|segment| segment.range.0 % 0x10000 == 0));
+
+        // segments needing more padding than the maximum gap are packed
+        let raw = JifRaw::from_materialized_with_layout(jif(), true, layout.with_max_gap(0x1000));
+        assert_eq!(raw.layout().padding(), 0);
+    }
+
+    #[test]
+    fn layout() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x5000), &[(0x1000, 0x2000), (0x3000, 0x5000)]),
+            ((0x10000, 0x12000), &[(0x10000, 0x12000)]),
+        ]);
+        let mut raw = JifRaw::from_materialized(jif, false);
+        raw.set_checksums(true);
+        let mut file = Vec::new();
+        raw.to_writer(&mut file).unwrap();
+
+        let raw = JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        let layout = raw.layout();
+        assert_eq!(layout.header, (0, 0x20));
+        assert_eq!(layout.pheaders.0, layout.header.1);
+        for (section, next) in [
+            (layout.pheaders, layout.strings),
+            (layout.strings, layout.itrees),
+            (layout.itrees, layout.ord),
+            (layout.ord, layout.data),
+        ] {
+            assert!(section.1 <= next.0);
+            assert_eq!(next.0 % PAGE_SIZE as u64, 0);
+        }
+        assert_eq!(layout.data.0, raw.data_offset);
+        assert_eq!(layout.file_size(), file.len() as u64);
+
+        // the segments hold the private data of their intervals (the equal ones deduplicated)
+        assert_eq!(
+            layout
+                .segments
+                .iter()
+                .map(|segment| segment.owners.len())
+                .sum::<usize>(),
+            3
+        );
+        for segment in &layout.segments {
+            let (start, end) = (segment.range.0 as usize, segment.range.1 as usize);
+            assert!(layout.data.0 <= segment.range.0 && segment.range.1 <= layout.data.1);
+            for owner in &segment.owners {
+                assert_eq!(
+                    owner.virtual_range.1 - owner.virtual_range.0,
+                    segment.range.1 - segment.range.0
+                );
+            }
+            assert!(file[start..end].iter().all(|byte| *byte == 42));
+        }
+    }
+}
@@ -2,18 +2,97 @@
 //!
 //! `jif` is a library for parsing, dumping and manipulating JIF (Junction Image Format) files
 
+mod address_space;
+pub mod arch;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod audit;
+pub mod builder;
+mod canonical;
+#[cfg(all(feature = "fs", target_os = "linux"))]
+pub mod capture;
+mod coalesce;
+mod compress;
+mod cow;
 pub mod deduper;
+mod delta;
+pub mod diff;
+pub mod digest;
+mod duplication;
+pub mod entropy;
 pub mod error;
+mod fanout;
+pub mod footprint;
+mod huge_page;
+mod integrity;
 pub mod itree;
 mod jif;
+mod jif_set;
+pub mod layout;
+#[cfg(all(feature = "loader", target_os = "linux"))]
+pub mod loader;
+pub mod meta;
+mod minimize;
+#[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+mod mmap;
 pub mod ord;
+mod ord_quality;
+mod ord_repair;
+mod page_dedup;
+mod page_index;
+mod paths;
 pub mod pheader;
+mod pheader_split;
+pub mod prefetch;
+mod prot;
+pub mod provenance;
+mod rebase;
+pub mod restore;
+pub mod scan;
+mod sparse;
+mod split;
+mod string_usage;
+pub mod synthetic;
+mod transform;
+mod update;
 mod utils;
+pub mod validate;
 
 mod read;
 mod write;
 
-pub use jif::{Jif, JifRaw};
-pub use pheader::Prot;
+pub use address_space::SpaceInterval;
+pub use arch::Arch;
+pub use audit::RefFinding;
+pub use builder::JifBuilder;
+pub use coalesce::CoalesceStats;
+pub use compress::Compression;
+pub use deduper::DedupStats;
+pub use diff::JifDiff;
+pub use digest::Sha256Hash;
+pub use duplication::{DuplicatePage, DuplicationReport};
+pub use entropy::DataStats;
+pub use footprint::Footprint;
+pub use itree::diff::{ExactComparator, IgnoreRanges, PageComparator};
+pub use jif::{Jif, JifRaw, LazyJif};
+pub use jif_set::{JifSet, SetSharing};
+pub use layout::{DataLayout, LayoutMap, SegmentPadding};
+#[cfg(all(feature = "loader", target_os = "linux"))]
+pub use loader::LoadedJif;
+pub use minimize::MinimizeStats;
+pub use ord_quality::OrdQuality;
+pub use ord_repair::{OrdCompactStats, OrdNormalizeStats, OrdRepairStats};
+pub use page_dedup::PageDedupStats;
+pub use page_index::PageHashIndex;
+pub use paths::{AsRecorded, PathMap, PathResolver};
+pub use prefetch::PrefetchLayout;
+pub use prot::ProtFlags;
+pub use provenance::DataProvenance;
+pub use restore::RestoreOp;
+pub use scan::MatchContext;
+pub use sparse::FileSize;
+pub use string_usage::StringUsage;
+pub use synthetic::SyntheticJif;
+pub use validate::ValidationReport;
 
 pub use error::{JifError, JifResult};
@@ -0,0 +1,305 @@
+//! Loading a JIF into the current process
+//!
+//! A reference implementation of the restore plan (see [`crate::restore`]): the pheaders are
+//! mapped at their addresses in the current process, the private data is copied in and the
+//! zero intervals of the reference pheaders are zero filled. It is meant to check the JIFs the
+//! tools produce without a full junction runtime: a JIF holds no register state, so the loaded
+//! memory is not a runnable process
+//!
+//! The pheaders are mapped with `MAP_FIXED_NOREPLACE`: a JIF overlapping the mappings of the
+//! process (e.g., its own libraries) is not loaded, but it can be moved out of the way (see
+//! [`Jif::rebase`]). The pages of a reference pheader past the end of its file would fault when
+//! touched: they are mapped anonymously (i.e., zero filled, as when reading the file) instead
+
+use crate::error::*;
+use crate::jif::Jif;
+use crate::paths::PathResolver;
+use crate::pheader::ProtFlags;
+use crate::restore::RestoreOp;
+use crate::utils::{open_file, page_align};
+
+use std::ffi::{c_int, c_void};
+use std::os::fd::AsRawFd;
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const PROT_EXEC: c_int = 0x4;
+const MAP_PRIVATE: c_int = 0x2;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_FIXED_NOREPLACE: c_int = 0x100000;
+const MADV_WILLNEED: c_int = 3;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    fn getpagesize() -> c_int;
+}
+
+/// A JIF loaded into the current process (see [`Jif::load`])
+///
+/// The memory is unmapped when it is dropped (unless it is leaked: see [`LoadedJif::leak`])
+#[derive(Debug)]
+pub struct LoadedJif {
+    /// mappings, as `(range, protections)`, in address order
+    mappings: Vec<((u64, u64), ProtFlags)>,
+}
+
+impl LoadedJif {
+    /// The mappings, as `(range, protections)`, in address order (a reference pheader is mapped
+    /// in two parts when it runs past the end of its file)
+    pub fn mappings(&self) -> &[((u64, u64), ProtFlags)] {
+        &self.mappings
+    }
+
+    /// The loaded memory of `[addr; addr + len)`, if it is mapped readable
+    pub fn read(&self, addr: u64, len: u64) -> Option<&[u8]> {
+        let end = addr.checked_add(len)?;
+        let mut cursor = addr;
+        for (range, prot) in &self.mappings {
+            if cursor >= end {
+                break;
+            } else if range.1 <= cursor {
+                continue;
+            } else if range.0 > cursor || !prot.contains(ProtFlags::READ) {
+                return None;
+            }
+            cursor = range.1;
+        }
+        if cursor < end {
+            return None;
+        }
+
+        // SAFETY: the range is mapped readable for as long as `self` lives, and it is only
+        // written through the loader
+        Some(unsafe { std::slice::from_raw_parts(addr as *const u8, len as usize) })
+    }
+
+    /// Keep the memory mapped for the lifetime of the process, returning the mappings
+    pub fn leak(self) -> Vec<((u64, u64), ProtFlags)> {
+        let loaded = std::mem::ManuallyDrop::new(self);
+        loaded.mappings.clone()
+    }
+}
+
+impl Drop for LoadedJif {
+    fn drop(&mut self) {
+        for ((start, end), _prot) in &self.mappings {
+            // SAFETY: the range was mapped by the loader, and is not borrowed past `self`
+            unsafe { munmap(*start as *mut c_void, (end - start) as usize) };
+        }
+    }
+}
+
+/// Protections, as the bits of `mmap(2)`
+fn os_prot(prot: ProtFlags) -> c_int {
+    [
+        (ProtFlags::READ, PROT_READ),
+        (ProtFlags::WRITE, PROT_WRITE),
+        (ProtFlags::EXEC, PROT_EXEC),
+    ]
+    .into_iter()
+    .filter(|(flag, _bits)| prot.contains(*flag))
+    .fold(0, |bits, (_flag, flag_bits)| bits | flag_bits)
+}
+
+/// Map a range at its address, without replacing the existing mappings
+fn map_fixed(
+    (start, end): (u64, u64),
+    prot: ProtFlags,
+    flags: c_int,
+    fd: c_int,
+    offset: u64,
+) -> std::io::Result<()> {
+    let len = (end - start) as usize;
+    // SAFETY: MAP_FIXED_NOREPLACE never replaces an existing mapping
+    let ptr = unsafe {
+        mmap(
+            start as *mut c_void,
+            len,
+            os_prot(prot),
+            flags | MAP_FIXED_NOREPLACE,
+            fd,
+            offset as i64,
+        )
+    };
+
+    if ptr as isize == -1 {
+        return Err(std::io::Error::last_os_error());
+    } else if ptr as u64 != start {
+        // kernels before 4.17 take the address as a hint
+        // SAFETY: the mapping was just created, and is not referenced
+        unsafe { munmap(ptr, len) };
+        return Err(std::io::ErrorKind::AddrInUse.into());
+    }
+
+    Ok(())
+}
+
+/// Check the return value of a system call
+fn check(ret: c_int) -> std::io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+impl Jif {
+    /// Load the JIF into the current process, following its restore plan (see
+    /// [`crate::loader`])
+    ///
+    /// The referenced files are opened as resolved by `paths`. On failure, the memory mapped so
+    /// far is unmapped
+    pub fn load(&self, paths: &dyn PathResolver) -> JifResult<LoadedJif> {
+        let page_size = self.arch.page_size;
+        // SAFETY: getpagesize(2) has no preconditions
+        let system_page_size = unsafe { getpagesize() } as usize;
+        if page_size != system_page_size {
+            return Err(JifError::LoadPageSize {
+                page_size,
+                system_page_size,
+            });
+        }
+
+        let mut loaded = LoadedJif {
+            mappings: Vec::new(),
+        };
+        for op in self.restore_plan() {
+            let range = op.range();
+            let len = (range.1 - range.0) as usize;
+            let failed = |op| move |error| JifError::LoadFailed { op, range, error };
+            match op {
+                RestoreOp::MapAnonymous { prot, .. } => {
+                    map_fixed(range, prot, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+                        .map_err(failed("map"))?;
+                    loaded.mappings.push((range, prot));
+                }
+                RestoreOp::MapFile {
+                    prot, path, offset, ..
+                } => {
+                    let file = open_file(&paths.resolve_path(&path))?;
+                    let file_len = page_align(file.metadata()?.len(), page_size);
+                    let file_end = std::cmp::min(
+                        range.1,
+                        range.0.saturating_add(file_len.saturating_sub(offset)),
+                    );
+                    if file_end > range.0 {
+                        let file_range = (range.0, file_end);
+                        map_fixed(file_range, prot, MAP_PRIVATE, file.as_raw_fd(), offset)
+                            .map_err(failed("map"))?;
+                        loaded.mappings.push((file_range, prot));
+                    }
+                    if file_end < range.1 {
+                        let anon_range = (file_end, range.1);
+                        map_fixed(anon_range, prot, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+                            .map_err(failed("map"))?;
+                        loaded.mappings.push((anon_range, prot));
+                    }
+                }
+                RestoreOp::Copy { .. } => {
+                    for addr in (range.0..range.1).step_by(page_size) {
+                        let page = self
+                            .resolve_data(addr)
+                            .expect("the plan copies the private pages");
+                        let page_len = std::cmp::min(page.len(), (range.1 - addr) as usize);
+                        // SAFETY: the page was mapped writable by the plan, and is not borrowed
+                        unsafe {
+                            std::ptr::copy_nonoverlapping(page.as_ptr(), addr as *mut u8, page_len)
+                        };
+                    }
+                }
+                // SAFETY: the range was mapped writable by the plan, and is not borrowed
+                RestoreOp::Zero { .. } => unsafe {
+                    std::ptr::write_bytes(range.0 as *mut u8, 0, len)
+                },
+                RestoreOp::WillNeed { .. } => {
+                    // SAFETY: the range was mapped by the plan
+                    check(unsafe { madvise(range.0 as *mut c_void, len, MADV_WILLNEED) })
+                        .map_err(failed("advise"))?
+                }
+                RestoreOp::Protect { prot, .. } => {
+                    // SAFETY: the range was mapped by the plan, and is not borrowed
+                    check(unsafe { mprotect(range.0 as *mut c_void, len, os_prot(prot)) })
+                        .map_err(failed("protect"))?;
+                    for (_range, mapping_prot) in loaded
+                        .mappings
+                        .iter_mut()
+                        .filter(|(mapping, _prot)| range.0 <= mapping.0 && mapping.1 <= range.1)
+                    {
+                        *mapping_prot = prot;
+                    }
+                }
+            }
+        }
+
+        Ok(loaded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::ord::OrdChunk;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn load() {
+        // SAFETY: getpagesize(2) has no preconditions
+        if unsafe { getpagesize() } as usize != PAGE_SIZE {
+            return;
+        }
+
+        let path = std::env::temp_dir().join(format!("jif-loader-test-{}", std::process::id()));
+        std::fs::write(&path, [vec![7; PAGE_SIZE], vec![8; PAGE_SIZE]].concat()).unwrap();
+
+        let page = PAGE_SIZE as u64;
+        let (anon, reference) = (0x5a5a_0000_0000u64, 0x5a5a_0010_0000u64);
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (anon, anon + 4 * page),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![(anon + page, vec![1; 2 * PAGE_SIZE])],
+            )
+            // the file ends before the pheader
+            .add_reference_segment(
+                (reference, reference + 4 * page),
+                ProtFlags::READ,
+                path.to_str().unwrap().to_string(),
+                0,
+                vec![(reference + page, vec![2; PAGE_SIZE])],
+            )
+            .set_ordering(vec![OrdChunk::new(anon + page, 1, DataSource::Private)]);
+        let jif = builder.build().unwrap();
+
+        let loaded = jif.load(&crate::AsRecorded).unwrap();
+        assert_eq!(loaded.mappings().len(), 3);
+        for (start, len) in [(anon, 4 * page), (reference, 4 * page)] {
+            assert_eq!(
+                loaded.read(start, len).unwrap(),
+                jif.read_range(start, len, &crate::AsRecorded).unwrap()
+            );
+        }
+        assert_eq!(loaded.read(anon + 3 * page, 2 * page), None);
+
+        // the address range is taken
+        assert!(matches!(
+            jif.load(&crate::AsRecorded),
+            Err(JifError::LoadFailed { op: "map", .. })
+        ));
+
+        drop(loaded);
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        assert!(!maps.contains("5a5a0000"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! Custom metadata section
+//!
+//! JIFs can carry string key/value pairs (e.g., build information: the image the snapshot was
+//! taken from, the version of the tracer), in a section trailing the data section (and preceding
+//! the integrity section, which covers it). The section is laid out as:
+//!  - for every pair, in key order: the length of the key (`u32`), the length of the value
+//!    (`u32`), and the key and value bytes (UTF-8)
+//!  - size of the section, including the footer (`u64`)
+//!  - [`META_MAGIC`]
+//!
+//! No header flag announces the section, and JIFs without custom metadata do not have one: it is
+//! found by its footer, at the end of the file (or right before the integrity section) and past
+//! the end of the data. Readers which predate it read the data segments at their offsets, and
+//! ignore it, except for compressed data sections (which they read up to the integrity section,
+//! or the end of the file)
+
+use crate::jif::{Jif, JifRaw};
+
+use std::collections::BTreeMap;
+
+/// Magic number at the end of the custom metadata section
+pub(crate) const META_MAGIC: [u8; 4] = [0x77, b'J', b'M', b'D'];
+
+/// Size of the footer of the trailing sections (the custom metadata section and the page hash
+/// index): the size of the section and a magic number
+pub(crate) const FOOTER_SIZE: usize = std::mem::size_of::<u64>() + META_MAGIC.len();
+
+/// Size of the custom metadata section (none is written without metadata)
+pub(crate) fn meta_size(meta: &BTreeMap<String, String>) -> u64 {
+    if meta.is_empty() {
+        return 0;
+    }
+
+    (meta
+        .iter()
+        .map(|(key, value)| 2 * std::mem::size_of::<u32>() + key.len() + value.len())
+        .sum::<usize>()
+        + FOOTER_SIZE) as u64
+}
+
+impl Jif {
+    /// Custom metadata, as key/value pairs (see [`crate::meta`])
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.meta
+    }
+
+    /// Set a custom metadata key, returning its previous value
+    pub fn set_metadata(&mut self, key: &str, value: &str) -> Option<String> {
+        self.meta.insert(key.to_string(), value.to_string())
+    }
+
+    /// Remove a custom metadata key, returning its value
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.meta.remove(key)
+    }
+}
+
+impl JifRaw {
+    /// Custom metadata, as key/value pairs (see [`crate::meta`])
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.meta
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::Compression;
+    use crate::error::*;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::{BufReader, Cursor};
+
+    fn gen_jif() -> Jif {
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x100000, 0x100000 + 4 * PAGE_SIZE as u64),
+            ProtFlags::READ | ProtFlags::WRITE,
+            vec![(0x101000, vec![0x4d; PAGE_SIZE])],
+        );
+        let mut jif = builder.build().unwrap();
+        jif.set_metadata("build", "v1.2.3");
+        jif.set_metadata("image", "nginx:latest");
+        jif
+    }
+
+    #[test]
+    fn round_trip() {
+        let jif = gen_jif();
+        for (compression, checksums) in [
+            (Compression::None, false),
+            (Compression::None, true),
+            (Compression::Lz4, false),
+            (Compression::Lz4, true),
+        ] {
+            let mut raw = JifRaw::from_materialized(jif.clone_cow(), false);
+            raw.set_compression(compression);
+            raw.set_checksums(checksums);
+            let mut file = Vec::new();
+            let written = raw.to_writer(&mut file).unwrap();
+            assert_eq!(written, file.len());
+            assert_eq!(raw.layout().file_size(), file.len() as u64);
+
+            let read = JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+            assert_eq!(read.metadata(), jif.metadata());
+            let read = Jif::from_raw(read).unwrap();
+            assert_eq!(read.metadata().get("build").unwrap(), "v1.2.3");
+            assert_eq!(read.resolve_data(0x101000), Some(&[0x4d; PAGE_SIZE][..]));
+        }
+    }
+
+    #[test]
+    fn ignored_without_section() {
+        let mut jif = gen_jif();
+        assert_eq!(jif.remove_metadata("build").as_deref(), Some("v1.2.3"));
+        assert_eq!(
+            jif.remove_metadata("image").as_deref(),
+            Some("nginx:latest")
+        );
+
+        // no section is written without metadata
+        let mut with_meta = Vec::new();
+        gen_jif().to_writer(&mut with_meta).unwrap();
+        let mut without_meta = Vec::new();
+        jif.to_writer(&mut without_meta).unwrap();
+        assert_eq!(
+            with_meta.len() - without_meta.len(),
+            meta_size(gen_jif().metadata()) as usize
+        );
+
+        // the data segments do not end in a (bogus) section
+        let read = Jif::from_bytes(&without_meta).unwrap();
+        assert!(read.metadata().is_empty());
+
+        // a corrupted section is reported (the last value is not UTF-8 anymore)
+        let len = with_meta.len();
+        with_meta[len - FOOTER_SIZE - 1] = 0xff;
+        assert!(matches!(
+            Jif::from_bytes(&with_meta),
+            Err(JifError::InSection {
+                section: JifSection::Meta,
+                ..
+            })
+        ));
+    }
+}
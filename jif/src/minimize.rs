@@ -0,0 +1,251 @@
+//! Working set minimization
+//!
+//! A snapshot stores every private page of the process, while a restored function often touches
+//! only a fraction of them (e.g., a large heap which is mostly left alone). Minimizing keeps the
+//! private pages of the working set (the pages an access trace touched) and demotes the others:
+//! to zero pages in anonymous pheaders, and to the shared pages of the referenced file in
+//! reference pheaders (see [`Jif::minimize`]). This is lossy: the demoted pages no longer hold
+//! their snapshotted contents
+
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::huge_page::split_part;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::pheader::JifPheader;
+use crate::utils::page_align_down;
+
+use std::collections::BTreeSet;
+
+/// Result of a minimization (see [`Jif::minimize`])
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MinimizeStats {
+    /// Number of private pages kept (touched)
+    pub kept_pages: usize,
+
+    /// Number of private pages of anonymous pheaders demoted to zero pages
+    pub zeroed_pages: usize,
+
+    /// Number of private pages of reference pheaders demoted to shared pages
+    pub shared_pages: usize,
+
+    /// Private data removed, in B (before deduplication)
+    pub removed_bytes: usize,
+}
+
+impl MinimizeStats {
+    /// Number of private pages demoted
+    pub fn demoted_pages(&self) -> usize {
+        self.zeroed_pages + self.shared_pages
+    }
+}
+
+impl Jif {
+    /// Keep only the private pages touched by the `accesses` (addresses anywhere in the page),
+    /// demoting the other private pages to zero pages (anonymous pheaders) or to shared pages
+    /// (reference pheaders)
+    ///
+    /// The ordering chunks are then retagged to the new data sources of their pages (see
+    /// [`Jif::repair_ord_chunks`])
+    pub fn minimize(
+        &mut self,
+        accesses: impl IntoIterator<Item = u64>,
+    ) -> JifResult<MinimizeStats> {
+        let page_size = self.arch.page_size;
+        let touched = accesses
+            .into_iter()
+            .map(|addr| page_align_down(addr, page_size))
+            .collect::<BTreeSet<_>>();
+
+        let mut stats = MinimizeStats::default();
+        for pheader in self.pheaders.iter_mut() {
+            let virtual_range = pheader.virtual_range();
+            let invalid_itree = |error| JifError::InvalidITree {
+                virtual_range,
+                error,
+            };
+
+            match pheader {
+                JifPheader::Anonymous { itree, .. } => {
+                    let (intervals, kept, demoted) = keep_touched(
+                        itree.take().into_iter_intervals().collect(),
+                        &self.deduper,
+                        page_size,
+                        &touched,
+                        AnonIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                    stats.kept_pages += kept;
+                    stats.zeroed_pages += demoted;
+                }
+                JifPheader::Reference { itree, .. } => {
+                    let (intervals, kept, demoted) = keep_touched(
+                        itree.take().into_iter_intervals().collect(),
+                        &self.deduper,
+                        page_size,
+                        &touched,
+                        RefIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                    stats.kept_pages += kept;
+                    stats.shared_pages += demoted;
+                }
+            }
+        }
+        stats.removed_bytes = stats.demoted_pages() * page_size;
+
+        self.repair_ord_chunks();
+        Ok(stats)
+    }
+}
+
+/// Cut the data intervals down to their touched pages, returning the intervals with the number
+/// of kept and demoted pages
+///
+/// The demoted parts are left without an interval: the default source of the pheader backs them
+fn keep_touched<Data: IntervalData + Clone>(
+    intervals: Vec<Interval<Data>>,
+    deduper: &Deduper,
+    page_size: usize,
+    touched: &BTreeSet<u64>,
+    owned: fn(Vec<u8>) -> Data,
+) -> (Vec<Interval<Data>>, usize, usize) {
+    let (mut kept, mut demoted) = (0, 0);
+    let mut result = Vec::with_capacity(intervals.len());
+    for ival in intervals.into_iter().filter(|ival| !ival.data.is_none()) {
+        if !ival.data.is_data() {
+            result.push(ival);
+            continue;
+        }
+
+        // runs of consecutive touched pages
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for page in touched.range(page_align_down(ival.start, page_size)..ival.end) {
+            let (start, end) = (
+                std::cmp::max(*page, ival.start),
+                std::cmp::min(page + page_size as u64, ival.end),
+            );
+            match runs.last_mut() {
+                Some(run) if run.1 == start => run.1 = end,
+                _ => runs.push((start, end)),
+            }
+        }
+
+        let n_pages = (ival.end - ival.start).div_ceil(page_size as u64) as usize;
+        let n_touched = runs
+            .iter()
+            .map(|(start, end)| (end - start).div_ceil(page_size as u64) as usize)
+            .sum::<usize>();
+        kept += n_touched;
+        demoted += n_pages - n_touched;
+        if runs == [(ival.start, ival.end)] {
+            result.push(ival);
+            continue;
+        }
+
+        let data = ival.data.get_data(deduper);
+        let slice = |start: u64, end: u64| {
+            data.map(|data| {
+                data[(start - ival.start) as usize..(end - ival.start) as usize].to_vec()
+            })
+        };
+        result.extend(
+            runs.iter()
+                .map(|(start, end)| split_part(&ival, *start, *end, slice, owned)),
+        );
+    }
+
+    (result, kept, demoted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::ord::OrdChunk;
+    use crate::paths::AsRecorded;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn minimize() {
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x10000, 0x18000),
+            ProtFlags::READ | ProtFlags::WRITE,
+            vec![(0x10000, vec![1; 6 * PAGE_SIZE])],
+        );
+        builder.add_reference_segment(
+            (0x20000, 0x24000),
+            ProtFlags::READ,
+            "/lib/a.so".to_string(),
+            0,
+            vec![(0x21000, vec![2; 2 * PAGE_SIZE])],
+        );
+        builder.set_ordering(vec![OrdChunk::new(0x10000, 4, DataSource::Private)]);
+        let mut jif = builder.build().unwrap();
+
+        let stats = jif
+            .minimize([
+                0x10010, 0x10ff0, 0x12000, 0x13fff, 0x17000, 0x22000, 0x30000,
+            ])
+            .unwrap();
+        assert_eq!(
+            stats,
+            MinimizeStats {
+                kept_pages: 4,
+                zeroed_pages: 3,
+                shared_pages: 1,
+                removed_bytes: 4 * PAGE_SIZE,
+            }
+        );
+
+        let sources = |jif: &Jif, start: u64, end: u64| {
+            (start..end)
+                .step_by(PAGE_SIZE)
+                .map(|page| jif.resolve(page).unwrap().source)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sources(&jif, 0x10000, 0x18000),
+            vec![
+                DataSource::Private,
+                DataSource::Zero,
+                DataSource::Private,
+                DataSource::Private,
+                DataSource::Zero,
+                DataSource::Zero,
+                DataSource::Zero,
+                DataSource::Zero,
+            ]
+        );
+        assert_eq!(
+            sources(&jif, 0x20000, 0x24000),
+            vec![
+                DataSource::Shared,
+                DataSource::Shared,
+                DataSource::Private,
+                DataSource::Shared,
+            ]
+        );
+        assert_eq!(
+            jif.read_range(0x12000, 2 * PAGE_SIZE as u64, &AsRecorded)
+                .unwrap(),
+            vec![1; 2 * PAGE_SIZE]
+        );
+
+        // the ordering chunk follows the demoted page
+        assert_eq!(
+            jif.ord_chunks()
+                .iter()
+                .map(|chunk| (chunk.addr(), chunk.size(), chunk.kind()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x10000, 1, DataSource::Private),
+                (0x11000, 1, DataSource::Zero),
+                (0x12000, 2, DataSource::Private),
+            ]
+        );
+    }
+}
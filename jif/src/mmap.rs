@@ -0,0 +1,101 @@
+//! Read-only memory maps of files
+//!
+//! Thin wrapper over `mmap(2)`, so that large JIFs can be parsed without copying their data
+
+use std::ffi::{c_int, c_void};
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+const PROT_READ: c_int = 0x1;
+const MAP_PRIVATE: c_int = 0x2;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+/// A read-only, private mapping of a whole file
+pub struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// the mapping is read-only: sharing it across threads is sound
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map a file in its entirety
+    ///
+    /// The file should not be modified while the map is alive
+    pub fn map(file: &File) -> std::io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mmap(2) refuses empty mappings
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len: 0,
+            });
+        }
+
+        // SAFETY: we ask for a fresh mapping (no fixed address) of a valid file descriptor
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+
+        if ptr as isize == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(Mmap { ptr, len })
+    }
+
+    /// Access the mapped bytes
+    pub fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+
+        // SAFETY: the mapping is valid for `len` bytes for as long as `self` lives
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl std::ops::Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            // SAFETY: `ptr` and `len` describe a mapping we own
+            unsafe {
+                munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mmap {{ len: {:#x} }}", self.len)
+    }
+}
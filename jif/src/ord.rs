@@ -1,4 +1,5 @@
 //! The ordering segments
+use crate::error::*;
 use crate::itree::interval::DataSource;
 use crate::jif::Jif;
 use crate::utils::{page_align_down, PAGE_SIZE};
@@ -8,7 +9,71 @@ pub const ORD_PRIVATE_FLAG: u64 = 1 << 62;
 pub const ORD_ZERO_FLAG: u64 = 1 << 61;
 pub const ORD_FLAG_MASK: u64 = ORD_ZERO_FLAG - 1;
 
+/// Marks the provenance words holding a thread id (in the lower 32 bits)
+pub const ORD_TID_FLAG: u64 = 1 << 32;
+pub const ORD_READ_FLAG: u64 = 1 << 33;
+pub const ORD_WRITE_FLAG: u64 = 1 << 34;
+
+/// Marks the provenance words holding a weight (in the bits from [`ORD_WEIGHT_SHIFT`]), which
+/// only the JIFs of the format version with weights have (see [`OrdChunk::weight`])
+pub const ORD_WEIGHT_FLAG: u64 = 1 << 35;
+pub const ORD_WEIGHT_SHIFT: u32 = 40;
+
+/// Largest weight of a chunk (weights saturate at it)
+pub const ORD_WEIGHT_MAX: u32 = (1 << (u64::BITS - ORD_WEIGHT_SHIFT)) - 1;
+
+/// Kind of the access which faulted in an ordering chunk
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Whether any of the ordering chunks records its provenance (in which case they are all
+/// serialized with it)
+pub(crate) fn has_provenance(ord_chunks: &[OrdChunk]) -> bool {
+    ord_chunks.iter().any(OrdChunk::has_provenance)
+}
+
+/// Whether any of the ordering chunks records its weight (in which case the JIF is written in
+/// the format version which has them)
+pub(crate) fn has_weights(ord_chunks: &[OrdChunk]) -> bool {
+    ord_chunks.iter().any(|chunk| chunk.weight.is_some())
+}
+
+/// The indices of the ordering chunks, in the order they place the data segments: the hottest
+/// first (by their weight per page), and then in the order of the ordering section
+///
+/// The chunks without a weight are the coldest, so the placement of an unweighted ordering
+/// section is its order
+pub(crate) fn placement_order(ord_chunks: &[OrdChunk]) -> Vec<usize> {
+    let heat = |chunk: &OrdChunk| (chunk.weight.unwrap_or(0) as u128, chunk.n_pages as u128);
+    let mut order = (0..ord_chunks.len()).collect::<Vec<_>>();
+    order.sort_by(|lhs, rhs| {
+        let ((lhs_weight, lhs_pages), (rhs_weight, rhs_pages)) =
+            (heat(&ord_chunks[*lhs]), heat(&ord_chunks[*rhs]));
+        (rhs_weight * lhs_pages).cmp(&(lhs_weight * rhs_pages))
+    });
+    order
+}
+
 /// An ordering chunk represents a range of pages to pre-fault
+///
+/// Tracers can build the ordering section directly, and attach it to a JIF with
+/// [`Jif::add_ordering_info`]:
+/// ```
+/// use jif::itree::interval::DataSource;
+/// use jif::ord::{AccessKind, OrdChunk};
+///
+/// let mut chunk = OrdChunk::from_pages(0x7f0000001000, 4, DataSource::Private).unwrap();
+/// chunk.set_tid(Some(7));
+/// chunk.record_access(Some(AccessKind::Write));
+/// assert_eq!(chunk.end(0x1000), 0x7f0000005000);
+/// assert_eq!(chunk.access(), Some(AccessKind::Write));
+///
+/// // the address has to be page aligned
+/// assert!(OrdChunk::from_pages(0x7f0000001234, 4, DataSource::Private).is_err());
+/// ```
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct OrdChunk {
     /// Page number of the first page
@@ -18,25 +83,129 @@ pub struct OrdChunk {
     pub(crate) n_pages: u64,
 
     pub(crate) kind: DataSource,
+
+    /// Provenance: the thread which first faulted in the chunk
+    pub(crate) tid: Option<u32>,
+
+    /// Provenance: the kind of access which faulted in the chunk (a write if any page was
+    /// written to)
+    pub(crate) access: Option<AccessKind>,
+
+    /// Number of accesses to the pages of the chunk (see [`OrdChunk::weight`])
+    pub(crate) weight: Option<u32>,
 }
 
 impl OrdChunk {
-    /// The size of the [`OrdChunk`] when serialized on disk
-    pub(crate) const fn serialized_size() -> usize {
-        2 * std::mem::size_of::<u64>()
+    /// The size of the [`OrdChunk`] when serialized on disk (with or without its provenance)
+    pub(crate) const fn serialized_size(provenance: bool) -> usize {
+        if provenance {
+            3 * std::mem::size_of::<u64>()
+        } else {
+            2 * std::mem::size_of::<u64>()
+        }
     }
 
     /// Create a new ordering chunk
     ///
-    /// Will silently clamp the `vaddr`
+    /// Will silently clamp the `vaddr` (to the smallest page size: JIFs with larger pages reject
+    /// the chunks which are not aligned to theirs)
     pub fn new(vaddr: u64, n_pages: u64, kind: DataSource) -> Self {
         OrdChunk {
-            vaddr: page_align_down(vaddr),
+            vaddr: page_align_down(vaddr, PAGE_SIZE),
 
             n_pages,
 
             kind,
+
+            tid: None,
+            access: None,
+            weight: None,
+        }
+    }
+
+    /// Create an ordering chunk of `n_pages` pages starting at `vaddr`
+    ///
+    /// Unlike [`OrdChunk::new`], the address is not clamped: it has to be aligned to the
+    /// smallest page size, and the chunk has to fit in the addresses the ordering section can
+    /// represent
+    pub fn from_pages(vaddr: u64, n_pages: u64, kind: DataSource) -> OrdChunkResult<Self> {
+        if page_align_down(vaddr, PAGE_SIZE) != vaddr {
+            return Err(OrdChunkError::BadAlignment(vaddr));
+        }
+        if n_pages
+            .checked_mul(PAGE_SIZE as u64)
+            .and_then(|len| vaddr.checked_add(len))
+            .is_none_or(|end| end > ORD_FLAG_MASK + 1)
+        {
+            return Err(OrdChunkError::Overflow(vaddr, n_pages));
         }
+
+        Ok(OrdChunk::new(vaddr, n_pages, kind))
+    }
+
+    /// Attach the provenance of the chunk: the thread which faulted it in and the kind of access
+    pub fn with_provenance(mut self, tid: Option<u32>, access: Option<AccessKind>) -> Self {
+        self.tid = tid;
+        self.access = access;
+        self
+    }
+
+    /// Record another access to the chunk (a write supersedes a read)
+    pub fn record_access(&mut self, access: Option<AccessKind>) {
+        self.access = match (self.access, access) {
+            (Some(AccessKind::Write), _) | (_, Some(AccessKind::Write)) => Some(AccessKind::Write),
+            (current, None) => current,
+            (_, access) => access,
+        };
+    }
+
+    /// Record more accesses to the chunk, adding to its weight
+    pub fn add_weight(&mut self, n_accesses: u32) {
+        let weight = self.weight.unwrap_or(0).saturating_add(n_accesses);
+        self.weight = Some(std::cmp::min(weight, ORD_WEIGHT_MAX));
+    }
+
+    /// The part of the chunk covering `n_pages` pages from `vaddr`, keeping its provenance
+    ///
+    /// The part gets the share of the weight of its pages (rounded up)
+    pub(crate) fn part(&self, vaddr: u64, n_pages: u64, kind: DataSource) -> OrdChunk {
+        let mut part = OrdChunk::new(vaddr, n_pages, kind).with_provenance(self.tid, self.access);
+        part.weight = self.weight.map(|weight| match self.n_pages {
+            0 => weight,
+            total => (weight as u64 * n_pages).div_ceil(total).min(weight as u64) as u32,
+        });
+        part
+    }
+
+    /// Set the address of the first page (clamped, as in [`OrdChunk::new`])
+    pub fn set_addr(&mut self, vaddr: u64) {
+        self.vaddr = page_align_down(vaddr, PAGE_SIZE);
+    }
+
+    /// Set the number of pages in the ordering chunk
+    pub fn set_size(&mut self, n_pages: u64) {
+        self.n_pages = n_pages;
+    }
+
+    /// Set the kind of ordering segment
+    pub fn set_kind(&mut self, kind: DataSource) {
+        self.kind = kind;
+    }
+
+    /// Set the thread which first faulted in the chunk
+    pub fn set_tid(&mut self, tid: Option<u32>) {
+        self.tid = tid;
+    }
+
+    /// Set the kind of access which faulted in the chunk (see [`OrdChunk::record_access`] to
+    /// merge in another access)
+    pub fn set_access(&mut self, access: Option<AccessKind>) {
+        self.access = access;
+    }
+
+    /// Set the weight of the chunk (saturated at [`ORD_WEIGHT_MAX`])
+    pub fn set_weight(&mut self, weight: Option<u32>) {
+        self.weight = weight.map(|weight| std::cmp::min(weight, ORD_WEIGHT_MAX));
     }
 
     /// Whether this ordering chunk has any data
@@ -54,23 +223,53 @@ impl OrdChunk {
         self.kind
     }
 
+    /// The thread which first faulted in the chunk (if recorded)
+    pub fn tid(&self) -> Option<u32> {
+        self.tid
+    }
+
+    /// The kind of access which faulted in the chunk (if recorded)
+    pub fn access(&self) -> Option<AccessKind> {
+        self.access
+    }
+
+    /// The weight of the chunk: the number of accesses to its pages recorded in the traces it
+    /// was built from (if recorded)
+    ///
+    /// The first touch of a page is the one which places it in the ordering, but the other
+    /// accesses tell how hot the chunk is: the data of the hottest chunks (by weight per page)
+    /// is laid out first in the prefetched part of the data section
+    pub fn weight(&self) -> Option<u32> {
+        self.weight
+    }
+
+    /// Whether the chunk records any provenance (or weight)
+    pub fn has_provenance(&self) -> bool {
+        self.tid.is_some() || self.access.is_some() || self.weight.is_some()
+    }
+
     /// The address of the first page in the ordering chunk
     pub fn addr(&self) -> u64 {
         self.vaddr
     }
 
-    /// The address of the last page in the ordering chunk
-    pub fn last_page_addr(&self) -> u64 {
+    /// The address of the last page (of `page_size`) in the ordering chunk
+    pub fn last_page_addr(&self, page_size: usize) -> u64 {
         if self.n_pages > 1 {
-            self.vaddr + (self.n_pages - 1) * PAGE_SIZE as u64
+            self.vaddr + (self.n_pages - 1) * page_size as u64
         } else {
             self.vaddr
         }
     }
 
-    /// First address of each page
-    pub fn pages(&self) -> impl Iterator<Item = u64> {
-        (self.vaddr..=(self.last_page_addr())).step_by(PAGE_SIZE)
+    /// The address past the end of the ordering chunk (with pages of `page_size`)
+    pub fn end(&self, page_size: usize) -> u64 {
+        self.vaddr + self.n_pages * page_size as u64
+    }
+
+    /// First address of each page (of `page_size`)
+    pub fn pages(&self, page_size: usize) -> impl Iterator<Item = u64> {
+        (self.vaddr..=(self.last_page_addr(page_size))).step_by(page_size)
     }
 
     /// Attempt to merge a page (`vaddr`) into the ordering chunk, which happens if:
@@ -79,7 +278,8 @@ impl OrdChunk {
     ///
     /// Return false if it is not possible to merge the page
     pub fn merge_page(&mut self, jif: &Jif, vaddr: u64) -> bool {
-        let vaddr = page_align_down(vaddr);
+        let page_size = jif.page_size();
+        let vaddr = page_align_down(vaddr, page_size);
 
         if self.n_pages == 0 {
             self.vaddr = vaddr;
@@ -93,18 +293,18 @@ impl OrdChunk {
             return false;
         }
 
-        if vaddr == self.vaddr - PAGE_SIZE as u64 {
+        if vaddr == self.vaddr - page_size as u64 {
             // if the page is immediately before the ordering chunk
 
             self.vaddr = vaddr;
             self.n_pages += 1;
             true
-        } else if vaddr == self.vaddr + (self.n_pages * PAGE_SIZE as u64) {
+        } else if vaddr == self.end(page_size) {
             // if the page is immediately after the ordering chunk
 
             self.n_pages += 1;
             true
-        } else if self.vaddr <= vaddr && vaddr < self.vaddr + (self.n_pages * PAGE_SIZE as u64) {
+        } else if self.vaddr <= vaddr && vaddr < self.end(page_size) {
             // if the page is already in the ordering chunk
 
             true
@@ -118,9 +318,23 @@ impl std::fmt::Debug for OrdChunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("ord: [")?;
         self.vaddr.fmt(f)?;
-        f.write_str("; ")?;
-        (self.vaddr + self.n_pages * PAGE_SIZE as u64).fmt(f)?;
-        f.write_str(")")
+        f.write_str("; +")?;
+        self.n_pages.fmt(f)?;
+        f.write_str(" pages)")?;
+        if let Some(tid) = self.tid {
+            f.write_str(" tid: ")?;
+            tid.fmt(f)?;
+        }
+        match self.access {
+            Some(AccessKind::Read) => f.write_str(" read")?,
+            Some(AccessKind::Write) => f.write_str(" write")?,
+            None => {}
+        }
+        if let Some(weight) = self.weight {
+            f.write_str(" weight: ")?;
+            weight.fmt(f)?;
+        }
+        Ok(())
     }
 }
 
@@ -128,6 +342,9 @@ impl std::fmt::Debug for OrdChunk {
 mod test {
     use super::*;
     use crate::jif::test::gen_jif;
+    use crate::jif::JifRaw;
+
+    use std::io::BufReader;
 
     #[test]
     fn empty_ord() {
@@ -137,7 +354,10 @@ mod test {
             OrdChunk {
                 vaddr: 0x1000,
                 n_pages: 0,
-                kind: DataSource::Zero
+                kind: DataSource::Zero,
+                tid: None,
+                access: None,
+                weight: None,
             }
         );
         assert!(ord.is_empty());
@@ -151,11 +371,14 @@ mod test {
             OrdChunk {
                 vaddr: 0x1000,
                 n_pages: 1,
-                kind: DataSource::Zero
+                kind: DataSource::Zero,
+                tid: None,
+                access: None,
+                weight: None,
             }
         );
         assert!(!ord.is_empty());
-        assert_eq!(ord.last_page_addr(), 0x1000);
+        assert_eq!(ord.last_page_addr(PAGE_SIZE), 0x1000);
     }
 
     #[test]
@@ -166,11 +389,14 @@ mod test {
             OrdChunk {
                 vaddr: 0x1000,
                 n_pages: 10,
-                kind: DataSource::Zero
+                kind: DataSource::Zero,
+                tid: None,
+                access: None,
+                weight: None,
             }
         );
         assert!(!ord.is_empty());
-        assert_eq!(ord.last_page_addr(), 0xa000);
+        assert_eq!(ord.last_page_addr(PAGE_SIZE), 0xa000);
     }
 
     #[test]
@@ -228,4 +454,188 @@ mod test {
             assert_eq!(ord, OrdChunk::new(0x10000, 0x10, DataSource::Zero));
         }
     }
+
+    #[test]
+    fn record_access() {
+        let mut ord = OrdChunk::new(0x1000, 1, DataSource::Private);
+        assert!(!ord.has_provenance());
+        ord.record_access(Some(AccessKind::Read));
+        assert_eq!(ord.access(), Some(AccessKind::Read));
+        ord.record_access(Some(AccessKind::Write));
+        ord.record_access(Some(AccessKind::Read));
+        ord.record_access(None);
+        assert_eq!(ord.access(), Some(AccessKind::Write));
+        assert_eq!(ord.tid(), None);
+    }
+
+    #[test]
+    fn provenance_roundtrip() {
+        let roundtrip = |ord_chunks: Vec<OrdChunk>| {
+            let mut jif = gen_jif(&[((0x10000, 0x20000), &[(0x10000, 0x18000)])]);
+            jif.add_ordering_info(ord_chunks).unwrap();
+            let mut buffer = Vec::new();
+            JifRaw::from_materialized(jif, false)
+                .to_writer(&mut buffer)
+                .unwrap();
+            JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap()
+        };
+
+        let ord_chunks = vec![
+            OrdChunk::new(0x10000, 2, DataSource::Private)
+                .with_provenance(Some(17), Some(AccessKind::Write)),
+            OrdChunk::new(0x12000, 1, DataSource::Private).with_provenance(Some(0), None),
+            OrdChunk::new(0x13000, 1, DataSource::Private),
+            OrdChunk::new(0x18000, 1, DataSource::Zero)
+                .with_provenance(None, Some(AccessKind::Read)),
+        ];
+        assert_eq!(roundtrip(ord_chunks.clone()).ord_chunks(), &ord_chunks);
+
+        // without provenance, the chunks are read back the same
+        let ord_chunks = vec![OrdChunk::new(0x10000, 2, DataSource::Private)];
+        assert_eq!(roundtrip(ord_chunks.clone()).ord_chunks(), &ord_chunks);
+    }
+
+    #[test]
+    fn bad_provenance() {
+        let mut buffer = Vec::new();
+        for word in [ORD_PRIVATE_FLAG | 0x1000, 1, 3 << 33] {
+            buffer.extend_from_slice(&word.to_le_bytes());
+        }
+        assert!(matches!(
+            OrdChunk::from_reader(&mut buffer.as_slice(), PAGE_SIZE, true, true),
+            Err(crate::error::OrdChunkError::BadProvenance(_))
+        ));
+
+        let chunk = OrdChunk::from_reader(&mut &buffer[..16], PAGE_SIZE, false, false).unwrap();
+        assert_eq!(chunk, OrdChunk::new(0x1000, 1, DataSource::Private));
+    }
+
+    #[test]
+    fn kindless_chunk() {
+        let read = |vaddr: u64, n_pages: u64| {
+            let mut buffer = vaddr.to_le_bytes().to_vec();
+            buffer.extend_from_slice(&n_pages.to_le_bytes());
+            OrdChunk::from_reader(&mut buffer.as_slice(), PAGE_SIZE, false, false)
+        };
+
+        // only the empty chunk has no kind
+        assert!(read(0, 0).unwrap().is_empty());
+        assert!(matches!(read(0, 0x10), Err(OrdChunkError::BadKind(0))));
+        assert!(matches!(
+            read(0x1000, 1),
+            Err(OrdChunkError::BadKind(0x1000))
+        ));
+    }
+
+    #[test]
+    fn weights() {
+        let mut chunk = OrdChunk::new(0x10000, 4, DataSource::Private);
+        chunk.add_weight(6);
+        chunk.add_weight(2);
+        assert_eq!(chunk.weight(), Some(8));
+        assert!(chunk.has_provenance());
+        chunk.add_weight(u32::MAX);
+        assert_eq!(chunk.weight(), Some(ORD_WEIGHT_MAX));
+
+        // the parts of a chunk share its weight
+        chunk.set_weight(Some(9));
+        assert_eq!(
+            chunk.part(0x10000, 1, DataSource::Private).weight(),
+            Some(3)
+        );
+        assert_eq!(
+            chunk.part(0x11000, 3, DataSource::Private).weight(),
+            Some(7)
+        );
+
+        // the weights are only read in the format version which has them
+        let mut buffer = Vec::new();
+        chunk.to_writer(&mut buffer, true).unwrap();
+        assert_eq!(
+            OrdChunk::from_reader(&mut buffer.as_slice(), PAGE_SIZE, true, true).unwrap(),
+            chunk
+        );
+        assert!(matches!(
+            OrdChunk::from_reader(&mut buffer.as_slice(), PAGE_SIZE, true, false),
+            Err(OrdChunkError::BadProvenance(_))
+        ));
+    }
+
+    #[test]
+    fn weights_roundtrip() {
+        let write = |ord_chunks: Vec<OrdChunk>| {
+            let mut jif = gen_jif(&[((0x10000, 0x20000), &[(0x10000, 0x18000)])]);
+            jif.add_ordering_info(ord_chunks).unwrap();
+            let mut buffer = Vec::new();
+            // breaking the intervals per chunk, so that each chunk places its own segment
+            JifRaw::from_materialized(jif, true)
+                .to_writer(&mut buffer)
+                .unwrap();
+            buffer
+        };
+        let version =
+            |buffer: &[u8]| u32::from_le_bytes(buffer[20..24].try_into().unwrap()) & 0xffff;
+
+        let mut hot = OrdChunk::new(0x14000, 1, DataSource::Private);
+        hot.set_weight(Some(10));
+        let mut cold = OrdChunk::new(0x10000, 2, DataSource::Private);
+        cold.set_weight(Some(2));
+        let ord_chunks = vec![cold, hot];
+        let buffer = write(ord_chunks.clone());
+        assert_eq!(version(&buffer), crate::jif::JIF_VERSION_ORD_WEIGHTS);
+        let raw = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(raw.ord_chunks(), &ord_chunks);
+
+        // the data of the hot chunk is laid out first
+        let provenance = raw.data_provenance();
+        let pinned = provenance
+            .segments
+            .iter()
+            .filter_map(|segment| segment.chunk.map(|(idx, _chunk)| (segment.range.0, idx)))
+            .collect::<Vec<_>>();
+        assert_eq!(pinned.len(), 2);
+        assert!(pinned[0].0 < pinned[1].0);
+        assert_eq!((pinned[0].1, pinned[1].1), (1, 0));
+
+        // without weights, the JIF keeps the previous version
+        let buffer = write(vec![OrdChunk::new(0x10000, 2, DataSource::Private)]);
+        assert_eq!(version(&buffer), crate::jif::JIF_VERSION);
+    }
+
+    #[test]
+    fn from_pages_and_setters() {
+        let mut chunk = OrdChunk::from_pages(0x1000, 3, DataSource::Zero).unwrap();
+        assert_eq!(chunk, OrdChunk::new(0x1000, 3, DataSource::Zero));
+
+        chunk.set_addr(0x5678);
+        chunk.set_size(2);
+        chunk.set_kind(DataSource::Private);
+        chunk.set_tid(Some(3));
+        chunk.set_access(Some(AccessKind::Read));
+        assert_eq!(
+            chunk,
+            OrdChunk::new(0x5000, 2, DataSource::Private)
+                .with_provenance(Some(3), Some(AccessKind::Read))
+        );
+        chunk.set_access(None);
+        chunk.set_tid(None);
+        assert!(!chunk.has_provenance());
+
+        assert!(matches!(
+            OrdChunk::from_pages(0x1234, 1, DataSource::Private),
+            Err(OrdChunkError::BadAlignment(0x1234))
+        ));
+        // the top bits of the address hold the kind flags
+        assert!(matches!(
+            OrdChunk::from_pages(ORD_FLAG_MASK + 1, 1, DataSource::Private),
+            Err(OrdChunkError::Overflow(_, 1))
+        ));
+        assert!(matches!(
+            OrdChunk::from_pages(0x1000, u64::MAX, DataSource::Private),
+            Err(OrdChunkError::Overflow(0x1000, u64::MAX))
+        ));
+        let last_page = ORD_FLAG_MASK + 1 - PAGE_SIZE as u64;
+        assert!(OrdChunk::from_pages(last_page, 1, DataSource::Private).is_ok());
+        assert!(OrdChunk::from_pages(last_page, 2, DataSource::Private).is_err());
+    }
 }
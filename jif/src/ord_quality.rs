@@ -0,0 +1,173 @@
+//! Ordering section quality
+//!
+//! How well an ordering section serves the restore: how much of the private data it prefetches,
+//! how large its chunks are and how much it jumps around the address space while walking it.
+//! These are meant to compare the orderings built from different tracing configurations (see
+//! [`OrdQuality::score`])
+
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+
+use std::collections::BTreeSet;
+
+/// Quality metrics of the ordering section (see [`Jif::ord_quality`])
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OrdQuality {
+    /// Number of ordering chunks
+    pub n_chunks: usize,
+
+    /// Number of pages in the ordering chunks
+    pub ord_pages: u64,
+
+    /// Number of private pages in the JIF
+    pub private_pages: usize,
+
+    /// Number of (distinct) private pages in the ordering chunks
+    pub covered_private_pages: usize,
+
+    /// Number of consecutive chunks which are in different pheaders
+    pub vma_switches: usize,
+
+    /// Mean distance from the end of a chunk to the start of the next one, in B
+    pub mean_stride: f64,
+}
+
+impl OrdQuality {
+    /// Fraction of the private pages in the ordering section
+    pub fn coverage(&self) -> f64 {
+        if self.private_pages == 0 {
+            return 0.0;
+        }
+
+        self.covered_private_pages as f64 / self.private_pages as f64
+    }
+
+    /// Average number of pages in a chunk
+    pub fn mean_chunk_pages(&self) -> f64 {
+        if self.n_chunks == 0 {
+            return 0.0;
+        }
+
+        self.ord_pages as f64 / self.n_chunks as f64
+    }
+
+    /// Fraction of the transitions between chunks which switch pheaders
+    pub fn switch_rate(&self) -> f64 {
+        if self.n_chunks < 2 {
+            return 0.0;
+        }
+
+        self.vma_switches as f64 / (self.n_chunks - 1) as f64
+    }
+
+    /// Single figure of merit, from 0 to 1: the coverage, discounted by up to a half when every
+    /// chunk switches pheaders
+    pub fn score(&self) -> f64 {
+        self.coverage() * (1.0 - self.switch_rate() / 2.0)
+    }
+}
+
+impl std::fmt::Display for OrdQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "chunks: {} ({} pages, mean {:.1} pages)",
+            self.n_chunks,
+            self.ord_pages,
+            self.mean_chunk_pages()
+        )?;
+        writeln!(
+            f,
+            "coverage: {} of {} private pages ({:.1}%)",
+            self.covered_private_pages,
+            self.private_pages,
+            self.coverage() * 100.0
+        )?;
+        writeln!(
+            f,
+            "vma switches: {} ({:.1}% of the transitions)",
+            self.vma_switches,
+            self.switch_rate() * 100.0
+        )?;
+        writeln!(f, "mean stride: {:#x} B", self.mean_stride as u64)?;
+        writeln!(f, "score: {:.3}", self.score())
+    }
+}
+
+impl Jif {
+    /// Measure the quality of the ordering section against the interval trees
+    pub fn ord_quality(&self) -> OrdQuality {
+        let page_size = self.arch.page_size;
+
+        let covered = self
+            .ord_chunks
+            .iter()
+            .flat_map(|chunk| chunk.pages(page_size))
+            .filter(|page| {
+                self.resolve(*page)
+                    .is_some_and(|ival| ival.source == DataSource::Private)
+            })
+            .collect::<BTreeSet<_>>();
+
+        let (mut vma_switches, mut strides) = (0, 0u64);
+        for (prev, next) in self.ord_chunks.iter().zip(self.ord_chunks.iter().skip(1)) {
+            if self.mapping_pheader_idx(prev.addr()) != self.mapping_pheader_idx(next.addr()) {
+                vma_switches += 1;
+            }
+            strides += next.addr().abs_diff(prev.end(page_size));
+        }
+
+        OrdQuality {
+            n_chunks: self.ord_chunks.len(),
+            ord_pages: self.ord_chunks.iter().map(|chunk| chunk.size()).sum(),
+            private_pages: self.private_pages(),
+            covered_private_pages: covered.len(),
+            vma_switches,
+            mean_stride: if self.ord_chunks.len() < 2 {
+                0.0
+            } else {
+                strides as f64 / (self.ord_chunks.len() - 1) as f64
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::test::gen_jif;
+    use crate::ord::OrdChunk;
+
+    #[test]
+    fn ord_quality() {
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x5000), &[(0x1000, 0x2000), (0x3000, 0x5000)]),
+            ((0x10000, 0x14000), &[(0x10000, 0x14000)]),
+        ]);
+        assert_eq!(jif.ord_quality().score(), 0.0);
+
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x3000, 2, DataSource::Private),
+            OrdChunk::new(0x10000, 2, DataSource::Private),
+            OrdChunk::new(0x12000, 1, DataSource::Private),
+            OrdChunk::new(0x2000, 1, DataSource::Zero),
+        ])
+        .unwrap();
+        let quality = jif.ord_quality();
+        assert_eq!(
+            quality,
+            OrdQuality {
+                n_chunks: 4,
+                ord_pages: 6,
+                private_pages: 7,
+                covered_private_pages: 5,
+                vma_switches: 2,
+                // 0x5000 -> 0x10000, 0x12000 -> 0x12000, 0x13000 -> 0x2000
+                mean_stride: (0xb000 + 0x11000) as f64 / 3.0,
+            }
+        );
+        assert_eq!(quality.mean_chunk_pages(), 1.5);
+        assert_eq!(quality.coverage(), 5.0 / 7.0);
+        assert_eq!(quality.score(), 5.0 / 7.0 * (1.0 - 2.0 / 3.0 / 2.0));
+    }
+}
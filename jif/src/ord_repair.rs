@@ -0,0 +1,431 @@
+//! Ordering section repair and normalization
+//!
+//! The ordering section is checked against the pheaders when it is added, but the JIF can change
+//! under it afterwards (e.g., dropping pheaders, rebasing or rebuilding the interval trees).
+//! Repairing re-validates every chunk against the current pheaders: the chunks are realigned to
+//! the page size, the pages which are no longer mapped are pruned, and the chunks are split where
+//! the data source of their pages changes (so that their kind matches it)
+//!
+//! Normalizing makes each chunk map into exactly one logical interval, which is what prefetching
+//! expects of them (see [`Jif::normalize_ordering`]). Compacting merges the chunks which continue
+//! one another in the same interval (see [`Jif::compact_ordering`])
+
+use crate::error::*;
+use crate::itree::interval::{DataSource, LogicalInterval};
+use crate::jif::{sort_ord_chunks_by_kind, Jif};
+use crate::ord::OrdChunk;
+use crate::utils::{is_page_aligned, page_align_down};
+
+/// Result of a repair of the ordering section (see [`Jif::repair_ord_chunks`])
+#[derive(Debug, Default)]
+pub struct OrdRepairStats {
+    /// Problems found, by index of the chunk in the ordering section before the repair: the
+    /// chunks which were not page aligned, and the first page of each run of unmapped pages
+    pub findings: Vec<(usize, OrdChunkError)>,
+
+    /// Number of chunks before the repair
+    pub chunks_before: usize,
+
+    /// Number of chunks after the repair
+    pub chunks_after: usize,
+
+    /// Number of unmapped pages pruned
+    pub dropped_pages: usize,
+
+    /// Number of pages whose kind was changed to their current data source
+    pub retagged_pages: usize,
+}
+
+impl OrdRepairStats {
+    /// Whether the ordering section was left untouched
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty() && self.retagged_pages == 0
+    }
+}
+
+/// Result of a normalization of the ordering section (see [`Jif::normalize_ordering`])
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OrdNormalizeStats {
+    /// Number of chunks before the normalization
+    pub chunks_before: usize,
+
+    /// Number of chunks after the normalization
+    pub chunks_after: usize,
+
+    /// Number of chunks which were split across logical intervals
+    pub split_chunks: usize,
+}
+
+/// Result of a compaction of the ordering section (see [`Jif::compact_ordering`])
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OrdCompactStats {
+    /// Number of chunks before the compaction
+    pub chunks_before: usize,
+
+    /// Number of chunks after the compaction
+    pub chunks_after: usize,
+}
+
+impl OrdCompactStats {
+    /// Number of chunks merged into the ones before them
+    pub fn merged_chunks(&self) -> usize {
+        self.chunks_before - self.chunks_after
+    }
+}
+
+impl Jif {
+    /// Add a new ordering section, pruning what does not fit the pheaders instead of rejecting
+    /// it (see [`Jif::repair_ord_chunks`])
+    pub fn add_ordering_info_lenient(&mut self, ordering_info: Vec<OrdChunk>) -> OrdRepairStats {
+        self.ord_chunks = ordering_info;
+        self.repair_ord_chunks()
+    }
+
+    /// Re-validate the ordering section against the current pheaders, fixing or pruning the
+    /// stale chunks
+    ///
+    /// The chunks keep their order and provenance: a chunk which is split is replaced by its
+    /// parts, and a chunk none of whose pages is mapped is dropped
+    pub fn repair_ord_chunks(&mut self) -> OrdRepairStats {
+        let page_size = self.arch.page_size;
+        let mut stats = OrdRepairStats {
+            chunks_before: self.ord_chunks.len(),
+            ..Default::default()
+        };
+
+        let mut repaired = Vec::with_capacity(self.ord_chunks.len());
+        for (ord_chunk_idx, chunk) in std::mem::take(&mut self.ord_chunks).into_iter().enumerate() {
+            let mut vaddr = chunk.vaddr;
+            if !is_page_aligned(vaddr, page_size) {
+                stats
+                    .findings
+                    .push((ord_chunk_idx, OrdChunkError::BadAlignment(vaddr)));
+                vaddr = page_align_down(vaddr, page_size);
+            }
+
+            // runs of consecutive pages with the same source
+            let mut run: Option<(u64, u64, DataSource)> = None;
+            let mut unmapped = false;
+            for idx in 0..chunk.n_pages {
+                let Some(page) = vaddr.checked_add(idx * page_size as u64) else {
+                    stats.dropped_pages += (chunk.n_pages - idx) as usize;
+                    break;
+                };
+
+                let Some(source) = self.resolve(page).map(|ival| ival.source) else {
+                    if !unmapped {
+                        stats
+                            .findings
+                            .push((ord_chunk_idx, OrdChunkError::UnmappedAddress(page)));
+                    }
+                    unmapped = true;
+                    stats.dropped_pages += 1;
+                    repaired.extend(run.take().map(|run| run_chunk(&chunk, run)));
+                    continue;
+                };
+                unmapped = false;
+
+                if source != chunk.kind {
+                    stats.retagged_pages += 1;
+                }
+                run = match run {
+                    Some((start, n_pages, kind)) if kind == source => {
+                        Some((start, n_pages + 1, kind))
+                    }
+                    run => {
+                        repaired.extend(run.map(|run| run_chunk(&chunk, run)));
+                        Some((page, 1, source))
+                    }
+                };
+            }
+            repaired.extend(run.map(|run| run_chunk(&chunk, run)));
+        }
+
+        stats.chunks_after = repaired.len();
+        self.ord_chunks = repaired;
+        stats
+    }
+}
+
+impl Jif {
+    /// Split the ordering chunks so that each one maps into exactly one logical interval, and
+    /// merge back the consecutive chunks which continue one another in the same interval
+    ///
+    /// The order of the pages is kept: a chunk is only merged into the one right before it in the
+    /// ordering section, if it starts where that one ends (keeping the thread of the first, and
+    /// recording a write if either was faulted in by one). The unmapped pages (see
+    /// [`Jif::repair_ord_chunks`]) are kept, in chunks of their own
+    pub fn normalize_ordering(&mut self) -> OrdNormalizeStats {
+        let page_size = self.arch.page_size as u64;
+        let mut stats = OrdNormalizeStats {
+            chunks_before: self.ord_chunks.len(),
+            ..Default::default()
+        };
+
+        // the interval each normalized chunk maps into
+        let mut normalized: Vec<(OrdChunk, Option<LogicalInterval>)> = Vec::new();
+        for chunk in std::mem::take(&mut self.ord_chunks) {
+            let end = chunk.vaddr.saturating_add(chunk.n_pages * page_size);
+            let mut n_parts = 0;
+            let mut page = chunk.vaddr;
+            while page < end {
+                let ival = self.resolve(page);
+                let part_end = ival.map_or(page + page_size, |ival| std::cmp::min(ival.end, end));
+                let n_pages = (part_end - page).div_ceil(page_size);
+                n_parts += 1;
+
+                match normalized.last_mut() {
+                    Some((last, last_ival))
+                        if *last_ival == ival
+                            && last.kind == chunk.kind
+                            && last.vaddr + last.n_pages * page_size == page =>
+                    {
+                        last.n_pages += n_pages;
+                        last.record_access(chunk.access);
+                        if let Some(weight) = chunk.part(page, n_pages, chunk.kind).weight {
+                            last.add_weight(weight);
+                        }
+                    }
+                    _ => normalized.push((chunk.part(page, n_pages, chunk.kind), ival)),
+                }
+                page += n_pages * page_size;
+            }
+
+            if n_parts > 1 {
+                stats.split_chunks += 1;
+            }
+        }
+
+        self.ord_chunks = normalized.into_iter().map(|(chunk, _ival)| chunk).collect();
+        stats.chunks_after = self.ord_chunks.len();
+        stats
+    }
+}
+
+impl Jif {
+    /// Merge each ordering chunk into the one right before it in the ordering section, if it has
+    /// the same kind, starts where that one ends and continues it in the same logical interval
+    ///
+    /// Unlike [`Jif::normalize_ordering`], no chunk is split: this only undoes the fragmentation
+    /// of the ordering section, which is left with a chunk per run of the trace through an
+    /// interval (e.g., once the chunks are grouped by kind, when setting up the prefetch: see
+    /// [`crate::JifRaw::from_materialized`]). The merged chunk keeps the thread of the first,
+    /// records a write if either was faulted in by one and adds up their weights
+    pub fn compact_ordering(&mut self) -> OrdCompactStats {
+        let page_size = self.arch.page_size as u64;
+        let chunks_before = self.ord_chunks.len();
+
+        let mut compacted: Vec<OrdChunk> = Vec::with_capacity(chunks_before);
+        for chunk in std::mem::take(&mut self.ord_chunks) {
+            match compacted.last_mut() {
+                Some(last)
+                    if last.kind == chunk.kind
+                        && last.vaddr + last.n_pages * page_size == chunk.vaddr
+                        && self.resolve(chunk.vaddr).is_some()
+                        && self.resolve(chunk.vaddr - page_size) == self.resolve(chunk.vaddr) =>
+                {
+                    last.n_pages += chunk.n_pages;
+                    last.record_access(chunk.access);
+                    if let Some(weight) = chunk.weight {
+                        last.add_weight(weight);
+                    }
+                }
+                _ => compacted.push(chunk),
+            }
+        }
+
+        self.ord_chunks = compacted;
+        OrdCompactStats {
+            chunks_before,
+            chunks_after: self.ord_chunks.len(),
+        }
+    }
+
+    /// Group the ordering chunks by kind, as they are written out, and compact them: the chunks
+    /// of a kind which were apart in the trace may then continue one another
+    pub(crate) fn compact_ordering_by_kind(&mut self) -> OrdCompactStats {
+        sort_ord_chunks_by_kind(&mut self.ord_chunks);
+        self.compact_ordering()
+    }
+}
+
+/// The part of a chunk covering a run of pages (keeping its provenance)
+fn run_chunk(chunk: &OrdChunk, (vaddr, n_pages, kind): (u64, u64, DataSource)) -> OrdChunk {
+    chunk.part(vaddr, n_pages, kind)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ord::AccessKind;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn repair_ord_chunks() {
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x10000, 0x14000),
+            ProtFlags::READ,
+            vec![(0x10000, vec![1; 2 * PAGE_SIZE])],
+        );
+        builder.add_anonymous_segment((0x20000, 0x22000), ProtFlags::READ, vec![]);
+        let mut jif = builder.build().unwrap();
+
+        let chunks = vec![
+            // spans the private and zero pages
+            OrdChunk::new(0x10000, 4, DataSource::Private)
+                .with_provenance(Some(7), Some(AccessKind::Write)),
+            // partially unmapped
+            OrdChunk::new(0x13000, 3, DataSource::Zero),
+            // unmapped
+            OrdChunk::new(0x30000, 2, DataSource::Private),
+            OrdChunk::new(0x21000, 1, DataSource::Zero),
+        ];
+        assert!(jif.add_ordering_info(chunks.clone()).is_err());
+
+        let stats = jif.add_ordering_info_lenient(chunks);
+        assert_eq!(stats.chunks_before, 4);
+        assert_eq!(stats.chunks_after, 4);
+        assert_eq!(stats.dropped_pages, 4);
+        assert_eq!(stats.retagged_pages, 2);
+        assert_eq!(
+            stats
+                .findings
+                .iter()
+                .map(|(idx, error)| match error {
+                    OrdChunkError::UnmappedAddress(vaddr) => (*idx, *vaddr),
+                    error => panic!("unexpected finding: {}", error),
+                })
+                .collect::<Vec<_>>(),
+            vec![(1, 0x14000), (2, 0x30000)]
+        );
+
+        let ord_chunks = jif.ord_chunks();
+        assert_eq!(
+            ord_chunks
+                .iter()
+                .map(|chunk| (chunk.addr(), chunk.size(), chunk.kind()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x10000, 2, DataSource::Private),
+                (0x12000, 2, DataSource::Zero),
+                (0x13000, 1, DataSource::Zero),
+                (0x21000, 1, DataSource::Zero),
+            ]
+        );
+        assert_eq!(ord_chunks[1].tid(), Some(7));
+        assert_eq!(ord_chunks[1].access(), Some(AccessKind::Write));
+        assert!(jif.validate_ord_chunks(ord_chunks).is_ok());
+
+        // repairing a valid ordering section leaves it untouched
+        let before = jif.ord_chunks().to_vec();
+        assert!(jif.repair_ord_chunks().is_clean());
+        assert_eq!(jif.ord_chunks(), before);
+    }
+
+    #[test]
+    fn normalize_ordering() {
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x10000, 0x14000),
+            ProtFlags::READ,
+            vec![(0x10000, vec![1; 2 * PAGE_SIZE])],
+        );
+        builder.add_anonymous_segment((0x14000, 0x16000), ProtFlags::READ, vec![]);
+        builder.set_ordering(vec![
+            // spans the private and zero intervals, and the next pheader
+            OrdChunk::new(0x11000, 4, DataSource::Private).with_provenance(Some(1), None),
+            // continues the previous chunk
+            OrdChunk::new(0x15000, 1, DataSource::Private)
+                .with_provenance(Some(2), Some(AccessKind::Write)),
+            // does not continue it
+            OrdChunk::new(0x10000, 1, DataSource::Private),
+        ]);
+        let mut jif = builder.build().unwrap();
+
+        let stats = jif.normalize_ordering();
+        assert_eq!(
+            stats,
+            OrdNormalizeStats {
+                chunks_before: 3,
+                chunks_after: 4,
+                split_chunks: 1,
+            }
+        );
+        assert_eq!(
+            jif.ord_chunks()
+                .iter()
+                .map(|chunk| (chunk.addr(), chunk.size(), chunk.tid(), chunk.access()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x11000, 1, Some(1), None),
+                (0x12000, 2, Some(1), None),
+                (0x14000, 2, Some(1), Some(AccessKind::Write)),
+                (0x10000, 1, None, None),
+            ]
+        );
+        for chunk in jif.ord_chunks() {
+            let ival = jif.resolve(chunk.addr()).unwrap();
+            assert!(chunk.end(PAGE_SIZE) <= ival.end);
+        }
+
+        // normalizing is idempotent
+        let before = jif.ord_chunks().to_vec();
+        assert_eq!(jif.normalize_ordering().split_chunks, 0);
+        assert_eq!(jif.ord_chunks(), before);
+    }
+
+    #[test]
+    fn compact_ordering() {
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x10000, 0x18000),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![(0x10000, vec![1; 4 * PAGE_SIZE])],
+            )
+            .add_anonymous_segment((0x18000, 0x1a000), ProtFlags::READ, vec![]);
+        let mut jif = builder.build().unwrap();
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x10000, 1, DataSource::Private).with_provenance(Some(1), None),
+            OrdChunk::new(0x11000, 2, DataSource::Private)
+                .with_provenance(Some(2), Some(AccessKind::Write)),
+            // the end of the interval
+            OrdChunk::new(0x13000, 1, DataSource::Private),
+            // the next interval
+            OrdChunk::new(0x14000, 1, DataSource::Zero),
+            OrdChunk::new(0x15000, 3, DataSource::Zero),
+            // the next pheader
+            OrdChunk::new(0x18000, 1, DataSource::Zero),
+            // not continuing the one before
+            OrdChunk::new(0x11000, 1, DataSource::Private),
+        ])
+        .unwrap();
+
+        let stats = jif.compact_ordering();
+        assert_eq!(
+            stats,
+            OrdCompactStats {
+                chunks_before: 7,
+                chunks_after: 4,
+            }
+        );
+        assert_eq!(stats.merged_chunks(), 3);
+        assert_eq!(
+            jif.ord_chunks()
+                .iter()
+                .map(|chunk| (chunk.addr(), chunk.size(), chunk.tid(), chunk.access()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x10000, 4, Some(1), Some(AccessKind::Write)),
+                (0x14000, 4, None, None),
+                (0x18000, 1, None, None),
+                (0x11000, 1, None, None),
+            ]
+        );
+
+        // compacting is idempotent
+        assert_eq!(jif.compact_ordering().merged_chunks(), 0);
+    }
+}
@@ -0,0 +1,260 @@
+//! Page granularity deduplication
+//!
+//! The [`Deduper`] only shares identical data segments, so identical pages inside different
+//! intervals are stored once per interval. Splitting the intervals around the duplicated pages
+//! makes each of them a data segment of its own, which is then stored once (with every interval
+//! referencing it pointing to the same offset of the data section)
+
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::pheader::JifPheader;
+use std::collections::HashSet;
+
+/// Statistics of a page deduplication pass (see [`Jif::dedup_pages`])
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PageDedupStats {
+    /// Number of private pages which were found elsewhere in the JIF
+    pub duplicate_pages: usize,
+
+    /// Bytes of private data stored before the pass
+    pub stored_bytes_before: usize,
+
+    /// Bytes of private data stored after the pass
+    pub stored_bytes_after: usize,
+}
+
+impl PageDedupStats {
+    /// Bytes saved by the pass
+    pub fn saved_bytes(&self) -> usize {
+        self.stored_bytes_before - self.stored_bytes_after
+    }
+}
+
+impl Jif {
+    /// Deduplicate the private data at page granularity, such that identical pages (across
+    /// intervals and pheaders) are stored once when the JIF is written out
+    ///
+    /// This comes at the cost of more intervals, as the duplicated pages are split into their own
+    pub fn dedup_pages(&mut self) -> JifResult<PageDedupStats> {
+        let stored_bytes_before = self.stored_data_size();
+        let page_size = self.arch.page_size;
+
+        // addresses of the pages whose contents appear more than once
+        let (duplicate_pages, duplicate_addrs) = {
+            let duplicated = self.duplicated_pages();
+            (
+                duplicated
+                    .iter()
+                    .map(|addrs| addrs.len() - 1)
+                    .sum::<usize>(),
+                duplicated.into_iter().flatten().collect::<HashSet<_>>(),
+            )
+        };
+
+        for pheader in self.pheaders.iter_mut() {
+            let virtual_range = pheader.virtual_range();
+            let invalid_itree = |error| JifError::InvalidITree {
+                virtual_range,
+                error,
+            };
+            match pheader {
+                JifPheader::Anonymous { itree, .. } => {
+                    let intervals = split_duplicates(
+                        itree.take().into_iter_intervals(),
+                        &self.deduper,
+                        &duplicate_addrs,
+                        page_size,
+                        AnonIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                }
+                JifPheader::Reference { itree, .. } => {
+                    let intervals = split_duplicates(
+                        itree.take().into_iter_intervals(),
+                        &self.deduper,
+                        &duplicate_addrs,
+                        page_size,
+                        RefIntervalData::Owned,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                }
+            }
+        }
+
+        Ok(PageDedupStats {
+            duplicate_pages,
+            stored_bytes_before,
+            stored_bytes_after: self.stored_data_size(),
+        })
+    }
+
+    /// Size of the private data once deduplicated (i.e., when written out)
+    pub(crate) fn stored_data_size(&self) -> usize {
+        self.pheaders
+            .iter()
+            .flat_map(|pheader| data_intervals(pheader, &self.deduper))
+            .map(|(_start, data)| data)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|data| data.len())
+            .sum()
+    }
+}
+
+/// The data intervals of a pheader, as `(start, data)` pairs
+fn data_intervals<'a>(
+    pheader: &'a JifPheader,
+    deduper: &'a Deduper,
+) -> Box<dyn Iterator<Item = (u64, &'a [u8])> + 'a> {
+    fn with_data<'a, Data: IntervalData>(
+        itree: &'a ITree<Data>,
+        deduper: &'a Deduper,
+    ) -> Box<dyn Iterator<Item = (u64, &'a [u8])> + 'a> {
+        Box::new(
+            itree
+                .in_order_intervals()
+                .filter_map(move |ival| ival.data.get_data(deduper).map(|data| (ival.start, data))),
+        )
+    }
+
+    match pheader {
+        JifPheader::Anonymous { itree, .. } => with_data(itree, deduper),
+        JifPheader::Reference { itree, .. } => with_data(itree, deduper),
+    }
+}
+
+/// Split the intervals such that every duplicated page is an interval of its own (the runs of
+/// pages which are not duplicated are kept together)
+fn split_duplicates<Data: IntervalData>(
+    intervals: impl Iterator<Item = Interval<Data>>,
+    deduper: &Deduper,
+    duplicate_addrs: &HashSet<u64>,
+    page_size: usize,
+    owned: fn(Vec<u8>) -> Data,
+) -> Vec<Interval<Data>> {
+    let mut split = Vec::new();
+    for interval in intervals {
+        let Some(data) = interval.data.get_data(deduper) else {
+            split.push(interval);
+            continue;
+        };
+
+        let is_duplicate = |page_idx: usize| {
+            duplicate_addrs.contains(&(interval.start + (page_idx * page_size) as u64))
+        };
+        let n_pages = data.len() / page_size;
+
+        // indices of the pages which start a new run
+        let run_starts = (0..n_pages)
+            .filter(|idx| *idx == 0 || is_duplicate(*idx) || is_duplicate(idx - 1))
+            .chain(std::iter::once(n_pages))
+            .collect::<Vec<_>>();
+
+        if run_starts.len() <= 2 {
+            split.push(interval);
+            continue;
+        }
+
+        for run in run_starts.windows(2) {
+            let (first, last) = (run[0] * page_size, run[1] * page_size);
+            split.push(Interval::new(
+                interval.start + first as u64,
+                interval.start + last as u64,
+                owned(data[first..last].to_vec()),
+            ));
+        }
+    }
+
+    split
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::jif::JifRaw;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::collections::BTreeMap;
+    use std::io::BufReader;
+
+    fn gen_anon(vaddr_range: (u64, u64), ivals: &[(u64, &[u8])]) -> JifPheader {
+        JifPheader::Anonymous {
+            vaddr_range,
+            itree: ITree::build(
+                ivals
+                    .iter()
+                    .map(|(start, pages)| {
+                        Interval::new(
+                            *start,
+                            start + (pages.len() * PAGE_SIZE) as u64,
+                            AnonIntervalData::Owned(
+                                pages
+                                    .iter()
+                                    .flat_map(|byte| std::iter::repeat_n(*byte, PAGE_SIZE))
+                                    .collect(),
+                            ),
+                        )
+                    })
+                    .collect(),
+                vaddr_range,
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+        }
+    }
+
+    #[test]
+    fn dedup_pages_roundtrip() {
+        let gen_jif = || Jif {
+            pheaders: vec![
+                gen_anon(
+                    (0x1000, 0x10000),
+                    &[(0x1000, &[1, 2, 3, 4]), (0x8000, &[5])],
+                ),
+                gen_anon(
+                    (0x20000, 0x30000),
+                    &[(0x20000, &[6, 3, 7, 1]), (0x28000, &[5])],
+                ),
+            ],
+            ord_chunks: vec![],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        };
+
+        let mut jif = gen_jif();
+        let stats = jif.dedup_pages().unwrap();
+        assert_eq!(
+            stats,
+            PageDedupStats {
+                duplicate_pages: 3,
+                // the intervals made of page 5 were already deduplicated
+                stored_bytes_before: 9 * PAGE_SIZE,
+                stored_bytes_after: 7 * PAGE_SIZE,
+            }
+        );
+        assert_eq!(stats.saved_bytes(), 2 * PAGE_SIZE);
+        assert_eq!(jif.pheaders()[0].itree().n_intervals(), 5);
+
+        let raw = JifRaw::from_materialized(jif, false);
+        assert_eq!(raw.data_size(), 7 * PAGE_SIZE);
+
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        let orig = gen_jif();
+        for addr in (0x1000..0x10000).chain(0x20000..0x30000).step_by(PAGE_SIZE) {
+            assert_eq!(
+                read.resolve_data(addr),
+                orig.resolve_data(addr),
+                "{:#x}",
+                addr
+            );
+        }
+    }
+}
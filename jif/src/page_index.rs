@@ -0,0 +1,242 @@
+//! Page hash index
+//!
+//! Deduplicating across snapshots (e.g., building a delta against a base, or sharing pages in a
+//! [`crate::JifSet`]) starts by hashing every page of the data section. A JIF can carry these
+//! digests in an index section, so that readers look them up instead of hashing the data again.
+//! The index trails the data section (preceding the custom metadata section: see
+//! [`crate::meta`]), and is laid out as:
+//!  - for every page, in digest order (and then in offset order): its SHA-256 digest and its
+//!    offset in the (decompressed) data section (`u64`)
+//!  - size of the section, including the footer (`u64`)
+//!  - [`PAGE_INDEX_MAGIC`]
+//!
+//! As the custom metadata section, it is found by its footer, and only written when asked for
+//! (see [`JifRaw::set_page_hash_index`]). When reading a JIF, the digests of the index are
+//! trusted as much as its data is: the integrity section covers both
+
+use crate::digest::{sha256, Sha256Hash};
+use crate::itree::interval::DataSource;
+use crate::jif::{Jif, JifRaw};
+use crate::meta::FOOTER_SIZE;
+use crate::utils::par_map;
+
+use std::borrow::Cow;
+
+/// Magic number at the end of the page hash index
+pub(crate) const PAGE_INDEX_MAGIC: [u8; 4] = [0x77, b'J', b'P', b'X'];
+
+/// Size of an entry of the page hash index: the digest and the offset
+pub(crate) const ENTRY_SIZE: usize = std::mem::size_of::<Sha256Hash>() + std::mem::size_of::<u64>();
+
+/// Pages by content: the locations of the pages with each SHA-256 digest
+///
+/// The locations are offsets in the data section for the index of a [`JifRaw`], and virtual
+/// addresses for the index of a [`Jif`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageHashIndex {
+    /// sorted by digest, and then by location
+    pub(crate) entries: Vec<(Sha256Hash, u64)>,
+}
+
+impl PageHashIndex {
+    /// Index pages, given as `(location, digest)`
+    pub fn from_pages<I: IntoIterator<Item = (u64, Sha256Hash)>>(pages: I) -> Self {
+        let mut entries = pages
+            .into_iter()
+            .map(|(location, digest)| (digest, location))
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+        PageHashIndex { entries }
+    }
+
+    /// Number of pages in the index
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Lowest location of a page with a digest
+    pub fn get(&self, digest: &Sha256Hash) -> Option<u64> {
+        self.locations(digest).next()
+    }
+
+    /// Locations of the pages with a digest, in increasing order
+    pub fn locations<'a>(&'a self, digest: &Sha256Hash) -> impl Iterator<Item = u64> + 'a {
+        let first = self.entries.partition_point(|(found, _)| found < digest);
+        let digest = *digest;
+        self.entries[first..]
+            .iter()
+            .take_while(move |(found, _)| *found == digest)
+            .map(|(_, location)| *location)
+    }
+
+    /// Iterate over the pages, as `(digest, location)`, in digest order
+    pub fn iter(&self) -> impl Iterator<Item = (&Sha256Hash, u64)> {
+        self.entries
+            .iter()
+            .map(|(digest, location)| (digest, *location))
+    }
+
+    /// Number of distinct digests
+    pub fn n_unique(&self) -> usize {
+        self.entries
+            .chunk_by(|(lhs, _), (rhs, _)| lhs == rhs)
+            .count()
+    }
+
+    /// Size of the index section, as written
+    pub(crate) fn serialized_size(&self) -> u64 {
+        (self.entries.len() * ENTRY_SIZE + FOOTER_SIZE) as u64
+    }
+}
+
+impl JifRaw {
+    /// Index the pages of the data segments by their digests, hashing them in parallel
+    fn compute_page_hash_index(&self) -> PageHashIndex {
+        let page_size = self.arch.page_size;
+        let pages = self
+            .data_segments
+            .iter()
+            .flat_map(|((start, _end), data)| {
+                data.chunks(page_size)
+                    .enumerate()
+                    .map(move |(idx, page)| (start + (idx * page_size) as u64, page))
+            })
+            .collect::<Vec<_>>();
+
+        PageHashIndex::from_pages(par_map(&pages, |(offset, page)| (*offset, sha256(page))))
+    }
+
+    /// Index of the pages of the data segments (by their offsets in the data section)
+    ///
+    /// The index is the one read from the JIF when it has one (or the one set with
+    /// [`JifRaw::set_page_hash_index`]), and is computed otherwise
+    pub fn page_hash_index(&self) -> Cow<'_, PageHashIndex> {
+        match &self.page_index {
+            Some(index) => Cow::Borrowed(index),
+            None => Cow::Owned(self.compute_page_hash_index()),
+        }
+    }
+
+    /// Check whether the JIF has a page hash index
+    pub fn has_page_hash_index(&self) -> bool {
+        self.page_index.is_some()
+    }
+
+    /// Set whether to write a page hash index (see [`crate::page_index`]), computing it if
+    /// needed
+    pub fn set_page_hash_index(&mut self, enable: bool) {
+        self.page_index = match (enable, self.page_index.take()) {
+            (false, _) => None,
+            (true, Some(index)) => Some(index),
+            (true, None) => Some(self.compute_page_hash_index()),
+        };
+    }
+
+    /// Recompute the page hash index, if the JIF has one, after its data segments changed
+    pub(crate) fn refresh_page_hash_index(&mut self) {
+        if self.page_index.is_some() {
+            self.page_index = Some(self.compute_page_hash_index());
+        }
+    }
+}
+
+impl Jif {
+    /// Index of the private pages (by their virtual addresses)
+    ///
+    /// The digests of the data read from a JIF with a page hash index are loaded from the index
+    /// (see [`crate::page_index`]); the others are hashed (once: see [`Jif::page_digests`])
+    pub fn page_hash_index(&self) -> PageHashIndex {
+        PageHashIndex::from_pages(self.page_digests(DataSource::Private))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::Compression;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    fn gen_jif() -> Jif {
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x100000, 0x100000 + 4 * PAGE_SIZE as u64),
+            ProtFlags::READ | ProtFlags::WRITE,
+            vec![(
+                0x101000,
+                [vec![1; PAGE_SIZE], vec![2; PAGE_SIZE], vec![1; PAGE_SIZE]].concat(),
+            )],
+        );
+        let mut jif = builder.build().unwrap();
+        jif.set_metadata("build", "v1.2.3");
+        jif
+    }
+
+    #[test]
+    fn round_trip() {
+        let (ones, twos) = (sha256(&[1; PAGE_SIZE]), sha256(&[2; PAGE_SIZE]));
+        for (compression, checksums) in [(Compression::None, false), (Compression::Lz4, true)] {
+            let mut raw = JifRaw::from_materialized(gen_jif(), false);
+            raw.set_compression(compression);
+            raw.set_checksums(checksums);
+            let computed = raw.page_hash_index().into_owned();
+            assert_eq!((computed.len(), computed.n_unique()), (3, 2));
+            assert_eq!(
+                computed.locations(&ones).collect::<Vec<_>>(),
+                vec![0, 2 * PAGE_SIZE as u64]
+            );
+            assert_eq!(computed.get(&twos), Some(PAGE_SIZE as u64));
+            assert_eq!(computed.get(&sha256(&[3; PAGE_SIZE])), None);
+
+            raw.set_page_hash_index(true);
+            let mut file = Vec::new();
+            let written = raw.to_writer(&mut file).unwrap();
+            assert_eq!(written, file.len());
+            let layout = raw.layout();
+            assert_eq!(layout.file_size(), file.len() as u64);
+            assert_eq!(
+                layout.page_index.map(|(start, end)| end - start),
+                Some(computed.serialized_size())
+            );
+
+            let read = JifRaw::from_bytes(&file).unwrap();
+            assert_eq!(read.page_hash_index(), Cow::Borrowed(&computed));
+            assert_eq!(read.metadata().get("build").unwrap(), "v1.2.3");
+            let jif = Jif::from_raw(read).unwrap();
+            assert_eq!(jif.resolve_data(0x102000), Some(&[2; PAGE_SIZE][..]));
+            assert_eq!(jif.page_hash_index().get(&ones), Some(0x101000));
+        }
+
+        // no section is written without asking for one
+        let mut raw = JifRaw::from_materialized(gen_jif(), false);
+        let mut file = Vec::new();
+        raw.to_writer(&mut file).unwrap();
+        assert!(!JifRaw::from_bytes(&file).unwrap().has_page_hash_index());
+        raw.set_page_hash_index(false);
+        assert!(!raw.has_page_hash_index());
+    }
+
+    #[test]
+    fn digests_from_index() {
+        // the digests of the index are used as they are, without hashing the data again: a
+        // (deliberately) wrong index shows through
+        let mut raw = JifRaw::from_materialized(gen_jif(), false);
+        let bogus = [0xab; 32];
+        raw.page_index = Some(PageHashIndex::from_pages(
+            (0..3).map(|idx| ((idx * PAGE_SIZE) as u64, bogus)),
+        ));
+        let mut file = Vec::new();
+        raw.to_writer(&mut file).unwrap();
+
+        let jif = Jif::from_bytes(&file).unwrap();
+        assert_eq!(
+            jif.page_digests(DataSource::Private),
+            vec![(0x101000, bogus), (0x102000, bogus), (0x103000, bogus)]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Resolving the referenced files
+//!
+//! The paths recorded in the reference pheaders are those of the machine the snapshot was taken
+//! on. Building the interval trees, reading the shared pages or auditing a JIF elsewhere (e.g.,
+//! from the overlayfs of a container, or a rootfs mounted under another name) takes a
+//! [`PathResolver`] mapping them to the files on this machine
+//!
+//! The recorded paths may also be canonicalized (see [`Jif::canonicalize_paths`]): snapshots
+//! taken inside containers record paths such as `/proc/self/root/../usr/lib/libc.so`, which the
+//! restorer rejects
+
+use crate::jif::Jif;
+
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Maps the paths recorded in the JIF to the files to open
+///
+/// Files may be opened concurrently, so resolvers have to be [`Sync`]
+pub trait PathResolver: Sync {
+    /// Path of the file to open for the referenced `path`
+    fn resolve_path(&self, path: &str) -> PathBuf;
+}
+
+impl<F: Fn(&str) -> PathBuf + Sync> PathResolver for F {
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        self(path)
+    }
+}
+
+/// The paths as recorded in the JIF (the default)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AsRecorded;
+
+impl PathResolver for AsRecorded {
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        PathBuf::from(path)
+    }
+}
+
+/// Path relative to the `chroot`, if any (absolute paths are taken from its root)
+fn chroot_path(path: &Path, chroot: &Option<PathBuf>) -> PathBuf {
+    match chroot {
+        None => path.to_path_buf(),
+        Some(chroot) if path.is_absolute() => chroot.join(path.iter().skip(1).collect::<PathBuf>()),
+        Some(chroot) => chroot.join(path),
+    }
+}
+
+/// The paths relative to the chroot, if any
+impl PathResolver for Option<PathBuf> {
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        chroot_path(Path::new(path), self)
+    }
+}
+
+/// Table of path prefixes to replace, followed by an optional chroot
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PathMap {
+    /// `(old, new)` prefixes, longest first
+    prefixes: Vec<(PathBuf, PathBuf)>,
+
+    chroot: Option<PathBuf>,
+}
+
+impl PathMap {
+    /// Replace the `old` prefix of the paths with `new`, for each `(old, new)` pair
+    ///
+    /// Prefixes match whole path components, and the longest matching one is replaced
+    pub fn new(mut prefixes: Vec<(PathBuf, PathBuf)>) -> Self {
+        prefixes.sort_by_key(|(old, _new)| std::cmp::Reverse(old.components().count()));
+        PathMap {
+            prefixes,
+            chroot: None,
+        }
+    }
+
+    /// Resolve the (mapped) paths relative to the `chroot`, if any
+    pub fn with_chroot(mut self, chroot: Option<PathBuf>) -> Self {
+        self.chroot = chroot;
+        self
+    }
+
+    /// The `(old, new)` prefixes (longest first)
+    pub fn prefixes(&self) -> &[(PathBuf, PathBuf)] {
+        &self.prefixes
+    }
+}
+
+impl PathResolver for PathMap {
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        let mapped = self
+            .prefixes
+            .iter()
+            .find_map(|(old, new)| path.strip_prefix(old).ok().map(|rest| new.join(rest)))
+            .unwrap_or_else(|| path.to_path_buf());
+
+        chroot_path(&mapped, &self.chroot)
+    }
+}
+
+/// Resolve the symlinks and `..` components of the path of a file (without file system access,
+/// i.e., the `fs` feature, there is no file to resolve)
+fn canonicalize_file(path: &Path) -> Option<PathBuf> {
+    if !cfg!(feature = "fs") {
+        return None;
+    }
+
+    std::fs::canonicalize(path).ok()
+}
+
+/// Normalize the `.` and `..` components of a path, without looking at the filesystem (`..`
+/// stops at the root)
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) => {}
+                _ => normalized.push(component),
+            },
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+impl Jif {
+    /// Canonicalize the referenced paths, returning the number of paths which changed
+    ///
+    /// The files (as resolved by `paths`) have their symlinks and `..` components resolved.
+    /// With a `root`, the file has to be inside it, and the path recorded is the one inside the
+    /// root (e.g., `/srv/rootfs/usr/lib/libc.so` is recorded as `/usr/lib/libc.so`). The paths of
+    /// missing files (and of files outside the root) are only normalized lexically
+    pub fn canonicalize_paths(&mut self, paths: &dyn PathResolver, root: Option<&Path>) -> usize {
+        let root = root.map(|root| canonicalize_file(root).unwrap_or_else(|| root.to_path_buf()));
+        let canonicalize = |path: &str| {
+            let canonical = canonicalize_file(&paths.resolve_path(path));
+            let canonical = match &root {
+                None => canonical,
+                Some(root) => canonical.and_then(|canonical| {
+                    canonical
+                        .strip_prefix(root)
+                        .ok()
+                        .map(|inner| Path::new("/").join(inner))
+                }),
+            };
+            canonical.unwrap_or_else(|| normalize_lexically(Path::new(path)))
+        };
+
+        let renames = self
+            .strings()
+            .into_iter()
+            .filter_map(|path| {
+                let canonical = canonicalize(path);
+                let canonical = canonical.to_str()?;
+                (canonical != path).then(|| (path.to_string(), canonical.to_string()))
+            })
+            .collect::<BTreeSet<_>>();
+        for (old, new) in &renames {
+            self.rename_file(old, new);
+        }
+
+        renames.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn path_map() {
+        let chroot = Some(PathBuf::from("/srv/rootfs"));
+        assert_eq!(
+            chroot.resolve_path("/lib/ld.so"),
+            Path::new("/srv/rootfs/lib/ld.so")
+        );
+        assert_eq!(
+            chroot.resolve_path("lib/ld.so"),
+            Path::new("/srv/rootfs/lib/ld.so")
+        );
+        assert_eq!(
+            None::<PathBuf>.resolve_path("/lib/ld.so"),
+            Path::new("/lib/ld.so")
+        );
+        assert_eq!(
+            AsRecorded.resolve_path("/lib/ld.so"),
+            Path::new("/lib/ld.so")
+        );
+
+        let map = PathMap::new(vec![
+            ("/usr".into(), "/mnt/usr".into()),
+            ("/usr/lib".into(), "/overlay/lib".into()),
+        ]);
+        assert_eq!(map.prefixes()[0].0, Path::new("/usr/lib"));
+        assert_eq!(
+            map.resolve_path("/usr/lib/libc.so"),
+            Path::new("/overlay/lib/libc.so")
+        );
+        assert_eq!(
+            map.resolve_path("/usr/bin/ls"),
+            Path::new("/mnt/usr/bin/ls")
+        );
+        // whole components only
+        assert_eq!(map.resolve_path("/usrx/ls"), Path::new("/usrx/ls"));
+
+        let map = map.with_chroot(chroot);
+        assert_eq!(
+            map.resolve_path("/usr/bin/ls"),
+            Path::new("/srv/rootfs/mnt/usr/bin/ls")
+        );
+        assert_eq!(map.resolve_path("/bin/ls"), Path::new("/srv/rootfs/bin/ls"));
+
+        let closure = |path: &str| PathBuf::from(path.replace("ld", "LD"));
+        assert_eq!(closure.resolve_path("/lib/ld.so"), Path::new("/lib/LD.so"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "fs", unix))]
+    fn canonicalize_paths() {
+        let root = std::env::temp_dir().join(format!("jif-canonical-paths-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("usr/lib")).unwrap();
+        std::fs::write(root.join("usr/lib/libc.so"), [0; 16]).unwrap();
+        std::os::unix::fs::symlink("usr/lib", root.join("lib")).unwrap();
+
+        let mut builder = crate::JifBuilder::new();
+        for (idx, path) in [
+            "/lib/libc.so",
+            "/proc/../usr/./lib/libc.so",
+            "/usr/lib/libc.so",
+            "/missing/../lib/ld.so",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let start = 0x10000 * (idx as u64 + 1);
+            builder.add_reference_segment(
+                (start, start + 0x1000),
+                crate::ProtFlags::READ,
+                path.to_string(),
+                0,
+                vec![],
+            );
+        }
+        let mut jif = builder.build().unwrap();
+
+        let chroot = Some(root.clone());
+        let renamed = jif.canonicalize_paths(&chroot, Some(root.as_path()));
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(renamed, 3);
+        assert_eq!(
+            jif.strings().into_iter().collect::<Vec<_>>(),
+            ["/lib/ld.so", "/usr/lib/libc.so"]
+        );
+        assert_eq!(
+            normalize_lexically(Path::new("a/../../b")),
+            Path::new("../b")
+        );
+        assert_eq!(
+            normalize_lexically(Path::new("../../b")),
+            Path::new("../../b")
+        );
+        assert_eq!(
+            normalize_lexically(Path::new("/../b/./c")),
+            Path::new("/b/c")
+        );
+    }
+}
@@ -1,32 +1,25 @@
 //! The pheader representation
 
 use std::collections::BTreeMap;
-use std::u64;
 
 use crate::deduper::{DedupToken, Deduper};
 use crate::error::*;
 use crate::itree::diff::{
     create_anon_itree_from_zero_page, create_itree_from_diff, create_ref_itree_from_zero_page,
+    ExactComparator, PageComparator,
 };
 use crate::itree::interval::{
-    AnonIntervalData, Interval, IntervalData, LogicalInterval, RefIntervalData,
+    AnonIntervalData, DataSource, Interval, IntervalData, LogicalInterval, RefIntervalData,
 };
 use crate::itree::itree_node::IntermediateITreeNode;
 use crate::itree::{ITree, ITreeView};
 use crate::jif::JifRaw;
-use crate::utils::{page_align, PAGE_SIZE};
+use crate::paths::PathResolver;
+pub use crate::prot::ProtFlags;
+use crate::utils::open_file;
 
 use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::path::PathBuf;
-
-/// VMA protection bits
-#[repr(u8)]
-pub enum Prot {
-    Read = 1u8 << 2,
-    Write = 1u8 << 1,
-    Exec = 1u8 << 0,
-}
 
 /// A materialized JIF pheader
 ///
@@ -44,6 +37,7 @@ pub enum Prot {
 /// Failing to resolve means it should be backed by the underlying file mapping.
 ///
 /// Can be used to visualize the VMA and manipulate it (e.g., construct an interal tree)
+#[derive(Clone)]
 pub enum JifPheader {
     Anonymous {
         /// virtual address range
@@ -52,7 +46,7 @@ pub enum JifPheader {
         itree: ITree<AnonIntervalData>,
 
         /// VMA protections
-        prot: u8,
+        prot: ProtFlags,
     },
     Reference {
         /// virtual address range
@@ -61,7 +55,7 @@ pub enum JifPheader {
         itree: ITree<RefIntervalData>,
 
         /// VMA protections
-        prot: u8,
+        prot: ProtFlags,
 
         /// reference path
         ref_path: String,
@@ -117,7 +111,7 @@ impl JifPheader {
                 ref_path,
                 ref_offset,
                 itree,
-                prot: raw.prot,
+                prot: raw.prot(),
             })
         } else {
             let itree = jif.get_anon_itree(
@@ -131,21 +125,35 @@ impl JifPheader {
             Ok(JifPheader::Anonymous {
                 vaddr_range,
                 itree,
-                prot: raw.prot,
+                prot: raw.prot(),
             })
         }
     }
 
-    /// Build an itree for a particular pheader
+    /// Build an itree for a particular pheader (with pages of `page_size`)
     pub fn build_itree(
         &mut self,
         deduper: &Deduper,
-        chroot: &Option<std::path::PathBuf>,
+        page_size: usize,
+        paths: &dyn PathResolver,
+    ) -> ITreeResult<()> {
+        self.build_itree_with(deduper, page_size, paths, &ExactComparator)
+    }
+
+    /// Build an itree for a particular pheader (with pages of `page_size`), comparing the pages of
+    /// a reference pheader with those of the file with the `comparator`
+    pub fn build_itree_with(
+        &mut self,
+        deduper: &Deduper,
+        page_size: usize,
+        paths: &dyn PathResolver,
+        comparator: &dyn PageComparator,
     ) -> ITreeResult<()> {
         fn build_anon_from_zero(
             itree: &mut ITree<AnonIntervalData>,
             virtual_range: (u64, u64),
             deduper: &Deduper,
+            page_size: usize,
         ) -> ITreeResult<()> {
             let orig_itree = itree.take();
             let mut intervals = vec![];
@@ -158,7 +166,12 @@ impl JifPheader {
                 let ival_len = data_interval.len() as usize;
                 if let Some(data) = data_interval.data.get_data(deduper) {
                     assert_eq!(data.len(), ival_len);
-                    create_anon_itree_from_zero_page(data, data_interval.start, &mut intervals)
+                    create_anon_itree_from_zero_page(
+                        data,
+                        data_interval.start,
+                        page_size,
+                        &mut intervals,
+                    )
                 } else {
                     panic!("we checked that this was an interval with data but there was no data");
                 }
@@ -173,46 +186,29 @@ impl JifPheader {
             virtual_range: (u64, u64),
             refs: &str,
             ref_offset: u64,
-            chroot: &Option<std::path::PathBuf>,
+            page_size: usize,
+            paths: &dyn PathResolver,
+            comparator: &dyn PageComparator,
         ) -> ITreeResult<ITree<RefIntervalData>> {
-            let mut file = {
-                let ref_path = PathBuf::from(refs);
-                let full_path = match chroot {
-                    None => ref_path,
-                    Some(cpath) => {
-                        let mut cp = cpath.clone();
-                        if ref_path.is_absolute() {
-                            cp.push(ref_path.iter().skip(1).collect::<std::path::PathBuf>());
-                        } else {
-                            cp.push(ref_path);
-                        }
-                        cp
-                    }
-                };
-                let mut f = BufReader::new(File::open(&full_path)?);
-                f.seek(SeekFrom::Start(ref_offset))?;
-                f
-            };
-
-            let base = {
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf)?;
-
-                let delta_to_page = page_align(buf.len() as u64) as usize - buf.len();
-                if delta_to_page > 0 {
-                    buf.extend(std::iter::repeat(0x00u8).take(delta_to_page));
-                }
-                buf
-            };
-
+            let file = open_file(&paths.resolve_path(refs))?;
             let mut intervals = Vec::new();
-            create_itree_from_diff(&base, overlay, virtual_range.0, &mut intervals);
+            with_file_window(&file, ref_offset, overlay.len(), |base| {
+                create_itree_from_diff(
+                    base,
+                    overlay,
+                    virtual_range.0,
+                    page_size,
+                    (refs, comparator),
+                    &mut intervals,
+                )
+            })?;
             ITree::build(intervals, virtual_range)
         }
         fn build_ref_from_zero(
             itree: &mut ITree<RefIntervalData>,
             virtual_range: (u64, u64),
             deduper: &Deduper,
+            page_size: usize,
         ) -> ITreeResult<()> {
             let orig_itree = itree.take();
             let mut intervals = orig_itree
@@ -229,7 +225,12 @@ impl JifPheader {
                 let ival_len = data_interval.len() as usize;
                 if let Some(data) = data_interval.data.get_data(deduper) {
                     assert_eq!(data.len(), ival_len);
-                    create_ref_itree_from_zero_page(data, data_interval.start, &mut intervals)
+                    create_ref_itree_from_zero_page(
+                        data,
+                        data_interval.start,
+                        page_size,
+                        &mut intervals,
+                    )
                 } else {
                     panic!("we checked that this was an interval with data but there was no data");
                 }
@@ -248,7 +249,7 @@ impl JifPheader {
                 ..
             } => {
                 if itree.n_data_intervals() != 1 {
-                    build_ref_from_zero(itree, *vaddr_range, deduper)?
+                    build_ref_from_zero(itree, *vaddr_range, deduper, page_size)?
                 } else {
                     let data_interval = itree
                         .in_order_intervals()
@@ -256,10 +257,17 @@ impl JifPheader {
                         .expect("we checked there was a data interval");
 
                     if data_interval.start != vaddr_range.0 {
-                        build_ref_from_zero(itree, *vaddr_range, deduper)?
+                        build_ref_from_zero(itree, *vaddr_range, deduper, page_size)?
                     } else if let Some(overlay) = data_interval.data.get_data(deduper) {
-                        *itree =
-                            build_from_diff(overlay, *vaddr_range, ref_path, *ref_offset, chroot)?;
+                        *itree = build_from_diff(
+                            overlay,
+                            *vaddr_range,
+                            ref_path,
+                            *ref_offset,
+                            page_size,
+                            paths,
+                            comparator,
+                        )?;
                     } else {
                         panic!("we checked this was a data interval but there was no data");
                     }
@@ -268,7 +276,7 @@ impl JifPheader {
             JifPheader::Anonymous {
                 itree, vaddr_range, ..
             } => {
-                build_anon_from_zero(itree, *vaddr_range, deduper)?;
+                build_anon_from_zero(itree, *vaddr_range, deduper, page_size)?;
             }
         }
 
@@ -277,17 +285,36 @@ impl JifPheader {
 
     /// Fragment pheader based on data source
     pub fn fragment(
+        self,
+        deduper: &Deduper,
+        page_size: usize,
+        paths: &dyn PathResolver,
+    ) -> JifResult<Vec<JifPheader>> {
+        self.fragment_with(deduper, page_size, paths, &ExactComparator)
+    }
+
+    /// Fragment pheader based on data source, building its itree with the `comparator` (see
+    /// [`JifPheader::build_itree_with`])
+    pub fn fragment_with(
         mut self,
         deduper: &Deduper,
-        chroot: &Option<std::path::PathBuf>,
+        page_size: usize,
+        paths: &dyn PathResolver,
+        comparator: &dyn PageComparator,
     ) -> JifResult<Vec<JifPheader>> {
-        self.build_itree(deduper, chroot)
+        self.build_itree_with(deduper, page_size, paths, comparator)
             .map_err(|error| JifError::InvalidITree {
                 virtual_range: self.virtual_range(),
                 error,
             })?;
 
-        Ok(match self {
+        Ok(self.split_intervals())
+    }
+
+    /// Split the pheader into one pheader per interval (and per gap between intervals), without
+    /// rebuilding the interval tree
+    pub(crate) fn split_intervals(self) -> Vec<JifPheader> {
+        match self {
             JifPheader::Anonymous {
                 vaddr_range,
                 itree,
@@ -391,7 +418,7 @@ impl JifPheader {
                     first_iter.chain(second_iter)
                 })
                 .collect(),
-        })
+        }
     }
 
     /// Rename the file in this pheader if 1) it has a file and 2) it matches the name
@@ -403,19 +430,175 @@ impl JifPheader {
         }
     }
 
+    /// Tokens of the private data of the intervals (once per interval)
+    pub(crate) fn dedup_tokens(&self) -> Vec<DedupToken> {
+        fn tokens<Data: IntervalData>(itree: &ITree<Data>) -> Vec<DedupToken> {
+            itree
+                .in_order_intervals()
+                .filter_map(|ival| ival.data.dedup_token())
+                .collect()
+        }
+
+        match self {
+            JifPheader::Anonymous { itree, .. } => tokens(itree),
+            JifPheader::Reference { itree, .. } => tokens(itree),
+        }
+    }
+
+    /// Tokens of the private data held by the interval tree nodes (including the intervals
+    /// which a malformed tree does not reach, unlike [`JifPheader::dedup_tokens`])
+    pub(crate) fn held_tokens(&self) -> Vec<DedupToken> {
+        fn tokens<Data: IntervalData>(itree: &ITree<Data>) -> Vec<DedupToken> {
+            itree
+                .nodes
+                .iter()
+                .flat_map(|node| node.ranges.iter())
+                .filter_map(|ival| ival.data.dedup_token())
+                .collect()
+        }
+
+        match self {
+            JifPheader::Anonymous { itree, .. } => tokens(itree),
+            JifPheader::Reference { itree, .. } => tokens(itree),
+        }
+    }
+
+    /// Move the data deduplicated in `from` into `to`, reissuing the tokens
+    pub(crate) fn move_data(&mut self, from: &Deduper, to: &mut Deduper) {
+        match self {
+            JifPheader::Anonymous { itree, .. } => {
+                for ival in itree
+                    .nodes
+                    .iter_mut()
+                    .flat_map(|node| node.ranges.iter_mut())
+                {
+                    if let AnonIntervalData::Ref(token) = ival.data {
+                        ival.data = AnonIntervalData::Ref(to.insert(from.get(token).to_vec()));
+                    }
+                }
+            }
+            JifPheader::Reference { itree, .. } => {
+                for ival in itree
+                    .nodes
+                    .iter_mut()
+                    .flat_map(|node| node.ranges.iter_mut())
+                {
+                    if let RefIntervalData::Ref(token) = ival.data {
+                        ival.data = RefIntervalData::Ref(to.insert(from.get(token).to_vec()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Shift the virtual range (and the intervals) by `delta` bytes
+    ///
+    /// The caller has to make sure the shifted range does not overflow
+    pub(crate) fn shift(&mut self, delta: i64) {
+        let shift = |(start, end): (u64, u64)| {
+            (
+                start.wrapping_add_signed(delta),
+                end.wrapping_add_signed(delta),
+            )
+        };
+        match self {
+            JifPheader::Anonymous {
+                vaddr_range, itree, ..
+            } => {
+                *vaddr_range = shift(*vaddr_range);
+                itree.shift(delta);
+            }
+            JifPheader::Reference {
+                vaddr_range, itree, ..
+            } => {
+                *vaddr_range = shift(*vaddr_range);
+                itree.shift(delta);
+            }
+        }
+    }
+
     /// Check whether this pheader maps a particular address
     pub(crate) fn mapps_addr(&self, addr: u64) -> bool {
         self.virtual_range().0 <= addr && addr < self.virtual_range().1
     }
 
-    /// Resolve an address
-    pub(crate) fn resolve(&self, addr: u64) -> LogicalInterval {
+    /// Resolve an address into the logical interval (and its data source) which maps it
+    pub fn resolve(&self, addr: u64) -> LogicalInterval {
         self.itree().resolve(addr)
     }
 
-    /// Resolve an address into a private data page
-    pub(crate) fn resolve_data<'a>(&'a self, addr: u64, deduper: &'a Deduper) -> Option<&'a [u8]> {
-        self.itree().resolve_data(addr, deduper)
+    /// Resolve an address into a private data page (of `page_size`)
+    pub(crate) fn resolve_data<'a>(
+        &'a self,
+        addr: u64,
+        deduper: &'a Deduper,
+        page_size: usize,
+    ) -> Option<&'a [u8]> {
+        self.itree().resolve_data(addr, deduper, page_size)
+    }
+
+    /// Append the logical contents of `[start; end)` (which has to be mapped by this pheader)
+    /// to `out`: private data, zero pages and contents of the referenced file (as resolved by
+    /// `paths`), zero filled past its end
+    pub(crate) fn read_range_into(
+        &self,
+        (start, end): (u64, u64),
+        deduper: &Deduper,
+        page_size: usize,
+        paths: &dyn PathResolver,
+        out: &mut Vec<u8>,
+    ) -> JifResult<()> {
+        let mut file = None;
+        for ival in self
+            .itree()
+            .iter_logical_intervals()
+            .filter(|ival| ival.end > start && ival.start < end)
+        {
+            let (ival_start, ival_end) = (
+                std::cmp::max(ival.start, start),
+                std::cmp::min(ival.end, end),
+            );
+            match ival.source {
+                DataSource::Zero => out.resize(out.len() + (ival_end - ival_start) as usize, 0),
+                DataSource::Private => {
+                    let mut addr = ival_start;
+                    while addr < ival_end {
+                        let page_start = addr - addr % page_size as u64;
+                        let page_end = std::cmp::min(page_start + page_size as u64, ival_end);
+                        let page = self
+                            .resolve_data(page_start, deduper, page_size)
+                            .expect("private intervals have data");
+                        out.extend_from_slice(
+                            &page[(addr - page_start) as usize..(page_end - page_start) as usize],
+                        );
+                        addr = page_end;
+                    }
+                }
+                DataSource::Shared => {
+                    let JifPheader::Reference {
+                        vaddr_range,
+                        ref_path,
+                        ref_offset,
+                        ..
+                    } = self
+                    else {
+                        unreachable!("only reference pheaders have shared intervals");
+                    };
+
+                    if file.is_none() {
+                        file = Some(open_file(&paths.resolve_path(ref_path))?);
+                    }
+                    let file = file.as_mut().unwrap();
+                    file.seek(SeekFrom::Start(ref_offset + (ival_start - vaddr_range.0)))?;
+
+                    let len = (ival_end - ival_start) as usize;
+                    let read = file.take(len as u64).read_to_end(out)?;
+                    out.resize(out.len() + len - read, 0);
+                }
+            }
+        }
+
+        Ok(())
     }
 
     /// The virtual address space range that this pheader maps
@@ -427,7 +610,7 @@ impl JifPheader {
     }
 
     /// A view over the underlying [`ITree`]
-    pub fn itree(&self) -> ITreeView {
+    pub fn itree(&self) -> ITreeView<'_> {
         match self {
             JifPheader::Anonymous { itree, .. } => ITreeView::Anon { inner: itree },
             JifPheader::Reference { itree, .. } => ITreeView::Ref { inner: itree },
@@ -456,7 +639,7 @@ impl JifPheader {
     }
 
     /// The protections concerning this vma
-    pub fn prot(&self) -> u8 {
+    pub fn prot(&self) -> ProtFlags {
         match self {
             JifPheader::Anonymous { prot, .. } => *prot,
             JifPheader::Reference { prot, .. } => *prot,
@@ -468,57 +651,64 @@ impl JifPheader {
         self.itree().private_data_size()
     }
 
-    /// Number of zero pages encoded (by ommission) in this pheader
-    pub fn zero_pages(&self) -> usize {
+    /// Number of zero pages (of `page_size`) encoded (by ommission) in this pheader
+    pub fn zero_pages(&self, page_size: usize) -> usize {
         (match self {
             JifPheader::Anonymous {
                 itree, vaddr_range, ..
             } => itree.implicitely_mapped_subregion_size(vaddr_range.0, vaddr_range.1),
             JifPheader::Reference { itree, .. } => itree.zero_byte_size(),
-        }) / PAGE_SIZE
+        }) / page_size
     }
 
-    /// Number of private data pages in this pheader
-    pub fn private_pages(&self) -> usize {
-        self.data_size() / PAGE_SIZE
+    /// Number of private data pages (of `page_size`) in this pheader
+    pub fn private_pages(&self, page_size: usize) -> usize {
+        self.data_size() / page_size
     }
 
-    /// Number of pages coming from the reference file
-    pub fn shared_pages(&self) -> usize {
+    /// Number of pages (of `page_size`) coming from the reference file
+    pub fn shared_pages(&self, page_size: usize) -> usize {
         (match self {
             JifPheader::Anonymous { .. } => 0,
             JifPheader::Reference {
                 itree, vaddr_range, ..
             } => itree.implicitely_mapped_subregion_size(vaddr_range.0, vaddr_range.1),
-        }) / PAGE_SIZE
+        }) / page_size
     }
 
-    /// Total number of pages in the pheader
-    pub fn total_pages(&self) -> usize {
+    /// Total number of pages (of `page_size`) in the pheader
+    pub fn total_pages(&self, page_size: usize) -> usize {
         let (begin, end) = self.virtual_range();
 
         assert_eq!(
-            (end as usize - begin as usize) / PAGE_SIZE,
-            self.zero_pages() + self.private_pages() + self.shared_pages()
+            (end as usize - begin as usize) / page_size,
+            self.zero_pages(page_size)
+                + self.private_pages(page_size)
+                + self.shared_pages(page_size)
         );
-        (end as usize - begin as usize) / PAGE_SIZE
+        (end as usize - begin as usize) / page_size
     }
 
-    /// Iterate over the private pages in the pheader
+    /// Iterate over the private pages (of `page_size`) in the pheader
     pub(crate) fn iter_private_pages<'a>(
         &'a self,
         deduper: &'a Deduper,
-    ) -> Box<dyn Iterator<Item = &[u8]> + 'a> {
+        page_size: usize,
+    ) -> Box<dyn Iterator<Item = &'a [u8]> + 'a> {
         match self {
-            JifPheader::Anonymous { itree, .. } => Box::new(itree.iter_private_pages(deduper)),
-            JifPheader::Reference { itree, .. } => Box::new(itree.iter_private_pages(deduper)),
+            JifPheader::Anonymous { itree, .. } => {
+                Box::new(itree.iter_private_pages(deduper, page_size))
+            }
+            JifPheader::Reference { itree, .. } => {
+                Box::new(itree.iter_private_pages(deduper, page_size))
+            }
         }
     }
 
     /// Iterate over the private pages in the pheader
     pub(crate) fn iter_shared_regions<'a>(
         &'a self,
-    ) -> Box<dyn Iterator<Item = (&str, u64, u64)> + 'a> {
+    ) -> Box<dyn Iterator<Item = (&'a str, u64, u64)> + 'a> {
         match self {
             JifPheader::Anonymous { .. } => Box::new(std::iter::empty()),
             JifPheader::Reference {
@@ -578,7 +768,7 @@ impl JifRawPheader {
                     itree_idx,
                     itree_n_nodes,
                     pathname_offset: u32::MAX,
-                    prot,
+                    prot: prot.bits(),
                 }
             }
             JifPheader::Reference {
@@ -613,7 +803,7 @@ impl JifRawPheader {
                     itree_idx,
                     itree_n_nodes,
                     pathname_offset,
-                    prot,
+                    prot: prot.bits(),
                 }
             }
         }
@@ -640,11 +830,36 @@ impl JifRawPheader {
     }
 
     /// The protections concerning this vma
-    pub fn prot(&self) -> u8 {
-        self.prot
+    pub fn prot(&self) -> ProtFlags {
+        ProtFlags::from_bits(self.prot)
     }
 }
 
+/// Run `f` over the `[offset; offset + len)` window of a file (cut short where the file ends)
+///
+/// The file is mapped when possible, so that only the pages `f` touches are read in (instead of
+/// copying the whole file, which may be a large library, for every pheader referencing it)
+fn with_file_window<T>(
+    file: &File,
+    offset: u64,
+    len: usize,
+    f: impl FnOnce(&[u8]) -> T,
+) -> std::io::Result<T> {
+    #[cfg(all(feature = "fs", unix, target_pointer_width = "64"))]
+    if let Ok(map) = crate::mmap::Mmap::map(file) {
+        let start = std::cmp::min(offset, map.len() as u64) as usize;
+        let end = start + std::cmp::min(len, map.len() - start);
+        return Ok(f(&map[start..end]));
+    }
+
+    // not mappable: read the window in
+    let mut file = BufReader::new(file);
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut buf)?;
+    Ok(f(&buf))
+}
+
 impl std::fmt::Debug for JifPheader {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut dbg_struct = f.debug_struct("JifPheader");
@@ -658,10 +873,7 @@ impl std::fmt::Debug for JifPheader {
                     self.virtual_range().1
                 ),
             )
-            .field(
-                "data_size",
-                &format!("{:#x} B", self.private_pages() * PAGE_SIZE),
-            );
+            .field("data_size", &format!("{:#x} B", self.data_size()));
 
         match self {
             JifPheader::Anonymous { itree, .. } => {
@@ -681,29 +893,7 @@ impl std::fmt::Debug for JifPheader {
             }
         }
 
-        dbg_struct
-            .field(
-                "prot",
-                &format!(
-                    "{}{}{}",
-                    if self.prot() & Prot::Read as u8 != 0 {
-                        "r"
-                    } else {
-                        "-"
-                    },
-                    if self.prot() & Prot::Write as u8 != 0 {
-                        "w"
-                    } else {
-                        "-"
-                    },
-                    if self.prot() & Prot::Exec as u8 != 0 {
-                        "x"
-                    } else {
-                        "-"
-                    }
-                ),
-            )
-            .finish()
+        dbg_struct.field("prot", &self.prot()).finish()
     }
 }
 
@@ -733,29 +923,7 @@ impl std::fmt::Debug for JifRawPheader {
             );
         }
 
-        dbg_struct
-            .field(
-                "prot",
-                &format!(
-                    "{}{}{}",
-                    if self.prot & Prot::Read as u8 != 0 {
-                        "r"
-                    } else {
-                        "-"
-                    },
-                    if self.prot & Prot::Write as u8 != 0 {
-                        "w"
-                    } else {
-                        "-"
-                    },
-                    if self.prot & Prot::Exec as u8 != 0 {
-                        "x"
-                    } else {
-                        "-"
-                    }
-                ),
-            )
-            .finish()
+        dbg_struct.field("prot", &self.prot()).finish()
     }
 }
 
@@ -763,13 +931,15 @@ impl std::fmt::Debug for JifRawPheader {
 pub(crate) mod test {
     use super::*;
     use crate::itree::test::*;
+    use crate::paths::AsRecorded;
+    use crate::utils::PAGE_SIZE;
 
     pub(crate) fn gen_pheader(vaddr_range: (u64, u64), ivals: &[(u64, u64)]) -> JifPheader {
         JifPheader::Anonymous {
             vaddr_range,
             itree: ITree::build(
                 ivals
-                    .into_iter()
+                    .iter()
                     .map(|(start, end)| Interval {
                         start: *start,
                         end: *end,
@@ -779,17 +949,20 @@ pub(crate) mod test {
                 vaddr_range,
             )
             .unwrap(),
-            prot: Prot::Read as u8,
+            prot: ProtFlags::READ,
         }
     }
 
     #[test]
     fn fragment_anon_pheader() {
         let itree = gen_anon_tree();
-        let mut cnt = 0;
         // ranges are mapped on and off
         // we query the midpoint in each range
-        for range in VADDRS.into_iter().zip(VADDRS.into_iter().skip(1)) {
+        for (cnt, range) in VADDRS
+            .into_iter()
+            .zip(VADDRS.into_iter().skip(1))
+            .enumerate()
+        {
             let addr = (range.0 + range.1) / 2;
             let resolve = itree.resolve(addr);
             match cnt % 2 {
@@ -800,19 +973,18 @@ pub(crate) mod test {
                 1 => assert_eq!(resolve.unwrap_err(), range),
                 _ => unreachable!(),
             };
-            cnt += 1
         }
 
         let pheader = JifPheader::Anonymous {
             vaddr_range: (VADDR_BEGIN, VADDR_END),
             itree,
-            prot: Prot::Read as u8,
+            prot: ProtFlags::READ,
         };
 
         let prot = pheader.prot();
 
         let deduper = Deduper::default();
-        let pheaders = pheader.fragment(&deduper, &None).unwrap();
+        let pheaders = pheader.fragment(&deduper, PAGE_SIZE, &AsRecorded).unwrap();
         assert_eq!(pheaders.len(), 16);
 
         for (cnt, pheader) in pheaders.iter().enumerate() {
@@ -824,7 +996,7 @@ pub(crate) mod test {
                 }
                 JifPheader::Anonymous { .. } => assert_eq!(cnt, 1),
                 JifPheader::Reference { .. } => {
-                    assert!(false);
+                    panic!("anonymous pheaders should only fragment into anonymous pheaders");
                 }
             }
         }
@@ -833,10 +1005,13 @@ pub(crate) mod test {
     #[test]
     fn fragment_ref_pheader() {
         let itree = gen_ref_tree();
-        let mut cnt = 0;
         // ranges are mapped in an Owned -> Zero -> Ref cycle (Ref is implied)
         // we query the midpoint in each range
-        for range in VADDRS.into_iter().zip(VADDRS.into_iter().skip(1)) {
+        for (cnt, range) in VADDRS
+            .into_iter()
+            .zip(VADDRS.into_iter().skip(1))
+            .enumerate()
+        {
             let addr = (range.0 + range.1) / 2;
             let resolve = itree.resolve(addr);
             match cnt % 3 {
@@ -845,13 +1020,12 @@ pub(crate) mod test {
                 2 => assert_eq!(resolve.unwrap_err(), range),
                 _ => unreachable!(),
             };
-            cnt += 1
         }
 
         let pheader = JifPheader::Reference {
             vaddr_range: (VADDR_BEGIN, VADDR_END),
             itree,
-            prot: Prot::Read as u8,
+            prot: ProtFlags::READ,
             ref_path: "abc".into(),
             ref_offset: 0,
         };
@@ -859,7 +1033,7 @@ pub(crate) mod test {
         let prot = pheader.prot();
 
         let deduper = Deduper::default();
-        let pheaders = pheader.fragment(&deduper, &None).unwrap();
+        let pheaders = pheader.fragment(&deduper, PAGE_SIZE, &AsRecorded).unwrap();
         assert_eq!(pheaders.len(), 16);
 
         for (cnt, pheader) in pheaders.iter().enumerate() {
@@ -877,4 +1051,49 @@ pub(crate) mod test {
             }
         }
     }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn build_itree_from_file_window() {
+        let path =
+            std::env::temp_dir().join(format!("jif-file-window-test-{}", std::process::id()));
+        let mut contents = vec![0x11u8; 0x1000];
+        contents.extend([0x22u8; 0x2000]);
+        contents.extend([0x33u8; 0x800]);
+        std::fs::write(&path, &contents).unwrap();
+
+        // maps the file from 0x1000, with the last page past its end
+        let mut overlay = vec![0x22u8; 0x1000 * 4];
+        overlay[0x1000..0x2000].fill(0x44);
+        overlay[0x2000..0x2800].fill(0x33);
+        overlay[0x2800..].fill(0x00);
+        let mut deduper = Deduper::default();
+        let token = deduper.insert(overlay.clone());
+        let mut pheader = JifPheader::Reference {
+            vaddr_range: (0x10000, 0x14000),
+            itree: ITree::single((0x10000, 0x14000), RefIntervalData::Ref(token)),
+            prot: ProtFlags::READ,
+            ref_path: path.to_str().unwrap().to_string(),
+            ref_offset: 0x1000,
+        };
+        pheader
+            .build_itree(&deduper, PAGE_SIZE, &AsRecorded)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let JifPheader::Reference { itree, .. } = pheader else {
+            unreachable!("the pheader is a reference pheader");
+        };
+        assert_eq!(
+            itree
+                .in_order_intervals()
+                .map(|ival| (ival.start, ival.end, ival.data.is_zero()))
+                .collect::<Vec<_>>(),
+            vec![(0x11000, 0x12000, false), (0x13000, 0x14000, true)]
+        );
+        assert_eq!(
+            itree.resolve(0x11000).unwrap().data.get_data(&deduper),
+            Some(&overlay[0x1000..0x2000])
+        );
+    }
 }
@@ -0,0 +1,145 @@
+//! Splitting large pheaders
+//!
+//! The restorer reserves a VMA for each pheader: a giant anonymous pheader (e.g., a heap
+//! reservation with a few pages of data) has it reserve the whole range at once. Splitting the
+//! pheaders above a size into contiguous pheaders of at most that size keeps their intervals,
+//! data and ordering chunks (which are split at the new boundaries), so the JIF restores the same
+//! memory contents (see [`Jif::split_large_pheaders`])
+
+use crate::error::*;
+use crate::jif::Jif;
+use crate::ord::OrdChunk;
+use crate::prot::split_chunk_at;
+use crate::utils::is_page_aligned;
+
+use std::collections::BTreeSet;
+
+impl Jif {
+    /// Split the pheaders larger than `max_size` B (page aligned) into contiguous pheaders of
+    /// `max_size` B (the last one holding the remainder), returning the number of pheaders added
+    ///
+    /// The pieces keep the protections of the pheader, and reference pheaders keep mapping the
+    /// same file offsets
+    pub fn split_large_pheaders(&mut self, max_size: u64) -> JifResult<usize> {
+        let page_size = self.arch.page_size;
+        if max_size == 0 || !is_page_aligned(max_size, page_size) {
+            return Err(JifError::BadPheaderSize { max_size });
+        }
+
+        let mut boundaries = BTreeSet::new();
+        let mut pheaders = Vec::with_capacity(self.pheaders.len());
+        for pheader in std::mem::take(&mut self.pheaders) {
+            let (start, end) = pheader.virtual_range();
+            let mut rest = pheader;
+            let mut addr = start.saturating_add(max_size);
+            while addr < end {
+                let (left, right) = rest.split_at(addr, &self.deduper)?;
+                pheaders.push(left);
+                boundaries.insert(addr);
+                rest = right;
+                addr = addr.saturating_add(max_size);
+            }
+            pheaders.push(rest);
+        }
+        self.pheaders = pheaders;
+
+        self.ord_chunks = std::mem::take(&mut self.ord_chunks)
+            .into_iter()
+            .flat_map(|chunk| split_chunk_at_all(chunk, &boundaries, page_size))
+            .collect();
+
+        Ok(boundaries.len())
+    }
+}
+
+/// Split an ordering chunk at every boundary it crosses
+fn split_chunk_at_all(
+    chunk: OrdChunk,
+    boundaries: &BTreeSet<u64>,
+    page_size: usize,
+) -> Vec<OrdChunk> {
+    let mut pieces = Vec::new();
+    let mut rest = chunk;
+    for addr in boundaries.range(chunk.vaddr + 1..chunk.end(page_size)) {
+        let mut split = split_chunk_at(rest, *addr, page_size);
+        rest = split.pop().expect("the chunk crosses the boundary");
+        pieces.extend(split);
+    }
+    pieces.push(rest);
+
+    pieces
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::jif::test::gen_jif;
+    use crate::jif::JifRaw;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::BufReader;
+
+    #[test]
+    fn split_large_pheaders() {
+        let gen = || {
+            let mut jif = gen_jif(&[
+                ((0x1000, 0x3000), &[(0x1000, 0x2000)]),
+                ((0x100000, 0x10a000), &[(0x101000, 0x106000)]),
+            ]);
+            jif.ord_chunks = vec![
+                OrdChunk::new(0x103000, 3, DataSource::Private),
+                OrdChunk::new(0x1000, 1, DataSource::Private),
+            ];
+            jif
+        };
+
+        let mut jif = gen();
+        assert!(jif.split_large_pheaders(0).is_err());
+        assert!(jif.split_large_pheaders(0x1800).is_err());
+        assert_eq!(jif.split_large_pheaders(0x4000).unwrap(), 2);
+        assert_eq!(
+            jif.pheaders()
+                .iter()
+                .map(|pheader| pheader.virtual_range())
+                .collect::<Vec<_>>(),
+            vec![
+                (0x1000, 0x3000),
+                (0x100000, 0x104000),
+                (0x104000, 0x108000),
+                (0x108000, 0x10a000),
+            ]
+        );
+        assert_eq!(
+            jif.ord_chunks(),
+            &[
+                OrdChunk::new(0x103000, 1, DataSource::Private),
+                OrdChunk::new(0x104000, 2, DataSource::Private),
+                OrdChunk::new(0x1000, 1, DataSource::Private),
+            ]
+        );
+        assert_eq!(jif.split_large_pheaders(0x4000).unwrap(), 0);
+
+        // the contents are unchanged
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        let orig = gen();
+        for addr in (0x1000..0x10a000).step_by(PAGE_SIZE) {
+            assert_eq!(
+                read.resolve(addr).map(|ival| ival.source),
+                orig.resolve(addr).map(|ival| ival.source),
+                "{:#x}",
+                addr
+            );
+            assert_eq!(
+                read.resolve_data(addr),
+                orig.resolve_data(addr),
+                "{:#x}",
+                addr
+            );
+        }
+    }
+}
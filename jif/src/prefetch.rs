@@ -0,0 +1,224 @@
+//! Prefetch layout
+//!
+//! When the prefetch is set up (see [`JifRaw::from_materialized`]), the private data of the
+//! ordering chunks is laid out first in the data section, in ordering order, and the header
+//! records how many pages of the data section are to be prefetched. The prefetched ranges are
+//! partitioned by the kind of access which faulted them in, as recorded in the ordering chunks
+//! (see [`OrdChunk::access`](crate::ord::OrdChunk::access))
+
+use crate::jif::JifRaw;
+use crate::ord::AccessKind;
+
+/// Prefetch layout of a [`JifRaw`] (see [`JifRaw::prefetch_layout`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrefetchLayout {
+    /// Number of pages at the start of the data section which are prefetched
+    pub n_prefetch: u64,
+
+    /// Prefetched address ranges faulted in by a write (in prefetch order)
+    pub write_ranges: Vec<(u64, u64)>,
+
+    /// Prefetched address ranges faulted in by a read or without a recorded access (in
+    /// prefetch order)
+    pub read_ranges: Vec<(u64, u64)>,
+}
+
+impl PrefetchLayout {
+    /// Whether the JIF has the prefetch set up
+    pub fn is_set_up(&self) -> bool {
+        self.n_prefetch > 0
+    }
+
+    /// Number of prefetched pages faulted in by a write
+    pub fn n_write_pages(&self, page_size: usize) -> u64 {
+        n_pages(&self.write_ranges, page_size)
+    }
+
+    /// Number of prefetched pages faulted in by a read (or without a recorded access)
+    pub fn n_read_pages(&self, page_size: usize) -> u64 {
+        n_pages(&self.read_ranges, page_size)
+    }
+}
+
+impl JifRaw {
+    /// Number of pages at the start of the data section which are prefetched
+    pub fn n_prefetch(&self) -> u64 {
+        self.n_prefetch
+    }
+
+    /// Compute the prefetch layout: the address ranges whose data is in the prefetched part of
+    /// the data section, split by the access of the ordering chunk which faulted them in
+    ///
+    /// Private data shared between intervals is prefetched once, but it is part of the ranges of
+    /// every interval referencing it
+    pub fn prefetch_layout(&self) -> PrefetchLayout {
+        let page_size = self.arch.page_size;
+        let data_ivals = self
+            .itree_nodes
+            .iter()
+            .flat_map(|n| n.ranges.iter())
+            .filter(|ival| ival.is_data());
+
+        // the header of a corrupt file may claim more pages than the data section has
+        let data_end = data_ivals
+            .clone()
+            .map(|ival| ival.offset.saturating_add(ival.len()))
+            .max()
+            .unwrap_or(self.data_offset);
+        let prefetch_end = self
+            .n_prefetch
+            .checked_mul(page_size as u64)
+            .and_then(|size| self.data_offset.checked_add(size))
+            .map_or(data_end, |end| std::cmp::min(end, data_end));
+
+        let mut write_ranges = self
+            .ord_chunks
+            .iter()
+            .filter(|chunk| chunk.access() == Some(AccessKind::Write))
+            .map(|chunk| {
+                let size = chunk.size().saturating_mul(page_size as u64);
+                (chunk.addr(), chunk.addr().saturating_add(size))
+            })
+            .collect::<Vec<_>>();
+        write_ranges.sort_unstable();
+        let write_ranges = write_ranges.into_iter().fold(
+            Vec::new(),
+            |mut merged: Vec<(u64, u64)>, (start, end)| {
+                match merged.last_mut() {
+                    Some((_start, last_end)) if *last_end >= start => {
+                        *last_end = std::cmp::max(*last_end, end)
+                    }
+                    _ => merged.push((start, end)),
+                }
+                merged
+            },
+        );
+
+        let mut prefetched = data_ivals
+            .filter(|ival| ival.offset < prefetch_end)
+            .map(|ival| {
+                let end = std::cmp::min(
+                    ival.end,
+                    ival.start.saturating_add(prefetch_end - ival.offset),
+                );
+                (ival.offset, ival.start, end)
+            })
+            .collect::<Vec<_>>();
+        prefetched.sort_unstable();
+
+        let mut layout = PrefetchLayout {
+            n_prefetch: self.n_prefetch,
+            ..Default::default()
+        };
+        for (_offset, start, end) in prefetched {
+            // split the range by the write ranges it intersects
+            let mut cursor = start;
+            let mut idx = write_ranges.partition_point(|(_start, end)| *end <= cursor);
+            while cursor < end {
+                let (ranges, next) = match write_ranges.get(idx) {
+                    Some(&(write_start, write_end)) if write_start <= cursor => {
+                        idx += 1;
+                        (&mut layout.write_ranges, std::cmp::min(write_end, end))
+                    }
+                    Some(&(write_start, _write_end)) if write_start < end => {
+                        (&mut layout.read_ranges, write_start)
+                    }
+                    _ => (&mut layout.read_ranges, end),
+                };
+                push_range(ranges, cursor, next);
+                cursor = next;
+            }
+        }
+
+        layout
+    }
+}
+
+/// Add a range to the ranges, extending the last one if it is adjacent
+fn push_range(ranges: &mut Vec<(u64, u64)>, start: u64, end: u64) {
+    match ranges.last_mut() {
+        Some((_start, last_end)) if *last_end == start => *last_end = end,
+        _ => ranges.push((start, end)),
+    }
+}
+
+fn n_pages(ranges: &[(u64, u64)], page_size: usize) -> u64 {
+    ranges
+        .iter()
+        .map(|(start, end)| (end - start) / page_size as u64)
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::jif::test::gen_jif;
+    use crate::jif::Jif;
+    use crate::ord::OrdChunk;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn prefetch_layout() {
+        let gen = || {
+            let mut jif = gen_jif(&[
+                ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ]);
+            jif.ord_chunks = vec![
+                OrdChunk::new(0x11000, 1, DataSource::Private)
+                    .with_provenance(None, Some(AccessKind::Write)),
+                OrdChunk::new(0x1000, 2, DataSource::Private)
+                    .with_provenance(Some(1), Some(AccessKind::Read)),
+                OrdChunk::new(0x12000, 1, DataSource::Private)
+                    .with_provenance(None, Some(AccessKind::Write)),
+            ];
+            jif
+        };
+
+        let layout = JifRaw::from_materialized(gen(), false).prefetch_layout();
+        assert!(!layout.is_set_up());
+        assert_eq!(layout, PrefetchLayout::default());
+
+        let raw = JifRaw::from_materialized(gen(), true);
+        let layout = raw.prefetch_layout();
+        assert!(layout.is_set_up());
+        assert_eq!(layout.n_prefetch, 4);
+        assert_eq!(layout.write_ranges, vec![(0x11000, 0x13000)]);
+        // every interval of gen_jif holds the same bytes: 0x5000 shares the data of 0x11000
+        assert_eq!(layout.read_ranges, vec![(0x5000, 0x6000), (0x1000, 0x3000)]);
+        assert_eq!(layout.n_write_pages(PAGE_SIZE), 2);
+        assert_eq!(layout.n_read_pages(PAGE_SIZE), 3);
+
+        // the layout is the same once read back
+        let mut buffer = Vec::new();
+        raw.to_writer(&mut buffer).unwrap();
+        let read = JifRaw::from_reader(&mut BufReader::new(Cursor::new(buffer.clone()))).unwrap();
+        assert_eq!(read.n_prefetch(), 4);
+        assert_eq!(read.prefetch_layout(), layout);
+
+        // and after a materialization round trip
+        let jif = Jif::from_reader(&mut BufReader::new(Cursor::new(buffer))).unwrap();
+        assert_eq!(
+            JifRaw::from_materialized(jif, true).prefetch_layout(),
+            layout
+        );
+    }
+
+    #[test]
+    fn corrupt_prefetch_layout() {
+        let mut jif = gen_jif(&[((0x10000, 0x14000), &[(0x10000, 0x13000)])]);
+        jif.ord_chunks = vec![OrdChunk::new(0x11000, 1, DataSource::Private)
+            .with_provenance(None, Some(AccessKind::Write))];
+        let mut raw = JifRaw::from_materialized(jif, true);
+
+        // the prefetch is clamped to the data section, and the chunks are not walked page by page
+        raw.n_prefetch = u64::MAX;
+        raw.ord_chunks[0].n_pages = u64::MAX / PAGE_SIZE as u64;
+        let layout = raw.prefetch_layout();
+        assert_eq!(layout.write_ranges, vec![(0x11000, 0x13000)]);
+        assert_eq!(layout.read_ranges, vec![(0x10000, 0x11000)]);
+    }
+}
@@ -0,0 +1,367 @@
+//! VMA protections and changing them
+//!
+//! The protections are set per pheader: changing them on part of a pheader splits it at the
+//! boundaries of the range. The split halves keep their intervals (the interval crossing the
+//! boundary is split in two) and reference pheaders keep mapping the same file offsets
+
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::huge_page::split_part;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::ord::OrdChunk;
+use crate::pheader::JifPheader;
+use crate::utils::is_page_aligned;
+
+use std::fmt::Write as _;
+
+/// VMA protection bits
+///
+/// Displayed as `rwx` (with `-` for the missing bits). The bits other than the protections are
+/// kept as they were read, so they survive a roundtrip
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ProtFlags(u8);
+
+impl ProtFlags {
+    pub const READ: ProtFlags = ProtFlags(1 << 2);
+    pub const WRITE: ProtFlags = ProtFlags(1 << 1);
+    pub const EXEC: ProtFlags = ProtFlags(1 << 0);
+
+    /// No protections
+    pub const fn empty() -> Self {
+        ProtFlags(0)
+    }
+
+    /// The flags from their serialized bits
+    pub const fn from_bits(bits: u8) -> Self {
+        ProtFlags(bits)
+    }
+
+    /// The serialized bits
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all the flags of `other` are set
+    pub const fn contains(self, other: ProtFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for ProtFlags {
+    type Output = ProtFlags;
+    fn bitor(self, rhs: ProtFlags) -> ProtFlags {
+        ProtFlags(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for ProtFlags {
+    fn bitor_assign(&mut self, rhs: ProtFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl std::fmt::Display for ProtFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (flag, c) in [
+            (ProtFlags::READ, 'r'),
+            (ProtFlags::WRITE, 'w'),
+            (ProtFlags::EXEC, 'x'),
+        ] {
+            f.write_char(if self.contains(flag) { c } else { '-' })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ProtFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+impl Jif {
+    /// Set the protections of the page aligned range `[start; end)` to `prot`
+    ///
+    /// The pheaders partially inside the range are split at its boundaries (and so are the
+    /// ordering chunks crossing them). Returns the number of pheaders inside the range once split
+    pub fn set_prot(&mut self, (start, end): (u64, u64), prot: ProtFlags) -> JifResult<usize> {
+        let page_size = self.arch.page_size;
+        if !is_page_aligned(start, page_size) || !is_page_aligned(end, page_size) || start >= end {
+            return Err(JifError::BadProtRange {
+                range: (start, end),
+            });
+        }
+
+        let mut pheaders = Vec::with_capacity(self.pheaders.len());
+        let mut n_changed = 0;
+        for pheader in std::mem::take(&mut self.pheaders) {
+            let (phdr_start, phdr_end) = pheader.virtual_range();
+            if phdr_end <= start || end <= phdr_start {
+                pheaders.push(pheader);
+                continue;
+            }
+
+            let mut inside = pheader;
+            if phdr_start < start {
+                let (left, right) = inside.split_at(start, &self.deduper)?;
+                pheaders.push(left);
+                inside = right;
+            }
+            let right = if end < phdr_end {
+                let (left, right) = inside.split_at(end, &self.deduper)?;
+                inside = left;
+                Some(right)
+            } else {
+                None
+            };
+
+            inside.set_prot(prot);
+            n_changed += 1;
+            pheaders.push(inside);
+            pheaders.extend(right);
+        }
+        self.pheaders = pheaders;
+
+        self.ord_chunks = std::mem::take(&mut self.ord_chunks)
+            .into_iter()
+            .flat_map(|chunk| split_chunk_at(chunk, start, page_size))
+            .flat_map(|chunk| split_chunk_at(chunk, end, page_size))
+            .collect();
+
+        Ok(n_changed)
+    }
+}
+
+impl JifPheader {
+    /// Set the protections of the pheader
+    pub fn set_prot(&mut self, new_prot: ProtFlags) {
+        match self {
+            JifPheader::Anonymous { prot, .. } => *prot = new_prot,
+            JifPheader::Reference { prot, .. } => *prot = new_prot,
+        }
+    }
+
+    /// Split the pheader into `[start; addr)` and `[addr; end)`
+    ///
+    /// The address has to be strictly inside the virtual range
+    pub(crate) fn split_at(
+        self,
+        addr: u64,
+        deduper: &Deduper,
+    ) -> JifResult<(JifPheader, JifPheader)> {
+        let virtual_range = self.virtual_range();
+        debug_assert!(virtual_range.0 < addr && addr < virtual_range.1);
+        let invalid_itree = |error| JifError::InvalidITree {
+            virtual_range,
+            error,
+        };
+
+        match self {
+            JifPheader::Anonymous {
+                vaddr_range: (start, end),
+                mut itree,
+                prot,
+            } => {
+                let (left, right) = split_intervals_at(
+                    itree.take().into_iter_intervals(),
+                    deduper,
+                    addr,
+                    AnonIntervalData::Owned,
+                );
+                Ok((
+                    JifPheader::Anonymous {
+                        vaddr_range: (start, addr),
+                        itree: ITree::build(left, (start, addr)).map_err(invalid_itree)?,
+                        prot,
+                    },
+                    JifPheader::Anonymous {
+                        vaddr_range: (addr, end),
+                        itree: ITree::build(right, (addr, end)).map_err(invalid_itree)?,
+                        prot,
+                    },
+                ))
+            }
+            JifPheader::Reference {
+                vaddr_range: (start, end),
+                mut itree,
+                prot,
+                ref_path,
+                ref_offset,
+            } => {
+                let (left, right) = split_intervals_at(
+                    itree.take().into_iter_intervals(),
+                    deduper,
+                    addr,
+                    RefIntervalData::Owned,
+                );
+                Ok((
+                    JifPheader::Reference {
+                        vaddr_range: (start, addr),
+                        itree: ITree::build(left, (start, addr)).map_err(invalid_itree)?,
+                        prot,
+                        ref_path: ref_path.clone(),
+                        ref_offset,
+                    },
+                    JifPheader::Reference {
+                        vaddr_range: (addr, end),
+                        itree: ITree::build(right, (addr, end)).map_err(invalid_itree)?,
+                        prot,
+                        ref_path,
+                        ref_offset: ref_offset + (addr - start),
+                    },
+                ))
+            }
+        }
+    }
+}
+
+/// Split the intervals into the ones before and after `addr` (splitting the one crossing it)
+fn split_intervals_at<Data: IntervalData + Clone>(
+    intervals: impl Iterator<Item = Interval<Data>>,
+    deduper: &Deduper,
+    addr: u64,
+    owned: fn(Vec<u8>) -> Data,
+) -> (Vec<Interval<Data>>, Vec<Interval<Data>>) {
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+    for ival in intervals.filter(|ival| !ival.is_none()) {
+        if ival.end <= addr {
+            left.push(ival);
+        } else if addr <= ival.start {
+            right.push(ival);
+        } else {
+            let data = ival.data.get_data(deduper);
+            let slice = |start: u64, end: u64| {
+                data.map(|data| {
+                    data[(start - ival.start) as usize..(end - ival.start) as usize].to_vec()
+                })
+            };
+            left.push(split_part(&ival, ival.start, addr, slice, owned));
+            right.push(split_part(&ival, addr, ival.end, slice, owned));
+        }
+    }
+
+    (left, right)
+}
+
+/// Split an ordering chunk crossing `addr` in two
+pub(crate) fn split_chunk_at(chunk: OrdChunk, addr: u64, page_size: usize) -> Vec<OrdChunk> {
+    let chunk_end = chunk.end(page_size);
+    if addr <= chunk.vaddr || chunk_end <= addr {
+        return vec![chunk];
+    }
+
+    vec![
+        chunk.part(
+            chunk.vaddr,
+            (addr - chunk.vaddr) / page_size as u64,
+            chunk.kind,
+        ),
+        chunk.part(addr, (chunk_end - addr) / page_size as u64, chunk.kind),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::jif::test::gen_jif;
+    use crate::jif::JifRaw;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::BufReader;
+
+    #[test]
+    fn prot_flags() {
+        let prot = ProtFlags::READ | ProtFlags::EXEC;
+        assert!(prot.contains(ProtFlags::READ));
+        assert!(!prot.contains(ProtFlags::READ | ProtFlags::WRITE));
+        assert!(prot.contains(ProtFlags::empty()));
+        assert_eq!(prot.to_string(), "r-x");
+        assert_eq!(ProtFlags::empty().to_string(), "---");
+
+        // unknown bits are kept, but not displayed
+        let prot = ProtFlags::from_bits(0xf0 | ProtFlags::WRITE.bits());
+        assert_eq!(prot.bits(), 0xf2);
+        assert_eq!(prot.to_string(), "-w-");
+    }
+
+    #[test]
+    fn set_prot() {
+        let gen = || {
+            let mut jif = gen_jif(&[
+                ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ]);
+            jif.ord_chunks = vec![OrdChunk::new(0x1000, 2, DataSource::Private)];
+            jif
+        };
+
+        let mut jif = gen();
+        assert!(jif.set_prot((0x1800, 0x3000), ProtFlags::empty()).is_err());
+        assert_eq!(
+            jif.set_prot((0x20000, 0x30000), ProtFlags::WRITE).unwrap(),
+            0
+        );
+        assert_eq!(
+            jif.set_prot((0x2000, 0x12000), ProtFlags::READ | ProtFlags::WRITE)
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            jif.pheaders()
+                .iter()
+                .map(|pheader| (pheader.virtual_range(), pheader.prot()))
+                .collect::<Vec<_>>(),
+            vec![
+                ((0x1000, 0x2000), ProtFlags::READ),
+                ((0x2000, 0x8000), ProtFlags::READ | ProtFlags::WRITE),
+                ((0x10000, 0x12000), ProtFlags::READ | ProtFlags::WRITE),
+                ((0x12000, 0x14000), ProtFlags::READ),
+            ]
+        );
+        assert_eq!(
+            jif.ord_chunks(),
+            &[
+                OrdChunk::new(0x1000, 1, DataSource::Private),
+                OrdChunk::new(0x2000, 1, DataSource::Private)
+            ]
+        );
+
+        // the contents are unchanged
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        let orig = gen();
+        for addr in (0x1000..0x14000).step_by(PAGE_SIZE) {
+            assert_eq!(
+                read.resolve_data(addr),
+                orig.resolve_data(addr),
+                "{:#x}",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn split_reference() {
+        let pheader = JifPheader::Reference {
+            vaddr_range: (0x10000, 0x14000),
+            itree: ITree::single((0x11000, 0x13000), RefIntervalData::Zero),
+            prot: ProtFlags::READ,
+            ref_path: "/lib/libc.so".to_string(),
+            ref_offset: 0x3000,
+        };
+        let (left, right) = pheader.split_at(0x12000, &Deduper::default()).unwrap();
+        assert_eq!(left.ref_offset(), Some(0x3000));
+        assert_eq!(right.ref_offset(), Some(0x5000));
+        assert_eq!(left.itree().n_intervals(), 1);
+        assert_eq!(right.itree().n_intervals(), 1);
+        assert_eq!(right.resolve(0x12000).source, DataSource::Zero);
+        assert_eq!(right.resolve(0x13000).source, DataSource::Shared);
+    }
+}
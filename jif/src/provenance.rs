@@ -0,0 +1,223 @@
+//! Data placement provenance
+//!
+//! Writing a materialized JIF out places the data segments in ordering chunk order (the hottest
+//! chunks first: see [`OrdChunk::weight`]): the first chunk which lands on an interval pins its
+//! segment, and the segments no chunk lands on follow, in address order (see
+//! [`JifRaw::from_materialized`]). Replaying that walk over a raw JIF tells
+//! which chunk (and so which trace entry, with its thread and access) placed each segment, so
+//! that a poorly placed segment can be traced back to the trace which caused it (see
+//! [`JifRaw::data_provenance`])
+
+use crate::jif::JifRaw;
+use crate::layout::SegmentOwner;
+use crate::ord::{placement_order, OrdChunk};
+
+/// Which ordering chunk placed each data segment of a [`JifRaw`] (see
+/// [`JifRaw::data_provenance`])
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DataProvenance {
+    /// Data segments, in file order
+    pub segments: Vec<SegmentProvenance>,
+}
+
+/// A data segment and the ordering chunk which placed it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SegmentProvenance {
+    /// Byte range of the segment (see [`LayoutMap::segments`](crate::LayoutMap::segments))
+    pub range: (u64, u64),
+
+    /// Intervals backed by the segment
+    pub owners: Vec<SegmentOwner>,
+
+    /// Index (in the ordering section) and value of the chunk which pinned the segment, if
+    /// any: the other segments are placed in address order, after the pinned ones
+    pub chunk: Option<(usize, OrdChunk)>,
+}
+
+impl DataProvenance {
+    /// Number of segments pinned by an ordering chunk
+    pub fn n_pinned(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| segment.chunk.is_some())
+            .count()
+    }
+}
+
+impl JifRaw {
+    /// Trace each data segment back to the ordering chunk which placed it, if any
+    ///
+    /// The segment of an interval is pinned by the first chunk (in the order of the ordering
+    /// section, the hottest chunks first) which starts in it, unless an earlier chunk already
+    /// pinned it through another interval sharing the data
+    pub fn data_provenance(&self) -> DataProvenance {
+        let mut segments = self
+            .segment_layouts()
+            .into_iter()
+            .map(|segment| SegmentProvenance {
+                range: segment.range,
+                owners: segment.owners,
+                chunk: None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut intervals = segments
+            .iter()
+            .enumerate()
+            .flat_map(|(segment_idx, segment)| {
+                segment
+                    .owners
+                    .iter()
+                    .map(move |owner| (owner.virtual_range, segment_idx))
+            })
+            .collect::<Vec<_>>();
+        intervals.sort_unstable();
+
+        for (chunk_idx, chunk) in placement_order(&self.ord_chunks)
+            .into_iter()
+            .map(|idx| (idx, &self.ord_chunks[idx]))
+        {
+            let idx = intervals.partition_point(|((start, _end), _idx)| *start <= chunk.addr());
+            let Some(((_start, end), segment_idx)) = idx.checked_sub(1).map(|idx| intervals[idx])
+            else {
+                continue;
+            };
+
+            let segment = &mut segments[segment_idx];
+            if chunk.addr() < end && segment.chunk.is_none() {
+                segment.chunk = Some((chunk_idx, *chunk));
+            }
+        }
+
+        DataProvenance { segments }
+    }
+}
+
+impl std::fmt::Display for DataProvenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} segments, {} pinned by the ordering chunks\n",
+            self.segments.len(),
+            self.n_pinned()
+        ))?;
+        for segment in &self.segments {
+            f.write_fmt(format_args!(
+                "  segment [{:#x}; {:#x}) ({:#x} B): ",
+                segment.range.0,
+                segment.range.1,
+                segment.range.1 - segment.range.0
+            ))?;
+            match &segment.chunk {
+                None => f.write_str("address order,")?,
+                Some((chunk_idx, chunk)) => {
+                    f.write_fmt(format_args!(
+                        "ord[{}] {{ vaddr: {:#x}, n_pages: {}, kind: {:?}",
+                        chunk_idx,
+                        chunk.addr(),
+                        chunk.size(),
+                        chunk.kind()
+                    ))?;
+                    if let Some(tid) = chunk.tid() {
+                        f.write_fmt(format_args!(", tid: {}", tid))?;
+                    }
+                    if let Some(access) = chunk.access() {
+                        f.write_fmt(format_args!(", access: {:?}", access))?;
+                    }
+                    if let Some(weight) = chunk.weight() {
+                        f.write_fmt(format_args!(", weight: {}", weight))?;
+                    }
+                    f.write_str(" },")?;
+                }
+            }
+            for owner in &segment.owners {
+                f.write_fmt(format_args!(
+                    " pheader {} [{:#x}; {:#x})",
+                    owner.pheader_idx, owner.virtual_range.0, owner.virtual_range.1
+                ))?;
+            }
+            f.write_str("\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::DataSource;
+    use crate::ord::AccessKind;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn data_provenance() {
+        let mut builder = crate::JifBuilder::new();
+        for (start, pages) in [
+            (0x100000, &[0u8, 1][..]),
+            (0x200000, &[2, 3]),
+            (0x300000, &[4]),
+            (0x400000, &[4]),
+        ] {
+            builder.add_anonymous_segment(
+                (start, start + (pages.len() * PAGE_SIZE) as u64),
+                ProtFlags::READ,
+                vec![(
+                    start,
+                    pages
+                        .iter()
+                        .flat_map(|byte| vec![*byte; PAGE_SIZE])
+                        .collect(),
+                )],
+            );
+        }
+        let mut jif = builder.build().unwrap();
+        jif.add_ordering_info(vec![
+            OrdChunk::new(0x201000, 1, DataSource::Private)
+                .with_provenance(Some(7), Some(AccessKind::Write)),
+            OrdChunk::new(0x400000, 1, DataSource::Private),
+            OrdChunk::new(0x300000, 1, DataSource::Private),
+        ])
+        .unwrap();
+
+        let mut file = Vec::new();
+        JifRaw::from_materialized(jif, true)
+            .to_writer(&mut file)
+            .unwrap();
+        let raw = JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file))).unwrap();
+        let provenance = raw.data_provenance();
+
+        // the pinned segments come first, in chunk order (the segment shared by 0x300000 and
+        // 0x400000 is pinned by the chunk which lands on it first), then the others
+        let summary = provenance
+            .segments
+            .iter()
+            .map(|segment| {
+                (
+                    segment
+                        .owners
+                        .iter()
+                        .map(|owner| owner.virtual_range.0)
+                        .collect::<Vec<_>>(),
+                    segment.chunk.map(|(idx, _chunk)| idx),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (vec![0x201000], Some(0)),
+                (vec![0x300000, 0x400000], Some(1)),
+                (vec![0x100000], None),
+                (vec![0x200000], None),
+            ],
+            "{}",
+            provenance
+        );
+        assert_eq!(provenance.n_pinned(), 2);
+        assert_eq!(provenance.segments[0].chunk.unwrap().1.tid(), Some(7));
+        assert_eq!(raw.ord_chunks()[1].addr(), 0x400000);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::async_io::{read_full, seek, AsyncRead, AsyncSeek};
+use crate::compress::Compression;
+use crate::error::*;
+use crate::integrity::crc32c;
+use crate::itree::itree_node::RawITreeNode;
+use crate::jif::{Jif, JifHeaderBinary, JifRaw};
+use crate::pheader::JifRawPheader;
+use crate::utils::page_align;
+
+use super::jif::JifHeader;
+
+use std::collections::BTreeMap;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+
+/// The end of a file, from `offset` on, read into memory (as the trailing sections are read
+/// relative to the end of the file): seeking before `offset` fails
+struct Tail {
+    offset: u64,
+    data: Cursor<Vec<u8>>,
+}
+
+impl Read for Tail {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+impl Seek for Tail {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => {
+                SeekFrom::Start(pos.checked_sub(self.offset).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "seeking before the end of the data section",
+                    )
+                })?)
+            }
+            pos => pos,
+        };
+        Ok(self.offset + self.data.seek(pos)?)
+    }
+}
+
+impl JifRaw {
+    /// Read and parse a JIF asynchronously (see [`JifRaw::from_reader`] and
+    /// [`crate::async_io`])
+    ///
+    /// The metadata and the trailing sections are read into buffers, while the data segments are
+    /// read one at a time. With an integrity section, the file is read a second time to verify
+    /// its checksum
+    pub async fn from_async_reader<R: AsyncRead + AsyncSeek + Unpin>(r: &mut R) -> JifResult<Self> {
+        let file_len = seek(r, SeekFrom::End(0)).await?;
+
+        // the header tells the size of the metadata
+        let header_size = std::mem::size_of::<JifHeaderBinary>();
+        let mut buffer = vec![0u8; header_size];
+        seek(r, SeekFrom::Start(0)).await?;
+        let read = read_full(r, &mut buffer).await?;
+        let header = JifHeader::from_reader(&mut &buffer[..read])
+            .map_err(|error| error.in_section(JifSection::Header, (0, header_size as u64)))?;
+        let page_size = header.arch.page_size;
+        let metadata_size = page_align(
+            page_align(
+                header_size as u64
+                    + header.n_pheaders as u64 * JifRawPheader::serialized_size() as u64,
+                page_size,
+            ) + header.strings_size as u64
+                + header.itrees_size as u64
+                + header.ord_size as u64,
+            page_size,
+        );
+
+        // a truncated metadata fails parsing as the file does
+        let mut metadata = vec![0u8; std::cmp::min(metadata_size, file_len) as usize];
+        seek(r, SeekFrom::Start(0)).await?;
+        let read = read_full(r, &mut metadata).await?;
+        metadata.truncate(read);
+        let mut raw = JifRaw::from_reader_metadata(&mut BufReader::new(Cursor::new(metadata)))?;
+
+        // the trailing sections follow the data section (which is only known in full when
+        // compressed)
+        let tail_offset = match raw.compression {
+            Compression::None => JifRaw::data_segment_ranges(&raw.itree_nodes, raw.data_offset)
+                .into_iter()
+                .map(|(offset, len)| raw.data_offset.saturating_add(offset).saturating_add(len))
+                .max()
+                .unwrap_or(raw.data_offset),
+            Compression::Lz4 => raw.data_offset,
+        }
+        .min(file_len);
+        let mut tail = vec![0u8; (file_len - tail_offset) as usize];
+        seek(r, SeekFrom::Start(tail_offset)).await?;
+        let read = read_full(r, &mut tail).await?;
+        tail.truncate(read);
+        let mut tail = Tail {
+            offset: tail_offset,
+            data: Cursor::new(tail),
+        };
+
+        let trailer = raw.read_integrity_trailer(&mut tail)?;
+        raw.read_trailing_sections(&mut tail)?;
+        raw.data_segments = match raw.compression {
+            Compression::None => {
+                read_data_segments(r, &raw.itree_nodes, raw.data_offset, file_len).await?
+            }
+            Compression::Lz4 => raw.read_compressed_data_segments(&mut tail, trailer.as_ref())?,
+        };
+
+        if let Some(trailer) = trailer {
+            let file_crc = read_crc(r, trailer.offset).await.map_err(JifError::from);
+            let data_segments = raw
+                .data_segments
+                .iter()
+                .map(|(range, data)| (*range, data.as_slice()));
+            trailer
+                .verify_segments(data_segments)
+                .and(file_crc)
+                .and_then(|file_crc| trailer.verify_file(file_crc))
+                .map_err(|error| raw.locate_integrity_error(&trailer, error))?;
+        }
+
+        Ok(raw)
+    }
+}
+
+impl Jif {
+    /// Read and parse a JIF asynchronously (see [`JifRaw::from_async_reader`])
+    pub async fn from_async_reader<R: AsyncRead + AsyncSeek + Unpin>(r: &mut R) -> JifResult<Self> {
+        Jif::from_raw(JifRaw::from_async_reader(r).await?)
+    }
+}
+
+/// Read the data segments referenced by the interval tree nodes, one at a time (see
+/// [`JifRaw::read_data_segments`])
+async fn read_data_segments<R: AsyncRead + AsyncSeek + Unpin>(
+    r: &mut R,
+    itree_nodes: &[RawITreeNode],
+    data_offset: u64,
+    file_len: u64,
+) -> JifResult<BTreeMap<(u64, u64), Vec<u8>>> {
+    let mut map = BTreeMap::new();
+    for (offset, len) in JifRaw::data_segment_ranges(itree_nodes, data_offset) {
+        let start = data_offset + offset;
+        let end = start.saturating_add(len);
+        let truncated = || {
+            JifError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+                .in_section(JifSection::Data, (start, end))
+        };
+        // the segment is not allocated if it is not in the file
+        if end > file_len {
+            return Err(truncated());
+        }
+
+        let mut data = vec![0u8; len as usize];
+        seek(r, SeekFrom::Start(start)).await?;
+        if read_full(r, &mut data).await? != data.len() {
+            return Err(truncated());
+        }
+        map.insert((offset, offset + len), data);
+    }
+
+    Ok(map)
+}
+
+/// Compute the checksum of the first `len` bytes of the file
+async fn read_crc<R: AsyncRead + AsyncSeek + Unpin>(r: &mut R, len: u64) -> std::io::Result<u32> {
+    seek(r, SeekFrom::Start(0)).await?;
+    let mut crc = 0;
+    let mut to_read = len;
+    let mut buffer = vec![0u8; 1 << 16];
+    while to_read > 0 {
+        let chunk = &mut buffer[..std::cmp::min(to_read as usize, 1 << 16)];
+        if read_full(r, chunk).await? != chunk.len() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        crc = crc32c(crc, chunk);
+        to_read -= chunk.len() as u64;
+    }
+
+    Ok(crc)
+}
@@ -0,0 +1,107 @@
+use crate::error::*;
+use crate::integrity::{crc32c, IntegrityTrailer};
+use crate::utils::{read_u32, read_u64};
+
+use std::io::{Read, Seek, SeekFrom};
+
+impl IntegrityTrailer {
+    /// Find the offset of the trailer, from its size at the end of the file
+    ///
+    /// Leaves the reader positioned at the end of the file
+    pub(crate) fn offset_from_reader<R: Read + Seek>(r: &mut R) -> JifResult<u64> {
+        let file_size = r.seek(SeekFrom::End(-(std::mem::size_of::<u64>() as i64)))?
+            + std::mem::size_of::<u64>() as u64;
+        let trailer_size = read_u64(r, &mut [0u8; 8])?;
+        file_size
+            .checked_sub(trailer_size)
+            .ok_or(JifError::BadIntegrityTrailer)
+    }
+
+    /// Read the trailer at the end of the file
+    ///
+    /// The stream position is not preserved
+    pub(crate) fn from_reader<R: Read + Seek>(r: &mut R) -> JifResult<Self> {
+        let mut buffer = [0u8; 8];
+        let offset = IntegrityTrailer::offset_from_reader(r)?;
+        let trailer_size = r.stream_position()? - offset;
+
+        r.seek(SeekFrom::Start(offset))?;
+        let n_segments = read_u64(r, &mut buffer)?;
+
+        let mut trailer = IntegrityTrailer {
+            segment_crcs: Vec::new(),
+            file_crc: 0,
+            offset,
+        };
+        if (n_segments as usize).saturating_mul(std::mem::size_of::<u32>()) > trailer_size as usize
+        {
+            return Err(JifError::BadIntegrityTrailer);
+        }
+
+        let mut buffer = [0u8; 4];
+        trailer.segment_crcs = (0..n_segments)
+            .map(|_| read_u32(r, &mut buffer))
+            .collect::<Result<Vec<_>, _>>()?;
+        trailer.file_crc = read_u32(r, &mut buffer)?;
+
+        if trailer.serialized_size() as u64 != trailer_size {
+            return Err(JifError::BadIntegrityTrailer);
+        }
+
+        Ok(trailer)
+    }
+
+    /// Verify the data segments and the file contents (up to the trailer) against the checksums
+    ///
+    /// The stream position is not preserved
+    pub(crate) fn verify<'a, R: Read + Seek>(
+        &self,
+        r: &mut R,
+        data_segments: impl ExactSizeIterator<Item = ((u64, u64), &'a [u8])>,
+    ) -> JifResult<()> {
+        self.verify_segments(data_segments)?;
+
+        r.seek(SeekFrom::Start(0))?;
+        let mut file_crc = 0;
+        let mut to_read = self.offset;
+        let mut buffer = vec![0u8; 1 << 16];
+        while to_read > 0 {
+            let chunk = &mut buffer[..std::cmp::min(to_read as usize, 1 << 16)];
+            r.read_exact(chunk)?;
+            file_crc = crc32c(file_crc, chunk);
+            to_read -= chunk.len() as u64;
+        }
+
+        self.verify_file(file_crc)
+    }
+
+    /// Verify the data segments (by their ranges in the data section) against their checksums
+    pub(crate) fn verify_segments<'a>(
+        &self,
+        data_segments: impl ExactSizeIterator<Item = ((u64, u64), &'a [u8])>,
+    ) -> JifResult<()> {
+        if self.segment_crcs.len() != data_segments.len() {
+            return Err(JifError::BadIntegrityTrailer);
+        }
+
+        for ((range, data), crc) in data_segments.zip(self.segment_crcs.iter()) {
+            if crc32c(0, data) != *crc {
+                return Err(JifError::BadSegmentChecksum { data_range: range });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify the checksum of the file up to the trailer
+    pub(crate) fn verify_file(&self, file_crc: u32) -> JifResult<()> {
+        if file_crc != self.file_crc {
+            return Err(JifError::BadFileChecksum {
+                expected: self.file_crc,
+                found: file_crc,
+            });
+        }
+
+        Ok(())
+    }
+}
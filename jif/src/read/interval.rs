@@ -5,9 +5,9 @@ use crate::utils::{is_page_aligned, read_u64};
 use std::io::Read;
 
 impl RawInterval {
-    /// Read and parse a RawInterval
-    pub fn from_reader<R: Read>(r: &mut R) -> IntervalResult<Self> {
-        fn read_page_aligned_u64<R: Read>(r: &mut R, buffer: &mut [u8; 8]) -> IntervalResult<u64> {
+    /// Read and parse a RawInterval (aligned to the `page_size` of the JIF)
+    pub fn from_reader<R: Read>(r: &mut R, page_size: usize) -> IntervalResult<Self> {
+        let read_page_aligned_u64 = |r: &mut R, buffer: &mut [u8; 8]| {
             let v = read_u64(r, buffer)?;
 
             // MAX is a special value
@@ -15,12 +15,12 @@ impl RawInterval {
                 return Ok(v);
             }
 
-            if !is_page_aligned(v) {
+            if !is_page_aligned(v, page_size) {
                 Err(IntervalError::BadAlignment(v))
             } else {
                 Ok(v)
             }
-        }
+        };
 
         let mut buffer = [0u8; 8];
 
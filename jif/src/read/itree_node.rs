@@ -1,19 +1,25 @@
 use crate::error::*;
 use crate::itree::interval::RawInterval;
-use crate::itree::itree_node::{RawITreeNode, IVAL_PER_NODE};
+use crate::itree::itree_node::RawITreeNode;
 
 use std::io::Read;
 
 impl RawITreeNode {
-    /// Read and parse an RawITreeNode
-    pub fn from_reader<R: Read>(r: &mut R) -> ITreeNodeResult<Self> {
-        let mut ranges = [RawInterval::default(); IVAL_PER_NODE];
+    /// Read and parse an RawITreeNode (aligned to the `page_size` of the JIF) of a tree with
+    /// this `fanout`
+    pub fn from_reader<R: Read>(
+        r: &mut R,
+        page_size: usize,
+        fanout: usize,
+    ) -> ITreeNodeResult<Self> {
+        let mut ranges = vec![RawInterval::default(); fanout - 1];
         for (interval_idx, interval) in ranges.iter_mut().enumerate() {
-            *interval =
-                RawInterval::from_reader(r).map_err(|interval_err| ITreeNodeError::Interval {
+            *interval = RawInterval::from_reader(r, page_size).map_err(|interval_err| {
+                ITreeNodeError::Interval {
                     interval_idx,
                     interval_err,
-                })?;
+                }
+            })?;
         }
 
         Ok(RawITreeNode::new(ranges))
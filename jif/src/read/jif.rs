@@ -1,23 +1,173 @@
+use crate::arch::Arch;
+use crate::compress::{decompress_blocks, Compression};
 use crate::error::*;
+use crate::fanout::fanout_from_flags;
+use crate::integrity::IntegrityTrailer;
 use crate::itree::itree_node::RawITreeNode;
-use crate::jif::{JifRaw, JIF_MAGIC_HEADER, JIF_VERSION};
+use crate::jif::{
+    JifHeaderBinary, JifRaw, JIF_FANOUT_SHIFT_MASK, JIF_FLAGS_MASK, JIF_FLAG_BIG_ENDIAN,
+    JIF_FLAG_CHECKSUMS, JIF_FLAG_DELTA, JIF_FLAG_LZ4, JIF_FLAG_ORD_PROVENANCE, JIF_ISA_MASK,
+    JIF_MAGIC_HEADER, JIF_PAGE_SHIFT_MASK, JIF_VERSION, JIF_VERSION_ORD_WEIGHTS,
+};
+use crate::layout::DataLayout;
+use crate::meta::meta_size;
 use crate::ord::OrdChunk;
+use crate::page_index::PageHashIndex;
 use crate::pheader::JifRawPheader;
 use crate::utils::{is_page_aligned, read_u32, read_u64, seek_to_page};
 
 use std::collections::{BTreeMap, BTreeSet};
-use std::io::{BufReader, Read, Seek};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 
 impl JifRaw {
+    /// Read and parse a JIF from the bytes of a file (see [`JifRaw::from_reader`])
+    pub fn from_bytes(bytes: &[u8]) -> JifResult<Self> {
+        JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(bytes)))
+    }
+
     /// Read and parse a JIF
     pub fn from_reader<R: Read + Seek>(r: &mut BufReader<R>) -> JifResult<Self> {
-        let header = JifHeader::from_reader(r)?;
+        let mut raw = JifRaw::from_reader_metadata(r)?;
+        let trailer = raw.read_integrity_trailer(r)?;
+        raw.read_trailing_sections(r)?;
+        r.seek(SeekFrom::Start(raw.data_offset))?;
+
+        raw.data_segments = match raw.compression {
+            Compression::None => JifRaw::read_data_segments(r, &raw.itree_nodes, raw.data_offset)?,
+            Compression::Lz4 => raw.read_compressed_data_segments(r, trailer.as_ref())?,
+        };
+
+        if let Some(trailer) = trailer {
+            let data_segments = raw
+                .data_segments
+                .iter()
+                .map(|(range, data)| (*range, data.as_slice()));
+            raw.verify_integrity(&trailer, r, data_segments)?;
+        }
+
+        Ok(raw)
+    }
+
+    /// Read the data segments out of the compressed data section, which runs up to the trailing
+    /// sections (see [`JifRaw::read_trailing_sections`])
+    pub(crate) fn read_compressed_data_segments<R: Read + Seek>(
+        &self,
+        r: &mut R,
+        trailer: Option<&IntegrityTrailer>,
+    ) -> JifResult<BTreeMap<(u64, u64), Vec<u8>>> {
+        let end = match trailer {
+            Some(trailer) => trailer.offset,
+            None => r.seek(SeekFrom::End(0))?,
+        } - meta_size(&self.meta)
+            - self
+                .page_index
+                .as_ref()
+                .map_or(0, PageHashIndex::serialized_size);
+        r.seek(SeekFrom::Start(self.data_offset))?;
+        let mut compressed = Vec::new();
+        r.take(end - self.data_offset)
+            .read_to_end(&mut compressed)?;
+        // offsets in the decompressed data are not file offsets: errors are located in the whole
+        // data section
+        let data_range = (self.data_offset, self.data_offset + compressed.len() as u64);
+        decompress_blocks(&compressed)
+            .and_then(|data| {
+                JifRaw::read_data_segments(
+                    &mut BufReader::new(std::io::Cursor::new(data)),
+                    &self.itree_nodes,
+                    self.data_offset,
+                )
+            })
+            .map_err(|error| match error {
+                JifError::InSection { error, .. } => *error,
+                error => error,
+            })
+            .map_err(|error| error.in_section(JifSection::Data, data_range))
+    }
+
+    /// Read the integrity trailer, if the JIF has checksums
+    pub(crate) fn read_integrity_trailer<R: Read + Seek>(
+        &self,
+        r: &mut R,
+    ) -> JifResult<Option<IntegrityTrailer>> {
+        if !self.checksums {
+            return Ok(None);
+        }
+
+        let trailer = IntegrityTrailer::from_reader(r)?;
+        if trailer.offset < self.data_offset {
+            return Err(JifError::BadIntegrityTrailer
+                .in_section(JifSection::Integrity, (trailer.offset, self.data_offset)));
+        }
+        Ok(Some(trailer))
+    }
+
+    /// Verify the data segments (by their ranges in the data section) and the file against the
+    /// integrity trailer, locating the errors in the file
+    pub(crate) fn verify_integrity<'a, R: Read + Seek>(
+        &self,
+        trailer: &IntegrityTrailer,
+        r: &mut R,
+        data_segments: impl ExactSizeIterator<Item = ((u64, u64), &'a [u8])>,
+    ) -> JifResult<()> {
+        trailer
+            .verify(r, data_segments)
+            .map_err(|error| self.locate_integrity_error(trailer, error))
+    }
+
+    /// Locate an error of the integrity verification in the file: in the data section for the
+    /// data segments, in the integrity section otherwise
+    pub(crate) fn locate_integrity_error(
+        &self,
+        trailer: &IntegrityTrailer,
+        error: JifError,
+    ) -> JifError {
+        match error {
+            JifError::BadSegmentChecksum { data_range } => error.in_section(
+                JifSection::Data,
+                (
+                    self.data_offset + data_range.0,
+                    self.data_offset + data_range.1,
+                ),
+            ),
+            error => error.in_section(
+                JifSection::Integrity,
+                (
+                    trailer.offset,
+                    trailer.offset + trailer.serialized_size() as u64,
+                ),
+            ),
+        }
+    }
+
+    /// Read and parse everything in the JIF up to the data segments
+    /// (which are left empty)
+    ///
+    /// Leaves the reader positioned at the data offset. Parsing errors are located in the file
+    /// (see [`JifError::file_range`])
+    pub(crate) fn from_reader_metadata<R: Read + Seek>(r: &mut BufReader<R>) -> JifResult<Self> {
+        let header_size = std::mem::size_of::<JifHeaderBinary>() as u64;
+        let header = JifHeader::from_reader(r)
+            .map_err(|error| error.in_section(JifSection::Header, (0, header_size)))?;
+
+        // byte range of the `idx`-th item of a section of items of `size` B
+        let item_range = |start: u64, size: usize, idx: usize| {
+            (
+                start + (idx * size) as u64,
+                start + ((idx + 1) * size) as u64,
+            )
+        };
+        let pheader_range =
+            |pheader_idx| item_range(header_size, JifRawPheader::serialized_size(), pheader_idx);
 
         let pheaders = (0..(header.n_pheaders as usize))
             .map(|pheader_idx| {
-                JifRawPheader::from_reader(r).map_err(|pheader_err| JifError::BadPheader {
-                    pheader_idx,
-                    pheader_err,
+                JifRawPheader::from_reader(r, header.arch.page_size).map_err(|pheader_err| {
+                    JifError::BadPheader {
+                        pheader_idx,
+                        pheader_err,
+                    }
+                    .in_section(JifSection::Pheaders, pheader_range(pheader_idx))
                 })
             })
             .collect::<Result<Vec<_>, _>>()?;
@@ -35,7 +185,8 @@ impl JifRaw {
                         offset: p.pathname_offset,
                         size: header.strings_size,
                     },
-                });
+                }
+                .in_section(JifSection::Pheaders, pheader_range(pheader_idx)));
             } else if p.itree_n_nodes > 0
                 && p.itree_idx.saturating_add(p.itree_n_nodes) as usize > n_itree_nodes
             {
@@ -46,88 +197,93 @@ impl JifRaw {
                         tree_len: p.itree_n_nodes,
                         len: n_itree_nodes,
                     },
-                });
+                }
+                .in_section(JifSection::Pheaders, pheader_range(pheader_idx)));
             }
         }
 
-        seek_to_page(r)?;
+        let strings_offset = seek_to_page(r, header.arch.page_size)?;
 
         // read strings
+        let strings_end = strings_offset + header.strings_size as u64;
         let strings_backing = {
-            let mut s = Vec::with_capacity(header.strings_size as usize);
+            let mut s = Vec::new();
             let mut string_reader = r.take(header.strings_size as u64);
 
             string_reader.read_to_end(&mut s)?;
             Ok::<_, JifError>(s)
-        }?;
+        }
+        .map_err(|error| error.in_section(JifSection::Strings, (strings_offset, strings_end)))?;
 
         // read itree nodes
-        let to_skip =
-            header.itrees_size as i64 - (n_itree_nodes * RawITreeNode::serialized_size()) as i64;
+        let node_size = RawITreeNode::serialized_size(header.itree_fanout);
+        let node_range = |itree_node_idx| item_range(strings_end, node_size, itree_node_idx);
+        let to_skip = header.itrees_size as i64 - (n_itree_nodes * node_size) as i64;
         let itree_nodes = (0..n_itree_nodes)
             .map(|itree_node_idx| {
-                RawITreeNode::from_reader(r).map_err(|itree_node_err| JifError::BadITreeNode {
-                    itree_node_idx,
-                    itree_node_err,
-                })
+                RawITreeNode::from_reader(r, header.arch.page_size, header.itree_fanout).map_err(
+                    |itree_node_err| {
+                        JifError::BadITreeNode {
+                            itree_node_idx,
+                            itree_node_err,
+                        }
+                        .in_section(JifSection::ITrees, node_range(itree_node_idx))
+                    },
+                )
             })
             .collect::<Result<Vec<_>, _>>()?;
         r.seek_relative(to_skip)?;
 
         // read ord segments
-        let n_ords = header.ord_size as usize / OrdChunk::serialized_size();
+        let ord_offset = strings_end + header.itrees_size as u64;
+        let ord_chunk_size = OrdChunk::serialized_size(header.ord_provenance);
+        let n_ords = header.ord_size as usize / ord_chunk_size;
         let ord_chunks = (0..n_ords)
             .map(|ord_chunk_idx| {
-                OrdChunk::from_reader(r).map_err(|ord_chunk_err| JifError::BadOrdChunk {
-                    ord_chunk_idx,
-                    ord_chunk_err,
+                OrdChunk::from_reader(
+                    r,
+                    header.arch.page_size,
+                    header.ord_provenance,
+                    header.ord_weights,
+                )
+                .map_err(|ord_chunk_err| {
+                    JifError::BadOrdChunk {
+                        ord_chunk_idx,
+                        ord_chunk_err,
+                    }
+                    .in_section(
+                        JifSection::Ord,
+                        item_range(ord_offset, ord_chunk_size, ord_chunk_idx),
+                    )
                 })
             })
             .filter(|o| o.as_ref().map(|x| !x.is_empty()).unwrap_or(true))
             .collect::<Result<Vec<_>, _>>()?;
 
-        let data_offset = seek_to_page(r)?;
-
-        // read data segments
-        let data_segments = {
-            // deduplicated intervals can issue the same data ranges
-            // we need to deduplicate them here
-            let data_offset_intervals = itree_nodes
-                .iter()
-                .flat_map(|n| n.ranges.iter())
-                .filter(|i| i.is_data())
-                .map(|i| (i.offset - data_offset, i.len()))
-                .collect::<BTreeSet<_>>();
+        let data_offset = seek_to_page(r, header.arch.page_size)?;
 
-            for (ival1, ival2) in data_offset_intervals
+        // the data intervals have to point into the data section
+        for (itree_node_idx, node) in itree_nodes.iter().enumerate() {
+            if let Some((interval_idx, ival)) = node
+                .ranges
                 .iter()
-                .zip(data_offset_intervals.iter().skip(1))
+                .enumerate()
+                .find(|(_idx, ival)| ival.is_data() && ival.offset < data_offset)
             {
-                assert_eq!(
-                    ival1.0 + ival1.1,
-                    ival2.0,
-                    "intervals are not contiguous: [{:#x}; {:#x}) and [{:#x}; {:#x})",
-                    ival1.0,
-                    ival1.0 + ival1.1,
-                    ival2.0,
-                    ival2.0 + ival2.1,
-                );
-            }
-
-            let mut map = BTreeMap::new();
-            for (offset, len) in data_offset_intervals {
-                let data = {
-                    let mut d = Vec::new();
-                    let mut reader = r.take(len);
-                    reader.read_to_end(&mut d)?;
-                    Ok::<Vec<_>, std::io::Error>(d)
-                }?;
-
-                map.insert((offset, offset + len), data);
+                return Err(JifError::BadITreeNode {
+                    itree_node_idx,
+                    itree_node_err: ITreeNodeError::Interval {
+                        interval_idx,
+                        interval_err: IntervalError::InvalidInterval(
+                            ival.start,
+                            ival.end,
+                            ival.offset,
+                        ),
+                    },
+                }
+                .in_section(JifSection::ITrees, node_range(itree_node_idx)));
             }
-
-            Ok::<_, JifError>(map)
-        }?;
+        }
 
         Ok(JifRaw {
             pheaders,
@@ -135,24 +291,98 @@ impl JifRaw {
             itree_nodes,
             ord_chunks,
             data_offset,
-            data_segments,
+            data_segments: BTreeMap::new(),
             n_prefetch: header.n_prefetch,
+            compression: header.compression,
+            delta: header.delta,
+            checksums: header.checksums,
+            itree_fanout: header.itree_fanout,
+            data_layout: DataLayout::default(),
+            arch: header.arch,
+            page_index: None,
+            meta: BTreeMap::new(),
         })
     }
+
+    /// Find the data segments referenced by the interval tree nodes, as `(offset, len)` pairs
+    /// relative to the data offset
+    ///
+    /// The segments written out by [`JifRaw::to_writer`] are contiguous, but the ones left by
+    /// [`JifRaw::rewrite_in_place`] may have gaps (replaced data) or overlap (split intervals)
+    pub(crate) fn data_segment_ranges(
+        itree_nodes: &[RawITreeNode],
+        data_offset: u64,
+    ) -> BTreeSet<(u64, u64)> {
+        // deduplicated intervals can issue the same data ranges
+        // we need to deduplicate them here
+        itree_nodes
+            .iter()
+            .flat_map(|n| n.ranges.iter())
+            .filter(|i| i.is_data())
+            .map(|i| (i.offset - data_offset, i.len()))
+            .collect::<BTreeSet<_>>()
+    }
+
+    /// Read the data segments referenced by the interval tree nodes
+    ///
+    /// Assumes the reader is positioned at the data offset
+    pub(crate) fn read_data_segments<R: Read + Seek>(
+        r: &mut BufReader<R>,
+        itree_nodes: &[RawITreeNode],
+        data_offset: u64,
+    ) -> JifResult<BTreeMap<(u64, u64), Vec<u8>>> {
+        let data_offset_intervals = JifRaw::data_segment_ranges(itree_nodes, data_offset);
+
+        let mut map = BTreeMap::new();
+        let mut cursor = 0;
+        for (offset, len) in data_offset_intervals {
+            if offset != cursor {
+                r.seek_relative(offset as i64 - cursor as i64)?;
+            }
+            cursor = offset + len;
+
+            let data = {
+                let mut d = Vec::new();
+                let mut reader = r.take(len);
+                reader.read_to_end(&mut d)?;
+                Ok::<Vec<_>, std::io::Error>(d)
+            }?;
+            if data.len() as u64 != len {
+                return Err(JifError::from(std::io::Error::from(
+                    std::io::ErrorKind::UnexpectedEof,
+                ))
+                .in_section(
+                    JifSection::Data,
+                    (data_offset + offset, data_offset + offset + len),
+                ));
+            }
+
+            map.insert((offset, offset + len), data);
+        }
+
+        Ok(map)
+    }
 }
 
 #[derive(Debug)]
-struct JifHeader {
-    n_pheaders: u32,
-    strings_size: u32,
-    itrees_size: u32,
-    ord_size: u32,
-    n_prefetch: u64,
+pub(super) struct JifHeader {
+    pub(super) n_pheaders: u32,
+    pub(super) strings_size: u32,
+    pub(super) itrees_size: u32,
+    pub(super) ord_size: u32,
+    pub(super) n_prefetch: u64,
+    pub(super) compression: Compression,
+    pub(super) delta: bool,
+    pub(super) checksums: bool,
+    pub(super) ord_provenance: bool,
+    pub(super) ord_weights: bool,
+    pub(super) itree_fanout: usize,
+    pub(super) arch: Arch,
 }
 
 impl JifHeader {
     /// Read and parse a JIF header
-    fn from_reader<R: Read>(r: &mut R) -> JifResult<Self> {
+    pub(super) fn from_reader<R: Read>(r: &mut R) -> JifResult<Self> {
         let mut buffer = [0u8; 4];
         r.read_exact(&mut buffer)?;
 
@@ -162,26 +392,40 @@ impl JifHeader {
 
         let n_pheaders = read_u32(r, &mut buffer)?;
         let strings_size = read_u32(r, &mut buffer)?;
-        if !is_page_aligned(strings_size as u64) {
-            return Err(JifError::BadAlignment);
-        }
         let itrees_size = read_u32(r, &mut buffer)?;
-        if !is_page_aligned(itrees_size as u64) {
-            return Err(JifError::BadAlignment);
-        }
         let ord_size = read_u32(r, &mut buffer)?;
-        if !is_page_aligned(ord_size as u64) {
-            return Err(JifError::BadAlignment);
-        }
 
-        let version = read_u32(r, &mut buffer)?;
-        if version != JIF_VERSION {
+        // the upper bits of the version word hold the flags
+        let version_word = read_u32(r, &mut buffer)?;
+        let version = version_word & !JIF_FLAGS_MASK;
+        if version != JIF_VERSION && version != JIF_VERSION_ORD_WEIGHTS {
             return Err(JifError::BadVersion {
-                expected: JIF_VERSION,
+                expected: JIF_VERSION_ORD_WEIGHTS,
                 found: version,
             });
         }
 
+        let flags = version_word & JIF_FLAGS_MASK;
+        let known_flags = JIF_FLAG_LZ4
+            | JIF_FLAG_DELTA
+            | JIF_FLAG_CHECKSUMS
+            | JIF_FLAG_BIG_ENDIAN
+            | JIF_ISA_MASK
+            | JIF_PAGE_SHIFT_MASK
+            | JIF_FLAG_ORD_PROVENANCE
+            | JIF_FANOUT_SHIFT_MASK;
+        if flags & !known_flags != 0 {
+            return Err(JifError::BadFlags { flags });
+        }
+
+        let arch = Arch::from_flags(flags)?;
+        if [strings_size, itrees_size, ord_size]
+            .into_iter()
+            .any(|size| !is_page_aligned(size as u64, arch.page_size))
+        {
+            return Err(JifError::BadAlignment);
+        }
+
         let mut buffer = [0u8; 8];
         let n_prefetch = read_u64(r, &mut buffer)?;
 
@@ -191,6 +435,13 @@ impl JifHeader {
             itrees_size,
             ord_size,
             n_prefetch,
+            compression: Compression::from_flags(flags),
+            delta: flags & JIF_FLAG_DELTA != 0,
+            checksums: flags & JIF_FLAG_CHECKSUMS != 0,
+            ord_provenance: flags & JIF_FLAG_ORD_PROVENANCE != 0,
+            ord_weights: version == JIF_VERSION_ORD_WEIGHTS,
+            itree_fanout: fanout_from_flags(flags),
+            arch,
         })
     }
 }
@@ -0,0 +1,559 @@
+//! Lenient parsing: recover as much as possible of a corrupt JIF
+//!
+//! The header is needed to find the sections, so a bad header is still an error. Past it, a bad
+//! structure is recorded and skipped: pheaders, interval tree nodes and ord chunks are fixed
+//! size, so a bad one does not throw off the ones after it. The pheaders which depend on a bad
+//! structure (their interval tree nodes, their string or their data segments) are dropped
+//! along with it, and a bad trailing section is left out. Once materialized, the pheaders which
+//! fail the validation (see [`crate::validate`]) or overlap another one are dropped too: what is
+//! salvaged can be written back out
+
+use crate::compress::{decompress_blocks, Compression};
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::integrity::IntegrityTrailer;
+use crate::itree::interval::RawInterval;
+use crate::itree::itree_node::{RawITreeNode, FANOUT};
+use crate::jif::{Jif, JifHeaderBinary, JifRaw};
+use crate::layout::DataLayout;
+use crate::meta::meta_size;
+use crate::ord::OrdChunk;
+use crate::page_index::PageHashIndex;
+use crate::pheader::{JifPheader, JifRawPheader};
+use crate::utils::page_align;
+use crate::validate::{Severity, ValidationReport};
+
+use super::jif::JifHeader;
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+/// Error for a structure cut short by the end of the file
+fn truncated(section: JifSection, file_range: (u64, u64)) -> JifError {
+    JifError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+        .in_section(section, file_range)
+}
+
+impl JifRaw {
+    /// Parse the bytes of a JIF, skipping the bad structures and recording their errors
+    pub(crate) fn from_bytes_lenient(bytes: &[u8], errors: &mut Vec<JifError>) -> JifResult<Self> {
+        let header_size = std::mem::size_of::<JifHeaderBinary>() as u64;
+        let header = JifHeader::from_reader(&mut &bytes[..])
+            .map_err(|error| error.in_section(JifSection::Header, (0, header_size)))?;
+        let page_size = header.arch.page_size;
+
+        let item_range = |start: u64, size: usize, idx: usize| {
+            (
+                start + (idx * size) as u64,
+                start + ((idx + 1) * size) as u64,
+            )
+        };
+        let item = |(start, end): (u64, u64)| bytes.get(start as usize..end as usize);
+
+        // read the pheaders (with their indices, to locate the errors)
+        let pheader_size = JifRawPheader::serialized_size();
+        let pheader_range = |pheader_idx| item_range(header_size, pheader_size, pheader_idx);
+        let mut pheaders = Vec::new();
+        for pheader_idx in 0..header.n_pheaders as usize {
+            let Some(mut pheader) = item(pheader_range(pheader_idx)) else {
+                errors.push(truncated(JifSection::Pheaders, pheader_range(pheader_idx)));
+                break;
+            };
+            match JifRawPheader::from_reader(&mut pheader, page_size) {
+                Ok(pheader) => pheaders.push((pheader_idx, pheader)),
+                Err(pheader_err) => errors.push(
+                    JifError::BadPheader {
+                        pheader_idx,
+                        pheader_err,
+                    }
+                    .in_section(JifSection::Pheaders, pheader_range(pheader_idx)),
+                ),
+            }
+        }
+
+        // read the strings
+        let strings_offset = page_align(
+            header_size + header.n_pheaders as u64 * pheader_size as u64,
+            page_size,
+        );
+        let strings_end = strings_offset + header.strings_size as u64;
+        let strings_backing = match item((strings_offset, strings_end)) {
+            Some(strings) => strings.to_vec(),
+            None => {
+                errors.push(truncated(
+                    JifSection::Strings,
+                    (strings_offset, strings_end),
+                ));
+                bytes
+                    .get(strings_offset as usize..)
+                    .unwrap_or_default()
+                    .to_vec()
+            }
+        };
+
+        // read the interval tree nodes the pheaders need (`None` for the bad ones)
+        let node_size = RawITreeNode::serialized_size(header.itree_fanout);
+        let node_range = |itree_node_idx| item_range(strings_end, node_size, itree_node_idx);
+        let ord_offset = strings_end + header.itrees_size as u64;
+        let data_offset = page_align(ord_offset + header.ord_size as u64, page_size);
+        let n_itree_nodes = std::cmp::min(
+            header.itrees_size as usize / node_size,
+            pheaders
+                .iter()
+                .map(|(_idx, p)| p.itree_idx.saturating_add(p.itree_n_nodes) as usize)
+                .max()
+                .unwrap_or(0),
+        );
+        let mut itree_nodes = Vec::with_capacity(n_itree_nodes);
+        for itree_node_idx in 0..n_itree_nodes {
+            let Some(mut node) = item(node_range(itree_node_idx)) else {
+                errors.push(truncated(JifSection::ITrees, node_range(itree_node_idx)));
+                break;
+            };
+            let node = RawITreeNode::from_reader(&mut node, page_size, header.itree_fanout)
+                .and_then(|node| {
+                    // the data intervals have to point into the data section
+                    match node
+                        .ranges
+                        .iter()
+                        .enumerate()
+                        .find(|(_idx, ival)| ival.is_data() && ival.offset < data_offset)
+                    {
+                        Some((interval_idx, ival)) => Err(ITreeNodeError::Interval {
+                            interval_idx,
+                            interval_err: IntervalError::InvalidInterval(
+                                ival.start,
+                                ival.end,
+                                ival.offset,
+                            ),
+                        }),
+                        None => Ok(node),
+                    }
+                });
+            match node {
+                Ok(node) => itree_nodes.push(Some(node)),
+                Err(itree_node_err) => {
+                    errors.push(
+                        JifError::BadITreeNode {
+                            itree_node_idx,
+                            itree_node_err,
+                        }
+                        .in_section(JifSection::ITrees, node_range(itree_node_idx)),
+                    );
+                    itree_nodes.push(None);
+                }
+            }
+        }
+
+        // keep the pheaders with a string and (good) interval tree nodes
+        pheaders.retain(|(pheader_idx, p)| {
+            // the strings may be cut short
+            let pheader_err = if p.pathname_offset != u32::MAX
+                && p.pathname_offset as usize >= strings_backing.len()
+            {
+                PheaderError::InvalidOffset {
+                    offset: p.pathname_offset,
+                    size: header.strings_size,
+                }
+            } else if p.itree_n_nodes > 0
+                && p.itree_idx.saturating_add(p.itree_n_nodes) as usize > itree_nodes.len()
+            {
+                PheaderError::InvalidITreeIndex {
+                    index: p.itree_idx,
+                    tree_len: p.itree_n_nodes,
+                    len: itree_nodes.len(),
+                }
+            } else {
+                // a bad node is reported on its own
+                return p.itree().is_none_or(|(idx, n)| {
+                    itree_nodes[idx as usize..(idx + n) as usize]
+                        .iter()
+                        .all(Option::is_some)
+                });
+            };
+
+            errors.push(
+                JifError::BadPheader {
+                    pheader_idx: *pheader_idx,
+                    pheader_err,
+                }
+                .in_section(JifSection::Pheaders, pheader_range(*pheader_idx)),
+            );
+            false
+        });
+
+        // read the ord chunks
+        let ord_chunk_size = OrdChunk::serialized_size(header.ord_provenance);
+        let mut ord_chunks = Vec::new();
+        for ord_chunk_idx in 0..header.ord_size as usize / ord_chunk_size {
+            let ord_range = item_range(ord_offset, ord_chunk_size, ord_chunk_idx);
+            let Some(mut chunk) = item(ord_range) else {
+                errors.push(truncated(JifSection::Ord, ord_range));
+                break;
+            };
+            match OrdChunk::from_reader(
+                &mut chunk,
+                page_size,
+                header.ord_provenance,
+                header.ord_weights,
+            ) {
+                Ok(chunk) if chunk.is_empty() => {}
+                Ok(chunk) => ord_chunks.push(chunk),
+                Err(ord_chunk_err) => errors.push(
+                    JifError::BadOrdChunk {
+                        ord_chunk_idx,
+                        ord_chunk_err,
+                    }
+                    .in_section(JifSection::Ord, ord_range),
+                ),
+            }
+        }
+
+        // the bad nodes are left empty: no pheader references them anymore
+        let empty_node =
+            || RawITreeNode::new(vec![RawInterval::default(); header.itree_fanout - 1]);
+        let mut raw = JifRaw {
+            pheaders: pheaders
+                .into_iter()
+                .map(|(_idx, pheader)| pheader)
+                .collect(),
+            strings_backing,
+            itree_nodes: itree_nodes
+                .into_iter()
+                .map(|node| node.unwrap_or_else(empty_node))
+                .collect(),
+            ord_chunks,
+            data_offset,
+            data_segments: BTreeMap::new(),
+            n_prefetch: header.n_prefetch,
+            compression: header.compression,
+            delta: header.delta,
+            checksums: header.checksums,
+            itree_fanout: header.itree_fanout,
+            data_layout: DataLayout::default(),
+            arch: header.arch,
+            page_index: None,
+            meta: BTreeMap::new(),
+        };
+
+        let mut file = std::io::Cursor::new(bytes);
+        let trailer = match raw.checksums {
+            true => IntegrityTrailer::from_reader(&mut file)
+                .map_err(|error| errors.push(error))
+                .ok()
+                .filter(|trailer| trailer.offset >= data_offset),
+            false => None,
+        };
+        if let Err(error) = raw.read_trailing_sections(&mut file) {
+            errors.push(error);
+            raw.meta = BTreeMap::new();
+            raw.page_index = None;
+        }
+
+        // read the data segments
+        let data = match raw.compression {
+            Compression::None => bytes
+                .get(data_offset as usize..)
+                .unwrap_or_default()
+                .to_vec(),
+            Compression::Lz4 => {
+                // the compressed data runs up to the trailing sections
+                let end = trailer.as_ref().map_or(bytes.len() as u64, |t| t.offset)
+                    - meta_size(&raw.meta)
+                    - raw
+                        .page_index
+                        .as_ref()
+                        .map_or(0, PageHashIndex::serialized_size);
+                let data_range = (data_offset, std::cmp::max(data_offset, end));
+                match item(data_range).map(decompress_blocks) {
+                    Some(Ok(data)) => data,
+                    Some(Err(error)) => {
+                        errors.push(error.in_section(JifSection::Data, data_range));
+                        Vec::new()
+                    }
+                    None => {
+                        errors.push(truncated(JifSection::Data, data_range));
+                        Vec::new()
+                    }
+                }
+            }
+        };
+        for (offset, len) in JifRaw::data_segment_ranges(&raw.itree_nodes, data_offset) {
+            match data.get(offset as usize..(offset + len) as usize) {
+                Some(segment) => {
+                    raw.data_segments
+                        .insert((offset, offset + len), segment.to_vec());
+                }
+                None => errors.push(truncated(
+                    JifSection::Data,
+                    (data_offset + offset, data_offset + offset + len),
+                )),
+            }
+        }
+
+        if let Some(trailer) = trailer {
+            if let Err(error) = trailer.verify(
+                &mut file,
+                raw.data_segments
+                    .iter()
+                    .map(|(range, data)| (*range, data.as_slice())),
+            ) {
+                errors.push(error.in_section(
+                    JifSection::Integrity,
+                    (
+                        trailer.offset,
+                        trailer.offset + trailer.serialized_size() as u64,
+                    ),
+                ));
+            }
+        }
+
+        Ok(raw)
+    }
+}
+
+impl Jif {
+    /// Read the [`Jif`] from a file, recovering as much as possible when it is corrupt
+    ///
+    /// The bad structures (pheaders, interval tree nodes, ord chunks, data segments and trailing
+    /// sections) are skipped, along with the pheaders which depend on them, and their errors are
+    /// returned alongside the JIF. The pheaders which fail the validation (or overlap) are
+    /// dropped as well, and so are the ord chunks they leave unmapped. Only a bad header fails
+    /// the read: without it, the sections cannot be found
+    pub fn from_reader_lenient<R: Read + Seek>(
+        r: &mut BufReader<R>,
+    ) -> JifResult<(Self, Vec<JifError>)> {
+        let mut bytes = Vec::new();
+        r.seek(SeekFrom::Start(0))?;
+        r.read_to_end(&mut bytes)?;
+
+        let mut errors = Vec::new();
+        let mut raw = JifRaw::from_bytes_lenient(&bytes, &mut errors)?;
+        if raw.delta {
+            return Err(JifError::DeltaWithoutBase);
+        }
+
+        let (deduper, offset_index) = Deduper::from_data_map(raw.take_data());
+        if let Some(index) = &raw.page_index {
+            deduper
+                .digests()
+                .seed(index, &offset_index, raw.arch.page_size);
+        }
+        raw.set_itree_fanout(FANOUT)?;
+        let mut pheaders = Vec::with_capacity(raw.pheaders.len());
+        for raw_pheader in &raw.pheaders {
+            match JifPheader::from_raw(&raw, raw_pheader, &deduper, &offset_index) {
+                Ok(pheader) => pheaders.push(pheader),
+                Err(error) => errors.push(error),
+            }
+        }
+        pheaders.sort_by_key(|pheader| pheader.virtual_range().0);
+
+        let mut jif = Jif {
+            pheaders,
+            ord_chunks: Vec::new(),
+            deduper,
+            arch: raw.arch,
+            meta: raw.meta,
+        };
+        jif.drop_invalid_pheaders(&mut errors);
+        // the ord chunks have to fit the pheaders which are left
+        for (ord_chunk_idx, chunk) in raw.ord_chunks.into_iter().enumerate() {
+            match jif.unmapped_page(&chunk) {
+                Some(vaddr) => errors.push(JifError::BadOrdChunk {
+                    ord_chunk_idx,
+                    ord_chunk_err: OrdChunkError::UnmappedAddress(vaddr),
+                }),
+                None => jif.ord_chunks.push(chunk),
+            }
+        }
+        jif.gc();
+
+        Ok((jif, errors))
+    }
+
+    /// Drop the (sorted) pheaders which are sound on their own but not together: the ones which
+    /// fail the validation, and the ones which overlap another pheader
+    fn drop_invalid_pheaders(&mut self, errors: &mut Vec<JifError>) {
+        let mut dropped = vec![false; self.pheaders.len()];
+        for (idx, pheader) in self.pheaders.iter().enumerate() {
+            let mut report = ValidationReport::default();
+            self.validate_pheader(pheader, &mut HashSet::new(), &mut report);
+            for finding in report.with_severity(Severity::Error) {
+                dropped[idx] = true;
+                errors.push(JifError::InvalidPheader {
+                    virtual_range: pheader.virtual_range(),
+                    finding: finding.clone(),
+                });
+            }
+        }
+
+        // either of two overlapping pheaders may be the corrupt one: both are dropped
+        let mut furthest: Option<usize> = None;
+        for idx in 0..self.pheaders.len() {
+            let range = self.pheaders[idx].virtual_range();
+            match furthest {
+                Some(prev) if self.pheaders[prev].virtual_range().1 > range.0 => {
+                    errors.push(JifError::OverlappingPheaders {
+                        pheader_1: self.pheaders[prev].virtual_range(),
+                        pheader_2: range,
+                    });
+                    dropped[prev] = true;
+                    dropped[idx] = true;
+                    if range.1 > self.pheaders[prev].virtual_range().1 {
+                        furthest = Some(idx);
+                    }
+                }
+                _ => furthest = Some(idx),
+            }
+        }
+
+        let mut dropped = dropped.into_iter();
+        self.pheaders.retain(|_pheader| !dropped.next().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    fn gen_file() -> Vec<u8> {
+        let page = PAGE_SIZE as u64;
+        let mut builder = crate::JifBuilder::new();
+        for (idx, start) in [0x100000u64, 0x200000, 0x300000].into_iter().enumerate() {
+            builder.add_anonymous_segment(
+                (start, start + 2 * page),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![(start, vec![idx as u8 + 1; PAGE_SIZE])],
+            );
+        }
+        builder.add_reference_segment(
+            (0x400000, 0x400000 + page),
+            ProtFlags::READ,
+            "/lib/libc.so".to_string(),
+            0,
+            vec![],
+        );
+        let mut jif = builder.build().unwrap();
+        jif.set_metadata("build", "v1");
+
+        let mut file = Vec::new();
+        jif.to_writer(&mut file).unwrap();
+        file
+    }
+
+    fn read(file: &[u8]) -> JifResult<(Jif, Vec<JifError>)> {
+        Jif::from_reader_lenient(&mut BufReader::new(std::io::Cursor::new(file)))
+    }
+
+    fn starts(jif: &Jif) -> Vec<u64> {
+        jif.pheaders()
+            .iter()
+            .map(|pheader| pheader.virtual_range().0)
+            .collect()
+    }
+
+    #[test]
+    fn lenient() {
+        let file = gen_file();
+        let (jif, errors) = read(&file).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(jif.equivalent(&Jif::from_bytes(&file).unwrap()));
+
+        let raw = JifRaw::from_bytes(&file).unwrap();
+        let layout = raw.layout();
+        let file_idx = |start| {
+            raw.pheaders()
+                .iter()
+                .position(|pheader| pheader.virtual_range().0 == start)
+                .unwrap()
+        };
+
+        // a pheader with an unaligned address is skipped
+        let mut bad_pheader = file.clone();
+        let pheader_offset = (layout.pheaders.0
+            + (file_idx(0x200000) * JifRawPheader::serialized_size()) as u64)
+            as usize;
+        bad_pheader[pheader_offset] = 0x10;
+        assert!(Jif::from_bytes(&bad_pheader).is_err());
+        let (jif, errors) = read(&bad_pheader).unwrap();
+        assert_eq!(starts(&jif), vec![0x100000, 0x300000, 0x400000]);
+        assert!(matches!(
+            &errors[..],
+            [JifError::InSection {
+                section: JifSection::Pheaders,
+                ..
+            }]
+        ));
+        assert_eq!(jif.resolve_data(0x300000), Some(&[3; PAGE_SIZE][..]));
+        assert_eq!(jif.metadata().get("build").unwrap(), "v1");
+
+        // so is the pheader of a bad interval tree node
+        let mut bad_node = file.clone();
+        let (itree_idx, _n) = raw.pheaders()[file_idx(0x100000)].itree().unwrap();
+        let node_offset = (layout.itrees.0
+            + itree_idx as u64 * RawITreeNode::serialized_size(FANOUT) as u64)
+            as usize;
+        // the offset of the first interval points before the data section
+        bad_node[node_offset + 16..node_offset + 24].copy_from_slice(&0u64.to_le_bytes());
+        let (jif, errors) = read(&bad_node).unwrap();
+        assert_eq!(starts(&jif), vec![0x200000, 0x300000, 0x400000]);
+        assert!(matches!(
+            errors[0].file_range(),
+            Some((JifSection::ITrees, _))
+        ));
+
+        // a truncated file keeps the pheaders with their data
+        let last_segment = layout.segments.last().unwrap().range;
+        let (jif, errors) = read(&file[..last_segment.0 as usize]).unwrap();
+        assert_eq!(jif.pheaders().len(), 3);
+        // the missing segment, and the interval tree which cannot be materialized without it
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0].file_range(),
+            Some((JifSection::Data, _))
+        ));
+
+        // without a header, there is nothing to recover
+        assert!(read(&file[..8]).is_err());
+    }
+
+    #[test]
+    fn lenient_overlap() {
+        let file = gen_file();
+        let raw = JifRaw::from_bytes(&file).unwrap();
+        let pheader_idx = raw
+            .pheaders()
+            .iter()
+            .position(|pheader| pheader.virtual_range().0 == 0x100000)
+            .unwrap();
+
+        // each pheader is sound on its own, but the first one now runs into the second one
+        let mut overlapping = file.clone();
+        let vend_offset = (raw.layout().pheaders.0
+            + (pheader_idx * JifRawPheader::serialized_size()) as u64
+            + 8) as usize;
+        overlapping[vend_offset..vend_offset + 8]
+            .copy_from_slice(&(0x200000u64 + PAGE_SIZE as u64).to_le_bytes());
+        let (jif, errors) = read(&overlapping).unwrap();
+        assert_eq!(starts(&jif), vec![0x300000, 0x400000]);
+        assert!(matches!(
+            &errors[..],
+            [JifError::OverlappingPheaders {
+                pheader_1: (0x100000, _),
+                pheader_2: (0x200000, _),
+            }]
+        ));
+        assert!(jif.validate().is_clean(), "{}", jif.validate());
+
+        // what is salvaged can be written back out
+        let mut salvaged = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut salvaged)
+            .unwrap();
+        let (jif, _errors) = read(&overlapping).unwrap();
+        assert!(jif.equivalent(&Jif::from_bytes(&salvaged).unwrap()));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::compress::Compression;
+use crate::error::*;
+use crate::integrity::IntegrityTrailer;
+use crate::jif::JifRaw;
+use crate::meta::{meta_size, FOOTER_SIZE, META_MAGIC};
+use crate::utils::{read_u32, read_u64};
+
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+
+/// A trailing section: its range, and its contents (without the footer)
+type TrailingSection = ((u64, u64), Vec<u8>);
+
+/// Find a trailing section (see [`crate::meta`]) ending at `end` by its footer, if there is one
+/// (at or after `start`)
+///
+/// Without a footer of the `magic` (or with one which does not fit in the range), there is no
+/// section. The stream position is not preserved
+pub(super) fn read_trailing_section<R: Read + Seek>(
+    r: &mut R,
+    (start, end): (u64, u64),
+    magic: [u8; 4],
+) -> std::io::Result<Option<TrailingSection>> {
+    if end < start.saturating_add(FOOTER_SIZE as u64) {
+        return Ok(None);
+    }
+
+    r.seek(SeekFrom::Start(end - FOOTER_SIZE as u64))?;
+    let size = read_u64(r, &mut [0u8; 8])?;
+    let mut found = [0u8; 4];
+    r.read_exact(&mut found)?;
+    if found != magic || size < FOOTER_SIZE as u64 || size > end - start {
+        return Ok(None);
+    }
+
+    r.seek(SeekFrom::Start(end - size))?;
+    let mut contents = vec![0u8; size as usize - FOOTER_SIZE];
+    r.read_exact(&mut contents)?;
+    Ok(Some(((end - size, end), contents)))
+}
+
+impl JifRaw {
+    /// Read the trailing sections (the page hash index and the custom metadata), if the JIF has
+    /// them (see [`crate::meta`])
+    ///
+    /// The sections end at the integrity section (or at the end of the file), past the end of
+    /// the data segments. The stream position is not preserved
+    pub(crate) fn read_trailing_sections<R: Read + Seek>(&mut self, r: &mut R) -> JifResult<()> {
+        let end = if self.checksums {
+            IntegrityTrailer::offset_from_reader(r)?
+        } else {
+            r.seek(SeekFrom::End(0))?
+        };
+        let start = match self.compression {
+            Compression::None => JifRaw::data_segment_ranges(&self.itree_nodes, self.data_offset)
+                .into_iter()
+                .map(|(offset, len)| self.data_offset + offset + len)
+                .max()
+                .unwrap_or(self.data_offset),
+            // the end of the compressed data is not known
+            Compression::Lz4 => self.data_offset,
+        };
+
+        self.meta = JifRaw::read_meta_in(r, (start, end))?;
+        let end = end - meta_size(&self.meta);
+        self.page_index = JifRaw::read_page_index_in(r, (start, end))?;
+        Ok(())
+    }
+
+    /// Read the custom metadata section ending at `end`, if there is one (at or after `start`)
+    ///
+    /// Without a section, the metadata is empty. The stream position is not preserved
+    pub(crate) fn read_meta_in<R: Read + Seek>(
+        r: &mut R,
+        range: (u64, u64),
+    ) -> JifResult<BTreeMap<String, String>> {
+        let mut meta = BTreeMap::new();
+        let Some((range, entries)) = read_trailing_section(r, range, META_MAGIC)? else {
+            return Ok(meta);
+        };
+
+        let bad_meta = |reason| JifError::BadMeta { reason }.in_section(JifSection::Meta, range);
+        let mut cursor = &entries[..];
+        let mut buffer = [0u8; 4];
+        while !cursor.is_empty() {
+            let (key_len, value_len) = match (
+                read_u32(&mut cursor, &mut buffer),
+                read_u32(&mut cursor, &mut buffer),
+            ) {
+                (Ok(key_len), Ok(value_len)) => (key_len as usize, value_len as usize),
+                _ => return Err(bad_meta("truncated entry")),
+            };
+            if key_len.saturating_add(value_len) > cursor.len() {
+                return Err(bad_meta("truncated entry"));
+            }
+
+            let (key, rest) = cursor.split_at(key_len);
+            let (value, rest) = rest.split_at(value_len);
+            let (Ok(key), Ok(value)) = (std::str::from_utf8(key), std::str::from_utf8(value))
+            else {
+                return Err(bad_meta("entry is not UTF-8"));
+            };
+            if meta.insert(key.to_string(), value.to_string()).is_some() {
+                return Err(bad_meta("duplicate key"));
+            }
+            cursor = rest;
+        }
+
+        Ok(meta)
+    }
+}
@@ -1,35 +1,94 @@
 use crate::error::*;
 use crate::itree::interval::DataSource;
-use crate::ord::OrdChunk;
-use crate::ord::{ORD_FLAG_MASK, ORD_PRIVATE_FLAG, ORD_SHARED_FLAG, ORD_ZERO_FLAG};
+use crate::ord::{AccessKind, OrdChunk};
+use crate::ord::{
+    ORD_FLAG_MASK, ORD_PRIVATE_FLAG, ORD_READ_FLAG, ORD_SHARED_FLAG, ORD_TID_FLAG, ORD_WEIGHT_FLAG,
+    ORD_WEIGHT_SHIFT, ORD_WRITE_FLAG, ORD_ZERO_FLAG,
+};
 use crate::utils::{is_page_aligned, read_u64};
 use std::io::Read;
 
 impl OrdChunk {
-    /// Read and parse an OrdChunk
-    pub fn from_reader<R: Read>(r: &mut R) -> OrdChunkResult<Self> {
+    /// Read and parse an OrdChunk (aligned to the `page_size` of the JIF)
+    ///
+    /// With `provenance`, the chunk is followed by its provenance word, which may hold a weight
+    /// with `weights` (i.e., in the format version with weights)
+    pub fn from_reader<R: Read>(
+        r: &mut R,
+        page_size: usize,
+        provenance: bool,
+        weights: bool,
+    ) -> OrdChunkResult<Self> {
         let mut buffer = [0u8; 8];
         let vaddr = read_u64(r, &mut buffer)?;
-        if !is_page_aligned(vaddr) {
+        if !is_page_aligned(vaddr, page_size) {
             return Err(OrdChunkError::BadAlignment(vaddr));
         }
 
+        let n_pages = read_u64(r, &mut buffer)?;
         let kind = match vaddr & !ORD_FLAG_MASK {
             ORD_ZERO_FLAG => DataSource::Zero,
             ORD_PRIVATE_FLAG => DataSource::Private,
             ORD_SHARED_FLAG => DataSource::Shared,
-            0 => {
-                assert!(vaddr == 0);
-                DataSource::Zero
-            }
-            _ => panic!("bad flag"),
+            // an empty chunk (which is filtered out)
+            0 if vaddr == 0 && n_pages == 0 => DataSource::Zero,
+            _ => return Err(OrdChunkError::BadKind(vaddr)),
+        };
+
+        if n_pages
+            .checked_mul(page_size as u64)
+            .and_then(|size| (vaddr & ORD_FLAG_MASK).checked_add(size))
+            .is_none()
+        {
+            return Err(OrdChunkError::Overflow(vaddr & ORD_FLAG_MASK, n_pages));
+        }
+        let (tid, access, weight) = if provenance {
+            parse_provenance(read_u64(r, &mut buffer)?, weights)?
+        } else {
+            (None, None, None)
         };
 
-        let n_pages = read_u64(r, &mut buffer)?;
         Ok(OrdChunk {
             vaddr: vaddr & ORD_FLAG_MASK,
             n_pages,
             kind,
+            tid,
+            access,
+            weight,
         })
     }
 }
+
+/// Parse a provenance word: the thread id is in the lower 32 bits, the flags above it say what
+/// was recorded, and the weight (with `weights`) is in the upper bits
+fn parse_provenance(
+    word: u64,
+    weights: bool,
+) -> OrdChunkResult<(Option<u32>, Option<AccessKind>, Option<u32>)> {
+    let known_flags = match weights {
+        true => ORD_TID_FLAG | ORD_READ_FLAG | ORD_WRITE_FLAG | ORD_WEIGHT_FLAG,
+        false => ORD_TID_FLAG | ORD_READ_FLAG | ORD_WRITE_FLAG,
+    };
+    let (flags, weight_bits) = (
+        word & !(u32::MAX as u64) & !(u64::MAX << ORD_WEIGHT_SHIFT),
+        word >> ORD_WEIGHT_SHIFT,
+    );
+    if flags & !known_flags != 0
+        || (word & ORD_TID_FLAG == 0 && word as u32 != 0)
+        || (word & ORD_WEIGHT_FLAG == 0 && weight_bits != 0)
+    {
+        return Err(OrdChunkError::BadProvenance(word));
+    }
+
+    let tid = (word & ORD_TID_FLAG != 0).then_some(word as u32);
+    let access = match (word & ORD_READ_FLAG != 0, word & ORD_WRITE_FLAG != 0) {
+        (false, false) => None,
+        (true, false) => Some(AccessKind::Read),
+        (false, true) => Some(AccessKind::Write),
+        (true, true) => return Err(OrdChunkError::BadProvenance(word)),
+    };
+
+    let weight = (word & ORD_WEIGHT_FLAG != 0).then_some(weight_bits as u32);
+
+    Ok((tid, access, weight))
+}
@@ -0,0 +1,44 @@
+use crate::error::*;
+use crate::jif::JifRaw;
+use crate::page_index::{PageHashIndex, ENTRY_SIZE, PAGE_INDEX_MAGIC};
+
+use super::meta::read_trailing_section;
+
+use std::io::{Read, Seek};
+
+impl JifRaw {
+    /// Read the page hash index ending at `end`, if there is one (at or after `start`; see
+    /// [`crate::page_index`])
+    ///
+    /// The stream position is not preserved
+    pub(crate) fn read_page_index_in<R: Read + Seek>(
+        r: &mut R,
+        range: (u64, u64),
+    ) -> JifResult<Option<PageHashIndex>> {
+        let Some((range, entries)) = read_trailing_section(r, range, PAGE_INDEX_MAGIC)? else {
+            return Ok(None);
+        };
+
+        let bad_index =
+            |reason| JifError::BadPageIndex { reason }.in_section(JifSection::PageIndex, range);
+        if entries.len() % ENTRY_SIZE != 0 {
+            return Err(bad_index("truncated entry"));
+        }
+
+        let entries = entries
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let (digest, offset) = entry.split_at(ENTRY_SIZE - std::mem::size_of::<u64>());
+                (
+                    digest.try_into().expect("the digest is 32 bytes long"),
+                    u64::from_le_bytes(offset.try_into().expect("the offset is 8 bytes long")),
+                )
+            })
+            .collect::<Vec<_>>();
+        if !entries.is_sorted() {
+            return Err(bad_index("entries out of order"));
+        }
+
+        Ok(Some(PageHashIndex { entries }))
+    }
+}
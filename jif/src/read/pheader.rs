@@ -7,6 +7,7 @@ use std::io::Read;
 fn read_page_aligned_u64<R: Read>(
     r: &mut R,
     buffer: &mut [u8; 8],
+    page_size: usize,
     special_value: bool,
 ) -> PheaderResult<u64> {
     let v = read_u64(r, buffer)?;
@@ -16,7 +17,7 @@ fn read_page_aligned_u64<R: Read>(
         return Ok(v);
     }
 
-    if !is_page_aligned(v) {
+    if !is_page_aligned(v, page_size) {
         Err(PheaderError::BadAlignment(v))
     } else {
         Ok(v)
@@ -26,10 +27,11 @@ fn read_page_aligned_u64<R: Read>(
 fn read_page_aligned_u64_pair<R: Read, F: FnOnce(u64, u64) -> PheaderError>(
     r: &mut R,
     buffer: &mut [u8; 8],
+    page_size: usize,
     pheader_error_builder: F,
 ) -> PheaderResult<(u64, u64)> {
-    let begin = read_page_aligned_u64(r, buffer, false /* special value */)?;
-    let end = read_page_aligned_u64(r, buffer, false /* special value */)?;
+    let begin = read_page_aligned_u64(r, buffer, page_size, false /* special value */)?;
+    let end = read_page_aligned_u64(r, buffer, page_size, false /* special value */)?;
 
     if begin > end {
         Err(pheader_error_builder(begin, end))
@@ -38,20 +40,30 @@ fn read_page_aligned_u64_pair<R: Read, F: FnOnce(u64, u64) -> PheaderError>(
     }
 }
 
-fn read_virtual_range<R: Read>(r: &mut R, buffer: &mut [u8; 8]) -> PheaderResult<(u64, u64)> {
-    read_page_aligned_u64_pair(r, buffer, PheaderError::BadVirtualRange)
+fn read_virtual_range<R: Read>(
+    r: &mut R,
+    buffer: &mut [u8; 8],
+    page_size: usize,
+) -> PheaderResult<(u64, u64)> {
+    read_page_aligned_u64_pair(r, buffer, page_size, PheaderError::BadVirtualRange)
 }
 
-fn read_ref_offset<R: Read>(r: &mut R, buffer: &mut [u8; 8]) -> PheaderResult<u64> {
-    read_page_aligned_u64(r, buffer, true /* special value */)
+fn read_ref_offset<R: Read>(
+    r: &mut R,
+    buffer: &mut [u8; 8],
+    page_size: usize,
+) -> PheaderResult<u64> {
+    read_page_aligned_u64(r, buffer, page_size, true /* special value */)
 }
 
 impl JifRawPheader {
     /// Read and parse a pheader
-    pub fn from_reader<R: Read>(r: &mut R) -> PheaderResult<Self> {
+    ///
+    /// The addresses and offsets have to be aligned to the `page_size` of the JIF
+    pub fn from_reader<R: Read>(r: &mut R, page_size: usize) -> PheaderResult<Self> {
         let mut buffer_8 = [0u8; 8];
-        let (vbegin, vend) = read_virtual_range(r, &mut buffer_8)?;
-        let ref_offset = read_ref_offset(r, &mut buffer_8)?;
+        let (vbegin, vend) = read_virtual_range(r, &mut buffer_8, page_size)?;
+        let ref_offset = read_ref_offset(r, &mut buffer_8, page_size)?;
 
         let mut buffer_4 = [0u8; 4];
         let itree_idx = read_u32(r, &mut buffer_4)?;
@@ -0,0 +1,204 @@
+//! Moving virtual address ranges
+//!
+//! The layout of the address space depends on where the snapshot was captured (e.g., with ASLR),
+//! so restoring it elsewhere may require moving the pheaders. Moving a pheader shifts its virtual
+//! range, its intervals and the ordering chunks prefetching it: the data and the offsets into
+//! the referenced files are unchanged
+
+use crate::error::*;
+use crate::jif::Jif;
+use crate::utils::is_aligned;
+
+impl Jif {
+    /// Move the pheaders inside the virtual address range `[start; end)` by `delta` bytes (along
+    /// with their intervals and ordering chunks), returning the number of moved pheaders
+    ///
+    /// The delta has to be page aligned, the pheaders overlapping the range have to be fully
+    /// inside it, and the moved pheaders cannot collide with the ones left in place. On error,
+    /// the JIF is left untouched
+    pub fn move_pheaders(&mut self, (start, end): (u64, u64), delta: i64) -> JifResult<usize> {
+        let in_range = |range: (u64, u64)| start <= range.0 && range.1 <= end;
+        for range in self.pheaders.iter().map(|pheader| pheader.virtual_range()) {
+            let overlaps = range.0 < end && start < range.1;
+            let shifted = range
+                .0
+                .checked_add_signed(delta)
+                .zip(range.1.checked_add_signed(delta));
+            if overlaps
+                && (!in_range(range)
+                    || shifted.is_none()
+                    || !is_aligned(delta.unsigned_abs(), self.arch.page_size))
+            {
+                return Err(JifError::BadMove {
+                    virtual_range: range,
+                    delta,
+                });
+            }
+        }
+
+        let mut ranges = self
+            .pheaders
+            .iter()
+            .map(|pheader| {
+                let range = pheader.virtual_range();
+                if in_range(range) {
+                    (
+                        range.0.wrapping_add_signed(delta),
+                        range.1.wrapping_add_signed(delta),
+                    )
+                } else {
+                    range
+                }
+            })
+            .collect::<Vec<_>>();
+        ranges.sort();
+        if let Some((pheader_1, pheader_2)) = ranges
+            .iter()
+            .zip(ranges.iter().skip(1))
+            .find(|(a, b)| a.1 > b.0)
+        {
+            return Err(JifError::OverlappingPheaders {
+                pheader_1: *pheader_1,
+                pheader_2: *pheader_2,
+            });
+        }
+
+        // the chunks are matched against the pheaders before they move
+        let moved_chunks = self
+            .ord_chunks
+            .iter()
+            .map(|chunk| {
+                self.mapping_pheader(chunk.vaddr)
+                    .is_some_and(|pheader| in_range(pheader.virtual_range()))
+            })
+            .collect::<Vec<_>>();
+        for (chunk, moved) in self.ord_chunks.iter_mut().zip(moved_chunks) {
+            if moved {
+                chunk.vaddr = chunk.vaddr.wrapping_add_signed(delta);
+            }
+        }
+
+        let mut n_moved = 0;
+        for pheader in self.pheaders.iter_mut() {
+            if in_range(pheader.virtual_range()) {
+                pheader.shift(delta);
+                n_moved += 1;
+            }
+        }
+        self.pheaders
+            .sort_by_key(|pheader| pheader.virtual_range().0);
+
+        Ok(n_moved)
+    }
+
+    /// Move every pheader by `delta` bytes (e.g., to restore on a different address space layout)
+    pub fn rebase(&mut self, delta: i64) -> JifResult<()> {
+        self.move_pheaders((0, u64::MAX), delta).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::deduper::Deduper;
+    use crate::itree::interval::{AnonIntervalData, DataSource, Interval};
+    use crate::itree::ITree;
+    use crate::jif::JifRaw;
+    use crate::ord::OrdChunk;
+    use crate::pheader::{JifPheader, ProtFlags};
+    use crate::utils::PAGE_SIZE;
+
+    use std::collections::BTreeMap;
+    use std::io::BufReader;
+
+    fn gen_anon(vaddr_range: (u64, u64), ival: (u64, u64, u8)) -> JifPheader {
+        JifPheader::Anonymous {
+            vaddr_range,
+            itree: ITree::build(
+                vec![Interval::new(
+                    ival.0,
+                    ival.1,
+                    AnonIntervalData::Owned(vec![ival.2; (ival.1 - ival.0) as usize]),
+                )],
+                vaddr_range,
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+        }
+    }
+
+    fn gen_jif() -> Jif {
+        Jif {
+            pheaders: vec![
+                gen_anon((0x1000, 0x4000), (0x2000, 0x3000, 1)),
+                gen_anon((0x10000, 0x12000), (0x10000, 0x11000, 2)),
+            ],
+            ord_chunks: vec![
+                OrdChunk::new(0x2000, 1, DataSource::Private),
+                OrdChunk::new(0x10000, 1, DataSource::Private),
+            ],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn move_pheaders() {
+        let mut jif = gen_jif();
+        assert_eq!(jif.move_pheaders((0x10000, 0x20000), -0x8000).unwrap(), 1);
+        assert_eq!(jif.pheaders()[1].virtual_range(), (0x8000, 0xa000));
+        assert_eq!(jif.ord_chunks()[0].addr(), 0x2000);
+        assert_eq!(jif.ord_chunks()[1].addr(), 0x8000);
+        assert_eq!(jif.resolve_data(0x8000), Some(&[2u8; PAGE_SIZE][..]));
+        assert_eq!(jif.resolve_data(0x10000), None);
+
+        // the moved JIF is consistent once written out
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(jif, false)
+            .to_writer(&mut buffer)
+            .unwrap();
+        let read = Jif::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert_eq!(read.resolve_data(0x2000), Some(&[1u8; PAGE_SIZE][..]));
+        assert_eq!(read.resolve_data(0x8000), Some(&[2u8; PAGE_SIZE][..]));
+    }
+
+    #[test]
+    fn rebase() {
+        let mut jif = gen_jif();
+        jif.rebase(0x7f00_0000_0000).unwrap();
+        assert_eq!(
+            jif.pheaders()[0].virtual_range(),
+            (0x7f00_0000_1000, 0x7f00_0000_4000)
+        );
+        assert_eq!(
+            jif.resolve_data(0x7f00_0001_0000),
+            Some(&[2u8; PAGE_SIZE][..])
+        );
+        assert_eq!(jif.ord_chunks()[1].addr(), 0x7f00_0001_0000);
+    }
+
+    #[test]
+    fn bad_moves() {
+        let mut jif = gen_jif();
+
+        // collides with the first pheader
+        assert!(matches!(
+            jif.move_pheaders((0x10000, 0x20000), -0xe000),
+            Err(JifError::OverlappingPheaders { .. })
+        ));
+        // partially covered pheader
+        assert!(matches!(
+            jif.move_pheaders((0x11000, 0x20000), 0x1000),
+            Err(JifError::BadMove { .. })
+        ));
+        // unaligned delta, out of the address space
+        assert!(matches!(jif.rebase(0x800), Err(JifError::BadMove { .. })));
+        assert!(matches!(jif.rebase(-0x2000), Err(JifError::BadMove { .. })));
+
+        // untouched
+        assert_eq!(jif.pheaders()[0].virtual_range(), (0x1000, 0x4000));
+        assert_eq!(jif.pheaders()[1].virtual_range(), (0x10000, 0x12000));
+    }
+}
@@ -0,0 +1,331 @@
+//! Restore plan
+//!
+//! The operations a loader performs to restore a JIF, in order (see [`Jif::restore_plan`]):
+//!  1. every pheader is mapped, in address order: anonymous pheaders to anonymous memory, and
+//!     reference pheaders to a private (copy-on-write) mapping of the referenced file, starting
+//!     at the reference offset. The mappings which get data written into them are mapped
+//!     writable
+//!  2. the ordering section is prefetched, chunk by chunk: the private pages are copied in, and
+//!     the shared pages are advised to be needed (`MADV_WILLNEED`), so that the file is read
+//!     ahead. Zero pages need nothing
+//!  3. the private data which was not prefetched is copied in, and the zero intervals of the
+//!     reference pheaders (which would otherwise show the file) are zero filled
+//!  4. the mappings which were made writable get their protections
+//!
+//! The anonymous memory reads as zero, and the shared intervals read the file: neither needs
+//! more than the mapping
+
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+use crate::pheader::{JifPheader, ProtFlags};
+
+use std::collections::BTreeMap;
+
+/// An operation of a restore plan (see [`Jif::restore_plan`]), over a virtual address range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreOp {
+    /// Map anonymous memory (`mmap` with `MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED`)
+    MapAnonymous { range: (u64, u64), prot: ProtFlags },
+
+    /// Map a file, starting at an offset (`mmap` with `MAP_PRIVATE | MAP_FIXED`)
+    MapFile {
+        range: (u64, u64),
+        prot: ProtFlags,
+        path: String,
+        offset: u64,
+    },
+
+    /// Copy the private data of the range (from the data section) into memory
+    Copy { range: (u64, u64) },
+
+    /// Fill the range with zeros
+    Zero { range: (u64, u64) },
+
+    /// Advise that the range is needed soon (`madvise` with `MADV_WILLNEED`)
+    WillNeed { range: (u64, u64) },
+
+    /// Set the protections of the range (`mprotect`)
+    Protect { range: (u64, u64), prot: ProtFlags },
+}
+
+impl RestoreOp {
+    /// Virtual address range of the operation
+    pub fn range(&self) -> (u64, u64) {
+        match self {
+            RestoreOp::MapAnonymous { range, .. }
+            | RestoreOp::MapFile { range, .. }
+            | RestoreOp::Copy { range }
+            | RestoreOp::Zero { range }
+            | RestoreOp::WillNeed { range }
+            | RestoreOp::Protect { range, .. } => *range,
+        }
+    }
+}
+
+impl std::fmt::Display for RestoreOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (start, end) = self.range();
+        let name = match self {
+            RestoreOp::MapAnonymous { .. } | RestoreOp::MapFile { .. } => "mmap",
+            RestoreOp::Copy { .. } => "memcpy",
+            RestoreOp::Zero { .. } => "memset",
+            RestoreOp::WillNeed { .. } => "madvise",
+            RestoreOp::Protect { .. } => "mprotect",
+        };
+        f.write_fmt(format_args!("{:<8} [{:#x}; {:#x})", name, start, end))?;
+
+        match self {
+            RestoreOp::MapAnonymous { prot, .. } => {
+                f.write_fmt(format_args!(" {} anonymous", prot))
+            }
+            RestoreOp::MapFile {
+                prot, path, offset, ..
+            } => f.write_fmt(format_args!(" {} {} @ {:#x}", prot, path, offset)),
+            RestoreOp::Copy { .. } => f.write_str(" private data"),
+            RestoreOp::Zero { .. } => f.write_str(" 0"),
+            RestoreOp::WillNeed { .. } => f.write_str(" WILLNEED"),
+            RestoreOp::Protect { prot, .. } => f.write_fmt(format_args!(" {}", prot)),
+        }
+    }
+}
+
+/// Add an operation to the plan, extending the last one if it is of the same kind and adjacent
+fn push_op(ops: &mut Vec<RestoreOp>, op: RestoreOp) {
+    match (ops.last_mut(), &op) {
+        (Some(RestoreOp::Copy { range: last }), RestoreOp::Copy { range })
+        | (Some(RestoreOp::Zero { range: last }), RestoreOp::Zero { range })
+        | (Some(RestoreOp::WillNeed { range: last }), RestoreOp::WillNeed { range })
+            if last.1 == range.0 =>
+        {
+            last.1 = range.1
+        }
+        _ => ops.push(op),
+    }
+}
+
+/// The parts of `[start; end)` which are not in the (disjoint) prefetched ranges, in order
+fn not_prefetched(prefetched: &BTreeMap<u64, u64>, (start, end): (u64, u64)) -> Vec<(u64, u64)> {
+    let mut parts = Vec::new();
+    if start >= end {
+        return parts;
+    }
+    let mut cursor = start;
+    let overlapping = prefetched
+        .range(..=start)
+        .next_back()
+        .into_iter()
+        .chain(prefetched.range(start + 1..end));
+    for (prefetched_start, prefetched_end) in overlapping {
+        if *prefetched_end <= cursor {
+            continue;
+        }
+        if *prefetched_start > cursor {
+            parts.push((cursor, *prefetched_start));
+        }
+        cursor = std::cmp::min(end, *prefetched_end);
+    }
+    if cursor < end {
+        parts.push((cursor, end));
+    }
+
+    parts
+}
+
+/// Whether restoring the pheader writes into its mapping: copying private data in, or zero
+/// filling over the referenced file
+fn is_written(pheader: &JifPheader) -> bool {
+    pheader.itree().iter_logical_intervals().any(|ival| {
+        ival.source == DataSource::Private
+            || (ival.source == DataSource::Zero && pheader.pathname().is_some())
+    })
+}
+
+impl Jif {
+    /// The operations a loader performs to restore the JIF, in order (see [`crate::restore`])
+    ///
+    /// Adjacent operations of the same kind are merged, so a range is copied, zero filled or
+    /// advised at most once
+    pub fn restore_plan(&self) -> Vec<RestoreOp> {
+        let page_size = self.arch.page_size;
+        let mut ops = Vec::new();
+
+        // map every pheader (writable, if it is written into)
+        let written = self.pheaders.iter().map(is_written).collect::<Vec<_>>();
+        for (pheader, written) in self.pheaders.iter().zip(&written) {
+            let range = pheader.virtual_range();
+            let prot = match written {
+                true => pheader.prot() | ProtFlags::WRITE,
+                false => pheader.prot(),
+            };
+            ops.push(match pheader {
+                JifPheader::Anonymous { .. } => RestoreOp::MapAnonymous { range, prot },
+                JifPheader::Reference {
+                    ref_path,
+                    ref_offset,
+                    ..
+                } => RestoreOp::MapFile {
+                    range,
+                    prot,
+                    path: ref_path.clone(),
+                    offset: *ref_offset,
+                },
+            });
+        }
+
+        // prefetch the ordering section, walking the logical intervals each chunk spans (the
+        // parts which no pheader maps are skipped)
+        let mut prefetched = BTreeMap::new();
+        for chunk in &self.ord_chunks {
+            let size = chunk.size().saturating_mul(page_size as u64);
+            let (mut cursor, end) = (chunk.addr(), chunk.addr().saturating_add(size));
+            while cursor < end {
+                let Some(ival) = self.resolve(cursor) else {
+                    let next_idx = self
+                        .pheaders
+                        .partition_point(|pheader| pheader.virtual_range().0 <= cursor);
+                    match self.pheaders.get(next_idx) {
+                        Some(pheader) => cursor = pheader.virtual_range().0,
+                        None => break,
+                    }
+                    continue;
+                };
+                let range = (cursor, std::cmp::min(ival.end, end));
+                cursor = range.1;
+                for range in not_prefetched(&prefetched, range) {
+                    match ival.source {
+                        DataSource::Private => push_op(&mut ops, RestoreOp::Copy { range }),
+                        DataSource::Shared => push_op(&mut ops, RestoreOp::WillNeed { range }),
+                        DataSource::Zero => continue,
+                    }
+                    prefetched.insert(range.0, range.1);
+                }
+            }
+        }
+
+        // copy the rest of the private data, and zero fill over the referenced files
+        for pheader in &self.pheaders {
+            for ival in pheader.itree().iter_logical_intervals() {
+                match ival.source {
+                    DataSource::Private => {
+                        for range in not_prefetched(&prefetched, (ival.start, ival.end)) {
+                            push_op(&mut ops, RestoreOp::Copy { range })
+                        }
+                    }
+                    DataSource::Zero if pheader.pathname().is_some() => {
+                        let range = (ival.start, ival.end);
+                        push_op(&mut ops, RestoreOp::Zero { range })
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // drop the write permission the copies needed
+        for (pheader, written) in self.pheaders.iter().zip(written) {
+            if written && !pheader.prot().contains(ProtFlags::WRITE) {
+                ops.push(RestoreOp::Protect {
+                    range: pheader.virtual_range(),
+                    prot: pheader.prot(),
+                });
+            }
+        }
+
+        ops
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ord::OrdChunk;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn restore_plan() {
+        let page = PAGE_SIZE as u64;
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment(
+                (0x100000, 0x100000 + 4 * page),
+                ProtFlags::READ | ProtFlags::WRITE,
+                vec![(0x100000, vec![1; 3 * PAGE_SIZE])],
+            )
+            .add_reference_segment(
+                (0x200000, 0x200000 + 4 * page),
+                ProtFlags::READ | ProtFlags::EXEC,
+                "/lib/libc.so".to_string(),
+                0x1000,
+                vec![(0x200000 + 2 * page, vec![2; PAGE_SIZE])],
+            )
+            .set_ordering(vec![
+                OrdChunk::new(0x100000 + page, 1, DataSource::Private),
+                OrdChunk::new(0x200000 + 3 * page, 1, DataSource::Shared),
+                OrdChunk::new(0x100000 + page, 1, DataSource::Private),
+            ]);
+        let jif = builder.build().unwrap();
+
+        let rw = ProtFlags::READ | ProtFlags::WRITE;
+        let rx = ProtFlags::READ | ProtFlags::EXEC;
+        assert_eq!(
+            jif.restore_plan(),
+            vec![
+                RestoreOp::MapAnonymous {
+                    range: (0x100000, 0x104000),
+                    prot: rw,
+                },
+                RestoreOp::MapFile {
+                    range: (0x200000, 0x204000),
+                    prot: rx | ProtFlags::WRITE,
+                    path: "/lib/libc.so".to_string(),
+                    offset: 0x1000,
+                },
+                // prefetched (once)
+                RestoreOp::Copy {
+                    range: (0x101000, 0x102000),
+                },
+                RestoreOp::WillNeed {
+                    range: (0x203000, 0x204000),
+                },
+                // the rest of the private data
+                RestoreOp::Copy {
+                    range: (0x100000, 0x101000),
+                },
+                RestoreOp::Copy {
+                    range: (0x102000, 0x103000),
+                },
+                RestoreOp::Copy {
+                    range: (0x202000, 0x203000),
+                },
+                RestoreOp::Protect {
+                    range: (0x200000, 0x204000),
+                    prot: rx,
+                },
+            ]
+        );
+        assert_eq!(
+            jif.restore_plan()[1].to_string(),
+            "mmap     [0x200000; 0x204000) rwx /lib/libc.so @ 0x1000"
+        );
+    }
+
+    #[test]
+    fn restore_plan_huge_chunk() {
+        let page = PAGE_SIZE as u64;
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x100000, 0x100000 + 4 * page),
+            ProtFlags::READ | ProtFlags::WRITE,
+            vec![(0x100000 + page, vec![1; 2 * PAGE_SIZE])],
+        );
+        let mut jif = builder.build().unwrap();
+
+        // a (corrupt) chunk spanning the address space only prefetches what is mapped
+        jif.ord_chunks = vec![OrdChunk::new(0, 1 << 40, DataSource::Private)];
+        assert_eq!(
+            jif.restore_plan()[1..],
+            [RestoreOp::Copy {
+                range: (0x101000, 0x103000),
+            }]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Searching the memory contents of a JIF
+//!
+//! The private data is searched by default; the shared pages can be searched as well, by reading
+//! them from the referenced files. The adjacent intervals of a pheader are searched as one, so
+//! matches may cross interval (but not pheader) boundaries
+
+use crate::error::*;
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+use crate::paths::{AsRecorded, PathResolver};
+use crate::pheader::JifPheader;
+
+/// Where a match was found (see [`Jif::scan`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchContext {
+    /// Virtual address range of the pheader holding the match
+    pub pheader_range: (u64, u64),
+
+    /// Pathname of the pheader, if it is a reference pheader
+    pub pathname: Option<String>,
+
+    /// Data source of the page where the match starts
+    pub source: DataSource,
+}
+
+impl Jif {
+    /// Search the private data for `pattern`, returning the address of every match (overlapping
+    /// ones included), in address order
+    pub fn scan(&self, pattern: &[u8]) -> Vec<(u64, MatchContext)> {
+        self.scan_sources(pattern, false, &AsRecorded)
+            .expect("private data is not read from the referenced files")
+    }
+
+    /// Search the private data and the shared pages for `pattern` (see [`Jif::scan`])
+    ///
+    /// Shared pages are read from the referenced files (as resolved by `paths`)
+    pub fn scan_with_shared(
+        &self,
+        pattern: &[u8],
+        paths: &dyn PathResolver,
+    ) -> JifResult<Vec<(u64, MatchContext)>> {
+        self.scan_sources(pattern, true, paths)
+    }
+
+    fn scan_sources(
+        &self,
+        pattern: &[u8],
+        shared: bool,
+        paths: &dyn PathResolver,
+    ) -> JifResult<Vec<(u64, MatchContext)>> {
+        let mut matches = Vec::new();
+        if pattern.is_empty() {
+            return Ok(matches);
+        }
+
+        let mut contents = Vec::new();
+        for pheader in &self.pheaders {
+            for run in scanned_runs(pheader, shared) {
+                contents.clear();
+                pheader.read_range_into(
+                    run,
+                    &self.deduper,
+                    self.arch.page_size,
+                    paths,
+                    &mut contents,
+                )?;
+
+                for (idx, _window) in contents
+                    .windows(pattern.len())
+                    .enumerate()
+                    .filter(|(_idx, window)| *window == pattern)
+                {
+                    let addr = run.0 + idx as u64;
+                    matches.push((
+                        addr,
+                        MatchContext {
+                            pheader_range: pheader.virtual_range(),
+                            pathname: pheader.pathname().map(str::to_string),
+                            source: pheader.resolve(addr).source,
+                        },
+                    ));
+                }
+            }
+        }
+
+        Ok(matches)
+    }
+}
+
+/// The runs of adjacent intervals of a pheader which are searched
+fn scanned_runs(pheader: &JifPheader, shared: bool) -> Vec<(u64, u64)> {
+    let (start, end) = pheader.virtual_range();
+    let mut runs: Vec<(u64, u64)> = Vec::new();
+    for ival in pheader.itree().iter_logical_intervals().filter(|ival| {
+        ival.source == DataSource::Private || (shared && ival.source == DataSource::Shared)
+    }) {
+        let (ival_start, ival_end) = (
+            std::cmp::max(ival.start, start),
+            std::cmp::min(ival.end, end),
+        );
+        match runs.last_mut() {
+            Some((_run_start, run_end)) if *run_end == ival_start => *run_end = ival_end,
+            _ => runs.push((ival_start, ival_end)),
+        }
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::itree::interval::{AnonIntervalData, Interval};
+    use crate::itree::ITree;
+    use crate::jif::test::gen_jif;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn scan() {
+        let mut jif = gen_jif(&[((0x10000, 0x14000), &[(0x11000, 0x12000)])]);
+
+        // a secret crossing the boundary of two adjacent intervals
+        let mut first = vec![0; PAGE_SIZE];
+        first[PAGE_SIZE - 3..].copy_from_slice(b"sec");
+        let mut second = vec![0; PAGE_SIZE];
+        second[..3].copy_from_slice(b"ret");
+        second[0x100..0x106].copy_from_slice(b"secret");
+        jif.pheaders.insert(
+            0,
+            JifPheader::Anonymous {
+                vaddr_range: (0x1000, 0x4000),
+                itree: ITree::build(
+                    vec![
+                        Interval::new(0x1000, 0x2000, AnonIntervalData::Owned(first)),
+                        Interval::new(0x2000, 0x3000, AnonIntervalData::Owned(second)),
+                    ],
+                    (0x1000, 0x4000),
+                )
+                .unwrap(),
+                prot: ProtFlags::READ,
+            },
+        );
+
+        let context = MatchContext {
+            pheader_range: (0x1000, 0x4000),
+            pathname: None,
+            source: DataSource::Private,
+        };
+        assert_eq!(
+            jif.scan(b"secret"),
+            vec![(0x1ffd, context.clone()), (0x2100, context)]
+        );
+        assert_eq!(jif.scan(&[42; 3]).len(), PAGE_SIZE - 2);
+        assert!(jif.scan(b"missing").is_empty());
+        assert!(jif.scan(b"").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn scan_with_shared() {
+        let dir = std::env::temp_dir().join(format!("jif-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut contents = vec![0u8; 2 * PAGE_SIZE];
+        contents[PAGE_SIZE + 0x10..PAGE_SIZE + 0x16].copy_from_slice(b"needle");
+        std::fs::write(dir.join("lib.so"), &contents).unwrap();
+
+        let mut jif = gen_jif(&[]);
+        jif.pheaders.push(JifPheader::Reference {
+            vaddr_range: (0x10000, 0x12000),
+            itree: ITree::build(
+                vec![Interval::new(
+                    0x10000,
+                    0x11000,
+                    crate::itree::interval::RefIntervalData::Zero,
+                )],
+                (0x10000, 0x12000),
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+            ref_path: "/lib.so".to_string(),
+            ref_offset: 0,
+        });
+
+        assert!(jif.scan(b"needle").is_empty());
+        assert_eq!(
+            jif.scan_with_shared(b"needle", &Some(dir.clone())).unwrap(),
+            vec![(
+                0x11010,
+                MatchContext {
+                    pheader_range: (0x10000, 0x12000),
+                    pathname: Some("/lib.so".to_string()),
+                    source: DataSource::Shared,
+                }
+            )]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! Sparse files
+//!
+//! The private data can hold long runs of zeros which are not detected as zero pages (e.g., when
+//! they do not span a whole page of the address space, or when the interval trees were built
+//! without zero detection). Writing a JIF as a sparse file seeks over every page of the file
+//! which is only zeros, leaving a hole for the filesystem not to allocate (see
+//! [`JifRaw::to_writer_sparse`]). The file reads the same: only its physical size shrinks (see
+//! [`FileSize`])
+
+use crate::jif::JifRaw;
+use crate::utils::is_zero;
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+
+/// Logical and physical size of a file, in B
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileSize {
+    /// Size of the file (where its last byte is)
+    pub logical: u64,
+
+    /// Space allocated for the file by the filesystem
+    pub physical: u64,
+}
+
+impl FileSize {
+    /// Look up the sizes of an open file
+    ///
+    /// Where the allocated space is not known, the physical size is the logical one
+    pub fn from_file(file: &File) -> std::io::Result<Self> {
+        let metadata = file.metadata()?;
+
+        #[cfg(unix)]
+        let physical = std::os::unix::fs::MetadataExt::blocks(&metadata) * 512;
+        #[cfg(not(unix))]
+        let physical = metadata.len();
+
+        Ok(FileSize {
+            logical: metadata.len(),
+            physical,
+        })
+    }
+
+    /// Whether the file has holes (i.e., it takes up less space than its size)
+    pub fn is_sparse(&self) -> bool {
+        self.physical < self.logical
+    }
+}
+
+impl std::fmt::Display for FileSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{:#x} B ({:#x} B allocated{})",
+            self.logical,
+            self.physical,
+            if self.is_sparse() { ", sparse" } else { "" }
+        ))
+    }
+}
+
+impl JifRaw {
+    /// Write a JIF as a sparse file, seeking over the (page aligned) zero pages of the file
+    /// instead of writing them (see [`JifRaw::to_writer`])
+    ///
+    /// The writer is expected to start at the beginning of the file
+    pub fn to_writer_sparse<W: Write + Seek>(&self, w: &mut W) -> std::io::Result<usize> {
+        let mut sparse = SparseWriter::new(w, self.arch.page_size);
+        let written = self.to_writer(&mut sparse)?;
+        sparse.finish()?;
+        Ok(written)
+    }
+}
+
+/// Writer which seeks over its aligned blocks of zeros instead of writing them
+pub(crate) struct SparseWriter<'a, W: Write + Seek> {
+    inner: &'a mut W,
+    block_size: usize,
+
+    /// Number of bytes written so far (including the holes)
+    cursor: u64,
+
+    /// Size of the hole yet to be seeked over
+    hole: u64,
+}
+
+impl<'a, W: Write + Seek> SparseWriter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W, block_size: usize) -> Self {
+        SparseWriter {
+            inner,
+            block_size,
+            cursor: 0,
+            hole: 0,
+        }
+    }
+
+    /// Seek over the pending hole
+    fn skip_hole(&mut self) -> std::io::Result<()> {
+        if self.hole > 0 {
+            self.inner.seek(SeekFrom::Current(self.hole as i64))?;
+            self.hole = 0;
+        }
+        Ok(())
+    }
+
+    /// Finish the file: a trailing hole still needs its last byte written for the file to have
+    /// its full size
+    pub(crate) fn finish(mut self) -> std::io::Result<()> {
+        if self.hole > 0 {
+            self.hole -= 1;
+            self.skip_hole()?;
+            self.inner.write_all(&[0])?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Seek> Write for SparseWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // the data written since the last hole
+        let mut run_start = 0;
+        let mut offset = 0;
+        while offset < buf.len() {
+            let to_boundary = self.block_size - (self.cursor % self.block_size as u64) as usize;
+            let len = std::cmp::min(to_boundary, buf.len() - offset);
+            let block = &buf[offset..(offset + len)];
+            if len == self.block_size && is_zero(block) {
+                if run_start < offset {
+                    self.skip_hole()?;
+                    self.inner.write_all(&buf[run_start..offset])?;
+                }
+                self.hole += len as u64;
+                run_start = offset + len;
+            }
+
+            offset += len;
+            self.cursor += len as u64;
+        }
+
+        if run_start < buf.len() {
+            self.skip_hole()?;
+            self.inner.write_all(&buf[run_start..])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufReader, Cursor, Read};
+
+    /// Writer which records where it was written to (as the filesystem would allocate it)
+    #[derive(Default)]
+    struct Blocks {
+        file: Cursor<Vec<u8>>,
+        written: Vec<(u64, u64)>,
+    }
+
+    impl Write for Blocks {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let start = self.file.position();
+            let written = self.file.write(buf)?;
+            self.written.push((start, start + written as u64));
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Blocks {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    #[test]
+    fn sparse_writer() {
+        // a run of zeros in the middle of the private data, and at the end of the file
+        let mut data = vec![42; 0x10000];
+        data[0x3800..0x8000].fill(0);
+        data[0xf000..].fill(0);
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x10000, 0x20000),
+            crate::ProtFlags::READ,
+            vec![(0x10000, data)],
+        );
+        let jif = builder.build().unwrap();
+        let raw = JifRaw::from_materialized(jif, false);
+
+        let mut dense = Vec::new();
+        raw.to_writer(&mut dense).unwrap();
+        let mut sparse = Blocks::default();
+        let written = raw.to_writer_sparse(&mut sparse).unwrap();
+        assert_eq!(written, dense.len());
+
+        // the file reads the same
+        let mut file = Vec::new();
+        sparse.file.set_position(0);
+        sparse.file.read_to_end(&mut file).unwrap();
+        assert_eq!(file, dense);
+        assert_eq!(
+            JifRaw::from_reader(&mut BufReader::new(Cursor::new(&file)))
+                .unwrap()
+                .data_size(),
+            raw.data_size()
+        );
+
+        // only the whole zero pages were skipped
+        let data_offset = raw.data_offset;
+        let skipped = |(start, end): (u64, u64)| {
+            !sparse
+                .written
+                .iter()
+                .any(|(w_start, w_end)| *w_start < end && start < *w_end)
+        };
+        assert!(skipped((data_offset + 0x4000, data_offset + 0x8000)));
+        assert!(!skipped((data_offset + 0x3000, data_offset + 0x4000)));
+        assert!(skipped((data_offset + 0xf000, data_offset + 0xffff)));
+        assert_eq!(
+            sparse.written.last(),
+            Some(&(data_offset + 0xffff, data_offset + 0x10000))
+        );
+    }
+}
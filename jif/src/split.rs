@@ -0,0 +1,181 @@
+//! Split JIFs
+//!
+//! For content addressable storage, the metadata of a JIF (header, pheaders, strings, interval
+//! trees and ordering section) can be stored apart from the bulk of the data. A split JIF is a
+//! metadata file, which is the JIF up to its data offset (followed by its custom metadata
+//! section, if any: see [`crate::meta`]), and a data blob: a header
+//! ([`DATA_BLOB_MAGIC`] and the number of segments), an index of the data segments (their
+//! offset and length in the data section, as little endian `u64`s) and their contents, in index
+//! order. The page hash index of a JIF (see [`crate::page_index`]) is not kept
+
+use crate::error::*;
+use crate::jif::JifRaw;
+use crate::utils::{read_u32, read_u64};
+
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+/// Magic number of the data blobs of split JIFs
+pub(crate) const DATA_BLOB_MAGIC: [u8; 4] = [0x77, b'J', b'D', b'B'];
+
+impl JifRaw {
+    /// Split the JIF into its metadata (a JIF without the data section) and a data blob
+    ///
+    /// Returns the number of bytes written to each. The integrity section is not written:
+    /// it is rebuilt once the JIF is joined back (see [`JifRaw::join`])
+    pub fn split<M: Write, D: Write>(
+        &self,
+        metadata: &mut M,
+        data: &mut D,
+    ) -> std::io::Result<(usize, usize)> {
+        let metadata_size = self.write_metadata(metadata)? + self.write_meta_section(metadata)?;
+
+        let segments = JifRaw::data_segment_ranges(&self.itree_nodes, self.data_offset)
+            .into_iter()
+            .map(|(offset, len)| {
+                self.segment(offset, len)
+                    .map(|segment| (offset, segment))
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("missing data segment [{:#x}; +{:#x})", offset, len),
+                        )
+                    })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        data.write_all(&DATA_BLOB_MAGIC)?;
+        data.write_all(&(segments.len() as u32).to_le_bytes())?;
+        let mut data_size = DATA_BLOB_MAGIC.len() + std::mem::size_of::<u32>();
+        for (offset, segment) in &segments {
+            data.write_all(&offset.to_le_bytes())?;
+            data.write_all(&(segment.len() as u64).to_le_bytes())?;
+            data_size += 2 * std::mem::size_of::<u64>();
+        }
+        for (_offset, segment) in &segments {
+            data.write_all(segment)?;
+            data_size += segment.len();
+        }
+
+        Ok((metadata_size, data_size))
+    }
+
+    /// Join the metadata and the data blob of a split JIF (see [`JifRaw::split`])
+    ///
+    /// The blob has to hold every data segment referenced by the interval trees
+    pub fn join<M: Read + Seek, D: Read>(
+        metadata: &mut BufReader<M>,
+        data: &mut D,
+    ) -> JifResult<Self> {
+        let mut raw = JifRaw::from_reader_metadata(metadata)?;
+        let end = metadata.seek(SeekFrom::End(0))?;
+        raw.meta = JifRaw::read_meta_in(metadata, (raw.data_offset, end))?;
+
+        let mut magic = [0u8; 4];
+        data.read_exact(&mut magic)?;
+        if magic != DATA_BLOB_MAGIC {
+            return Err(JifError::BadDataBlob {
+                reason: "bad magic",
+            });
+        }
+
+        let mut buffer = [0u8; 8];
+        let n_segments = read_u32(data, &mut [0u8; 4])? as usize;
+        let index = (0..n_segments)
+            .map(|_| Ok((read_u64(data, &mut buffer)?, read_u64(data, &mut buffer)?)))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let mut data_segments = BTreeMap::new();
+        for (offset, len) in index {
+            let mut segment = Vec::new();
+            data.take(len).read_to_end(&mut segment)?;
+            if segment.len() as u64 != len {
+                return Err(JifError::BadDataBlob {
+                    reason: "truncated data segment",
+                });
+            }
+            data_segments.insert((offset, offset + len), segment);
+        }
+
+        if JifRaw::data_segment_ranges(&raw.itree_nodes, raw.data_offset)
+            .into_iter()
+            .any(|(offset, len)| !data_segments.contains_key(&(offset, offset + len)))
+        {
+            return Err(JifError::BadDataBlob {
+                reason: "missing data segment",
+            });
+        }
+
+        raw.data_segments = data_segments;
+        Ok(raw)
+    }
+
+    /// The data segment at `offset` (relative to the data section)
+    fn segment(&self, offset: u64, len: u64) -> Option<&[u8]> {
+        self.data_segments
+            .get(&(offset, offset + len))
+            .map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::test::gen_jif;
+    use crate::jif::Jif;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn split_join() {
+        let gen = || {
+            JifRaw::from_materialized(
+                gen_jif(&[
+                    ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                    ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+                ]),
+                false,
+            )
+        };
+
+        let (mut metadata, mut data) = (Vec::new(), Vec::new());
+        let (metadata_size, data_size) = gen().split(&mut metadata, &mut data).unwrap();
+        assert_eq!((metadata_size, data_size), (metadata.len(), data.len()));
+        assert_eq!(metadata_size as u64, gen().data_offset);
+
+        let joined = JifRaw::join(
+            &mut BufReader::new(Cursor::new(metadata.clone())),
+            &mut data.as_slice(),
+        )
+        .unwrap();
+
+        // splitting again (once read back) gives the same files
+        let (mut metadata_again, mut data_again) = (Vec::new(), Vec::new());
+        joined.split(&mut metadata_again, &mut data_again).unwrap();
+        assert_eq!((&metadata_again, &data_again), (&metadata, &data));
+
+        let (mut joined_bytes, mut orig_bytes) = (Vec::new(), Vec::new());
+        joined.to_writer(&mut joined_bytes).unwrap();
+        gen().to_writer(&mut orig_bytes).unwrap();
+        assert_eq!(joined_bytes, orig_bytes);
+
+        let jif = Jif::from_reader(&mut BufReader::new(Cursor::new(joined_bytes))).unwrap();
+        assert_eq!(jif.resolve_data(0x11000), Some(&[42u8; 0x1000][..]));
+
+        // the blob has to hold every data segment
+        let truncated = &data[..data.len() - 1];
+        assert!(matches!(
+            JifRaw::join(
+                &mut BufReader::new(Cursor::new(metadata.clone())),
+                &mut &truncated[..]
+            ),
+            Err(JifError::BadDataBlob { .. })
+        ));
+        let mut empty = DATA_BLOB_MAGIC.to_vec();
+        empty.extend_from_slice(&0u32.to_le_bytes());
+        assert!(matches!(
+            JifRaw::join(&mut BufReader::new(Cursor::new(metadata)), &mut &empty[..]),
+            Err(JifError::BadDataBlob { .. })
+        ));
+    }
+}
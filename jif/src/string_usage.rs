@@ -0,0 +1,186 @@
+//! Usage of the strings (referenced files)
+//!
+//! Tells which referenced files (e.g., shared objects) dominate a snapshot: how many pheaders
+//! reference each string, how much of the address space is mapped from it, and how many pages
+//! are actually read from the file (the shared pages, which are not overlaid by private data or
+//! zero pages)
+
+use crate::jif::{Jif, JifRaw};
+
+use std::collections::BTreeMap;
+
+/// Usage of a string of the JIF (see [`Jif::string_usage`])
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StringUsage {
+    /// Number of pheaders referencing the string
+    pub pheaders: usize,
+
+    /// Size of the virtual address ranges mapped from the file, in B
+    pub virtual_size: u64,
+
+    /// Number of shared pages (read from the file)
+    pub shared_pages: usize,
+}
+
+impl StringUsage {
+    /// Account for a pheader referencing the string
+    fn add(&mut self, virtual_size: u64, shared_pages: usize) {
+        self.pheaders += 1;
+        self.virtual_size += virtual_size;
+        self.shared_pages += shared_pages;
+    }
+}
+
+impl std::fmt::Display for StringUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} pheaders, {:#x} B mapped, {} shared pages",
+            self.pheaders, self.virtual_size, self.shared_pages
+        ))
+    }
+}
+
+impl Jif {
+    /// Usage of each referenced path: the pheaders referencing it, the address space mapped from it
+    /// and the shared pages read from it
+    pub fn string_usage(&self) -> BTreeMap<&str, StringUsage> {
+        let page_size = self.arch.page_size;
+        let mut usage: BTreeMap<&str, StringUsage> = BTreeMap::new();
+        for pheader in &self.pheaders {
+            if let Some(path) = pheader.pathname() {
+                let (start, end) = pheader.virtual_range();
+                usage
+                    .entry(path)
+                    .or_default()
+                    .add(end - start, pheader.shared_pages(page_size));
+            }
+        }
+
+        usage
+    }
+}
+
+impl JifRaw {
+    /// Usage of each string of the string table (see [`Jif::string_usage`])
+    ///
+    /// Unlike [`Jif::string_usage`], the strings no pheader references are included, with no
+    /// usage (they are dropped when the JIF is written out again: see
+    /// [`JifRaw::from_materialized`]). The empty strings (e.g., the padding of the string table,
+    /// or the table of a JIF with only anonymous pheaders) are not
+    pub fn string_usage(&self) -> BTreeMap<&str, StringUsage> {
+        let page_size = self.arch.page_size as u64;
+        let mut usage = self
+            .strings()
+            .into_iter()
+            .filter(|string| !string.is_empty())
+            .map(|string| (string, StringUsage::default()))
+            .collect::<BTreeMap<_, _>>();
+        for pheader in &self.pheaders {
+            let Some(path) = pheader
+                .pathname_offset()
+                .and_then(|offset| self.string_at_offset(offset as usize))
+            else {
+                continue;
+            };
+
+            // the pages which are not overlaid by an interval are read from the file
+            let (start, end) = pheader.virtual_range();
+            let overlaid = pheader
+                .itree()
+                .and_then(|(idx, n)| self.itree_nodes.get(idx as usize..(idx + n) as usize))
+                .unwrap_or_default()
+                .iter()
+                .flat_map(|node| node.ranges())
+                .map(|ival| ival.len())
+                .sum::<u64>();
+            usage.entry(path).or_default().add(
+                end - start,
+                ((end - start).saturating_sub(overlaid) / page_size) as usize,
+            );
+        }
+
+        usage
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    #[test]
+    fn string_usage() {
+        let page = PAGE_SIZE as u64;
+        let mut builder = crate::JifBuilder::new();
+        builder
+            .add_anonymous_segment((0x1000, 0x2000), ProtFlags::READ, vec![])
+            .add_reference_segment(
+                (0x10000, 0x10000 + 4 * page),
+                ProtFlags::READ | ProtFlags::EXEC,
+                "/lib/libc.so".to_string(),
+                0,
+                vec![(0x10000, vec![1; PAGE_SIZE])],
+            )
+            .add_reference_segment(
+                (0x20000, 0x20000 + 2 * page),
+                ProtFlags::READ,
+                "/lib/libc.so".to_string(),
+                0x4000,
+                vec![],
+            )
+            .add_reference_segment(
+                (0x30000, 0x30000 + page),
+                ProtFlags::READ,
+                "/bin/app".to_string(),
+                0,
+                vec![],
+            );
+        let jif = builder.build().unwrap();
+
+        let expected = BTreeMap::from([
+            (
+                "/bin/app",
+                StringUsage {
+                    pheaders: 1,
+                    virtual_size: page,
+                    shared_pages: 1,
+                },
+            ),
+            (
+                "/lib/libc.so",
+                StringUsage {
+                    pheaders: 2,
+                    virtual_size: 6 * page,
+                    shared_pages: 5,
+                },
+            ),
+        ]);
+        assert_eq!(jif.string_usage(), expected);
+        assert_eq!(
+            expected["/lib/libc.so"].to_string(),
+            "2 pheaders, 0x6000 B mapped, 5 shared pages"
+        );
+
+        // the raw JIF agrees
+        let raw = JifRaw::from_materialized(jif, false);
+        assert_eq!(raw.string_usage(), expected);
+    }
+
+    #[test]
+    fn anonymous_string_usage() {
+        let mut builder = crate::JifBuilder::new();
+        builder.add_anonymous_segment(
+            (0x1000, 0x3000),
+            ProtFlags::READ,
+            vec![(0x1000, vec![1; PAGE_SIZE])],
+        );
+        let jif = builder.build().unwrap();
+        assert!(jif.string_usage().is_empty());
+
+        // the empty string table holds no strings to report
+        let raw = JifRaw::from_materialized(jif, false);
+        assert_eq!(raw.strings(), vec![""]);
+        assert!(raw.string_usage().is_empty());
+    }
+}
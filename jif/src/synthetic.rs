@@ -0,0 +1,336 @@
+//! Synthetic JIFs
+//!
+//! Generating JIFs of a configurable size and shape, for benchmarks and tests. The JIFs are made
+//! of anonymous pheaders whose data is stored whole (i.e., before building the interval trees),
+//! mixing runs of zero pages with runs of pseudo-random ones, optionally with reference pheaders
+//! (overlaying private pages over synthetic files), and an ordering section over some of the
+//! non-zero pages. Generation is deterministic: the same parameters give the same JIF
+
+use crate::builder::JifBuilder;
+use crate::error::*;
+use crate::itree::interval::DataSource;
+use crate::jif::Jif;
+use crate::ord::{AccessKind, OrdChunk};
+use crate::pheader::ProtFlags;
+
+/// Parameters of a synthetic JIF (see [`SyntheticJif::generate`])
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticJif {
+    /// Number of pheaders
+    pub n_pheaders: usize,
+
+    /// Number of pages in each pheader
+    pub pages_per_pheader: usize,
+
+    /// Fraction of the pages which are zero pages (between 0 and 1): for the reference
+    /// pheaders, these are shared with the file instead
+    pub zero_ratio: f64,
+
+    /// Fraction of the pheaders which are reference pheaders (between 0 and 1), spread evenly
+    /// over the address space
+    ///
+    /// Their files (`/synthetic/<index>.so`) do not exist: their interval trees are built by the
+    /// generation, and building them anew fails
+    pub shared_ratio: f64,
+
+    /// Mean length of the runs of pages of the same kind, in pages (at least 1): the longer the
+    /// runs, the fewer the intervals
+    pub mean_run_pages: usize,
+
+    /// Store the data of the anonymous pheaders by interval, as if the interval trees were built
+    /// (see [`Jif::build_itrees`])
+    pub prebuilt_itrees: bool,
+
+    /// Fraction of the non-zero pages in the ordering section (between 0 and 1)
+    pub ord_ratio: f64,
+
+    /// Seed of the pseudo-random contents and ordering
+    pub seed: u64,
+
+    /// Page size
+    pub page_size: usize,
+}
+
+impl Default for SyntheticJif {
+    fn default() -> Self {
+        SyntheticJif {
+            n_pheaders: 16,
+            pages_per_pheader: 64,
+            zero_ratio: 0.25,
+            shared_ratio: 0.0,
+            mean_run_pages: 1,
+            prebuilt_itrees: false,
+            ord_ratio: 0.5,
+            seed: 0,
+            page_size: crate::utils::PAGE_SIZE,
+        }
+    }
+}
+
+/// Start of the address space of the synthetic JIFs
+const BASE_ADDR: u64 = 0x10_0000_0000;
+
+/// Split the data of a pheader into the runs of data pages (`pages` tells which pages hold data)
+fn data_runs(start: u64, data: &[u8], pages: &[bool], page_size: usize) -> Vec<(u64, Vec<u8>)> {
+    let mut runs: Vec<(u64, Vec<u8>)> = Vec::new();
+    let mut last = None;
+    for (page_idx, page) in data.chunks_exact(page_size).enumerate() {
+        if !pages[page_idx] {
+            continue;
+        }
+
+        match runs.last_mut() {
+            Some((_vaddr, contents)) if last == Some(page_idx - 1) => {
+                contents.extend_from_slice(page)
+            }
+            _ => runs.push((start + (page_idx * page_size) as u64, page.to_vec())),
+        }
+        last = Some(page_idx);
+    }
+
+    runs
+}
+
+impl SyntheticJif {
+    /// Parameters for a JIF with `n_pheaders` pheaders of `pages_per_pheader` pages (the other
+    /// parameters are the default ones)
+    pub fn new(n_pheaders: usize, pages_per_pheader: usize) -> Self {
+        SyntheticJif {
+            n_pheaders,
+            pages_per_pheader,
+            ..Default::default()
+        }
+    }
+
+    /// Total number of pages
+    pub fn n_pages(&self) -> usize {
+        self.n_pheaders * self.pages_per_pheader
+    }
+
+    /// Whether a pheader is a reference pheader
+    fn is_reference(&self, pheader_idx: usize) -> bool {
+        let n_references = |n_pheaders: usize| (n_pheaders as f64 * self.shared_ratio) as usize;
+        n_references(pheader_idx + 1) > n_references(pheader_idx)
+    }
+
+    /// Length of a run of pages, with a geometric distribution of mean [`Self::mean_run_pages`]
+    fn run_length(&self, rng: &mut XorShift) -> usize {
+        if self.mean_run_pages <= 1 {
+            return 1;
+        }
+
+        let p = 1.0 / self.mean_run_pages as f64;
+        ((1.0 - rng.next_f64()).ln() / (1.0 - p).ln())
+            .ceil()
+            .max(1.0) as usize
+    }
+
+    /// Generate the JIF
+    pub fn generate(&self) -> JifResult<Jif> {
+        let page_size = self.page_size as u64;
+        let mut rng = XorShift::new(self.seed);
+        let mut builder = JifBuilder::new();
+        builder.page_size(self.page_size);
+
+        let mut data_pages = Vec::new();
+        for pheader_idx in 0..self.n_pheaders as u64 {
+            // leave a one page gap between the pheaders
+            let start = BASE_ADDR + pheader_idx * (self.pages_per_pheader as u64 + 1) * page_size;
+            let end = start + self.pages_per_pheader as u64 * page_size;
+
+            let mut data = vec![0u8; self.pages_per_pheader * self.page_size];
+            let mut pages = vec![false; self.pages_per_pheader];
+            let mut run = (false, 0);
+            for (page_idx, page) in data.chunks_exact_mut(self.page_size).enumerate() {
+                if run.1 == 0 {
+                    run = (rng.next_f64() >= self.zero_ratio, self.run_length(&mut rng));
+                }
+                run.1 -= 1;
+                if !run.0 {
+                    continue;
+                }
+
+                for word in page.chunks_exact_mut(8) {
+                    word.copy_from_slice(&rng.next_u64().to_le_bytes());
+                }
+                pages[page_idx] = true;
+                data_pages.push(start + page_idx as u64 * page_size);
+            }
+
+            if self.is_reference(pheader_idx as usize) {
+                builder.add_reference_segment(
+                    (start, end),
+                    ProtFlags::READ | ProtFlags::EXEC,
+                    format!("/synthetic/{}.so", pheader_idx),
+                    0,
+                    data_runs(start, &data, &pages, self.page_size),
+                );
+                continue;
+            }
+
+            let prot = if pheader_idx % 2 == 0 {
+                ProtFlags::READ | ProtFlags::WRITE
+            } else {
+                ProtFlags::READ
+            };
+            let data = if self.prebuilt_itrees {
+                data_runs(start, &data, &pages, self.page_size)
+            } else {
+                vec![(start, data)]
+            };
+            builder.add_anonymous_segment((start, end), prot, data);
+        }
+
+        let mut ordered = data_pages
+            .into_iter()
+            .filter(|_addr| rng.next_f64() < self.ord_ratio)
+            .collect::<Vec<_>>();
+        for idx in (1..ordered.len()).rev() {
+            ordered.swap(idx, rng.next_u64() as usize % (idx + 1));
+        }
+        builder.set_ordering(
+            ordered
+                .into_iter()
+                .map(|addr| {
+                    let access = if rng.next_u64().is_multiple_of(2) {
+                        AccessKind::Read
+                    } else {
+                        AccessKind::Write
+                    };
+                    OrdChunk::new(addr, 1, DataSource::Private).with_provenance(None, Some(access))
+                })
+                .collect(),
+        );
+
+        builder.build()
+    }
+}
+
+/// Pseudo-random number generator (xorshift64*)
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // the state cannot be zero
+        XorShift(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `[0; 1)`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::JifRaw;
+    use crate::paths::AsRecorded;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn generate() {
+        let params = SyntheticJif::new(4, 32);
+        let mut jif = params.generate().unwrap();
+        assert_eq!(jif.pheaders().len(), 4);
+        assert_eq!(jif.total_pages(), params.n_pages());
+        assert!(jif.validate().is_valid());
+
+        // the zero pages are only found once the interval trees are built
+        assert_eq!(jif.zero_pages(), 0);
+        jif.build_itrees(&AsRecorded, None).unwrap();
+        assert!(jif.zero_pages() > 0);
+        assert_eq!(jif.zero_pages() + jif.private_pages(), params.n_pages());
+        assert!(!jif.ord_chunks().is_empty());
+        assert!(jif
+            .ord_chunks()
+            .iter()
+            .all(|chunk| jif.resolve(chunk.addr()).unwrap().source == DataSource::Private));
+
+        // generation is deterministic
+        let write = |jif: Jif| {
+            let mut buffer = Vec::new();
+            JifRaw::from_materialized(jif, true)
+                .to_writer(&mut buffer)
+                .unwrap();
+            buffer
+        };
+        let buffer = write(params.generate().unwrap());
+        assert_eq!(buffer, write(params.generate().unwrap()));
+        assert_ne!(
+            buffer,
+            write(
+                SyntheticJif {
+                    seed: 1,
+                    ..params.clone()
+                }
+                .generate()
+                .unwrap()
+            )
+        );
+        assert!(Jif::from_reader(&mut BufReader::new(Cursor::new(buffer))).is_ok());
+    }
+    #[test]
+    fn generate_shapes() {
+        let params = SyntheticJif {
+            shared_ratio: 0.5,
+            mean_run_pages: 8,
+            prebuilt_itrees: true,
+            ..SyntheticJif::new(4, 64)
+        };
+        let jif = params.generate().unwrap();
+        assert!(jif.validate().is_valid());
+        assert_eq!(
+            jif.pheaders()
+                .iter()
+                .filter(|pheader| pheader.pathname().is_some())
+                .count(),
+            2
+        );
+        assert!(jif.zero_pages() > 0);
+        assert!(jif.shared_pages() > 0);
+        assert_eq!(
+            jif.zero_pages() + jif.private_pages() + jif.shared_pages(),
+            params.n_pages()
+        );
+
+        // the interval trees are as if built, and the longer runs make for fewer intervals
+        let n_intervals = |jif: &Jif| {
+            jif.pheaders()
+                .iter()
+                .map(|pheader| pheader.itree().iter_logical_intervals().count())
+                .sum::<usize>()
+        };
+        let anonymous = SyntheticJif {
+            shared_ratio: 0.0,
+            ..params.clone()
+        };
+        let mut built = SyntheticJif {
+            prebuilt_itrees: false,
+            ..anonymous.clone()
+        }
+        .generate()
+        .unwrap();
+        built.build_itrees(&AsRecorded, None).unwrap();
+        let prebuilt = anonymous.generate().unwrap();
+        assert_eq!(prebuilt.zero_pages(), built.zero_pages());
+        assert_eq!(n_intervals(&prebuilt), n_intervals(&built));
+
+        let mut short_runs = SyntheticJif {
+            mean_run_pages: 1,
+            ..anonymous
+        }
+        .generate()
+        .unwrap();
+        short_runs.build_itrees(&AsRecorded, None).unwrap();
+        assert!(n_intervals(&prebuilt) < n_intervals(&short_runs));
+    }
+}
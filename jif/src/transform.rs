@@ -0,0 +1,161 @@
+//! Rewriting private pages
+//!
+//! The private data of an interval may be shared (through its [`DedupToken`]) with other
+//! intervals. Rewriting a page copies the contents of its interval into a new token, such that
+//! the intervals sharing the old one are left untouched
+
+use crate::deduper::{DedupToken, Deduper};
+use crate::error::*;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::Jif;
+use crate::pheader::JifPheader;
+
+impl Jif {
+    /// Visit every private page (of `page_size`), in address order, letting `f` rewrite its
+    /// contents in place
+    ///
+    /// The closure gets the address of the page and its contents. Returns the number of pages
+    /// whose contents were changed
+    pub fn transform_private_pages<F: FnMut(u64, &mut [u8])>(
+        &mut self,
+        mut f: F,
+    ) -> JifResult<usize> {
+        let page_size = self.arch.page_size;
+        let mut n_changed = 0;
+        for pheader in self.pheaders.iter_mut() {
+            let virtual_range = pheader.virtual_range();
+            let invalid_itree = |error| JifError::InvalidITree {
+                virtual_range,
+                error,
+            };
+            match pheader {
+                JifPheader::Anonymous { itree, .. } => {
+                    let intervals = transform_intervals(
+                        itree.take().into_iter_intervals(),
+                        &mut self.deduper,
+                        page_size,
+                        &mut f,
+                        &mut n_changed,
+                        AnonIntervalData::Ref,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                }
+                JifPheader::Reference { itree, .. } => {
+                    let intervals = transform_intervals(
+                        itree.take().into_iter_intervals(),
+                        &mut self.deduper,
+                        page_size,
+                        &mut f,
+                        &mut n_changed,
+                        RefIntervalData::Ref,
+                    );
+                    *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+                }
+            }
+        }
+
+        Ok(n_changed)
+    }
+}
+
+/// Rewrite the pages of the data intervals, moving the changed ones to a new token
+fn transform_intervals<Data: IntervalData, F: FnMut(u64, &mut [u8])>(
+    intervals: impl Iterator<Item = Interval<Data>>,
+    deduper: &mut Deduper,
+    page_size: usize,
+    f: &mut F,
+    n_changed: &mut usize,
+    token: fn(DedupToken) -> Data,
+) -> Vec<Interval<Data>> {
+    let mut transformed = Vec::new();
+    for interval in intervals.filter(|ival| !ival.is_none()) {
+        let Some(data) = interval.data.get_data(deduper) else {
+            transformed.push(interval);
+            continue;
+        };
+
+        let mut new_data = data.to_vec();
+        let mut changed = 0;
+        for ((page_idx, page), old_page) in new_data
+            .chunks_exact_mut(page_size)
+            .enumerate()
+            .zip(data.chunks_exact(page_size))
+        {
+            f(interval.start + (page_idx * page_size) as u64, page);
+            if page != old_page {
+                changed += 1;
+            }
+        }
+
+        if changed == 0 {
+            transformed.push(interval);
+        } else {
+            *n_changed += changed;
+            transformed.push(Interval::new(
+                interval.start,
+                interval.end,
+                token(deduper.insert(new_data)),
+            ));
+        }
+    }
+
+    transformed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::jif::test::gen_jif;
+    use crate::jif::JifRaw;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn transform_private_pages() {
+        // once read back, every interval of gen_jif shares the same token
+        let mut buffer = Vec::new();
+        JifRaw::from_materialized(
+            gen_jif(&[
+                ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+                ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+            ]),
+            false,
+        )
+        .to_writer(&mut buffer)
+        .unwrap();
+        let mut jif = Jif::from_reader(&mut BufReader::new(Cursor::new(buffer))).unwrap();
+        assert_eq!(jif.dedup_stats().n_tokens, 2);
+
+        let mut visited = Vec::new();
+        let n_changed = jif
+            .transform_private_pages(|addr, page| {
+                visited.push(addr);
+                if addr == 0x2000 || addr == 0x12000 {
+                    page[..8].copy_from_slice(&[0; 8]);
+                }
+            })
+            .unwrap();
+        assert_eq!(n_changed, 2);
+        assert_eq!(visited, vec![0x1000, 0x2000, 0x5000, 0x11000, 0x12000]);
+
+        let mut scrubbed = vec![42; PAGE_SIZE];
+        scrubbed[..8].copy_from_slice(&[0; 8]);
+        for (addr, expected) in [
+            (0x1000, &[42; PAGE_SIZE][..]),
+            (0x2000, &scrubbed[..]),
+            (0x5000, &[42; PAGE_SIZE][..]),
+            (0x11000, &[42; PAGE_SIZE][..]),
+            (0x12000, &scrubbed[..]),
+        ] {
+            assert_eq!(jif.resolve_data(addr), Some(expected), "{:#x}", addr);
+        }
+
+        // the rewritten intervals are identical, so they share their new token
+        assert_eq!(jif.dedup_stats().n_tokens, 2);
+
+        // nothing changes when no page is rewritten
+        assert_eq!(jif.transform_private_pages(|_addr, _page| {}).unwrap(), 0);
+    }
+}
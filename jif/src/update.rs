@@ -0,0 +1,484 @@
+//! Incremental updates
+//!
+//! Re-snapshotting a process often changes only a handful of pages. [`Jif::update_interval`]
+//! replaces the contents of an address range, and [`JifRaw::rewrite_in_place`] writes the result
+//! over the file the JIF was read from: the metadata is rewritten, but only the data segments
+//! which changed are written (appended after the existing data section)
+
+use crate::compress::Compression;
+use crate::deduper::Deduper;
+use crate::error::*;
+use crate::huge_page::split_part;
+use crate::itree::interval::{
+    AnonIntervalData, DataSource, Interval, IntervalData, RefIntervalData,
+};
+use crate::itree::itree_node::RawITreeNode;
+use crate::itree::ITree;
+use crate::jif::{Jif, JifHeaderBinary, JifRaw};
+use crate::ord::{has_provenance, OrdChunk};
+use crate::pheader::{JifPheader, JifRawPheader};
+use crate::utils::{is_page_aligned, page_align};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+impl Jif {
+    /// Replace the contents of the page aligned range `[start; end)` with private `data`
+    ///
+    /// The range has to be inside a single pheader. The intervals overlapping it are split around
+    /// it, and so are the ordering chunks (which now prefetch private data inside the range)
+    pub fn update_interval(&mut self, (start, end): (u64, u64), data: Vec<u8>) -> JifResult<()> {
+        let page_size = self.arch.page_size;
+        let pheader_idx = self.mapping_pheader_idx(start);
+        let in_pheader = pheader_idx.is_some_and(|idx| end <= self.pheaders[idx].virtual_range().1);
+        if !is_page_aligned(start, page_size)
+            || !is_page_aligned(end, page_size)
+            || start >= end
+            || !in_pheader
+            || data.len() as u64 != end - start
+        {
+            return Err(JifError::BadUpdate {
+                range: (start, end),
+                len: data.len(),
+            });
+        }
+
+        let pheader = &mut self.pheaders[pheader_idx.expect("checked to be in a pheader")];
+        let virtual_range = pheader.virtual_range();
+        let invalid_itree = |error| JifError::InvalidITree {
+            virtual_range,
+            error,
+        };
+        match pheader {
+            JifPheader::Anonymous { itree, .. } => {
+                let intervals = replace_range(
+                    itree.take().into_iter_intervals(),
+                    &self.deduper,
+                    (start, end),
+                    data,
+                    AnonIntervalData::Owned,
+                );
+                *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+            }
+            JifPheader::Reference { itree, .. } => {
+                let intervals = replace_range(
+                    itree.take().into_iter_intervals(),
+                    &self.deduper,
+                    (start, end),
+                    data,
+                    RefIntervalData::Owned,
+                );
+                *itree = ITree::build(intervals, virtual_range).map_err(invalid_itree)?;
+            }
+        }
+
+        self.ord_chunks = std::mem::take(&mut self.ord_chunks)
+            .into_iter()
+            .flat_map(|chunk| split_chunk(chunk, (start, end), page_size))
+            .collect();
+
+        Ok(())
+    }
+}
+
+/// Replace the parts of the intervals inside `[start; end)` with an interval holding `data`
+fn replace_range<Data: IntervalData + Clone>(
+    intervals: impl Iterator<Item = Interval<Data>>,
+    deduper: &Deduper,
+    (start, end): (u64, u64),
+    data: Vec<u8>,
+    owned: fn(Vec<u8>) -> Data,
+) -> Vec<Interval<Data>> {
+    let mut replaced = Vec::new();
+    for ival in intervals.filter(|ival| !ival.is_none()) {
+        if ival.end <= start || end <= ival.start {
+            replaced.push(ival);
+            continue;
+        }
+
+        let ival_data = ival.data.get_data(deduper);
+        let slice = |part_start: u64, part_end: u64| {
+            ival_data.map(|data| {
+                data[(part_start - ival.start) as usize..(part_end - ival.start) as usize].to_vec()
+            })
+        };
+        if ival.start < start {
+            replaced.push(split_part(&ival, ival.start, start, slice, owned));
+        }
+        if end < ival.end {
+            replaced.push(split_part(&ival, end, ival.end, slice, owned));
+        }
+    }
+
+    replaced.push(Interval::new(start, end, owned(data)));
+    replaced
+}
+
+/// Split an ordering chunk around `[start; end)`, such that it does not span across the
+/// boundaries of the replaced range (the part inside it prefetches private data)
+fn split_chunk(chunk: OrdChunk, (start, end): (u64, u64), page_size: usize) -> Vec<OrdChunk> {
+    let chunk_end = chunk.end(page_size);
+    if chunk_end <= start || end <= chunk.vaddr {
+        return vec![chunk];
+    }
+
+    let inside = (
+        std::cmp::max(chunk.vaddr, start),
+        std::cmp::min(chunk_end, end),
+    );
+    [
+        (chunk.vaddr, inside.0, chunk.kind),
+        (inside.0, inside.1, DataSource::Private),
+        (inside.1, chunk_end, chunk.kind),
+    ]
+    .into_iter()
+    .filter(|(part_start, part_end, _kind)| part_start < part_end)
+    .map(|(part_start, part_end, kind)| {
+        chunk.part(part_start, (part_end - part_start) / page_size as u64, kind)
+    })
+    .collect()
+}
+
+impl JifRaw {
+    /// Write this JIF over a `file` holding a previous version of it (e.g., the one it was read
+    /// from, before [`Jif::update_interval`])
+    ///
+    /// The data segments found unchanged in the file are kept in place, and the others are
+    /// appended after its data section: the replaced data is not reclaimed until the JIF is
+    /// written out anew. The metadata has to fit before the data section of the file, and
+    /// neither JIF can be compressed, a delta or have a page hash index, a custom metadata or an
+    /// integrity section
+    ///
+    /// The data is laid out as in the file, which requires reading the candidate segments back.
+    /// Returns the number of bytes written
+    pub fn rewrite_in_place<F: Read + Write + Seek>(&mut self, file: &mut F) -> JifResult<usize> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut old = JifRaw::from_reader_metadata(&mut BufReader::new(&mut *file))?;
+        old.read_trailing_sections(file)?;
+        if self.compression != Compression::None || old.compression != Compression::None {
+            return Err(JifError::CompressedDataSection);
+        } else if self.delta || old.delta {
+            return Err(JifError::CannotRewriteInPlace {
+                reason: "delta JIFs reference the layout of their base",
+            });
+        } else if self.checksums || old.checksums {
+            return Err(JifError::CannotRewriteInPlace {
+                reason: "the integrity section covers the whole file",
+            });
+        } else if !self.meta.is_empty() || !old.meta.is_empty() {
+            return Err(JifError::CannotRewriteInPlace {
+                reason: "the custom metadata section trails the data section",
+            });
+        } else if self.page_index.is_some() || old.page_index.is_some() {
+            return Err(JifError::CannotRewriteInPlace {
+                reason: "the page hash index trails the data section",
+            });
+        } else if self.arch != old.arch {
+            return Err(JifError::ArchMismatch {
+                expected: old.arch,
+                found: self.arch,
+            });
+        } else if self.metadata_size() > old.data_offset {
+            return Err(JifError::CannotRewriteInPlace {
+                reason: "the metadata does not fit before the data section",
+            });
+        }
+
+        // old data intervals, as (start, end, offset), sorted by address
+        let old_intervals = {
+            let mut v = old
+                .itree_nodes
+                .iter()
+                .flat_map(|n| n.ranges.iter())
+                .filter(|i| i.is_data())
+                .map(|i| (i.start, i.end, i.offset))
+                .collect::<Vec<_>>();
+            v.sort();
+            v
+        };
+        let mut append_at = page_align(
+            old_intervals
+                .iter()
+                .map(|(start, end, offset)| offset + (end - start))
+                .max()
+                .unwrap_or(old.data_offset),
+            self.arch.page_size,
+        );
+
+        // virtual ranges referencing each segment (by its offset)
+        let mut referencing: BTreeMap<u64, Vec<(u64, u64)>> = BTreeMap::new();
+        for ival in self
+            .itree_nodes
+            .iter()
+            .flat_map(|n| n.ranges.iter())
+            .filter(|i| i.is_data())
+        {
+            referencing
+                .entry(ival.offset)
+                .or_default()
+                .push((ival.start, ival.end));
+        }
+
+        let prefetch_end = self.data_offset + self.n_prefetch * self.arch.page_size as u64;
+        let segments = JifRaw::data_segment_ranges(&self.itree_nodes, self.data_offset)
+            .into_iter()
+            .zip(std::mem::take(&mut self.data_segments).into_values())
+            .map(|((offset, _len), data)| (self.data_offset + offset, data))
+            .collect::<Vec<_>>();
+
+        // offset in this JIF -> (offset in the file, length, whether it is prefetched)
+        let mut relocation = BTreeMap::new();
+        let mut buffer = Vec::new();
+        let mut written = 0;
+        for (offset, data) in &segments {
+            let len = data.len() as u64;
+
+            // the segment is unchanged if the file has the same data at the same addresses
+            let mut found = None;
+            for (start, end) in referencing.get(offset).into_iter().flatten() {
+                let idx = old_intervals.partition_point(|ival| ival.0 <= *start);
+                let Some((old_start, _old_end, old_offset)) = idx
+                    .checked_sub(1)
+                    .map(|idx| old_intervals[idx])
+                    .filter(|ival| *end <= ival.1)
+                else {
+                    continue;
+                };
+
+                let candidate = old_offset + (start - old_start);
+                buffer.resize(data.len(), 0);
+                file.seek(SeekFrom::Start(candidate))?;
+                file.read_exact(&mut buffer)?;
+                if buffer == *data {
+                    found = Some(candidate);
+                    break;
+                }
+            }
+
+            let new_offset = match found {
+                Some(candidate) => candidate,
+                None => {
+                    file.seek(SeekFrom::Start(append_at))?;
+                    file.write_all(data)?;
+                    written += data.len();
+                    append_at += len;
+                    append_at - len
+                }
+            };
+            relocation.insert(*offset, (new_offset, len, *offset < prefetch_end));
+        }
+
+        for ival in self
+            .itree_nodes
+            .iter_mut()
+            .flat_map(|n| n.ranges.iter_mut())
+            .filter(|i| i.is_data())
+        {
+            ival.offset = relocation[&ival.offset].0;
+        }
+        self.data_offset = old.data_offset;
+        self.data_segments = segments
+            .into_iter()
+            .map(|(offset, data)| {
+                let new_offset = relocation[&offset].0 - self.data_offset;
+                ((new_offset, new_offset + data.len() as u64), data)
+            })
+            .collect();
+
+        // the prefetched pages are the ones at the start of the data section, as long as they
+        // are still (contiguously) covered by prefetched segments
+        let mut prefetched = relocation
+            .values()
+            .filter(|(_offset, _len, prefetch)| *prefetch)
+            .map(|(offset, len, _prefetch)| (*offset, *len))
+            .collect::<Vec<_>>();
+        prefetched.sort();
+        let mut cursor = self.data_offset;
+        for (offset, len) in prefetched {
+            if offset > cursor {
+                break;
+            }
+            cursor = std::cmp::max(cursor, offset + len);
+        }
+        self.n_prefetch = std::cmp::min(
+            self.n_prefetch,
+            (cursor - self.data_offset) / self.arch.page_size as u64,
+        );
+
+        let mut metadata = Vec::with_capacity(self.data_offset as usize);
+        self.write_metadata(&mut metadata)?;
+        debug_assert_eq!(metadata.len() as u64, self.data_offset);
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&metadata)?;
+        file.flush()?;
+
+        Ok(written + metadata.len())
+    }
+
+    /// Size of the metadata sections (i.e., the smallest data offset)
+    pub(crate) fn metadata_size(&self) -> u64 {
+        let page_size = self.arch.page_size;
+        page_align(
+            (std::mem::size_of::<JifHeaderBinary>()
+                + self.pheaders.len() * JifRawPheader::serialized_size()) as u64,
+            page_size,
+        ) + page_align(self.strings_backing.len() as u64, page_size)
+            + page_align(
+                (self.itree_nodes.len() * RawITreeNode::serialized_size(self.itree_fanout)) as u64,
+                page_size,
+            )
+            + page_align(
+                (self.ord_chunks.len()
+                    * OrdChunk::serialized_size(has_provenance(&self.ord_chunks)))
+                    as u64,
+                page_size,
+            )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arch::Arch;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::Cursor;
+
+    const PAGE: u64 = PAGE_SIZE as u64;
+
+    fn gen_jif() -> Jif {
+        let vaddr_range = (0x10000, 0x20000);
+        Jif {
+            pheaders: vec![JifPheader::Anonymous {
+                vaddr_range,
+                itree: ITree::build(
+                    vec![Interval::new(
+                        0x10000,
+                        0x18000,
+                        AnonIntervalData::Owned(
+                            (0..8u8)
+                                .flat_map(|byte| std::iter::repeat_n(byte, PAGE_SIZE))
+                                .collect(),
+                        ),
+                    )],
+                    vaddr_range,
+                )
+                .unwrap(),
+                prot: ProtFlags::READ,
+            }],
+            ord_chunks: vec![
+                OrdChunk::new(0x11000, 4, DataSource::Private),
+                OrdChunk::new(0x18000, 2, DataSource::Zero),
+            ],
+            deduper: Deduper::default(),
+            arch: Arch::default(),
+            meta: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn update_interval() {
+        let mut jif = gen_jif();
+        jif.update_interval((0x12000, 0x14000), vec![0xa; 2 * PAGE_SIZE])
+            .unwrap();
+        jif.update_interval((0x19000, 0x1b000), vec![0xb; 2 * PAGE_SIZE])
+            .unwrap();
+
+        assert_eq!(jif.pheaders()[0].itree().n_intervals(), 4);
+        assert_eq!(jif.resolve_data(0x11000), Some(&[1u8; PAGE_SIZE][..]));
+        assert_eq!(jif.resolve_data(0x13000), Some(&[0xau8; PAGE_SIZE][..]));
+        assert_eq!(jif.resolve_data(0x14000), Some(&[4u8; PAGE_SIZE][..]));
+        assert_eq!(jif.resolve(0x18000).unwrap().source, DataSource::Zero);
+        assert_eq!(jif.resolve_data(0x1a000), Some(&[0xbu8; PAGE_SIZE][..]));
+        assert_eq!(
+            jif.ord_chunks()
+                .iter()
+                .map(|chunk| (chunk.addr(), chunk.size(), chunk.kind()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x11000, 1, DataSource::Private),
+                (0x12000, 2, DataSource::Private),
+                (0x14000, 1, DataSource::Private),
+                (0x18000, 1, DataSource::Zero),
+                (0x19000, 1, DataSource::Private),
+            ]
+        );
+
+        for range in [(0x12800, 0x13000), (0x1f000, 0x21000), (0x30000, 0x31000)] {
+            assert!(matches!(
+                jif.update_interval(range, vec![0; (range.1 - range.0) as usize]),
+                Err(JifError::BadUpdate { .. })
+            ));
+        }
+        assert!(matches!(
+            jif.update_interval((0x10000, 0x11000), vec![0; 1]),
+            Err(JifError::BadUpdate { .. })
+        ));
+    }
+
+    #[test]
+    fn rewrite_in_place() {
+        let mut file = Cursor::new(Vec::new());
+        JifRaw::from_materialized(gen_jif(), true)
+            .to_writer(&mut file)
+            .unwrap();
+        let file_len = file.get_ref().len();
+
+        let read = |file: &mut Cursor<Vec<u8>>| {
+            file.set_position(0);
+            Jif::from_reader(&mut BufReader::new(file)).unwrap()
+        };
+
+        // only the metadata and the new page are written
+        let mut jif = read(&mut file);
+        jif.update_interval((0x13000, 0x14000), vec![0xa; PAGE_SIZE])
+            .unwrap();
+        let mut raw = JifRaw::from_materialized(jif, true);
+        let written = raw.rewrite_in_place(&mut file).unwrap();
+        assert_eq!(written as u64, raw.data_offset + PAGE);
+        assert_eq!(file.get_ref().len(), file_len + PAGE_SIZE);
+
+        // again, over the gaps left by the first update
+        let mut jif = read(&mut file);
+        jif.update_interval((0x12000, 0x14000), vec![0xb; 2 * PAGE_SIZE])
+            .unwrap();
+        let mut raw = JifRaw::from_materialized(jif, false);
+        let written = raw.rewrite_in_place(&mut file).unwrap();
+        assert_eq!(written as u64, raw.data_offset + 2 * PAGE);
+
+        let mut expected = gen_jif();
+        expected
+            .update_interval((0x12000, 0x14000), vec![0xb; 2 * PAGE_SIZE])
+            .unwrap();
+        let jif = read(&mut file);
+        for addr in (0x10000..0x20000).step_by(PAGE_SIZE) {
+            assert_eq!(
+                jif.resolve_data(addr),
+                expected.resolve_data(addr),
+                "{:#x}",
+                addr
+            );
+        }
+        assert_eq!(jif.ord_chunks().len(), 5);
+    }
+
+    #[test]
+    fn rewrite_in_place_incompatible() {
+        let mut file = Cursor::new(Vec::new());
+        gen_jif().to_writer(&mut file).unwrap();
+
+        let mut raw = JifRaw::from_materialized(gen_jif(), false);
+        raw.set_checksums(true);
+        assert!(matches!(
+            raw.rewrite_in_place(&mut file),
+            Err(JifError::CannotRewriteInPlace { .. })
+        ));
+
+        let mut raw = JifRaw::from_materialized(gen_jif(), false);
+        raw.set_compression(Compression::Lz4);
+        assert!(matches!(
+            raw.rewrite_in_place(&mut file),
+            Err(JifError::CompressedDataSection)
+        ));
+    }
+}
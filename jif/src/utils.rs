@@ -1,7 +1,20 @@
-use std::io::{BufReader, Read, Seek};
+use std::fs::File;
+use std::io::{BufReader, IoSlice, Read, Seek, Write};
+use std::path::Path;
 
+/// Default (and smallest supported) page size
 pub(crate) const PAGE_SIZE: usize = 0x1000;
 
+/// Largest supported page size
+pub(crate) const MAX_PAGE_SIZE: usize = 0x100000;
+
+pub(crate) const HUGE_PAGE_SIZE: usize = 0x200000;
+
+/// Whether a page size is supported: a power of two between [`PAGE_SIZE`] and [`MAX_PAGE_SIZE`]
+pub(crate) const fn is_valid_page_size(page_size: usize) -> bool {
+    page_size.is_power_of_two() && PAGE_SIZE <= page_size && page_size <= MAX_PAGE_SIZE
+}
+
 pub(crate) fn read_u8<R: Read>(r: &mut R, buffer: &mut [u8; 1]) -> std::io::Result<u8> {
     r.read_exact(buffer)?;
     Ok(buffer[0])
@@ -19,14 +32,15 @@ pub(crate) fn read_u64<R: Read>(r: &mut R, buffer: &mut [u8; 8]) -> std::io::Res
 
 /// seek to the next aligned position
 /// return the new position
-pub(crate) fn seek_to_alignment<R: Read + Seek, const ALIGNMENT: usize>(
+pub(crate) fn seek_to_alignment<R: Read + Seek>(
     r: &mut BufReader<R>,
+    alignment: usize,
 ) -> std::io::Result<u64> {
     let cur = r.stream_position()?;
-    let delta = cur % ALIGNMENT as u64;
+    let delta = cur % alignment as u64;
     if delta != 0 {
-        r.seek_relative((ALIGNMENT as u64 - delta) as i64)?;
-        Ok(cur + ALIGNMENT as u64 - delta)
+        r.seek_relative((alignment as u64 - delta) as i64)?;
+        Ok(cur + alignment as u64 - delta)
     } else {
         Ok(cur)
     }
@@ -34,42 +48,40 @@ pub(crate) fn seek_to_alignment<R: Read + Seek, const ALIGNMENT: usize>(
 
 /// seek to the next aligned page
 /// return the new position
-pub(crate) fn seek_to_page<R: Read + Seek>(r: &mut BufReader<R>) -> std::io::Result<u64> {
-    seek_to_alignment::<R, PAGE_SIZE>(r)
+pub(crate) fn seek_to_page<R: Read + Seek>(
+    r: &mut BufReader<R>,
+    page_size: usize,
+) -> std::io::Result<u64> {
+    seek_to_alignment(r, page_size)
 }
 
-pub(crate) const fn is_aligned<const ALIGNMENT: usize>(v: u64) -> bool {
-    v % ALIGNMENT as u64 == 0
+pub(crate) const fn is_aligned(v: u64, alignment: usize) -> bool {
+    v.is_multiple_of(alignment as u64)
 }
 
-pub(crate) const fn is_page_aligned(v: u64) -> bool {
-    is_aligned::<PAGE_SIZE>(v)
+pub(crate) const fn is_page_aligned(v: u64, page_size: usize) -> bool {
+    is_aligned(v, page_size)
 }
 
-pub(crate) const fn align<const ALIGNMENT: usize>(val: u64) -> u64 {
-    let delta = val % ALIGNMENT as u64;
+pub(crate) const fn align(val: u64, alignment: usize) -> u64 {
+    let delta = val % alignment as u64;
     if delta != 0 {
-        val + ALIGNMENT as u64 - delta
+        val + alignment as u64 - delta
     } else {
         val
     }
 }
 
-pub(crate) const fn page_align(val: u64) -> u64 {
-    align::<PAGE_SIZE>(val)
+pub(crate) const fn page_align(val: u64, page_size: usize) -> u64 {
+    align(val, page_size)
 }
 
-pub(crate) const fn align_down<const ALIGNMENT: usize>(val: u64) -> u64 {
-    let delta = val % ALIGNMENT as u64;
-    if delta != 0 {
-        val - delta
-    } else {
-        val
-    }
+pub(crate) const fn align_down(val: u64, alignment: usize) -> u64 {
+    val - val % alignment as u64
 }
 
-pub(crate) const fn page_align_down(val: u64) -> u64 {
-    align_down::<PAGE_SIZE>(val)
+pub(crate) const fn page_align_down(val: u64, page_size: usize) -> u64 {
+    align_down(val, page_size)
 }
 
 #[derive(Debug)]
@@ -79,7 +91,7 @@ pub(crate) enum PageCmp {
     Zero,
 }
 
-// ASSUMPTION: page.len() == PAGE_SIZE
+// ASSUMPTION: page.len() is a multiple of 16 (i.e., a page)
 pub(crate) fn is_zero(page: &[u8]) -> bool {
     !(0..page.len())
         .step_by(std::mem::size_of::<u128>())
@@ -93,20 +105,97 @@ pub(crate) fn is_zero(page: &[u8]) -> bool {
         .any(|x| x != 0)
 }
 
-// ASSUMPTION: base.len() == overlay.len() == PAGE_SIZE
-// TODO(array_chunks): waiting on the `array_chunks` (#![feature(iter_array_chunks)]) that carries
-// the size information to change the input types to &[u8; PAGE_SIZE]
-//
-// required right now because the slices are created by `[u8]::chunks_exact`, which does not carry
-// size information
-pub(crate) fn compare_pages(base: &[u8], overlay: &[u8]) -> PageCmp {
-    if is_zero(overlay) {
-        return PageCmp::Zero;
+/// Open a file to read (without file system access, i.e., the `fs` feature, this fails as
+/// unsupported)
+pub(crate) fn open_file(path: &Path) -> std::io::Result<File> {
+    if !cfg!(feature = "fs") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("cannot open {}: no file system access", path.display()),
+        ));
     }
 
-    if base == overlay {
-        PageCmp::Same
+    File::open(path)
+}
+
+/// Map `f` over the `items`, spreading them over the available cores (preserving their order)
+pub(crate) fn par_map<T: Sync, U: Send>(items: &[T], f: impl Fn(&T) -> U + Sync) -> Vec<U> {
+    let n_threads = if cfg!(feature = "threads") {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
     } else {
-        PageCmp::Diff
+        1
+    };
+    if n_threads == 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let chunk_size = items.len().div_ceil(n_threads);
+    std::thread::scope(|scope| {
+        let handles = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| chunk.iter().map(&f).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Write all the buffers (in order) with vectored writes
+pub(crate) fn write_all_vectored<W: Write>(
+    w: &mut W,
+    mut bufs: &mut [IoSlice<'_>],
+) -> std::io::Result<()> {
+    // skip the leading empty buffers, which would otherwise look like a zero sized write
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match w.write_vectored(bufs) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writer which accepts at most 3 bytes per write
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = std::cmp::min(3, buf.len());
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parallel_and_vectored_helpers() {
+        let items = (0..1000u64).collect::<Vec<_>>();
+        assert_eq!(
+            par_map(&items, |x| x * 2),
+            items.iter().map(|x| x * 2).collect::<Vec<_>>()
+        );
+
+        let mut w = Trickle(Vec::new());
+        let mut bufs = [
+            IoSlice::new(b""),
+            IoSlice::new(b"hello"),
+            IoSlice::new(b""),
+            IoSlice::new(b", world"),
+        ];
+        write_all_vectored(&mut w, &mut bufs).unwrap();
+        assert_eq!(w.0, b"hello, world");
     }
 }
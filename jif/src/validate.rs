@@ -0,0 +1,671 @@
+//! Structural validation of JIFs
+//!
+//! Parsing a JIF only guarantees that it is well formed enough to be materialized: the
+//! validation goes further and checks the invariants the rest of the tooling (and the restore
+//! path) relies on, reporting every violation found as a [`Finding`] (see [`Jif::validate`]
+//! and [`JifRaw::validate`])
+
+use crate::deduper::DedupToken;
+use crate::itree::interval::{AnonIntervalData, Interval, IntervalData, RefIntervalData};
+use crate::itree::ITree;
+use crate::jif::{Jif, JifRaw};
+use crate::pheader::JifPheader;
+use crate::utils::is_page_aligned;
+
+use std::collections::{BTreeSet, HashSet};
+
+/// How serious a [`Finding`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The JIF is valid, but could be stored more efficiently
+    Warning,
+
+    /// The JIF violates an invariant and may not be restored correctly
+    Error,
+}
+
+/// A violation found while validating a JIF
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// The pheader starts before the preceding one
+    UnsortedPheaders { pheader_idx: usize },
+
+    /// Two pheaders map intersecting virtual ranges
+    OverlappingPheaders {
+        pheader_1: (u64, u64),
+        pheader_2: (u64, u64),
+    },
+
+    /// The pheader range (or its offset into the referenced file) is not page aligned
+    MisalignedPheader { virtual_range: (u64, u64) },
+
+    /// The pheader maps an empty virtual range
+    EmptyPheader { virtual_range: (u64, u64) },
+
+    /// An interval is not page aligned
+    MisalignedInterval {
+        virtual_range: (u64, u64),
+        interval: (u64, u64),
+    },
+
+    /// An interval is not contained in the virtual range of its pheader
+    IntervalOutOfRange {
+        virtual_range: (u64, u64),
+        interval: (u64, u64),
+    },
+
+    /// Two intervals intersect (or are not ordered in the tree, which breaks lookups)
+    IntersectingIntervals {
+        virtual_range: (u64, u64),
+        interval_1: (u64, u64),
+        interval_2: (u64, u64),
+    },
+
+    /// Some intervals of the tree are not reachable by a lookup
+    UnreachableIntervals {
+        virtual_range: (u64, u64),
+        n_intervals: usize,
+        n_reachable: usize,
+    },
+
+    /// The data of an interval does not match its length
+    DataSizeMismatch {
+        virtual_range: (u64, u64),
+        interval: (u64, u64),
+        data_len: usize,
+    },
+
+    /// An interval references data which is not in the deduper
+    DanglingDedupToken {
+        virtual_range: (u64, u64),
+        interval: (u64, u64),
+    },
+
+    /// Two adjacent intervals map to the zero page, and could be a single one
+    MergeableIntervals {
+        virtual_range: (u64, u64),
+        interval_1: (u64, u64),
+        interval_2: (u64, u64),
+    },
+
+    /// The interval tree has more nodes than its intervals need
+    SparseITree {
+        virtual_range: (u64, u64),
+        n_nodes: usize,
+        min_nodes: usize,
+    },
+
+    /// An ord chunk covers a page which no pheader maps
+    UnmappedOrdChunk { ord_chunk_idx: usize, vaddr: u64 },
+
+    /// An ord chunk does not start at a page boundary
+    MisalignedOrdChunk { ord_chunk_idx: usize, vaddr: u64 },
+
+    /// An ord chunk covers no pages
+    EmptyOrdChunk { ord_chunk_idx: usize },
+
+    /// Data segments which no interval references
+    UnreferencedData { n_segments: usize },
+
+    /// The pathname offset of a pheader does not point to the start of a string
+    DanglingPathname {
+        pheader_idx: usize,
+        pathname_offset: u32,
+    },
+
+    /// A string which no pheader references
+    UnreferencedString { offset: usize },
+}
+
+impl Finding {
+    /// The severity of the finding
+    pub fn severity(&self) -> Severity {
+        match self {
+            Finding::MergeableIntervals { .. }
+            | Finding::SparseITree { .. }
+            | Finding::EmptyOrdChunk { .. }
+            | Finding::UnreferencedData { .. }
+            | Finding::UnreferencedString { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::UnsortedPheaders { pheader_idx } => f.write_fmt(format_args!(
+                "pheader (idx = {}) starts before the preceding one",
+                pheader_idx
+            )),
+            Finding::OverlappingPheaders {
+                pheader_1,
+                pheader_2,
+            } => f.write_fmt(format_args!(
+                "pheaders [{:#x}; {:#x}) and [{:#x}; {:#x}) overlap",
+                pheader_1.0, pheader_1.1, pheader_2.0, pheader_2.1
+            )),
+            Finding::MisalignedPheader { virtual_range } => f.write_fmt(format_args!(
+                "pheader [{:#x}; {:#x}) is not page aligned",
+                virtual_range.0, virtual_range.1
+            )),
+            Finding::EmptyPheader { virtual_range } => f.write_fmt(format_args!(
+                "pheader [{:#x}; {:#x}) is empty",
+                virtual_range.0, virtual_range.1
+            )),
+            Finding::MisalignedInterval {
+                virtual_range,
+                interval,
+            } => f.write_fmt(format_args!(
+                "interval [{:#x}; {:#x}) of pheader [{:#x}; {:#x}) is not page aligned",
+                interval.0, interval.1, virtual_range.0, virtual_range.1
+            )),
+            Finding::IntervalOutOfRange {
+                virtual_range,
+                interval,
+            } => f.write_fmt(format_args!(
+                "interval [{:#x}; {:#x}) is out of the range of pheader [{:#x}; {:#x})",
+                interval.0, interval.1, virtual_range.0, virtual_range.1
+            )),
+            Finding::IntersectingIntervals {
+                virtual_range,
+                interval_1,
+                interval_2,
+            } => f.write_fmt(format_args!(
+                "intervals [{:#x}; {:#x}) and [{:#x}; {:#x}) of pheader [{:#x}; {:#x}) intersect or are out of order",
+                interval_1.0, interval_1.1, interval_2.0, interval_2.1, virtual_range.0, virtual_range.1
+            )),
+            Finding::UnreachableIntervals {
+                virtual_range,
+                n_intervals,
+                n_reachable,
+            } => f.write_fmt(format_args!(
+                "only {} of the {} intervals of pheader [{:#x}; {:#x}) are reachable",
+                n_reachable, n_intervals, virtual_range.0, virtual_range.1
+            )),
+            Finding::DataSizeMismatch {
+                virtual_range,
+                interval,
+                data_len,
+            } => f.write_fmt(format_args!(
+                "interval [{:#x}; {:#x}) of pheader [{:#x}; {:#x}) has {:#x} B of data",
+                interval.0, interval.1, virtual_range.0, virtual_range.1, data_len
+            )),
+            Finding::DanglingDedupToken {
+                virtual_range,
+                interval,
+            } => f.write_fmt(format_args!(
+                "interval [{:#x}; {:#x}) of pheader [{:#x}; {:#x}) references missing data",
+                interval.0, interval.1, virtual_range.0, virtual_range.1
+            )),
+            Finding::MergeableIntervals {
+                virtual_range,
+                interval_1,
+                interval_2,
+            } => f.write_fmt(format_args!(
+                "zero intervals [{:#x}; {:#x}) and [{:#x}; {:#x}) of pheader [{:#x}; {:#x}) could be merged",
+                interval_1.0, interval_1.1, interval_2.0, interval_2.1, virtual_range.0, virtual_range.1
+            )),
+            Finding::SparseITree {
+                virtual_range,
+                n_nodes,
+                min_nodes,
+            } => f.write_fmt(format_args!(
+                "itree of pheader [{:#x}; {:#x}) has {} nodes, where {} would do",
+                virtual_range.0, virtual_range.1, n_nodes, min_nodes
+            )),
+            Finding::UnmappedOrdChunk {
+                ord_chunk_idx,
+                vaddr,
+            } => f.write_fmt(format_args!(
+                "ord chunk (idx = {}) covers unmapped address {:#x}",
+                ord_chunk_idx, vaddr
+            )),
+            Finding::MisalignedOrdChunk {
+                ord_chunk_idx,
+                vaddr,
+            } => f.write_fmt(format_args!(
+                "ord chunk (idx = {}) starts at misaligned address {:#x}",
+                ord_chunk_idx, vaddr
+            )),
+            Finding::EmptyOrdChunk { ord_chunk_idx } => f.write_fmt(format_args!(
+                "ord chunk (idx = {}) is empty",
+                ord_chunk_idx
+            )),
+            Finding::UnreferencedData { n_segments } => f.write_fmt(format_args!(
+                "{} data segments are not referenced by any interval",
+                n_segments
+            )),
+            Finding::DanglingPathname {
+                pheader_idx,
+                pathname_offset,
+            } => f.write_fmt(format_args!(
+                "pathname offset {:#x} of pheader (idx = {}) does not point to a string",
+                pathname_offset, pheader_idx
+            )),
+            Finding::UnreferencedString { offset } => f.write_fmt(format_args!(
+                "string at offset {:#x} is not referenced by any pheader",
+                offset
+            )),
+        }
+    }
+}
+
+/// The findings of a validation
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    /// All the findings, in the order they were found
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    /// Iterate over the findings of a particular severity
+    pub fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(move |finding| finding.severity() == severity)
+    }
+
+    /// Whether there are no errors (warnings are allowed)
+    pub fn is_valid(&self) -> bool {
+        self.with_severity(Severity::Error).next().is_none()
+    }
+
+    /// Whether there are no findings at all
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Add the findings of another report
+    pub fn extend(&mut self, other: ValidationReport) {
+        self.findings.extend(other.findings)
+    }
+
+    fn push(&mut self, finding: Finding) {
+        self.findings.push(finding)
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for finding in &self.findings {
+            f.write_fmt(format_args!("{}: {}\n", finding.severity(), finding))?;
+        }
+
+        f.write_fmt(format_args!(
+            "{} errors, {} warnings",
+            self.with_severity(Severity::Error).count(),
+            self.with_severity(Severity::Warning).count()
+        ))
+    }
+}
+
+/// The data of an interval, as far as the validation is concerned
+enum IntervalContents {
+    Zero,
+    Owned(usize),
+    Token(DedupToken),
+}
+
+impl From<&AnonIntervalData> for IntervalContents {
+    fn from(data: &AnonIntervalData) -> Self {
+        match data {
+            AnonIntervalData::Owned(data) => IntervalContents::Owned(data.len()),
+            AnonIntervalData::Ref(token) => IntervalContents::Token(*token),
+            AnonIntervalData::None => IntervalContents::Zero,
+        }
+    }
+}
+
+impl From<&RefIntervalData> for IntervalContents {
+    fn from(data: &RefIntervalData) -> Self {
+        match data {
+            RefIntervalData::Owned(data) => IntervalContents::Owned(data.len()),
+            RefIntervalData::Ref(token) => IntervalContents::Token(*token),
+            RefIntervalData::Zero | RefIntervalData::None => IntervalContents::Zero,
+        }
+    }
+}
+
+impl Jif {
+    /// Validate the structure of the JIF
+    ///
+    /// Checks that the pheaders are sorted, disjoint and page aligned, that the interval trees
+    /// cover their pheaders (with intervals that are reachable, disjoint and backed by data of
+    /// the right size) and are compact, that the ord chunks only cover mapped pages and that all
+    /// the data is referenced
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        for (pheader_idx, (prev, pheader)) in self
+            .pheaders
+            .iter()
+            .zip(self.pheaders.iter().skip(1))
+            .enumerate()
+        {
+            let (prev_range, range) = (prev.virtual_range(), pheader.virtual_range());
+            if range.0 < prev_range.0 {
+                report.push(Finding::UnsortedPheaders {
+                    pheader_idx: pheader_idx + 1,
+                });
+            } else if prev_range.1 > range.0 {
+                report.push(Finding::OverlappingPheaders {
+                    pheader_1: prev_range,
+                    pheader_2: range,
+                });
+            }
+        }
+
+        let mut referenced = HashSet::new();
+        for pheader in &self.pheaders {
+            self.validate_pheader(pheader, &mut referenced, &mut report);
+        }
+
+        let n_unreferenced = self
+            .deduper
+            .tokens()
+            .filter(|token| !referenced.contains(token))
+            .count();
+        if n_unreferenced > 0 {
+            report.push(Finding::UnreferencedData {
+                n_segments: n_unreferenced,
+            });
+        }
+
+        for (ord_chunk_idx, chunk) in self.ord_chunks.iter().enumerate() {
+            if chunk.is_empty() {
+                report.push(Finding::EmptyOrdChunk { ord_chunk_idx });
+            } else if !is_page_aligned(chunk.addr(), self.arch.page_size) {
+                report.push(Finding::MisalignedOrdChunk {
+                    ord_chunk_idx,
+                    vaddr: chunk.addr(),
+                });
+            } else if let Some(vaddr) = self.unmapped_page(chunk) {
+                report.push(Finding::UnmappedOrdChunk {
+                    ord_chunk_idx,
+                    vaddr,
+                });
+            }
+        }
+
+        report
+    }
+
+    pub(crate) fn validate_pheader(
+        &self,
+        pheader: &JifPheader,
+        referenced: &mut HashSet<DedupToken>,
+        report: &mut ValidationReport,
+    ) {
+        let virtual_range = pheader.virtual_range();
+        if virtual_range.0 >= virtual_range.1 {
+            report.push(Finding::EmptyPheader { virtual_range });
+        }
+
+        let aligned = |v: u64| is_page_aligned(v, self.arch.page_size);
+        let ref_offset_aligned = pheader.ref_offset().is_none_or(aligned);
+        if !aligned(virtual_range.0) || !aligned(virtual_range.1) || !ref_offset_aligned {
+            report.push(Finding::MisalignedPheader { virtual_range });
+        }
+
+        match pheader {
+            JifPheader::Anonymous { itree, .. } => self.validate_itree(itree, referenced, report),
+            JifPheader::Reference { itree, .. } => self.validate_itree(itree, referenced, report),
+        }
+    }
+
+    fn validate_itree<Data: IntervalData>(
+        &self,
+        itree: &ITree<Data>,
+        referenced: &mut HashSet<DedupToken>,
+        report: &mut ValidationReport,
+    ) where
+        for<'a> &'a Data: Into<IntervalContents>,
+    {
+        let virtual_range = itree.virtual_range();
+        let intervals = itree.in_order_intervals().collect::<Vec<_>>();
+        let bounds = |ival: &Interval<Data>| (ival.start, ival.end);
+
+        if intervals.len() != itree.n_intervals() {
+            report.push(Finding::UnreachableIntervals {
+                virtual_range,
+                n_intervals: itree.n_intervals(),
+                n_reachable: intervals.len(),
+            });
+        }
+
+        let min_nodes = ITree::<Data>::n_itree_nodes_from_intervals(itree.n_intervals());
+        if itree.n_nodes() > min_nodes {
+            report.push(Finding::SparseITree {
+                virtual_range,
+                n_nodes: itree.n_nodes(),
+                min_nodes,
+            });
+        }
+
+        for ival in &intervals {
+            let interval = bounds(ival);
+            if !is_page_aligned(ival.start, self.arch.page_size)
+                || !is_page_aligned(ival.end, self.arch.page_size)
+            {
+                report.push(Finding::MisalignedInterval {
+                    virtual_range,
+                    interval,
+                });
+            }
+            if ival.start < virtual_range.0 || ival.end > virtual_range.1 {
+                report.push(Finding::IntervalOutOfRange {
+                    virtual_range,
+                    interval,
+                });
+            }
+
+            let data_len = match (&ival.data).into() {
+                IntervalContents::Zero => continue,
+                IntervalContents::Owned(data_len) => data_len,
+                IntervalContents::Token(token) => {
+                    referenced.insert(token);
+                    match self.deduper.try_get(token) {
+                        Some(data) => data.len(),
+                        None => {
+                            report.push(Finding::DanglingDedupToken {
+                                virtual_range,
+                                interval,
+                            });
+                            continue;
+                        }
+                    }
+                }
+            };
+            if data_len as u64 != ival.len() {
+                report.push(Finding::DataSizeMismatch {
+                    virtual_range,
+                    interval,
+                    data_len,
+                });
+            }
+        }
+
+        for pair in intervals.windows(2) {
+            let (interval_1, interval_2) = (bounds(pair[0]), bounds(pair[1]));
+            if interval_1.1 > interval_2.0 {
+                report.push(Finding::IntersectingIntervals {
+                    virtual_range,
+                    interval_1,
+                    interval_2,
+                });
+            } else if interval_1.1 == interval_2.0 && pair[0].is_zero() && pair[1].is_zero() {
+                report.push(Finding::MergeableIntervals {
+                    virtual_range,
+                    interval_1,
+                    interval_2,
+                });
+            }
+        }
+    }
+}
+
+impl JifRaw {
+    /// Validate the references into the string table, which are resolved when materializing
+    /// (and are therefore not checked by [`Jif::validate`])
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        // offsets at which strings start
+        let string_starts = std::iter::once(0)
+            .chain(
+                self.strings_backing
+                    .iter()
+                    .enumerate()
+                    .filter(|(_idx, byte)| **byte == 0)
+                    .map(|(idx, _byte)| idx + 1),
+            )
+            .filter(|offset| self.strings_backing.get(*offset).is_some_and(|b| *b != 0))
+            .collect::<BTreeSet<_>>();
+
+        let mut referenced = BTreeSet::new();
+        for (pheader_idx, pheader) in self.pheaders.iter().enumerate() {
+            let Some(pathname_offset) = pheader.pathname_offset() else {
+                continue;
+            };
+
+            if string_starts.contains(&(pathname_offset as usize)) {
+                referenced.insert(pathname_offset as usize);
+            } else {
+                report.push(Finding::DanglingPathname {
+                    pheader_idx,
+                    pathname_offset,
+                });
+            }
+        }
+
+        for offset in string_starts.difference(&referenced) {
+            report.push(Finding::UnreferencedString { offset: *offset });
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deduper::Deduper;
+    use crate::itree::interval::DataSource;
+    use crate::jif::test::gen_jif;
+    use crate::ord::OrdChunk;
+    use crate::pheader::ProtFlags;
+    use crate::utils::PAGE_SIZE;
+
+    use std::io::BufReader;
+
+    #[test]
+    fn validate_clean() {
+        let jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000), (0x5000, 0x6000)]),
+            ((0x10000, 0x14000), &[(0x11000, 0x13000)]),
+        ]);
+        assert!(jif.validate().is_clean(), "{}", jif.validate());
+
+        // read back, the data is deduplicated and the strings referenced
+        let mut buffer = Vec::new();
+        jif.to_writer(&mut buffer).unwrap();
+        let raw = JifRaw::from_reader(&mut BufReader::new(std::io::Cursor::new(buffer))).unwrap();
+        assert!(raw.validate().is_clean(), "{}", raw.validate());
+        let jif = Jif::from_raw(raw).unwrap();
+        assert!(jif.validate().is_clean(), "{}", jif.validate());
+    }
+
+    #[test]
+    fn validate_findings() {
+        let zero_ival = |start, end| Interval::new(start, end, RefIntervalData::Zero);
+        let mut jif = gen_jif(&[
+            ((0x1000, 0x8000), &[(0x1000, 0x3000)]),
+            ((0x7000, 0x9000), &[]),
+        ]);
+        jif.pheaders.push(JifPheader::Reference {
+            vaddr_range: (0x10000, 0x14000),
+            itree: ITree::build(
+                vec![
+                    zero_ival(0x10000, 0x11000),
+                    zero_ival(0x11000, 0x12000),
+                    Interval::new(0x12000, 0x13000, RefIntervalData::Owned(vec![0; 0x800])),
+                ],
+                (0x10000, 0x14000),
+            )
+            .unwrap(),
+            prot: ProtFlags::READ,
+            ref_path: "/lib/libfoo.so".to_string(),
+            ref_offset: 0x800,
+        });
+        jif.ord_chunks = vec![
+            OrdChunk::new(0x8000, 2, DataSource::Zero),
+            OrdChunk::new(0x20000, 0, DataSource::Zero),
+        ];
+        jif.deduper = Deduper::default();
+        jif.deduper.insert(vec![1; PAGE_SIZE]);
+
+        let report = jif.validate();
+        let expected = [
+            Finding::OverlappingPheaders {
+                pheader_1: (0x1000, 0x8000),
+                pheader_2: (0x7000, 0x9000),
+            },
+            Finding::MisalignedPheader {
+                virtual_range: (0x10000, 0x14000),
+            },
+            Finding::DataSizeMismatch {
+                virtual_range: (0x10000, 0x14000),
+                interval: (0x12000, 0x13000),
+                data_len: 0x800,
+            },
+            Finding::MergeableIntervals {
+                virtual_range: (0x10000, 0x14000),
+                interval_1: (0x10000, 0x11000),
+                interval_2: (0x11000, 0x12000),
+            },
+            Finding::UnreferencedData { n_segments: 1 },
+            Finding::UnmappedOrdChunk {
+                ord_chunk_idx: 0,
+                vaddr: 0x9000,
+            },
+            Finding::EmptyOrdChunk { ord_chunk_idx: 1 },
+        ];
+        assert_eq!(report.findings(), expected, "{}", report);
+        assert!(!report.is_valid());
+        assert_eq!(report.with_severity(Severity::Warning).count(), 3);
+    }
+
+    #[test]
+    fn validate_strings() {
+        let jif = gen_jif(&[((0x1000, 0x8000), &[(0x1000, 0x3000)])]);
+        let mut raw = JifRaw::from_materialized(jif, false);
+        raw.strings_backing = b"/lib/libfoo.so\0/lib/libbar.so\0".to_vec();
+        raw.pheaders[0].pathname_offset = 3;
+
+        assert_eq!(
+            raw.validate().findings(),
+            [
+                Finding::DanglingPathname {
+                    pheader_idx: 0,
+                    pathname_offset: 3
+                },
+                Finding::UnreferencedString { offset: 0 },
+                Finding::UnreferencedString { offset: 15 },
+            ]
+        );
+    }
+}
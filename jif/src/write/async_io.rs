@@ -0,0 +1,104 @@
+use crate::async_io::{flush, write_all, AsyncWrite};
+use crate::compress::{compress_blocks, Compression};
+use crate::integrity::{crc32c, IntegrityTrailer};
+use crate::jif::{Jif, JifRaw};
+use crate::utils::par_map;
+
+/// Asynchronous writer which counts the bytes written (and computes their checksum, if needed)
+struct Sink<'a, W> {
+    w: &'a mut W,
+    written: usize,
+    crc: Option<u32>,
+}
+
+impl<W: AsyncWrite + Unpin> Sink<'_, W> {
+    async fn write(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        write_all(self.w, buf).await?;
+        self.written += buf.len();
+        if let Some(crc) = &mut self.crc {
+            *crc = crc32c(*crc, buf);
+        }
+        Ok(())
+    }
+}
+
+impl JifRaw {
+    /// Write a JIF asynchronously, writing out the same bytes as [`JifRaw::to_writer`] (see
+    /// [`crate::async_io`])
+    ///
+    /// The metadata and the trailing sections are serialized into buffers, while the data
+    /// segments are written straight from theirs, one at a time (unless compressed). The writer
+    /// is flushed once done
+    pub async fn to_async_writer<W: AsyncWrite + Unpin>(
+        &self,
+        w: &mut W,
+    ) -> std::io::Result<usize> {
+        let mut sink = Sink {
+            w,
+            written: 0,
+            crc: self.checksums.then_some(0),
+        };
+
+        let mut metadata = Vec::with_capacity(self.data_offset as usize);
+        self.write_metadata(&mut metadata)?;
+        assert!(metadata.len() <= self.data_offset as usize);
+        metadata.resize(self.data_offset as usize, 0);
+        sink.write(&metadata).await?;
+
+        match self.compression {
+            Compression::None => {
+                // the segments are padded up to their offset (see [`crate::DataLayout`])
+                let zero_page = vec![0u8; self.arch.page_size];
+                for ((start, end), data) in self.data_segments.iter() {
+                    let offset = (self.data_offset + start) as usize;
+                    while sink.written < offset {
+                        let to_write = std::cmp::min(zero_page.len(), offset - sink.written);
+                        sink.write(&zero_page[..to_write]).await?;
+                    }
+
+                    assert_eq!(
+                        data.len() as u64,
+                        end - start,
+                        "length does not match the range"
+                    );
+                    sink.write(data).await?;
+                }
+            }
+            Compression::Lz4 => {
+                let mut data = Vec::new();
+                self.write_data_segments(&mut data, sink.written)?;
+                sink.write(&compress_blocks(&data)).await?;
+            }
+        }
+
+        let mut trailing = Vec::new();
+        self.write_page_index(&mut trailing)?;
+        self.write_meta_section(&mut trailing)?;
+        sink.write(&trailing).await?;
+
+        if let Some(file_crc) = sink.crc {
+            let trailer = IntegrityTrailer {
+                segment_crcs: par_map(&self.data_segments.values().collect::<Vec<_>>(), |data| {
+                    crc32c(0, data)
+                }),
+                file_crc,
+                offset: sink.written as u64,
+            };
+            let mut buffer = Vec::with_capacity(trailer.serialized_size());
+            trailer.to_writer(&mut buffer)?;
+            sink.write(&buffer).await?;
+        }
+
+        flush(sink.w).await?;
+        Ok(sink.written)
+    }
+}
+
+impl Jif {
+    /// Write the [`Jif`] asynchronously (see [`Jif::to_writer`] and [`JifRaw::to_async_writer`])
+    pub async fn to_async_writer<W: AsyncWrite + Unpin>(self, w: &mut W) -> std::io::Result<usize> {
+        JifRaw::from_materialized(self, false)
+            .to_async_writer(w)
+            .await
+    }
+}
@@ -0,0 +1,16 @@
+use crate::integrity::IntegrityTrailer;
+
+use std::io::Write;
+
+impl IntegrityTrailer {
+    /// Write the trailer
+    pub(crate) fn to_writer<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
+        w.write_all(&(self.segment_crcs.len() as u64).to_le_bytes())?;
+        for crc in &self.segment_crcs {
+            w.write_all(&crc.to_le_bytes())?;
+        }
+        w.write_all(&self.file_crc.to_le_bytes())?;
+        w.write_all(&(self.serialized_size() as u64).to_le_bytes())?;
+        Ok(self.serialized_size())
+    }
+}
@@ -1,35 +1,227 @@
+use crate::compress::{compress_blocks, Compression};
+use crate::fanout::fanout_flags;
+use crate::integrity::{crc32c, CrcWriter, IntegrityTrailer};
 use crate::itree::itree_node::RawITreeNode;
-use crate::jif::{JifHeaderBinary, JifRaw, JIF_MAGIC_HEADER, JIF_VERSION};
-use crate::ord::OrdChunk;
-use crate::utils::{is_page_aligned, page_align, PAGE_SIZE};
+use crate::jif::{
+    JifHeaderBinary, JifRaw, JIF_FLAG_CHECKSUMS, JIF_FLAG_DELTA, JIF_FLAG_ORD_PROVENANCE,
+    JIF_MAGIC_HEADER, JIF_VERSION, JIF_VERSION_ORD_WEIGHTS,
+};
+use crate::ord::{has_provenance, has_weights, OrdChunk};
+use crate::pheader::JifRawPheader;
+use crate::utils::{is_page_aligned, page_align, par_map, write_all_vectored};
 
-use std::io::Write;
+use std::fs::File;
+use std::io::{BufWriter, IoSlice, Seek, Write};
+
+/// Maximum number of buffers in a vectored write (`IOV_MAX` on Linux)
+const MAX_IO_SLICES: usize = 1024;
+
+/// Pad up to the alignment of the (page long) buffer, returning the number of written bytes
+fn write_to_page_alignment<W: Write>(
+    w: &mut W,
+    cursor: usize,
+    buffer: &[u8],
+) -> std::io::Result<usize> {
+    let delta = page_align(cursor as u64, buffer.len()) as usize - cursor;
+    if delta > 0 {
+        w.write_all(&buffer[..delta])?;
+    }
+
+    Ok(delta)
+}
+
+/// Sizes of the metadata sections, as written (each padded to the page size)
+pub(crate) struct MetadataSizes {
+    pub(crate) strings: u64,
+    pub(crate) itrees: u64,
+    pub(crate) ord_offset: u64,
+    pub(crate) ord: u64,
+}
 
 impl JifRaw {
+    /// Compute the sizes of the metadata sections (the header and pheaders take up the pages up
+    /// to the strings)
+    pub(crate) fn metadata_sizes(&self) -> MetadataSizes {
+        let page_size = self.arch.page_size;
+        let provenance = has_provenance(&self.ord_chunks);
+
+        let strings = page_align(self.strings_backing.len() as u64, page_size);
+        let itrees = page_align(
+            (self.itree_nodes.len() * RawITreeNode::serialized_size(self.itree_fanout)) as u64,
+            page_size,
+        );
+        // the ord section is padded up to the data offset (e.g., when the metadata shrank in an
+        // in-place rewrite): the zeroed chunks are skipped when reading
+        let ord_offset = page_align(
+            (std::mem::size_of::<JifHeaderBinary>()
+                + self.pheaders.len() * JifRawPheader::serialized_size()) as u64,
+            page_size,
+        ) + strings
+            + itrees;
+        let ord = std::cmp::max(
+            page_align(
+                (self.ord_chunks.len() * OrdChunk::serialized_size(provenance)) as u64,
+                page_size,
+            ),
+            self.data_offset.saturating_sub(ord_offset),
+        );
+
+        MetadataSizes {
+            strings,
+            itrees,
+            ord_offset,
+            ord,
+        }
+    }
+
     /// Write a JIF
+    ///
+    /// The page hash index (see [`JifRaw::set_page_hash_index`]) and the custom metadata section,
+    /// if any, are appended after the data section, and if checksums are enabled (see
+    /// [`JifRaw::set_checksums`]), the integrity section after that
     pub fn to_writer<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
-        fn write_to_page_alignment<W: Write>(
-            w: &mut W,
-            cursor: usize,
-            buffer: &[u8; PAGE_SIZE],
-        ) -> std::io::Result<usize> {
-            let delta = page_align(cursor as u64) as usize - cursor;
-            if delta > 0 {
-                w.write_all(&buffer[..delta])?;
+        if !self.checksums {
+            return self.write_sections(w);
+        }
+
+        let mut crc_writer = CrcWriter::new(w);
+        let written = self.write_sections(&mut crc_writer)?;
+        let trailer = IntegrityTrailer {
+            segment_crcs: par_map(&self.data_segments.values().collect::<Vec<_>>(), |data| {
+                crc32c(0, data)
+            }),
+            file_crc: crc_writer.crc(),
+            offset: written as u64,
+        };
+
+        Ok(written + trailer.to_writer(w)?)
+    }
+
+    /// Write a JIF to a file with positioned writes, without going through a sequential stream
+    /// (see [`JifRaw::to_writer`])
+    ///
+    /// The metadata is written first, and the data segments are then written in parallel, each
+    /// straight from its buffer to its offset in the file. The file is truncated to the size of
+    /// the JIF. A compressed data section or an integrity section are computed over the whole
+    /// stream, so those JIFs (and any JIF on platforms without positioned writes) are written
+    /// sequentially instead
+    pub fn to_file(&self, file: &File) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        if self.compression == Compression::None && !self.checksums {
+            return self.write_positioned(file);
+        }
+
+        file.set_len(0)?;
+        let mut w = BufWriter::new(file);
+        w.rewind()?;
+        let written = self.to_writer(&mut w)?;
+        w.flush()?;
+        Ok(written)
+    }
+
+    /// Write the sections of an uncompressed JIF at their offsets in the file
+    #[cfg(unix)]
+    fn write_positioned(&self, file: &File) -> std::io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        let mut metadata = Vec::with_capacity(self.data_offset as usize);
+        let cursor = self.write_metadata(&mut metadata)?;
+        assert!(cursor <= self.data_offset as usize);
+
+        // offsets of the data segments, as laid out by `write_data_segments`
+        let mut cursor = self.data_offset;
+        let segments = self
+            .data_segments
+            .iter()
+            .map(|((start, end), data)| {
+                assert_eq!(
+                    data.len() as u64,
+                    end - start,
+                    "length does not match the range"
+                );
+                cursor = std::cmp::max(cursor, self.data_offset + start);
+                cursor += data.len() as u64;
+                (cursor - data.len() as u64, data.as_slice())
+            })
+            .collect::<Vec<_>>();
+
+        let mut trailing = Vec::new();
+        self.write_page_index(&mut trailing)?;
+        self.write_meta_section(&mut trailing)?;
+
+        // the padding (up to the data offset and between segments) is left as zeros
+        file.set_len(0)?;
+        file.set_len(cursor + trailing.len() as u64)?;
+        file.write_all_at(&metadata, 0)?;
+        par_map(&segments, |(offset, data)| file.write_all_at(data, *offset))
+            .into_iter()
+            .collect::<std::io::Result<()>>()?;
+        file.write_all_at(&trailing, cursor)?;
+
+        Ok(cursor as usize + trailing.len())
+    }
+
+    /// Write the header, metadata and data sections of the JIF
+    fn write_sections<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
+        let page_size = self.arch.page_size;
+        let zero_page = vec![0u8; page_size];
+        let mut cursor = self.write_metadata(w)?;
+
+        // data segments
+        if cursor != self.data_offset as usize {
+            eprintln!(
+                "WARN: cursor ({:#x}) did not match up with expected data offset ({:#x})",
+                cursor, self.data_offset
+            );
+
+            assert!(cursor < self.data_offset as usize);
+
+            if !is_page_aligned(cursor as u64, page_size) {
+                eprintln!("WARN: cursor ({:#x}) should be page aligned by now", cursor);
+                let written = write_to_page_alignment(w, cursor, &zero_page)?;
+                cursor += written;
             }
 
-            Ok(delta)
+            let n_pages = (self.data_offset as usize - cursor) / page_size;
+
+            for _ in 0..n_pages {
+                w.write_all(&zero_page)?;
+                cursor += zero_page.len();
+            }
         }
 
-        let zero_page = [0u8; PAGE_SIZE];
-        let ones_page = [0xffu8; PAGE_SIZE];
+        let cursor = match self.compression {
+            Compression::None => self.write_data_segments(w, cursor)?,
+            Compression::Lz4 => {
+                let mut data = Vec::new();
+                self.write_data_segments(&mut data, cursor)?;
+
+                let compressed = compress_blocks(&data);
+                w.write_all(&compressed)?;
+                cursor + compressed.len()
+            }
+        };
+
+        Ok(cursor + self.write_page_index(w)? + self.write_meta_section(w)?)
+    }
+
+    /// Write the header and the metadata sections (pheaders, strings, interval trees and
+    /// ordering chunks), returning the cursor at the end of the metadata
+    pub(crate) fn write_metadata<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
+        let page_size = self.arch.page_size;
+        let zero_page = vec![0u8; page_size];
+        let ones_page = vec![0xffu8; page_size];
+        let provenance = has_provenance(&self.ord_chunks);
 
         let n_pheaders = self.pheaders.len() as u32;
-        let strings_size = page_align(self.strings_backing.len() as u64) as u32;
-        let itrees_size =
-            page_align((self.itree_nodes.len() * RawITreeNode::serialized_size()) as u64) as u32;
-        let ord_size =
-            page_align((self.ord_chunks.len() * OrdChunk::serialized_size()) as u64) as u32;
+        let MetadataSizes {
+            strings: strings_size,
+            itrees: itrees_size,
+            ord_offset,
+            ord: ord_size,
+        } = self.metadata_sizes();
+        let (strings_size, itrees_size, ord_size) =
+            (strings_size as u32, itrees_size as u32, ord_size as u32);
 
         let mut cursor = 0;
 
@@ -39,7 +231,25 @@ impl JifRaw {
         w.write_all(&strings_size.to_le_bytes())?;
         w.write_all(&itrees_size.to_le_bytes())?;
         w.write_all(&ord_size.to_le_bytes())?;
-        w.write_all(&JIF_VERSION.to_le_bytes())?;
+        let flags = self.compression.flags()
+            | self.arch.flags()
+            | fanout_flags(self.itree_fanout)
+            | if self.delta { JIF_FLAG_DELTA } else { 0 }
+            | if self.checksums {
+                JIF_FLAG_CHECKSUMS
+            } else {
+                0
+            }
+            | if provenance {
+                JIF_FLAG_ORD_PROVENANCE
+            } else {
+                0
+            };
+        let version = match has_weights(&self.ord_chunks) {
+            true => JIF_VERSION_ORD_WEIGHTS,
+            false => JIF_VERSION,
+        };
+        w.write_all(&(version | flags).to_le_bytes())?;
         w.write_all(&self.n_prefetch.to_le_bytes())?;
 
         cursor += std::mem::size_of::<JifHeaderBinary>();
@@ -69,51 +279,48 @@ impl JifRaw {
 
         // ord chunks
         for ord in &self.ord_chunks {
-            cursor += ord.to_writer(w)?;
+            cursor += ord.to_writer(w, provenance)?;
         }
         let written = write_to_page_alignment(w, cursor, &zero_page)?;
         cursor += written;
-
-        // data segments
-        if cursor != self.data_offset as usize {
-            eprintln!(
-                "WARN: cursor ({:#x}) did not match up with expected data offset ({:#x})",
-                cursor, self.data_offset
-            );
-
-            assert!(cursor < self.data_offset as usize);
-
-            if !is_page_aligned(cursor as u64) {
-                eprintln!("WARN: cursor ({:#x}) should be page aligned by now", cursor);
-                let written = write_to_page_alignment(w, cursor, &zero_page)?;
-                cursor += written;
-            }
-
-            let n_pages = (self.data_offset as usize - cursor) / PAGE_SIZE;
-
-            for _ in 0..n_pages {
-                w.write_all(&zero_page)?;
-                cursor += zero_page.len();
-            }
+        while (cursor as u64) < ord_offset + ord_size as u64 {
+            w.write_all(&zero_page)?;
+            cursor += zero_page.len();
         }
 
+        Ok(cursor)
+    }
+
+    /// Write the data segments, starting with the cursor at the data offset
+    ///
+    /// The segments are written with vectored writes, to avoid copying them through the buffers
+    /// of the writer. Returns the cursor at the end of the data section
+    pub(crate) fn write_data_segments<W: Write>(
+        &self,
+        w: &mut W,
+        mut cursor: usize,
+    ) -> std::io::Result<usize> {
+        let zero_page = vec![0u8; self.arch.page_size];
+        let mut bufs = Vec::with_capacity(self.data_segments.len());
         for ((start, end), data) in self.data_segments.iter() {
-            while (cursor as u64) < *start {
-                eprintln!(
-                    "WARN: cursor ({:#x}) is behind the requested range to write [{:#x}, {:#x})",
-                    cursor, start, end
-                );
-                let page = [0u8; PAGE_SIZE];
-                let to_write = std::cmp::min(PAGE_SIZE, *start as usize - cursor);
-                w.write_all(&page[..to_write])?;
+            // the segments are padded up to their offset (see [`DataLayout`])
+            let offset = (self.data_offset + start) as usize;
+            while cursor < offset {
+                let to_write = std::cmp::min(self.arch.page_size, offset - cursor);
+                bufs.push(IoSlice::new(&zero_page[..to_write]));
                 cursor += to_write;
             }
 
             let len = data.len() as u64;
             assert_eq!(len, end - start, "length does not match the range");
-            w.write_all(data)?;
+            bufs.push(IoSlice::new(data));
             cursor += len as usize;
         }
+
+        // keep each write under the limit of the number of buffers accepted by the OS
+        for bufs in bufs.chunks_mut(MAX_IO_SLICES) {
+            write_all_vectored(w, bufs)?;
+        }
         Ok(cursor)
     }
 }
@@ -0,0 +1,26 @@
+use crate::jif::JifRaw;
+use crate::meta::{meta_size, META_MAGIC};
+
+use std::io::Write;
+
+impl JifRaw {
+    /// Write the custom metadata section (nothing, without metadata; see [`crate::meta`])
+    pub(crate) fn write_meta_section<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
+        let meta = &self.meta;
+        if meta.is_empty() {
+            return Ok(0);
+        }
+
+        for (key, value) in meta {
+            w.write_all(&(key.len() as u32).to_le_bytes())?;
+            w.write_all(&(value.len() as u32).to_le_bytes())?;
+            w.write_all(key.as_bytes())?;
+            w.write_all(value.as_bytes())?;
+        }
+        let size = meta_size(meta);
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&META_MAGIC)?;
+
+        Ok(size as usize)
+    }
+}
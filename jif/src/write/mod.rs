@@ -1,5 +1,10 @@
+#[cfg(feature = "async")]
+mod async_io;
+mod integrity;
 mod interval;
 mod itree_node;
 mod jif;
+mod meta;
 mod ord;
+mod page_index;
 mod pheader;
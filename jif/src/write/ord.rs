@@ -1,11 +1,16 @@
 use crate::itree::interval::DataSource;
-use crate::ord::OrdChunk;
-use crate::ord::{ORD_FLAG_MASK, ORD_PRIVATE_FLAG, ORD_SHARED_FLAG, ORD_ZERO_FLAG};
+use crate::ord::{AccessKind, OrdChunk};
+use crate::ord::{
+    ORD_FLAG_MASK, ORD_PRIVATE_FLAG, ORD_READ_FLAG, ORD_SHARED_FLAG, ORD_TID_FLAG, ORD_WEIGHT_FLAG,
+    ORD_WEIGHT_SHIFT, ORD_WRITE_FLAG, ORD_ZERO_FLAG,
+};
 use std::io::Write;
 
 impl OrdChunk {
     /// Write an ordering chunk
-    pub fn to_writer<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
+    ///
+    /// With `provenance`, the chunk is followed by its provenance word
+    pub fn to_writer<W: Write>(&self, w: &mut W, provenance: bool) -> std::io::Result<usize> {
         let mut vaddr = self.vaddr;
         assert!((vaddr & !ORD_FLAG_MASK) == 0);
         vaddr |= match self.kind {
@@ -15,6 +20,25 @@ impl OrdChunk {
         };
         w.write_all(&vaddr.to_le_bytes())?;
         w.write_all(&self.n_pages.to_le_bytes())?;
-        Ok(OrdChunk::serialized_size())
+        if provenance {
+            w.write_all(&self.provenance_word().to_le_bytes())?;
+        }
+        Ok(OrdChunk::serialized_size(provenance))
+    }
+
+    /// The provenance word: the thread id in the lower 32 bits, flags for what was recorded, and
+    /// the weight in the upper bits
+    fn provenance_word(&self) -> u64 {
+        let tid = self.tid.map(|tid| ORD_TID_FLAG | tid as u64).unwrap_or(0);
+        let access = match self.access {
+            Some(AccessKind::Read) => ORD_READ_FLAG,
+            Some(AccessKind::Write) => ORD_WRITE_FLAG,
+            None => 0,
+        };
+        let weight = self
+            .weight
+            .map(|weight| ORD_WEIGHT_FLAG | (weight as u64) << ORD_WEIGHT_SHIFT)
+            .unwrap_or(0);
+        tid | access | weight
     }
 }
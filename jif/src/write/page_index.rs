@@ -0,0 +1,23 @@
+use crate::jif::JifRaw;
+use crate::page_index::PAGE_INDEX_MAGIC;
+
+use std::io::Write;
+
+impl JifRaw {
+    /// Write the page hash index (nothing, without one; see [`crate::page_index`])
+    pub(crate) fn write_page_index<W: Write>(&self, w: &mut W) -> std::io::Result<usize> {
+        let Some(index) = &self.page_index else {
+            return Ok(0);
+        };
+
+        for (digest, offset) in index.iter() {
+            w.write_all(digest)?;
+            w.write_all(&offset.to_le_bytes())?;
+        }
+        let size = index.serialized_size();
+        w.write_all(&size.to_le_bytes())?;
+        w.write_all(&PAGE_INDEX_MAGIC)?;
+
+        Ok(size as usize)
+    }
+}
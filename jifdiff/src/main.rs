@@ -0,0 +1,155 @@
+//! # `jifdiff`
+//!
+//! A tool to report the structural differences between two JIF files
+//!
+//! Example usage:
+//! ```sh
+//! $ jifdiff before.jif after.jif # human-readable report
+//! $ jifdiff --json before.jif after.jif # machine-readable report
+//! ```
+//!
+//! Like `diff(1)`, it exits with status 1 if the JIFs differ
+
+use jif::diff::{PheaderDiff, RefSource};
+use jif::itree::interval::LogicalInterval;
+use jif::*;
+
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::Context;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// jifdiff: report the differences between two JIF files
+///
+/// Reports added/removed pheaders and, for pheaders in both files, changes in protections,
+/// backing files, intervals and private page contents
+struct Cli {
+    /// Original JIF file
+    #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    a: std::path::PathBuf,
+
+    /// Modified JIF file
+    #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    b: std::path::PathBuf,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Open the JIF file
+fn open_jif(path: &std::path::Path) -> anyhow::Result<Jif> {
+    Jif::from_reader(&mut BufReader::new(File::open(path).context(format!(
+        "failed to open file {}",
+        path.to_str().unwrap_or("<invalid path>")
+    ))?))
+    .context(format!(
+        "failed to read jif {}",
+        path.to_str().unwrap_or("<invalid path>")
+    ))
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_list<T>(items: &[T], f: impl Fn(&T) -> String) -> String {
+    format!("[{}]", items.iter().map(f).collect::<Vec<_>>().join(", "))
+}
+
+fn json_range(range: &(u64, u64)) -> String {
+    format!("{{\"start\": {}, \"end\": {}}}", range.0, range.1)
+}
+
+fn json_interval(ival: &LogicalInterval) -> String {
+    format!(
+        "{{\"start\": {}, \"end\": {}, \"source\": {}}}",
+        ival.start,
+        ival.end,
+        json_string(&format!("{:?}", ival.source).to_lowercase())
+    )
+}
+
+fn json_reference(reference: &RefSource) -> String {
+    match reference {
+        Some((path, offset)) => format!(
+            "{{\"path\": {}, \"offset\": {}}}",
+            json_string(path),
+            offset
+        ),
+        None => "null".to_string(),
+    }
+}
+
+fn json_pheader_diff(diff: &PheaderDiff) -> String {
+    let prot = diff
+        .prot
+        .map(|(a, b)| format!("{{\"from\": {}, \"to\": {}}}", a.bits(), b.bits()))
+        .unwrap_or_else(|| "null".to_string());
+    let reference = diff
+        .reference
+        .as_ref()
+        .map(|(a, b)| {
+            format!(
+                "{{\"from\": {}, \"to\": {}}}",
+                json_reference(a),
+                json_reference(b)
+            )
+        })
+        .unwrap_or_else(|| "null".to_string());
+
+    format!(
+        "{{\"virtual_range\": {}, \"prot\": {}, \"reference\": {}, \"removed_intervals\": {}, \"added_intervals\": {}, \"changed_pages\": {}}}",
+        json_range(&diff.virtual_range),
+        prot,
+        reference,
+        json_list(&diff.removed_intervals, json_interval),
+        json_list(&diff.added_intervals, json_interval),
+        json_list(&diff.changed_pages, |page| page.to_string()),
+    )
+}
+
+fn json_diff(diff: &JifDiff) -> String {
+    format!(
+        "{{\"removed_pheaders\": {}, \"added_pheaders\": {}, \"changed_pheaders\": {}}}",
+        json_list(&diff.removed_pheaders, json_range),
+        json_list(&diff.added_pheaders, json_range),
+        json_list(&diff.changed_pheaders, json_pheader_diff),
+    )
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Cli::parse();
+
+    let a = open_jif(&args.a)?;
+    let b = open_jif(&args.b)?;
+    let diff = a.diff(&b);
+
+    if args.json {
+        println!("{}", json_diff(&diff));
+    } else {
+        print!("{}", diff);
+    }
+
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
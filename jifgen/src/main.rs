@@ -0,0 +1,115 @@
+//! # `jifgen`
+//!
+//! A tool to generate synthetic JIF files
+//!
+//! The JIFs are randomized but valid, and generation is deterministic: the same options (seed
+//! included) give the same JIF, so that the fixtures of the tests and benchmarks can be generated
+//! instead of checked in (see the `SyntheticJif` of the `jif` library)
+//!
+//! Example usage:
+//! ```sh
+//! $ jifgen a.jif # 16 anonymous pheaders of 64 pages
+//! $ jifgen --pheaders 256 --pages 1024 --seed 7 big.jif
+//! $ jifgen --shared-ratio 0.5 --zero-ratio 0.6 --run-pages 16 mixed.jif # half reference pheaders, long intervals
+//! $ jifgen --build-itrees --ord-ratio 0.9 --setup-prefetch ordered.jif
+//! ```
+
+use jif::*;
+
+use std::fs::File;
+
+use anyhow::Context;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// jifgen: generate synthetic JIF files
+struct Cli {
+    /// Output JIF file path
+    #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    output_file: std::path::PathBuf,
+
+    /// Number of pheaders
+    #[arg(long, default_value_t = SyntheticJif::default().n_pheaders)]
+    pheaders: usize,
+
+    /// Number of pages in each pheader
+    #[arg(long, default_value_t = SyntheticJif::default().pages_per_pheader)]
+    pages: usize,
+
+    /// Fraction of the pages which are zero pages (shared with the file, in reference pheaders)
+    #[arg(long, value_name = "RATIO", value_parser = parse_ratio, default_value_t = SyntheticJif::default().zero_ratio)]
+    zero_ratio: f64,
+
+    /// Fraction of the pheaders which are reference pheaders (whose files do not exist)
+    #[arg(long, value_name = "RATIO", value_parser = parse_ratio, default_value_t = SyntheticJif::default().shared_ratio)]
+    shared_ratio: f64,
+
+    /// Fraction of the private pages in the ordering section
+    #[arg(long, value_name = "RATIO", value_parser = parse_ratio, default_value_t = SyntheticJif::default().ord_ratio)]
+    ord_ratio: f64,
+
+    /// Mean length of the runs of pages of the same kind (i.e., of the intervals), in pages
+    #[arg(long, value_name = "PAGES", value_parser = clap::value_parser!(u64).range(1..), default_value_t = SyntheticJif::default().mean_run_pages as u64)]
+    run_pages: u64,
+
+    /// Seed of the pseudo-random contents and ordering
+    #[arg(long, default_value_t = SyntheticJif::default().seed)]
+    seed: u64,
+
+    /// Page size, in B
+    #[arg(long, default_value_t = SyntheticJif::default().page_size)]
+    page_size: usize,
+
+    /// Store the anonymous data by interval, as `jiftool build-itrees` would (instead of whole)
+    #[arg(long)]
+    build_itrees: bool,
+
+    /// Set up the prefetch (breaking the intervals per ord chunks)
+    #[arg(long)]
+    setup_prefetch: bool,
+}
+
+/// Parse a fraction (between 0 and 1)
+fn parse_ratio(s: &str) -> anyhow::Result<f64> {
+    let ratio = s
+        .parse::<f64>()
+        .with_context(|| format!("failed to parse ratio {}", s))?;
+    if !(0.0..=1.0).contains(&ratio) {
+        anyhow::bail!("ratio out of [0; 1]: {}", s);
+    }
+
+    Ok(ratio)
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let params = SyntheticJif {
+        n_pheaders: cli.pheaders,
+        pages_per_pheader: cli.pages,
+        zero_ratio: cli.zero_ratio,
+        shared_ratio: cli.shared_ratio,
+        mean_run_pages: cli.run_pages as usize,
+        prebuilt_itrees: cli.build_itrees,
+        ord_ratio: cli.ord_ratio,
+        seed: cli.seed,
+        page_size: cli.page_size,
+    };
+    let jif = params.generate().context("failed to generate the JIF")?;
+    eprintln!(
+        "generated {} pheaders: {} zero, {} private and {} shared pages, {} ord chunks",
+        jif.pheaders().len(),
+        jif.zero_pages(),
+        jif.private_pages(),
+        jif.shared_pages(),
+        jif.ord_chunks().len()
+    );
+
+    let output_file = File::create(&cli.output_file).context("failed to open output JIF")?;
+    JifRaw::from_materialized(jif, cli.setup_prefetch)
+        .to_file(&output_file)
+        .context("failed to write JIF")?;
+
+    Ok(())
+}
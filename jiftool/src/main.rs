@@ -6,17 +6,53 @@
 //! ```sh
 //! $ jiftool orig.jif terse.jif # remove duplicate strings, etc.
 //! $ jiftool orig.jif new.jif rename /usr/bin/ld.so /bin/ld.so # rename path to `ld.so`
+//! $ jiftool orig.jif stripped.jif drop-vma 0x7f0000000000-0x7f0000200000 # drop the VMAs in the range
+//! $ jiftool orig.jif extract-vma 0x7f0000000000-0x7f0000001000 vma.jif # keep only the VMAs in the range
+//! $ jiftool libc.jif merged.jif merge app.jif # combine the VMAs of both JIFs
+//! $ jiftool orig.jif new.jif rebase 0x55d4a0000000 0x555555554000 # move every VMA
+//! $ jiftool orig.jif new.jif move 0x7f0000000000-0x7f0000100000 0x7e0000000000 # move some VMAs
+//! $ jiftool orig.jif ro.jif set-prot 0x7f0000000000-0x7f0000100000 r-- # make a range read-only
+//! $ jiftool orig.jif tagged.jif set-meta image=nginx:1.25 commit=3f2a9c1 # embed build information
+//! $ jiftool orig.jif small-vmas.jif split-vmas --max-size 256M # keep the VMAs under 256MiB
+//! $ jiftool --interactive orig.jif # apply commands one by one (with undo), then `write new.jif`
+//! $ jiftool orig.jif validate # report structural problems
+//! $ jiftool orig.jif dedup-stats # report how much private data is shared
+//! $ jiftool orig.jif audit-refs --chroot /srv/rootfs # check the referenced files can back the JIF
+//! $ jiftool --path-map /usr=/mnt/overlay/usr orig.jif itree.jif build-itrees # read the referenced files from elsewhere
+//! $ jiftool orig.jif extract 0x7f0000001000-0x7f000000a000 out.bin # dump the memory contents of the range
 //! $ jiftool orig.jif itree.jif build-itrees # build interval trees
+//! $ jiftool orig.jif itree.jif build-itrees --huge-page-align 64 # keep 2MiB regions with few zero pages whole
+//! $ jiftool itree.jif compact.jif optimize # merge adjacent intervals with the same source
+//! $ jiftool orig.jif minimal.jif minimize run.log # only keep the private pages touched in run.log
 //! $ jiftool orig.jif ordered.jif add-ord tsa.ord # add an ordering section
+//! $ jiftool orig.jif ordered.jif add-ord --merge frequency run1.ord run2.ord # order by the accesses of several runs
+//! $ jiftool orig.jif ordered.jif add-ord --weights tsa.ord # lay the hottest prefetched data out first
+//! $ perf script | jiftool orig.jif ordered.jif add-ord --format perf --pid 1234 # add an ordering section from the page faults recorded by perf
+//! $ jiftool ordered.jif unordered.jif strip-ord # remove the ordering section
+//! $ jiftool orig.jif terse.jif gc-strings # drop the unreferenced strings
+//! $ jiftool orig.jif small.jif compress # compress the data section
+//! $ jiftool small.jif orig.jif decompress # decompress the data section
+//! $ jiftool snapshot.jif delta.jif make-delta base.jif # only store the pages not in base.jif
+//! $ jiftool orig.jif split meta.jif data.blob # store the metadata apart from the data
+//! $ jiftool meta.jif full.jif join data.blob # reassemble a split JIF
+//! $ jiftool --dedup-pages orig.jif small.jif # store identical pages once
+//! $ jiftool --canonicalize-paths=/srv/rootfs orig.jif clean.jif # resolve the symlinks in the referenced paths
+//! $ jiftool --base base.jif delta.jif full.jif # resolve a delta against its base
+//! $ jiftool --sparse orig.jif sparse.jif # leave holes for the zero pages of the file
+//! $ jiftool --page-index orig.jif indexed.jif # store the page digests, to dedup against it quickly
+//! $ jiftool --data-padding ordered --data-alignment 64K orig.jif ordered.jif add-ord --setup-prefetch tsa.ord # align the prefetched data to the device blocks
 //! ```
 use jif::*;
-use tracer_format::{dedup_and_sort, read_trace};
+use tracer_format::{
+    access_counts, merge_traces, read_perf_trace, read_trace, MergePolicy, TimestampedAccess,
+};
 
 use anyhow::Context;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 
+mod repl;
 mod tsa;
 use tsa::*;
 
@@ -28,14 +64,66 @@ struct Cli {
     #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
     input_file: std::path::PathBuf,
 
-    /// Output file path
+    /// Output file path (not needed by `extract`, `extract-vma`, `split`, `validate`,
+    /// `dedup-stats` and `audit-refs`)
     #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-    output_file: std::path::PathBuf,
+    output_file: Option<std::path::PathBuf>,
 
     /// Whether to print out the resulting JIF
     #[arg(long)]
     show: bool,
 
+    /// Read the commands from `stdin`, applying them one by one to the JIF in memory (with
+    /// undo), instead of running a single command (see `help` in the prompt)
+    #[arg(short, long, conflicts_with_all = ["output_file", "show"])]
+    interactive: bool,
+
+    /// Base JIF to resolve the input against (if the input is a delta)
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    base: Option<std::path::PathBuf>,
+
+    /// Write an integrity section (data segment and file checksums)
+    #[arg(long)]
+    checksums: bool,
+
+    /// Write a page hash index (the digests of the data pages, for fast deduplication against
+    /// the JIF)
+    #[arg(long)]
+    page_index: bool,
+
+    /// Write the output as a sparse file (leaving holes for the zero pages of the file)
+    #[arg(long)]
+    sparse: bool,
+
+    /// Store identical private pages once, even across intervals (at the cost of more intervals)
+    #[arg(long)]
+    dedup_pages: bool,
+
+    /// Open the referenced files under another path prefix (e.g., where the rootfs of a
+    /// container is mounted): <old>=<new>, applied before the `--chroot` of the command
+    #[arg(long = "path-map", value_name = "OLD=NEW", value_parser = parse_path_map)]
+    path_map: Vec<(std::path::PathBuf, std::path::PathBuf)>,
+
+    /// Canonicalize the referenced paths (resolving symlinks and `..`), optionally as the paths
+    /// inside a root directory (where the referenced files are)
+    #[arg(long, value_name = "ROOT", value_hint = clap::ValueHint::DirPath, num_args = 0..=1, require_equals = true)]
+    canonicalize_paths: Option<Option<std::path::PathBuf>>,
+
+    /// Align the data segments of the output to the blocks of the storage device: none, those
+    /// of the ordering chunks (i.e., the prefetched ones) or all of them
+    #[arg(long, value_enum, default_value_t = DataPadding::None)]
+    data_padding: DataPadding,
+
+    /// Alignment of the padded data segments, in B (hexadecimal, or decimal with a K, M or G
+    /// suffix)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "64K")]
+    data_alignment: u64,
+
+    /// Do not align the data segments which would need more padding, in B (hexadecimal, or
+    /// decimal with a K, M or G suffix)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_data_gap: Option<u64>,
+
     /// Modifying command
     ///
     /// In the absence of a command it will simply
@@ -56,28 +144,189 @@ enum Command {
         #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
         new_path: String,
     },
+    /// Drop the VMAs overlapping an address range (with their data and ordering chunks)
+    DropVma {
+        /// Virtual address range, as `<start>-<end>` (hexadecimal)
+        #[arg(value_name = "RANGE", value_parser = parse_range)]
+        range: (u64, u64),
+    },
+
+    /// Extract the VMAs overlapping an address range as a standalone JIF
+    ///
+    /// Only their strings, data and ordering chunks are kept (e.g., to share a reproducer of a
+    /// corrupted VMA)
+    ExtractVma {
+        /// Virtual address range, as `<start>-<end>` (hexadecimal)
+        #[arg(value_name = "RANGE", value_parser = parse_range)]
+        range: (u64, u64),
+
+        /// Output JIF file path
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        output_file: std::path::PathBuf,
+    },
+
+    /// Shift every VMA (with its intervals and ordering chunks) from one base address to another
+    Rebase {
+        /// Base address at capture (hexadecimal)
+        #[arg(value_name = "OLD_BASE", value_parser = parse_addr)]
+        old_base: u64,
+
+        /// Base address at restore (hexadecimal)
+        #[arg(value_name = "NEW_BASE", value_parser = parse_addr)]
+        new_base: u64,
+    },
+
+    /// Move the VMAs inside an address range (with their intervals and ordering chunks)
+    Move {
+        /// Virtual address range, as `<start>-<end>` (hexadecimal)
+        #[arg(value_name = "RANGE", value_parser = parse_range)]
+        range: (u64, u64),
+
+        /// New start of the range (hexadecimal)
+        #[arg(value_name = "NEW_START", value_parser = parse_addr)]
+        new_start: u64,
+    },
+
+    /// Set the protections of an address range (splitting the VMAs partially inside it)
+    SetProt {
+        /// Virtual address range, as `<start>-<end>` (hexadecimal, page aligned)
+        #[arg(value_name = "RANGE", value_parser = parse_range)]
+        range: (u64, u64),
+
+        /// Protections, as `rwx` (with `-` for the missing ones, e.g. `r-x`)
+        #[arg(value_name = "PROT", value_parser = parse_prot)]
+        prot: ProtFlags,
+    },
+
+    /// Set custom metadata keys (e.g., build information), kept in a section of the JIF
+    SetMeta {
+        /// Key and value, as `<key>=<value>` (an empty value removes the key)
+        #[arg(value_name = "KEY=VALUE", required = true, value_parser = parse_meta)]
+        pairs: Vec<(String, String)>,
+    },
+
+    /// Split the VMAs larger than a size into contiguous VMAs of at most that size
+    ///
+    /// Their intervals, data and ordering chunks are kept: the restorer reserves smaller VMAs
+    SplitVmas {
+        /// Largest VMA, in B (hexadecimal, or decimal with a K, M or G suffix; page aligned)
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: u64,
+    },
+
+    /// Merge another JIF (with disjoint VMAs) into the input
+    Merge {
+        /// JIF to merge
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        other: std::path::PathBuf,
+    },
+
+    /// Validate the structure of the input JIF (without writing a JIF)
+    ///
+    /// Every finding is reported, and the command fails if any is an error
+    Validate,
+
+    /// Report how much of the private data is shared between intervals (without writing a JIF)
+    DedupStats,
+
+    /// Check that the referenced files exist, are readable and are long enough for the shared
+    /// pages mapped from them (without writing a JIF)
+    ///
+    /// Every pheader which cannot be restored is reported, and the command fails if there is any
+    AuditRefs {
+        /// Directory the referenced files are relative to
+        #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+        chroot: Option<std::path::PathBuf>,
+    },
+
+    /// Extract the memory contents of an address range to a binary file (without writing a JIF)
+    ///
+    /// Private data, zero pages and the contents of referenced files are stitched together
+    Extract {
+        /// Virtual address range, as `<start>-<end>` (hexadecimal)
+        #[arg(value_name = "RANGE", value_parser = parse_range)]
+        range: (u64, u64),
+
+        /// Output binary file path
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        output_file: std::path::PathBuf,
+
+        /// Directory the referenced files are relative to
+        #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+        chroot: Option<std::path::PathBuf>,
+    },
+
     /// Build the interval trees in the JIF
     BuildItrees {
         #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
         chroot_path: Option<std::path::PathBuf>,
+
+        /// Keep the 2MiB regions with up to this many zero pages whole (huge page mappable)
+        #[arg(long, value_name = "MAX_ZERO_PAGES")]
+        huge_page_align: Option<usize>,
+
+        /// Ignore the differences to the referenced files in this range (lossy): <start>-<end>
+        #[arg(long = "ignore", value_name = "RANGE", value_parser = parse_range)]
+        ignore: Vec<(u64, u64)>,
     },
 
     /// Fragment VMAs in the JIF, but still finding zero pages and ref segments
     Fragment {
         #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
         chroot_path: Option<std::path::PathBuf>,
+
+        /// Keep the 2MiB regions with up to this many zero pages whole (huge page mappable)
+        #[arg(long, value_name = "MAX_ZERO_PAGES")]
+        huge_page_align: Option<usize>,
+
+        /// Ignore the differences to the referenced files in this range (lossy): <start>-<end>
+        #[arg(long = "ignore", value_name = "RANGE", value_parser = parse_range)]
+        ignore: Vec<(u64, u64)>,
+    },
+
+    /// Merge the adjacent intervals with the same source, compacting the interval trees
+    ///
+    /// Merged data is stored whole, undoing page deduplication and prefetch setup
+    Optimize,
+
+    /// Keep only the private pages touched in the access logs (lossy), reporting the bytes removed
+    ///
+    /// The untouched private pages are demoted to zero pages (anonymous VMAs) or to the shared
+    /// pages of the referenced file (reference VMAs)
+    Minimize {
+        /// Filepaths of the timestamped access logs (defaults to `stdin`)
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        time_logs: Vec<std::path::PathBuf>,
     },
 
     /// Add an ordering section
     ///
-    /// Ingests a timestamped access log (each line of format `<usecs>: <address>`)
-    /// to construct the ordering list
+    /// Ingests a timestamped access log (each line of format `<usecs>: <address> [r|w [<tid>]]`)
+    /// to construct the ordering list. Page fault traces recorded with perf (`perf script` of the
+    /// `exceptions:page_fault_user` event) or ftrace can be ingested as they are (see `--format`)
     AddOrd {
-        /// Filepath of the timestamped access log (defaults to `stdin`)
+        /// Filepaths of the timestamped access logs, one per run (defaults to `stdin`)
         #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-        time_log: Option<std::path::PathBuf>,
+        time_logs: Vec<std::path::PathBuf>,
+
+        /// Format of the access logs
+        #[arg(long, value_enum, default_value_t = TraceFormat::Tracer)]
+        format: TraceFormat,
+
+        /// Keep only the page faults of this process (perf and ftrace traces)
+        #[arg(long, value_name = "PID")]
+        pid: Option<u32>,
+
+        /// How to merge the access logs of several runs
+        #[arg(long, value_enum, default_value_t = Merge::Earliest)]
+        merge: Merge,
 
-        // True if doing prefetch setup (breaking intervals per ord chunks).
+        /// Weight the ord chunks by the accesses to their pages, laying the hottest data out
+        /// first (the JIFs with weights are not read by the tools which predate them)
+        #[arg(long)]
+        weights: bool,
+
+        // True if doing prefetch setup (compacting the ord chunks and breaking intervals per ord chunks).
         #[arg(long)]
         setup_prefetch: bool,
 
@@ -88,63 +337,689 @@ enum Command {
         // fragment itrees into different vams
         #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, num_args = 0..=1, default_missing_value = None)]
         chroot: Option<std::path::PathBuf>,
+
+        /// Prune the accesses to unmapped pages (with a warning) instead of failing
+        #[arg(long)]
+        lenient: bool,
+    },
+
+    /// Remove the ordering section, laying the data out by address (without prefetching)
+    StripOrd,
+
+    /// Re-validate the ordering section against the pheaders, fixing or pruning the stale chunks
+    ///
+    /// The chunks are realigned to the page size, their unmapped pages are dropped and they are
+    /// split where the data source of their pages changes
+    RepairOrd,
+
+    /// Split the ord chunks at the logical interval boundaries (re-merging the consecutive ones),
+    /// reporting the number of chunks before and after
+    NormalizeOrd,
+
+    /// Drop the strings which no pheader references, reporting the reclaimed bytes
+    ///
+    /// Writing out a JIF only keeps the referenced strings, so every command garbage collects the
+    /// string table: this one just reports it
+    GcStrings,
+
+    /// Compress the data section (LZ4)
+    ///
+    /// Compressed JIFs are meant for storage and transfer: they cannot be mapped directly
+    Compress,
+
+    /// Decompress the data section
+    Decompress,
+
+    /// Lay the interval trees out with another fanout, reporting the number of nodes
+    ///
+    /// Wider nodes make for shallower trees. The fanout is recorded in the header: readers lay
+    /// the trees out anew with the default fanout when materializing
+    RebuildItrees {
+        /// Number of children of each node (a power of two from 4 to 512)
+        #[arg(long, default_value_t = 4)]
+        fanout: usize,
+    },
+
+    /// Make a delta JIF, which references the private pages found in a base JIF
+    ///
+    /// The base is needed to read the delta back (see `--base`)
+    MakeDelta {
+        /// Base JIF
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        base: std::path::PathBuf,
+    },
+
+    /// Split the JIF into a metadata file and a data blob (without writing a JIF)
+    ///
+    /// The metadata file is the JIF up to the data section, and the blob holds an index of the
+    /// data segments followed by their contents
+    Split {
+        /// Output metadata file path
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        metadata_file: std::path::PathBuf,
+
+        /// Output data blob path
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        data_file: std::path::PathBuf,
+    },
+
+    /// Join a split JIF: the input is the metadata file
+    Join {
+        /// Data blob path
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        data_file: std::path::PathBuf,
     },
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
-    let mut input_file =
-        BufReader::new(File::open(&args.input_file).context("failed to open input JIF")?);
-
-    let mut jif = Jif::from_reader(&mut input_file)?;
-
-    let mut reorder = false;
-    match args.command {
-        None => {}
-        Some(Command::Rename { old_path, new_path }) => jif.rename_file(&old_path, &new_path),
-        Some(Command::BuildItrees { chroot_path }) => jif
-            .build_itrees(chroot_path)
+/// Formats of the access logs
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum TraceFormat {
+    /// Timestamped accesses, as written by the tracer
+    Tracer,
+
+    /// Page faults, as printed by `perf script` or read from ftrace
+    Perf,
+}
+
+/// Policies to merge the access logs of several runs
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Merge {
+    /// Keep every page, ordered by its earliest access in any run
+    Earliest,
+
+    /// Keep every page, ordered by the number of runs accessing it
+    Frequency,
+
+    /// Keep the pages accessed in every run
+    Intersection,
+}
+
+/// Data segments to align in the output (see `--data-padding`)
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum DataPadding {
+    None,
+    Ordered,
+    All,
+}
+
+impl From<DataPadding> for SegmentPadding {
+    fn from(padding: DataPadding) -> Self {
+        match padding {
+            DataPadding::None => SegmentPadding::None,
+            DataPadding::Ordered => SegmentPadding::Ordered,
+            DataPadding::All => SegmentPadding::All,
+        }
+    }
+}
+
+impl From<Merge> for MergePolicy {
+    fn from(merge: Merge) -> Self {
+        match merge {
+            Merge::Earliest => MergePolicy::Earliest,
+            Merge::Frequency => MergePolicy::Frequency,
+            Merge::Intersection => MergePolicy::Intersection,
+        }
+    }
+}
+
+/// Parse a hexadecimal address
+fn parse_addr(addr: &str) -> anyhow::Result<u64> {
+    u64::from_str_radix(addr.trim().trim_start_matches("0x"), 16)
+        .context(format!("failed to parse address {}", addr))
+}
+
+/// Parse a size: hexadecimal (`0x` prefixed), or decimal with an optional K, M or G suffix
+fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).context(format!("failed to parse size {}", s));
+    }
+
+    let (digits, unit) = match s.as_bytes().last() {
+        Some(b'K' | b'k') => (&s[..s.len() - 1], 1 << 10),
+        Some(b'M' | b'm') => (&s[..s.len() - 1], 1 << 20),
+        Some(b'G' | b'g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(unit))
+        .ok_or_else(|| anyhow::anyhow!("failed to parse size {}", s))
+}
+
+/// Parse a `<start>-<end>` hexadecimal address range
+fn parse_range(s: &str) -> anyhow::Result<(u64, u64)> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| anyhow::anyhow!("expected a range as <start>-<end>: {}", s))?;
+    let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+    if start >= end {
+        return Err(anyhow::anyhow!("empty range: {}", s));
+    }
+
+    Ok((start, end))
+}
+
+/// Parse a `<old>=<new>` path prefix mapping
+fn parse_path_map(s: &str) -> anyhow::Result<(std::path::PathBuf, std::path::PathBuf)> {
+    let (old, new) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected a path mapping as <old>=<new>: {}", s))?;
+    Ok((old.into(), new.into()))
+}
+
+/// Parse a `<key>=<value>` metadata pair
+fn parse_meta(s: &str) -> anyhow::Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("expected metadata as <key>=<value>: {}", s))?;
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("empty metadata key: {}", s));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse `rwx` style protections (`-` is ignored)
+fn parse_prot(s: &str) -> anyhow::Result<ProtFlags> {
+    s.chars().try_fold(ProtFlags::empty(), |prot, c| match c {
+        'r' => Ok(prot | ProtFlags::READ),
+        'w' => Ok(prot | ProtFlags::WRITE),
+        'x' => Ok(prot | ProtFlags::EXEC),
+        '-' => Ok(prot),
+        c => Err(anyhow::anyhow!("unknown protection `{}` in {}", c, s)),
+    })
+}
+
+/// Offset between two addresses
+fn delta(from: u64, to: u64) -> anyhow::Result<i64> {
+    i64::try_from(to as i128 - from as i128)
+        .map_err(|_| anyhow::anyhow!("cannot move {:#x} to {:#x}", from, to))
+}
+
+/// Read the timestamped access logs (or `stdin`, if there are none), one trace per log
+fn read_traces(time_logs: &[std::path::PathBuf]) -> anyhow::Result<Vec<Vec<TimestampedAccess>>> {
+    read_traces_as(time_logs, TraceFormat::Tracer, None)
+}
+
+/// Read the access logs (or `stdin`, if there are none) in a format, one trace per log
+fn read_traces_as(
+    time_logs: &[std::path::PathBuf],
+    format: TraceFormat,
+    pid: Option<u32>,
+) -> anyhow::Result<Vec<Vec<TimestampedAccess>>> {
+    let read = |reader: &mut dyn BufRead| match format {
+        TraceFormat::Tracer => read_trace(reader),
+        TraceFormat::Perf => read_perf_trace(reader, pid),
+    };
+    if pid.is_some() && format == TraceFormat::Tracer {
+        anyhow::bail!("`--pid` only applies to the perf and ftrace traces");
+    }
+
+    if time_logs.is_empty() {
+        let stdin = std::io::stdin();
+        return Ok(vec![
+            read(&mut stdin.lock()).context("failed to read trace")?
+        ]);
+    }
+
+    time_logs
+        .iter()
+        .map(|fname| {
+            let mut file = BufReader::new(File::open(fname).context("failed to open ord list")?);
+            read(&mut file).with_context(|| format!("failed to read trace {}", fname.display()))
+        })
+        .collect()
+}
+
+/// Warn about the problems found when repairing the ordering section, and summarize the repair
+fn report_ord_repair(stats: &OrdRepairStats) {
+    for (ord_chunk_idx, finding) in &stats.findings {
+        eprintln!("WARN: ord chunk {}: {}", ord_chunk_idx, finding);
+    }
+    eprintln!(
+        "repaired the ordering section: {} chunks into {} (dropped {} unmapped pages, retagged {})",
+        stats.chunks_before, stats.chunks_after, stats.dropped_pages, stats.retagged_pages
+    );
+}
+
+/// Read a raw JIF from a file
+fn read_raw(path: &std::path::Path) -> anyhow::Result<JifRaw> {
+    let mut file = BufReader::new(File::open(path).context("failed to open JIF")?);
+    JifRaw::from_reader(&mut file).context("failed to read JIF")
+}
+
+/// Open the referenced files under the path prefix mappings (see `--path-map`) and a root
+fn paths(
+    path_map: &[(std::path::PathBuf, std::path::PathBuf)],
+    chroot: Option<std::path::PathBuf>,
+) -> PathMap {
+    PathMap::new(path_map.to_vec()).with_chroot(chroot)
+}
+
+/// Apply a command which edits the JIF in memory, handing back the other commands (which report
+/// on the JIF or change how it is written out)
+fn apply_edit(
+    jif: &mut Jif,
+    command: Command,
+    path_map: &[(std::path::PathBuf, std::path::PathBuf)],
+) -> anyhow::Result<Option<Command>> {
+    match command {
+        Command::Rename { old_path, new_path } => jif.rename_file(&old_path, &new_path),
+        Command::DropVma { range } => {
+            if jif.drop_pheaders(range) == 0 {
+                eprintln!("WARN: no VMA overlaps [{:#x}; {:#x})", range.0, range.1);
+            }
+        }
+        Command::Rebase { old_base, new_base } => jif
+            .rebase(delta(old_base, new_base)?)
+            .context("failed to rebase the JIF")?,
+        Command::Move { range, new_start } => {
+            let n_moved = jif
+                .move_pheaders(range, delta(range.0, new_start)?)
+                .context("failed to move the VMAs")?;
+            if n_moved == 0 {
+                eprintln!("WARN: no VMA inside [{:#x}; {:#x})", range.0, range.1);
+            }
+        }
+        Command::SetProt { range, prot } => {
+            let n_changed = jif
+                .set_prot(range, prot)
+                .context("failed to set the protections")?;
+            if n_changed == 0 {
+                eprintln!("WARN: no VMA overlaps [{:#x}; {:#x})", range.0, range.1);
+            }
+        }
+        Command::SetMeta { pairs } => {
+            for (key, value) in pairs {
+                if !value.is_empty() {
+                    jif.set_metadata(&key, &value);
+                } else if jif.remove_metadata(&key).is_none() {
+                    eprintln!("WARN: no metadata key `{}`", key);
+                }
+            }
+        }
+        Command::SplitVmas { max_size } => {
+            let n_added = jif
+                .split_large_pheaders(max_size)
+                .context("failed to split the VMAs")?;
+            eprintln!(
+                "split the VMAs larger than {:#x} B: {} more VMAs",
+                max_size, n_added
+            );
+        }
+        Command::Merge { other } => {
+            let other = Jif::from_raw(read_raw(&other).context("failed to read JIF to merge")?)?;
+            jif.merge(other).context("failed to merge JIFs")?
+        }
+        Command::BuildItrees {
+            chroot_path,
+            huge_page_align,
+            ignore,
+        } => jif
+            .build_itrees_with(
+                &paths(path_map, chroot_path),
+                huge_page_align,
+                &IgnoreRanges::new(ignore),
+            )
             .context("failed to build ITrees")?,
-        Some(Command::Fragment { chroot_path }) => jif
-            .fragment(chroot_path)
+        Command::Fragment {
+            chroot_path,
+            huge_page_align,
+            ignore,
+        } => jif
+            .fragment_with(
+                &paths(path_map, chroot_path),
+                huge_page_align,
+                &IgnoreRanges::new(ignore),
+            )
             .context("failed to fragment vmas")?,
-        Some(Command::AddOrd {
-            time_log,
-            setup_prefetch,
+        Command::Optimize => {
+            let stats = jif.coalesce().context("failed to coalesce intervals")?;
+            eprintln!(
+                "coalesced {} intervals into {}: {} itree nodes instead of {} (saved {})",
+                stats.intervals_before,
+                stats.intervals_after,
+                stats.nodes_after,
+                stats.nodes_before,
+                stats.saved_nodes()
+            );
+        }
+        Command::Minimize { time_logs } => {
+            let traces = read_traces(&time_logs)?;
+            let stats = jif
+                .minimize(traces.iter().flatten().map(|tsa| tsa.addr as u64))
+                .context("failed to minimize the JIF")?;
+            eprintln!(
+                "kept {} private pages: demoted {} to zero pages and {} to shared pages (removed {:#x} B of private data)",
+                stats.kept_pages, stats.zeroed_pages, stats.shared_pages, stats.removed_bytes
+            );
+        }
+        Command::StripOrd => {
+            if jif.clear_ordering() == 0 {
+                eprintln!("WARN: the JIF has no ordering section");
+            }
+        }
+        Command::RepairOrd => {
+            if jif.ord_chunks().is_empty() {
+                eprintln!("WARN: the JIF has no ordering section");
+            }
+            report_ord_repair(&jif.repair_ord_chunks());
+        }
+        Command::NormalizeOrd => {
+            let stats = jif.normalize_ordering();
+            eprintln!(
+                "normalized the ordering section: {} chunks into {} ({} split across intervals)",
+                stats.chunks_before, stats.chunks_after, stats.split_chunks
+            );
+        }
+        Command::AddOrd {
+            time_logs,
+            format,
+            pid,
+            merge,
+            weights,
+            setup_prefetch: _,
             fragment,
             chroot,
-        }) => {
-            let tsa_log = match time_log {
-                Some(fname) => {
-                    let file =
-                        BufReader::new(File::open(fname).context("failed to open ord list")?);
-                    read_trace(file).context("failed to read trace")?
-                }
-                None => {
-                    let stdin = std::io::stdin();
-                    read_trace(stdin.lock()).context("failed to read trace")?
-                }
-            };
-
-            let tsa_log = dedup_and_sort(tsa_log);
-            let ords = construct_ord_chunks(&jif, tsa_log);
-            reorder = setup_prefetch;
+            lenient,
+        } => {
+            let traces = read_traces_as(&time_logs, format, pid)?;
+            let counts = weights.then(|| access_counts(&traces));
+            let tsa_log = merge_traces(traces, merge.into());
+            let ords = construct_ord_chunks(jif, tsa_log, counts.as_ref());
 
-            jif.add_ordering_info(ords)?;
+            if lenient {
+                report_ord_repair(&jif.add_ordering_info_lenient(ords));
+            } else {
+                jif.add_ordering_info(ords)?;
+            }
             if fragment {
-                jif.fragment(chroot)?;
+                jif.fragment(&paths(path_map, chroot), None)?;
             }
         }
+        command => return Ok(Some(command)),
     }
 
-    let mut output_file =
-        BufWriter::new(File::create(&args.output_file).context("failed to open output JIF")?);
-    let raw = JifRaw::from_materialized(jif, reorder);
+    Ok(None)
+}
+
+/// Check that the referenced files can back the JIF, reporting the pheaders which cannot be
+/// restored
+fn audit_refs(jif: &Jif, paths: &dyn PathResolver) -> anyhow::Result<()> {
+    let findings = jif.audit_refs(paths);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let n_refs = jif
+        .pheaders()
+        .iter()
+        .filter(|p| p.pathname().is_some())
+        .count();
+    println!(
+        "{} of {} reference pheaders cannot be restored",
+        findings.len(),
+        n_refs
+    );
+    if !findings.is_empty() {
+        anyhow::bail!("referenced files failed the audit");
+    }
+    Ok(())
+}
+
+/// Apply the options which edit the JIF before it is written out (`--canonicalize-paths` and
+/// `--dedup-pages`)
+fn prepare_output(jif: &mut Jif, args: &Cli) -> anyhow::Result<()> {
+    if let Some(root) = &args.canonicalize_paths {
+        let n_renamed =
+            jif.canonicalize_paths(&paths(&args.path_map, root.clone()), root.as_deref());
+        eprintln!("canonicalized {} referenced paths", n_renamed);
+    }
+
+    if args.dedup_pages {
+        let stats = jif.dedup_pages().context("failed to deduplicate pages")?;
+        eprintln!(
+            "deduplicated {} pages: {:#x} B stored instead of {:#x} B (saved {:#x} B)",
+            stats.duplicate_pages,
+            stats.stored_bytes_after,
+            stats.stored_bytes_before,
+            stats.saved_bytes()
+        );
+    }
+
+    Ok(())
+}
+
+/// Layout of the data section of the output (see `--data-padding`)
+fn data_layout(args: &Cli) -> anyhow::Result<DataLayout> {
+    let mut layout = DataLayout::new(args.data_alignment, args.data_padding.into())
+        .context("bad data layout")?;
+    if let Some(max_gap) = args.max_data_gap {
+        layout = layout.with_max_gap(max_gap);
+    }
+    Ok(layout)
+}
+
+/// Write a raw JIF out to a file (as a sparse file, with `sparse`)
+fn write_raw(raw: &JifRaw, output_file: &File, sparse: bool) -> anyhow::Result<()> {
+    if sparse {
+        raw.to_writer_sparse(&mut BufWriter::new(output_file))
+            .context("failed to write JIF")?;
+        eprintln!(
+            "wrote sparse JIF: {}",
+            FileSize::from_file(output_file).context("failed to look up the output size")?
+        );
+    } else {
+        raw.to_file(output_file).context("failed to write JIF")?;
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = Cli::parse();
+    if args.interactive && args.command.is_some() {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "the commands are read from stdin with --interactive",
+            )
+            .exit();
+    }
+    let mut raw = match &args.command {
+        Some(Command::Join { data_file }) => {
+            let mut metadata = BufReader::new(
+                File::open(&args.input_file).context("failed to open metadata file")?,
+            );
+            let mut data =
+                BufReader::new(File::open(data_file).context("failed to open data blob")?);
+            JifRaw::join(&mut metadata, &mut data).context("failed to join the split JIF")?
+        }
+        _ => read_raw(&args.input_file).context("failed to read input JIF")?,
+    };
+    if let Some(base) = &args.base {
+        raw.resolve_base(&read_raw(base).context("failed to read base JIF")?)
+            .context("failed to resolve the input against the base JIF")?;
+    }
+
+    if let Some(Command::Validate) = args.command {
+        let mut report = raw.validate();
+        report.extend(Jif::from_raw(raw)?.validate());
+        println!("{}", report);
+        if !report.is_valid() {
+            anyhow::bail!("JIF failed validation");
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Split {
+        metadata_file,
+        data_file,
+    }) = &args.command
+    {
+        let mut metadata = BufWriter::new(
+            File::create(metadata_file).context("failed to open output metadata file")?,
+        );
+        let mut data =
+            BufWriter::new(File::create(data_file).context("failed to open output data blob")?);
+        raw.split(&mut metadata, &mut data)
+            .context("failed to split the JIF")?;
+        metadata
+            .flush()
+            .context("failed to write the metadata file")?;
+        data.flush().context("failed to write the data blob")?;
+        return Ok(());
+    }
+
+    // (number of strings, size of the string table) of the input
+    let input_strings = (
+        raw.strings().iter().filter(|s| !s.is_empty()).count(),
+        raw.strings_size(),
+    );
+    let mut jif = Jif::from_raw(raw)?;
+    if let Some(Command::DedupStats) = args.command {
+        print!("{}", jif.dedup_stats());
+        return Ok(());
+    }
+    if let Some(Command::AuditRefs { chroot }) = &args.command {
+        return audit_refs(&jif, &paths(&args.path_map, chroot.clone()));
+    }
+    if args.interactive {
+        return repl::run(jif, &args);
+    }
+
+    let reorder = matches!(
+        args.command,
+        Some(Command::AddOrd {
+            setup_prefetch: true,
+            ..
+        })
+    );
+    // the edits which drop or split intervals can leave data unreferenced: it is collected (and
+    // reported) once they are done, the others only touch the headers
+    let orphans_data = matches!(
+        args.command,
+        Some(
+            Command::DropVma { .. }
+                | Command::ExtractVma { .. }
+                | Command::SetProt { .. }
+                | Command::SplitVmas { .. }
+                | Command::BuildItrees { .. }
+                | Command::Fragment { .. }
+                | Command::Optimize
+                | Command::Minimize { .. }
+        )
+    );
+    let command = match args.command.take() {
+        Some(command) => apply_edit(&mut jif, command, &args.path_map)?,
+        None => None,
+    };
+    let mut gc_strings = false;
+    let mut compression = Compression::None;
+    let mut delta_base = None;
+    let mut itree_fanout = None;
+    let mut output_file = args.output_file.clone();
+    match command {
+        None | Some(Command::Decompress) | Some(Command::Join { .. }) => {}
+        Some(Command::Split { .. }) => unreachable!("splitting does not modify the JIF"),
+        Some(Command::Validate) => unreachable!("validation does not modify the JIF"),
+        Some(Command::DedupStats) => unreachable!("dedup statistics do not modify the JIF"),
+        Some(Command::AuditRefs { .. }) => unreachable!("auditing does not modify the JIF"),
+        Some(Command::Compress) => compression = Compression::Lz4,
+        Some(Command::RebuildItrees { fanout }) => itree_fanout = Some(fanout),
+        Some(Command::GcStrings) => gc_strings = true,
+        Some(Command::MakeDelta { base }) => {
+            delta_base = Some(read_raw(&base).context("failed to read base JIF")?)
+        }
+        Some(Command::ExtractVma {
+            range,
+            output_file: vma_file,
+        }) => {
+            if output_file.is_some() {
+                anyhow::bail!("extract-vma takes the output JIF after the range");
+            }
+            if jif.keep_pheaders(range) == 0 {
+                anyhow::bail!("no VMA overlaps [{:#x}; {:#x})", range.0, range.1);
+            }
+            output_file = Some(vma_file);
+        }
+        Some(Command::Extract {
+            range: (start, end),
+            output_file,
+            chroot,
+        }) => {
+            let data = jif
+                .read_range(start, end - start, &paths(&args.path_map, chroot))
+                .context("failed to read the address range")?;
+            std::fs::write(&output_file, data).context("failed to write the extracted data")?;
+            return Ok(());
+        }
+        Some(_) => unreachable!("the edits are applied above"),
+    }
+    if orphans_data {
+        let reclaimed = jif.gc();
+        if reclaimed > 0 {
+            eprintln!("dropped {:#x} B of unreferenced data", reclaimed);
+        }
+    }
+
+    prepare_output(&mut jif, &args)?;
+
+    let layout = data_layout(&args)?;
+    let output_file = output_file.ok_or_else(|| anyhow::anyhow!("missing output JIF file path"))?;
+    let output_file = File::create(output_file).context("failed to open output JIF")?;
+    let mut raw = match delta_base {
+        Some(base) => {
+            if layout.padding() != SegmentPadding::None {
+                eprintln!("WARN: the data segments of a delta JIF are packed");
+            }
+            JifRaw::make_delta(jif, &base).context("failed to make delta JIF")?
+        }
+        None => {
+            let n_chunks = jif.ord_chunks().len();
+            let raw = JifRaw::from_materialized_with_gc(jif, reorder, layout, false);
+            if raw.ord_chunks().len() < n_chunks {
+                eprintln!(
+                    "compacted the ordering section: {} chunks into {}",
+                    n_chunks,
+                    raw.ord_chunks().len()
+                );
+            }
+            raw
+        }
+    };
+    if raw.data_layout().padding() != SegmentPadding::None {
+        eprintln!(
+            "laid out the data section: {}, with {:#x} B of padding",
+            layout,
+            raw.layout().padding()
+        );
+    }
+    raw.set_compression(compression);
+    raw.set_checksums(args.checksums);
+    raw.set_page_hash_index(args.page_index);
+    if let Some(fanout) = itree_fanout {
+        let n_nodes = raw.itree_nodes().len();
+        raw.set_itree_fanout(fanout)
+            .context("failed to rebuild the interval trees")?;
+        eprintln!(
+            "rebuilt the interval trees with fanout {}: {} nodes instead of {}",
+            fanout,
+            raw.itree_nodes().len(),
+            n_nodes
+        );
+    }
+    if gc_strings {
+        let (n_strings, strings_size) = input_strings;
+        eprintln!(
+            "dropped {} unreferenced strings: reclaimed {:#x} B of the string table",
+            n_strings.saturating_sub(raw.strings().iter().filter(|s| !s.is_empty()).count()),
+            strings_size.saturating_sub(raw.strings_size())
+        );
+    }
 
     if args.show {
         println!("{:#x?}", raw);
     }
-    raw.to_writer(&mut output_file)
-        .context("failed to write JIF")?;
-    Ok(())
+    write_raw(&raw, &output_file, args.sparse)
 }
@@ -0,0 +1,291 @@
+//! Interactive mode
+//!
+//! `jiftool --interactive a.jif` reads the JIF once and then applies the commands read from
+//! `stdin` (one per line, with the same syntax as the command line) to the JIF in memory. Every
+//! edit is applied to a copy-on-write clone of the JIF (see [`Jif::clone_cow`]): a failed edit
+//! leaves the JIF as it was, and the JIF before each edit is kept around to `undo` it
+
+use crate::*;
+
+use clap::{Parser, Subcommand};
+use std::io::{BufRead, IsTerminal};
+
+/// Commands of the interactive mode, one per line
+#[derive(Parser)]
+#[command(no_binary_name = true, disable_version_flag = true)]
+struct Line {
+    #[command(subcommand)]
+    command: LineCommand,
+}
+
+#[derive(Subcommand)]
+enum LineCommand {
+    #[command(flatten)]
+    Jiftool(Command),
+
+    /// Write the JIF out (with the output options of the command line, e.g. `--sparse`)
+    Write {
+        /// Output JIF file path
+        #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        output_file: std::path::PathBuf,
+
+        /// Set up the prefetch (breaking the intervals per ord chunks), as `add-ord
+        /// --setup-prefetch` does
+        #[arg(long)]
+        setup_prefetch: bool,
+    },
+
+    /// Undo the last edit
+    Undo,
+
+    /// List the edits applied so far
+    History,
+
+    /// Print the JIF, or the pheaders overlapping an address range
+    Show {
+        /// Virtual address range, as `<start>-<end>` (hexadecimal)
+        #[arg(value_name = "RANGE", value_parser = parse_range)]
+        range: Option<(u64, u64)>,
+    },
+
+    /// Print the number of pheaders, pages (by kind) and ord chunks
+    Summary,
+
+    /// Print the referenced files, with the pheaders referencing them
+    Strings,
+
+    /// Resolve a virtual address to its pheader and interval
+    Resolve {
+        /// Virtual address (hexadecimal)
+        #[arg(value_name = "ADDR", value_parser = parse_addr)]
+        addr: u64,
+    },
+
+    /// Leave the interactive mode (as does the end of the input)
+    #[command(alias = "exit")]
+    Quit,
+}
+
+/// An edit, with the JIF it was applied to
+struct Edit {
+    line: String,
+    jif: Jif,
+    reorder: bool,
+}
+
+/// State of the interactive mode
+struct Session<'a> {
+    args: &'a Cli,
+    jif: Jif,
+
+    /// Whether to set up the prefetch when writing out (see `add-ord --setup-prefetch`)
+    reorder: bool,
+
+    history: Vec<Edit>,
+
+    /// Number of edits in the history when the JIF was last written out
+    written: Option<usize>,
+}
+
+/// Split a line into words, at the whitespace outside of quotes
+fn split_words(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        anyhow::bail!("unterminated quote");
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+impl Session<'_> {
+    /// Run a line, returning whether to go on
+    fn run_line(&mut self, line: &str) -> anyhow::Result<bool> {
+        let words = split_words(line)?;
+        if words.is_empty() {
+            return Ok(true);
+        }
+
+        let command = match Line::try_parse_from(&words) {
+            Ok(line) => line.command,
+            Err(err) => {
+                // help is reported as an error as well
+                err.print()?;
+                return Ok(true);
+            }
+        };
+
+        match command {
+            LineCommand::Jiftool(command) => self.run_command(line, &words[0], command)?,
+            LineCommand::Write {
+                output_file,
+                setup_prefetch,
+            } => self.write(&output_file, self.reorder || setup_prefetch)?,
+            LineCommand::Undo => match self.history.pop() {
+                Some(edit) => {
+                    eprintln!("undid `{}`", edit.line);
+                    self.jif = edit.jif;
+                    self.reorder = edit.reorder;
+                }
+                None => eprintln!("WARN: nothing to undo"),
+            },
+            LineCommand::History => {
+                for (idx, edit) in self.history.iter().enumerate() {
+                    println!("{}: {}", idx + 1, edit.line);
+                }
+            }
+            LineCommand::Show { range: None } => println!("{:#x?}", self.jif),
+            LineCommand::Show {
+                range: Some((start, end)),
+            } => {
+                for pheader in self.jif.pheaders().iter().filter(|pheader| {
+                    let (pheader_start, pheader_end) = pheader.virtual_range();
+                    pheader_start < end && start < pheader_end
+                }) {
+                    println!("{:#x?}", pheader);
+                }
+            }
+            LineCommand::Summary => {
+                println!("pheaders:      {}", self.jif.pheaders().len());
+                println!("zero pages:    {}", self.jif.zero_pages());
+                println!("private pages: {}", self.jif.private_pages());
+                println!("shared pages:  {}", self.jif.shared_pages());
+                println!("total pages:   {}", self.jif.total_pages());
+                println!("ord chunks:    {}", self.jif.ord_chunks().len());
+            }
+            LineCommand::Strings => {
+                for (path, pheaders) in self.jif.path_usage() {
+                    println!("{}: {:?}", path, pheaders);
+                }
+            }
+            LineCommand::Resolve { addr } => match self.jif.resolve(addr) {
+                Some(interval) => {
+                    let pheader = self
+                        .jif
+                        .mapping_pheader(addr)
+                        .expect("a resolved address is mapped");
+                    let (start, end) = pheader.virtual_range();
+                    println!(
+                        "{:#x}: pheader [{:#x}; {:#x}), interval [{:#x}; {:#x}) ({:?})",
+                        addr, start, end, interval.start, interval.end, interval.source
+                    );
+                }
+                None => println!("{:#x}: not mapped", addr),
+            },
+            LineCommand::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Run a jiftool command: the edits are applied to a clone of the JIF, and the reports are
+    /// printed
+    fn run_command(&mut self, line: &str, name: &str, command: Command) -> anyhow::Result<()> {
+        let paths = |chroot: Option<std::path::PathBuf>| paths(&self.args.path_map, chroot);
+        match command {
+            Command::Validate => {
+                let report = self.jif.validate();
+                println!("{}", report);
+                if !report.is_valid() {
+                    eprintln!("WARN: JIF failed validation");
+                }
+            }
+            Command::DedupStats => print!("{}", self.jif.dedup_stats()),
+            Command::AuditRefs { chroot } => audit_refs(&self.jif, &paths(chroot))?,
+            Command::Extract {
+                range: (start, end),
+                output_file,
+                chroot,
+            } => {
+                let data = self
+                    .jif
+                    .read_range(start, end - start, &paths(chroot))
+                    .context("failed to read the address range")?;
+                std::fs::write(&output_file, data).context("failed to write the extracted data")?;
+            }
+            command => {
+                let reorder = self.reorder
+                    || matches!(
+                        command,
+                        Command::AddOrd {
+                            setup_prefetch: true,
+                            ..
+                        }
+                    );
+                let mut jif = self.jif.clone_cow();
+                if apply_edit(&mut jif, command, &self.args.path_map)?.is_some() {
+                    anyhow::bail!("`{}` is not available in the interactive mode", name);
+                }
+                self.history.push(Edit {
+                    line: line.trim().to_string(),
+                    jif: std::mem::replace(&mut self.jif, jif),
+                    reorder: std::mem::replace(&mut self.reorder, reorder),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write (a clone of) the JIF out
+    fn write(&mut self, output_file: &std::path::Path, reorder: bool) -> anyhow::Result<()> {
+        let mut jif = self.jif.clone_cow();
+        prepare_output(&mut jif, self.args)?;
+        let mut raw = JifRaw::from_materialized_with_layout(jif, reorder, data_layout(self.args)?);
+        raw.set_checksums(self.args.checksums);
+        raw.set_page_hash_index(self.args.page_index);
+        let output_file = File::create(output_file).context("failed to open output JIF")?;
+        write_raw(&raw, &output_file, self.args.sparse)?;
+        self.written = Some(self.history.len());
+
+        Ok(())
+    }
+}
+
+/// Run the interactive mode over a JIF
+pub(crate) fn run(jif: Jif, args: &Cli) -> anyhow::Result<()> {
+    let mut session = Session {
+        args,
+        jif,
+        reorder: false,
+        history: Vec::new(),
+        written: None,
+    };
+
+    let stdin = std::io::stdin();
+    let prompt = stdin.is_terminal();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if prompt {
+            eprint!("jiftool> ");
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line.context("failed to read the command")?;
+        match session.run_line(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => eprintln!("error: {:#}", err),
+        }
+    }
+
+    if !session.history.is_empty() && session.written != Some(session.history.len()) {
+        eprintln!("WARN: the last edits were not written out");
+    }
+    Ok(())
+}
@@ -1,25 +1,59 @@
 use jif::itree::interval::DataSource;
-use jif::ord::OrdChunk;
+use jif::ord::{AccessKind, OrdChunk};
 use jif::Jif;
 use tracer_format::TimestampedAccess;
 
+use std::collections::HashMap;
+
 /// construct the ord chunks from the timestamped log
-pub(crate) fn construct_ord_chunks(jif: &Jif, log: Vec<TimestampedAccess>) -> Vec<OrdChunk> {
+///
+/// Each chunk records the thread of the access which started it, and whether any of the accesses
+/// merged into it was a write. With the access counts of the pages, each chunk is weighted by the
+/// accesses to its pages
+pub(crate) fn construct_ord_chunks(
+    jif: &Jif,
+    log: Vec<TimestampedAccess>,
+    counts: Option<&HashMap<usize, usize>>,
+) -> Vec<OrdChunk> {
     let mut chunk = OrdChunk::new(0, 0, DataSource::Zero);
     let mut chunks = Vec::with_capacity(log.len());
     for tsa in log {
+        let access = tsa.kind.map(|kind| match kind {
+            tracer_format::AccessKind::Read => AccessKind::Read,
+            tracer_format::AccessKind::Write => AccessKind::Write,
+        });
+        let weight = counts.map(|counts| counts.get(&tsa.addr).copied().unwrap_or(1) as u32);
+
         // check if we can merge (empty chunk is always mergeable)
-        if !chunk.merge_page(jif, tsa.addr as u64) {
+        let was_empty = chunk.is_empty();
+        if chunk.merge_page(jif, tsa.addr as u64) {
+            if was_empty {
+                if let Some(iv) = jif.resolve(tsa.addr as u64) {
+                    chunk.set_kind(iv.source);
+                }
+                chunk = chunk.with_provenance(tsa.tid, access);
+            } else {
+                chunk.record_access(access);
+            }
+            if let Some(weight) = weight {
+                chunk.add_weight(weight);
+            }
+        } else {
             // we couldn't merge, push the chunk
             chunks.push(chunk);
 
             let iv = jif.resolve(tsa.addr as u64);
             if iv.is_none() {
                 println!("Warning: unresolved address in ordering data: {}", tsa.addr);
+                chunk = OrdChunk::new(0, 0, DataSource::Zero);
                 continue;
             }
 
-            chunk = OrdChunk::new(tsa.addr as u64, 1 /* n pages */, iv.unwrap().source);
+            chunk = OrdChunk::new(tsa.addr as u64, 1 /* n pages */, iv.unwrap().source)
+                .with_provenance(tsa.tid, access);
+            if let Some(weight) = weight {
+                chunk.add_weight(weight);
+            }
         }
     }
 
@@ -0,0 +1,131 @@
+//! # `loadjif`
+//!
+//! A tool to load a JIF into its own address space, as a loader restoring it would (see the
+//! `loader` module of the `jif` library), to check the JIFs the tools produce without a full
+//! junction runtime
+//!
+//! Example usage:
+//! ```sh
+//! $ loadjif a.jif # loads the jif, checks the loaded memory against it and unloads it
+//! $ loadjif --plan a.jif # also prints the restore plan the loader follows
+//! $ loadjif --chroot /srv/rootfs a.jif # maps the referenced files from elsewhere
+//! $ loadjif --exec-entry 0x7f0000001000 code.jif # calls a function of the loaded jif
+//! ```
+//!
+//! A JIF holds no register state: the function called by `--exec-entry` gets no arguments and
+//! has to be self contained (e.g., position independent code written into the JIF)
+
+use jif::*;
+
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::Context;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// loadjif: load a JIF into the current process
+///
+/// Maps the pheaders at their addresses (failing if they overlap the mappings of loadjif itself:
+/// see `jiftool rebase`), copies the private data in and zero fills the zero intervals of the
+/// reference pheaders. The loaded memory is then checked against the JIF
+struct Cli {
+    /// Input JIF file path
+    #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    input_file: std::path::PathBuf,
+
+    /// Directory the referenced files are relative to
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    chroot: Option<std::path::PathBuf>,
+
+    /// Print the restore plan (one operation per line) before loading
+    #[arg(long)]
+    plan: bool,
+
+    /// Do not check the loaded memory against the JIF
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Call the function at this address (hexadecimal) once loaded, printing what it returns
+    #[arg(long, value_name = "ADDR", value_parser = parse_addr)]
+    exec_entry: Option<u64>,
+}
+
+/// Parse a hexadecimal address (with or without the `0x` prefix)
+fn parse_addr(s: &str) -> anyhow::Result<u64> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u64::from_str_radix(digits, 16).with_context(|| format!("failed to parse address {}", s))
+}
+
+/// Compare the loaded memory of the readable pheaders with their contents in the JIF, returning
+/// the number of pheaders checked
+fn verify(jif: &Jif, loaded: &LoadedJif, paths: &dyn PathResolver) -> anyhow::Result<usize> {
+    let mut n_checked = 0;
+    for pheader in jif
+        .pheaders()
+        .iter()
+        .filter(|pheader| pheader.prot().contains(ProtFlags::READ))
+    {
+        let (start, end) = pheader.virtual_range();
+        let expected = jif
+            .read_range(start, end - start, paths)
+            .with_context(|| format!("failed to read [{:#x}; {:#x}) from the JIF", start, end))?;
+        let found = loaded
+            .read(start, end - start)
+            .with_context(|| format!("[{:#x}; {:#x}) is not loaded", start, end))?;
+        if let Some(idx) = expected.iter().zip(found).position(|(e, f)| e != f) {
+            anyhow::bail!(
+                "loaded memory differs from the JIF at {:#x} (pheader [{:#x}; {:#x}))",
+                start + idx as u64,
+                start,
+                end
+            );
+        }
+        n_checked += 1;
+    }
+
+    Ok(n_checked)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Cli::parse();
+
+    let mut file =
+        BufReader::new(File::open(&args.input_file).context("failed to open input JIF")?);
+    let jif = Jif::from_reader(&mut file).context("failed to read the JIF")?;
+    if args.plan {
+        for op in jif.restore_plan() {
+            println!("{}", op);
+        }
+    }
+
+    let loaded = jif.load(&args.chroot).context("failed to load the JIF")?;
+    eprintln!(
+        "loaded {} pheaders ({} mappings)",
+        jif.pheaders().len(),
+        loaded.mappings().len()
+    );
+
+    if !args.no_verify {
+        let n_checked = verify(&jif, &loaded, &args.chroot)?;
+        eprintln!(
+            "the loaded memory of {} pheaders matches the JIF",
+            n_checked
+        );
+    }
+
+    if let Some(entry) = args.exec_entry {
+        if !loaded.mappings().iter().any(|((start, end), prot)| {
+            (*start..*end).contains(&entry) && prot.contains(ProtFlags::EXEC)
+        }) {
+            anyhow::bail!("{:#x} is not in an executable mapping of the JIF", entry);
+        }
+
+        // SAFETY: the entry is in executable memory of the JIF, which the user vouches for
+        let entry: extern "C" fn() -> u64 = unsafe { std::mem::transmute(entry as usize) };
+        println!("{:#x} returned {:#x}", entry as usize, entry());
+    }
+
+    Ok(())
+}
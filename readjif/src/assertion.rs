@@ -0,0 +1,219 @@
+//! Assertions over the numeric selectors
+//!
+//! `--assert '<selector> <op> <threshold>'` evaluates a selector which yields a number (e.g.,
+//! `jif.private_pages` or `pheader[prot=rw].private_pages`, summed over the selected pheaders) and
+//! compares it against the threshold, so that CI can gate on the size and shape of a snapshot
+//! from the exit code alone
+
+use crate::filter::{CmpOp, FieldValue, PheaderField, PheaderFields};
+use crate::selectors::*;
+use crate::utils::parse_int;
+
+use jif::itree::interval::DataSource;
+use jif::ord::AccessKind;
+use jif::Jif;
+
+/// Exit code when an assertion does not hold (1 is taken by the errors, 2 by the usage errors)
+pub(crate) const ASSERTION_FAILED: u8 = 3;
+
+pub(crate) const ASSERTION_USAGE: &str = "assertions: <selector> <op> <threshold>
+
+with the operators =, !=, <, <=, >, >= and an integer threshold (decimal, or hexadecimal with 0x),
+over the selectors which yield a number:
+  jif.zero_pages, jif.private_pages, jif.shared_pages, jif.pages
+  ord.len, ord.size, ord.private_pages, ord.shared_pages, ord.zero_pages, ord.write_pages
+  intervals.len
+  pheader.len (mixable with predicates)
+  pheader.<field>, summed over the selected pheaders (mixable with range, predicates and
+  modifiers), over the fields
+    virtual_size, data_size, n_itree_nodes, zero_pages, private_pages, shared_pages, pages,
+    huge_mappable_pages
+e.g.: 'jif.private_pages < 100000', 'pheader[prot=rwx].len = 0', 'pheader[pathname~=libc].shared_pages <= 0x400'
+";
+
+/// An assertion: a numeric selector compared against a threshold
+#[derive(Debug)]
+pub(crate) struct Assertion {
+    selector: String,
+    cmd: MaterializedCommand,
+    op: &'static str,
+    cmp: CmpOp,
+    threshold: u64,
+}
+
+/// The pheader fields which can be summed over the selected pheaders
+fn summed_field(selector: &PheaderSelector) -> Option<PheaderField> {
+    let others = [
+        selector.virtual_range,
+        selector.pathname,
+        selector.ref_offset,
+        selector.prot,
+        selector.itree,
+        selector.intervals,
+        selector.itree_stats,
+        selector.entropy,
+        selector.compressibility,
+    ];
+    let mut numeric = [
+        (selector.virtual_size, PheaderField::VirtualSize),
+        (selector.data_size, PheaderField::DataSize),
+        (selector.n_itree_nodes, PheaderField::NItreeNodes),
+        (selector.zero_pages, PheaderField::ZeroPages),
+        (selector.private_pages, PheaderField::PrivatePages),
+        (selector.shared_pages, PheaderField::SharedPages),
+        (selector.pages, PheaderField::Pages),
+        (
+            selector.huge_mappable_pages,
+            PheaderField::HugeMappablePages,
+        ),
+    ]
+    .into_iter()
+    .filter(|(selected, _field)| *selected)
+    .map(|(_selected, field)| field);
+
+    match (numeric.next(), numeric.next()) {
+        (Some(field), None) if !others.contains(&true) => Some(field),
+        _ => None,
+    }
+}
+
+/// Whether the materialized command yields a single number
+fn is_numeric(cmd: &MaterializedCommand) -> bool {
+    match cmd {
+        MaterializedCommand::Jif(JifCmd::Pages(pages)) => {
+            [pages.zero, pages.private, pages.shared, pages.total]
+                .into_iter()
+                .filter(|selected| *selected)
+                .count()
+                == 1
+        }
+        MaterializedCommand::Ord(ord) => matches!(
+            ord,
+            OrdCmd::Len
+                | OrdCmd::Size
+                | OrdCmd::PrivatePages
+                | OrdCmd::SharedPages
+                | OrdCmd::ZeroPages
+                | OrdCmd::WritePages
+        ),
+        MaterializedCommand::Intervals(IntervalsCmd::Len) => true,
+        MaterializedCommand::Pheader(PheaderCmd::Len(_)) => true,
+        MaterializedCommand::Pheader(PheaderCmd::Selector { selector, .. }) => {
+            summed_field(selector).is_some()
+        }
+        _ => false,
+    }
+}
+
+impl Assertion {
+    /// Parse an assertion: `<selector> <op> <threshold>`
+    ///
+    /// The operator is looked for after the brackets of the selector, so that predicates (e.g.,
+    /// `pheader[private_pages>100].len > 2`) are left to the selector
+    pub(crate) fn parse(assertion: &str) -> anyhow::Result<Self> {
+        let after_brackets = assertion.rfind(']').map(|pos| pos + 1).unwrap_or(0);
+        let (pos, op, cmp) = CmpOp::OPERATORS
+            .iter()
+            .filter(|(_op, cmp)| *cmp != CmpOp::Contains)
+            .filter_map(|(op, cmp)| {
+                assertion[after_brackets..]
+                    .find(op)
+                    .map(|pos| (after_brackets + pos, *op, *cmp))
+            })
+            .min_by_key(|(pos, op, _cmp)| (*pos, std::cmp::Reverse(op.len())))
+            .ok_or_else(|| {
+                anyhow::anyhow!("no comparison operator in assertion `{}`", assertion)
+            })?;
+
+        let selector = assertion[..pos].trim().to_string();
+        let threshold = parse_int(assertion[pos + op.len()..].trim())?;
+        let cmd = MaterializedCommand::try_from(Some(selector.clone()))?;
+        if !is_numeric(&cmd) {
+            return Err(anyhow::anyhow!(
+                "selector `{}` does not yield a number",
+                selector
+            ));
+        }
+
+        Ok(Assertion {
+            selector,
+            cmd,
+            op,
+            cmp,
+            threshold,
+        })
+    }
+
+    /// Value of the selector over the JIF
+    pub(crate) fn value(&self, jif: &Jif) -> u64 {
+        let ords = jif.ord_chunks();
+        let ord_pages = |keep: &dyn Fn(&jif::ord::OrdChunk) -> bool| {
+            ords.iter()
+                .filter(|o| keep(o))
+                .map(|o| o.size())
+                .sum::<u64>()
+        };
+        let page_size = jif.page_size();
+
+        match &self.cmd {
+            MaterializedCommand::Jif(JifCmd::Pages(pages)) => {
+                if pages.zero {
+                    jif.zero_pages() as u64
+                } else if pages.private {
+                    jif.private_pages() as u64
+                } else if pages.shared {
+                    jif.shared_pages() as u64
+                } else {
+                    jif.total_pages() as u64
+                }
+            }
+            MaterializedCommand::Ord(OrdCmd::Len) => ords.len() as u64,
+            MaterializedCommand::Ord(OrdCmd::Size) => ord_pages(&|_o| true),
+            MaterializedCommand::Ord(OrdCmd::PrivatePages) => {
+                ord_pages(&|o| o.kind() == DataSource::Private)
+            }
+            MaterializedCommand::Ord(OrdCmd::SharedPages) => {
+                ord_pages(&|o| o.kind() == DataSource::Shared)
+            }
+            MaterializedCommand::Ord(OrdCmd::ZeroPages) => {
+                ord_pages(&|o| o.kind() == DataSource::Zero)
+            }
+            MaterializedCommand::Ord(OrdCmd::WritePages) => {
+                ord_pages(&|o| o.access() == Some(AccessKind::Write))
+            }
+            MaterializedCommand::Intervals(IntervalsCmd::Len) => jif
+                .pheaders()
+                .iter()
+                .map(|pheader| pheader.itree().iter_logical_intervals().count() as u64)
+                .sum(),
+            MaterializedCommand::Pheader(PheaderCmd::Len(filter)) => {
+                filter.apply(jif.pheaders(), page_size).len() as u64
+            }
+            MaterializedCommand::Pheader(PheaderCmd::Selector { filter, selector }) => {
+                let field = summed_field(selector).expect("the selector is numeric");
+                filter
+                    .apply(jif.pheaders(), page_size)
+                    .into_iter()
+                    .filter_map(|pheader| match pheader.field(field, page_size) {
+                        Some(FieldValue::Int(value)) => Some(value),
+                        _ => None,
+                    })
+                    .sum()
+            }
+            _ => unreachable!("the assertion selectors are numeric"),
+        }
+    }
+
+    /// Check the assertion over the JIF, returning whether it holds and a description of the
+    /// comparison
+    pub(crate) fn check(&self, jif: &Jif) -> (bool, String) {
+        let value = self.value(jif);
+        (
+            self.cmp.eval(value, self.threshold),
+            format!(
+                "{} = {} (expected {} {})",
+                self.selector, value, self.op, self.threshold
+            ),
+        )
+    }
+}
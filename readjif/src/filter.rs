@@ -0,0 +1,459 @@
+use crate::utils::{find_range, parse_int, IndexRange};
+
+use jif::pheader::{JifPheader, JifRawPheader};
+use jif::ProtFlags;
+
+/// Comparison operators in pheader predicates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// substring for strings, superset for protections
+    Contains,
+}
+
+impl CmpOp {
+    /// Operators, longest first (so that `<=` is not mistaken for `<`)
+    pub(crate) const OPERATORS: [(&'static str, CmpOp); 7] = [
+        ("~=", CmpOp::Contains),
+        ("!=", CmpOp::Ne),
+        ("<=", CmpOp::Le),
+        (">=", CmpOp::Ge),
+        ("=", CmpOp::Eq),
+        ("<", CmpOp::Lt),
+        (">", CmpOp::Gt),
+    ];
+
+    /// Check if the string contains an operator (i.e., is a predicate rather than a range)
+    pub(crate) fn is_in(s: &str) -> bool {
+        s.contains(['=', '<', '>', '~', '!'])
+    }
+
+    pub(crate) fn eval<T: PartialOrd>(&self, a: T, b: T) -> bool {
+        match self {
+            CmpOp::Eq => a == b,
+            CmpOp::Ne => a != b,
+            CmpOp::Lt => a < b,
+            CmpOp::Le => a <= b,
+            CmpOp::Gt => a > b,
+            CmpOp::Ge => a >= b,
+            CmpOp::Contains => unreachable!("contains is not an ordering operator"),
+        }
+    }
+}
+
+/// Pheader fields which can be filtered on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PheaderField {
+    Vaddr,
+    VirtualSize,
+    DataSize,
+    Pathname,
+    PathnameOffset,
+    RefOffset,
+    Prot,
+    NItreeNodes,
+    ZeroPages,
+    PrivatePages,
+    SharedPages,
+    Pages,
+    HugeMappablePages,
+}
+
+/// Fields of materialized pheaders
+pub(crate) const MATERIALIZED_FIELDS: &[(&str, PheaderField)] = &[
+    ("vaddr", PheaderField::Vaddr),
+    ("virtual_size", PheaderField::VirtualSize),
+    ("data_size", PheaderField::DataSize),
+    ("pathname", PheaderField::Pathname),
+    ("ref_offset", PheaderField::RefOffset),
+    ("prot", PheaderField::Prot),
+    ("n_itree_nodes", PheaderField::NItreeNodes),
+    ("zero_pages", PheaderField::ZeroPages),
+    ("private_pages", PheaderField::PrivatePages),
+    ("shared_pages", PheaderField::SharedPages),
+    ("pages", PheaderField::Pages),
+    ("huge_mappable_pages", PheaderField::HugeMappablePages),
+];
+
+/// Fields of raw pheaders
+pub(crate) const RAW_FIELDS: &[(&str, PheaderField)] = &[
+    ("vaddr", PheaderField::Vaddr),
+    ("virtual_size", PheaderField::VirtualSize),
+    ("pathname_offset", PheaderField::PathnameOffset),
+    ("ref_offset", PheaderField::RefOffset),
+    ("prot", PheaderField::Prot),
+];
+
+/// Value of a pheader field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FieldValue {
+    Int(u64),
+    Str(String),
+    Prot(ProtFlags),
+}
+
+/// A predicate over a pheader field: `<field><op><value>` (e.g., `private_pages>100`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PheaderPredicate {
+    field: PheaderField,
+    op: CmpOp,
+    value: FieldValue,
+}
+
+impl PheaderPredicate {
+    /// Parse a predicate, over one of the allowed `fields`
+    pub(crate) fn parse(predicate: &str, fields: &[(&str, PheaderField)]) -> anyhow::Result<Self> {
+        let (pos, op_str, op) = CmpOp::OPERATORS
+            .iter()
+            .filter_map(|(op_str, op)| predicate.find(op_str).map(|pos| (pos, *op_str, *op)))
+            .min_by_key(|(pos, op_str, _op)| (*pos, std::cmp::Reverse(op_str.len())))
+            .ok_or_else(|| {
+                anyhow::anyhow!("no comparison operator in predicate `{}`", predicate)
+            })?;
+
+        let field_str = predicate[..pos].trim();
+        let value_str = predicate[pos + op_str.len()..].trim();
+
+        let field = fields
+            .iter()
+            .find(|(name, _field)| *name == field_str)
+            .map(|(_name, field)| *field)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown field `{}` in predicate `{}`: {:?}",
+                    field_str,
+                    predicate,
+                    fields.iter().map(|(name, _)| name).collect::<Vec<_>>()
+                )
+            })?;
+
+        let value = match field {
+            PheaderField::Pathname => FieldValue::Str(value_str.to_string()),
+            PheaderField::Prot => FieldValue::Prot(parse_prot(value_str)?),
+            _ => FieldValue::Int(parse_int(value_str)?),
+        };
+
+        let op_allowed = match (&value, op) {
+            (FieldValue::Int(_), CmpOp::Contains) => false,
+            (FieldValue::Int(_), _) => true,
+            (_, op) => matches!(op, CmpOp::Eq | CmpOp::Ne | CmpOp::Contains),
+        };
+        if !op_allowed {
+            return Err(anyhow::anyhow!(
+                "operator `{}` cannot be applied to field `{}`",
+                op_str,
+                field_str
+            ));
+        }
+
+        Ok(PheaderPredicate { field, op, value })
+    }
+
+    /// Parse a comma separated list of predicates
+    pub(crate) fn parse_list(
+        predicates: &str,
+        fields: &[(&str, PheaderField)],
+    ) -> anyhow::Result<Vec<Self>> {
+        predicates
+            .split(',')
+            .map(|p| PheaderPredicate::parse(p, fields))
+            .collect()
+    }
+
+    /// Check if the predicate holds for the pheader (of a JIF with pages of `page_size`)
+    ///
+    /// Predicates over fields the pheader does not have (e.g., the pathname of an anonymous
+    /// pheader) only hold for `!=`
+    pub(crate) fn matches(&self, pheader: &impl PheaderFields, page_size: usize) -> bool {
+        match (pheader.field(self.field, page_size), &self.value) {
+            (None, _) => self.op == CmpOp::Ne,
+            (Some(FieldValue::Int(a)), FieldValue::Int(b)) => self.op.eval(a, *b),
+            (Some(FieldValue::Str(a)), FieldValue::Str(b)) => match self.op {
+                CmpOp::Contains => a.contains(b.as_str()),
+                op => op.eval(a.as_str(), b.as_str()),
+            },
+            (Some(FieldValue::Prot(a)), FieldValue::Prot(b)) => match self.op {
+                CmpOp::Contains => a.contains(*b),
+                op => op.eval(a.bits(), b.bits()),
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Parse `rwx` style protections (`-` is ignored)
+fn parse_prot(s: &str) -> anyhow::Result<ProtFlags> {
+    s.chars().try_fold(ProtFlags::empty(), |prot, c| match c {
+        'r' => Ok(prot | ProtFlags::READ),
+        'w' => Ok(prot | ProtFlags::WRITE),
+        'x' => Ok(prot | ProtFlags::EXEC),
+        '-' => Ok(prot),
+        c => Err(anyhow::anyhow!("unknown protection `{}` in {}", c, s)),
+    })
+}
+
+/// Access to the fields of a pheader (raw or materialized)
+pub(crate) trait PheaderFields {
+    fn field(&self, field: PheaderField, page_size: usize) -> Option<FieldValue>;
+}
+
+impl PheaderFields for JifPheader {
+    fn field(&self, field: PheaderField, page_size: usize) -> Option<FieldValue> {
+        let (start, end) = self.virtual_range();
+        Some(match field {
+            PheaderField::Vaddr => FieldValue::Int(start),
+            PheaderField::VirtualSize => FieldValue::Int(end - start),
+            PheaderField::DataSize => FieldValue::Int(self.data_size() as u64),
+            PheaderField::Pathname => FieldValue::Str(self.pathname()?.to_string()),
+            PheaderField::RefOffset => FieldValue::Int(self.ref_offset()?),
+            PheaderField::Prot => FieldValue::Prot(self.prot()),
+            PheaderField::NItreeNodes => FieldValue::Int(self.n_itree_nodes() as u64),
+            PheaderField::ZeroPages => FieldValue::Int(self.zero_pages(page_size) as u64),
+            PheaderField::PrivatePages => FieldValue::Int(self.private_pages(page_size) as u64),
+            PheaderField::SharedPages => FieldValue::Int(self.shared_pages(page_size) as u64),
+            PheaderField::Pages => FieldValue::Int(self.total_pages(page_size) as u64),
+            PheaderField::HugeMappablePages => {
+                FieldValue::Int(self.huge_mappable_pages(page_size) as u64)
+            }
+            PheaderField::PathnameOffset => return None,
+        })
+    }
+}
+
+impl PheaderFields for JifRawPheader {
+    fn field(&self, field: PheaderField, _page_size: usize) -> Option<FieldValue> {
+        let (start, end) = self.virtual_range();
+        Some(match field {
+            PheaderField::Vaddr => FieldValue::Int(start),
+            PheaderField::VirtualSize => FieldValue::Int(end - start),
+            PheaderField::PathnameOffset => FieldValue::Int(self.pathname_offset()? as u64),
+            PheaderField::RefOffset => FieldValue::Int(self.ref_offset()?),
+            PheaderField::Prot => FieldValue::Prot(self.prot()),
+            _ => return None,
+        })
+    }
+}
+
+/// Order of the selected pheaders, by one of their fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PheaderOrder {
+    field: PheaderField,
+    descending: bool,
+}
+
+impl PheaderOrder {
+    /// Compare two pheaders by the field (the pheaders without it go last)
+    fn cmp(
+        &self,
+        a: &impl PheaderFields,
+        b: &impl PheaderFields,
+        page_size: usize,
+    ) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+
+        let ordering = match (
+            a.field(self.field, page_size),
+            b.field(self.field, page_size),
+        ) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Greater,
+            (Some(_), None) => return Ordering::Less,
+            (Some(FieldValue::Int(a)), Some(FieldValue::Int(b))) => a.cmp(&b),
+            (Some(FieldValue::Str(a)), Some(FieldValue::Str(b))) => a.cmp(&b),
+            (Some(FieldValue::Prot(a)), Some(FieldValue::Prot(b))) => a.bits().cmp(&b.bits()),
+            _ => Ordering::Equal,
+        };
+
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+/// Selection of pheaders: either by index range or by predicates (which all have to hold),
+/// optionally sorted by a field and cut to the first few
+#[derive(Debug)]
+pub(crate) struct PheaderFilter {
+    pub(crate) range: IndexRange,
+    pub(crate) predicates: Vec<PheaderPredicate>,
+    pub(crate) order: Option<PheaderOrder>,
+    pub(crate) top: Option<usize>,
+}
+
+/// Split the modifiers off a pheader selection: they follow the first whitespace outside of the
+/// brackets (e.g., `pheader[pathname ~= libc].private_pages sort desc top 10`)
+pub(crate) fn split_modifiers(s: &str) -> (&str, &str) {
+    let mut depth = 0usize;
+    for (idx, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() && depth == 0 => return (&s[..idx], s[idx..].trim()),
+            _ => {}
+        }
+    }
+
+    (s, "")
+}
+
+impl PheaderFilter {
+    /// Finds if `suffix` starts with a filter: `[<range>]` or `[<predicate>,...]`
+    /// returns the suffix after the `]` codepoint
+    pub(crate) fn find<'a>(
+        original: &str,
+        suffix: &'a str,
+        fields: &[(&str, PheaderField)],
+    ) -> anyhow::Result<(Self, &'a str)> {
+        if let Some((predicates, rest)) = suffix
+            .strip_prefix('[')
+            .and_then(|inner| inner.split_once(']'))
+            .filter(|(predicates, _rest)| CmpOp::is_in(predicates))
+        {
+            let predicates = PheaderPredicate::parse_list(predicates, fields)?;
+            return Ok((
+                PheaderFilter {
+                    range: IndexRange::None,
+                    predicates,
+                    order: None,
+                    top: None,
+                },
+                rest,
+            ));
+        }
+
+        let (range, suffix) = find_range(original, suffix)?;
+        Ok((
+            PheaderFilter {
+                range,
+                predicates: Vec::new(),
+                order: None,
+                top: None,
+            },
+            suffix,
+        ))
+    }
+
+    /// Parse the modifiers of the selection (see [`split_modifiers`]):
+    ///  - `sort [<field>] [asc|desc]` (or `sort_by=<field>`): sort the pheaders by one of the
+    ///    `fields` (by default, the only selected one), in ascending order unless `desc`
+    ///  - `top <N>` (or `top=<N>`): keep the first `N` pheaders
+    pub(crate) fn parse_modifiers(
+        &mut self,
+        original: &str,
+        modifiers: &str,
+        fields: &[(&str, PheaderField)],
+        selected_field: Option<PheaderField>,
+    ) -> anyhow::Result<()> {
+        let find_field = |name: &str| {
+            fields
+                .iter()
+                .find(|(field_name, _field)| *field_name == name)
+                .map(|(_name, field)| *field)
+        };
+
+        let mut tokens = modifiers
+            .split(|c: char| c.is_whitespace() || c == '=')
+            .filter(|token| !token.is_empty())
+            .peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                "sort" | "sort_by" => {
+                    if self.order.is_some() {
+                        return Err(anyhow::anyhow!("pheaders sorted twice in `{}`", original));
+                    }
+
+                    let field = match tokens.peek().and_then(|name| find_field(name)) {
+                        Some(field) => {
+                            tokens.next();
+                            field
+                        }
+                        None => selected_field.ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "expected a field to sort by in `{}`: {:?}",
+                                original,
+                                fields.iter().map(|(name, _)| name).collect::<Vec<_>>()
+                            )
+                        })?,
+                    };
+                    let descending = tokens
+                        .next_if(|token| *token == "asc" || *token == "desc")
+                        .is_some_and(|token| token == "desc");
+
+                    self.order = Some(PheaderOrder { field, descending });
+                }
+                "top" => {
+                    let n = tokens.next().ok_or_else(|| {
+                        anyhow::anyhow!("expected a number of pheaders after top in `{}`", original)
+                    })?;
+                    self.top = Some(parse_int(n)? as usize);
+                }
+                token => {
+                    return Err(anyhow::anyhow!(
+                        "unknown modifier `{}` in `{}`: expected sort or top",
+                        token,
+                        original
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Select the pheaders (of a JIF with pages of `page_size`)
+    pub(crate) fn apply<'a, P: PheaderFields>(
+        &self,
+        pheaders: &'a [P],
+        page_size: usize,
+    ) -> Vec<&'a P> {
+        let ranged_pheaders = match self.range {
+            IndexRange::None => pheaders,
+            IndexRange::Closed { start, end } => {
+                if start < pheaders.len() {
+                    &pheaders[start..std::cmp::min(end, pheaders.len())]
+                } else {
+                    &[]
+                }
+            }
+            IndexRange::LeftOpen { end } => &pheaders[..std::cmp::min(end, pheaders.len())],
+            IndexRange::RightOpen { start } => {
+                if start < pheaders.len() {
+                    &pheaders[start..]
+                } else {
+                    &[]
+                }
+            }
+            IndexRange::Index(idx) => {
+                if idx < pheaders.len() {
+                    &pheaders[idx..(idx + 1)]
+                } else {
+                    &[]
+                }
+            }
+        };
+
+        let mut selected = ranged_pheaders
+            .iter()
+            .filter(|pheader| {
+                self.predicates
+                    .iter()
+                    .all(|p| p.matches(*pheader, page_size))
+            })
+            .collect::<Vec<_>>();
+        if let Some(order) = self.order {
+            selected.sort_by(|a, b| order.cmp(*a, *b, page_size));
+        }
+        if let Some(top) = self.top {
+            selected.truncate(top);
+        }
+
+        selected
+    }
+}
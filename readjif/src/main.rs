@@ -6,6 +6,15 @@
 //! ```sh
 //! $ readjif a.jif # reads the jif file, dumps a representation of the materialized JIF
 //! $ readjif --raw a.jif # reads the jif file, dumps a representation of the raw JIF
+//! $ readjif --check --verify a.jif # checks the jif file against its integrity section
+//! $ readjif --check --strict a.jif # validates the structure of the jif file
+//! $ readjif --check a.jif # on a parsing failure, prints the section and byte range of the bad bytes
+//! $ readjif --lenient a.jif pheader # reads what can be salvaged of a corrupt jif, reporting what was skipped
+//! $ diff <(readjif --canonical a.jif) <(readjif --canonical b.jif) # compares two snapshots
+//! $ readjif --assert 'jif.private_pages < 100000' a.jif # exits with 3 if the jif has too many private pages
+//! $ readjif -q --assert 'pheader[prot=rwx].len = 0' a.jif # only the exit code tells whether the assertions hold
+//! $ readjif a.jif plan # prints the mmap/memcpy/madvise/mprotect operations of a loader, in order
+//! $ readjif --render-map map.svg a.jif # draws the address space, one band per pheader colored by data source (`.html` for a page)
 //! ```
 //!
 //!
@@ -14,6 +23,14 @@
 //! For materialized JIFs, the API is the following:
 //! - `jif`: select the whole JIF
 //! - `jif.strings`: strings in the JIF (incompatible with the page selectors)
+//! - `jif.strings.usage`: the referenced files, by decreasing mapped size, each with the number of pheaders referencing it, the size of the address ranges mapped from it and the number of shared pages read from it, to tell which shared objects dominate a snapshot (incompatible with the page selectors)
+//! - `jif.arch`: architecture tag: instruction set, endianness and page size (incompatible with the page selectors)
+//! - `jif.dedup`: statistics of the sharing of the private data segments: segments, references, stored and referenced bytes, histogram of sizes (incompatible with the page selectors)
+//! - `jif.duplication`: duplication of the private data at page granularity (which `jiftool --dedup-pages` removes): number of private pages, number of unique pages, unique and duplicated bytes, and the most duplicated page contents, with their copies, addresses and fill byte (for the pages of a single repeated byte, e.g., `0xcc`) (incompatible with the page selectors)
+//! - `jif.footprint`: estimated memory footprint after restore (private data and shared pages expected to be copied on write), on-disk size by section and the contribution of each pheader (incompatible with the page selectors)
+//! - `jif.prefetch`: prefetch layout the JIF gets when written with the prefetch set up: number of prefetched pages and the address ranges faulted in by a write or a read (incompatible with the page selectors)
+//! - `jif.itree_stats`: statistics of the interval trees of every pheader: number of intervals and nodes, how full the nodes are, and the number, size and size histogram of the logical intervals of each data source (incompatible with the page selectors)
+//! - `jif.meta`: custom metadata (e.g., build information), as `key=value` pairs (incompatible with the page selectors)
 //! - `jif.zero_pages`: number of zero pages
 //! - `jif.private_pages`: number of private pages in the JIF
 //! - `jif.shared_pages`: number of shared pages in the pheader
@@ -25,9 +42,13 @@
 //! - `ord.private_pages`: number of private pages in the ordering section
 //! - `ord.shared_pages`: number of shared pages in the ordering section
 //! - `ord.zero_pages`: number of zero pages in the ordering section
+//! - `ord.write_pages`: number of pages in the ord chunks faulted in by a write
+//! - `ord.threads`: number of pages in the ordering section by faulting thread (for the traced chunks)
+//! - `ord.quality`: quality of the ordering section against the interval trees: fraction of the private pages covered, mean chunk size, number of VMA switches while walking it, mean address stride between chunks and a single score (coverage, discounted by the VMA switches) to compare tracing configurations
 //! - `pheader`: select all the pheaders
 //! - `pheader[<range>]`: select the pheaders in the range
-//! - `pheader.len`: number of pheaders (incompatible with the range and field selectors)
+//! - `pheader[<predicate>,...]`: select the pheaders for which all the predicates (e.g., `prot=rx`, `pathname~=libc`, `private_pages>100`) hold
+//! - `pheader.len`: number of pheaders (incompatible with the range and field selectors, mixable with predicates)
 //! - `pheader.data_size`: size of the data region (mixable with range and other selectors)
 //! - `pheader.pathname`: reference pathname (mixable with range and other selectors)
 //! - `pheader.ref_offset`: offset into the file
@@ -40,17 +61,38 @@
 //! - `pheader.private_pages`: the same as `data_size % PAGE_SIZE`
 //! - `pheader.shared_pages`: number of shared pages in the pheader
 //! - `pheader.pages`: total number of pages
+//! - `pheader.huge_mappable_pages`: number of pages in 2MiB regions backed by a single source
+//! - `pheader.intervals`: logical intervals of the pheader, with their range, source and size (mixable with range and other selectors)
+//! - `pheader.itree_stats`: statistics of the interval tree: number of intervals and nodes, how full the nodes are, and the sizes of the logical intervals by data source (bucketed by the next power of two; mixable with range and other selectors)
+//! - `pheader.entropy`: Shannon entropy of the private data, in bits per byte, estimated over a sample of pages (mixable with range and other selectors)
+//! - `pheader.compressibility`: estimated (LZ4) compression ratio of the private data, over a sample of pages (mixable with range and other selectors)
+//! - `pheader<selection> sort [<field>] [asc|desc] top <N>`: modifiers of a pheader selection (after a space): sort the pheaders by one of the predicate fields (by default, the selected one; e.g., `pheader.private_pages sort desc top 10`) and keep the first `N` (`sort_by=<field>` and `top=<N>` also work)
+//! - `intervals`: select all the logical intervals (of every pheader, sorted by address)
+//! - `intervals[<range>]`: select the logical intervals in the range
+//! - `intervals.len`: number of logical intervals (incompatible with the range selector)
+//! - `addr[<vaddr>]`: resolve a virtual address (mapping pheader, interval, data source, backing file and offset)
+//! - `data[<start>..<end>]`: logical bytes of an address range (the end may also be given as `<start>+<len>`), pulled from the private data, zero pages or the referenced files (relative to `--chroot`); written as raw bytes to stdout, printed as a hexdump (with `--hex`) or saved to a file (with `--out`)
+//! - `scan <pattern>`: search the private data for a pattern (`0x` prefixed hexadecimal bytes, or else a string), reporting the address, pheader and data source of every match; with `--shared`, the shared pages are searched as well (reading the referenced files, relative to `--chroot`)
+//! - `plan`: restore plan, one operation per line, in the order a loader performs them: the mappings of the pheaders (writable when data is copied into them), the prefetch of the ordering section (copying the private pages, `madvise(MADV_WILLNEED)` on the shared ones), the copies of the remaining private data, the zero fills over the referenced files and the final `mprotect`s
 //!
 //! For raw JIFs, the API is similar:
 //! - `jif`: select the whole JIF
 //! - `jif.data`: size of the data section
+//! - `jif.arch`: architecture tag: instruction set, endianness and page size
+//! - `jif.prefetch`: whether the prefetch is set up, number of prefetched pages (in total, faulted in by a write and by a read) and the prefetched address ranges, split by the access which faulted them in
+//! - `jif.file_size`: size of the file and the space allocated for it by the filesystem (less than its size when the file is sparse, see `jiftool --sparse`)
+//! - `jif.meta`: custom metadata (e.g., build information), as `key=value` pairs (see `jiftool set-meta`)
 //! - `jif.zero_pages`: number of zero pages
 //! - `jif.private_pages`: the same as `data % PAGE_SIZE`
 //! - `jif.pages`: total number of pages
 //! - `strings`: select the strings in the JIF
+//! - `strings.usage`: the strings, by decreasing mapped size, each with the number of pheaders referencing it, the size of the address ranges mapped from it and the number of shared pages read from it (the strings no pheader references show no usage)
+//! - `layout`: file offsets of the header, the pheader table, the strings, the interval tree nodes, the ordering section, the data section (and each data segment, with the pheaders and intervals it backs, and the padding of the aligned segments) and the integrity section
+//! - `data.provenance`: the ordering chunk which placed each data segment (the first one landing on an interval it backs, with its index, thread and access, when traced), or whether it was placed in address order, to trace a poorly placed segment back to the trace entry which caused it
 //! - `itrees`: select all the interval trees
 //! - `itrees[<range>]`: select the interval trees in the range
 //! - `itrees.len`: number of interval trees (incompatible with the range selector)
+//! - `itrees.by_pheader`: the interval tree nodes grouped by the pheader owning them (with its index, virtual range and node range), as the node indices of `itrees[<range>]` are only meaningful against the `itree` of each pheader
 //! - `ord`: select all the ord chunks
 //! - `ord[<range>]`: select the ord chunks in the range
 //! - `ord.len`: number of ord chunks (incompatible with the range selector)
@@ -58,32 +100,58 @@
 //! - `ord.private_pages`: number of private pages in the ordering section
 //! - `ord.shared_pages`: number of shared pages in the ordering section
 //! - `ord.zero_pages`: number of zero pages in the ordering section
+//! - `ord.write_pages`: number of pages in the ord chunks faulted in by a write
+//! - `ord.threads`: number of pages in the ordering section by faulting thread (for the traced chunks)
 //! - `pheader`: select all the pheaders
 //! - `pheader[<range>]`: select the pheaders in the range
-//! - `pheader.len`: number of pheaders (incompatible with the range and field selectors)
+//! - `pheader[<predicate>,...]`: select the pheaders for which all the predicates (e.g., `prot=rx`, `pathname~=libc`, `private_pages>100`) hold
+//! - `pheader.len`: number of pheaders (incompatible with the range and field selectors, mixable with predicates)
 //! - `pheader.pathname_offset`: reference pathname (mixable with range and other selectors)
 //! - `pheader.ref_offset`: offset into the file
 //! - `pheader.virtual_range`: virtual address range of the pheader (mixable with range and other selectors)
 //! - `pheader.virtual_size`: size of the virtual address range (mixable with range and other selectors)
 //! - `pheader.prot`: area `rwx` protections (mixable with range and other selectors)
 //! - `pheader.itree`: show the interval tree offset and size in number of nodes (mixable with range and other selectors)
+//! - `pheader.itree_nodes`: the interval tree nodes of the pheader, resolved from its `itree` (e.g., `pheader[3].itree_nodes`; mixable with range and other selectors)
 //! - `pheader.zero_pages`: number of zero pages
+//! - `pheader<selection> sort [<field>] [asc|desc] top <N>`: modifiers of a pheader selection (after a space): sort the pheaders by one of the predicate fields (by default, the selected one; e.g., `pheader.virtual_size sort desc top 10`) and keep the first `N` (`sort_by=<field>` and `top=<N>` also work)
+//!
+//! Assertions (`--assert '<selector> <op> <threshold>'`, which may be repeated) compare a selector
+//! yielding a number against an integer threshold, with the operators `=`, `!=`, `<`, `<=`, `>` and
+//! `>=`: the page counts of the JIF (`jif.private_pages`, ...), the counts of the ordering section
+//! (`ord.len`, `ord.private_pages`, ...), `intervals.len`, `pheader.len` and the numeric pheader
+//! fields (e.g., `pheader[pathname~=libc].shared_pages`), summed over the selected pheaders.
+//! Each assertion is printed with its value (unless `--quiet`), and the exit code tells how it went:
+//! - `0`: every assertion holds
+//! - `1`: the JIF could not be read (or the assertions could not be parsed)
+//! - `2`: bad usage
+//! - `3`: some assertion does not hold
 
 use jif::*;
 
+mod assertion;
+mod filter;
+mod map;
 mod selectors;
 mod utils;
 
+use crate::assertion::*;
 use crate::selectors::*;
-use crate::utils::IndexRange;
+use crate::utils::{pages_by_thread, IndexRange};
 
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter, Write};
+use std::process::ExitCode;
 
 use anyhow::Context;
 use clap::Parser;
 
-use self::itree::interval::DataSource;
+use self::itree::interval::{DataSource, LogicalInterval};
+use self::itree::itree_node::RawITreeNode;
+use self::pheader::JifRawPheader;
+
+/// Number of the most duplicated page contents printed by `jif.duplication`
+const DUPLICATION_TOP: usize = 10;
 
 #[derive(Parser)]
 #[command(version)]
@@ -108,19 +176,101 @@ struct Cli {
     /// Just check
     #[arg(short, long)]
     check: bool,
+
+    /// When checking, require the JIF to have an integrity section (which is always verified)
+    #[arg(long, requires = "check")]
+    verify: bool,
+
+    /// When checking, also validate the structure of the JIF (reporting every finding)
+    #[arg(long, requires = "check")]
+    strict: bool,
+
+    /// Recover what can be read of a corrupt JIF, skipping the bad structures (and the pheaders
+    /// depending on them) and reporting each of them
+    #[arg(long, conflicts_with_all = ["raw", "check"])]
+    lenient: bool,
+
+    /// Print a canonical dump of the JIF (line oriented and independent of its layout, to be diffed)
+    #[arg(long, conflicts_with_all = ["raw", "check", "command"])]
+    canonical: bool,
+
+    /// Assert that a numeric selector compares to a threshold (e.g., `jif.private_pages < 100000`),
+    /// exiting with code 3 if any assertion does not hold
+    ///
+    /// For help, type `help` as the assertion
+    #[arg(
+        long = "assert",
+        value_name = "ASSERTION",
+        conflicts_with_all = ["raw", "check", "canonical", "command"]
+    )]
+    assertions: Vec<String>,
+
+    /// Render the address space as a map (one band per pheader, colored by data source, with
+    /// tooltips) to this file: an SVG, or an HTML page if the file ends in `.html`
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath,
+        conflicts_with_all = ["raw", "check", "canonical", "assertions", "command"]
+    )]
+    render_map: Option<std::path::PathBuf>,
+
+    /// When asserting, do not print the assertions (only the exit code tells whether they hold)
+    #[arg(short, long, requires = "assertions")]
+    quiet: bool,
+
+    /// When scanning, also search the shared pages (reading the referenced files)
+    #[arg(long)]
+    shared: bool,
+
+    /// Directory the referenced files are relative to (when reading the shared pages)
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    chroot: Option<std::path::PathBuf>,
+
+    /// When dumping data, print it as a hexdump
+    #[arg(long, conflicts_with = "out")]
+    hex: bool,
+
+    /// When dumping data, save it to a file
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    out: Option<std::path::PathBuf>,
+}
+
+/// The interval tree nodes of a raw pheader (none if it has no interval tree, or if its nodes
+/// are out of bounds)
+fn pheader_itree_nodes<'a>(jif: &'a JifRaw, pheader: &JifRawPheader) -> &'a [RawITreeNode] {
+    pheader
+        .itree()
+        .and_then(|(idx, n_nodes)| {
+            jif.itree_nodes()
+                .get(idx as usize..idx as usize + n_nodes as usize)
+        })
+        .unwrap_or_default()
 }
 
-fn select_raw(jif: JifRaw, cmd: RawCommand) {
+fn select_raw(jif: JifRaw, cmd: RawCommand, file: &File) -> anyhow::Result<()> {
     match cmd {
         RawCommand::Jif(j) => match j {
             RawJifCmd::All => println!("{:#x?}", jif),
             RawJifCmd::Data => println!("data section: {:#x} B", jif.data_size()),
+            RawJifCmd::Arch => println!("arch: {}", jif.arch()),
+            RawJifCmd::Prefetch => {
+                print_prefetch(&jif.prefetch_layout(), jif.arch().page_size, false)
+            }
+            RawJifCmd::FileSize => println!(
+                "file: {}",
+                FileSize::from_file(file).context("failed to look up the file size")?
+            ),
+            RawJifCmd::Meta => print_meta(jif.metadata()),
         },
         RawCommand::Strings => {
             for s in jif.strings().iter() {
                 println!("{}", s);
             }
         }
+        RawCommand::StringsUsage => print_string_usage(jif.string_usage()),
+        RawCommand::Layout => print!("{}", jif.layout()),
+        RawCommand::Provenance => print!("{}", jif.data_provenance()),
         RawCommand::Ord(o) => {
             let ords = jif.ord_chunks();
             match o {
@@ -150,6 +300,17 @@ fn select_raw(jif: JifRaw, cmd: RawCommand) {
                         .map(|o| o.size())
                         .sum::<u64>()
                 ),
+                OrdCmd::WritePages => println!(
+                    "write_pages: {}",
+                    ords.iter()
+                        .filter(|o| o.access() == Some(ord::AccessKind::Write))
+                        .map(|o| o.size())
+                        .sum::<u64>()
+                ),
+                OrdCmd::Threads => println!("threads: {:?}", pages_by_thread(ords)),
+                OrdCmd::Quality => {
+                    unreachable!("the quality is only selected for materialized JIFs")
+                }
                 OrdCmd::Range(IndexRange::RightOpen { start }) => println!(
                     "{:x?}",
                     if start < ords.len() {
@@ -183,6 +344,25 @@ fn select_raw(jif: JifRaw, cmd: RawCommand) {
                     println!("{:#x?}", itree_nodes)
                 }
                 ITreeCmd::Len => println!("n_itree_nodes: {}", itree_nodes.len()),
+                ITreeCmd::ByPheader => {
+                    for (idx, pheader) in jif.pheaders().iter().enumerate() {
+                        let (start, end) = pheader.virtual_range();
+                        match pheader.itree() {
+                            Some((itree_idx, n_nodes)) => println!(
+                                "pheader {} [{:#x}; {:#x}) itree [{}; #{}): {:#x?}",
+                                idx,
+                                start,
+                                end,
+                                itree_idx,
+                                n_nodes,
+                                pheader_itree_nodes(&jif, pheader)
+                            ),
+                            None => {
+                                println!("pheader {} [{:#x}; {:#x}): no itree", idx, start, end)
+                            }
+                        }
+                    }
+                }
                 ITreeCmd::Range(IndexRange::RightOpen { start }) => println!(
                     "{:#x?}",
                     if start < itree_nodes.len() {
@@ -214,37 +394,14 @@ fn select_raw(jif: JifRaw, cmd: RawCommand) {
         }
         RawCommand::Pheader(p) => {
             let pheaders = jif.pheaders();
+            let page_size = jif.page_size();
             match p {
-                RawPheaderCmd::Len => println!("n_pheaders: {}", pheaders.len()),
-                RawPheaderCmd::All => println!("{:#x?}", pheaders),
-                RawPheaderCmd::Selector { range, selector } => {
-                    let ranged_pheaders = match range {
-                        IndexRange::None => pheaders,
-                        IndexRange::Closed { start, end } => {
-                            if start < pheaders.len() {
-                                &pheaders[start..std::cmp::min(end, pheaders.len())]
-                            } else {
-                                &[]
-                            }
-                        }
-                        IndexRange::LeftOpen { end } => {
-                            &pheaders[..std::cmp::min(end, pheaders.len())]
-                        }
-                        IndexRange::RightOpen { start } => {
-                            if start < pheaders.len() {
-                                &pheaders[start..]
-                            } else {
-                                &[]
-                            }
-                        }
-                        IndexRange::Index(idx) => {
-                            if idx < pheaders.len() {
-                                &pheaders[idx..(idx + 1)]
-                            } else {
-                                &[]
-                            }
-                        }
-                    };
+                RawPheaderCmd::Len(filter) => {
+                    println!("n_pheaders: {}", filter.apply(pheaders, page_size).len())
+                }
+                RawPheaderCmd::All(filter) => println!("{:#x?}", filter.apply(pheaders, page_size)),
+                RawPheaderCmd::Selector { filter, selector } => {
+                    let ranged_pheaders = filter.apply(pheaders, page_size);
 
                     println!("[");
                     for pheader in ranged_pheaders {
@@ -268,31 +425,16 @@ fn select_raw(jif: JifRaw, cmd: RawCommand) {
                             }
                         }
                         if selector.prot {
-                            let prot = pheader.prot();
-                            print!(
-                                "prot: {}{}{}, ",
-                                if prot & Prot::Read as u8 != 0 {
-                                    "r"
-                                } else {
-                                    "-"
-                                },
-                                if prot & Prot::Write as u8 != 0 {
-                                    "w"
-                                } else {
-                                    "-"
-                                },
-                                if prot & Prot::Exec as u8 != 0 {
-                                    "x"
-                                } else {
-                                    "-"
-                                },
-                            )
+                            print!("prot: {}, ", pheader.prot())
                         }
                         if selector.itree {
                             if let Some((idx, n_nodes)) = pheader.itree() {
                                 print!("itree: [{}; #{}), ", idx, n_nodes);
                             }
                         }
+                        if selector.itree_nodes {
+                            print!("itree_nodes: {:x?}, ", pheader_itree_nodes(&jif, pheader));
+                        }
                         println!("}}")
                     }
                     println!("]");
@@ -300,17 +442,36 @@ fn select_raw(jif: JifRaw, cmd: RawCommand) {
             }
         }
     }
+
+    Ok(())
 }
 
-fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
+fn select_materialized(jif: Jif, cmd: MaterializedCommand, args: &Cli) -> anyhow::Result<()> {
     match cmd {
         MaterializedCommand::Jif(j) => match j {
             JifCmd::All => println!("{:#x?}", jif),
+            JifCmd::Arch => println!("arch: {}", jif.arch()),
+            JifCmd::Dedup => println!("{:#x?}", jif.dedup_stats()),
+            JifCmd::Duplication => print!("{}", jif.duplication(DUPLICATION_TOP)),
+            JifCmd::Footprint => println!("{:#x?}", jif.restore_footprint()),
+            JifCmd::ITreeStats => print!("{}", jif.interval_histogram()),
+            JifCmd::Meta => print_meta(jif.metadata()),
+            JifCmd::Prefetch => {
+                // the materialized JIF does not know whether it was written with the prefetch set
+                // up: the layout is the one it would get
+                let page_size = jif.arch().page_size;
+                print_prefetch(
+                    &JifRaw::from_materialized(jif, true).prefetch_layout(),
+                    page_size,
+                    true,
+                )
+            }
             JifCmd::Strings => {
                 for s in jif.strings().iter() {
                     println!("{}", s);
                 }
             }
+            JifCmd::StringsUsage => print_string_usage(jif.string_usage()),
             JifCmd::Pages(p) => {
                 print!("{{ ");
                 if p.zero {
@@ -328,6 +489,87 @@ fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
                 println!("}}");
             }
         },
+        MaterializedCommand::Addr(addr) => match jif.mapping_pheader(addr) {
+            None => println!("addr {:#x}: not mapped", addr),
+            Some(pheader) => {
+                let (start, end) = pheader.virtual_range();
+                let ival = pheader.resolve(addr);
+                let (ival_start, ival_end) = (
+                    std::cmp::max(ival.start, start),
+                    std::cmp::min(ival.end, end),
+                );
+
+                print!("addr {:#x} {{ ", addr);
+                print!("pheader: [{:#x}; {:#x}), ", start, end);
+                print!("interval: [{:#x}; {:#x}), ", ival_start, ival_end);
+                print!("source: {:?}, ", ival.source);
+                if let Some(path) = pheader.pathname() {
+                    print!("path: {}, ", path);
+                }
+                if let Some(offset) = pheader.ref_offset() {
+                    print!("file_offset: {:#x}, ", offset + (addr - start));
+                }
+                println!("}}");
+            }
+        },
+        MaterializedCommand::Data(start, end) => {
+            let data = jif
+                .read_range(start, end - start, &args.chroot)
+                .context("failed to read the address range")?;
+            if let Some(out) = &args.out {
+                std::fs::write(out, data).context("failed to write the data")?;
+            } else if args.hex {
+                print_hexdump(start, &data);
+            } else {
+                std::io::stdout()
+                    .write_all(&data)
+                    .context("failed to write the data")?;
+            }
+        }
+        MaterializedCommand::Scan(pattern) => {
+            let matches = if args.shared {
+                jif.scan_with_shared(&pattern, &args.chroot)
+                    .context("failed to read the shared pages")?
+            } else {
+                jif.scan(&pattern)
+            };
+            println!("[");
+            for (addr, context) in matches {
+                print!("match {:#x} {{ ", addr);
+                print!(
+                    "pheader: [{:#x}; {:#x}), ",
+                    context.pheader_range.0, context.pheader_range.1
+                );
+                print!("source: {:?}, ", context.source);
+                if let Some(path) = context.pathname {
+                    print!("path: {}, ", path);
+                }
+                println!("}}");
+            }
+            println!("]");
+        }
+        MaterializedCommand::Plan => {
+            for op in jif.restore_plan() {
+                println!("{}", op);
+            }
+        }
+        MaterializedCommand::Intervals(i) => {
+            let intervals = jif
+                .pheaders()
+                .iter()
+                .flat_map(|pheader| pheader.itree().iter_logical_intervals())
+                .collect::<Vec<_>>();
+            match i {
+                IntervalsCmd::Len => println!("n_intervals: {}", intervals.len()),
+                IntervalsCmd::Range(range) => {
+                    println!("[");
+                    for ival in range.slice(&intervals) {
+                        println!("{}", interval_str(ival));
+                    }
+                    println!("]");
+                }
+            }
+        }
         MaterializedCommand::Ord(o) => {
             let ords = jif.ord_chunks();
             match o {
@@ -357,6 +599,15 @@ fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
                         .map(|o| o.size())
                         .sum::<u64>()
                 ),
+                OrdCmd::WritePages => println!(
+                    "write_pages: {}",
+                    ords.iter()
+                        .filter(|o| o.access() == Some(ord::AccessKind::Write))
+                        .map(|o| o.size())
+                        .sum::<u64>()
+                ),
+                OrdCmd::Threads => println!("threads: {:?}", pages_by_thread(ords)),
+                OrdCmd::Quality => print!("{}", jif.ord_quality()),
                 OrdCmd::Range(IndexRange::RightOpen { start }) => println!(
                     "{:#x?}",
                     if start < ords.len() {
@@ -385,37 +636,14 @@ fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
         }
         MaterializedCommand::Pheader(p) => {
             let pheaders = jif.pheaders();
+            let page_size = jif.page_size();
             match p {
-                PheaderCmd::Len => println!("n_pheaders: {}", pheaders.len()),
-                PheaderCmd::All => println!("{:#x?}", pheaders),
-                PheaderCmd::Selector { range, selector } => {
-                    let ranged_pheaders = match range {
-                        IndexRange::None => pheaders,
-                        IndexRange::Closed { start, end } => {
-                            if start < pheaders.len() {
-                                &pheaders[start..std::cmp::min(end, pheaders.len())]
-                            } else {
-                                &[]
-                            }
-                        }
-                        IndexRange::LeftOpen { end } => {
-                            &pheaders[..std::cmp::min(end, pheaders.len())]
-                        }
-                        IndexRange::RightOpen { start } => {
-                            if start < pheaders.len() {
-                                &pheaders[start..]
-                            } else {
-                                &[]
-                            }
-                        }
-                        IndexRange::Index(idx) => {
-                            if idx < pheaders.len() {
-                                &pheaders[idx..(idx + 1)]
-                            } else {
-                                &[]
-                            }
-                        }
-                    };
+                PheaderCmd::Len(filter) => {
+                    println!("n_pheaders: {}", filter.apply(pheaders, page_size).len())
+                }
+                PheaderCmd::All(filter) => println!("{:#x?}", filter.apply(pheaders, page_size)),
+                PheaderCmd::Selector { filter, selector } => {
+                    let ranged_pheaders = filter.apply(pheaders, page_size);
 
                     println!("[");
                     for pheader in ranged_pheaders {
@@ -443,25 +671,7 @@ fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
                         }
 
                         if selector.prot {
-                            let prot = pheader.prot();
-                            print!(
-                                "prot: {}{}{}, ",
-                                if prot & Prot::Read as u8 != 0 {
-                                    "r"
-                                } else {
-                                    "-"
-                                },
-                                if prot & Prot::Write as u8 != 0 {
-                                    "w"
-                                } else {
-                                    "-"
-                                },
-                                if prot & Prot::Exec as u8 != 0 {
-                                    "x"
-                                } else {
-                                    "-"
-                                },
-                            )
+                            print!("prot: {}, ", pheader.prot())
                         }
                         if selector.itree {
                             print!("itree: {:?}, ", pheader.itree());
@@ -470,16 +680,45 @@ fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
                             print!("n_itree_nodes: {:?}, ", pheader.n_itree_nodes());
                         }
                         if selector.zero_pages {
-                            print!("zero_pages: {}, ", pheader.zero_pages())
+                            print!("zero_pages: {}, ", pheader.zero_pages(page_size))
                         }
                         if selector.private_pages {
-                            print!("private_pages: {}, ", pheader.private_pages())
+                            print!("private_pages: {}, ", pheader.private_pages(page_size))
                         }
                         if selector.shared_pages {
-                            print!("shared_pages: {}, ", pheader.shared_pages())
+                            print!("shared_pages: {}, ", pheader.shared_pages(page_size))
                         }
                         if selector.pages {
-                            print!("total_pages: {}, ", pheader.total_pages())
+                            print!("total_pages: {}, ", pheader.total_pages(page_size))
+                        }
+                        if selector.huge_mappable_pages {
+                            print!(
+                                "huge_mappable_pages: {}, ",
+                                pheader.huge_mappable_pages(page_size)
+                            )
+                        }
+                        if selector.intervals {
+                            let intervals = pheader
+                                .itree()
+                                .iter_logical_intervals()
+                                .map(|ival| interval_str(&ival))
+                                .collect::<Vec<_>>();
+                            print!("intervals: [{}], ", intervals.join(", "));
+                        }
+                        if selector.itree_stats {
+                            print!(
+                                "itree_stats: {}, ",
+                                itree_stats_str(&pheader.itree().interval_histogram())
+                            );
+                        }
+                        if selector.entropy || selector.compressibility {
+                            let stats = jif.data_stats(pheader);
+                            if selector.entropy {
+                                print!("entropy: {:.2} bits/B, ", stats.entropy)
+                            }
+                            if selector.compressibility {
+                                print!("compressibility: {:.2}x, ", stats.compression_ratio())
+                            }
                         }
                         println!("}}")
                     }
@@ -488,19 +727,219 @@ fn select_materialized(jif: Jif, cmd: MaterializedCommand) {
             }
         }
     }
+
+    Ok(())
+}
+
+/// Print the usage of the strings, one per line, by decreasing mapped size
+fn print_string_usage(usage: std::collections::BTreeMap<&str, StringUsage>) {
+    let mut usage = usage.into_iter().collect::<Vec<_>>();
+    usage.sort_by_key(|(_string, usage)| std::cmp::Reverse(usage.virtual_size));
+    for (string, usage) in usage {
+        println!("{}: {}", string, usage);
+    }
 }
 
-fn main() -> anyhow::Result<()> {
+/// Print the custom metadata, one `key=value` pair per line
+fn print_meta(meta: &std::collections::BTreeMap<String, String>) {
+    for (key, value) in meta {
+        println!("{}={}", key, value);
+    }
+}
+
+/// Print a prefetch layout: the number of prefetched pages and the ranges of each partition
+///
+/// A `hypothetical` layout is the one the JIF gets when written with the prefetch set up, whether
+/// or not it was
+fn print_prefetch(layout: &PrefetchLayout, page_size: usize, hypothetical: bool) {
+    if hypothetical {
+        println!("hypothetical layout, once the prefetch is set up");
+    } else {
+        println!("prefetch set up: {}", layout.is_set_up());
+    }
+    println!(
+        "n_prefetch: {} pages ({} write pages, {} read pages)",
+        layout.n_prefetch,
+        layout.n_write_pages(page_size),
+        layout.n_read_pages(page_size)
+    );
+    for (partition, ranges) in [
+        ("write", &layout.write_ranges),
+        ("read", &layout.read_ranges),
+    ] {
+        println!("{}: [", partition);
+        for (start, end) in ranges {
+            println!("    [{:#x}; {:#x}),", start, end);
+        }
+        println!("]");
+    }
+}
+
+/// Print bytes as a hexdump: the address, 16 bytes in hexadecimal and their printable characters
+fn print_hexdump(addr: u64, data: &[u8]) {
+    for (idx, line) in data.chunks(16).enumerate() {
+        let hex = line
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii = line
+            .iter()
+            .map(|byte| {
+                if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        println!("{:#014x}  {:<47}  |{}|", addr + 16 * idx as u64, hex, ascii);
+    }
+}
+
+/// Format a logical interval: its range, source and size
+fn interval_str(ival: &LogicalInterval) -> String {
+    format!(
+        "ival {{ virtual_range: [{:#x}; {:#x}), source: {:?}, size: {:#x} B, }}",
+        ival.start,
+        ival.end,
+        ival.source,
+        ival.end - ival.start
+    )
+}
+
+/// Format the interval statistics of a pheader: its fill and the interval sizes by source
+fn itree_stats_str(histogram: &jif::itree::IntervalHistogram) -> String {
+    let sizes = |histogram: &jif::itree::SizeHistogram| {
+        histogram
+            .buckets
+            .iter()
+            .map(|(bucket, count)| format!("<= {:#x} B: {}", bucket, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "{{ intervals: {}, nodes: {}, fill: {:.0}%, private: [{}], zero: [{}], shared: [{}], }}",
+        histogram.n_intervals,
+        histogram.n_nodes,
+        histogram.node_fill() * 100.0,
+        sizes(&histogram.private),
+        sizes(&histogram.zero),
+        sizes(&histogram.shared)
+    )
+}
+
+/// Read the materialized JIF (recovering what can be read of a corrupt one, with `--lenient`)
+fn read_jif(args: &Cli) -> anyhow::Result<Jif> {
+    let mut file = BufReader::new(File::open(&args.jif_file).context("failed to open file")?);
+    if !args.lenient {
+        return Jif::from_reader(&mut file).context("failed to open jif");
+    }
+
+    let (jif, errors) = Jif::from_reader_lenient(&mut file).context("failed to open jif")?;
+    for error in errors {
+        match error.file_range() {
+            Some((section, (start, end))) => eprintln!(
+                "skipped {} section, bytes [{:#x}; {:#x}): {}",
+                section,
+                start,
+                end,
+                error.root()
+            ),
+            None => eprintln!("skipped: {}", error),
+        }
+    }
+    Ok(jif)
+}
+
+fn main() -> anyhow::Result<ExitCode> {
     let args = Cli::parse();
 
     if args.check {
         let mut file = BufReader::new(File::open(&args.jif_file).context("failed to open file")?);
-        if args.raw {
-            JifRaw::from_reader(&mut file).context("failed to open jif in raw mode")?;
+        let raw = JifRaw::from_reader(&mut file).map_err(|err| {
+            if let Some((section, (start, end))) = err.file_range() {
+                println!(
+                    "{} section, bytes [{:#x}; {:#x}): {}",
+                    section,
+                    start,
+                    end,
+                    err.root()
+                );
+            }
+            anyhow::Error::new(err).context("failed to open jif in raw mode")
+        })?;
+        if args.verify && !raw.checksums() {
+            anyhow::bail!("jif does not have an integrity section");
+        }
+
+        let mut report = if args.strict {
+            raw.validate()
         } else {
-            Jif::from_reader(&mut file).context("failed to open jif in raw mode")?;
+            ValidationReport::default()
+        };
+        if !args.raw {
+            let jif = Jif::from_raw(raw).context("failed to open jif")?;
+            if args.strict {
+                report.extend(jif.validate());
+            }
         }
-        return Ok(());
+
+        if !report.is_clean() {
+            println!("{}", report);
+        }
+        if !report.is_valid() {
+            anyhow::bail!("jif failed validation");
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if args.canonical {
+        let jif = read_jif(&args)?;
+        let mut stdout = BufWriter::new(std::io::stdout().lock());
+        jif.write_canonical(&mut stdout)
+            .context("failed to dump the jif")?;
+        stdout.flush().context("failed to dump the jif")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(out) = &args.render_map {
+        let jif = read_jif(&args)?;
+        let title = args.jif_file.display().to_string();
+        let svg = map::render_map(&title, &jif);
+        let rendered = match out.extension().and_then(|ext| ext.to_str()) {
+            Some("html" | "htm") => map::to_html(&title, &svg),
+            _ => svg,
+        };
+        std::fs::write(out, rendered).context("failed to write the map")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !args.assertions.is_empty() {
+        let assertions = args
+            .assertions
+            .iter()
+            .map(|assertion| Assertion::parse(assertion))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| {
+                anyhow::anyhow!("failed to parse assertion: {}\n{}", e, ASSERTION_USAGE)
+            })?;
+
+        let jif = read_jif(&args)?;
+        let mut holds = true;
+        for assertion in assertions {
+            let (ok, comparison) = assertion.check(&jif);
+            if !args.quiet {
+                println!("{}: {}", if ok { "ok" } else { "violated" }, comparison);
+            }
+            holds &= ok;
+        }
+
+        return Ok(if holds {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(ASSERTION_FAILED)
+        });
     }
 
     if args.raw {
@@ -514,9 +953,9 @@ fn main() -> anyhow::Result<()> {
 
         let mut file = BufReader::new(File::open(&args.jif_file).context("failed to open file")?);
         let jif = JifRaw::from_reader(&mut file).context("failed to open jif in raw mode")?;
-        select_raw(jif, cmd)
+        select_raw(jif, cmd, file.get_ref())?;
     } else {
-        let cmd: MaterializedCommand = args.command.try_into().map_err(|e| {
+        let cmd: MaterializedCommand = args.command.clone().try_into().map_err(|e| {
             anyhow::anyhow!(
                 "failed to parse materialized selector command: {}\n{}",
                 e,
@@ -524,10 +963,9 @@ fn main() -> anyhow::Result<()> {
             )
         })?;
 
-        let mut file = BufReader::new(File::open(&args.jif_file).context("failed to open file")?);
-        let jif = Jif::from_reader(&mut file).context("failed to open jif")?;
-        select_materialized(jif, cmd)
+        let jif = read_jif(&args)?;
+        select_materialized(jif, cmd, &args)?;
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,159 @@
+//! Native rendering of the address space of a JIF as an SVG map
+//!
+//! The address space is sparse (the pheaders of a process are spread over the whole of it), so
+//! it is drawn as bands, one per pheader (in address order), each scaled to the width of the map.
+//! Each band is split into the logical intervals of the pheader, colored by their data source,
+//! and every band and interval has a tooltip with its range, size and (for the bands) pathname
+
+use jif::itree::interval::DataSource;
+use jif::Jif;
+
+use std::fmt::Write;
+
+/// Width of the bands
+const BAND_WIDTH: usize = 960;
+
+/// Height of a band
+const BAND_HEIGHT: usize = 20;
+
+/// Space between the bands
+const BAND_GAP: usize = 6;
+
+/// Approximate width of a character of the labels
+const CHAR_WIDTH: usize = 7;
+
+const MARGIN: usize = 20;
+const TITLE_HEIGHT: usize = 40;
+const LEGEND_HEIGHT: usize = 30;
+
+/// Color of the intervals of each data source
+fn color(source: DataSource) -> &'static str {
+    match source {
+        DataSource::Private => "#d62728",
+        DataSource::Shared => "#1f77b4",
+        DataSource::Zero => "#dddddd",
+    }
+}
+
+/// Label of a pheader: its range, protections and pathname
+fn pheader_label(jif: &Jif, idx: usize) -> String {
+    let pheader = &jif.pheaders()[idx];
+    let (start, end) = pheader.virtual_range();
+    match pheader.pathname() {
+        Some(path) => format!("[{:#x}; {:#x}) {} {}", start, end, pheader.prot(), path),
+        None => format!("[{:#x}; {:#x}) {}", start, end, pheader.prot()),
+    }
+}
+
+/// Render the map of the address space of the JIF
+pub(crate) fn render_map(title: &str, jif: &Jif) -> String {
+    let labels = (0..jif.pheaders().len())
+        .map(|idx| pheader_label(jif, idx))
+        .collect::<Vec<_>>();
+    let label_width = labels.iter().map(String::len).max().unwrap_or(0) * CHAR_WIDTH + MARGIN;
+
+    let bands_x = MARGIN + label_width;
+    let bands_y = TITLE_HEIGHT + LEGEND_HEIGHT;
+    let width = bands_x + BAND_WIDTH + MARGIN;
+    let height = bands_y + jif.pheaders().len() * (BAND_HEIGHT + BAND_GAP) + MARGIN;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="monospace" font-size="11">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{width}" height="{height}" fill="white"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle" font-size="16" font-family="sans-serif">{}</text>"#,
+        width / 2,
+        TITLE_HEIGHT / 2 + 6,
+        escape(title)
+    );
+
+    // legend
+    for (idx, source) in [DataSource::Private, DataSource::Shared, DataSource::Zero]
+        .into_iter()
+        .enumerate()
+    {
+        let x = bands_x + idx * 120;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{x}" y="{}" width="14" height="14" fill="{}" stroke="black" stroke-width="0.5"/>"#,
+            TITLE_HEIGHT,
+            color(source)
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">{:?}</text>"#,
+            x + 20,
+            TITLE_HEIGHT + 11,
+            source
+        );
+    }
+
+    for (row, (pheader, label)) in jif.pheaders().iter().zip(&labels).enumerate() {
+        let (start, end) = pheader.virtual_range();
+        let scale = BAND_WIDTH as f64 / (end - start).max(1) as f64;
+        let y = bands_y + row * (BAND_HEIGHT + BAND_GAP);
+
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+            bands_x - MARGIN / 2,
+            y + BAND_HEIGHT / 2 + 4,
+            escape(label)
+        );
+
+        let _ = writeln!(svg, "<g>");
+        let _ = writeln!(
+            svg,
+            "<title>{} ({:#x} B)</title>",
+            escape(label),
+            end - start
+        );
+        for ival in pheader.itree().iter_logical_intervals() {
+            let (ival_start, ival_end) = (ival.start.max(start), ival.end.min(end));
+            // the smallest intervals are still visible
+            let x = bands_x as f64 + (ival_start - start) as f64 * scale;
+            let ival_width = ((ival_end - ival_start) as f64 * scale).max(0.5);
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{x:.2}" y="{y}" width="{ival_width:.2}" height="{BAND_HEIGHT}" fill="{}"><title>[{:#x}; {:#x}) {:?}, {:#x} B</title></rect>"#,
+                color(ival.source),
+                ival_start,
+                ival_end,
+                ival.source,
+                ival_end - ival_start
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{bands_x}" y="{y}" width="{BAND_WIDTH}" height="{BAND_HEIGHT}" fill="none" stroke="black" stroke-width="0.5"/>"#
+        );
+        let _ = writeln!(svg, "</g>");
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Wrap the map into a standalone HTML page
+pub(crate) fn to_html(title: &str, svg: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        svg
+    )
+}
+
+/// Escape the XML special characters
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,9 +1,18 @@
+use crate::filter::*;
 use crate::utils::*;
 
 pub(crate) const MATERIALIZED_COMMAND_USAGE: &str = "materialized command: selection over the materialized JIF representation
 
 jif                                select the whole JIF
 jif.strings                        strings in the JIF
+jif.strings.usage                  strings by mapped size, with the number of pheaders and shared pages referencing them
+jif.arch                           architecture tag (instruction set, endianness and page size)
+jif.dedup                          statistics of the sharing of the private data segments
+jif.duplication                    unique and duplicated private pages, with the most duplicated page contents
+jif.footprint                      estimated memory footprint after restore and on-disk size, by pheader
+jif.prefetch                       prefetch layout the JIF gets when written with the prefetch set up
+jif.itree_stats                    logical interval sizes by data source and interval tree node fill, over every pheader
+jif.meta                           custom metadata, as key=value pairs
 jif.zero_pages                     number of zero pages
 jif.private_pages                  number of private pages in the JIF
 jif.shared_pages                   number of shared pages in the pheader
@@ -16,10 +25,14 @@ ord.size                           number of pages in the ordering section
 ord.private_pages                  number of private pages in the ordering section
 ord.shared_pages                   number of shared pages in the ordering section
 ord.zero_pages                     number of shared pages in the ordering section
+ord.write_pages                    number of pages in the ord chunks faulted in by a write
+ord.threads                        number of pages in the ordering section by faulting thread (when traced)
+ord.quality                        quality of the ordering: coverage of the private pages, chunk size, vma switches, mean stride and score
 
 pheader                            select all the pheaders
 pheader[<range>]                   select the pheaders in the range
-pheader.len                        number of pheaders
+pheader[<predicate>,...]           select the pheaders for which all the predicates hold
+pheader.len                        number of pheaders (mixable with predicates)
 pheader.data_size                  size of the data region (mixable with range and other selectors)
 pheader.pathname                   reference pathname (mixable with range and other selectors)
 pheader.ref_offset                 offset into the file
@@ -32,10 +45,42 @@ pheader.zero_pages                 number of zero pages
 pheader.private_pages              == data_size % PAGE_SIZE
 pheader.shared_pages               number of shared pages in the pheader
 pheader.pages                      total number of pages
+pheader.huge_mappable_pages        number of pages in 2MiB regions backed by a single source
+pheader.intervals                  logical intervals of the pheader (mixable with range and other selectors)
+pheader.itree_stats                logical interval sizes by data source and node fill (mixable with range and other selectors)
+pheader.entropy                    entropy of the private data, in bits per byte (sampled; mixable with range and other selectors)
+pheader.compressibility            estimated compression ratio of the private data (sampled; mixable with range and other selectors)
+
+intervals                          select all the logical intervals (of every pheader, sorted by address)
+intervals[<range>]                 select the logical intervals in the range
+intervals.len                      number of logical intervals
+
+addr[<vaddr>]                      resolve a virtual address: mapping pheader, interval, data source and backing file
+data[<start>..<end>]               logical bytes of an address range (the end may be <start>+<len>): raw, as a hexdump (--hex) or saved (--out)
+
+scan <pattern>                     search the private data (and the shared pages, with --shared) for a pattern: 0x<hex bytes> or a string
+plan                               restore plan: the mmap, memcpy, memset, madvise and mprotect operations of a loader, in order
+
+predicates: <field><op><value>, over the fields
+  vaddr, virtual_size, data_size, pathname, ref_offset, prot, n_itree_nodes,
+  zero_pages, private_pages, shared_pages, pages, huge_mappable_pages
+with the operators =, !=, <, <=, >, >= (numeric fields) and ~= (substring on pathname,
+containing protections on prot); e.g.: pheader[prot=rx], pheader[pathname~=libc],
+pheader[vaddr=0x7f0000000000], pheader[private_pages>100].prot
+
+modifiers (after a pheader selection and a space):
+  sort [<field>] [asc|desc]        sort the pheaders by a field (by default, the selected one)
+  top <N>                          keep the first N pheaders
+e.g.: pheader.private_pages sort desc top 10, pheader[prot=rw].pathname sort_by=data_size top=5
 ";
 
 #[derive(Debug)]
 pub(crate) enum MaterializedCommand {
+    Addr(u64),
+    Data(u64, u64),
+    Scan(Vec<u8>),
+    Plan,
+    Intervals(IntervalsCmd),
     Ord(OrdCmd),
     Pheader(PheaderCmd),
     Jif(JifCmd),
@@ -53,9 +98,23 @@ pub(crate) struct PageSelector {
 pub(crate) enum JifCmd {
     All,
     Strings,
+    StringsUsage,
+    Arch,
+    Dedup,
+    Duplication,
+    Footprint,
+    Prefetch,
+    ITreeStats,
+    Meta,
     Pages(PageSelector),
 }
 
+#[derive(Debug)]
+pub(crate) enum IntervalsCmd {
+    Range(IndexRange),
+    Len,
+}
+
 #[derive(Debug)]
 pub(crate) enum OrdCmd {
     All,
@@ -65,6 +124,9 @@ pub(crate) enum OrdCmd {
     PrivatePages,
     SharedPages,
     ZeroPages,
+    WritePages,
+    Threads,
+    Quality,
 }
 
 #[derive(Debug, Default)]
@@ -81,28 +143,42 @@ pub(crate) struct PheaderSelector {
     pub(crate) private_pages: bool,
     pub(crate) shared_pages: bool,
     pub(crate) pages: bool,
+    pub(crate) huge_mappable_pages: bool,
+    pub(crate) intervals: bool,
+    pub(crate) itree_stats: bool,
+    pub(crate) entropy: bool,
+    pub(crate) compressibility: bool,
 }
 
 #[derive(Debug)]
 pub(crate) enum PheaderCmd {
-    Len,
+    Len(PheaderFilter),
     Selector {
-        range: IndexRange,
+        filter: PheaderFilter,
         selector: PheaderSelector,
     },
-    All,
+    All(PheaderFilter),
 }
 
 pub(crate) const RAW_COMMAND_USAGE: &str = "raw command: selection over the raw JIF representation
 
 jif                                select the whole JIF
 jif.data                           size of the data section
+jif.arch                           architecture tag (instruction set, endianness and page size)
+jif.prefetch                       prefetched pages and the address ranges faulted in by a write or a read
+jif.file_size                      size of the file and the space allocated for it (less, when the file is sparse)
+jif.meta                           custom metadata, as key=value pairs
 
 strings                            select the strings in the JIF
+strings.usage                      strings by mapped size, with the number of pheaders and shared pages referencing them
+
+layout                             file offsets of each section and data segment (with the intervals it backs and the padding)
+data.provenance                    ordering chunk (with its tid and access, when traced) which placed each data segment
 
 itrees                             select all the interval trees
 itrees[<range>]                    select the interval trees in the range
 itrees.len                         number of interval trees
+itrees.by_pheader                  interval tree nodes grouped by the pheader owning them
 
 ord                                select all the ord chunks
 ord[<range>]                       select the ord chunks in the range
@@ -111,16 +187,29 @@ ord.size                           number of pages in the ordering section
 ord.private_pages                  number of private pages in the ordering section
 ord.shared_pages                   number of shared pages in the ordering section
 ord.zero_pages                     number of shared pages in the ordering section
+ord.write_pages                    number of pages in the ord chunks faulted in by a write
+ord.threads                        number of pages in the ordering section by faulting thread (when traced)
 
 pheader                            select all the pheaders
 pheader[<range>]                   select the pheaders in the range
-pheader.len                        number of pheaders
+pheader[<predicate>,...]           select the pheaders for which all the predicates hold
+pheader.len                        number of pheaders (mixable with predicates)
 pheader.pathname_offset            reference pathname (mixable with range and other selectors)
 pheader.ref_offset                 offset into the file
 pheader.virtual_range              virtual address range of the pheader (mixable with range and other selectors)
 pheader.virtual_size               size of the virtual address range (mixable with range and other selectors)
 pheader.prot                       area `rwx` protections (mixable with range and other selectors)
 pheader.itree                      show the interval tree offset and size in number of nodes (mixable with range and other selectors)
+pheader.itree_nodes                interval tree nodes of the pheader (mixable with range and other selectors)
+
+predicates: <field><op><value>, over the fields
+  vaddr, virtual_size, pathname_offset, ref_offset, prot
+with the operators =, !=, <, <=, >, >= (numeric fields) and ~= (containing protections on prot)
+
+modifiers (after a pheader selection and a space):
+  sort [<field>] [asc|desc]        sort the pheaders by a field (by default, the selected one)
+  top <N>                          keep the first N pheaders
+e.g.: pheader.virtual_size sort desc top 10
 ";
 
 #[derive(Debug)]
@@ -128,6 +217,9 @@ pub(crate) enum RawCommand {
     Ord(OrdCmd),
     Pheader(RawPheaderCmd),
     Strings,
+    StringsUsage,
+    Layout,
+    Provenance,
     ITree(ITreeCmd),
     Jif(RawJifCmd),
 }
@@ -136,6 +228,10 @@ pub(crate) enum RawCommand {
 pub(crate) enum RawJifCmd {
     All,
     Data,
+    Arch,
+    Prefetch,
+    FileSize,
+    Meta,
 }
 
 #[derive(Debug)]
@@ -143,6 +239,7 @@ pub(crate) enum ITreeCmd {
     All,
     Range(IndexRange),
     Len,
+    ByPheader,
 }
 
 #[derive(Debug, Default)]
@@ -153,16 +250,41 @@ pub(crate) struct RawPheaderSelector {
     pub(crate) ref_offset: bool,
     pub(crate) prot: bool,
     pub(crate) itree: bool,
+    pub(crate) itree_nodes: bool,
 }
 
 #[derive(Debug)]
 pub(crate) enum RawPheaderCmd {
-    Len,
+    Len(PheaderFilter),
     Selector {
-        range: IndexRange,
+        filter: PheaderFilter,
         selector: RawPheaderSelector,
     },
-    All,
+    All(PheaderFilter),
+}
+
+/// The field of the only selected option which is a pheader field, if any (the default field to
+/// sort by)
+fn selected_field(
+    found_options: &std::collections::HashSet<usize>,
+    options: &[&str],
+    fields: &[(&str, PheaderField)],
+) -> Option<PheaderField> {
+    let mut selected = found_options.iter().filter_map(|idx| {
+        let name = match options[*idx] {
+            ".virtual_range" => "vaddr",
+            option => option.strip_prefix('.')?,
+        };
+        fields
+            .iter()
+            .find(|(field_name, _field)| *field_name == name)
+            .map(|(_name, field)| *field)
+    });
+
+    match (selected.next(), selected.next()) {
+        (Some(field), None) => Some(field),
+        _ => None,
+    }
 }
 
 impl TryFrom<Option<String>> for MaterializedCommand {
@@ -182,6 +304,14 @@ impl TryFrom<Option<String>> for MaterializedCommand {
                         ".private_pages", // 3
                         ".shared_pages",  // 4
                         ".pages",         // 5
+                        ".arch",          // 6
+                        ".dedup",         // 7
+                        ".footprint",     // 8
+                        ".prefetch",      // 9
+                        ".itree_stats",   // 10
+                        ".meta",          // 11
+                        ".strings.usage", // 12
+                        ".duplication",   // 13
                     ];
                     let found_options = find_multiple_option(trimmed, suffix, &options)?;
 
@@ -195,6 +325,70 @@ impl TryFrom<Option<String>> for MaterializedCommand {
                         }
 
                         MaterializedCommand::Jif(JifCmd::Strings)
+                    } else if found_options.contains(&12) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "strings usage option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::StringsUsage)
+                    } else if found_options.contains(&6) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "arch option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::Arch)
+                    } else if found_options.contains(&7) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "dedup option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::Dedup)
+                    } else if found_options.contains(&13) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "duplication option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::Duplication)
+                    } else if found_options.contains(&8) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "footprint option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::Footprint)
+                    } else if found_options.contains(&9) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "prefetch option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::Prefetch)
+                    } else if found_options.contains(&10) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "itree_stats option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::ITreeStats)
+                    } else if found_options.contains(&11) {
+                        if found_options.len() > 1 {
+                            return Err(anyhow::anyhow!(
+                                "meta option is incompatible with the other options"
+                            ));
+                        }
+
+                        MaterializedCommand::Jif(JifCmd::Meta)
                     } else {
                         let mut selector = PageSelector::default();
                         if found_options.contains(&2) {
@@ -234,6 +428,9 @@ impl TryFrom<Option<String>> for MaterializedCommand {
                             ".private_pages",
                             ".shared_pages",
                             ".zero_pages",
+                            ".write_pages",
+                            ".threads",
+                            ".quality",
                         ];
                         let idx = find_single_option(trimmed, suffix, &options)?;
                         if options[idx] == ".len" {
@@ -246,42 +443,117 @@ impl TryFrom<Option<String>> for MaterializedCommand {
                             MaterializedCommand::Ord(OrdCmd::SharedPages)
                         } else if options[idx] == ".zero_pages" {
                             MaterializedCommand::Ord(OrdCmd::ZeroPages)
+                        } else if options[idx] == ".write_pages" {
+                            MaterializedCommand::Ord(OrdCmd::WritePages)
+                        } else if options[idx] == ".threads" {
+                            MaterializedCommand::Ord(OrdCmd::Threads)
+                        } else if options[idx] == ".quality" {
+                            MaterializedCommand::Ord(OrdCmd::Quality)
                         } else {
                             MaterializedCommand::Ord(OrdCmd::All)
                         }
                     }
-                } else if trimmed.starts_with("pheader") {
-                    let (_prefix, suffix) = trimmed.split_at("pheader".len());
+                } else if trimmed.starts_with("intervals") {
+                    let (_prefix, suffix) = trimmed.split_at("intervals".len());
                     let (range, suffix) = find_range(trimmed, suffix)?;
 
+                    if range.is_some() {
+                        if !suffix.is_empty() {
+                            return Err(anyhow::anyhow!(
+                                "trailing data after range in {}: {}",
+                                trimmed,
+                                suffix
+                            ));
+                        }
+
+                        MaterializedCommand::Intervals(IntervalsCmd::Range(range))
+                    } else {
+                        let options = ["", ".len"];
+                        let idx = find_single_option(trimmed, suffix, &options)?;
+                        if options[idx] == ".len" {
+                            MaterializedCommand::Intervals(IntervalsCmd::Len)
+                        } else {
+                            MaterializedCommand::Intervals(IntervalsCmd::Range(IndexRange::None))
+                        }
+                    }
+                } else if trimmed.starts_with("addr") {
+                    let (_prefix, suffix) = trimmed.split_at("addr".len());
+                    let addr = suffix
+                        .strip_prefix('[')
+                        .and_then(|s| s.strip_suffix(']'))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("expected address in brackets in {}", trimmed)
+                        })?;
+
+                    MaterializedCommand::Addr(parse_int(addr.trim())?)
+                } else if trimmed.starts_with("data") {
+                    let (_prefix, suffix) = trimmed.split_at("data".len());
+                    let range = suffix
+                        .strip_prefix('[')
+                        .and_then(|s| s.strip_suffix(']'))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("expected address range in brackets in {}", trimmed)
+                        })?;
+
+                    let (start, end) = parse_addr_range(range)?;
+                    MaterializedCommand::Data(start, end)
+                } else if trimmed.starts_with("scan") {
+                    let (_prefix, suffix) = trimmed.split_at("scan".len());
+                    if !suffix.starts_with(char::is_whitespace) {
+                        return Err(anyhow::anyhow!(
+                            "expected a pattern to scan for in {}",
+                            trimmed
+                        ));
+                    }
+
+                    MaterializedCommand::Scan(parse_pattern(suffix.trim())?)
+                } else if trimmed == "plan" {
+                    MaterializedCommand::Plan
+                } else if trimmed.starts_with("pheader") {
+                    let (selection, modifiers) = split_modifiers(trimmed);
+                    let (_prefix, suffix) = selection.split_at("pheader".len());
+                    let (mut filter, suffix) =
+                        PheaderFilter::find(selection, suffix, MATERIALIZED_FIELDS)?;
+
                     let options = [
-                        "",               // 0
-                        ".len",           // 1
-                        ".virtual_range", // 2
-                        ".virtual_size",  // 3
-                        ".data_size",     // 4
-                        ".pathname",      // 5
-                        ".ref_offset",    // 6
-                        ".prot",          // 7
-                        ".itree",         // 8
-                        ".n_itree_nodes", // 9
-                        ".zero_pages",    // 10
-                        ".private_pages", // 11
-                        ".shared_pages",  // 12
-                        ".pages",         // 13
+                        "",                     // 0
+                        ".len",                 // 1
+                        ".virtual_range",       // 2
+                        ".virtual_size",        // 3
+                        ".data_size",           // 4
+                        ".pathname",            // 5
+                        ".ref_offset",          // 6
+                        ".prot",                // 7
+                        ".itree",               // 8
+                        ".n_itree_nodes",       // 9
+                        ".zero_pages",          // 10
+                        ".private_pages",       // 11
+                        ".shared_pages",        // 12
+                        ".pages",               // 13
+                        ".huge_mappable_pages", // 14
+                        ".intervals",           // 15
+                        ".entropy",             // 16
+                        ".compressibility",     // 17
+                        ".itree_stats",         // 18
                     ];
-                    let found_options = find_multiple_option(trimmed, suffix, &options)?;
+                    let found_options = find_multiple_option(selection, suffix, &options)?;
+                    filter.parse_modifiers(
+                        trimmed,
+                        modifiers,
+                        MATERIALIZED_FIELDS,
+                        selected_field(&found_options, &options, MATERIALIZED_FIELDS),
+                    )?;
 
                     if found_options.contains(&0) {
-                        MaterializedCommand::Pheader(PheaderCmd::All)
+                        MaterializedCommand::Pheader(PheaderCmd::All(filter))
                     } else if found_options.contains(&1) {
-                        if range.is_some() || found_options.len() > 1 {
+                        if filter.range.is_some() || found_options.len() > 1 {
                             return Err(anyhow::anyhow!(
                                 "length option is incompatible with the other options"
                             ));
                         }
 
-                        MaterializedCommand::Pheader(PheaderCmd::Len)
+                        MaterializedCommand::Pheader(PheaderCmd::Len(filter))
                     } else {
                         let mut selector = PheaderSelector::default();
 
@@ -321,8 +593,23 @@ impl TryFrom<Option<String>> for MaterializedCommand {
                         if found_options.contains(&13) {
                             selector.pages = true;
                         }
+                        if found_options.contains(&14) {
+                            selector.huge_mappable_pages = true;
+                        }
+                        if found_options.contains(&15) {
+                            selector.intervals = true;
+                        }
+                        if found_options.contains(&16) {
+                            selector.entropy = true;
+                        }
+                        if found_options.contains(&17) {
+                            selector.compressibility = true;
+                        }
+                        if found_options.contains(&18) {
+                            selector.itree_stats = true;
+                        }
 
-                        MaterializedCommand::Pheader(PheaderCmd::Selector { range, selector })
+                        MaterializedCommand::Pheader(PheaderCmd::Selector { filter, selector })
                     }
                 } else {
                     return Err(anyhow::anyhow!("unknown selector {}", trimmed));
@@ -343,20 +630,44 @@ impl TryFrom<Option<String>> for RawCommand {
                 if trimmed.starts_with("jif") {
                     let (_prefix, suffix) = trimmed.split_at("jif".len());
 
-                    let options = ["", ".data"];
+                    let options = ["", ".data", ".arch", ".prefetch", ".file_size", ".meta"];
                     let idx = find_single_option(trimmed, suffix, &options)?;
 
                     if options[idx] == ".data" {
                         RawCommand::Jif(RawJifCmd::Data)
+                    } else if options[idx] == ".arch" {
+                        RawCommand::Jif(RawJifCmd::Arch)
+                    } else if options[idx] == ".prefetch" {
+                        RawCommand::Jif(RawJifCmd::Prefetch)
+                    } else if options[idx] == ".file_size" {
+                        RawCommand::Jif(RawJifCmd::FileSize)
+                    } else if options[idx] == ".meta" {
+                        RawCommand::Jif(RawJifCmd::Meta)
                     } else {
                         RawCommand::Jif(RawJifCmd::All)
                     }
                 } else if trimmed.starts_with("strings") {
                     let (_prefix, suffix) = trimmed.split_at("strings".len());
 
+                    let options = ["", ".usage"];
+                    let idx = find_single_option(trimmed, suffix, &options)?;
+                    if options[idx] == ".usage" {
+                        RawCommand::StringsUsage
+                    } else {
+                        RawCommand::Strings
+                    }
+                } else if trimmed.starts_with("layout") {
+                    let (_prefix, suffix) = trimmed.split_at("layout".len());
+
                     let options = [""];
                     let _idx = find_single_option(trimmed, suffix, &options)?;
-                    RawCommand::Strings
+                    RawCommand::Layout
+                } else if trimmed.starts_with("data") {
+                    let (_prefix, suffix) = trimmed.split_at("data".len());
+
+                    let options = [".provenance"];
+                    let _idx = find_single_option(trimmed, suffix, &options)?;
+                    RawCommand::Provenance
                 } else if trimmed.starts_with("ord") {
                     let (_prefix, suffix) = trimmed.split_at("ord".len());
                     let (range, suffix) = find_range(trimmed, suffix)?;
@@ -379,6 +690,8 @@ impl TryFrom<Option<String>> for RawCommand {
                             ".private_pages",
                             ".shared_pages",
                             ".zero_pages",
+                            ".write_pages",
+                            ".threads",
                         ];
                         let idx = find_single_option(trimmed, suffix, &options)?;
                         if options[idx] == ".len" {
@@ -391,12 +704,22 @@ impl TryFrom<Option<String>> for RawCommand {
                             RawCommand::Ord(OrdCmd::SharedPages)
                         } else if options[idx] == ".zero_pages" {
                             RawCommand::Ord(OrdCmd::ZeroPages)
+                        } else if options[idx] == ".write_pages" {
+                            RawCommand::Ord(OrdCmd::WritePages)
+                        } else if options[idx] == ".threads" {
+                            RawCommand::Ord(OrdCmd::Threads)
                         } else {
                             RawCommand::Ord(OrdCmd::All)
                         }
                     }
                 } else if trimmed.starts_with("itree") {
-                    let (_prefix, suffix) = trimmed.split_at("itree".len());
+                    // `itrees` as documented (`itree` is kept for the older scripts)
+                    let prefix = if trimmed.starts_with("itrees") {
+                        "itrees"
+                    } else {
+                        "itree"
+                    };
+                    let (_prefix, suffix) = trimmed.split_at(prefix.len());
                     let (range, suffix) = find_range(trimmed, suffix)?;
 
                     if range.is_some() {
@@ -410,17 +733,20 @@ impl TryFrom<Option<String>> for RawCommand {
 
                         RawCommand::ITree(ITreeCmd::Range(range))
                     } else {
-                        let options = ["", ".len"];
+                        let options = ["", ".len", ".by_pheader"];
                         let idx = find_single_option(trimmed, suffix, &options)?;
                         if options[idx] == ".len" {
                             RawCommand::ITree(ITreeCmd::Len)
+                        } else if options[idx] == ".by_pheader" {
+                            RawCommand::ITree(ITreeCmd::ByPheader)
                         } else {
                             RawCommand::ITree(ITreeCmd::All)
                         }
                     }
                 } else if trimmed.starts_with("pheader") {
-                    let (_prefix, suffix) = trimmed.split_at("pheader".len());
-                    let (range, suffix) = find_range(trimmed, suffix)?;
+                    let (selection, modifiers) = split_modifiers(trimmed);
+                    let (_prefix, suffix) = selection.split_at("pheader".len());
+                    let (mut filter, suffix) = PheaderFilter::find(selection, suffix, RAW_FIELDS)?;
 
                     let options = [
                         "",                 // 0
@@ -431,19 +757,26 @@ impl TryFrom<Option<String>> for RawCommand {
                         ".ref_offset",      // 5
                         ".prot",            // 6
                         ".itree",           // 7
+                        ".itree_nodes",     // 8
                     ];
-                    let found_options = find_multiple_option(trimmed, suffix, &options)?;
+                    let found_options = find_multiple_option(selection, suffix, &options)?;
+                    filter.parse_modifiers(
+                        trimmed,
+                        modifiers,
+                        RAW_FIELDS,
+                        selected_field(&found_options, &options, RAW_FIELDS),
+                    )?;
 
                     if found_options.contains(&0) {
-                        RawCommand::Pheader(RawPheaderCmd::All)
+                        RawCommand::Pheader(RawPheaderCmd::All(filter))
                     } else if found_options.contains(&1) {
-                        if range.is_some() || found_options.len() > 1 {
+                        if filter.range.is_some() || found_options.len() > 1 {
                             return Err(anyhow::anyhow!(
                                 "length option is incompatible with the other options"
                             ));
                         }
 
-                        RawCommand::Pheader(RawPheaderCmd::Len)
+                        RawCommand::Pheader(RawPheaderCmd::Len(filter))
                     } else {
                         let mut selector = RawPheaderSelector::default();
 
@@ -465,8 +798,11 @@ impl TryFrom<Option<String>> for RawCommand {
                         if found_options.contains(&7) {
                             selector.itree = true;
                         }
+                        if found_options.contains(&8) {
+                            selector.itree_nodes = true;
+                        }
 
-                        RawCommand::Pheader(RawPheaderCmd::Selector { range, selector })
+                        RawCommand::Pheader(RawPheaderCmd::Selector { filter, selector })
                     }
                 } else {
                     return Err(anyhow::anyhow!("unknown selector {}", trimmed));
@@ -1,4 +1,6 @@
-use std::collections::HashSet;
+use jif::ord::OrdChunk;
+
+use std::collections::{BTreeMap, HashSet};
 
 #[derive(Debug)]
 pub(crate) enum IndexRange {
@@ -13,6 +15,88 @@ impl IndexRange {
     pub(crate) fn is_some(&self) -> bool {
         !matches!(self, IndexRange::None)
     }
+
+    /// The items in the range (out of bounds ranges are clamped)
+    pub(crate) fn slice<'a, T>(&self, items: &'a [T]) -> &'a [T] {
+        let (start, end) = match *self {
+            IndexRange::None => (0, items.len()),
+            IndexRange::LeftOpen { end } => (0, end),
+            IndexRange::RightOpen { start } => (start, items.len()),
+            IndexRange::Closed { start, end } => (start, end),
+            IndexRange::Index(idx) => (idx, idx.saturating_add(1)),
+        };
+        let end = std::cmp::min(end, items.len());
+        &items[std::cmp::min(start, end)..end]
+    }
+}
+
+/// Number of pages in the ord chunks faulted in by each thread (the chunks without a thread id
+/// are left out)
+pub(crate) fn pages_by_thread(ords: &[OrdChunk]) -> BTreeMap<u32, u64> {
+    let mut pages = BTreeMap::new();
+    for ord in ords {
+        if let Some(tid) = ord.tid() {
+            *pages.entry(tid).or_default() += ord.size();
+        }
+    }
+
+    pages
+}
+
+/// Parse a scan pattern: hexadecimal bytes with a `0x` prefix, or else a string
+pub(crate) fn parse_pattern(s: &str) -> anyhow::Result<Vec<u8>> {
+    let Some(hex) = s.strip_prefix("0x") else {
+        return Ok(s.as_bytes().to_vec());
+    };
+
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return Err(anyhow::anyhow!(
+            "expected an even number of hexadecimal digits in {}",
+            s
+        ));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| {
+            u8::from_str_radix(&hex[idx..idx + 2], 16)
+                .map_err(|e| anyhow::anyhow!("failed to parse pattern {}: {}", s, e))
+        })
+        .collect()
+}
+
+/// Parse an integer (decimal, or hexadecimal with a `0x` prefix)
+pub(crate) fn parse_int(s: &str) -> anyhow::Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse::<u64>(),
+    }
+    .map_err(|e| anyhow::anyhow!("failed to parse integer {}: {}", s, e))
+}
+
+/// Parse an address range: `<start>..<end>`, where the end may also be given as `<start>+<len>`
+/// (or just `+<len>`)
+pub(crate) fn parse_addr_range(s: &str) -> anyhow::Result<(u64, u64)> {
+    let (start_str, end_str) = s
+        .split_once("..")
+        .ok_or_else(|| anyhow::anyhow!("expected an address range in {}", s))?;
+    let start = parse_int(start_str.trim())?;
+    let end = match end_str.split_once('+') {
+        Some((base, len)) => {
+            let base = match base.trim() {
+                "" => start,
+                base => parse_int(base)?,
+            };
+            base.checked_add(parse_int(len.trim())?)
+                .ok_or_else(|| anyhow::anyhow!("end of the address range {} overflows", s))?
+        }
+        None => parse_int(end_str.trim())?,
+    };
+
+    if end < start {
+        return Err(anyhow::anyhow!("address range {} ends before it starts", s));
+    }
+
+    Ok((start, end))
 }
 
 /// Finds if a single option follows the prefix on the string
@@ -71,16 +155,15 @@ pub(crate) fn find_multiple_option(
     while cursor < suffix.len() {
         let old_cursor = cursor;
 
-        for (idx, opt) in options
+        // the longest option wins (e.g., `.itree_stats` over `.itree`)
+        if let Some((idx, opt)) = options
             .iter()
             .enumerate()
-            .filter(|(_, opt)| !opt.is_empty())
+            .filter(|(_, opt)| !opt.is_empty() && suffix[cursor..].starts_with(*opt))
+            .max_by_key(|(_, opt)| opt.len())
         {
-            if suffix[cursor..].starts_with(opt) {
-                found_options.insert(idx);
-                cursor += opt.len();
-                break;
-            }
+            found_options.insert(idx);
+            cursor += opt.len();
         }
 
         if cursor == old_cursor {
@@ -112,7 +195,7 @@ pub(crate) fn find_range<'a>(
 
     if let Some((range, suffix)) = suffix.split_once(']') {
         if let Some((start_str, end_str)) = range.split_once("..") {
-            let start_opt = (start_str.is_empty())
+            let start_opt = (!start_str.is_empty())
                 .then(|| {
                     start_str.parse::<usize>().map_err(|e| {
                         anyhow::anyhow!(
@@ -125,7 +208,7 @@ pub(crate) fn find_range<'a>(
                 })
                 .transpose()?;
 
-            let end_opt = (end_str.is_empty())
+            let end_opt = (!end_str.is_empty())
                 .then(|| {
                     end_str.parse::<usize>().map_err(|e| {
                         anyhow::anyhow!(
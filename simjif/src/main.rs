@@ -0,0 +1,249 @@
+//! # `simjif`
+//!
+//! A tool to simulate the prefetcher, to evaluate the quality of an ordering section without
+//! running junction
+//!
+//! The prefetcher is modelled as a sequential read of the pages in the ordering section (in
+//! order), at a fixed bandwidth: private and shared pages have to be read, while zero pages are
+//! ready as soon as the prefetcher reaches them. Each (first) access in the trace is then either
+//! a hit (the page was already prefetched), a late hit (the page is in the ordering section but
+//! was not prefetched yet) or a demand fault (the page is not in the ordering section)
+//!
+//! Example usage:
+//! ```sh
+//! $ simjif a.jif a.ord
+//! $ simjif --bandwidth 200 --timeline a.jif a.ord # simulate a slower disk, print a timeline
+//! ```
+
+use jif::*;
+use tracer_format::*;
+
+use jif::itree::interval::DataSource;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+
+use anyhow::Context;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// simjif: simulate the prefetcher over a memory trace
+struct Cli {
+    /// JIF file to read from (with an ordering section)
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    jif_file: std::path::PathBuf,
+
+    /// Ordering file outputted by junction_run --trace
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    ord_file: std::path::PathBuf,
+
+    /// Bandwidth of the prefetcher (in MiB/s)
+    #[arg(long, default_value_t = 1024.0)]
+    bandwidth: f64,
+
+    /// Time at which the prefetcher starts (in usecs, relative to the trace)
+    #[arg(long, default_value_t = 0)]
+    delay: usize,
+
+    /// Print a timeline of the accesses
+    #[arg(long)]
+    timeline: bool,
+
+    /// Width of the buckets of the timeline (in usecs)
+    #[arg(long, default_value_t = 1000, requires = "timeline")]
+    bucket: usize,
+}
+
+/// Outcome of an access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// The page was prefetched before the access
+    Hit,
+
+    /// The page is in the ordering section, but was not prefetched yet
+    Late,
+
+    /// The page is not in the ordering section
+    Demand,
+}
+
+/// Simulated prefetcher: the time (in usecs) at which each page in the ordering section is ready
+struct Prefetcher {
+    ready_at: HashMap<u64, f64>,
+
+    /// Time at which the prefetcher is done
+    done_at: f64,
+}
+
+impl Prefetcher {
+    fn simulate(jif: &Jif, bandwidth_mib: f64, delay: usize) -> Self {
+        let bytes_per_usec = bandwidth_mib * (1 << 20) as f64 / 1_000_000.0;
+        let page_cost = jif.page_size() as f64 / bytes_per_usec;
+
+        let mut ready_at = HashMap::new();
+        let mut now = delay as f64;
+        for chunk in jif.ord_chunks() {
+            for page in chunk.pages(jif.page_size()) {
+                if ready_at.contains_key(&page) {
+                    continue;
+                }
+
+                if chunk.kind() != DataSource::Zero {
+                    now += page_cost;
+                }
+                ready_at.insert(page, now);
+            }
+        }
+
+        Prefetcher {
+            ready_at,
+            done_at: now,
+        }
+    }
+
+    /// Outcome of an access, along with how long it would wait for the prefetcher
+    fn access(&self, entry: &TimestampedAccess) -> (Outcome, f64) {
+        match self.ready_at.get(&(entry.addr as u64)) {
+            None => (Outcome::Demand, 0.0),
+            Some(ready) if *ready <= entry.usecs as f64 => (Outcome::Hit, 0.0),
+            Some(ready) => (Outcome::Late, ready - entry.usecs as f64),
+        }
+    }
+}
+
+/// Per outcome counters
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    hits: usize,
+    late: usize,
+    demand: usize,
+}
+
+impl Counts {
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Hit => self.hits += 1,
+            Outcome::Late => self.late += 1,
+            Outcome::Demand => self.demand += 1,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.hits + self.late + self.demand
+    }
+}
+
+fn percentage(parcel: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        (parcel * 100) as f64 / total as f64
+    }
+}
+
+/// Simulate the prefetcher over the trace, printing out the results
+fn simulate(jif: &Jif, tsa: &[TimestampedAccess], cli: &Cli) {
+    let prefetcher = Prefetcher::simulate(jif, cli.bandwidth, cli.delay);
+
+    let mut counts = Counts::default();
+    let mut wait = 0.0;
+    let mut buckets: Vec<Counts> = Vec::new();
+    for entry in tsa {
+        let (outcome, waited) = prefetcher.access(entry);
+        counts.add(outcome);
+        wait += waited;
+
+        if cli.timeline {
+            let bucket = entry.usecs / cli.bucket;
+            if buckets.len() <= bucket {
+                buckets.resize(bucket + 1, Counts::default());
+            }
+            buckets[bucket].add(outcome);
+        }
+    }
+
+    let accessed = tsa
+        .iter()
+        .map(|entry| entry.addr as u64)
+        .collect::<std::collections::HashSet<_>>();
+    let unused = prefetcher
+        .ready_at
+        .keys()
+        .filter(|page| !accessed.contains(page))
+        .count();
+
+    let total = counts.total();
+    println!("accesses:        {:8}", total);
+    println!(
+        "hits:            {:8} ({:4.1}%)",
+        counts.hits,
+        percentage(counts.hits, total)
+    );
+    println!(
+        "late hits:       {:8} ({:4.1}%), waiting {:.0} us in total",
+        counts.late,
+        percentage(counts.late, total),
+        wait
+    );
+    println!(
+        "demand faults:   {:8} ({:4.1}%)",
+        counts.demand,
+        percentage(counts.demand, total)
+    );
+    println!(
+        "prefetched:      {:8} pages, done at {:.0} us ({} never accessed)",
+        prefetcher.ready_at.len(),
+        prefetcher.done_at,
+        unused
+    );
+
+    if cli.timeline {
+        let mut ready = prefetcher.ready_at.values().copied().collect::<Vec<_>>();
+        ready.sort_by(f64::total_cmp);
+
+        println!();
+        println!(
+            "{:>10} | {:>8} | {:>8} | {:>8} | {:>10}",
+            "time (us)", "hits", "late", "demand", "prefetched"
+        );
+        for (idx, bucket) in buckets.iter().enumerate() {
+            let end = ((idx + 1) * cli.bucket) as f64;
+            let prefetched = ready.partition_point(|ready| *ready <= end);
+            println!(
+                "{:>10} | {:8} | {:8} | {:8} | {:10}",
+                idx * cli.bucket,
+                bucket.hits,
+                bucket.late,
+                bucket.demand,
+                prefetched
+            );
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let jif = Jif::from_reader(&mut BufReader::new(
+        File::open(&cli.jif_file).context("failed to open file")?,
+    ))
+    .context("failed to read jif")?;
+
+    if cli.bandwidth <= 0.0 {
+        anyhow::bail!("the bandwidth has to be positive");
+    }
+    if jif.ord_chunks().is_empty() {
+        eprintln!("warning: the JIF has no ordering section, every access is a demand fault");
+    }
+
+    let trace = {
+        let file = BufReader::new(File::open(&cli.ord_file).context("failed to open ord list")?);
+        let trace = read_trace(file).context("failed to read the trace")?;
+
+        Ok::<Vec<TimestampedAccess>, anyhow::Error>(dedup_and_sort(trace))
+    }?;
+
+    simulate(&jif, &trace, &cli);
+    Ok(())
+}
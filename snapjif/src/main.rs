@@ -0,0 +1,61 @@
+//! # `snapjif`
+//!
+//! A tool to snapshot a live Linux process into a JIF file
+//!
+//! Example usage:
+//! ```sh
+//! $ snapjif 1234 proc.jif # snapshot process 1234
+//! ```
+//!
+//! The process should be stopped while it is snapshotted (e.g., with `kill -STOP`)
+
+use jif::*;
+
+use std::fs::File;
+use std::io::BufWriter;
+
+use anyhow::Context;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// snapjif: snapshot a live process into a JIF file
+///
+/// Reads the address space of the process from `/proc/<pid>/{maps,pagemap,mem}`: file backed
+/// private mappings become reference pheaders (with the pages copied on write as private data),
+/// the remaining mappings become anonymous pheaders
+struct Cli {
+    /// Process to snapshot
+    pid: u32,
+
+    /// Output file path
+    #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    output_file: std::path::PathBuf,
+
+    /// Write an integrity section (data segment and file checksums)
+    #[arg(long)]
+    checksums: bool,
+
+    /// Whether to print out the resulting JIF
+    #[arg(long)]
+    show: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Cli::parse();
+
+    let jif =
+        capture::capture(args.pid).context(format!("failed to capture process {}", args.pid))?;
+
+    let mut output_file =
+        BufWriter::new(File::create(&args.output_file).context("failed to open output JIF")?);
+    let mut raw = JifRaw::from_materialized(jif, false);
+    raw.set_checksums(args.checksums);
+
+    if args.show {
+        println!("{:#x?}", raw);
+    }
+    raw.to_writer(&mut output_file)
+        .context("failed to write JIF")?;
+    Ok(())
+}
@@ -0,0 +1,283 @@
+//! # `statsjif`
+//!
+//! A tool to aggregate the summary metrics of a fleet of JIF files
+//!
+//! The directories are walked (recursively) for JIF files, and each file is summarized by its
+//! pages by kind, interval tree nodes, ordering section (chunks, pages and coverage of the
+//! private pages) and data size. The metrics are aggregated over the files (total, mean, min,
+//! median, 90th and 99th percentiles and max): the per-file metrics or the aggregates are emitted
+//! as CSV, or both as JSON. Files which fail to parse are reported (and skipped)
+//!
+//! Example usage:
+//! ```sh
+//! $ statsjif snapshots/ # per-file metrics, as CSV
+//! $ statsjif --summary snapshots/ # aggregates of each metric, as CSV
+//! $ statsjif --format json snapshots/ other/a.jif > fleet.json # both, as JSON
+//! ```
+
+mod summary;
+
+use crate::summary::*;
+
+use jif::*;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// statsjif: aggregate summary metrics over directories of JIF files
+struct Cli {
+    /// Directories to walk for JIF files (or JIF files)
+    #[arg(value_name = "PATH", required = true, value_hint = clap::ValueHint::AnyPath)]
+    paths: Vec<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Emit the aggregates of each metric instead of the per-file metrics (CSV only: JSON has both)
+    #[arg(long)]
+    summary: bool,
+
+    /// Extension of the JIF files, when walking the directories
+    #[arg(long, default_value = "jif")]
+    extension: String,
+}
+
+/// Output formats
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// Collect the files with the extension under a directory (recursively)
+fn walk(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+    {
+        let path = entry
+            .with_context(|| format!("failed to read {}", dir.display()))?
+            .path();
+        if path.is_dir() {
+            walk(&path, extension, files)?;
+        } else if path.extension().is_some_and(|ext| ext == extension) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a JIF file and compute its metrics
+fn file_stats(path: &Path) -> anyhow::Result<FileStats> {
+    let file = File::open(path).context("failed to open file")?;
+    let file_size = file
+        .metadata()
+        .context("failed to look up the file size")?
+        .len();
+    let jif = Jif::from_reader(&mut BufReader::new(file)).context("failed to open jif")?;
+    Ok(FileStats::new(path.to_path_buf(), file_size, &jif))
+}
+
+/// Compute the metrics of the files, spreading them over the available cores (preserving their
+/// order)
+fn all_file_stats(files: &[PathBuf]) -> Vec<anyhow::Result<FileStats>> {
+    let n_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = std::cmp::max(1, files.len().div_ceil(n_threads));
+    std::thread::scope(|scope| {
+        let handles = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(|path| file_stats(path))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Quote a CSV field, if needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Quote a JSON string
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write the per-file metrics as CSV
+fn write_csv(w: &mut impl Write, stats: &[FileStats]) -> std::io::Result<()> {
+    let names = METRICS.iter().map(|(name, _metric)| *name);
+    writeln!(
+        w,
+        "{}",
+        std::iter::once("path")
+            .chain(names)
+            .collect::<Vec<_>>()
+            .join(",")
+    )?;
+    for file in stats {
+        let values = METRICS
+            .iter()
+            .map(|(_name, metric)| value_str(metric(file)));
+        writeln!(
+            w,
+            "{}",
+            std::iter::once(csv_field(&file.path.to_string_lossy()))
+                .chain(values)
+                .collect::<Vec<_>>()
+                .join(",")
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the aggregates of each metric as CSV
+fn write_summary_csv(w: &mut impl Write, stats: &[FileStats]) -> std::io::Result<()> {
+    let names = AGGREGATES.iter().map(|(name, _aggregate)| *name);
+    writeln!(
+        w,
+        "{}",
+        std::iter::once("metric")
+            .chain(names)
+            .collect::<Vec<_>>()
+            .join(",")
+    )?;
+    for (name, aggregate) in aggregate(stats) {
+        let values = AGGREGATES
+            .iter()
+            .map(|(_name, value)| value_str(value(&aggregate)));
+        writeln!(
+            w,
+            "{}",
+            std::iter::once(name.to_string())
+                .chain(values)
+                .collect::<Vec<_>>()
+                .join(",")
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the per-file metrics, the aggregates and the files which failed to parse as JSON
+fn write_json(
+    w: &mut impl Write,
+    stats: &[FileStats],
+    failed: &[(PathBuf, anyhow::Error)],
+) -> std::io::Result<()> {
+    writeln!(w, "{{")?;
+    writeln!(w, "  \"files\": [")?;
+    for (idx, file) in stats.iter().enumerate() {
+        let fields = std::iter::once(format!(
+            "\"path\": {}",
+            json_string(&file.path.to_string_lossy())
+        ))
+        .chain(
+            METRICS
+                .iter()
+                .map(|(name, metric)| format!("\"{}\": {}", name, value_str(metric(file)))),
+        )
+        .collect::<Vec<_>>();
+        let comma = if idx + 1 < stats.len() { "," } else { "" };
+        writeln!(w, "    {{ {} }}{}", fields.join(", "), comma)?;
+    }
+    writeln!(w, "  ],")?;
+
+    writeln!(w, "  \"failed\": [")?;
+    for (idx, (path, error)) in failed.iter().enumerate() {
+        let comma = if idx + 1 < failed.len() { "," } else { "" };
+        writeln!(
+            w,
+            "    {{ \"path\": {}, \"error\": {} }}{}",
+            json_string(&path.to_string_lossy()),
+            json_string(&format!("{:#}", error)),
+            comma
+        )?;
+    }
+    writeln!(w, "  ],")?;
+
+    writeln!(w, "  \"summary\": {{")?;
+    writeln!(w, "    \"n_files\": {},", stats.len())?;
+    writeln!(w, "    \"n_failed\": {},", failed.len())?;
+    let aggregates = aggregate(stats);
+    for (idx, (name, aggregate)) in aggregates.iter().enumerate() {
+        let fields = AGGREGATES
+            .iter()
+            .map(|(field, value)| format!("\"{}\": {}", field, value_str(value(aggregate))))
+            .collect::<Vec<_>>();
+        let comma = if idx + 1 < aggregates.len() { "," } else { "" };
+        writeln!(w, "    \"{}\": {{ {} }}{}", name, fields.join(", "), comma)?;
+    }
+    writeln!(w, "  }}")?;
+    writeln!(w, "}}")
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let mut files = Vec::new();
+    for path in &cli.paths {
+        if path.is_dir() {
+            walk(path, &cli.extension, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let mut stats = Vec::with_capacity(files.len());
+    let mut failed = Vec::new();
+    for (path, file_stats) in files.iter().zip(all_file_stats(&files)) {
+        match file_stats {
+            Ok(file_stats) => stats.push(file_stats),
+            Err(error) => {
+                eprintln!("WARN: skipping {}: {:#}", path.display(), error);
+                failed.push((path.clone(), error));
+            }
+        }
+    }
+
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    match (cli.format, cli.summary) {
+        (Format::Csv, false) => write_csv(&mut stdout, &stats),
+        (Format::Csv, true) => write_summary_csv(&mut stdout, &stats),
+        (Format::Json, _) => write_json(&mut stdout, &stats, &failed),
+    }
+    .context("failed to write the metrics")?;
+    stdout.flush().context("failed to write the metrics")?;
+
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! Per-file metrics and their aggregation over the fleet
+
+use jif::Jif;
+
+use std::path::PathBuf;
+
+/// Summary metrics of a JIF file
+#[derive(Debug, Clone)]
+pub(crate) struct FileStats {
+    pub(crate) path: PathBuf,
+
+    /// Size of the file, in B
+    pub(crate) file_size: u64,
+
+    pub(crate) pheaders: usize,
+    pub(crate) zero_pages: usize,
+    pub(crate) private_pages: usize,
+    pub(crate) shared_pages: usize,
+    pub(crate) total_pages: usize,
+    pub(crate) itree_nodes: usize,
+    pub(crate) ord_chunks: usize,
+    pub(crate) ord_pages: u64,
+
+    /// Fraction of the private pages in the ordering section
+    pub(crate) ord_coverage: f64,
+
+    /// Size of the private data, in B
+    pub(crate) data_size: usize,
+}
+
+/// Accessor of a metric
+pub(crate) type Metric = fn(&FileStats) -> f64;
+
+/// Names of the metrics, in output order, and how to get them
+pub(crate) const METRICS: &[(&str, Metric)] = &[
+    ("file_size", |s| s.file_size as f64),
+    ("pheaders", |s| s.pheaders as f64),
+    ("zero_pages", |s| s.zero_pages as f64),
+    ("private_pages", |s| s.private_pages as f64),
+    ("shared_pages", |s| s.shared_pages as f64),
+    ("total_pages", |s| s.total_pages as f64),
+    ("itree_nodes", |s| s.itree_nodes as f64),
+    ("ord_chunks", |s| s.ord_chunks as f64),
+    ("ord_pages", |s| s.ord_pages as f64),
+    ("ord_coverage", |s| s.ord_coverage),
+    ("data_size", |s| s.data_size as f64),
+];
+
+impl FileStats {
+    /// Compute the metrics of a JIF (read from the file at `path`, of `file_size` B)
+    pub(crate) fn new(path: PathBuf, file_size: u64, jif: &Jif) -> Self {
+        let quality = jif.ord_quality();
+        FileStats {
+            path,
+            file_size,
+            pheaders: jif.pheaders().len(),
+            zero_pages: jif.zero_pages(),
+            private_pages: jif.private_pages(),
+            shared_pages: jif.shared_pages(),
+            total_pages: jif.total_pages(),
+            itree_nodes: jif
+                .pheaders()
+                .iter()
+                .map(|pheader| pheader.n_itree_nodes())
+                .sum(),
+            ord_chunks: quality.n_chunks,
+            ord_pages: quality.ord_pages,
+            ord_coverage: quality.coverage(),
+            data_size: jif.date_size(),
+        }
+    }
+}
+
+/// Aggregate of a metric over the files
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Aggregate {
+    pub(crate) total: f64,
+    pub(crate) mean: f64,
+    pub(crate) min: f64,
+    pub(crate) p50: f64,
+    pub(crate) p90: f64,
+    pub(crate) p99: f64,
+    pub(crate) max: f64,
+}
+
+/// Accessor of an aggregate
+pub(crate) type AggregateValue = fn(&Aggregate) -> f64;
+
+/// Names of the aggregates, in output order, and how to get them
+pub(crate) const AGGREGATES: &[(&str, AggregateValue)] = &[
+    ("total", |a| a.total),
+    ("mean", |a| a.mean),
+    ("min", |a| a.min),
+    ("p50", |a| a.p50),
+    ("p90", |a| a.p90),
+    ("p99", |a| a.p99),
+    ("max", |a| a.max),
+];
+
+/// Nearest rank percentile of sorted values
+fn percentile(sorted: &[f64], percent: usize) -> f64 {
+    let rank = (percent * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+impl Aggregate {
+    /// Aggregate the values (all zero, when there are none)
+    pub(crate) fn new(mut values: Vec<f64>) -> Self {
+        if values.is_empty() {
+            return Aggregate::default();
+        }
+
+        values.sort_by(f64::total_cmp);
+        let total = values.iter().sum::<f64>();
+        Aggregate {
+            total,
+            mean: total / values.len() as f64,
+            min: values[0],
+            p50: percentile(&values, 50),
+            p90: percentile(&values, 90),
+            p99: percentile(&values, 99),
+            max: values[values.len() - 1],
+        }
+    }
+}
+
+/// Aggregate each metric over the files
+pub(crate) fn aggregate(stats: &[FileStats]) -> Vec<(&'static str, Aggregate)> {
+    METRICS
+        .iter()
+        .map(|(name, metric)| (*name, Aggregate::new(stats.iter().map(metric).collect())))
+        .collect()
+}
+
+/// Format a value: integers as such, fractions with 4 decimal places
+pub(crate) fn value_str(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.4}", value)
+    }
+}
@@ -4,9 +4,12 @@
 //!
 //! Example usage:
 //! ```sh
-//! $ timejif a.jif a.ord
+//! $ timejif a.jif a.ord a # plot to a.pdf (with python)
+//! $ timejif a.jif a.ord --plot a.svg --csv a.csv # plot natively and export the accesses
 //! ```
 
+mod svg;
+
 use jif::*;
 use tracer_format::*;
 
@@ -89,22 +92,128 @@ struct Cli {
     #[arg(value_hint = clap::ValueHint::FilePath)]
     ord_file: std::path::PathBuf,
 
-    /// Output file
-    #[arg(value_hint = clap::ValueHint::FilePath)]
-    output_file: std::path::PathBuf,
+    /// Output file (plotted with python, the `.pdf` extension is added)
+    #[arg(value_hint = clap::ValueHint::FilePath, required_unless_present_any = ["plot", "csv"])]
+    output_file: Option<std::path::PathBuf>,
 
     /// Title of the plot
     #[arg(long)]
     title: Option<String>,
+
+    /// Render the plot natively, as an SVG
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    plot: Option<std::path::PathBuf>,
+
+    /// Export the accesses as CSV: timestamp (ms), data source, address and pathname
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    csv: Option<std::path::PathBuf>,
+}
+
+/// A (first) access to a page
+struct Sample<'a> {
+    timestamp_ms: f64,
+    data_source: &'static str,
+    vaddr: u64,
+    pathname: Option<&'a str>,
+}
+
+/// Resolve the accesses against the JIF
+fn build_samples<'a>(jif: &'a Jif, tsa: &[TimestampedAccess]) -> Vec<Sample<'a>> {
+    tsa.iter()
+        .map(|entry| {
+            let vaddr = entry.addr as u64;
+            let data_source = match jif.resolve(vaddr).map(|ival| ival.source) {
+                Some(DataSource::Zero) => "zero",
+                Some(DataSource::Private) => "private",
+                Some(DataSource::Shared) => "shared",
+                None => "unknown",
+            };
+
+            Sample {
+                timestamp_ms: entry.usecs as f64 / 1000.0,
+                data_source,
+                vaddr,
+                pathname: jif
+                    .mapping_pheader(vaddr)
+                    .and_then(|pheader| pheader.pathname()),
+            }
+        })
+        .collect()
 }
 
-/// Plot the time plot
-fn plot_timeplot(
-    jif: &Jif,
-    tsa: &[TimestampedAccess],
-    title: String,
-    output_filename: PathBuf,
+/// Write the samples out as CSV
+fn write_csv(samples: &[Sample], output_filename: &std::path::Path) -> anyhow::Result<()> {
+    let mut writer = std::io::BufWriter::new(
+        File::create(output_filename).context("failed to create csv file")?,
+    );
+
+    writeln!(writer, "timestamp_ms,data_source,vaddr,pathname")?;
+    for sample in samples {
+        let pathname = sample.pathname.unwrap_or("");
+        let pathname = if pathname.contains([',', '"', '\n']) {
+            format!("\"{}\"", pathname.replace('"', "\"\""))
+        } else {
+            pathname.to_string()
+        };
+        writeln!(
+            writer,
+            "{},{},{:#x},{}",
+            sample.timestamp_ms, sample.data_source, sample.vaddr, pathname
+        )?;
+    }
+
+    writer.flush().context("failed to write csv file")
+}
+
+/// Plot the time plot natively, printing the same summary as the python plotter
+fn plot_timeplot_svg(
+    samples: &[Sample],
+    title: &str,
+    output_filename: &std::path::Path,
 ) -> anyhow::Result<()> {
+    let mut all = Vec::new();
+    let mut non_shared = Vec::new();
+    let mut private = Vec::new();
+    let (mut zero_cnt, mut shared_cnt) = (0, 0);
+    for sample in samples {
+        let t = sample.timestamp_ms;
+        all.push((t, (all.len() + 1) as f64));
+        match sample.data_source {
+            "private" => {
+                non_shared.push((t, (non_shared.len() + 1) as f64));
+                private.push((t, (private.len() + 1) as f64));
+            }
+            "zero" => {
+                non_shared.push((t, (non_shared.len() + 1) as f64));
+                zero_cnt += 1;
+            }
+            "shared" => shared_cnt += 1,
+            _ => {}
+        }
+    }
+
+    let (total, private_cnt) = (all.len(), private.len());
+    let plot = svg::render_scatter(
+        title,
+        "Time (ms)",
+        "Number of unique pages",
+        &[
+            ("all", all),
+            ("private", non_shared),
+            ("private - zero", private),
+        ],
+    );
+    std::fs::write(output_filename, plot).context("failed to write plot")?;
+
+    println!(
+        "{}, \t{}, \t{}, \t{}, \t{}",
+        title, total, private_cnt, shared_cnt, zero_cnt
+    );
+    Ok(())
+}
+
+/// Plot the time plot by shelling out to python
+fn plot_timeplot(samples: &[Sample], title: &str, output_filename: PathBuf) -> anyhow::Result<()> {
     let mut child = Command::new("python3")
         .arg("-c")
         .arg(PLOT_TIME_PY)
@@ -113,7 +222,7 @@ fn plot_timeplot(
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
-        .context("failed to spawn python plotter: make sure the python packages are installed (matplotlib)")?;
+        .context("failed to spawn python plotter: make sure the python packages are installed (matplotlib), or use `--plot`")?;
 
     {
         let mut stdin = child
@@ -121,16 +230,10 @@ fn plot_timeplot(
             .take()
             .context("failed to open pipe to plotter")?;
 
-        for entry in tsa {
-            let timestamp_ms = entry.usecs as f64 / 1000.0;
-
-            let data_source = match jif.resolve(entry.addr as u64).map(|ival| ival.source) {
-                Some(DataSource::Zero) => "zero",
-                Some(DataSource::Private) => "private",
-                Some(DataSource::Shared) => "shared",
-                None => "unknown",
-            };
-            stdin.write_all(format!("{} {}\n", timestamp_ms, data_source).as_bytes())?;
+        for sample in samples {
+            stdin.write_all(
+                format!("{} {}\n", sample.timestamp_ms, sample.data_source).as_bytes(),
+            )?;
         }
     }
 
@@ -164,10 +267,20 @@ fn main() -> anyhow::Result<()> {
         Ok::<Vec<TimestampedAccess>, anyhow::Error>(dedup_and_sort(trace))
     }?;
 
-    plot_timeplot(
-        &jif,
-        &trace,
-        cli.title.unwrap_or(default_title),
-        cli.output_file,
-    )
+    let title = cli.title.unwrap_or(default_title);
+    let samples = build_samples(&jif, &trace);
+
+    if let Some(csv) = cli.csv {
+        write_csv(&samples, &csv)?;
+    }
+
+    if let Some(plot) = cli.plot {
+        plot_timeplot_svg(&samples, &title, &plot)?;
+    }
+
+    if let Some(output_file) = cli.output_file {
+        plot_timeplot(&samples, &title, output_file)?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,182 @@
+//! Native rendering of scatter plots as SVG
+//!
+//! Mimics the defaults of `matplotlib` (colors, legend in the upper left corner), such that the
+//! plots are comparable to the ones produced with python
+
+use std::fmt::Write;
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 540.0;
+
+/// Room for the title, the axis labels and the ticks
+const LEFT: f64 = 80.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 50.0;
+const BOTTOM: f64 = 60.0;
+
+/// Approximate number of ticks per axis
+const N_TICKS: f64 = 8.0;
+
+const POINT_RADIUS: f64 = 1.5;
+
+/// Colors of the series (the default `matplotlib` color cycle)
+const COLORS: &[&str] = &[
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b",
+];
+
+/// A named series of `(x, y)` points
+pub(crate) type Series<'a> = (&'a str, Vec<(f64, f64)>);
+
+/// Render a scatter plot of the `series`
+pub(crate) fn render_scatter(
+    title: &str,
+    x_label: &str,
+    y_label: &str,
+    series: &[Series<'_>],
+) -> String {
+    let points = || series.iter().flat_map(|(_name, points)| points);
+    let x_max = points().map(|(x, _y)| *x).fold(0.0, f64::max);
+    let y_max = points().map(|(_x, y)| *y).fold(0.0, f64::max);
+    let (x_step, x_max) = ticks(x_max);
+    let (y_step, y_max) = ticks(y_max);
+
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let plot_height = HEIGHT - TOP - BOTTOM;
+    let to_x = |x: f64| LEFT + x / x_max * plot_width;
+    let to_y = |y: f64| TOP + plot_height - y / y_max * plot_height;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle" font-size="15">{}</text>"#,
+        LEFT + plot_width / 2.0,
+        TOP / 2.0 + 5.0,
+        escape(title)
+    );
+
+    // axes
+    let _ = writeln!(
+        svg,
+        r#"<rect x="{LEFT}" y="{TOP}" width="{plot_width}" height="{plot_height}" fill="none" stroke="black"/>"#
+    );
+    for tick in (0..)
+        .map(|i| i as f64 * x_step)
+        .take_while(|x| *x <= x_max + x_step / 2.0)
+    {
+        let x = to_x(tick);
+        let y = TOP + plot_height;
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{x}" y1="{y}" x2="{x}" y2="{}" stroke="black"/>"#,
+            y + 4.0
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{x}" y="{}" text-anchor="middle" font-size="10">{}</text>"#,
+            y + 16.0,
+            format_tick(tick, x_step)
+        );
+    }
+    for tick in (0..)
+        .map(|i| i as f64 * y_step)
+        .take_while(|y| *y <= y_max + y_step / 2.0)
+    {
+        let y = to_y(tick);
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{}" y1="{y}" x2="{LEFT}" y2="{y}" stroke="black"/>"#,
+            LEFT - 4.0
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="end" font-size="10">{}</text>"#,
+            LEFT - 6.0,
+            y + 3.0,
+            format_tick(tick, y_step)
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+        LEFT + plot_width / 2.0,
+        HEIGHT - BOTTOM / 3.0,
+        escape(x_label)
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{x}" y="{y}" text-anchor="middle" transform="rotate(-90 {x} {y})">{}</text>"#,
+        escape(y_label),
+        x = LEFT / 4.0,
+        y = TOP + plot_height / 2.0,
+    );
+
+    // points and legend
+    for (idx, (name, points)) in series.iter().enumerate() {
+        let color = COLORS[idx % COLORS.len()];
+        for (x, y) in points {
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{:.2}" cy="{:.2}" r="{POINT_RADIUS}" fill="{color}"/>"#,
+                to_x(*x),
+                to_y(*y)
+            );
+        }
+
+        let legend_y = TOP + 20.0 + idx as f64 * 18.0;
+        let _ = writeln!(
+            svg,
+            r#"<circle cx="{}" cy="{legend_y}" r="4" fill="{color}"/>"#,
+            LEFT + 20.0
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">{}</text>"#,
+            LEFT + 30.0,
+            legend_y + 4.0,
+            escape(name)
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Pick a round tick step for an axis going up to `max`, returning the step and the (rounded up)
+/// end of the axis
+fn ticks(max: f64) -> (f64, f64) {
+    if max <= 0.0 {
+        return (1.0, 1.0);
+    }
+
+    let raw_step = max / N_TICKS;
+    let magnitude = 10f64.powf(raw_step.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= raw_step)
+        .unwrap_or(10.0 * magnitude);
+
+    (step, (max / step).ceil() * step)
+}
+
+/// Format a tick with as many decimal places as its step needs
+fn format_tick(tick: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    format!("{:.*}", decimals, tick)
+}
+
+/// Escape the XML special characters
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -5,6 +5,7 @@
 //! Example usage:
 //! ```sh
 //! $ tracejif a.jif a.ord
+//! $ tracejif --summary a.jif a.ord # per VMA statistics
 //! ```
 
 use jif::*;
@@ -12,6 +13,7 @@ use tracer_format::*;
 
 use jif::itree::interval::DataSource;
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
 
@@ -29,6 +31,42 @@ struct Cli {
     /// Ordering file outputted by junction_run --trace
     #[arg(value_hint = clap::ValueHint::FilePath)]
     ord_file: std::path::PathBuf,
+
+    /// Print per VMA statistics instead of the annotated trace
+    #[arg(long)]
+    summary: bool,
+}
+
+/// Statistics of the (first) accesses to a VMA
+#[derive(Debug, Default)]
+struct VmaSummary {
+    accesses: usize,
+    reads: usize,
+    writes: usize,
+    zero: usize,
+    private: usize,
+    shared: usize,
+    first_usecs: Option<usize>,
+    last_usecs: Option<usize>,
+}
+
+impl VmaSummary {
+    fn add(&mut self, entry: &TimestampedAccess, source: Option<DataSource>) {
+        self.accesses += 1;
+        match entry.kind {
+            Some(AccessKind::Read) => self.reads += 1,
+            Some(AccessKind::Write) => self.writes += 1,
+            None => {}
+        }
+        match source {
+            Some(DataSource::Zero) => self.zero += 1,
+            Some(DataSource::Private) => self.private += 1,
+            Some(DataSource::Shared) => self.shared += 1,
+            None => {}
+        }
+        self.first_usecs = Some(self.first_usecs.map_or(entry.usecs, |u| u.min(entry.usecs)));
+        self.last_usecs = Some(self.last_usecs.map_or(entry.usecs, |u| u.max(entry.usecs)));
+    }
 }
 
 /// Print the trace
@@ -56,6 +94,76 @@ fn print_trace(jif: &Jif, tsa: &[TimestampedAccess]) {
     }
 }
 
+/// Print the per VMA statistics of the trace
+///
+/// The accesses outside of any VMA are reported on their own
+fn print_summary(jif: &Jif, tsa: &[TimestampedAccess]) {
+    let mut summaries: BTreeMap<(u64, u64), VmaSummary> = BTreeMap::new();
+    let mut unmapped = VmaSummary::default();
+    for entry in tsa {
+        let source = jif.resolve(entry.addr as u64).map(|ival| ival.source);
+        match jif.mapping_pheader(entry.addr as u64) {
+            Some(pheader) => summaries
+                .entry(pheader.virtual_range())
+                .or_default()
+                .add(entry, source),
+            None => unmapped.add(entry, source),
+        }
+    }
+
+    let fmt_usecs = |usecs: Option<usize>| usecs.map_or("-".to_string(), |u| u.to_string());
+    println!(
+        "{:^33} | {:^24} | {:>8} | {:>7} | {:>7} | {:>7} | {:>7} | {:>7} | {:>10} | {:>10} | {:>7}",
+        "vma",
+        "pathname",
+        "accesses",
+        "reads",
+        "writes",
+        "zero",
+        "private",
+        "shared",
+        "first (us)",
+        "last (us)",
+        "touched"
+    );
+    for pheader in jif.pheaders() {
+        let (start, end) = pheader.virtual_range();
+        let Some(summary) = summaries.get(&(start, end)) else {
+            continue;
+        };
+        println!(
+            "{:33} | {:24} | {:8} | {:7} | {:7} | {:7} | {:7} | {:7} | {:>10} | {:>10} | {:6.1}%",
+            format!("{:#014x}-{:#014x}", start, end),
+            pheader.pathname().unwrap_or("<unnamed>"),
+            summary.accesses,
+            summary.reads,
+            summary.writes,
+            summary.zero,
+            summary.private,
+            summary.shared,
+            fmt_usecs(summary.first_usecs),
+            fmt_usecs(summary.last_usecs),
+            (summary.accesses * 100) as f64 / pheader.total_pages(jif.page_size()) as f64
+        );
+    }
+    if unmapped.accesses > 0 {
+        println!(
+            "{:33} | {:24} | {:8} | {:7} | {:7} | {:>7} | {:>7} | {:>7} | {:>10} | {:>10} | {:>7}",
+            "<unmapped>",
+            "",
+            unmapped.accesses,
+            unmapped.reads,
+            unmapped.writes,
+            "-",
+            "-",
+            "-",
+            fmt_usecs(unmapped.first_usecs),
+            fmt_usecs(unmapped.last_usecs),
+            "-"
+        );
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     let jif = Jif::from_reader(&mut BufReader::new(
@@ -70,6 +178,10 @@ fn main() -> anyhow::Result<()> {
         Ok::<Vec<TimestampedAccess>, anyhow::Error>(dedup_and_sort(trace))
     }?;
 
-    print_trace(&jif, &trace);
+    if cli.summary {
+        print_summary(&jif, &trace);
+    } else {
+        print_trace(&jif, &trace);
+    }
     Ok(())
 }
@@ -6,6 +6,10 @@ pub enum ParseTimestampedAccessError {
     MissingDelimiter(String),
     BadTimestamp(ParseIntError),
     BadAddr(ParseIntError),
+    BadAccessKind(String),
+    BadTid(ParseIntError),
+    TrailingData(String),
+    MissingField(&'static str),
 }
 
 impl std::fmt::Display for ParseTimestampedAccessError {
@@ -21,6 +25,19 @@ impl std::fmt::Display for ParseTimestampedAccessError {
             ParseTimestampedAccessError::BadAddr(e) => {
                 f.write_fmt(format_args!("invalid address: {}", e))
             }
+            ParseTimestampedAccessError::BadAccessKind(s) => f.write_fmt(format_args!(
+                "invalid access kind (expected `r` or `w`): {}",
+                s
+            )),
+            ParseTimestampedAccessError::BadTid(e) => {
+                f.write_fmt(format_args!("invalid thread id: {}", e))
+            }
+            ParseTimestampedAccessError::TrailingData(s) => {
+                f.write_fmt(format_args!("trailing data in the log line: {}", s))
+            }
+            ParseTimestampedAccessError::MissingField(field) => {
+                f.write_fmt(format_args!("missing the {} field in the log line", field))
+            }
         }
     }
 }
@@ -31,6 +48,10 @@ impl std::error::Error for ParseTimestampedAccessError {
             ParseTimestampedAccessError::MissingDelimiter(_) => None,
             ParseTimestampedAccessError::BadTimestamp(e) => Some(e),
             ParseTimestampedAccessError::BadAddr(e) => Some(e),
+            ParseTimestampedAccessError::BadAccessKind(_) => None,
+            ParseTimestampedAccessError::BadTid(e) => Some(e),
+            ParseTimestampedAccessError::TrailingData(_) => None,
+            ParseTimestampedAccessError::MissingField(_) => None,
         }
     }
 }
@@ -1,7 +1,9 @@
 mod error;
+mod perf;
 mod timestamped_access;
 mod trace;
 
 pub use error::*;
+pub use perf::*;
 pub use timestamped_access::*;
 pub use trace::*;
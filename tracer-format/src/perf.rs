@@ -0,0 +1,280 @@
+//! Page fault traces recorded by the kernel
+//!
+//! Instead of the Junction tracer, the accesses of a process can be recorded from the
+//! `exceptions:page_fault_user` tracepoint, either with perf (`perf record -e
+//! exceptions:page_fault_user` and then `perf script`) or with ftrace (reading the `trace` or
+//! `trace_pipe` file). Both print one line per event, with the same layout:
+//! ```text
+//!    sleep  1234/1234 [002]  5678.123456: exceptions:page_fault_user: address=0x7f0000001000 ip=0x401000 error_code=0x6
+//!    sleep-1234    (  1234) [002] d....  5678.123456: page_fault_user: address=0x7f0000001000 ip=0x401000 error_code=0x6
+//! ```
+//!
+//! The task is identified by its command and its pid (and thread id) in perf, and by its command
+//! and its thread id (and, with the `record-tgid` option, its pid) in ftrace. The timestamps are
+//! in seconds since boot: once read, they are made relative to the first fault of the trace.
+//! Faults on writes are told apart by the error code (bit 1)
+
+use crate::error::{ParseTimestampedAccessError, TraceReadError};
+use crate::timestamped_access::{AccessKind, TimestampedAccess};
+
+use std::io::BufRead;
+
+/// Name of the page fault event
+const FAULT_EVENT: &str = "page_fault_user:";
+
+/// Bit of the error code set on faults on writes
+const WRITE_FAULT: u64 = 0x2;
+
+/// Parse a hexadecimal number (with or without the `0x` prefix)
+fn parse_hex(s: &str) -> Result<u64, std::num::ParseIntError> {
+    u64::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 0x10)
+}
+
+/// Parse a `<secs>.<fraction>:` timestamp into microseconds
+fn parse_timestamp(s: &str) -> Option<Result<usize, ParseTimestampedAccessError>> {
+    let (secs, fraction) = s.strip_suffix(':')?.split_once('.')?;
+    if secs.is_empty()
+        || !secs
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    // perf prints nanoseconds with `--ns`
+    let usecs = format!("{:0<6}", &fraction[..std::cmp::min(fraction.len(), 6)]);
+    Some(
+        secs.parse::<usize>()
+            .and_then(|secs| Ok(secs * 1_000_000 + usecs.parse::<usize>()?))
+            .map_err(ParseTimestampedAccessError::BadTimestamp),
+    )
+}
+
+/// Parse the task of an event, returning its pid and its thread id
+///
+/// The task is `<comm> <pid>[/<tid>]` in perf, and `<comm>-<tid> [(<pid>)]` in ftrace
+fn parse_task(task: &str) -> Result<(Option<u32>, u32), ParseTimestampedAccessError> {
+    let parse_id = |s: &str| {
+        s.trim()
+            .parse::<u32>()
+            .map_err(ParseTimestampedAccessError::BadTid)
+    };
+
+    let task = task.trim_end();
+    let (task, tgid) = match task
+        .strip_suffix(')')
+        .and_then(|task| task.rsplit_once('('))
+    {
+        // the pid of the task is unknown
+        Some((task, tgid)) if tgid.trim().bytes().all(|b| b == b'-') => (task, None),
+        Some((task, tgid)) => (task, Some(parse_id(tgid)?)),
+        None => (task, None),
+    };
+
+    let ids = task
+        .split_whitespace()
+        .last()
+        .ok_or(ParseTimestampedAccessError::MissingField("task"))?;
+    // the command of a ftrace task may have slashes (e.g., `kworker/0:1-42`)
+    match (ids.rsplit_once('-'), ids.split_once('/')) {
+        (Some((_comm, tid)), _) => Ok((tgid, parse_id(tid)?)),
+        (None, Some((pid, tid))) => Ok((Some(parse_id(pid)?), parse_id(tid)?)),
+        (None, None) => {
+            let pid = parse_id(ids)?;
+            Ok((Some(pid), pid))
+        }
+    }
+}
+
+/// Parse a line of a perf or ftrace trace, returning the pid of the task (if it is known) and
+/// the access (if the line is a page fault)
+///
+/// The lines are the `exceptions:page_fault_user` events, as printed by `perf script` or read
+/// from the ftrace `trace` file: `<task> [<cpu>] [<flags>] <secs>.<usecs>: <event>: <fields>`.
+/// The header lines (starting with `#`) and the other events are skipped
+pub fn parse_fault_line(
+    line: &str,
+) -> Result<Option<(Option<u32>, TimestampedAccess)>, ParseTimestampedAccessError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    // the task is followed by the cpu, as `[<cpu>]`
+    let (task, fields) = line
+        .match_indices(" [")
+        .find_map(|(idx, _)| {
+            let (cpu, fields) = line[idx + 2..].split_once(']')?;
+            cpu.bytes()
+                .all(|b| b.is_ascii_digit())
+                .then_some((&line[..idx], fields))
+        })
+        .ok_or(ParseTimestampedAccessError::MissingField("cpu"))?;
+
+    let mut fields = fields.split_whitespace();
+    let usecs = fields
+        .by_ref()
+        .find_map(parse_timestamp)
+        .ok_or(ParseTimestampedAccessError::MissingField("timestamp"))??;
+    let event = fields
+        .next()
+        .ok_or(ParseTimestampedAccessError::MissingField("event"))?;
+    if !event.ends_with(FAULT_EVENT) {
+        return Ok(None);
+    }
+
+    let mut addr = None;
+    let mut kind = None;
+    for (key, value) in fields.filter_map(|field| field.split_once('=')) {
+        match key {
+            "address" => {
+                addr = Some(parse_hex(value).map_err(ParseTimestampedAccessError::BadAddr)?)
+            }
+            "error_code" => {
+                let error_code = parse_hex(value)
+                    .map_err(|_| ParseTimestampedAccessError::BadAccessKind(value.to_string()))?;
+                kind = Some(match error_code & WRITE_FAULT {
+                    0 => AccessKind::Read,
+                    _ => AccessKind::Write,
+                });
+            }
+            _ => {}
+        }
+    }
+    let addr = addr.ok_or(ParseTimestampedAccessError::MissingField("address"))?;
+
+    let (pid, tid) = parse_task(task)?;
+    Ok(Some((
+        pid,
+        TimestampedAccess {
+            usecs,
+            addr: addr as usize,
+            kind,
+            tid: Some(tid),
+        },
+    )))
+}
+
+/// Read a full page fault trace, recorded with perf or ftrace (see [`parse_fault_line`])
+///
+/// When a pid is given, only the faults of its tasks are kept (the tasks of a ftrace trace
+/// without their pids are matched by their thread ids)
+pub fn read_perf_trace<BR: BufRead>(
+    reader: BR,
+    pid: Option<u32>,
+) -> Result<Vec<TimestampedAccess>, TraceReadError> {
+    let mut log = Vec::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let fault = parse_fault_line(&line?)
+            .map_err(|error| TraceReadError::ParseError { line: idx, error })?;
+        match (fault, pid) {
+            (Some((_, tsa)), None) => log.push(tsa),
+            (Some((task_pid, tsa)), Some(pid)) if task_pid.or(tsa.tid) == Some(pid) => {
+                log.push(tsa)
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(start) = log.iter().map(|tsa| tsa.usecs).min() {
+        for tsa in &mut log {
+            tsa.usecs -= start;
+        }
+    }
+
+    Ok(log)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TRACE: &str = "\
+# tracer: nop
+#
+           sleep  1234/1234 [002]  5678.123456: exceptions:page_fault_user: address=0x7f0000001000 ip=0x401000 error_code=0x6
+           sleep  1234/1240 [003]  5678.123500: exceptions:page_fault_user: address=0x7f0000002010 ip=0x401000 error_code=0x4
+           sleep  1234/1234 [002]  5678.123600: sched:sched_switch: prev_comm=sleep prev_pid=1234
+            bash  999/999   [000]  5678.200000: exceptions:page_fault_user: address=0x5000 ip=0x401000 error_code=0x6
+";
+
+    #[test]
+    fn perf_script() {
+        let log = read_perf_trace(TRACE.as_bytes(), None).unwrap();
+        assert_eq!(
+            log.iter()
+                .map(|tsa| (tsa.usecs, tsa.addr, tsa.kind, tsa.tid))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0x7f0000001000, Some(AccessKind::Write), Some(1234)),
+                (44, 0x7f0000002010, Some(AccessKind::Read), Some(1240)),
+                (76544, 0x5000, Some(AccessKind::Write), Some(999)),
+            ]
+        );
+
+        // the faults of the other process are dropped
+        let log = read_perf_trace(TRACE.as_bytes(), Some(1234)).unwrap();
+        assert_eq!(
+            log.iter().map(|tsa| tsa.addr).collect::<Vec<_>>(),
+            vec![0x7f0000001000, 0x7f0000002010]
+        );
+    }
+
+    #[test]
+    fn ftrace() {
+        let (pid, tsa) = parse_fault_line(
+            "  my-app-1240  ( 1234) [002] d.... 5678.123456789: page_fault_user: address=0x7f0000001000 ip=0x401000 error_code=0x15",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            (pid, tsa.usecs, tsa.addr, tsa.kind, tsa.tid),
+            (
+                Some(1234),
+                5678123456,
+                0x7f0000001000,
+                Some(AccessKind::Read),
+                Some(1240)
+            )
+        );
+
+        // without the pids, the thread ids are matched
+        let (pid, tsa) = parse_fault_line(
+            "  sleep-1240 [002] .... 5678.1: page_fault_user: address=0x1000 ip=0x401000 error_code=0x2",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            (pid, tsa.usecs, tsa.kind),
+            (None, 5678100000, Some(AccessKind::Write))
+        );
+    }
+
+    #[test]
+    fn parse_err() {
+        assert!(matches!(
+            parse_fault_line("not a trace"),
+            Err(ParseTimestampedAccessError::MissingField("cpu"))
+        ));
+        assert!(matches!(
+            parse_fault_line("sleep 1234 [002] 5678.1: exceptions:page_fault_user: ip=0x401000"),
+            Err(ParseTimestampedAccessError::MissingField("address"))
+        ));
+        assert!(matches!(
+            parse_fault_line("sleep 1234 [002] 5678.1: exceptions:page_fault_user: address=0xzz"),
+            Err(ParseTimestampedAccessError::BadAddr(_))
+        ));
+        assert!(matches!(
+            read_perf_trace(
+                "# header\nsleep 1234 [002] 5678.1: page_fault_user: address=0x1000 error_code=x"
+                    .as_bytes(),
+                None
+            ),
+            Err(TraceReadError::ParseError {
+                line: 1,
+                error: ParseTimestampedAccessError::BadAccessKind(_)
+            })
+        ));
+    }
+}
@@ -2,11 +2,23 @@ use crate::error::ParseTimestampedAccessError;
 
 use std::str::FromStr;
 
+/// Kind of a memory access
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
 /// Representation of an entry in the log of recorded adresses in a Junction tracer output
+///
+/// The kind of access and the faulting thread are optional (older traces do not record them)
+/// and do not take part in the comparisons
 #[derive(Debug, Copy, Clone)]
 pub struct TimestampedAccess {
     pub usecs: usize,
     pub addr: usize,
+    pub kind: Option<AccessKind>,
+    pub tid: Option<u32>,
 }
 
 impl TimestampedAccess {
@@ -37,12 +49,35 @@ impl FromStr for TimestampedAccess {
     type Err = ParseTimestampedAccessError;
     /// parse a line in the log of accesses
     ///
-    /// `<usecs>: <address>`
+    /// `<usecs>: <address> [r|w [<tid>]]`
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (usec_str, addr_str) = s
+        let (usec_str, access_str) = s
             .split_once(':')
             .ok_or_else(|| ParseTimestampedAccessError::MissingDelimiter(s.to_string()))?;
 
+        let mut fields = access_str.split_whitespace();
+        let addr_str = fields.next().unwrap_or(access_str);
+        let kind = fields
+            .next()
+            .map(|kind_str| match kind_str {
+                "r" => Ok(AccessKind::Read),
+                "w" => Ok(AccessKind::Write),
+                kind_str => Err(ParseTimestampedAccessError::BadAccessKind(
+                    kind_str.to_string(),
+                )),
+            })
+            .transpose()?;
+        let tid = fields
+            .next()
+            .map(|tid_str| tid_str.parse::<u32>())
+            .transpose()
+            .map_err(ParseTimestampedAccessError::BadTid)?;
+        if let Some(trailing) = fields.next() {
+            return Err(ParseTimestampedAccessError::TrailingData(
+                trailing.to_string(),
+            ));
+        }
+
         let usecs = usec_str
             .trim()
             .parse::<usize>()
@@ -54,7 +89,12 @@ impl FromStr for TimestampedAccess {
         }
         .map_err(ParseTimestampedAccessError::BadAddr)?;
 
-        Ok(TimestampedAccess { usecs, addr })
+        Ok(TimestampedAccess {
+            usecs,
+            addr,
+            kind,
+            tid,
+        })
     }
 }
 
@@ -68,18 +108,50 @@ mod tests {
             "1234: 5678".parse::<TimestampedAccess>().unwrap(),
             TimestampedAccess {
                 usecs: 1234,
-                addr: 5678
+                addr: 5678,
+                kind: None,
+                tid: None,
             }
         );
         assert_eq!(
             "1234: 0x1234".parse::<TimestampedAccess>().unwrap(),
             TimestampedAccess {
                 usecs: 1234,
-                addr: 0x1234
+                addr: 0x1234,
+                kind: None,
+                tid: None,
             }
         );
     }
 
+    #[test]
+    fn ok_parse_kind() {
+        let access = "1234: 0x1234 w".parse::<TimestampedAccess>().unwrap();
+        assert_eq!(
+            (access.addr, access.kind),
+            (0x1234, Some(AccessKind::Write))
+        );
+        let access = "1234: 5678 r".parse::<TimestampedAccess>().unwrap();
+        assert_eq!((access.addr, access.kind), (5678, Some(AccessKind::Read)));
+        assert_eq!(
+            "1234: 5678".parse::<TimestampedAccess>().unwrap().kind,
+            None
+        );
+    }
+
+    #[test]
+    fn ok_parse_tid() {
+        let access = "1234: 0x1234 w 17".parse::<TimestampedAccess>().unwrap();
+        assert_eq!(
+            (access.addr, access.kind, access.tid),
+            (0x1234, Some(AccessKind::Write), Some(17))
+        );
+        assert_eq!(
+            "1234: 0x1234 r".parse::<TimestampedAccess>().unwrap().tid,
+            None
+        );
+    }
+
     #[test]
     fn err_parse() {
         assert!(matches!(
@@ -102,6 +174,18 @@ mod tests {
             "1234  0x1234".parse::<TimestampedAccess>(),
             Err(ParseTimestampedAccessError::MissingDelimiter(_)),
         ));
+        assert!(matches!(
+            "1234: 0x1234 x".parse::<TimestampedAccess>(),
+            Err(ParseTimestampedAccessError::BadAccessKind(_)),
+        ));
+        assert!(matches!(
+            "1234: 0x1234 r x".parse::<TimestampedAccess>(),
+            Err(ParseTimestampedAccessError::BadTid(_)),
+        ));
+        assert!(matches!(
+            "1234: 0x1234 r 1 2".parse::<TimestampedAccess>(),
+            Err(ParseTimestampedAccessError::TrailingData(_)),
+        ));
     }
 
     #[test]
@@ -109,28 +193,40 @@ mod tests {
         assert!(
             TimestampedAccess {
                 usecs: 1234,
-                addr: 0xffff
+                addr: 0xffff,
+                kind: None,
+                tid: None,
             } < TimestampedAccess {
                 usecs: 5678,
-                addr: 0x0000
+                addr: 0x0000,
+                kind: None,
+                tid: None,
             }
         );
         assert!(
             TimestampedAccess {
                 usecs: 5678,
-                addr: 0xffff
+                addr: 0xffff,
+                kind: None,
+                tid: None,
             } > TimestampedAccess {
                 usecs: 1234,
-                addr: 0x0000
+                addr: 0x0000,
+                kind: None,
+                tid: None,
             }
         );
         assert!(
             TimestampedAccess {
                 usecs: 1234,
-                addr: 0x0000
+                addr: 0x0000,
+                kind: None,
+                tid: None,
             } == TimestampedAccess {
                 usecs: 1234,
-                addr: 0x0000
+                addr: 0x0000,
+                kind: None,
+                tid: None,
             }
         );
     }
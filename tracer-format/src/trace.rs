@@ -1,5 +1,5 @@
 use crate::error::TraceReadError;
-use crate::timestamped_access::TimestampedAccess;
+use crate::timestamped_access::{AccessKind, TimestampedAccess};
 
 use std::collections::HashMap;
 use std::io::BufRead;
@@ -37,11 +37,85 @@ pub fn dedup_and_sort(log: Vec<TimestampedAccess>) -> Vec<TimestampedAccess> {
     }
 
     let mut log = map.into_values().collect::<Vec<_>>();
-    log.sort_by_key(|tsa| tsa.addr);
+    log.sort_by_key(|tsa| (tsa.usecs, tsa.addr));
 
     log
 }
 
+/// Count the accesses to each page (by its page aligned address) over the traces of several runs
+///
+/// Deduping a trace keeps the first access to each page ([`dedup_and_sort`]): the counts tell
+/// how hot each page is
+pub fn access_counts(traces: &[Vec<TimestampedAccess>]) -> HashMap<usize, usize> {
+    let mut counts = HashMap::new();
+    for mut tsa in traces.iter().flatten().copied() {
+        tsa.truncate_addr();
+        *counts.entry(tsa.addr).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+/// How to merge the traces of several runs (see [`merge_traces`])
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Keep every page, ordered by its earliest access in any run
+    Earliest,
+
+    /// Keep every page, ordered by the number of runs accessing it (most frequent first), and
+    /// then by its earliest access
+    Frequency,
+
+    /// Keep the pages accessed in every run, ordered by their earliest access
+    Intersection,
+}
+
+/// Merge the traces of several runs into a single one, deduped and in the order given by the
+/// policy
+///
+/// Each page keeps its earliest access, which is marked as a write if the page was written in
+/// any of the runs. Merging a single trace is the same as deduping and sorting it (see
+/// [`dedup_and_sort`])
+pub fn merge_traces(
+    traces: Vec<Vec<TimestampedAccess>>,
+    policy: MergePolicy,
+) -> Vec<TimestampedAccess> {
+    let n_traces = traces.len();
+
+    // addr -> (earliest access, number of runs with an access)
+    let mut map: HashMap<usize, (TimestampedAccess, usize)> = HashMap::new();
+    for tsa in traces.into_iter().flat_map(dedup_and_sort) {
+        map.entry(tsa.addr)
+            .and_modify(|(existing, count)| {
+                let written =
+                    existing.kind == Some(AccessKind::Write) || tsa.kind == Some(AccessKind::Write);
+                if tsa < *existing {
+                    *existing = tsa
+                }
+                if written {
+                    existing.kind = Some(AccessKind::Write);
+                }
+                *count += 1;
+            })
+            .or_insert((tsa, 1));
+    }
+
+    let mut log = map
+        .into_values()
+        .filter(|(_tsa, count)| policy != MergePolicy::Intersection || *count == n_traces)
+        .collect::<Vec<_>>();
+    match policy {
+        MergePolicy::Earliest | MergePolicy::Intersection => {
+            log.sort_by_key(|(tsa, _count)| (tsa.usecs, tsa.addr))
+        }
+        MergePolicy::Frequency => {
+            log.sort_by_key(|(tsa, count)| (std::cmp::Reverse(*count), tsa.usecs, tsa.addr))
+        }
+    }
+
+    log.into_iter().map(|(tsa, _count)| tsa).collect()
+}
+
 #[cfg(test)]
 mod test {
     use crate::ParseTimestampedAccessError;
@@ -55,14 +129,18 @@ mod test {
             read_trace("01234: 0xdead".as_bytes()).unwrap(),
             vec![TimestampedAccess {
                 usecs: 1234,
-                addr: 0xdead
+                addr: 0xdead,
+                kind: None,
+                tid: None,
             }]
         );
         assert_eq!(
             read_trace("01234: 0xdead".as_bytes()).unwrap(),
             vec![TimestampedAccess {
                 usecs: 1234,
-                addr: 0xdead
+                addr: 0xdead,
+                kind: None,
+                tid: None,
             }]
         );
         assert_eq!(
@@ -70,11 +148,15 @@ mod test {
             vec![
                 TimestampedAccess {
                     usecs: 1234,
-                    addr: 0xdead
+                    addr: 0xdead,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 4,
-                    addr: 1234
+                    addr: 1234,
+                    kind: None,
+                    tid: None,
                 },
             ]
         );
@@ -83,15 +165,21 @@ mod test {
             vec![
                 TimestampedAccess {
                     usecs: 1234,
-                    addr: 0xdead
+                    addr: 0xdead,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 4,
-                    addr: 1234
+                    addr: 1234,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 1234,
-                    addr: 0xdead
+                    addr: 0xdead,
+                    kind: None,
+                    tid: None,
                 },
             ]
         );
@@ -128,14 +216,20 @@ mod test {
             TimestampedAccess {
                 usecs: 1,
                 addr: 0x1000,
+                kind: None,
+                tid: None,
             },
             TimestampedAccess {
                 usecs: 2,
                 addr: 0x3000,
+                kind: None,
+                tid: None,
             },
             TimestampedAccess {
                 usecs: 3,
                 addr: 0x2000,
+                kind: None,
+                tid: None,
             },
         ];
 
@@ -144,15 +238,21 @@ mod test {
             vec![
                 TimestampedAccess {
                     usecs: 1,
-                    addr: 0x1000
+                    addr: 0x1000,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 2,
-                    addr: 0x3000
+                    addr: 0x3000,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 3,
-                    addr: 0x2000
+                    addr: 0x2000,
+                    kind: None,
+                    tid: None,
                 },
             ]
         )
@@ -163,22 +263,32 @@ mod test {
             TimestampedAccess {
                 usecs: 1,
                 addr: 0x1000,
+                kind: None,
+                tid: None,
             },
             TimestampedAccess {
                 usecs: 2,
                 addr: 0x3000,
+                kind: None,
+                tid: None,
             },
             TimestampedAccess {
                 usecs: 4,
                 addr: 0x2000,
+                kind: None,
+                tid: None,
             },
             TimestampedAccess {
                 usecs: 3,
                 addr: 0x2000,
+                kind: None,
+                tid: None,
             },
             TimestampedAccess {
                 usecs: 2,
                 addr: 0x1000,
+                kind: None,
+                tid: None,
             },
         ];
 
@@ -187,17 +297,91 @@ mod test {
             vec![
                 TimestampedAccess {
                     usecs: 1,
-                    addr: 0x1000
+                    addr: 0x1000,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 2,
-                    addr: 0x3000
+                    addr: 0x3000,
+                    kind: None,
+                    tid: None,
                 },
                 TimestampedAccess {
                     usecs: 3,
-                    addr: 0x2000
+                    addr: 0x2000,
+                    kind: None,
+                    tid: None,
                 },
             ]
         )
     }
+
+    #[test]
+    fn merge() {
+        let tsa = |usecs, addr, kind| TimestampedAccess {
+            usecs,
+            addr,
+            kind,
+            tid: None,
+        };
+        let traces = vec![
+            vec![
+                tsa(1, 0x1000, Some(AccessKind::Read)),
+                tsa(0, 0x2000, None),
+                tsa(5, 0x1000, Some(AccessKind::Write)),
+            ],
+            vec![tsa(1, 0x3000, None), tsa(3, 0x1000, Some(AccessKind::Read))],
+            vec![
+                tsa(4, 0x1000, Some(AccessKind::Write)),
+                tsa(6, 0x3000, None),
+            ],
+        ];
+        let addrs = |log: Vec<TimestampedAccess>| log.iter().map(|t| t.addr).collect::<Vec<_>>();
+
+        let earliest = merge_traces(traces.clone(), MergePolicy::Earliest);
+        assert_eq!(
+            earliest,
+            vec![
+                tsa(0, 0x2000, None),
+                tsa(1, 0x1000, None),
+                tsa(1, 0x3000, None)
+            ]
+        );
+        // the page was written in one of the runs
+        assert_eq!(earliest[1].kind, Some(AccessKind::Write));
+
+        assert_eq!(
+            addrs(merge_traces(traces.clone(), MergePolicy::Frequency)),
+            vec![0x1000, 0x3000, 0x2000]
+        );
+        assert_eq!(
+            addrs(merge_traces(traces.clone(), MergePolicy::Intersection)),
+            vec![0x1000]
+        );
+
+        // merging a single trace dedups and sorts it
+        assert_eq!(
+            merge_traces(vec![traces[0].clone()], MergePolicy::Intersection),
+            dedup_and_sort(traces[0].clone())
+        );
+        assert!(merge_traces(vec![], MergePolicy::Earliest).is_empty());
+    }
+
+    #[test]
+    fn counts() {
+        let tsa = |usecs, addr| TimestampedAccess {
+            usecs,
+            addr,
+            kind: None,
+            tid: None,
+        };
+        let traces = vec![
+            vec![tsa(1, 0x1000), tsa(2, 0x1008), tsa(3, 0x2000)],
+            vec![tsa(1, 0x1fff)],
+        ];
+        let counts = access_counts(&traces);
+        assert_eq!((counts[&0x1000], counts[&0x2000]), (3, 1));
+        assert_eq!(counts.len(), 2);
+    }
 }